@@ -0,0 +1,60 @@
+//! Decoding arbitrary packets, then describing the resulting traffic.
+//!
+//! Each input is a sequence of packets with their timestamps, as they
+//! might be read from a capture file.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packetry_core::capture::{
+    create_capture,
+    CaptureReader,
+    ItemSource,
+    TrafficItem,
+};
+use packetry_core::decoder::Decoder;
+
+/// Maximum number of items to describe from each capture.
+const MAX_ITEMS: usize = 10_000;
+
+fuzz_target!(|packets: Vec<(u64, Vec<u8>)>| {
+    let (writer, mut reader) = create_capture().unwrap();
+    let mut decoder = Decoder::new(writer).unwrap();
+    for (timestamp, packet) in packets {
+        // Invalid packets may be rejected, but must not cause a panic.
+        if decoder.handle_raw_packet(&packet, timestamp).is_err() {
+            return;
+        }
+    }
+    if decoder.finish().is_err() {
+        return;
+    }
+    let mut budget = MAX_ITEMS;
+    let (_, count) =
+        ItemSource::<TrafficItem>::item_children(&mut reader, None).unwrap();
+    for index in 0..count {
+        let item: TrafficItem = reader.item(None, index).unwrap();
+        describe(&mut reader, &item, &mut budget);
+        if budget == 0 {
+            break;
+        }
+    }
+});
+
+/// Describe an item and its children, as the traffic view would.
+fn describe(reader: &mut CaptureReader,
+            item: &TrafficItem,
+            budget: &mut usize)
+{
+    *budget -= 1;
+    reader.summary(item).unwrap();
+    reader.connectors(item).unwrap();
+    let (_, count) = reader.item_children(Some(item)).unwrap();
+    for index in 0..count {
+        if *budget == 0 {
+            return;
+        }
+        let child = reader.child_item(item, index).unwrap();
+        describe(reader, &child, budget);
+    }
+}
@@ -0,0 +1,27 @@
+//! Splitting the data sent by a Cynthion into packets.
+//!
+//! Each input is a sequence of transfers as received from the analyzer.
+//! The packets framed from them, with their length headers restored, must
+//! match the start of the data.
+
+#![no_main]
+
+use std::sync::mpsc;
+
+use libfuzzer_sys::fuzz_target;
+use packetry_core::backend::cynthion::CynthionStream;
+
+fuzz_target!(|transfers: Vec<Vec<u8>>| {
+    let data = transfers.concat();
+    let (sender, receiver) = mpsc::channel();
+    for transfer in transfers {
+        sender.send(transfer).unwrap();
+    }
+    drop(sender);
+    let mut framed = Vec::new();
+    for packet in CynthionStream::new(receiver) {
+        framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&packet);
+    }
+    assert!(data.starts_with(&framed));
+});
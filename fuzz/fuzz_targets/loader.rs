@@ -0,0 +1,21 @@
+//! Loading arbitrary capture files.
+//!
+//! Each input is the content of a file, which may be a pcap file, a
+//! Packetry capture, or either of these compressed with zstd.
+
+#![no_main]
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use libfuzzer_sys::fuzz_target;
+use packetry_core::loader::load_readers;
+use packetry_core::pcap::decompress;
+
+fuzz_target!(|data: &[u8]| {
+    let path = PathBuf::from("fuzz.pcap");
+    if let Ok(reader) = decompress(Cursor::new(data.to_vec())) {
+        // Invalid files may be rejected, but must not cause a panic.
+        let _ = load_readers(&[path], vec![reader]);
+    }
+});
@@ -0,0 +1,114 @@
+//! Throughput of packet framing, decoding and indexing, measured using the
+//! captures in the tests directory.
+
+use std::fs::File;
+use std::sync::mpsc;
+
+use criterion::{
+    criterion_group,
+    criterion_main,
+    BatchSize,
+    Criterion,
+    Throughput,
+};
+use pcap_file::pcap::PcapReader;
+
+use packetry_core::backend::cynthion::CynthionStream;
+use packetry_core::capture::{create_capture, CaptureReader, Timestamp};
+use packetry_core::decoder::Decoder;
+use packetry_core::pcap::timestamp;
+use packetry_core::traffic_index::update_index;
+
+/// Captures used, chosen for their mix of traffic.
+const CAPTURES: [&str; 4] = [
+    "emf2022-badge",
+    "hackrf-restart-failure",
+    "mouse",
+    "split-enum",
+];
+
+/// Size of the transfers in which a Cynthion sends captured packets.
+const TRANSFER_SIZE: usize = 0x4000;
+
+fn load(name: &str) -> Vec<(Vec<u8>, Timestamp)> {
+    let file = File::open(format!("../tests/{name}/capture.pcap")).unwrap();
+    let mut pcap_reader = PcapReader::new(file).unwrap();
+    let header = pcap_reader.header();
+    let mut packets = Vec::new();
+    while let Some(result) = pcap_reader.next_raw_packet() {
+        let packet = result.unwrap();
+        packets.push((packet.data.to_vec(), timestamp(&header, &packet)));
+    }
+    packets
+}
+
+fn decode(packets: &[(Vec<u8>, Timestamp)]) -> CaptureReader {
+    let (writer, reader) = create_capture().unwrap();
+    let mut decoder = Decoder::new(writer).unwrap();
+    for (packet, timestamp) in packets {
+        decoder.handle_raw_packet(packet, *timestamp).unwrap();
+    }
+    decoder.finish().unwrap();
+    reader
+}
+
+/// Splitting the data received from a Cynthion into packets.
+fn framing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("framing");
+    for name in CAPTURES {
+        let packets = load(name);
+        let mut data = Vec::new();
+        for (packet, _) in &packets {
+            data.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+            data.extend_from_slice(packet);
+        }
+        let transfers: Vec<Vec<u8>> = data
+            .chunks(TRANSFER_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        group.throughput(Throughput::Elements(packets.len() as u64));
+        group.bench_function(name, |b| b.iter_batched(
+            || {
+                let (sender, receiver) = mpsc::channel();
+                for transfer in &transfers {
+                    sender.send(transfer.clone()).unwrap();
+                }
+                receiver
+            },
+            |receiver| {
+                let count = CynthionStream::new(receiver).count();
+                assert_eq!(count, packets.len());
+            },
+            BatchSize::SmallInput));
+    }
+    group.finish();
+}
+
+/// Decoding packets into transactions and transfers, and storing them.
+fn decoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoding");
+    for name in CAPTURES {
+        let packets = load(name);
+        group.throughput(Throughput::Elements(packets.len() as u64));
+        group.bench_function(name, |b| b.iter(|| decode(&packets)));
+    }
+    group.finish();
+}
+
+/// Indexing the transfers of a decoded capture by endpoint and error.
+fn indexing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("indexing");
+    for name in CAPTURES {
+        let packets = load(name);
+        let item_count = decode(&packets).item_index.len();
+        group.throughput(Throughput::Elements(item_count));
+        group.bench_function(name, |b| b.iter_batched(
+            || decode(&packets),
+            |mut capture| while update_index(&mut capture).unwrap() {},
+            BatchSize::LargeInput));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, framing, decoding, indexing);
+criterion_main!(benches);
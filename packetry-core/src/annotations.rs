@@ -0,0 +1,192 @@
+//! Bookmarks and comments attached to traffic items by the user.
+
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    ItemSource,
+    PacketId,
+    TrafficItem,
+    TransactionId,
+    TransferId,
+};
+
+/// Key for an annotated item, ordering items as they are displayed.
+type Key = (TransferId, Option<TransactionId>, Option<PacketId>);
+
+fn key(item: &TrafficItem) -> Key {
+    use TrafficItem::*;
+    match item {
+        Transfer(transfer_id) => (*transfer_id, None, None),
+        Transaction(transfer_id, transaction_id) =>
+            (*transfer_id, Some(*transaction_id), None),
+        Packet(transfer_id, transaction_id, packet_id) =>
+            (*transfer_id, Some(*transaction_id), Some(*packet_id)),
+    }
+}
+
+fn item(key: &Key) -> TrafficItem {
+    use TrafficItem::*;
+    match *key {
+        (transfer_id, None, _) => Transfer(transfer_id),
+        (transfer_id, Some(transaction_id), None) =>
+            Transaction(transfer_id, transaction_id),
+        (transfer_id, Some(transaction_id), Some(packet_id)) =>
+            Packet(transfer_id, transaction_id, packet_id),
+    }
+}
+
+/// A bookmark and comment on a traffic item.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Annotation {
+    pub bookmarked: bool,
+    pub comment: String,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        !self.bookmarked && self.comment.is_empty()
+    }
+
+    /// Describe an item with this annotation, given its summary.
+    pub fn describe(&self, summary: &str) -> String {
+        let mut text = String::new();
+        if self.bookmarked {
+            text.push_str("★ ");
+        }
+        text.push_str(summary);
+        if !self.comment.is_empty() {
+            text.push_str(" — ");
+            text.push_str(&self.comment);
+        }
+        text
+    }
+}
+
+/// The annotations made on a capture, in the order items are displayed.
+#[derive(Clone, Default)]
+pub struct Annotations {
+    items: BTreeMap<Key, Annotation>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Annotations::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Get the annotation on an item, if any.
+    pub fn get(&self, item: &TrafficItem) -> Option<&Annotation> {
+        self.items.get(&key(item))
+    }
+
+    /// Set the annotation on an item, removing it if empty.
+    pub fn set(&mut self, item: &TrafficItem, annotation: Annotation) {
+        if annotation.is_empty() {
+            self.items.remove(&key(item));
+        } else {
+            self.items.insert(key(item), annotation);
+        }
+    }
+
+    /// Iterate over the annotated items.
+    pub fn iter(&self) -> impl Iterator<Item=(TrafficItem, &Annotation)> {
+        self.items.iter().map(|(key, annotation)| (item(key), annotation))
+    }
+
+    /// Iterate over the bookmarked items.
+    pub fn bookmarks(&self) -> impl Iterator<Item=(TrafficItem, &Annotation)>
+    {
+        self.iter().filter(|(_, annotation)| annotation.bookmarked)
+    }
+
+    /// Map comments to the packets they should be attached to on export.
+    ///
+    /// Comments on transfers and transactions are attached to their first
+    /// packet. Where several comments fall on one packet, they are joined
+    /// on separate lines.
+    pub fn packet_comments(&self, capture: &mut CaptureReader)
+        -> Result<BTreeMap<PacketId, String>, Error>
+    {
+        let mut comments: BTreeMap<PacketId, String> = BTreeMap::new();
+        for (item, annotation) in self.iter() {
+            if annotation.comment.is_empty() {
+                continue;
+            }
+            let mut packet = item;
+            while !matches!(packet, TrafficItem::Packet(..)) {
+                packet = capture.child_item(&packet, 0)?;
+            }
+            if let TrafficItem::Packet(.., packet_id) = packet {
+                comments
+                    .entry(packet_id)
+                    .and_modify(|text| {
+                        text.push('\n');
+                        text.push_str(&annotation.comment);
+                    })
+                    .or_insert_with(|| annotation.comment.clone());
+            }
+        }
+        Ok(comments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_annotations() {
+        let path = PathBuf::from("../tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let transfer: TrafficItem = capture.item(None, 1).unwrap();
+        let transaction = capture.child_item(&transfer, 0).unwrap();
+        let packet = capture.child_item(&transaction, 0).unwrap();
+        let later_packet = capture.child_item(&transaction, 1).unwrap();
+        assert_eq!(capture.item_path(&later_packet).unwrap(),
+                   Some(vec![1, 0, 1]));
+        assert_eq!(capture.item_path(&transfer).unwrap(), Some(vec![1]));
+
+        let mut annotations = Annotations::new();
+        annotations.set(&later_packet, Annotation {
+            bookmarked: true,
+            comment: String::new(),
+        });
+        annotations.set(&transaction, Annotation {
+            bookmarked: false,
+            comment: String::from("Setup"),
+        });
+        annotations.set(&transfer, Annotation {
+            bookmarked: true,
+            comment: String::from("Descriptor request"),
+        });
+        assert_eq!(annotations.get(&packet), None);
+        assert_eq!(annotations.bookmarks().count(), 2);
+        assert_eq!(annotations.bookmarks().next().unwrap().0, transfer);
+        assert_eq!(
+            annotations.get(&transfer).unwrap().describe("Transfer"),
+            "★ Transfer — Descriptor request");
+
+        // Both comments fall on the first packet of the transfer.
+        let comments = annotations.packet_comments(&mut capture).unwrap();
+        let packet_id = match packet {
+            TrafficItem::Packet(.., packet_id) => packet_id,
+            _ => unreachable!(),
+        };
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[&packet_id], "Descriptor request\nSetup");
+
+        // Clearing an annotation removes it.
+        annotations.set(&later_packet, Annotation::default());
+        annotations.set(&transaction, Annotation::default());
+        annotations.set(&transfer, Annotation::default());
+        assert!(annotations.is_empty());
+    }
+}
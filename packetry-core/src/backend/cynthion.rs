@@ -1,7 +1,14 @@
-use std::collections::VecDeque;
+//! Capture from a Cynthion USB analyzer.
+//!
+//! Besides capturing, an analyzer's gateware may support test features
+//! for electrical validation, such as forcing a bus reset or sending the
+//! USB 2.0 test packet on the target port, which are performed by vendor
+//! requests through a [`CynthionTester`].
+
 use std::thread::{spawn, JoinHandle};
 use std::time::Duration;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
 
 use anyhow::{Context as ErrorContext, Error, bail};
 use futures_channel::oneshot;
@@ -21,6 +28,8 @@ use nusb::{
     Interface
 };
 
+use super::packet::Packet;
+
 const VID: u16 = 0x1d50;
 const PID: u16 = 0x615b;
 
@@ -30,6 +39,16 @@ const PROTOCOL: u8 = 0x00;
 
 const ENDPOINT: u8 = 0x81;
 
+/// Vendor request to pulse the analyzer's trigger output.
+const TRIGGER_REQUEST: u8 = 3;
+
+/// Vendor request to read a mask of the test features supported.
+const TEST_FEATURES_REQUEST: u8 = 4;
+
+/// Vendor request to perform a test feature, given in the value, or with
+/// a value of zero, to end any test state being held.
+const TEST_REQUEST: u8 = 5;
+
 const READ_LEN: usize = 0x4000;
 const NUM_TRANSFERS: usize = 4;
 
@@ -65,6 +84,69 @@ impl Speed {
     }
 }
 
+/// Test features which an analyzer's gateware may offer, acting on the
+/// target port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TestFeature {
+    /// Drive SE0 for 10 ms, resetting the device.
+    BusReset,
+    /// Send the test packet defined by USB 2.0, repeatedly.
+    TestPacket,
+    /// Hold the bus in the high speed J state.
+    TestJ,
+    /// Hold the bus in the high speed K state.
+    TestK,
+    /// Hold the bus in SE0, answering nothing.
+    TestSe0Nak,
+}
+
+impl TestFeature {
+    pub const ALL: [TestFeature; 5] = [
+        TestFeature::BusReset,
+        TestFeature::TestPacket,
+        TestFeature::TestJ,
+        TestFeature::TestK,
+        TestFeature::TestSe0Nak,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        use TestFeature::*;
+        match self {
+            BusReset => "Force bus reset",
+            TestPacket => "Send test packet",
+            TestJ => "Hold J state",
+            TestK => "Hold K state",
+            TestSe0Nak => "Hold SE0",
+        }
+    }
+
+    /// Whether the feature holds the bus in a state until ended.
+    pub fn is_held(&self) -> bool {
+        !matches!(self, TestFeature::BusReset)
+    }
+
+    /// Value of the vendor request performing this feature.
+    fn value(&self) -> u16 {
+        use TestFeature::*;
+        match self {
+            BusReset => 1,
+            TestPacket => 2,
+            TestJ => 3,
+            TestK => 4,
+            TestSe0Nak => 5,
+        }
+    }
+
+    /// The features listed in a mask read from an analyzer, in which bit
+    /// n stands for the feature with value n + 1.
+    fn from_mask(mask: u8) -> Vec<TestFeature> {
+        TestFeature::ALL
+            .into_iter()
+            .filter(|feature| mask & (1 << (feature.value() - 1)) != 0)
+            .collect()
+    }
+}
+
 bitfield! {
     #[derive(Copy, Clone)]
     struct State(u8);
@@ -109,12 +191,39 @@ pub struct CynthionHandle {
 
 pub struct CynthionStream {
     receiver: mpsc::Receiver<Vec<u8>>,
-    buffer: VecDeque<u8>,
+    /// The transfer which packets are currently being read from.
+    buffer: Arc<Vec<u8>>,
+    /// Position of the next unparsed byte in the buffer.
+    offset: usize,
+    /// Bytes of a packet split across transfers, with its length header.
+    partial: Vec<u8>,
+}
+
+/// Drives the trigger output of an analyzer during a capture.
+///
+/// The output is pulsed by a vendor request, which the analyzer's gateware
+/// must support. An emulated analyzer has no output, and only counts the
+/// pulses requested.
+#[derive(Clone)]
+pub struct CynthionTrigger {
+    interface: Option<Interface>,
+    pulses: Arc<AtomicU64>,
+}
+
+/// Performs the test features of an analyzer, during a capture or not.
+///
+/// An emulated analyzer offers every feature, but only counts the
+/// requests made.
+#[derive(Clone)]
+pub struct CynthionTester {
+    interface: Option<Interface>,
+    features: Vec<TestFeature>,
+    requests: Arc<AtomicU64>,
 }
 
 pub struct CynthionStop {
-    stop_request: oneshot::Sender<()>,
-    worker: JoinHandle::<()>,
+    pub(super) stop_request: oneshot::Sender<()>,
+    pub(super) worker: JoinHandle::<()>,
 }
 
 /// Check whether a Cynthion device has an accessible analyzer interface.
@@ -319,10 +428,7 @@ impl CynthionHandle {
         };
         let worker = spawn(move || result_handler(run_capture()));
         Ok((
-            CynthionStream {
-                receiver: rx,
-                buffer: VecDeque::new(),
-            },
+            CynthionStream::new(rx),
             CynthionStop {
                 stop_request: stop_tx,
                 worker,
@@ -330,6 +436,48 @@ impl CynthionHandle {
         ))
     }
 
+    /// Get a handle to drive the trigger output, which remains usable
+    /// while capturing.
+    pub fn trigger(&self) -> CynthionTrigger {
+        CynthionTrigger {
+            interface: Some(self.interface.clone()),
+            pulses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Read which test features the analyzer's gateware supports.
+    pub fn test_features(&self) -> Result<Vec<TestFeature>, Error> {
+        let control = Control {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request: TEST_FEATURES_REQUEST,
+            value: 0,
+            index: self.interface.interface_number() as u16,
+        };
+        let mut buf = [0; 64];
+        let timeout = Duration::from_secs(1);
+        match self.interface.control_in_blocking(control, &mut buf, timeout) {
+            Ok(1) => Ok(TestFeature::from_mask(buf[0])),
+            Ok(size) => bail!(
+                "Expected 1-byte response to test features request, got \
+                 {size}"),
+            // Gateware without test features stalls the request.
+            Err(TransferError::Stall) => Ok(Vec::new()),
+            Err(error) => Err(Error::from(error)
+                .context("Failed retrieving test features from device")),
+        }
+    }
+
+    /// Get a handle to perform test features, which remains usable while
+    /// capturing.
+    pub fn tester(&self) -> Result<CynthionTester, Error> {
+        Ok(CynthionTester {
+            interface: Some(self.interface.clone()),
+            features: self.test_features()?,
+            requests: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
     fn write_state(&mut self, state: State) -> Result<(), Error> {
         let control = Control {
             control_type: ControlType::Vendor,
@@ -348,9 +496,9 @@ impl CynthionHandle {
 }
 
 impl Iterator for CynthionStream {
-    type Item = Vec<u8>;
+    type Item = Packet;
 
-    fn next(&mut self) -> Option<Vec<u8>> {
+    fn next(&mut self) -> Option<Packet> {
         loop {
             // Do we have another packet already in the buffer?
             match self.next_buffered_packet() {
@@ -359,7 +507,7 @@ impl Iterator for CynthionStream {
                 // No; wait for more data from the capture thread.
                 None => match self.receiver.recv().ok() {
                     // Received more data; add it to the buffer and retry.
-                    Some(bytes) => self.buffer.extend(bytes.iter()),
+                    Some(bytes) => self.refill(bytes),
                     // Capture has ended, there are no more packets.
                     None => return None
                 }
@@ -369,25 +517,150 @@ impl Iterator for CynthionStream {
 }
 
 impl CynthionStream {
-    fn next_buffered_packet(&mut self) -> Option<Vec<u8>> {
-        // Do we have the length header for the next packet?
-        let buffer_len = self.buffer.len();
-        if buffer_len <= 2 {
-            return None;
+    /// Parse packets from the data of transfers received over a channel.
+    pub fn new(receiver: mpsc::Receiver<Vec<u8>>) -> CynthionStream {
+        CynthionStream {
+            receiver,
+            buffer: Arc::new(Vec::new()),
+            offset: 0,
+            partial: Vec::new(),
         }
+    }
 
-        // Do we have all the data for the next packet?
-        let packet_len = u16::from_be_bytes(
-            [self.buffer[0], self.buffer[1]]) as usize;
-        if buffer_len <= 2 + packet_len {
-            return None;
+    fn next_buffered_packet(&mut self) -> Option<Packet> {
+        // Finish any packet split across transfers, copying its bytes.
+        if !self.partial.is_empty() {
+            loop {
+                let wanted = match self.partial.as_slice() {
+                    [high, low, ..] =>
+                        2 + u16::from_be_bytes([*high, *low]) as usize,
+                    _ => 2,
+                };
+                if self.partial.len() == wanted {
+                    break;
+                }
+                let remaining = &self.buffer[self.offset..];
+                if remaining.is_empty() {
+                    return None;
+                }
+                let length = remaining.len().min(wanted - self.partial.len());
+                self.partial.extend_from_slice(&remaining[..length]);
+                self.offset += length;
+            }
+            let mut bytes = std::mem::take(&mut self.partial);
+            bytes.drain(0..2);
+            return Some(Packet::from(bytes));
+        }
+
+        // Do we have the length header and all the data for the next
+        // packet? If so, return it as a slice of the transfer buffer.
+        let remaining = &self.buffer[self.offset..];
+        if let [high, low, rest @ ..] = remaining {
+            let packet_len = u16::from_be_bytes([*high, *low]) as usize;
+            if rest.len() >= packet_len {
+                let start = self.offset + 2;
+                self.offset = start + packet_len;
+                return Some(
+                    Packet::new(self.buffer.clone(), start..self.offset));
+            }
+        }
+
+        // Otherwise the packet continues in the next transfer.
+        self.partial.extend_from_slice(remaining);
+        self.offset = self.buffer.len();
+        None
+    }
+
+    fn refill(&mut self, bytes: Vec<u8>) {
+        self.buffer = Arc::new(bytes);
+        self.offset = 0;
+    }
+}
+
+impl CynthionTrigger {
+    /// A trigger for an emulated analyzer.
+    pub fn emulated() -> CynthionTrigger {
+        CynthionTrigger {
+            interface: None,
+            pulses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Pulse the trigger output.
+    pub fn pulse(&self) -> Result<(), Error> {
+        if let Some(interface) = &self.interface {
+            let control = Control {
+                control_type: ControlType::Vendor,
+                recipient: Recipient::Interface,
+                request: TRIGGER_REQUEST,
+                value: 0,
+                index: interface.interface_number() as u16,
+            };
+            let timeout = Duration::from_secs(1);
+            interface
+                .control_out_blocking(control, &[], timeout)
+                .context("Failed pulsing trigger output")?;
+        }
+        self.pulses.fetch_add(1, Relaxed);
+        Ok(())
+    }
+
+    /// Number of pulses made so far.
+    pub fn pulses(&self) -> u64 {
+        self.pulses.load(Relaxed)
+    }
+}
+
+impl CynthionTester {
+    /// A tester for an emulated analyzer.
+    pub fn emulated() -> CynthionTester {
+        CynthionTester {
+            interface: None,
+            features: TestFeature::ALL.to_vec(),
+            requests: Arc::new(AtomicU64::new(0)),
         }
+    }
 
-        // Remove the length header from the buffer.
-        self.buffer.drain(0..2);
+    /// The test features supported.
+    pub fn features(&self) -> &[TestFeature] {
+        &self.features
+    }
+
+    /// Perform a test feature.
+    pub fn perform(&self, feature: TestFeature) -> Result<(), Error> {
+        if !self.features.contains(&feature) {
+            bail!("The analyzer does not support: {}",
+                  feature.description());
+        }
+        self.request(feature.value())
+            .with_context(|| format!("Failed to {}",
+                                     feature.description().to_lowercase()))
+    }
+
+    /// End any test state being held, returning the bus to normal.
+    pub fn end(&self) -> Result<(), Error> {
+        self.request(0).context("Failed to end test")
+    }
+
+    /// Number of requests made so far.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Relaxed)
+    }
 
-        // Remove the packet from the buffer and return it.
-        Some(self.buffer.drain(0..packet_len).collect())
+    fn request(&self, value: u16) -> Result<(), Error> {
+        if let Some(interface) = &self.interface {
+            let control = Control {
+                control_type: ControlType::Vendor,
+                recipient: Recipient::Interface,
+                request: TEST_REQUEST,
+                value,
+                index: interface.interface_number() as u16,
+            };
+            let timeout = Duration::from_secs(1);
+            interface.control_out_blocking(control, &[], timeout)?;
+        }
+        self.requests.fetch_add(1, Relaxed);
+        Ok(())
     }
 }
 
@@ -412,3 +685,53 @@ impl CynthionStop {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_framing() {
+        let (tx, rx) = mpsc::channel();
+        let stream = CynthionStream::new(rx);
+        // Packets split across transfers are reassembled, including those
+        // whose length header is split.
+        tx.send(vec![0x00, 0x03, 0xa5, 0xde]).unwrap();
+        tx.send(vec![0x1e, 0x00, 0x01, 0xd2, 0x00]).unwrap();
+        tx.send(vec![0x01, 0x5a, 0x00, 0x01, 0x4b]).unwrap();
+        drop(tx);
+        let packets: Vec<Vec<u8>> = stream
+            .map(|packet| packet.to_vec())
+            .collect();
+        assert_eq!(packets, vec![
+            vec![0xa5, 0xde, 0x1e],
+            vec![0xd2],
+            vec![0x5a],
+            vec![0x4b],
+        ]);
+    }
+
+    #[test]
+    fn test_test_features() {
+        use TestFeature::*;
+        assert_eq!(TestFeature::from_mask(0b00011), [BusReset, TestPacket]);
+        assert_eq!(TestFeature::from_mask(0b11100), [TestJ, TestK, TestSe0Nak]);
+        assert!(TestFeature::from_mask(0).is_empty());
+
+        let tester = CynthionTester::emulated();
+        assert_eq!(tester.features(), TestFeature::ALL);
+        tester.perform(BusReset).unwrap();
+        tester.clone().end().unwrap();
+        assert_eq!(tester.requests(), 2);
+
+        // Features the analyzer lacks are refused without a request.
+        let tester = CynthionTester {
+            features: vec![BusReset],
+            ..CynthionTester::emulated()
+        };
+        let error = tester.perform(TestJ).unwrap_err();
+        assert_eq!(error.to_string(),
+                   "The analyzer does not support: Hold J state");
+        assert_eq!(tester.requests(), 0);
+    }
+}
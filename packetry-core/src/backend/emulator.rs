@@ -0,0 +1,848 @@
+//! Emulation of a Cynthion analyzer, for testing without hardware.
+//!
+//! An emulated analyzer produces the same stream of data as a real one,
+//! capturing traffic described by a scenario file. Each line of the file
+//! is a command, and anything after a `#` is a comment:
+//!
+//! | Command                                   | Effect                        |
+//! |-------------------------------------------|-------------------------------|
+//! | `speed high\|full\|low`                   | Set the bus speed             |
+//! | `device ADDR [vid=VID] [pid=PID]`         | Declare a device              |
+//! | `endpoint ADDR NUM in\|out TYPE MAX`      | Declare an endpoint on it     |
+//! | `enumerate ADDR`                          | Enumerate a declared device   |
+//! | `sof COUNT`                               | Start of frame packets        |
+//! | `control ADDR TYPE REQ VALUE INDEX [DATA]`| Control transfer on EP0       |
+//! | `in ADDR NUM [DATA]`                      | IN transfer of the given data |
+//! | `out ADDR NUM [DATA]`                     | OUT transfer of the data      |
+//! | `nak ADDR NUM in\|out [COUNT]`            | NAKed transactions            |
+//! | `stall ADDR NUM in\|out`                  | STALLed transaction           |
+//! | `corrupt crc\|pid\|drop`                  | Damage the next packet        |
+//! | `delay MS`                                | Pause the capture             |
+//!
+//! Endpoint types are `bulk`, `interrupt` or `isochronous`. Numbers may be
+//! given in decimal or in hex with a `0x` prefix, and data is given as hex
+//! bytes, optionally separated by colons, e.g. `55:AA:01`. For IN control
+//! transfers, the data is the device's response.
+//!
+//! A scenario can be replayed faster or slower than written, or one packet
+//! at a time, under the control of a [`Playback`].
+
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use anyhow::{Context, Error, bail};
+use futures_channel::oneshot;
+use futures_lite::future::block_on;
+
+use super::cynthion::{CynthionStop, CynthionStream, Speed};
+use crate::usb::{crc5, crc16, EndpointType, PID};
+
+/// Size of the transfers in which the emulated data is delivered.
+const TRANSFER_SIZE: usize = 0x4000;
+
+/// Slowest speed at which a scenario can be replayed.
+pub const MIN_SPEED: f64 = 0.1;
+
+/// Fastest speed at which a scenario can be replayed.
+pub const MAX_SPEED: f64 = 100.0;
+
+/// Longest time slept at once while replaying, so that changes of speed
+/// and requests to stop take effect promptly.
+const SLEEP_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A declared device, from which its descriptors are generated.
+struct Device {
+    vendor_id: u16,
+    product_id: u16,
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Copy, Clone)]
+struct Endpoint {
+    number: u8,
+    is_in: bool,
+    ep_type: EndpointType,
+    max_packet_size: u16,
+}
+
+/// Ways in which the next packet of a scenario can be damaged.
+#[derive(Copy, Clone)]
+enum Corruption {
+    /// Invert a bit of the CRC, in the next packet which has one.
+    Crc,
+    /// Make the PID check bits invalid.
+    Pid,
+    /// Leave the packet out of the capture.
+    Drop,
+}
+
+#[derive(Clone)]
+enum Event {
+    Packet(Vec<u8>),
+    Delay(Duration),
+}
+
+/// Traffic to be captured by an emulated analyzer.
+#[derive(Clone)]
+pub struct Scenario {
+    /// Name of the scenario, for display.
+    pub name: String,
+    /// Speed of the emulated bus.
+    pub speed: Speed,
+    events: Vec<Event>,
+}
+
+/// Control of the pace at which a scenario is replayed, which can be
+/// changed while it is being captured.
+///
+/// The speed scales the pauses made by `delay` commands. In step mode,
+/// each packet is held back until released by [`Playback::step`], and
+/// pauses are skipped.
+#[derive(Clone)]
+pub struct Playback(Arc<PlaybackState>);
+
+struct PlaybackState {
+    /// Speed as a multiple of that written, stored as the bits of an f64.
+    speed: AtomicU64,
+    stepping: AtomicBool,
+    /// Number of packets released in step mode and not yet sent.
+    steps: AtomicU64,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback(Arc::new(PlaybackState {
+            speed: AtomicU64::new(1.0f64.to_bits()),
+            stepping: AtomicBool::new(false),
+            steps: AtomicU64::new(0),
+        }))
+    }
+}
+
+impl Playback {
+    pub fn new() -> Playback {
+        Playback::default()
+    }
+
+    pub fn speed(&self) -> f64 {
+        f64::from_bits(self.0.speed.load(Acquire))
+    }
+
+    /// Set the speed, as a multiple of that written in the scenario.
+    pub fn set_speed(&self, speed: f64) -> Result<(), Error> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+            bail!("Replay speed must be from {MIN_SPEED}x to {MAX_SPEED}x")
+        }
+        self.0.speed.store(speed.to_bits(), Release);
+        Ok(())
+    }
+
+    pub fn stepping(&self) -> bool {
+        self.0.stepping.load(Acquire)
+    }
+
+    /// Start or stop holding back packets until each is released.
+    pub fn set_stepping(&self, stepping: bool) {
+        self.0.steps.store(0, Release);
+        self.0.stepping.store(stepping, Release);
+    }
+
+    /// Release the next packet, in step mode.
+    pub fn step(&self) {
+        self.0.steps.fetch_add(1, AcqRel);
+    }
+
+    /// Wait until a packet may be sent. Returns false if stopped first.
+    fn wait_for_step(&self, stop_requested: &mut dyn FnMut() -> bool)
+        -> bool
+    {
+        loop {
+            if stop_requested() {
+                return false;
+            }
+            if !self.stepping() {
+                return true;
+            }
+            let released = self.0.steps
+                .fetch_update(AcqRel, Acquire, |steps| steps.checked_sub(1))
+                .is_ok();
+            if released {
+                return true;
+            }
+            sleep(SLEEP_INTERVAL);
+        }
+    }
+
+    /// Pause for a duration as written, scaled by the speed, which may
+    /// change during the pause. Returns false if stopped first.
+    fn pause(&self,
+             mut remaining: Duration,
+             stop_requested: &mut dyn FnMut() -> bool)
+        -> bool
+    {
+        while !remaining.is_zero() && !self.stepping() {
+            if stop_requested() {
+                return false;
+            }
+            let speed = self.speed();
+            let interval = remaining.div_f64(speed).min(SLEEP_INTERVAL);
+            if interval.is_zero() {
+                break;
+            }
+            sleep(interval);
+            remaining = remaining.saturating_sub(interval.mul_f64(speed));
+        }
+        true
+    }
+}
+
+/// State used while generating the packets of a scenario.
+struct Generator {
+    speed: Speed,
+    devices: HashMap<u8, Device>,
+    toggles: HashMap<(u8, u8, bool), bool>,
+    sof_count: u16,
+    corruption: Option<Corruption>,
+    events: Vec<Event>,
+}
+
+impl Scenario {
+    /// Load a scenario from a file.
+    pub fn load(path: &Path) -> Result<Scenario, Error> {
+        let text = read_to_string(path).with_context(|| format!(
+            "Failed to read scenario {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into());
+        Scenario::parse(&name, &text)
+            .with_context(|| format!("Invalid scenario {}", path.display()))
+    }
+
+    /// Parse a scenario from its text.
+    pub fn parse(name: &str, text: &str) -> Result<Scenario, Error> {
+        let mut generator = Generator {
+            speed: Speed::High,
+            devices: HashMap::new(),
+            toggles: HashMap::new(),
+            sof_count: 0,
+            corruption: None,
+            events: Vec::new(),
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let Some((command, args)) = words.split_first() {
+                generator.command(command, args).with_context(||
+                    format!("Line {}: {}", index + 1, line.trim()))?;
+            }
+        }
+        Ok(Scenario {
+            name: name.to_string(),
+            speed: generator.speed,
+            events: generator.events,
+        })
+    }
+
+    /// The packets of the scenario, as they would be captured.
+    pub fn packets(&self) -> impl Iterator<Item=&[u8]> {
+        self.events.iter().filter_map(|event| match event {
+            Event::Packet(bytes) => Some(bytes.as_slice()),
+            Event::Delay(_) => None,
+        })
+    }
+
+    /// Start capturing the scenario's traffic.
+    ///
+    /// As with a real analyzer, the capture continues until stopped, but
+    /// the stream of packets ends once the scenario is complete.
+    pub fn start<F>(&self, result_handler: F)
+        -> Result<(CynthionStream, CynthionStop), Error>
+        where F: FnOnce(Result<(), Error>) + Send + 'static
+    {
+        self.start_with_playback(Playback::new(), result_handler)
+    }
+
+    /// Start capturing the scenario's traffic, at the pace set by the
+    /// given playback control.
+    pub fn start_with_playback<F>(&self, playback: Playback, result_handler: F)
+        -> Result<(CynthionStream, CynthionStop), Error>
+        where F: FnOnce(Result<(), Error>) + Send + 'static
+    {
+        let (tx, rx) = mpsc::channel();
+        let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+        let events = self.events.clone();
+        let run_capture = move || {
+            println!("Emulated capture started");
+            let mut transfer = Vec::with_capacity(TRANSFER_SIZE);
+            let mut stopped = false;
+            let mut stop_requested =
+                || !matches!(stop_rx.try_recv(), Ok(None));
+            for event in events {
+                if stop_requested() {
+                    stopped = true;
+                    break;
+                }
+                match event {
+                    Event::Packet(bytes) => {
+                        if !playback.wait_for_step(&mut stop_requested) {
+                            stopped = true;
+                            break;
+                        }
+                        transfer.extend_from_slice(
+                            &(bytes.len() as u16).to_be_bytes());
+                        transfer.extend_from_slice(&bytes);
+                        while transfer.len() >= TRANSFER_SIZE {
+                            let rest = transfer.split_off(TRANSFER_SIZE);
+                            tx.send(std::mem::replace(&mut transfer, rest))
+                                .context("Failed sending capture data")?;
+                        }
+                        // Deliver each packet as soon as it is released.
+                        if playback.stepping() && !transfer.is_empty() {
+                            tx.send(std::mem::take(&mut transfer))
+                                .context("Failed sending capture data")?;
+                        }
+                    },
+                    Event::Delay(duration) => {
+                        if !transfer.is_empty() {
+                            tx.send(std::mem::take(&mut transfer))
+                                .context("Failed sending capture data")?;
+                        }
+                        if !playback.pause(duration, &mut stop_requested) {
+                            stopped = true;
+                            break;
+                        }
+                    },
+                }
+            }
+            if !transfer.is_empty() {
+                tx.send(transfer).context("Failed sending capture data")?;
+            }
+            // End the stream, but keep running until asked to stop.
+            drop(tx);
+            if !stopped {
+                let _ = block_on(stop_rx);
+            }
+            println!("Emulated capture stopped");
+            Ok(())
+        };
+        let worker = spawn(move || result_handler(run_capture()));
+        Ok((
+            CynthionStream::new(rx),
+            CynthionStop {
+                stop_request: stop_tx,
+                worker,
+            }
+        ))
+    }
+}
+
+impl Generator {
+    fn command(&mut self, command: &str, args: &[&str])
+        -> Result<(), Error>
+    {
+        use PID::*;
+        match (command, args) {
+            ("speed", [speed]) => {
+                self.speed = match *speed {
+                    "high" => Speed::High,
+                    "full" => Speed::Full,
+                    "low" => Speed::Low,
+                    _ => bail!("Unknown speed '{speed}'"),
+                };
+            },
+            ("device", [address, options @ ..]) => {
+                let mut device = Device {
+                    vendor_id: 0,
+                    product_id: 0,
+                    endpoints: Vec::new(),
+                };
+                for option in options {
+                    match option.split_once('=') {
+                        Some(("vid", value)) =>
+                            device.vendor_id = parse_number(value)?,
+                        Some(("pid", value)) =>
+                            device.product_id = parse_number(value)?,
+                        _ => bail!("Unknown device option '{option}'"),
+                    }
+                }
+                self.devices.insert(parse_address(address)?, device);
+            },
+            ("endpoint", [address, number, direction, ep_type, max]) => {
+                let endpoint = Endpoint {
+                    number: parse_endpoint(number)?,
+                    is_in: parse_direction(direction)?,
+                    ep_type: match *ep_type {
+                        "bulk" => EndpointType::Bulk,
+                        "interrupt" => EndpointType::Interrupt,
+                        "isochronous" => EndpointType::Isochronous,
+                        _ => bail!("Unknown endpoint type '{ep_type}'"),
+                    },
+                    max_packet_size: parse_number(max)?,
+                };
+                if endpoint.number == 0 || endpoint.max_packet_size == 0 {
+                    bail!("Endpoint number and size must not be zero")
+                }
+                self.device(parse_address(address)?)?
+                    .endpoints
+                    .push(endpoint);
+            },
+            ("enumerate", [address]) => self.enumerate(parse_address(address)?)?,
+            ("sof", [count]) => {
+                for _ in 0..parse_number::<u32>(count)? {
+                    self.sof();
+                }
+            },
+            ("control", [address, request_type, request, value, index,
+                         data @ ..]) =>
+            {
+                let request_type: u8 = parse_number(request_type)?;
+                let data = parse_data(data)?;
+                let mut setup = [0; 8];
+                setup[0] = request_type;
+                setup[1] = parse_number(request)?;
+                setup[2..4].copy_from_slice(
+                    &parse_number::<u16>(value)?.to_le_bytes());
+                setup[4..6].copy_from_slice(
+                    &parse_number::<u16>(index)?.to_le_bytes());
+                setup[6..8].copy_from_slice(
+                    &u16::try_from(data.len())?.to_le_bytes());
+                self.control(parse_address(address)?, setup, &data);
+            },
+            ("in" | "out", [address, number, data @ ..]) => {
+                let address = parse_address(address)?;
+                let endpoint = self.endpoint(
+                    address, parse_endpoint(number)?, command == "in")?;
+                let data = parse_data(data)?;
+                self.transfer(address, endpoint, &data);
+            },
+            ("nak", [address, number, direction, count @ ..]) => {
+                let count = match count {
+                    [] => 1,
+                    [count] => parse_number(count)?,
+                    _ => bail!("Too many arguments"),
+                };
+                let (address, number) =
+                    (parse_address(address)?, parse_endpoint(number)?);
+                let is_in = parse_direction(direction)?;
+                for _ in 0..count {
+                    self.handshake_only(address, number, is_in, NAK);
+                }
+            },
+            ("stall", [address, number, direction]) => {
+                let (address, number) =
+                    (parse_address(address)?, parse_endpoint(number)?);
+                let is_in = parse_direction(direction)?;
+                self.handshake_only(address, number, is_in, STALL);
+            },
+            ("corrupt", [kind]) => {
+                self.corruption = Some(match *kind {
+                    "crc" => Corruption::Crc,
+                    "pid" => Corruption::Pid,
+                    "drop" => Corruption::Drop,
+                    _ => bail!("Unknown corruption '{kind}'"),
+                });
+            },
+            ("delay", [ms]) => {
+                let ms = parse_number(ms)?;
+                self.events.push(Event::Delay(Duration::from_millis(ms)));
+            },
+            ("speed" | "device" | "endpoint" | "enumerate" | "sof" |
+             "control" | "in" | "out" | "nak" | "stall" | "corrupt" |
+             "delay", _) => bail!("Wrong number of arguments"),
+            _ => bail!("Unknown command '{command}'"),
+        }
+        Ok(())
+    }
+
+    fn device(&mut self, address: u8) -> Result<&mut Device, Error> {
+        self.devices
+            .get_mut(&address)
+            .with_context(|| format!("Device {address} is not declared"))
+    }
+
+    fn endpoint(&mut self, address: u8, number: u8, is_in: bool)
+        -> Result<Endpoint, Error>
+    {
+        let direction = if is_in { "IN" } else { "OUT" };
+        self.device(address)?
+            .endpoints
+            .iter()
+            .find(|ep| ep.number == number && ep.is_in == is_in)
+            .copied()
+            .with_context(|| format!(
+                "Endpoint {address}.{number} {direction} is not declared"))
+    }
+
+    /// Maximum packet size for control transfers at the current speed.
+    fn ep0_max(&self) -> usize {
+        match self.speed {
+            Speed::Low => 8,
+            _ => 64,
+        }
+    }
+
+    fn enumerate(&mut self, address: u8) -> Result<(), Error> {
+        let ep0_max = self.ep0_max() as u8;
+        let device = self.device(address)?;
+        let device_descriptor = device_descriptor(device, ep0_max);
+        let config_descriptor = config_descriptor(device);
+        let config_length = config_descriptor.len() as u16;
+        // GET_DESCRIPTOR(Device) at the default address.
+        self.control(0, setup(0x80, 6, 0x0100, 0, 64), &device_descriptor);
+        // SET_ADDRESS.
+        self.control(0, setup(0x00, 5, address as u16, 0, 0), &[]);
+        // GET_DESCRIPTOR(Device), then GET_DESCRIPTOR(Configuration),
+        // first for its header and then in full.
+        self.control(address, setup(0x80, 6, 0x0100, 0, 18),
+                     &device_descriptor);
+        self.control(address, setup(0x80, 6, 0x0200, 0, 9),
+                     &config_descriptor[..9]);
+        self.control(address, setup(0x80, 6, 0x0200, 0, config_length),
+                     &config_descriptor);
+        // SET_CONFIGURATION.
+        self.control(address, setup(0x00, 9, 1, 0, 0), &[]);
+        Ok(())
+    }
+
+    fn control(&mut self, address: u8, setup: [u8; 8], data: &[u8]) {
+        use PID::*;
+        let is_in = setup[0] & 0x80 != 0;
+        self.token(SETUP, address, 0);
+        self.data(DATA0, &setup);
+        self.packet(vec![ACK.into()]);
+        let mut pid = DATA1;
+        for chunk in data.chunks(self.ep0_max()) {
+            self.token(if is_in { IN } else { OUT }, address, 0);
+            self.data(pid, chunk);
+            self.packet(vec![ACK.into()]);
+            pid = if pid == DATA0 { DATA1 } else { DATA0 };
+        }
+        // The status stage is in the opposite direction to the data.
+        let status = if is_in && !data.is_empty() { OUT } else { IN };
+        self.token(status, address, 0);
+        self.data(DATA1, &[]);
+        self.packet(vec![ACK.into()]);
+    }
+
+    fn transfer(&mut self, address: u8, endpoint: Endpoint, data: &[u8]) {
+        use PID::*;
+        let max = endpoint.max_packet_size as usize;
+        let token = if endpoint.is_in { IN } else { OUT };
+        let isochronous = endpoint.ep_type == EndpointType::Isochronous;
+        let mut chunks: Vec<&[u8]> = data.chunks(max).collect();
+        // A transfer ends with a short packet, which may be empty.
+        if data.len() % max == 0 && !isochronous {
+            chunks.push(&[]);
+        }
+        for chunk in chunks {
+            self.token(token, address, endpoint.number);
+            if isochronous {
+                self.data(DATA0, chunk);
+                continue;
+            }
+            let key = (address, endpoint.number, endpoint.is_in);
+            let toggle = self.toggles.entry(key).or_insert(false);
+            let pid = if *toggle { DATA1 } else { DATA0 };
+            *toggle = !*toggle;
+            self.data(pid, chunk);
+            self.packet(vec![ACK.into()]);
+        }
+    }
+
+    /// A transaction answered with a handshake rather than data.
+    fn handshake_only(&mut self, address: u8, number: u8, is_in: bool,
+                      handshake: PID)
+    {
+        use PID::*;
+        if is_in {
+            self.token(IN, address, number);
+        } else {
+            self.token(OUT, address, number);
+            let key = (address, number, false);
+            let toggle = *self.toggles.get(&key).unwrap_or(&false);
+            self.data(if toggle { DATA1 } else { DATA0 }, &[]);
+        }
+        self.packet(vec![handshake.into()]);
+    }
+
+    fn sof(&mut self) {
+        // At high speed there are eight microframes in each frame.
+        let frame = match self.speed {
+            Speed::High => self.sof_count / 8,
+            _ => self.sof_count,
+        } & 0x7FF;
+        self.sof_count = self.sof_count.wrapping_add(1);
+        let crc = crc5(frame, 11);
+        self.packet(vec![
+            PID::SOF.into(),
+            frame as u8,
+            (frame >> 8) as u8 | (crc << 3),
+        ]);
+    }
+
+    fn token(&mut self, pid: PID, address: u8, endpoint: u8) {
+        let value = address as u16 | (endpoint as u16) << 7;
+        let crc = crc5(value, 11);
+        self.packet(vec![
+            pid.into(),
+            value as u8,
+            (value >> 8) as u8 | (crc << 3),
+        ]);
+    }
+
+    fn data(&mut self, pid: PID, payload: &[u8]) {
+        let mut packet = Vec::with_capacity(payload.len() + 3);
+        packet.push(pid.into());
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&crc16(payload).to_le_bytes());
+        self.packet(packet);
+    }
+
+    fn packet(&mut self, mut packet: Vec<u8>) {
+        match self.corruption {
+            Some(Corruption::Crc) if packet.len() >= 3 => {
+                if let Some(last) = packet.last_mut() {
+                    *last ^= 0x80;
+                }
+            },
+            // Handshakes have no CRC, so wait for a packet which does.
+            Some(Corruption::Crc) => {
+                self.events.push(Event::Packet(packet));
+                return;
+            },
+            Some(Corruption::Pid) => packet[0] ^= 0x10,
+            Some(Corruption::Drop) => {
+                self.corruption = None;
+                return;
+            },
+            None => {},
+        }
+        self.corruption = None;
+        self.events.push(Event::Packet(packet));
+    }
+}
+
+fn setup(request_type: u8, request: u8, value: u16, index: u16, length: u16)
+    -> [u8; 8]
+{
+    let mut setup = [request_type, request, 0, 0, 0, 0, 0, 0];
+    setup[2..4].copy_from_slice(&value.to_le_bytes());
+    setup[4..6].copy_from_slice(&index.to_le_bytes());
+    setup[6..8].copy_from_slice(&length.to_le_bytes());
+    setup
+}
+
+fn device_descriptor(device: &Device, ep0_max: u8) -> Vec<u8> {
+    let mut bytes = vec![18, 1, 0x00, 0x02, 0, 0, 0, ep0_max];
+    bytes.extend_from_slice(&device.vendor_id.to_le_bytes());
+    bytes.extend_from_slice(&device.product_id.to_le_bytes());
+    bytes.extend_from_slice(&[0x00, 0x01, 0, 0, 0, 1]);
+    bytes
+}
+
+fn config_descriptor(device: &Device) -> Vec<u8> {
+    let endpoint_count = device.endpoints.len() as u8;
+    let total_length = 9 + 9 + 7 * endpoint_count as u16;
+    let mut bytes = vec![9, 2];
+    bytes.extend_from_slice(&total_length.to_le_bytes());
+    bytes.extend_from_slice(&[1, 1, 0, 0x80, 50]);
+    bytes.extend_from_slice(&[9, 4, 0, 0, endpoint_count, 0xFF, 0, 0, 0]);
+    for ep in &device.endpoints {
+        let address = ep.number | if ep.is_in { 0x80 } else { 0 };
+        let interval = match ep.ep_type {
+            EndpointType::Bulk => 0,
+            _ => 1,
+        };
+        bytes.extend_from_slice(&[7, 5, address, ep.ep_type as u8]);
+        bytes.extend_from_slice(&ep.max_packet_size.to_le_bytes());
+        bytes.push(interval);
+    }
+    bytes
+}
+
+fn parse_number<T: TryFrom<u64>>(text: &str) -> Result<T, Error> {
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    }.with_context(|| format!("Invalid number '{text}'"))?;
+    T::try_from(value).ok().with_context(|| format!("{text} is out of range"))
+}
+
+fn parse_address(text: &str) -> Result<u8, Error> {
+    match parse_number(text)? {
+        address @ 0..=127 => Ok(address),
+        _ => bail!("Device address {text} is out of range"),
+    }
+}
+
+fn parse_endpoint(text: &str) -> Result<u8, Error> {
+    match parse_number(text)? {
+        number @ 0..=15 => Ok(number),
+        _ => bail!("Endpoint number {text} is out of range"),
+    }
+}
+
+fn parse_direction(text: &str) -> Result<bool, Error> {
+    match text {
+        "in" => Ok(true),
+        "out" => Ok(false),
+        _ => bail!("Unknown direction '{text}', expected in or out"),
+    }
+}
+
+fn parse_data(words: &[&str]) -> Result<Vec<u8>, Error> {
+    let hex: String = words.concat().replace(':', "");
+    if hex.len() % 2 != 0 {
+        bail!("Data must be a whole number of hex bytes")
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16)
+            .with_context(|| format!("Invalid hex byte '{}'", &hex[i..i + 2])))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::capture::{
+        create_capture,
+        CaptureReader,
+        DeviceId,
+        ItemSource,
+        TrafficItem,
+    };
+    use crate::decoder::Decoder;
+    use crate::errors::{packet_error, ErrorKind};
+
+    fn decode<'p>(packets: impl Iterator<Item=&'p [u8]>) -> CaptureReader {
+        let (writer, reader) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in packets.enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        reader
+    }
+
+    #[test]
+    fn test_scenario() {
+        let scenario = Scenario::load(
+            Path::new("../tests/scenarios/bulk-device.txt")).unwrap();
+        let mut capture = decode(scenario.packets());
+        for packet in scenario.packets().take(20) {
+            assert!(packet_error(packet).is_none());
+        }
+
+        // The device was enumerated, so its descriptors are known.
+        let data = capture.device_data(&DeviceId::from(1)).unwrap();
+        let descriptor = data.device_descriptor.load_full().unwrap();
+        assert_eq!(descriptor.vendor_id, 0x1d50);
+        assert_eq!(descriptor.product_id, 0x60e6);
+        let config = data.configuration(&crate::usb::ConfigNum(1)).unwrap();
+        assert_eq!(config.interfaces.len(), 1);
+
+        // The injected errors and the STALL are visible in the capture.
+        let errors: Vec<ErrorKind> = scenario.packets()
+            .filter_map(|packet| packet_error(packet).map(|(kind, _)| kind))
+            .collect();
+        assert_eq!(errors,
+                   [ErrorKind::Crc, ErrorKind::Protocol, ErrorKind::Halted]);
+        let item_count = capture.item_index.len();
+        let item_errors = (0..item_count)
+            .filter(|i| {
+                let item = ItemSource::<TrafficItem>::item(
+                    &mut capture, None, *i).unwrap();
+                capture.item_error(&item).unwrap()
+            })
+            .count();
+        assert!(item_errors >= 3);
+    }
+
+    #[test]
+    fn test_emulated_stream() {
+        let scenario = Scenario::load(
+            Path::new("../tests/scenarios/bulk-device.txt")).unwrap();
+        let (stream, stop) = scenario.start(|result| result.unwrap()).unwrap();
+        let streamed: Vec<Vec<u8>> = stream
+            .map(|packet| packet.to_vec())
+            .collect();
+        stop.stop().unwrap();
+        let expected: Vec<Vec<u8>> = scenario.packets()
+            .map(|packet| packet.to_vec())
+            .collect();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_playback() {
+        let scenario = Scenario::parse("playback", "
+            sof 4
+            delay 2000
+            sof 4
+        ").unwrap();
+        let expected: Vec<Vec<u8>> = scenario.packets()
+            .map(|packet| packet.to_vec())
+            .collect();
+        let playback = Playback::new();
+        assert!(playback.set_speed(0.05).is_err());
+        assert!(playback.set_speed(101.0).is_err());
+
+        // At 100x, the delay is cut to 20ms.
+        playback.set_speed(MAX_SPEED).unwrap();
+        let start = Instant::now();
+        let (stream, stop) = scenario
+            .start_with_playback(playback.clone(), |result| result.unwrap())
+            .unwrap();
+        let streamed: Vec<Vec<u8>> = stream
+            .map(|packet| packet.to_vec())
+            .collect();
+        stop.stop().unwrap();
+        assert_eq!(streamed, expected);
+        assert!(start.elapsed() < Duration::from_millis(1000));
+
+        // In step mode, packets are delivered one at a time as released,
+        // and the delay is skipped.
+        playback.set_speed(MIN_SPEED).unwrap();
+        playback.set_stepping(true);
+        let (mut stream, stop) = scenario
+            .start_with_playback(playback.clone(), |result| result.unwrap())
+            .unwrap();
+        for packet in &expected {
+            playback.step();
+            assert_eq!(&stream.next().unwrap().to_vec(), packet);
+        }
+        assert!(stream.next().is_none());
+        stop.stop().unwrap();
+
+        // Leaving step mode releases the rest.
+        let (stream, stop) = scenario
+            .start_with_playback(playback.clone(), |result| result.unwrap())
+            .unwrap();
+        playback.step();
+        sleep(Duration::from_millis(50));
+        playback.set_stepping(false);
+        playback.set_speed(MAX_SPEED).unwrap();
+        assert_eq!(stream.count(), expected.len());
+        stop.stop().unwrap();
+    }
+
+    #[test]
+    fn test_scenario_errors() {
+        for (text, message) in [
+            ("speed warp", "Line 1: speed warp"),
+            ("in 5 1 00", "Line 1: in 5 1 00"),
+            ("\n\nsof", "Line 3: sof"),
+        ] {
+            let error = Scenario::parse("test", text).err().unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
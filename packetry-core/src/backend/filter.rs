@@ -0,0 +1,354 @@
+//! Filtering of captured packets, before they are stored.
+//!
+//! A capture filter selects packets by device address, endpoint number and
+//! PID. Data and handshake packets do not carry an address, so they are
+//! kept or dropped along with the token packet which began their
+//! transaction. Packets not addressed to any device, such as SOF packets,
+//! are dropped if any device or endpoint is selected.
+
+use std::collections::VecDeque;
+use std::ops::Deref;
+
+use anyhow::{Context, Error, bail};
+
+use crate::usb::{PacketFields, PID};
+
+/// Values of a field to include, and to exclude.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Selection<T> {
+    include: Vec<T>,
+    exclude: Vec<T>,
+}
+
+impl<T: PartialEq> Selection<T> {
+    fn new() -> Self {
+        Selection {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(&self, value: &T) -> bool {
+        (self.include.is_empty() || self.include.contains(value)) &&
+            !self.exclude.contains(value)
+    }
+
+    /// Add values from a comma-separated list, where values to be excluded
+    /// are prefixed with `!`.
+    fn add<F>(&mut self, list: &str, parse: F) -> Result<(), Error>
+        where F: Fn(&str) -> Result<T, Error>
+    {
+        for item in list.split(',') {
+            let item = item.trim();
+            match item.strip_prefix('!') {
+                Some(value) => self.exclude.push(parse(value.trim())?),
+                None => self.include.push(parse(item)?),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which packets to keep during a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureFilter {
+    devices: Selection<u8>,
+    endpoints: Selection<u8>,
+    pids: Selection<PID>,
+}
+
+impl Default for CaptureFilter {
+    fn default() -> Self {
+        CaptureFilter {
+            devices: Selection::new(),
+            endpoints: Selection::new(),
+            pids: Selection::new(),
+        }
+    }
+}
+
+impl CaptureFilter {
+    /// Parse a capture filter such as `device=5 endpoint=0,1 pid=!SOF,!NAK`.
+    pub fn parse(text: &str) -> Result<CaptureFilter, Error> {
+        let mut filter = CaptureFilter::default();
+        for term in text.split_whitespace() {
+            let (field, list) = term
+                .split_once('=')
+                .with_context(|| format!(
+                    "Expected 'field=values' in capture filter, \
+                     found '{term}'"))?;
+            filter.add(field, list)?;
+        }
+        Ok(filter)
+    }
+
+    /// Add values of a field to select, as a comma-separated list.
+    ///
+    /// Values prefixed with `!` are excluded rather than included.
+    pub fn add(&mut self, field: &str, list: &str) -> Result<(), Error> {
+        match field {
+            "device" => self.devices.add(list, |value|
+                parse_number(value, "device address", 127)),
+            "endpoint" => self.endpoints.add(list, |value|
+                parse_number(value, "endpoint number", 15)),
+            "pid" => self.pids.add(list, parse_pid),
+            _ => bail!("Unknown capture filter field '{field}', \
+                        expected device, endpoint or pid"),
+        }
+    }
+
+    /// Whether this filter keeps all packets.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty() &&
+            self.endpoints.is_empty() &&
+            self.pids.is_empty()
+    }
+
+    /// Whether packets not addressed to any device are kept.
+    fn keeps_unaddressed(&self) -> bool {
+        self.devices.is_empty() && self.endpoints.is_empty()
+    }
+}
+
+fn parse_number(text: &str, name: &str, max: u8) -> Result<u8, Error> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    match result {
+        Ok(number) if number <= max => Ok(number),
+        _ => bail!("Invalid {name} '{text}', expected 0 to {max}"),
+    }
+}
+
+fn parse_pid(text: &str) -> Result<PID, Error> {
+    use PID::*;
+    for pid in [OUT, IN, SOF, SETUP, DATA0, DATA1, DATA2, MDATA,
+                ACK, NAK, STALL, NYET, PING, SPLIT, ERR, RSVD, Malformed]
+    {
+        if text.eq_ignore_ascii_case(&pid.to_string()) {
+            return Ok(pid);
+        }
+    }
+    bail!("Unknown PID '{text}'")
+}
+
+/// An iterator adapter which drops packets not selected by a capture filter.
+pub struct FilteredPackets<I> where I: Iterator {
+    packets: I,
+    filter: CaptureFilter,
+    /// Whether packets following the last token packet are being kept.
+    keep_transaction: bool,
+    /// A SPLIT packet waiting for the token packet which follows it.
+    pending_split: Option<I::Item>,
+    /// Packets which have been selected and are ready to be returned.
+    ready: VecDeque<I::Item>,
+    /// Number of packets dropped so far.
+    dropped: u64,
+}
+
+impl<I> FilteredPackets<I>
+    where I: Iterator, I::Item: Deref<Target=[u8]>
+{
+    pub fn new(packets: I, filter: CaptureFilter) -> Self {
+        FilteredPackets {
+            packets,
+            filter,
+            keep_transaction: false,
+            pending_split: None,
+            ready: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Number of packets dropped by the filter so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn keep(&mut self, packet: I::Item, keep: bool) {
+        if keep {
+            self.ready.push_back(packet);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    fn handle_packet(&mut self, packet: I::Item) {
+        use PID::*;
+        let pid = match packet.first() {
+            Some(byte) => PID::from(*byte),
+            None => Malformed,
+        };
+        let token = match pid {
+            SETUP | IN | OUT | PING if packet.len() == 3 =>
+                match PacketFields::from_packet(&packet) {
+                    PacketFields::Token(token) => Some(token),
+                    _ => None,
+                },
+            _ => None,
+        };
+        let unaddressed = self.filter.keeps_unaddressed();
+        if let Some(token) = token {
+            // A token begins a new transaction.
+            self.keep_transaction =
+                self.filter.devices.matches(&token.device_address().0) &&
+                self.filter.endpoints.matches(&token.endpoint_number().0);
+            if let Some(split) = self.pending_split.take() {
+                let keep = self.keep_transaction &&
+                    self.filter.pids.matches(&SPLIT);
+                self.keep(split, keep);
+            }
+            let keep = self.keep_transaction && self.filter.pids.matches(&pid);
+            self.keep(packet, keep);
+            return;
+        }
+        // A SPLIT packet not followed by a token is treated as unaddressed.
+        if let Some(split) = self.pending_split.take() {
+            let keep = unaddressed && self.filter.pids.matches(&SPLIT);
+            self.keep(split, keep);
+        }
+        match pid {
+            SPLIT if packet.len() == 4 => {
+                self.pending_split = Some(packet);
+            },
+            DATA0 | DATA1 | DATA2 | MDATA | ACK | NAK | STALL | NYET | ERR => {
+                let keep = self.keep_transaction &&
+                    self.filter.pids.matches(&pid);
+                self.keep(packet, keep);
+            },
+            _ => {
+                let keep = unaddressed && self.filter.pids.matches(&pid);
+                self.keep(packet, keep);
+            }
+        }
+    }
+}
+
+impl<I> Iterator for FilteredPackets<I>
+    where I: Iterator, I::Item: Deref<Target=[u8]>
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        loop {
+            if let Some(packet) = self.ready.pop_front() {
+                return Some(packet);
+            }
+            match self.packets.next() {
+                Some(packet) => self.handle_packet(packet),
+                None => {
+                    // Drop any SPLIT packet left without a token.
+                    if self.pending_split.take().is_some() {
+                        self.dropped += 1;
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(pid: PID, address: u16, endpoint: u16) -> Vec<u8> {
+        let fields = address | endpoint << 7;
+        vec![pid as u8, fields as u8, (fields >> 8) as u8]
+    }
+
+    fn data(pid: PID, bytes: &[u8]) -> Vec<u8> {
+        let mut packet = vec![pid as u8];
+        packet.extend_from_slice(bytes);
+        packet.extend_from_slice(&[0, 0]);
+        packet
+    }
+
+    fn handshake(pid: PID) -> Vec<u8> {
+        vec![pid as u8]
+    }
+
+    fn filter(text: &str, packets: &[Vec<u8>]) -> (Vec<Vec<u8>>, u64) {
+        let filter = CaptureFilter::parse(text).unwrap();
+        let mut filtered =
+            FilteredPackets::new(packets.iter().cloned(), filter);
+        let kept = filtered.by_ref().collect();
+        (kept, filtered.dropped())
+    }
+
+    #[test]
+    fn test_parse() {
+        let filter = CaptureFilter::parse("device=5,0x10 pid=!sof,!NAK")
+            .unwrap();
+        assert_eq!(filter.devices.include, vec![5, 16]);
+        assert!(filter.endpoints.is_empty());
+        assert_eq!(filter.pids.exclude, vec![PID::SOF, PID::NAK]);
+        assert!(CaptureFilter::parse("").unwrap().is_empty());
+        for (text, error) in [
+            ("device", "Expected 'field=values' in capture filter, \
+                        found 'device'"),
+            ("device=128", "Invalid device address '128', expected 0 to 127"),
+            ("endpoint=x", "Invalid endpoint number 'x', expected 0 to 15"),
+            ("pid=FOO", "Unknown PID 'FOO'"),
+            ("speed=high", "Unknown capture filter field 'speed', \
+                            expected device, endpoint or pid"),
+        ] {
+            let result = CaptureFilter::parse(text);
+            assert_eq!(result.unwrap_err().to_string(), error, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        use PID::*;
+        let sof = vec![SOF as u8, 0x00, 0x10];
+        let setup = token(SETUP, 5, 0);
+        let setup_data = data(DATA0, &[0x80, 6, 0, 1, 0, 0, 64, 0]);
+        let in_other = token(IN, 6, 1);
+        let in_data = data(DATA1, &[1, 2, 3]);
+        let packets = vec![
+            sof.clone(),
+            setup.clone(), setup_data.clone(), handshake(ACK),
+            in_other.clone(), in_data.clone(), handshake(ACK),
+            sof.clone(),
+        ];
+
+        // With an empty filter, everything is kept.
+        let (kept, dropped) = filter("", &packets);
+        assert_eq!(kept, packets);
+        assert_eq!(dropped, 0);
+
+        // Selecting a device keeps only its transactions.
+        let (kept, dropped) = filter("device=5", &packets);
+        assert_eq!(kept, vec![setup.clone(), setup_data.clone(), handshake(ACK)]);
+        assert_eq!(dropped, 5);
+
+        // Excluding an endpoint drops its transactions.
+        let (kept, _) = filter("endpoint=!1 pid=!SOF", &packets);
+        assert_eq!(kept, vec![setup, setup_data, handshake(ACK)]);
+
+        // Excluding PIDs drops just those packets.
+        let (kept, _) = filter("pid=!SOF,!ACK", &packets);
+        assert_eq!(kept.len(), 4);
+        assert_eq!(kept[2], in_other);
+        assert_eq!(kept[3], in_data);
+    }
+
+    #[test]
+    fn test_filter_split() {
+        use PID::*;
+        let split = vec![SPLIT as u8, 0x01, 0x02, 0x00];
+        let packets = vec![
+            split.clone(), token(IN, 7, 2), handshake(NAK),
+            split.clone(), token(IN, 8, 1), handshake(NAK),
+        ];
+        let (kept, dropped) = filter("device=7", &packets);
+        assert_eq!(kept, packets[..3]);
+        assert_eq!(dropped, 3);
+    }
+}
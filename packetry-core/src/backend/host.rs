@@ -0,0 +1,155 @@
+//! Fetching the descriptors of devices attached to this computer.
+//!
+//! An analyzer such as Cynthion only observes the bus, and cannot send
+//! requests to the devices on it. When the device being captured is
+//! attached to the computer running Packetry, its descriptors can instead
+//! be read through the operating system, for devices which enumerated
+//! before the capture began.
+
+use std::time::Duration;
+
+use anyhow::{Context, Error, bail};
+use nusb::DeviceInfo;
+
+use crate::overrides::KnownDescriptors;
+use crate::usb::DeviceAddr;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+const DEVICE_DESCRIPTOR: u8 = 1;
+const STRING_DESCRIPTOR: u8 = 3;
+
+/// A device attached to this computer.
+pub struct HostDevice {
+    info: DeviceInfo,
+}
+
+impl HostDevice {
+    /// List the devices attached to this computer.
+    pub fn list() -> Result<Vec<HostDevice>, Error> {
+        Ok(nusb::list_devices()
+            .context("Failed to list USB devices")?
+            .map(|info| HostDevice { info })
+            .collect())
+    }
+
+    /// List the devices attached with the given address, on any bus.
+    pub fn with_address(address: DeviceAddr) -> Result<Vec<HostDevice>, Error> {
+        Ok(HostDevice::list()?
+            .into_iter()
+            .filter(|device| device.address() == address)
+            .collect())
+    }
+
+    pub fn bus(&self) -> u8 {
+        self.info.bus_number()
+    }
+
+    pub fn address(&self) -> DeviceAddr {
+        DeviceAddr(self.info.device_address())
+    }
+
+    /// A description of the device, for choosing between devices.
+    pub fn description(&self) -> String {
+        let mut description = format!(
+            "Bus {}, device {}: {:04X}:{:04X}",
+            self.bus(), self.address(),
+            self.info.vendor_id(), self.info.product_id());
+        if let Some(product) = self.info.product_string() {
+            description.push(' ');
+            description.push_str(product);
+        }
+        description
+    }
+
+    /// Read the device's descriptors.
+    ///
+    /// The device and string descriptors are requested from the device,
+    /// which needs permission to open it. The configuration descriptors
+    /// are those read by the operating system when it was enumerated.
+    pub fn fetch_descriptors(&self) -> Result<KnownDescriptors, Error> {
+        let device = self.info.open().with_context(|| format!(
+            "Failed to open {}", self.description()))?;
+        let device_descriptor = device
+            .get_descriptor(DEVICE_DESCRIPTOR, 0, 0, TIMEOUT)
+            .context("Failed to read device descriptor")?;
+        if device_descriptor.len() != 18 {
+            bail!("Device descriptor has invalid length {}",
+                  device_descriptor.len())
+        }
+        let mut configurations = Vec::new();
+        let mut string_ids = vec![
+            device_descriptor[14],
+            device_descriptor[15],
+            device_descriptor[16],
+        ];
+        for config in device.configurations() {
+            let mut bytes = Vec::new();
+            for descriptor in config.descriptors() {
+                bytes.extend_from_slice(&descriptor);
+            }
+            string_ids.extend(config.string_index());
+            for alt in config.interface_alt_settings() {
+                string_ids.extend(alt.string_index());
+            }
+            configurations.push(bytes);
+        }
+        let config_number = device
+            .active_configuration()
+            .ok()
+            .map(|config| config.configuration_value())
+            .filter(|value| *value != 0);
+
+        // Strings are read in the first language the device supports.
+        // Devices with no strings may stall the request for languages.
+        let mut strings = Vec::new();
+        string_ids.retain(|id| *id != 0);
+        string_ids.sort_unstable();
+        string_ids.dedup();
+        if !string_ids.is_empty() {
+            if let Ok(languages) =
+                device.get_descriptor(STRING_DESCRIPTOR, 0, 0, TIMEOUT)
+            {
+                if languages.len() >= 4 {
+                    let language = u16::from_le_bytes(
+                        [languages[2], languages[3]]);
+                    for id in string_ids {
+                        if let Ok(string) = device.get_descriptor(
+                            STRING_DESCRIPTOR, id, language, TIMEOUT)
+                        {
+                            strings.push((id, string));
+                        }
+                    }
+                }
+                strings.push((0, languages));
+            }
+        }
+
+        Ok(KnownDescriptors {
+            device: self.address(),
+            device_descriptor,
+            configurations,
+            config_number,
+            strings,
+        })
+    }
+}
+
+/// Fetch the descriptors of every device on a bus of this computer.
+///
+/// Devices which cannot be opened are skipped, and their errors returned
+/// alongside the descriptors of the others.
+pub fn fetch_bus_descriptors(bus: u8)
+    -> Result<(Vec<KnownDescriptors>, Vec<Error>), Error>
+{
+    let mut descriptors = Vec::new();
+    let mut errors = Vec::new();
+    for device in HostDevice::list()? {
+        if device.bus() == bus {
+            match device.fetch_descriptors() {
+                Ok(known) => descriptors.push(known),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+    Ok((descriptors, errors))
+}
@@ -0,0 +1,8 @@
+//! Sources of live captures: analyzer hardware, and its emulation, and
+//! access to the devices attached to this computer.
+
+pub mod cynthion;
+pub mod emulator;
+pub mod filter;
+pub mod host;
+pub mod packet;
@@ -0,0 +1,53 @@
+//! Packets sliced from the buffers they were captured into.
+
+use std::fmt::Debug;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+/// A captured packet.
+///
+/// Packets share the reference-counted buffer of the transfer they arrived
+/// in, rather than each being copied into an allocation of its own. The
+/// buffer is freed once the last packet from it is dropped.
+#[derive(Clone)]
+pub struct Packet {
+    buffer: Arc<Vec<u8>>,
+    range: Range<usize>,
+}
+
+impl Packet {
+    /// A packet occupying the given range of a buffer.
+    pub fn new(buffer: Arc<Vec<u8>>, range: Range<usize>) -> Packet {
+        assert!(range.end <= buffer.len(), "Packet range outside buffer");
+        Packet { buffer, range }
+    }
+}
+
+impl From<Vec<u8>> for Packet {
+    fn from(bytes: Vec<u8>) -> Packet {
+        let range = 0..bytes.len();
+        Packet { buffer: Arc::new(bytes), range }
+    }
+}
+
+impl Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[self.range.clone()]
+    }
+}
+
+impl PartialEq for Packet {
+    fn eq(&self, other: &Packet) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Packet {}
+
+impl Debug for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
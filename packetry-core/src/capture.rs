@@ -0,0 +1,3124 @@
+//! Storage of decoded captures, and access to their traffic and devices.
+//!
+//! A capture has a single [`CaptureWriter`], used by the decoder, and any
+//! number of [`CaptureReader`]s, which may read it while it is written.
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::{Arc, RwLock};
+use std::mem::size_of;
+
+use crate::annotations::Annotations;
+use crate::markers::Markers;
+use crate::metadata::Metadata;
+use crate::errors::stored_packet_error;
+use crate::i18n::{tr, trf};
+use crate::id::{Id, HasLength};
+use crate::integrity::Integrity;
+use crate::filter::{Filter, FilteredItems};
+use crate::data_stream::{
+    data_stream, data_stream_with_block_size, DataWriter, DataReader};
+use crate::compact_index::{compact_index, CompactWriter, CompactReader};
+use crate::overrides::KnownDescriptors;
+use crate::rcu::SingleWriterRcu;
+use crate::traffic_index::TrafficIndex;
+use crate::usb_ids::database;
+use crate::vec_map::VecMap;
+use crate::usb::{self, prelude::*};
+use crate::util::{fmt_count, fmt_size};
+
+use anyhow::{Context, Error, bail};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use bytemuck_derive::{Pod, Zeroable};
+use num_enum::{IntoPrimitive, FromPrimitive};
+
+// Use 2MB block size for packet data, which is a large page size on x86_64.
+const PACKET_DATA_BLOCK_SIZE: usize = 0x200000;
+
+/// Capture state shared between readers and writers.
+pub struct CaptureShared {
+    pub device_data: ArcSwap<VecMap<DeviceId, Arc<DeviceData>>>,
+    pub endpoint_readers: ArcSwap<VecMap<EndpointId, Arc<EndpointReader>>>,
+    pub complete: AtomicBool,
+    /// Whether packets were reconstructed from a higher level capture, in
+    /// which case handshakes, data toggles and CRCs were not seen on the bus.
+    pub reconstructed: AtomicBool,
+    /// Names of the files a merged capture was read from.
+    pub sources: ArcSwap<Vec<String>>,
+    /// Bookmarks and comments added by the user.
+    pub annotations: ArcSwap<Annotations>,
+    /// Markers added during a live capture.
+    pub markers: ArcSwap<Markers>,
+    /// Details of the capture as a whole, entered by the user.
+    pub metadata: ArcSwap<Metadata>,
+    /// Hash and signature saved with a loaded capture, if any.
+    pub integrity: ArcSwapOption<Integrity>,
+    /// Index of the top-level items, built in the background.
+    pub traffic_index: RwLock<TrafficIndex>,
+}
+
+/// Unique handle for write access to a capture.
+pub struct CaptureWriter {
+    pub shared: Arc<CaptureShared>,
+    pub packet_data: DataWriter<u8, PACKET_DATA_BLOCK_SIZE>,
+    pub packet_index: CompactWriter<PacketId, PacketByteId, 2>,
+    pub packet_times: CompactWriter<PacketId, Timestamp, 4>,
+    pub packet_sources: DataWriter<u16>,
+    pub packet_source_index: CompactWriter<Id<u16>, PacketId>,
+    pub packet_lengths: DataWriter<u64>,
+    pub packet_length_index: CompactWriter<Id<u64>, PacketId>,
+    pub transaction_index: CompactWriter<TransactionId, PacketId>,
+    pub transfer_index: DataWriter<TransferIndexEntry>,
+    pub item_index: CompactWriter<TrafficItemId, TransferId>,
+    pub devices: DataWriter<Device>,
+    pub endpoints: DataWriter<Endpoint>,
+    pub endpoint_states: DataWriter<u8>,
+    pub endpoint_state_index: CompactWriter<TransferId, Id<u8>>,
+    pub end_index: CompactWriter<TransferId, TrafficItemId>,
+}
+
+/// Cloneable handle for read access to a capture.
+#[derive(Clone)]
+pub struct CaptureReader {
+    pub shared: Arc<CaptureShared>,
+    endpoint_readers: VecMap<EndpointId, EndpointReader>,
+    pub packet_data: DataReader<u8, PACKET_DATA_BLOCK_SIZE>,
+    pub packet_index: CompactReader<PacketId, PacketByteId>,
+    pub packet_times: CompactReader<PacketId, Timestamp>,
+    pub packet_sources: DataReader<u16>,
+    pub packet_source_index: CompactReader<Id<u16>, PacketId>,
+    pub packet_lengths: DataReader<u64>,
+    pub packet_length_index: CompactReader<Id<u64>, PacketId>,
+    pub transaction_index: CompactReader<TransactionId, PacketId>,
+    pub transfer_index: DataReader<TransferIndexEntry>,
+    pub item_index: CompactReader<TrafficItemId, TransferId>,
+    pub devices: DataReader<Device>,
+    pub endpoints: DataReader<Endpoint>,
+    pub endpoint_states: DataReader<u8>,
+    pub endpoint_state_index: CompactReader<TransferId, Id<u8>>,
+    pub end_index: CompactReader<TransferId, TrafficItemId>,
+    item_filter: Option<FilteredItems>,
+    item_order: Option<Arc<Vec<TrafficItemId>>>,
+    idle_frames: Option<u64>,
+    poll_durations: bool,
+    detail: DetailLevel,
+}
+
+/// Create a capture reader-writer pair.
+pub fn create_capture()
+    -> Result<(CaptureWriter, CaptureReader), Error>
+{
+    // Create all the required streams.
+    let (data_writer, data_reader) =
+        data_stream_with_block_size::<_, PACKET_DATA_BLOCK_SIZE>()?;
+    let (packets_writer, packets_reader) = compact_index()?;
+    let (timestamp_writer, timestamp_reader) = compact_index()?;
+    let (sources_writer, sources_reader) = data_stream()?;
+    let (source_index_writer, source_index_reader) = compact_index()?;
+    let (lengths_writer, lengths_reader) = data_stream()?;
+    let (length_index_writer, length_index_reader) = compact_index()?;
+    let (transactions_writer, transactions_reader) = compact_index()?;
+    let (transfers_writer, transfers_reader) = data_stream()?;
+    let (items_writer, items_reader) = compact_index()?;
+    let (devices_writer, devices_reader) = data_stream()?;
+    let (endpoints_writer, endpoints_reader) = data_stream()?;
+    let (endpoint_state_writer, endpoint_state_reader) = data_stream()?;
+    let (state_index_writer, state_index_reader) = compact_index()?;
+    let (end_writer, end_reader) = compact_index()?;
+
+    // Create the state shared by readers and writer.
+    let shared = Arc::new(CaptureShared {
+        device_data: ArcSwap::new(Arc::new(VecMap::new())),
+        endpoint_readers: ArcSwap::new(Arc::new(VecMap::new())),
+        complete: AtomicBool::from(false),
+        reconstructed: AtomicBool::from(false),
+        sources: ArcSwap::new(Arc::new(Vec::new())),
+        annotations: ArcSwap::new(Arc::new(Annotations::new())),
+        markers: ArcSwap::new(Arc::new(Markers::new())),
+        metadata: ArcSwap::new(Arc::new(Metadata::new())),
+        integrity: ArcSwapOption::const_empty(),
+        traffic_index: RwLock::new(TrafficIndex::new()),
+    });
+
+    // Create the write handle.
+    let writer = CaptureWriter {
+        shared: shared.clone(),
+        packet_data: data_writer,
+        packet_index: packets_writer,
+        packet_times: timestamp_writer,
+        packet_sources: sources_writer,
+        packet_source_index: source_index_writer,
+        packet_lengths: lengths_writer,
+        packet_length_index: length_index_writer,
+        transaction_index: transactions_writer,
+        transfer_index: transfers_writer,
+        item_index: items_writer,
+        devices: devices_writer,
+        endpoints: endpoints_writer,
+        endpoint_states: endpoint_state_writer,
+        endpoint_state_index: state_index_writer,
+        end_index: end_writer,
+    };
+
+    // Create the first read handle.
+    let reader = CaptureReader {
+        shared,
+        endpoint_readers: VecMap::new(),
+        packet_data: data_reader,
+        packet_index: packets_reader,
+        packet_times: timestamp_reader,
+        packet_sources: sources_reader,
+        packet_source_index: source_index_reader,
+        packet_lengths: lengths_reader,
+        packet_length_index: length_index_reader,
+        transaction_index: transactions_reader,
+        transfer_index: transfers_reader,
+        item_index: items_reader,
+        devices: devices_reader,
+        endpoints: endpoints_reader,
+        endpoint_states: endpoint_state_reader,
+        endpoint_state_index: state_index_reader,
+        end_index: end_reader,
+        item_filter: None,
+        item_order: None,
+        idle_frames: None,
+        poll_durations: false,
+        detail: DetailLevel::default(),
+    };
+
+    // Return the pair.
+    Ok((writer, reader))
+}
+
+/// Per-endpoint state shared between readers and writers.
+pub struct EndpointShared {
+    pub total_data: AtomicU64,
+    pub first_item_id: ArcSwapOption<TrafficItemId>,
+}
+
+/// Unique handle for write access to endpoint data.
+pub struct EndpointWriter {
+    pub shared: Arc<EndpointShared>,
+    pub transaction_ids: CompactWriter<EndpointTransactionId, TransactionId>,
+    pub transfer_index: CompactWriter<EndpointTransferId, EndpointTransactionId>,
+    pub data_transactions: CompactWriter<EndpointDataEvent, EndpointTransactionId>,
+    pub data_byte_counts: CompactWriter<EndpointDataEvent, EndpointByteCount>,
+    pub end_index: CompactWriter<EndpointTransferId, TrafficItemId>,
+    pub aborted_transfers: CompactWriter<Id<EndpointTransferId>, EndpointTransferId>,
+}
+
+/// Cloneable handle for read access to endpoint data.
+#[derive(Clone)]
+pub struct EndpointReader {
+    pub shared: Arc<EndpointShared>,
+    pub transaction_ids: CompactReader<EndpointTransactionId, TransactionId>,
+    pub transfer_index: CompactReader<EndpointTransferId, EndpointTransactionId>,
+    pub data_transactions: CompactReader<EndpointDataEvent, EndpointTransactionId>,
+    pub data_byte_counts: CompactReader<EndpointDataEvent, EndpointByteCount>,
+    pub end_index: CompactReader<EndpointTransferId, TrafficItemId>,
+    pub aborted_transfers: CompactReader<Id<EndpointTransferId>, EndpointTransferId>,
+}
+
+/// Create a per-endpoint reader-writer pair.
+pub fn create_endpoint()
+    -> Result<(EndpointWriter, EndpointReader), Error>
+{
+    // Create all the required streams.
+    let (transactions_writer, transactions_reader) = compact_index()?;
+    let (transfers_writer, transfers_reader) = compact_index()?;
+    let (data_transaction_writer, data_transaction_reader) = compact_index()?;
+    let (data_byte_count_writer, data_byte_count_reader) = compact_index()?;
+    let (end_writer, end_reader) = compact_index()?;
+    let (aborted_writer, aborted_reader) = compact_index()?;
+
+    // Create the shared state.
+    let shared = Arc::new(EndpointShared {
+        total_data: AtomicU64::from(0),
+        first_item_id: ArcSwapOption::const_empty(),
+    });
+
+    // Create the write handle.
+    let writer = EndpointWriter {
+        shared: shared.clone(),
+        transaction_ids: transactions_writer,
+        transfer_index: transfers_writer,
+        data_transactions: data_transaction_writer,
+        data_byte_counts: data_byte_count_writer,
+        end_index: end_writer,
+        aborted_transfers: aborted_writer,
+    };
+
+    // Create the read handle.
+    let reader = EndpointReader {
+        shared,
+        transaction_ids: transactions_reader,
+        transfer_index: transfers_reader,
+        data_transactions: data_transaction_reader,
+        data_byte_counts: data_byte_count_reader,
+        end_index: end_reader,
+        aborted_transfers: aborted_reader,
+    };
+
+    // Return the pair.
+    Ok((writer, reader))
+}
+
+pub type PacketByteId = Id<u8>;
+pub type PacketId = Id<PacketByteId>;
+pub type TransactionId = Id<PacketId>;
+pub type TransferId = Id<TransferIndexEntry>;
+pub type EndpointTransactionId = Id<TransactionId>;
+pub type EndpointTransferId = Id<EndpointTransactionId>;
+pub type TrafficItemId = Id<TransferId>;
+pub type DeviceId = Id<Device>;
+pub type EndpointId = Id<Endpoint>;
+pub type EndpointDataEvent = u64;
+pub type EndpointByteCount = u64;
+pub type DeviceVersion = u32;
+/// Packet timestamp, in nanoseconds since the Unix epoch.
+pub type Timestamp = u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrafficItem {
+    Transfer(TransferId),
+    Transaction(TransferId, TransactionId),
+    Packet(TransferId, TransactionId, PacketId),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum DeviceItem {
+    Device(DeviceId, DeviceVersion),
+    DeviceDescriptor(DeviceId),
+    DeviceDescriptorField(DeviceId, DeviceField, DeviceVersion),
+    Configuration(DeviceId, ConfigNum),
+    ConfigurationDescriptor(DeviceId, ConfigNum),
+    ConfigurationDescriptorField(DeviceId, ConfigNum,
+                                 ConfigField, DeviceVersion),
+    Interface(DeviceId, ConfigNum, InterfaceNum),
+    InterfaceDescriptor(DeviceId, ConfigNum, InterfaceNum),
+    InterfaceDescriptorField(DeviceId, ConfigNum,
+                             InterfaceNum, InterfaceField, DeviceVersion),
+    EndpointDescriptor(DeviceId, ConfigNum, InterfaceNum, InterfaceEpNum),
+    EndpointDescriptorField(DeviceId, ConfigNum, InterfaceNum,
+                            InterfaceEpNum, EndpointField, DeviceVersion),
+}
+
+impl DeviceItem {
+    pub fn device_id(&self) -> DeviceId {
+        use DeviceItem::*;
+        match self {
+            Device(dev, _) |
+            DeviceDescriptor(dev) |
+            DeviceDescriptorField(dev, ..) |
+            Configuration(dev, _) |
+            ConfigurationDescriptor(dev, _) |
+            ConfigurationDescriptorField(dev, ..) |
+            Interface(dev, ..) |
+            InterfaceDescriptor(dev, ..) |
+            InterfaceDescriptorField(dev, ..) |
+            EndpointDescriptor(dev, ..) |
+            EndpointDescriptorField(dev, ..) => *dev,
+        }
+    }
+}
+
+/// A subset of the packets in a capture, selected for export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExportScope {
+    /// Packets belonging to any of the given traffic items.
+    Items(Vec<TrafficItem>),
+    /// Packets with timestamps from the first value, up to but not
+    /// including the second.
+    TimeRange(Timestamp, Timestamp),
+    /// Packets exchanged with a device.
+    Device(DeviceId),
+}
+
+/// How far down the traffic tree items are shown.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DetailLevel {
+    /// Transfers and other top-level items only.
+    Transfers,
+    /// Transfers and their transactions, but not packets.
+    Transactions,
+    /// Every item, down to packets.
+    #[default]
+    Packets,
+}
+
+impl DetailLevel {
+    /// All levels, in the order they are offered.
+    pub const ALL: [DetailLevel; 3] = [
+        DetailLevel::Transfers,
+        DetailLevel::Transactions,
+        DetailLevel::Packets,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        use DetailLevel::*;
+        match self {
+            Transfers => "Transfers only",
+            Transactions => "Transfers and transactions",
+            Packets => "Transfers, transactions and packets",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use DetailLevel::*;
+        match self {
+            Transfers => "transfers",
+            Transactions => "transactions",
+            Packets => "packets",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DetailLevel> {
+        DetailLevel::ALL
+            .into_iter()
+            .find(|level| level.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+pub struct Device {
+    pub address: DeviceAddr,
+}
+
+bitfield! {
+    #[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+    #[repr(C)]
+    pub struct Endpoint(u64);
+    pub u64, from into DeviceId, device_id, set_device_id: 50, 0;
+    pub u8, from into DeviceAddr, device_address, set_device_address: 57, 51;
+    pub u8, from into EndpointNum, number, set_number: 62, 58;
+    pub u8, from into Direction, direction, set_direction: 63, 63;
+}
+
+impl Endpoint {
+    pub fn address(&self) -> EndpointAddr {
+        EndpointAddr::from_parts(self.number(), self.direction())
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{} {}",
+               self.device_address(),
+               self.number(),
+               self.direction()
+               )
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+    #[repr(C)]
+    pub struct TransferIndexEntry(u64);
+    pub u64, from into EndpointTransferId, transfer_id, set_transfer_id: 51, 0;
+    pub u64, from into EndpointId, endpoint_id, set_endpoint_id: 62, 52;
+    pub u8, _is_start, _set_is_start: 63, 63;
+}
+
+impl TransferIndexEntry {
+    pub fn is_start(&self) -> bool {
+        self._is_start() != 0
+    }
+    pub fn set_is_start(&mut self, value: bool) {
+        self._set_is_start(value as u8)
+    }
+}
+
+#[derive(Copy, Clone, IntoPrimitive, FromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EndpointState {
+    #[default]
+    Idle = 0,
+    Starting = 1,
+    Ongoing = 2,
+    Ending = 3,
+}
+
+pub const CONTROL_EP_NUM: EndpointNum = EndpointNum(0);
+pub const INVALID_EP_NUM: EndpointNum = EndpointNum(0x10);
+pub const FRAMING_EP_NUM: EndpointNum = EndpointNum(0x11);
+/// Endpoint holding packets stored without being grouped into transactions
+/// and transfers.
+pub const UNGROUPED_EP_NUM: EndpointNum = EndpointNum(0x12);
+pub const INVALID_EP_ID: EndpointId = EndpointId::constant(0);
+pub const FRAMING_EP_ID: EndpointId = EndpointId::constant(1);
+
+#[derive(Copy, Clone, Debug)]
+pub enum EndpointType {
+    Unidentified,
+    Framing,
+    Invalid,
+    Ungrouped,
+    Normal(usb::EndpointType)
+}
+
+impl std::fmt::Display for EndpointType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EndpointType::Normal(usb_type) => write!(f, "{usb_type:?}"),
+            special_type => write!(f, "{special_type:?}"),
+        }
+    }
+}
+
+type EndpointDetails = (usb::EndpointType, Option<usize>);
+
+#[derive(Default)]
+pub struct DeviceData {
+    pub device_descriptor: ArcSwapOption<DeviceDescriptor>,
+    pub configurations: ArcSwap<VecMap<ConfigNum, Arc<Configuration>>>,
+    pub config_number: ArcSwapOption<ConfigNum>,
+    pub endpoint_details: ArcSwap<VecMap<EndpointAddr, EndpointDetails>>,
+    pub strings: ArcSwap<VecMap<StringId, UTF16ByteVec>>,
+    pub version: AtomicU32,
+}
+
+impl DeviceData {
+    pub fn description(&self) -> String {
+        match self.device_descriptor.load().as_ref() {
+            None => "Unknown".to_string(),
+            Some(descriptor) => {
+                let str_id = descriptor.product_str_id;
+                if let Some(utf16) = self.strings.load().get(str_id) {
+                    let chars = utf16.chars();
+                    if let Ok(string) = String::from_utf16(&chars) {
+                        return format!("{}", string.escape_default());
+                    }
+                }
+                let (vendor_id, product_id) =
+                    (descriptor.vendor_id, descriptor.product_id);
+                let ids = format!("{vendor_id:04X}:{product_id:04X}");
+                match database().device_name(vendor_id, product_id) {
+                    Some(name) => format!("{name} ({ids})"),
+                    None => ids,
+                }
+            }
+        }
+    }
+
+    pub fn configuration(&self, number: &ConfigNum)
+        -> Result<Arc<Configuration>, Error>
+    {
+        match self.configurations.load().get(*number) {
+            Some(config) => Ok(config.clone()),
+            None => bail!("No descriptor for config {number}")
+        }
+    }
+
+    pub fn endpoint_details(&self, addr: EndpointAddr)
+        -> (EndpointType, Option<usize>)
+    {
+        use EndpointType::*;
+        match addr.number() {
+            INVALID_EP_NUM => (Invalid, None),
+            FRAMING_EP_NUM => (Framing, None),
+            UNGROUPED_EP_NUM => (Ungrouped, None),
+            CONTROL_EP_NUM => (
+                Normal(usb::EndpointType::Control),
+                self.device_descriptor.load().as_ref().map(|desc| {
+                    desc.max_packet_size_0 as usize
+                })
+            ),
+            _ => match self.endpoint_details.load().get(addr) {
+                Some((ep_type, ep_max)) => (Normal(*ep_type), *ep_max),
+                None => (Unidentified, None)
+            }
+        }
+    }
+
+    pub fn update_endpoint_details(&self) {
+        if let Some(number) = self.config_number.load().as_ref() {
+            if let Some(config) = &self.configurations.load().get(**number) {
+                self.endpoint_details.update(|endpoint_details| {
+                    for iface in &config.interfaces {
+                        for ep_desc in &iface.endpoint_descriptors {
+                            let ep_addr = ep_desc.endpoint_address;
+                            let ep_type = ep_desc.attributes.endpoint_type();
+                            let ep_max = ep_desc.max_packet_size as usize;
+                            endpoint_details.set(
+                                ep_addr,
+                                (ep_type, Some(ep_max))
+                            );
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    pub fn set_endpoint_type(&self,
+                             addr: EndpointAddr,
+                             ep_type: usb::EndpointType)
+    {
+        self.endpoint_details.maybe_update(|endpoint_details| {
+            if endpoint_details.get(addr).is_none() {
+                endpoint_details.set(addr, (ep_type, None));
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn decode_request(&self, fields: &SetupFields, payload: &[u8])
+        -> Result<(), Error>
+    {
+        let req_type = fields.type_fields.request_type();
+        let request = StandardRequest::from(fields.request);
+        match (req_type, request) {
+            (RequestType::Standard, StandardRequest::GetDescriptor)
+                => self.decode_descriptor_read(fields, payload)?,
+            (RequestType::Standard, StandardRequest::SetConfiguration)
+                => self.decode_configuration_set(fields)?,
+            _ => ()
+        }
+        Ok(())
+    }
+
+    pub fn decode_descriptor_read(&self,
+                                  fields: &SetupFields,
+                                  payload: &[u8])
+        -> Result<(), Error>
+    {
+        let recipient = fields.type_fields.recipient();
+        let desc_type = DescriptorType::from((fields.value >> 8) as u8);
+        match (recipient, desc_type) {
+            (Recipient::Device, DescriptorType::Device) =>
+                self.store_device_descriptor(payload),
+            (Recipient::Device, DescriptorType::Configuration) =>
+                self.store_configuration(payload),
+            (Recipient::Device, DescriptorType::String) =>
+                self.store_string((fields.value & 0xFF) as u8, payload),
+            _ => {}
+        };
+        Ok(())
+    }
+
+    /// Add descriptors obtained outside the capture.
+    ///
+    /// Descriptors read in the capture afterwards replace these.
+    pub fn apply_known_descriptors(&self, known: &KnownDescriptors) {
+        self.store_device_descriptor(&known.device_descriptor);
+        for bytes in &known.configurations {
+            self.store_configuration(bytes);
+        }
+        for (index, bytes) in &known.strings {
+            self.store_string(*index, bytes);
+        }
+        if let Some(number) = known.config_number {
+            self.config_number.swap(Some(Arc::new(ConfigNum(number))));
+            self.update_endpoint_details();
+            self.increment_version();
+        }
+    }
+
+    fn store_device_descriptor(&self, payload: &[u8]) {
+        if payload.len() == size_of::<DeviceDescriptor>() {
+            let descriptor = DeviceDescriptor::from_bytes(payload);
+            self.device_descriptor.swap(Some(Arc::new(descriptor)));
+            self.increment_version();
+        }
+    }
+
+    fn store_configuration(&self, payload: &[u8]) {
+        let size = size_of::<ConfigDescriptor>();
+        if payload.len() >= size {
+            let configuration = Configuration::from_bytes(payload);
+            if let Some(config) = configuration {
+                let config_num = ConfigNum::from(
+                    config.descriptor.config_value);
+                self.configurations.update(|configurations| {
+                    configurations.set(config_num, Arc::new(config));
+                });
+                self.update_endpoint_details();
+                self.increment_version();
+            }
+        }
+    }
+
+    fn store_string(&self, index: u8, payload: &[u8]) {
+        if payload.len() >= 2 {
+            let string = UTF16ByteVec(payload[2..].to_vec());
+            let string_id = StringId::from(index);
+            self.strings.update(|strings| {
+                strings.set(string_id, string)
+            });
+            self.increment_version();
+        }
+    }
+
+    fn decode_configuration_set(&self, fields: &SetupFields)
+        -> Result<(), Error>
+    {
+        let config_number = ConfigNum(fields.value.try_into()?);
+        self.config_number.swap(Some(Arc::new(config_number)));
+        self.update_endpoint_details();
+        self.increment_version();
+        Ok(())
+    }
+
+    fn increment_version(&self) {
+        self.version.fetch_add(1, Release);
+    }
+
+    fn version(&self) -> DeviceVersion {
+        self.version.load(Acquire)
+    }
+}
+
+impl Configuration {
+    pub fn interface(&self, number: &InterfaceNum)
+        -> Result<&Interface, Error>
+    {
+        match self.interfaces.get(*number) {
+            Some(iface) => Ok(iface),
+            _ => bail!("Configuration has no interface {number}")
+        }
+    }
+}
+
+impl Interface {
+    pub fn endpoint_descriptor(&self, number: &InterfaceEpNum)
+        -> Result<&EndpointDescriptor, Error>
+    {
+        match self.endpoint_descriptors.get(*number) {
+            Some(desc) => Ok(desc),
+            _ => bail!("Interface has no endpoint descriptor {number}")
+        }
+    }
+}
+
+pub struct Transaction {
+    start_pid: PID,
+    end_pid: PID,
+    split: Option<(SplitFields, PID)>,
+    packet_id_range: Range<PacketId>,
+    data_packet_id: Option<PacketId>,
+    payload_byte_range: Option<Range<Id<u8>>>,
+}
+
+impl Transaction {
+    fn packet_count(&self) -> u64 {
+        self.packet_id_range.len()
+    }
+
+    fn payload_size(&self) -> Option<u64> {
+        self.payload_byte_range.as_ref().map(|range| range.len())
+    }
+
+    fn successful(&self) -> bool {
+        use PID::*;
+        match (self.start_pid, self.end_pid) {
+
+            // SPLIT is successful if it ends with DATA0/DATA1/ACK/NYET.
+            (SPLIT, DATA0 | DATA1 | ACK | NYET) => true,
+
+            // SETUP/IN/OUT is successful if it ends with ACK/NYET.
+            (SETUP | IN | OUT, ACK | NYET) => true,
+
+            (..) => false
+        }
+    }
+
+    fn control_result(&self, direction: Direction) -> ControlResult {
+        use ControlResult::*;
+        use StartComplete::*;
+        use Direction::*;
+        use PID::*;
+        let end_pid = match (direction, self.start_pid, self.split.as_ref()) {
+            (In,  OUT,   None) |
+            (Out, IN,    None) =>
+                self.end_pid,
+            (In,  SPLIT, Some((split_fields, OUT))) |
+            (Out, SPLIT, Some((split_fields, IN ))) => {
+                if split_fields.sc() == Complete {
+                    self.end_pid
+                } else {
+                    return Incomplete
+                }
+            },
+            _ => return if self.end_pid == STALL { Stalled } else { Incomplete }
+        };
+        if end_pid == STALL {
+            Stalled
+        } else if self.successful() {
+            Completed
+        } else {
+            Incomplete
+        }
+    }
+
+    fn outcome(&self) -> Option<PID> {
+        use PID::*;
+        match self.end_pid {
+            // Any handshake response should be displayed as an outcome.
+            ACK | NAK | NYET | STALL | ERR => Some(self.end_pid),
+            _ => None
+        }
+    }
+
+    fn description(&self,
+                   capture: &mut CaptureReader,
+                   endpoint: &Endpoint)
+        -> Result<String, Error>
+    {
+        use PID::*;
+        use StartComplete::*;
+        Ok(match (self.start_pid, &self.split) {
+            (SOF, _) => {
+                let count = self.packet_count();
+                match capture.idle_frames {
+                    Some(min) if count >= min => format!(
+                        "{count} idle frames ({})",
+                        milliseconds(self.sof_duration(capture)?)),
+                    _ => format!("{count} SOF packets"),
+                }
+            },
+            (Malformed, _) => format!(
+                "{} malformed packets", self.packet_count()),
+            (SPLIT, Some((split_fields, token_pid))) => format!(
+                "{} {}",
+                match split_fields.sc() {
+                    Start => "Starting",
+                    Complete => "Completing",
+                },
+                self.inner_description(capture, endpoint, *token_pid)?
+            ),
+            (pid, _) => self.inner_description(capture, endpoint, pid)?
+        })
+    }
+
+    /// Time from the first SOF of a group to the packet after the group,
+    /// or to its last SOF if no packet follows yet, in nanoseconds.
+    fn sof_duration(&self, capture: &mut CaptureReader)
+        -> Result<u64, Error>
+    {
+        let range = &self.packet_id_range;
+        let end = if range.end.value < capture.packet_index.len() {
+            range.end
+        } else {
+            range.end - 1
+        };
+        let start_time = capture.packet_time(range.start)?;
+        let end_time = capture.packet_time(end)?;
+        Ok(end_time.saturating_sub(start_time))
+    }
+
+    fn inner_description(&self,
+                         capture: &mut CaptureReader,
+                         endpoint: &Endpoint,
+                         pid: PID)
+        -> Result<String, Error>
+    {
+        Ok(format!(
+            "{} transaction on {}.{}{}",
+            pid,
+            endpoint.device_address(),
+            endpoint.number(),
+            match (self.payload_size(), self.outcome()) {
+                (None, None) =>
+                    String::from(""),
+                (None, Some(outcome)) =>
+                    format!(", {outcome}"),
+                (Some(0), None) =>
+                    String::from(" with no data"),
+                (Some(0), Some(outcome)) =>
+                    format!(" with no data, {outcome}"),
+                (Some(size), None) => format!(
+                    " with {size} data bytes: {}",
+                    Bytes::first(100, &capture.transaction_bytes(self)?)),
+                (Some(size), Some(outcome)) => format!(
+                    " with {size} data bytes, {outcome}: {}",
+                    Bytes::first(100, &capture.transaction_bytes(self)?)),
+            }
+        ))
+    }
+}
+
+/// Format a duration in nanoseconds as milliseconds.
+///
+/// Durations of whole microseconds are shown to the microsecond, and any
+/// others to the nanosecond.
+pub(crate) fn milliseconds(nanoseconds: u64) -> String {
+    if nanoseconds % 1000 == 0 {
+        format!("{:.3} ms", nanoseconds as f64 / 1e6)
+    } else {
+        format!("{:.6} ms", nanoseconds as f64 / 1e6)
+    }
+}
+
+struct Bytes<'src> {
+    partial: bool,
+    bytes: &'src [u8],
+}
+
+impl<'src> Bytes<'src> {
+    fn first(max: usize, bytes: &'src [u8]) -> Self {
+        if bytes.len() > max {
+            Bytes {
+                partial: true,
+                bytes: &bytes[0..max],
+            }
+        } else {
+            Bytes {
+                partial: false,
+                bytes,
+            }
+        }
+    }
+
+    fn looks_like_ascii(&self) -> bool {
+        let mut num_printable = 0;
+        for &byte in self.bytes {
+            if byte == 0 || byte >= 0x80 {
+                // Outside ASCII range.
+                return false;
+            }
+            // Count printable and pseudo-printable characters.
+            let printable = match byte {
+                c if (0x20..0x7E).contains(&c) => true, // printable range
+                0x09                           => true, // tab
+                0x0A                           => true, // new line
+                0x0D                           => true, // carriage return
+                _ => false
+            };
+            if printable {
+                num_printable += 1;
+            }
+        }
+        // If the string is at least half printable, treat as ASCII.
+        num_printable > 0 && num_printable >= self.bytes.len() / 2
+    }
+}
+
+impl std::fmt::Display for Bytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.looks_like_ascii() {
+            write!(f, "'{}'", String::from_utf8(
+                self.bytes.iter()
+                          .flat_map(|c| {std::ascii::escape_default(*c)})
+                          .collect::<Vec<u8>>()).unwrap())?
+        } else {
+            write!(f, "{:02X?}", self.bytes)?
+        };
+        if self.partial {
+            write!(f, "...")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl CaptureWriter {
+    pub fn device_data(&self, id: DeviceId)
+        -> Result<Arc<DeviceData>, Error>
+    {
+        Ok(self.shared.device_data
+            .load()
+            .get(id)
+            .context("Capture has no device with ID {id}")?
+            .clone())
+    }
+
+    pub fn print_storage_summary(&self) {
+        let mut overhead: u64 =
+            self.packet_index.size() +
+            self.packet_times.size() +
+            self.packet_sources.size() +
+            self.packet_source_index.size() +
+            self.packet_lengths.size() +
+            self.packet_length_index.size() +
+            self.transaction_index.size() +
+            self.transfer_index.size() +
+            self.endpoint_states.size() +
+            self.endpoint_state_index.size();
+        let mut trx_count = 0;
+        let mut trx_size = 0;
+        let mut xfr_count = 0;
+        let mut xfr_size = 0;
+        for ep_traf in self.shared.endpoint_readers.load().as_ref() {
+            trx_count += ep_traf.transaction_ids.len();
+            trx_size += ep_traf.transaction_ids.size();
+            xfr_count += ep_traf.transfer_index.len();
+            xfr_size += ep_traf.transfer_index.size();
+            overhead += trx_size + xfr_size;
+        }
+        let ratio = (overhead as f32) / (self.packet_data.size() as f32);
+        let percentage = ratio * 100.0;
+        print!(concat!(
+            "Storage summary:\n",
+            "  Packet data: {}\n",
+            "  Packet index: {}\n",
+            "  Packet timestamps: {}\n",
+            "  Packet sources: {}\n",
+            "  Packet source index: {}\n",
+            "  Truncated packet lengths: {}\n",
+            "  Truncated packet index: {}\n",
+            "  Transaction index: {}\n",
+            "  Transfer index: {}\n",
+            "  Endpoint states: {}\n",
+            "  Endpoint state index: {}\n",
+            "  Endpoint transaction indices: {} values, {}\n",
+            "  Endpoint transfer indices: {} values, {}\n",
+            "Total overhead: {:.1}% ({})\n"),
+            fmt_size(self.packet_data.size()),
+            &self.packet_index,
+            &self.packet_times,
+            &self.packet_sources,
+            &self.packet_source_index,
+            &self.packet_lengths,
+            &self.packet_length_index,
+            &self.transaction_index,
+            &self.transfer_index,
+            &self.endpoint_states,
+            &self.endpoint_state_index,
+            fmt_count(trx_count), fmt_size(trx_size),
+            fmt_count(xfr_count), fmt_size(xfr_size),
+            percentage, fmt_size(overhead),
+        )
+    }
+}
+
+impl CaptureReader {
+    /// Size in bytes of each of the stores holding the capture, by name.
+    pub fn storage_sizes(&self) -> Vec<(&'static str, u64)> {
+        let mut endpoint_indexes = 0;
+        for ep_reader in self.shared.endpoint_readers.load().as_ref() {
+            endpoint_indexes +=
+                ep_reader.transaction_ids.size() +
+                ep_reader.transfer_index.size() +
+                ep_reader.data_transactions.size() +
+                ep_reader.data_byte_counts.size() +
+                ep_reader.end_index.size();
+        }
+        vec![
+            ("Packet data", self.packet_data.size()),
+            ("Packet index", self.packet_index.size()),
+            ("Packet timestamps", self.packet_times.size()),
+            ("Packet sources",
+             self.packet_sources.size() + self.packet_source_index.size()),
+            ("Truncated packet lengths",
+             self.packet_lengths.size() + self.packet_length_index.size()),
+            ("Transaction index", self.transaction_index.size()),
+            ("Transfer index", self.transfer_index.size()),
+            ("Item index", self.item_index.size()),
+            ("Devices and endpoints",
+             self.devices.size() + self.endpoints.size()),
+            ("Endpoint states",
+             self.endpoint_states.size() + self.endpoint_state_index.size()),
+            ("Transfer ends", self.end_index.size()),
+            ("Endpoint indexes", endpoint_indexes),
+        ]
+    }
+
+    /// Total size in bytes of the stores holding the capture.
+    pub fn storage_size(&self) -> u64 {
+        self.storage_sizes().iter().map(|(_, size)| size).sum()
+    }
+
+    pub fn endpoint_traffic(&mut self, endpoint_id: EndpointId)
+        -> Result<&mut EndpointReader, Error>
+    {
+        if self.shared.endpoint_readers.load().get(endpoint_id).is_none() {
+            bail!("Capture has no endpoint ID {endpoint_id}")
+        }
+
+        if self.endpoint_readers.get(endpoint_id).is_none() {
+            let reader = self.shared.endpoint_readers
+                .load()
+                .get(endpoint_id)
+                .unwrap()
+                .as_ref()
+                .clone();
+            self.endpoint_readers.set(endpoint_id, reader);
+        }
+
+        Ok(self.endpoint_readers.get_mut(endpoint_id).unwrap())
+    }
+
+    /// Range of endpoint transactions making up a transfer.
+    pub fn transfer_range(&mut self, entry: &TransferIndexEntry)
+        -> Result<Range<EndpointTransactionId>, Error>
+    {
+        let endpoint_id = entry.endpoint_id();
+        let ep_transfer_id = entry.transfer_id();
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        ep_traf.transfer_index.target_range(
+            ep_transfer_id, ep_traf.transaction_ids.len())
+    }
+
+    fn transaction_fields(&mut self, transaction: &Transaction)
+        -> Result<SetupFields, Error>
+    {
+        match transaction.data_packet_id {
+            None => bail!("Transaction has no data packet"),
+            Some(data_packet_id) => {
+                let data_packet = self.packet(data_packet_id)?;
+                match data_packet.first() {
+                    None => bail!("Found empty packet instead of setup data"),
+                    Some(byte) => {
+                        let pid = PID::from(*byte);
+                        if pid != PID::DATA0 {
+                            bail!("Found {pid} packet instead of setup data")
+                        } else if data_packet.len() != 11 {
+                            bail!("Found DATA0 with packet length {} \
+                                   instead of setup data", data_packet.len())
+                        } else {
+                            Ok(SetupFields::from_data_packet(&data_packet))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn transaction_bytes(&mut self, transaction: &Transaction)
+        -> Result<Vec<u8>, Error>
+    {
+        let data_packet_id = transaction.data_packet_id
+            .context("Transaction has no data packet")?;
+        let packet_byte_range = self.packet_index.target_range(
+            data_packet_id, self.packet_data.len())?;
+        let data_byte_range =
+            packet_byte_range.start + 1 .. packet_byte_range.end - 2;
+        self.packet_data.get_range(&data_byte_range)
+    }
+
+    fn transfer_bytes(&mut self,
+                      endpoint_id: EndpointId,
+                      data_range: &Range<EndpointDataEvent>,
+                      length: usize)
+        -> Result<Vec<u8>, Error>
+    {
+        let mut transfer_bytes = Vec::with_capacity(length);
+        let mut data_range = data_range.clone();
+        while transfer_bytes.len() < length {
+            let data_id = match data_range.next() {
+                Some(data_id) => data_id,
+                // Truncated packets hold fewer bytes than were transferred.
+                None if self.packet_length_index.len() > 0 => break,
+                None => bail!(
+                    "Ran out of data events after fetching {}/{} requested \
+                     bytes", transfer_bytes.len(), length),
+            };
+            let ep_traf = self.endpoint_traffic(endpoint_id)?;
+            let ep_transaction_id = ep_traf.data_transactions.get(data_id)?;
+            let transaction_id = ep_traf.transaction_ids.get(ep_transaction_id)?;
+            let transaction = self.transaction(transaction_id)?;
+            let transaction_bytes = self.transaction_bytes(&transaction)?;
+            let required = min(
+                length - transfer_bytes.len(),
+                transaction_bytes.len()
+            );
+            transfer_bytes.extend(&transaction_bytes[..required]);
+        }
+        Ok(transfer_bytes)
+    }
+
+    /// Payloads carried on an endpoint, in order, with the transactions
+    /// that carried them and the direction of each.
+    pub fn endpoint_payloads(&mut self, endpoint_id: EndpointId)
+        -> Result<Vec<(TransactionId, Direction, Vec<u8>)>, Error>
+    {
+        let count = self.endpoint_payload_count(endpoint_id)?;
+        let mut payloads = Vec::new();
+        for index in 0..count {
+            payloads.push(self.endpoint_payload(endpoint_id, index)?);
+        }
+        Ok(payloads)
+    }
+
+    /// Number of payloads carried on an endpoint so far.
+    pub fn endpoint_payload_count(&mut self, endpoint_id: EndpointId)
+        -> Result<u64, Error>
+    {
+        Ok(self.endpoint_traffic(endpoint_id)?.data_transactions.len())
+    }
+
+    /// One of the payloads carried on an endpoint, by its index in order,
+    /// with the transaction that carried it and its direction.
+    pub fn endpoint_payload(&mut self, endpoint_id: EndpointId, index: u64)
+        -> Result<(TransactionId, Direction, Vec<u8>), Error>
+    {
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        let ep_transaction_id = ep_traf.data_transactions.get(index)?;
+        let transaction_id = ep_traf.transaction_ids.get(ep_transaction_id)?;
+        let transaction = self.transaction(transaction_id)?;
+        let direction = match (transaction.start_pid, &transaction.split) {
+            (PID::IN, _) | (PID::SPLIT, Some((_, PID::IN))) =>
+                Direction::In,
+            _ => Direction::Out,
+        };
+        let data = self.transaction_bytes(&transaction)?;
+        Ok((transaction_id, direction, data))
+    }
+
+    fn endpoint_state(&mut self, transfer_id: TransferId)
+        -> Result<Vec<u8>, Error>
+    {
+        let range = self.endpoint_state_index.target_range(
+            transfer_id, self.endpoint_states.len())?;
+        self.endpoint_states.get_range(&range)
+    }
+
+    pub fn packet(&mut self, id: PacketId)
+        -> Result<Vec<u8>, Error>
+    {
+        let range = self.packet_index.target_range(
+            id, self.packet_data.len())?;
+        self.packet_data.get_range(&range)
+    }
+
+    fn packet_pid(&mut self, id: PacketId)
+        -> Result<PID, Error>
+    {
+        let range = self.packet_index.target_range(
+            id, self.packet_data.len())?;
+        let pid = PID::from(self.packet_data.get(range.start)?);
+        // Classify packets as the decoder did when they were captured.
+        if range.len() < pid.min_length() as u64 {
+            Ok(PID::Malformed)
+        } else {
+            Ok(pid)
+        }
+    }
+
+    pub fn packet_time(&mut self, id: PacketId)
+        -> Result<Timestamp, Error>
+    {
+        self.packet_times.get(id)
+    }
+
+    /// Name of the file a packet was read from, if the capture was merged.
+    pub fn packet_source(&mut self, id: PacketId)
+        -> Result<Option<String>, Error>
+    {
+        Ok(match self.packet_source_number(id)? {
+            Some(source) => self.shared.sources
+                .load()
+                .get(source as usize)
+                .cloned(),
+            None => None,
+        })
+    }
+
+    /// Index of the file a packet was read from, if the capture was merged.
+    pub fn packet_source_number(&mut self, id: PacketId)
+        -> Result<Option<u16>, Error>
+    {
+        let run_count = self.packet_source_index.len();
+        if run_count == 0 {
+            return Ok(None);
+        }
+        // Find the last run of packets starting at or before this one.
+        let mut run_id = self.packet_source_index.bisect_left(&id)?;
+        if run_id.value == run_count ||
+            self.packet_source_index.get(run_id)? != id
+        {
+            if run_id.value == 0 {
+                return Ok(None);
+            }
+            run_id -= 1;
+        }
+        Ok(Some(self.packet_sources.get(run_id)?))
+    }
+
+    /// Whether a transfer was aborted before it could complete.
+    ///
+    /// A control transfer is aborted if a new SETUP transaction starts on
+    /// its endpoint, or its device is reset and given its address again.
+    pub fn transfer_aborted(&mut self, entry: &TransferIndexEntry)
+        -> Result<bool, Error>
+    {
+        let ep_transfer_id = entry.transfer_id();
+        let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+        let count = ep_traf.aborted_transfers.len();
+        if count == 0 {
+            return Ok(false);
+        }
+        let index = ep_traf.aborted_transfers.bisect_left(&ep_transfer_id)?;
+        Ok(index.value < count &&
+           ep_traf.aborted_transfers.get(index)? == ep_transfer_id)
+    }
+
+    /// Original length of a packet, if only part of it was stored.
+    pub fn packet_original_length(&mut self, id: PacketId)
+        -> Result<Option<u64>, Error>
+    {
+        let count = self.packet_length_index.len();
+        if count == 0 {
+            return Ok(None);
+        }
+        let index = self.packet_length_index.bisect_left(&id)?;
+        if index.value < count && self.packet_length_index.get(index)? == id {
+            Ok(Some(self.packet_lengths.get(index)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Timestamp of the first packet of a traffic item.
+    ///
+    /// For an item marking the end of a transfer, this is the timestamp
+    /// of the first packet of the transfer's last transaction.
+    pub fn item_timestamp(&mut self, item: &TrafficItem)
+        -> Result<Timestamp, Error>
+    {
+        let packet_id = self.item_first_packet(item)?;
+        self.packet_time(packet_id)
+    }
+
+    /// First packet of a traffic item.
+    ///
+    /// For an item marking the end of a transfer, this is the first packet
+    /// of the transfer's last transaction.
+    pub fn item_first_packet(&mut self, item: &TrafficItem)
+        -> Result<PacketId, Error>
+    {
+        use TrafficItem::*;
+        Ok(match item {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let range = self.transfer_range(&entry)?;
+                let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+                let ep_transaction_id = if entry.is_start() {
+                    range.start
+                } else {
+                    range.end - 1
+                };
+                let transaction_id =
+                    ep_traf.transaction_ids.get(ep_transaction_id)?;
+                self.transaction_index.get(transaction_id)?
+            },
+            Transaction(_, transaction_id) =>
+                self.transaction_index.get(*transaction_id)?,
+            Packet(.., packet_id) => *packet_id,
+        })
+    }
+
+    /// The endpoint a traffic item was sent to or from.
+    pub fn item_endpoint(&mut self, item: &TrafficItem)
+        -> Result<Endpoint, Error>
+    {
+        let endpoint_id = self.item_endpoint_id(item)?;
+        self.endpoints.get(endpoint_id)
+    }
+
+    /// ID of the endpoint a traffic item was sent to or from.
+    pub fn item_endpoint_id(&mut self, item: &TrafficItem)
+        -> Result<EndpointId, Error>
+    {
+        use TrafficItem::*;
+        let transfer_id = match item {
+            Transfer(transfer_id) |
+            Transaction(transfer_id, _) |
+            Packet(transfer_id, ..) => *transfer_id,
+        };
+        Ok(self.transfer_index.get(transfer_id)?.endpoint_id())
+    }
+
+    /// Number of bytes carried by a traffic item.
+    ///
+    /// For transfers and transactions this is the length of the data
+    /// payload, and for packets it is the length of the whole packet.
+    pub fn item_length(&mut self, item: &TrafficItem)
+        -> Result<u64, Error>
+    {
+        use TrafficItem::*;
+        Ok(match item {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let range = self.transfer_range(&entry)?;
+                let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+                let data_range = ep_traf.transfer_data_range(&range)?;
+                ep_traf.transfer_data_length(&data_range)?
+            },
+            Transaction(_, transaction_id) => self
+                .transaction(*transaction_id)?
+                .payload_size()
+                .unwrap_or(0),
+            Packet(.., packet_id) => self.packet_index
+                .target_range(*packet_id, self.packet_data.len())?
+                .len(),
+        })
+    }
+
+    /// Bytes carried by a traffic item.
+    ///
+    /// For transfers and transactions this is the data payload, and for
+    /// packets it is the whole packet.
+    pub fn item_data(&mut self, item: &TrafficItem)
+        -> Result<Vec<u8>, Error>
+    {
+        use TrafficItem::*;
+        match item {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let endpoint_id = entry.endpoint_id();
+                let range = self.transfer_range(&entry)?;
+                let ep_traf = self.endpoint_traffic(endpoint_id)?;
+                let data_range = ep_traf.transfer_data_range(&range)?;
+                let length = ep_traf.transfer_data_length(&data_range)?;
+                self.transfer_bytes(endpoint_id, &data_range, length as usize)
+            },
+            Transaction(_, transaction_id) => {
+                let transaction = self.transaction(*transaction_id)?;
+                match transaction.payload_size() {
+                    Some(_) => self.transaction_bytes(&transaction),
+                    None => Ok(Vec::new()),
+                }
+            },
+            Packet(.., packet_id) => self.packet(*packet_id),
+        }
+    }
+
+    /// The transactions whose payloads make up the data of a transfer, in
+    /// order, with the number of bytes each contributed.
+    pub fn transfer_data_transactions(&mut self, transfer_id: TransferId)
+        -> Result<Vec<(TransactionId, u64)>, Error>
+    {
+        let entry = self.transfer_index.get(transfer_id)?;
+        let range = self.transfer_range(&entry)?;
+        let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+        let data_range = ep_traf.transfer_data_range(&range)?;
+        let mut transactions = Vec::new();
+        for data_id in data_range {
+            let length = ep_traf.transfer_data_length(&(data_id..data_id + 1))?;
+            let ep_transaction_id = ep_traf.data_transactions.get(data_id)?;
+            let transaction_id = ep_traf.transaction_ids.get(ep_transaction_id)?;
+            transactions.push((transaction_id, length));
+        }
+        Ok(transactions)
+    }
+
+    /// Whether a transfer is a block of packets stored without grouping.
+    fn ungrouped(&mut self, entry: &TransferIndexEntry)
+        -> Result<bool, Error>
+    {
+        let endpoint = self.endpoints.get(entry.endpoint_id())?;
+        Ok(endpoint.number() == UNGROUPED_EP_NUM)
+    }
+
+    /// Whether a traffic item shows an error.
+    ///
+    /// Packets are in error if malformed, if their CRC is wrong, or if they
+    /// are STALL or ERR handshakes. Transactions are in error if they contain
+    /// malformed packets or end with a STALL or ERR handshake. Transfers
+    /// are in error if they are on an invalid endpoint, were aborted, or
+    /// their last transaction is in error. Blocks of ungrouped packets are
+    /// not in error, though the packets in them may be.
+    pub fn item_error(&mut self, item: &TrafficItem)
+        -> Result<bool, Error>
+    {
+        use TrafficItem::*;
+        let transaction_id = match item {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let endpoint = self.endpoints.get(entry.endpoint_id())?;
+                let (ep_type, _) = self
+                    .device_data(&endpoint.device_id())?
+                    .endpoint_details(endpoint.address());
+                if matches!(ep_type, EndpointType::Ungrouped) {
+                    return Ok(false);
+                }
+                if matches!(ep_type, EndpointType::Invalid) ||
+                    self.transfer_aborted(&entry)?
+                {
+                    return Ok(true);
+                }
+                let range = self.transfer_range(&entry)?;
+                if range.start == range.end {
+                    return Ok(false);
+                }
+                let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+                ep_traf.transaction_ids.get(range.end - 1)?
+            },
+            Transaction(_, transaction_id) => *transaction_id,
+            Packet(.., packet_id) => {
+                let packet = self.packet(*packet_id)?;
+                let original_length =
+                    self.packet_original_length(*packet_id)?;
+                return Ok(
+                    stored_packet_error(&packet, original_length).is_some())
+            },
+        };
+        let transaction = self.transaction(transaction_id)?;
+        Ok(matches!(transaction.start_pid, PID::Malformed) ||
+           matches!(transaction.end_pid, PID::STALL | PID::ERR))
+    }
+
+    /// Whether a traffic item is part of a group of polls which were not
+    /// answered with data, such as IN transactions answered with NAK.
+    ///
+    /// Transactions and packets are polling if their transfer is.
+    pub fn item_polling(&mut self, item: &TrafficItem)
+        -> Result<bool, Error>
+    {
+        use TrafficItem::*;
+        let transfer_id = match item {
+            Transfer(i) | Transaction(i, _) | Packet(i, ..) => *i
+        };
+        let entry = self.transfer_index.get(transfer_id)?;
+        let endpoint_id = entry.endpoint_id();
+        let endpoint = self.endpoints.get(endpoint_id)?;
+        let (ep_type, _) = self
+            .device_data(&endpoint.device_id())?
+            .endpoint_details(endpoint.address());
+        if matches!(ep_type,
+                    EndpointType::Invalid |
+                    EndpointType::Framing |
+                    EndpointType::Ungrouped |
+                    EndpointType::Normal(usb::EndpointType::Control))
+        {
+            return Ok(false);
+        }
+        let range = self.transfer_range(&entry)?;
+        if range.start == range.end {
+            return Ok(false);
+        }
+        let transaction_id = self
+            .endpoint_traffic(endpoint_id)?
+            .transaction_ids
+            .get(range.start)?;
+        Ok(!self.transaction(transaction_id)?.successful())
+    }
+
+    /// Time from the first packet of one transaction to the last packet of
+    /// another, in nanoseconds.
+    fn transactions_duration(&mut self,
+                             first: TransactionId,
+                             last: TransactionId)
+        -> Result<u64, Error>
+    {
+        let start = self.transaction_index.get(first)?;
+        let end = self.transaction_index
+            .target_range(last, self.packet_index.len())?
+            .end - 1;
+        let start_time = self.packet_time(start)?;
+        let end_time = self.packet_time(end)?;
+        Ok(end_time.saturating_sub(start_time))
+    }
+
+    /// Decode the control transfer starting at a traffic item.
+    ///
+    /// Returns None if the item is not the start of a control transfer,
+    /// or the transfer is too incomplete to decode.
+    pub fn item_control_transfer(&mut self, item: &TrafficItem)
+        -> Result<Option<ControlTransfer>, Error>
+    {
+        let transfer_id = match item {
+            TrafficItem::Transfer(transfer_id) => *transfer_id,
+            _ => return Ok(None),
+        };
+        let entry = self.transfer_index.get(transfer_id)?;
+        if !entry.is_start() {
+            return Ok(None);
+        }
+        let endpoint_id = entry.endpoint_id();
+        let endpoint = self.endpoints.get(endpoint_id)?;
+        let (ep_type, _) = self
+            .device_data(&endpoint.device_id())?
+            .endpoint_details(endpoint.address());
+        if !matches!(ep_type, EndpointType::Normal(usb::EndpointType::Control)) {
+            return Ok(None);
+        }
+        Ok(self.control_transfer(endpoint.device_address(), &entry).ok())
+    }
+
+    /// Time from the first packet of a transfer to its last, in
+    /// nanoseconds.
+    pub fn transfer_duration(&mut self, transfer_id: TransferId)
+        -> Result<u64, Error>
+    {
+        let entry = self.transfer_index.get(transfer_id)?;
+        let range = self.transfer_range(&entry)?;
+        if range.start == range.end {
+            bail!("Transfer {transfer_id} has no transactions")
+        }
+        let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+        let first = ep_traf.transaction_ids.get(range.start)?;
+        let last = ep_traf.transaction_ids.get(range.end - 1)?;
+        self.transactions_duration(first, last)
+    }
+
+    /// Find the path of indices through the traffic tree to an item.
+    ///
+    /// Returns None if the item is not shown because of the filter. If it
+    /// is below the level of detail shown, the path is to the item shown
+    /// which contains it.
+    pub fn item_path(&mut self, item: &TrafficItem)
+        -> Result<Option<Vec<u64>>, Error>
+    {
+        use TrafficItem::*;
+        let transfer_id = match item {
+            Transfer(transfer_id) |
+            Transaction(transfer_id, _) |
+            Packet(transfer_id, ..) => *transfer_id,
+        };
+        let item_id = self.item_index.bisect_left(&transfer_id)?;
+        if item_id.value >= self.item_index.len() ||
+            self.item_index.get(item_id)? != transfer_id
+        {
+            bail!("Transfer {transfer_id} is not a top-level item")
+        }
+        let index = match (&self.item_order, &self.item_filter) {
+            (Some(order), _) => match order
+                .iter()
+                .position(|id| *id == item_id)
+            {
+                Some(index) => index as u64,
+                None => return Ok(None),
+            },
+            (None, Some(filtered)) => match filtered.position(item_id) {
+                Some(index) => index,
+                None => return Ok(None),
+            },
+            (None, None) => item_id.value,
+        };
+        let mut path = vec![index];
+        if let Transaction(_, transaction_id) | Packet(_, transaction_id, _) =
+            item
+        {
+            let entry = self.transfer_index.get(transfer_id)?;
+            let ungrouped = self.ungrouped(&entry)?;
+            if !self.transfer_children_shown(ungrouped) {
+                return Ok(Some(path));
+            }
+            let ep_transfer_id = entry.transfer_id();
+            let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+            let offset = ep_traf.transfer_index.get(ep_transfer_id)?;
+            let position = ep_traf.transaction_ids.bisect_left(transaction_id)?;
+            path.push(position.value - offset.value);
+            // Ungrouped packets are the direct children of their block.
+            if let (Packet(.., packet_id), false, DetailLevel::Packets) =
+                (item, ungrouped, self.detail)
+            {
+                let first = self.transaction_index.get(*transaction_id)?;
+                path.push(packet_id.value - first.value);
+            }
+        }
+        Ok(Some(path))
+    }
+
+    /// Find the traffic item for a packet, within its transaction.
+    pub fn packet_item(&mut self, packet_id: PacketId)
+        -> Result<TrafficItem, Error>
+    {
+        if packet_id.value >= self.packet_index.len() {
+            bail!("There is no packet {}", packet_id.value + 1)
+        }
+        // Find the last transaction starting at or before this packet.
+        let mut transaction_id = self.transaction_index.bisect_left(&packet_id)?;
+        if transaction_id.value == self.transaction_index.len() ||
+            self.transaction_index.get(transaction_id)? != packet_id
+        {
+            if transaction_id.value == 0 {
+                bail!("Packet {} is not part of a transaction",
+                      packet_id.value + 1)
+            }
+            transaction_id -= 1;
+        }
+        match self.transaction_item(transaction_id)? {
+            TrafficItem::Transaction(transfer_id, transaction_id) =>
+                Ok(TrafficItem::Packet(transfer_id, transaction_id, packet_id)),
+            _ => bail!("Expected a transaction item"),
+        }
+    }
+
+    /// Find the traffic item for a transaction, within its transfer.
+    pub fn transaction_item(&mut self, transaction_id: TransactionId)
+        -> Result<TrafficItem, Error>
+    {
+        if transaction_id.value >= self.transaction_index.len() {
+            bail!("There is no transaction {}", transaction_id.value + 1)
+        }
+        // Find the endpoint which the transaction was assigned to.
+        let endpoint_count = self.shared.endpoint_readers.load().len() as u64;
+        let mut found = None;
+        for endpoint_id in (0..endpoint_count).map(EndpointId::from) {
+            let ep_traf = self.endpoint_traffic(endpoint_id)?;
+            let ep_transaction_id =
+                ep_traf.transaction_ids.bisect_left(&transaction_id)?;
+            if ep_transaction_id.value < ep_traf.transaction_ids.len() &&
+                ep_traf.transaction_ids.get(ep_transaction_id)? ==
+                    transaction_id
+            {
+                found = Some((endpoint_id, ep_transaction_id));
+                break;
+            }
+        }
+        let (endpoint_id, ep_transaction_id) = found.with_context(|| format!(
+            "Transaction {} is not yet assigned to an endpoint",
+            transaction_id.value + 1))?;
+
+        // Find the transfer on that endpoint which contains it.
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        let ep_transfer_id =
+            ep_traf.transfer_index.bisect_left(&(ep_transaction_id + 1))?;
+        if ep_transfer_id.value == 0 {
+            bail!("Transaction {} is not yet part of a transfer",
+                  transaction_id.value + 1)
+        }
+        let ep_transfer_id = ep_transfer_id - 1;
+        let first_ep_transaction_id =
+            ep_traf.transfer_index.get(ep_transfer_id)?;
+        let first_transaction_id =
+            ep_traf.transaction_ids.get(first_ep_transaction_id)?;
+
+        // Top-level items are added in the order of the transactions that
+        // caused them, so the item for the start of the transfer can be
+        // found by searching for its first transaction.
+        let item_count = self.item_index.len();
+        let mut low = 0;
+        let mut high = item_count;
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let item_id = TrafficItemId::from(middle);
+            if self.item_transaction(item_id)? < first_transaction_id {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        for index in low..item_count {
+            let item_id = TrafficItemId::from(index);
+            let transfer_id = self.item_index.get(item_id)?;
+            let entry = self.transfer_index.get(transfer_id)?;
+            if entry.is_start() &&
+                entry.endpoint_id() == endpoint_id &&
+                entry.transfer_id() == ep_transfer_id
+            {
+                return Ok(TrafficItem::Transaction(transfer_id, transaction_id));
+            }
+            if self.item_transaction(item_id)? > first_transaction_id {
+                break;
+            }
+        }
+        bail!("Transfer for transaction {} not found", transaction_id.value + 1)
+    }
+
+    /// The transaction which was being decoded when an item was added.
+    fn item_transaction(&mut self, item_id: TrafficItemId)
+        -> Result<TransactionId, Error>
+    {
+        let transfer_id = self.item_index.get(item_id)?;
+        let entry = self.transfer_index.get(transfer_id)?;
+        let range = self.transfer_range(&entry)?;
+        let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+        let ep_transaction_id = if entry.is_start() {
+            range.start
+        } else {
+            range.end - 1
+        };
+        ep_traf.transaction_ids.get(ep_transaction_id)
+    }
+
+    /// Show only the top-level items which match a filter, or all if None.
+    pub fn set_filter(&mut self, filter: Option<Arc<Filter>>) {
+        self.item_filter = filter.map(FilteredItems::new);
+    }
+
+    /// Show the top-level items in a given order, or in capture order if
+    /// None.
+    ///
+    /// The order replaces any filter, so should list only the items which
+    /// match it.
+    pub fn set_order(&mut self, order: Option<Arc<Vec<TrafficItemId>>>) {
+        self.item_order = order;
+    }
+
+    /// Describe groups of at least `min` SOFs as idle frames, with the
+    /// time they span, or as SOF packets if None.
+    pub fn set_idle_frames(&mut self, min: Option<u64>) {
+        self.idle_frames = min;
+    }
+
+    /// Describe groups of polls with the time they span, as well as their
+    /// number.
+    pub fn set_poll_durations(&mut self, durations: bool) {
+        self.poll_durations = durations;
+    }
+
+    /// Show items down to the given level of detail. Transactions and
+    /// packets below it are hidden, by showing their parents as having no
+    /// children.
+    pub fn set_detail(&mut self, detail: DetailLevel) {
+        self.detail = detail;
+    }
+
+    /// Whether the children of a transfer are shown, given whether they
+    /// are ungrouped packets rather than transactions.
+    fn transfer_children_shown(&self, ungrouped: bool) -> bool {
+        match self.detail {
+            DetailLevel::Transfers => false,
+            DetailLevel::Transactions => !ungrouped,
+            DetailLevel::Packets => true,
+        }
+    }
+
+    /// Check further items against the current filter.
+    fn filtered_children(&mut self)
+        -> Result<Option<(CompletionStatus, u64)>, Error>
+    {
+        use CompletionStatus::*;
+        let mut filtered = match self.item_filter.take() {
+            Some(filtered) => filtered,
+            None => return Ok(None),
+        };
+        // Check whether the capture is complete before checking the last
+        // items, so that no items can be added after we finish.
+        let completion = self.completion();
+        let result = filtered.update(self);
+        let count = filtered.count();
+        self.item_filter = Some(filtered);
+        Ok(Some(match (completion, result?) {
+            (Complete, true) => (Complete, count),
+            _ => (Ongoing, count),
+        }))
+    }
+
+    /// Name of a device, giving its address, and if other devices used
+    /// the same address, which of them it was, e.g. `Device 5 #2`.
+    pub fn device_name(&mut self, device_id: DeviceId)
+        -> Result<String, Error>
+    {
+        let address = self.devices.get(device_id)?.address;
+        let mut count = 0;
+        let mut number = 0;
+        // Device 0 is the default address, used before devices are
+        // addressed.
+        for id in (1..self.devices.len()).map(DeviceId::from) {
+            if self.devices.get(id)?.address == address {
+                count += 1;
+                if id == device_id {
+                    number = count;
+                }
+            }
+        }
+        Ok(if count > 1 {
+            format!("Device {address} #{number}")
+        } else {
+            format!("Device {address}")
+        })
+    }
+
+    /// Comments to be saved with packets in a pcapng file: those of the
+    /// annotations on traffic items, and the labels of markers.
+    pub fn packet_comments(&mut self)
+        -> Result<BTreeMap<PacketId, String>, Error>
+    {
+        let annotations = self.shared.annotations.load_full();
+        let markers = self.shared.markers.load_full();
+        let mut comments = annotations.packet_comments(self)?;
+        markers.packet_comments(self, &mut comments)?;
+        Ok(comments)
+    }
+
+    /// Packets in the given scope, in capture order.
+    pub fn export_packets(&mut self, scope: &ExportScope)
+        -> Result<Vec<PacketId>, Error>
+    {
+        use ExportScope::*;
+        match scope {
+            Items(items) => {
+                let mut packet_ids = Vec::new();
+                for item in items {
+                    packet_ids.extend(self.item_packets(item)?);
+                }
+                // Items may overlap, e.g. a transfer and its transactions.
+                packet_ids.sort_unstable();
+                packet_ids.dedup();
+                Ok(packet_ids)
+            },
+            TimeRange(start, end) => {
+                let first = self.packet_times.bisect_left(start)?;
+                let last = self.packet_times.bisect_left(end)?;
+                Ok((first.value..last.value)
+                    .map(PacketId::from)
+                    .collect())
+            },
+            Device(device_id) => {
+                let mut transaction_ids = Vec::new();
+                let endpoint_count =
+                    self.shared.endpoint_readers.load().len() as u64;
+                for endpoint_id in (0..endpoint_count).map(EndpointId::from) {
+                    let endpoint = self.endpoints.get(endpoint_id)?;
+                    if endpoint.device_id() != *device_id {
+                        continue;
+                    }
+                    transaction_ids.extend(
+                        self.endpoint_transaction_ids(endpoint_id)?);
+                }
+                transaction_ids.sort_unstable();
+                self.transaction_packets(transaction_ids)
+            },
+        }
+    }
+
+    /// Packets belonging to a traffic item, in capture order.
+    fn item_packets(&mut self, item: &TrafficItem)
+        -> Result<Vec<PacketId>, Error>
+    {
+        use TrafficItem::*;
+        let transaction_ids = match item {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let range = self.transfer_range(&entry)?;
+                let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+                ep_traf.transaction_ids.get_range(&range)?
+            },
+            Transaction(_, transaction_id) => vec![*transaction_id],
+            Packet(.., packet_id) => return Ok(vec![*packet_id]),
+        };
+        self.transaction_packets(transaction_ids)
+    }
+
+    /// Packets exchanged with each device address, in capture order, in
+    /// order of address. Packets sent to the same address by different
+    /// devices are included together, and addresses with no packets are
+    /// left out. Packets which are not sent to any address, such as SOF
+    /// packets, are not included.
+    pub fn device_address_packets(&mut self)
+        -> Result<Vec<(DeviceAddr, Vec<PacketId>)>, Error>
+    {
+        let mut addresses: Vec<(DeviceAddr, Vec<TransactionId>)> =
+            Vec::new();
+        let endpoint_count =
+            self.shared.endpoint_readers.load().len() as u64;
+        for endpoint_id in (0..endpoint_count).map(EndpointId::from) {
+            let endpoint = self.endpoints.get(endpoint_id)?;
+            if endpoint.number().0 >= INVALID_EP_NUM.0 {
+                continue;
+            }
+            let address = endpoint.device_address();
+            let transaction_ids = self.endpoint_transaction_ids(endpoint_id)?;
+            match addresses.iter_mut().find(|(addr, _)| *addr == address) {
+                Some((_, ids)) => ids.extend(transaction_ids),
+                None => addresses.push((address, transaction_ids)),
+            }
+        }
+        addresses.retain(|(_, transaction_ids)| !transaction_ids.is_empty());
+        addresses.sort_by_key(|(address, _)| address.0);
+        addresses
+            .into_iter()
+            .map(|(address, mut transaction_ids)| {
+                transaction_ids.sort_unstable();
+                Ok((address, self.transaction_packets(transaction_ids)?))
+            })
+            .collect()
+    }
+
+    /// All transactions on an endpoint.
+    fn endpoint_transaction_ids(&mut self, endpoint_id: EndpointId)
+        -> Result<Vec<TransactionId>, Error>
+    {
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        let count = ep_traf.transaction_ids.len();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let range = EndpointTransactionId::from(0)..
+            EndpointTransactionId::from(count);
+        ep_traf.transaction_ids.get_range(&range)
+    }
+
+    /// The packets of the given transactions.
+    fn transaction_packets(&mut self, transaction_ids: Vec<TransactionId>)
+        -> Result<Vec<PacketId>, Error>
+    {
+        let mut packet_ids = Vec::new();
+        for transaction_id in transaction_ids {
+            let range = self.transaction_index.target_range(
+                transaction_id, self.packet_index.len())?;
+            packet_ids.extend(
+                (range.start.value..range.end.value).map(PacketId::from));
+        }
+        Ok(packet_ids)
+    }
+
+    fn transaction(&mut self, id: TransactionId)
+        -> Result<Transaction, Error>
+    {
+        let packet_id_range = self.transaction_index.target_range(
+            id, self.packet_index.len())?;
+        let packet_count = packet_id_range.len();
+        let start_packet_id = packet_id_range.start;
+        let start_pid = self.packet_pid(start_packet_id)?;
+        let end_pid = self.packet_pid(packet_id_range.end - 1)?;
+        use PID::*;
+        use StartComplete::*;
+        let (split, data_packet_id) = match start_pid {
+            SETUP | IN | OUT if packet_count >= 2 =>
+                (None, Some(start_packet_id + 1)),
+            SPLIT => {
+                let token_packet_id = start_packet_id + 1;
+                let split_packet = self.packet(start_packet_id)?;
+                let token_pid = self.packet_pid(token_packet_id)?;
+                let split_fields = SplitFields::from_packet(&split_packet);
+                let data_packet_id = match (split_fields.sc(), token_pid) {
+                    (Start, SETUP | OUT) | (Complete, IN) => {
+                        if packet_count >= 3 {
+                            Some(start_packet_id + 2)
+                        } else {
+                            None
+                        }
+                    },
+                    (..) => None
+                };
+                (Some((split_fields, token_pid)), data_packet_id)
+            },
+            _ => (None, None)
+        };
+        let payload_byte_range = if let Some(packet_id) = data_packet_id {
+            let packet_byte_range = self.packet_index.target_range(
+                packet_id, self.packet_data.len())?;
+            let pid = self.packet_data.get(packet_byte_range.start)?;
+            match PID::from(pid) {
+                DATA0 | DATA1 => Some({
+                    packet_byte_range.start + 1 .. packet_byte_range.end - 2
+                }),
+                _ => None
+            }
+        } else {
+            None
+        };
+        Ok(Transaction {
+            start_pid,
+            end_pid,
+            split,
+            data_packet_id,
+            packet_id_range,
+            payload_byte_range,
+        })
+    }
+
+    fn control_transfer(&mut self,
+                        address: DeviceAddr,
+                        entry: &TransferIndexEntry)
+        -> Result<ControlTransfer, Error>
+    {
+        let endpoint_id = entry.endpoint_id();
+        let range = self.transfer_range(entry)?;
+        let aborted = self.transfer_aborted(entry)?;
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        let transaction_ids = ep_traf.transaction_ids.get_range(&range)?;
+        let data_range = ep_traf.transfer_data_range(&range)?;
+        let data_length = ep_traf
+            .transfer_data_length(&data_range)?
+            .try_into()?;
+        let data = self.transfer_bytes(endpoint_id, &data_range, data_length)?;
+        let setup_transaction = self.transaction(transaction_ids[0])?;
+        let fields = self.transaction_fields(&setup_transaction)?;
+        let direction = fields.type_fields.direction();
+        let last = transaction_ids.len() - 1;
+        let last_transaction = self.transaction(transaction_ids[last])?;
+        let result = match last_transaction.control_result(direction) {
+            ControlResult::Incomplete if aborted => ControlResult::Aborted,
+            result => result,
+        };
+        Ok(ControlTransfer {
+            address,
+            fields,
+            data,
+            result,
+        })
+    }
+
+    /// All control transfers made to a device, in the order they started.
+    ///
+    /// Transfers which cannot be decoded, e.g. because they have no SETUP
+    /// stage, are skipped.
+    pub fn device_control_transfers(&mut self, device_id: DeviceId)
+        -> Result<Vec<ControlTransfer>, Error>
+    {
+        let address = self.devices.get(device_id)?.address;
+        let mut transfers = Vec::new();
+        for entry in self.device_control_entries(device_id)? {
+            if let Ok(transfer) = self.control_transfer(address, &entry) {
+                transfers.push(transfer);
+            }
+        }
+        Ok(transfers)
+    }
+
+    /// All control transfers made to a device, in the order they started,
+    /// with the times of the first and last packets of each.
+    ///
+    /// Transfers which cannot be decoded are skipped.
+    pub fn device_control_timeline(&mut self, device_id: DeviceId)
+        -> Result<Vec<(ControlTransfer, Timestamp, Timestamp)>, Error>
+    {
+        let address = self.devices.get(device_id)?.address;
+        let mut transfers = Vec::new();
+        for entry in self.device_control_entries(device_id)? {
+            if let Ok(transfer) = self.control_transfer(address, &entry) {
+                let (start, end) = self.transfer_times(&entry)?;
+                transfers.push((transfer, start, end));
+            }
+        }
+        Ok(transfers)
+    }
+
+    /// Index entries for the start of each transfer on a device's control
+    /// endpoint.
+    fn device_control_entries(&mut self, device_id: DeviceId)
+        -> Result<Vec<TransferIndexEntry>, Error>
+    {
+        let mut entries = Vec::new();
+        for index in 0..self.endpoints.len() {
+            let endpoint_id = EndpointId::from(index);
+            let endpoint = self.endpoints.get(endpoint_id)?;
+            if endpoint.device_id() != device_id ||
+                endpoint.number() != CONTROL_EP_NUM
+            {
+                continue;
+            }
+            let transfer_count =
+                self.endpoint_traffic(endpoint_id)?.transfer_index.len();
+            for transfer_id in 0..transfer_count {
+                let mut entry = TransferIndexEntry::default();
+                entry.set_endpoint_id(endpoint_id);
+                entry.set_transfer_id(transfer_id.into());
+                entry.set_is_start(true);
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Times of the first and last packets of a transfer.
+    fn transfer_times(&mut self, entry: &TransferIndexEntry)
+        -> Result<(Timestamp, Timestamp), Error>
+    {
+        let range = self.transfer_range(entry)?;
+        if range.start == range.end {
+            bail!("Transfer has no transactions")
+        }
+        let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+        let first = ep_traf.transaction_ids.get(range.start)?;
+        let last = ep_traf.transaction_ids.get(range.end - 1)?;
+        let start = self.transaction_index.get(first)?;
+        let end = self.transaction_index
+            .target_range(last, self.packet_index.len())?
+            .end - 1;
+        Ok((self.packet_time(start)?, self.packet_time(end)?))
+    }
+
+    pub fn device_data(&self, id: &DeviceId)
+        -> Result<Arc<DeviceData>, Error>
+    {
+        Ok(self.shared.device_data
+            .load()
+            .get(*id)
+            .with_context(|| format!("Capture has no device with ID {id}"))?
+            .clone())
+    }
+
+    fn device_version(&self, id: &DeviceId) -> Result<u32, Error> {
+        Ok(self.device_data(id)?.version())
+    }
+
+    pub fn try_configuration(&self, dev: &DeviceId, conf: &ConfigNum)
+        -> Option<Arc<Configuration>>
+    {
+        self.device_data(dev)
+            .ok()?
+            .configurations
+            .load()
+            .get(*conf)
+            .cloned()
+    }
+
+    fn transfer_extended(&mut self,
+                         endpoint_id: EndpointId,
+                         transfer_id: TransferId)
+        -> Result<bool, Error>
+    {
+        use EndpointState::*;
+        let count = self.transfer_index.len();
+        if transfer_id.value + 1 >= count {
+            return Ok(false);
+        };
+        let state = self.endpoint_state(transfer_id + 1)?;
+        Ok(match state.get(endpoint_id.value as usize) {
+            Some(ep_state) => EndpointState::from(*ep_state) == Ongoing,
+            None => false
+        })
+    }
+
+    pub fn finish(&mut self) {
+        self.shared.complete.store(true, Release);
+    }
+
+    fn completion(&self) -> CompletionStatus {
+        use CompletionStatus::*;
+        match self.shared.complete.load(Acquire) {
+            false => Ongoing,
+            true => Complete,
+        }
+    }
+}
+
+impl EndpointReader {
+    fn transfer_data_range(&mut self, range: &Range<EndpointTransactionId>)
+        -> Result<Range<EndpointDataEvent>, Error>
+    {
+        let first_data_id = self.data_transactions.bisect_left(&range.start)?;
+        let last_data_id = self.data_transactions.bisect_left(&range.end)?;
+        Ok(first_data_id..last_data_id)
+    }
+
+    fn transfer_data_length(&mut self, range: &Range<EndpointDataEvent>)
+        -> Result<u64, Error>
+    {
+        if range.start == range.end {
+            return Ok(0);
+        }
+        let num_data_events = self.data_byte_counts.len();
+        let first_byte_count = self.data_byte_counts.get(range.start)?;
+        let last_byte_count = if range.end >= num_data_events {
+            self.shared.as_ref().total_data.load(Acquire)
+        } else {
+            self.data_byte_counts.get(range.end)?
+        };
+        Ok(last_byte_count - first_byte_count)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum CompletionStatus {
+    Complete,
+    Ongoing
+}
+
+impl CompletionStatus {
+    pub fn is_complete(&self) -> bool {
+        use CompletionStatus::*;
+        match self {
+            Complete => true,
+            Ongoing => false,
+        }
+    }
+}
+
+pub trait ItemSource<Item> {
+    fn item(&mut self, parent: Option<&Item>, index: u64)
+        -> Result<Item, Error>;
+    fn item_update(&mut self, item: &Item)
+        -> Result<Option<Item>, Error>;
+    fn child_item(&mut self, parent: &Item, index: u64)
+        -> Result<Item, Error>;
+    fn item_children(&mut self, parent: Option<&Item>)
+        -> Result<(CompletionStatus, u64), Error>;
+    fn summary(&mut self, item: &Item) -> Result<String, Error>;
+    fn connectors(&mut self, item: &Item) -> Result<String, Error>;
+}
+
+impl ItemSource<TrafficItem> for CaptureReader {
+    fn item(&mut self, parent: Option<&TrafficItem>, index: u64)
+        -> Result<TrafficItem, Error>
+    {
+        match parent {
+            None => {
+                let item_id = match (&self.item_order, &self.item_filter) {
+                    (Some(order), _) => *order.get(index as usize)
+                        .with_context(||
+                            format!("Sorted item {index} not found"))?,
+                    (None, Some(filtered)) => filtered.get(index)
+                        .with_context(||
+                            format!("Filtered item {index} not found"))?,
+                    (None, None) => TrafficItemId::from(index),
+                };
+                let transfer_id = self.item_index.get(item_id)?;
+                Ok(TrafficItem::Transfer(transfer_id))
+            },
+            Some(item) => self.child_item(item, index)
+        }
+    }
+
+    fn item_update(&mut self, _item: &TrafficItem)
+        -> Result<Option<TrafficItem>, Error>
+    {
+        Ok(None)
+    }
+
+    fn child_item(&mut self, parent: &TrafficItem, index: u64)
+        -> Result<TrafficItem, Error>
+    {
+        use TrafficItem::*;
+        Ok(match parent {
+            Transfer(transfer_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let ungrouped = self.ungrouped(&entry)?;
+                let endpoint_id = entry.endpoint_id();
+                let ep_transfer_id = entry.transfer_id();
+                let ep_traf = self.endpoint_traffic(endpoint_id)?;
+                let offset = ep_traf.transfer_index.get(ep_transfer_id)?;
+                let transaction_id = ep_traf.transaction_ids.get(offset + index)?;
+                if ungrouped {
+                    // Each ungrouped packet is alone in its transaction,
+                    // and is shown directly within its block.
+                    let packet_id = self.transaction_index.get(transaction_id)?;
+                    Packet(*transfer_id, transaction_id, packet_id)
+                } else {
+                    Transaction(*transfer_id, transaction_id)
+                }
+            },
+            Transaction(transfer_id, transaction_id) =>
+                Packet(*transfer_id, *transaction_id, {
+                    self.transaction_index.get(*transaction_id)? + index}),
+            Packet(..) => bail!("Packets have no child items")
+        })
+    }
+
+    fn item_children(&mut self, parent: Option<&TrafficItem>)
+        -> Result<(CompletionStatus, u64), Error>
+    {
+        use TrafficItem::*;
+        use CompletionStatus::*;
+        Ok(match parent {
+            None => match &self.item_order {
+                Some(order) => (Complete, order.len() as u64),
+                None => match self.filtered_children()? {
+                    Some(children) => children,
+                    None => (self.completion(), self.item_index.len()),
+                },
+            },
+            Some(Transfer(transfer_id)) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                if !entry.is_start() {
+                    return Ok((Complete, 0));
+                }
+                let ungrouped = self.ungrouped(&entry)?;
+                if !self.transfer_children_shown(ungrouped) {
+                    return Ok((Complete, 0));
+                }
+                let transaction_count = self.transfer_range(&entry)?.len();
+                let ep_traf = self.endpoint_traffic(entry.endpoint_id())?;
+                if entry.transfer_id().value >= ep_traf.end_index.len() {
+                    (Ongoing, transaction_count)
+                } else {
+                    (Complete, transaction_count)
+                }
+            },
+            Some(Transaction(..)) if self.detail != DetailLevel::Packets =>
+                (Complete, 0),
+            Some(Transaction(_, transaction_id)) => {
+                let packet_count = self.transaction_index.target_range(
+                    *transaction_id, self.packet_index.len())?.len();
+                if transaction_id.value < self.transaction_index.len() - 1 {
+                    (Complete, packet_count)
+                } else {
+                    (Ongoing, packet_count)
+                }
+            },
+            Some(Packet(..)) => (Complete, 0),
+        })
+    }
+
+    fn summary(&mut self, item: &TrafficItem)
+        -> Result<String, Error>
+    {
+        use TrafficItem::*;
+        use usb::StartComplete::*;
+        Ok(match item {
+            Packet(.., packet_id) => {
+                let packet = self.packet(*packet_id)?;
+                let first_byte = *packet.first().with_context(|| format!(
+                    "Packet {packet_id} is empty, cannot retrieve PID"))?;
+                let pid = PID::from(first_byte);
+                let source = match self.packet_source(*packet_id)? {
+                    Some(name) => format!(" from {name}"),
+                    None => String::new(),
+                };
+                let original_length =
+                    self.packet_original_length(*packet_id)?;
+                format!("{pid} packet{}{source}",
+                    match PacketFields::from_packet(&packet) {
+                        PacketFields::SOF(sof) => format!(
+                            " with frame number {}, CRC {:02X}",
+                            sof.frame_number(),
+                            sof.crc()),
+                        PacketFields::Token(token) => format!(
+                            " on {}.{}, CRC {:02X}",
+                            token.device_address(),
+                            token.endpoint_number(),
+                            token.crc()),
+                        PacketFields::Data(data) if original_length.is_some() =>
+                            format!(
+                                " with CRC {:04X} and {} of {} data bytes: {}",
+                                data.crc,
+                                packet.len() - 3,
+                                original_length.map_or(0, |length| length - 3),
+                                Bytes::first(100, &packet[1 .. packet.len() - 2])),
+                        PacketFields::Data(data) if packet.len() <= 3 => format!(
+                            " with CRC {:04X} and no data",
+                            data.crc),
+                        PacketFields::Data(data) => format!(
+                            " with CRC {:04X} and {} data bytes: {}",
+                            data.crc,
+                            packet.len() - 3,
+                            Bytes::first(100, &packet[1 .. packet.len() - 2])),
+                        PacketFields::Split(split) => format!(
+                            " {} {} speed {} transaction on hub {} port {}",
+                            match split.sc() {
+                                Start => "starting",
+                                Complete => "completing",
+                            },
+                            format!("{:?}", split.speed()).to_lowercase(),
+                            format!("{:?}", split.endpoint_type()).to_lowercase(),
+                            split.hub_address(),
+                            split.port()),
+                        PacketFields::None => match pid {
+                            PID::Malformed => format!(": {packet:02X?}"),
+                            _ => "".to_string()
+                        }
+                    })
+            },
+            Transaction(transfer_id, transaction_id) => {
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let endpoint_id = entry.endpoint_id();
+                let endpoint = self.endpoints.get(endpoint_id)?;
+                let transaction = self.transaction(*transaction_id)?;
+                transaction.description(self, &endpoint)?
+            },
+            Transfer(transfer_id) => {
+                use EndpointType::*;
+                use usb::EndpointType::*;
+                let entry = self.transfer_index.get(*transfer_id)?;
+                let endpoint_id = entry.endpoint_id();
+                let endpoint = self.endpoints.get(endpoint_id)?;
+                let device_id = endpoint.device_id();
+                let dev_data = self.device_data(&device_id)?;
+                let ep_addr = endpoint.address();
+                let (ep_type, _) = dev_data.endpoint_details(ep_addr);
+                let range = self.transfer_range(&entry)?;
+                let count = range.len();
+                match (ep_type, entry.is_start()) {
+                    (Invalid, true) =>
+                        trf("{} invalid groups", &[&count]),
+                    (Invalid, false) =>
+                        tr("End of invalid groups"),
+                    (Framing, true) =>
+                        trf("{} SOF groups", &[&count]),
+                    (Framing, false) =>
+                        tr("End of SOF groups"),
+                    (Ungrouped, true) => {
+                        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+                        let first = ep_traf.transaction_ids.get(range.start)?;
+                        let first = self.transaction_index.get(first)?;
+                        trf("Packets {} to {}",
+                            &[&(first.value + 1), &(first.value + count)])
+                    },
+                    (Ungrouped, false) =>
+                        tr("End of packets"),
+                    (Normal(Control), true) => {
+                        let addr = endpoint.device_address();
+                        match self.control_transfer(addr, &entry) {
+                            Ok(transfer) => transfer.summary(),
+                            Err(_) => trf(
+                                "Incomplete control transfer on device {}",
+                                &[&addr])
+                        }
+                    },
+                    (Normal(Control), false) => {
+                        let addr = endpoint.device_address();
+                        if self.transfer_aborted(&entry)? {
+                            trf("End of aborted control transfer on device {}",
+                                &[&addr])
+                        } else {
+                            trf("End of control transfer on device {}",
+                                &[&addr])
+                        }
+                    },
+                    (endpoint_type, starting) => {
+                        let ep_transfer_id = entry.transfer_id();
+                        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+                        let range = ep_traf.transfer_index.target_range(
+                            ep_transfer_id, ep_traf.transaction_ids.len())?;
+                        let first_transaction_id =
+                            ep_traf.transaction_ids.get(range.start)?;
+                        let first_transaction =
+                            self.transaction(first_transaction_id)?;
+                        let ep_type_english = format!("{endpoint_type}");
+                        let ep_type_string = tr(&ep_type_english);
+                        let ep_type_lower =
+                            tr(&ep_type_english.to_lowercase());
+                        let count = if first_transaction.split.is_some() {
+                            (count + 1) / 2
+                        } else {
+                            count
+                        };
+                        match (first_transaction.successful(), starting) {
+                            (true, true) => {
+                                let ep_traf =
+                                    self.endpoint_traffic(endpoint_id)?;
+                                let data_range =
+                                    ep_traf.transfer_data_range(&range)?;
+                                let length =
+                                    ep_traf.transfer_data_length(&data_range)?;
+                                let length_string = fmt_size(length);
+                                let display_length = min(length, 100) as usize;
+                                let transfer_bytes = self.transfer_bytes(
+                                    endpoint_id, &data_range, display_length)?;
+                                let display_bytes = Bytes {
+                                    partial: length > display_length as u64,
+                                    bytes: &transfer_bytes,
+                                };
+                                trf("{} transfer of {} on endpoint {}: {}",
+                                    &[&ep_type_string, &length_string,
+                                      &endpoint, &display_bytes])
+                            },
+                            (true, false) => trf(
+                                "End of {} transfer on endpoint {}",
+                                &[&ep_type_lower, &endpoint]),
+                            (false, true) if self.poll_durations => {
+                                let last_transaction_id =
+                                    self.endpoint_traffic(endpoint_id)?
+                                        .transaction_ids
+                                        .get(range.end - 1)?;
+                                let duration = self.transactions_duration(
+                                    first_transaction_id,
+                                    last_transaction_id)?;
+                                trf("Polling {} times over {} for {} \
+                                     transfer on endpoint {}",
+                                    &[&count, &milliseconds(duration),
+                                      &ep_type_lower, &endpoint])
+                            },
+                            (false, true) => trf(
+                                "Polling {} times for {} transfer on \
+                                 endpoint {}",
+                                &[&count, &ep_type_lower, &endpoint]),
+                            (false, false) => trf(
+                                "End polling for {} transfer on endpoint {}",
+                                &[&ep_type_lower, &endpoint]),
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn connectors(&mut self, item: &TrafficItem)
+        -> Result<String, Error>
+    {
+        use EndpointState::*;
+        use TrafficItem::*;
+        let endpoint_count = self.endpoints.len() as usize;
+        let max_string_length = endpoint_count + "    └──".len();
+        let mut connectors = String::with_capacity(max_string_length);
+        let transfer_id = match item {
+            Transfer(i) | Transaction(i, _) | Packet(i, ..) => *i
+        };
+        let entry = self.transfer_index.get(transfer_id)?;
+        // Ungrouped packets are drawn at the level of transactions.
+        let item = &match item {
+            Packet(transfer_id, transaction_id, _)
+                if self.ungrouped(&entry)? =>
+                    Transaction(*transfer_id, *transaction_id),
+            item => *item,
+        };
+        let endpoint_id = entry.endpoint_id();
+        let endpoint_state = self.endpoint_state(transfer_id)?;
+        let extended = self.transfer_extended(endpoint_id, transfer_id)?;
+        let ep_traf = self.endpoint_traffic(endpoint_id)?;
+        let last_transaction = match item {
+            Transaction(_, transaction_id) | Packet(_, transaction_id, _) => {
+                let range = ep_traf.transfer_index.target_range(
+                    entry.transfer_id(), ep_traf.transaction_ids.len())?;
+                let last_transaction_id =
+                    ep_traf.transaction_ids.get(range.end - 1)?;
+                *transaction_id == last_transaction_id
+            }, _ => false
+        };
+        let last_packet = match item {
+            Packet(_, transaction_id, packet_id) => {
+                let range = self.transaction_index.target_range(
+                    *transaction_id, self.packet_index.len())?;
+                *packet_id == range.end - 1
+            }, _ => false
+        };
+        let last = last_transaction && !extended;
+        let mut thru = false;
+        for (i, &state) in endpoint_state.iter().enumerate() {
+            let state = EndpointState::from(state);
+            let active = state != Idle;
+            let on_endpoint = i == endpoint_id.value as usize;
+            thru |= match (item, state, on_endpoint) {
+                (Transfer(..), Starting | Ending, _) => true,
+                (Transaction(..) | Packet(..), _, true) => on_endpoint,
+                _ => false,
+            };
+            connectors.push(match item {
+                Transfer(..) => {
+                    match (state, thru) {
+                        (Idle,     false) => ' ',
+                        (Idle,     true ) => '─',
+                        (Starting, _    ) => '○',
+                        (Ongoing,  false) => '│',
+                        (Ongoing,  true ) => '┼',
+                        (Ending,   _    ) => '└',
+                    }
+                },
+                Transaction(..) => {
+                    match (on_endpoint, active, thru, last) {
+                        (false, false, false, _    ) => ' ',
+                        (false, false, true,  _    ) => '─',
+                        (false, true,  false, _    ) => '│',
+                        (false, true,  true,  _    ) => '┼',
+                        (true,  _,     _,     false) => '├',
+                        (true,  _,     _,     true ) => '└',
+                    }
+                },
+                Packet(..) => {
+                    match (on_endpoint, active, last) {
+                        (false, false, _    ) => ' ',
+                        (false, true,  _    ) => '│',
+                        (true,  _,     false) => '│',
+                        (true,  _,     true ) => ' ',
+                    }
+                }
+            });
+        };
+        let state_length = endpoint_state.len();
+        for _ in state_length..endpoint_count {
+            connectors.push(match item {
+                Transfer(..)    => '─',
+                Transaction(..) => '─',
+                Packet(..)      => ' ',
+            });
+        }
+        connectors.push_str(
+            match (item, last_packet) {
+                (Transfer(_), _) if entry.is_start() => "─",
+                (Transfer(_), _)                     => "──□ ",
+                (Transaction(..), _)                 => "───",
+                (Packet(..), false)                  => "    ├──",
+                (Packet(..), true)                   => "    └──",
+            }
+        );
+        Ok(connectors)
+    }
+}
+
+impl ItemSource<DeviceItem> for CaptureReader {
+    fn item(&mut self, parent: Option<&DeviceItem>, index: u64)
+        -> Result<DeviceItem, Error>
+    {
+        match parent {
+            None => {
+                let device_id = DeviceId::from(index + 1);
+                let data = self.device_data(&device_id)?;
+                Ok(DeviceItem::Device(device_id, data.version()))
+            },
+            Some(item) => self.child_item(item, index)
+        }
+    }
+
+    fn item_update(&mut self, item: &DeviceItem)
+        -> Result<Option<DeviceItem>, Error>
+    {
+        use DeviceItem::*;
+        Ok(match item {
+            Device(dev, version) |
+            DeviceDescriptorField(dev, .., version) |
+            ConfigurationDescriptorField(dev, .., version) |
+            InterfaceDescriptorField(dev, .., version) |
+            EndpointDescriptorField(dev, .., version) => {
+                let new = self.device_version(dev)?;
+                if *version != new {
+                    Some(match *item {
+                        Device(dev, _) =>
+                            Device(dev, new),
+                        DeviceDescriptorField(dev, field, _) =>
+                            DeviceDescriptorField(dev, field, new),
+                        ConfigurationDescriptorField(dev, conf, field, _) =>
+                            ConfigurationDescriptorField(dev, conf, field, new),
+                        InterfaceDescriptorField(dev, conf, iface, field, _) =>
+                            InterfaceDescriptorField(dev, conf, iface, field, new),
+                        EndpointDescriptorField(dev, conf, iface, ep, field, _) =>
+                            EndpointDescriptorField(dev, conf, iface, ep, field, new),
+                        _ => unreachable!()
+                    })
+                } else {
+                    None
+                }
+            },
+            _ => None
+        })
+    }
+
+    fn child_item(&mut self, parent: &DeviceItem, index: u64)
+        -> Result<DeviceItem, Error>
+    {
+        use DeviceItem::*;
+        Ok(match parent {
+            Device(dev, _version) => match index {
+                0 => DeviceDescriptor(*dev),
+                conf => Configuration(*dev,
+                    ConfigNum(conf.try_into()?)),
+            },
+            DeviceDescriptor(dev) =>
+                DeviceDescriptorField(*dev,
+                    DeviceField(index.try_into()?),
+                    self.device_version(dev)?),
+            Configuration(dev, conf) => match index {
+                0 => ConfigurationDescriptor(*dev, *conf),
+                n => Interface(*dev, *conf,
+                    InterfaceNum((n - 1).try_into()?)),
+            },
+            ConfigurationDescriptor(dev, conf) =>
+                ConfigurationDescriptorField(*dev, *conf,
+                    ConfigField(index.try_into()?),
+                    self.device_version(dev)?),
+            Interface(dev, conf, iface) => match index {
+                0 => InterfaceDescriptor(*dev, *conf, *iface),
+                n => EndpointDescriptor(*dev, *conf, *iface,
+                    InterfaceEpNum((n - 1).try_into()?))
+            },
+            InterfaceDescriptor(dev, conf, iface) =>
+                InterfaceDescriptorField(*dev, *conf, *iface,
+                    InterfaceField(index.try_into()?),
+                    self.device_version(dev)?),
+            EndpointDescriptor(dev, conf, iface, ep) =>
+                EndpointDescriptorField(*dev, *conf, *iface, *ep,
+                    EndpointField(index.try_into()?),
+                    self.device_version(dev)?),
+            _ => bail!("This device item type cannot have children")
+        })
+    }
+
+    fn item_children(&mut self, parent: Option<&DeviceItem>)
+        -> Result<(CompletionStatus, u64), Error>
+    {
+        use DeviceItem::*;
+        use CompletionStatus::*;
+        let (completion, children) = match parent {
+            None =>
+                (self.completion(),
+                 self.devices.len().saturating_sub(1) as usize),
+            Some(Device(dev, _version)) =>
+                (Ongoing, {
+                    let configs = &self.device_data(dev)?.configurations;
+                    let count = configs.load().len();
+                    if count == 0 { 1 } else { count }
+                }),
+            Some(DeviceDescriptor(dev)) =>
+                match self.device_data(dev)?.device_descriptor.load().as_ref() {
+                    Some(_) => (Ongoing, usb::DeviceDescriptor::NUM_FIELDS),
+                    None => (Ongoing, 0),
+                },
+            Some(Configuration(dev, conf)) =>
+                match self.try_configuration(dev, conf) {
+                    Some(conf) => (Ongoing, 1 + conf.interfaces.len()),
+                    None => (Ongoing, 0)
+                },
+            Some(ConfigurationDescriptor(dev, conf)) =>
+                match self.try_configuration(dev, conf) {
+                    Some(_) => (Ongoing, usb::ConfigDescriptor::NUM_FIELDS),
+                    None => (Ongoing, 0)
+                },
+            Some(Interface(dev, conf, iface)) =>
+                match self.try_configuration(dev, conf) {
+                    Some(conf) =>
+                        (Ongoing,
+                         1 + conf.interface(iface)?.endpoint_descriptors.len()),
+                    None => (Ongoing, 0)
+                },
+            Some(InterfaceDescriptor(..)) =>
+                (Ongoing, usb::InterfaceDescriptor::NUM_FIELDS),
+            Some(EndpointDescriptor(..)) =>
+                (Complete, usb::EndpointDescriptor::NUM_FIELDS),
+            _ => (Ongoing, 0)
+        };
+        Ok((completion, children as u64))
+    }
+
+    fn summary(&mut self, item: &DeviceItem)
+        -> Result<String, Error>
+    {
+        use DeviceItem::*;
+        Ok(match item {
+            Device(dev, _version) => {
+                let name = self.device_name(*dev)?;
+                let data = self.device_data(dev)?;
+                format!("{name}: {}", data.description())
+            },
+            DeviceDescriptor(dev) => {
+                match self.device_data(dev)?.device_descriptor.load().as_ref() {
+                    Some(_) => "Device descriptor",
+                    None => "No device descriptor"
+                }.to_string()
+            },
+            DeviceDescriptorField(dev, field, _ver) => {
+                let data = self.device_data(dev)?;
+                let device_descriptor = data.device_descriptor.load();
+                match device_descriptor.as_ref() {
+                    Some(descriptor) => {
+                        let strings = data.strings.load();
+                        descriptor.field_text(*field, strings.as_ref())
+                    },
+                    None => bail!("Device descriptor missing")
+                }
+            },
+            Configuration(_, conf) => format!(
+                "Configuration {conf}"),
+            ConfigurationDescriptor(..) =>
+                "Configuration descriptor".to_string(),
+            ConfigurationDescriptorField(dev, conf, field, _ver) => {
+                let data = self.device_data(dev)?;
+                let config_descriptor = data.configuration(conf)?.descriptor;
+                let strings = data.strings.load();
+                config_descriptor.field_text(*field, strings.as_ref())
+            },
+            Interface(_, _, iface) => format!(
+                "Interface {iface}"),
+            InterfaceDescriptor(..) =>
+                "Interface descriptor".to_string(),
+            InterfaceDescriptorField(dev, conf, iface, field, _ver) => {
+                let data = self.device_data(dev)?;
+                let config = data.configuration(conf)?;
+                let interface = config.interface(iface)?;
+                let strings = data.strings.load();
+                interface.descriptor.field_text(*field, strings.as_ref())
+            },
+            EndpointDescriptor(dev, conf, iface, ep) => {
+                let addr = self.device_data(dev)?
+                               .configuration(conf)?
+                               .interface(iface)?
+                               .endpoint_descriptor(ep)?
+                               .endpoint_address;
+                format!("Endpoint {} {}", addr.number(), addr.direction())
+            },
+            EndpointDescriptorField(dev, conf, iface, ep, field, _ver) => {
+                self.device_data(dev)?
+                    .configuration(conf)?
+                    .interface(iface)?
+                    .endpoint_descriptor(ep)?
+                    .field_text(*field)
+            }
+        })
+    }
+
+    fn connectors(&mut self, item: &DeviceItem) -> Result<String, Error> {
+        use DeviceItem::*;
+        let depth = match item {
+            Device(..) => 0,
+            DeviceDescriptor(..) => 1,
+            DeviceDescriptorField(..) => 2,
+            Configuration(..) => 1,
+            ConfigurationDescriptor(..) => 2,
+            ConfigurationDescriptorField(..) => 3,
+            Interface(..) => 2,
+            InterfaceDescriptor(..) => 3,
+            InterfaceDescriptorField(..) => 4,
+            EndpointDescriptor(..) => 3,
+            EndpointDescriptorField(..) => 4,
+        };
+        Ok("   ".repeat(depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read_to_string, remove_file, write, File};
+    use std::path::{Path, PathBuf};
+    use crate::decoder::Decoder;
+    use crate::export::write_text_item;
+    use crate::pcap::timestamp;
+    use pcap_file::pcap::PcapReader;
+
+    /// Set this variable to regenerate the reference decodes from the
+    /// current decoder, after an intended change to its output.
+    const UPDATE_VARIABLE: &str = "PACKETRY_UPDATE_REFERENCE";
+
+    /// Decode a capture.
+    fn load(path: &Path) -> CaptureReader {
+        let pcap_file = File::open(path).unwrap();
+        let mut pcap_reader = PcapReader::new(pcap_file).unwrap();
+        let header = pcap_reader.header();
+        let (writer, reader) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        while let Some(result) = pcap_reader.next_raw_packet() {
+            let packet = result.unwrap();
+            let timestamp = timestamp(&header, &packet);
+            decoder.handle_raw_packet(&packet.data, timestamp).unwrap();
+        }
+        decoder.finish().unwrap();
+        reader
+    }
+
+    /// Decode a capture, and describe every item in the traffic tree.
+    fn decode(path: &Path) -> String {
+        describe(&mut load(path))
+    }
+
+    /// Describe every item in the traffic tree of a capture.
+    fn describe(reader: &mut CaptureReader) -> String {
+        let mut output = Vec::new();
+        let num_items = reader.item_index.len();
+        for item_id in 0 .. num_items {
+            let item = reader.item(None, item_id).unwrap();
+            write_text_item(reader, &item, None, &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Describe the first line at which a decode differs from its
+    /// reference, if any.
+    fn first_difference(expected: &str, actual: &str) -> Option<String> {
+        let mut expected_lines = expected.lines();
+        let mut actual_lines = actual.lines();
+        for line_number in 1.. {
+            match (expected_lines.next(), actual_lines.next()) {
+                (None, None) => return None,
+                (expected, actual) if expected == actual => continue,
+                (expected, actual) => return Some(format!(
+                    "line {line_number}:\n  expected: {}\n  actual:   {}",
+                    expected.unwrap_or("(end of file)"),
+                    actual.unwrap_or("(end of file)"))),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_captures() {
+        let update = std::env::var_os(UPDATE_VARIABLE).is_some();
+        let test_dir = PathBuf::from("../tests/");
+        let list = read_to_string(test_dir.join("tests.txt")).unwrap();
+        let mut failures = Vec::new();
+        for test_name in list.lines() {
+            let test_path = test_dir.join(test_name);
+            let ref_path = test_path.join("reference.txt");
+            let out_path = test_path.join("output.txt");
+            let output = decode(&test_path.join("capture.pcap"));
+            if update {
+                write(ref_path, output).unwrap();
+                continue;
+            }
+            let reference = read_to_string(ref_path).unwrap();
+            match first_difference(&reference, &output) {
+                // Keep the output of a failed test, for comparison.
+                Some(difference) => {
+                    write(out_path, output).unwrap();
+                    failures.push(format!("{test_name}, {difference}"));
+                },
+                None => if out_path.exists() {
+                    remove_file(out_path).unwrap();
+                }
+            }
+        }
+        assert!(failures.is_empty(),
+            "Decodes differ from their references:\n\n{}\n\n\
+             The full output of each is in output.txt in its directory. \
+             If the changes are intended, run the tests with {} set to \
+             update the references.",
+            failures.join("\n"), UPDATE_VARIABLE);
+    }
+
+    #[test]
+    fn test_idle_frames() {
+        let path = PathBuf::from("../tests/hackrf-dfu-enum/capture.pcap");
+        let mut reader = load(&path);
+        reader.set_idle_frames(Some(8));
+        let output = describe(&mut reader);
+        let idle: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("idle frames"))
+            .collect();
+        assert_eq!(idle.len(), 2);
+        assert!(idle[0].starts_with(" 8 idle frames ("));
+        assert!(idle[1].starts_with(" 31 idle frames ("));
+        assert!(idle[1].ends_with(" ms)"));
+        // Shorter runs are still shown as SOF packets.
+        assert!(output.contains("\n 2 SOF packets\n"));
+        reader.set_idle_frames(None);
+        assert_eq!(describe(&mut reader), decode(&path));
+    }
+
+    #[test]
+    fn test_detail_level() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut reader = load(&path);
+        let full = describe(&mut reader);
+        // The second item is a control transfer, whose first transaction
+        // has three packets.
+        let transfer: TrafficItem = reader.item(None, 1).unwrap();
+        let transaction = reader.child_item(&transfer, 0).unwrap();
+        let packet = reader.child_item(&transaction, 2).unwrap();
+        let (_, transactions) = reader.item_children(Some(&transfer)).unwrap();
+        assert_eq!(reader.item_path(&packet).unwrap(), Some(vec![1, 0, 2]));
+
+        reader.set_detail(DetailLevel::Transactions);
+        assert_eq!(reader.item_children(Some(&transfer)).unwrap().1,
+                   transactions);
+        assert_eq!(reader.item_children(Some(&transaction)).unwrap().1, 0);
+        assert_eq!(reader.item_path(&packet).unwrap(), Some(vec![1, 0]));
+
+        reader.set_detail(DetailLevel::Transfers);
+        assert_eq!(reader.item_children(Some(&transfer)).unwrap().1, 0);
+        assert_eq!(reader.item_path(&packet).unwrap(), Some(vec![1]));
+        // Top-level items are unaffected.
+        let top: Vec<String> = full
+            .lines()
+            .filter(|line| !line.starts_with(' '))
+            .map(String::from)
+            .collect();
+        assert_eq!(describe(&mut reader).lines().collect::<Vec<_>>(), top);
+
+        reader.set_detail(DetailLevel::Packets);
+        assert_eq!(describe(&mut reader), full);
+        assert_eq!(DetailLevel::from_name("transactions"),
+                   Some(DetailLevel::Transactions));
+    }
+
+    #[test]
+    fn test_device_address_packets() {
+        let path = PathBuf::from("../tests/split-enum/capture.pcap");
+        let mut reader = load(&path);
+        let addresses = reader.device_address_packets().unwrap();
+        let counts: Vec<(u8, usize)> = addresses
+            .iter()
+            .map(|(address, packet_ids)| (address.0, packet_ids.len()))
+            .collect();
+        // SOF packets are in none of the files.
+        assert_eq!(counts, [(0, 45), (12, 127), (14, 146)]);
+        let mut all: Vec<PacketId> = Vec::new();
+        for (_, packet_ids) in &addresses {
+            assert!(packet_ids.windows(2).all(|pair| pair[0] < pair[1]));
+            all.extend(packet_ids);
+        }
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(all.len(), 45 + 127 + 146);
+    }
+
+    #[test]
+    fn test_export_packets() {
+        use ExportScope::*;
+        let path = PathBuf::from("../tests/split-enum/capture.pcap");
+        let mut reader = load(&path);
+        let ids = |range: Range<u64>| -> Vec<PacketId> {
+            range.map(PacketId::from).collect()
+        };
+
+        // A transfer includes the packets of all its transactions.
+        let transfer = reader.item(None, 1).unwrap();
+        let transfer_packets =
+            reader.export_packets(&Items(vec![transfer])).unwrap();
+        assert!(!transfer_packets.is_empty());
+        let (_, count) = reader.item_children(Some(&transfer)).unwrap();
+        let mut from_transactions = Vec::new();
+        for index in 0..count {
+            let transaction = reader.child_item(&transfer, index).unwrap();
+            from_transactions.extend(
+                reader.export_packets(&Items(vec![transaction])).unwrap());
+        }
+        assert_eq!(from_transactions, transfer_packets);
+
+        // A packet is exported alone.
+        let transaction = reader.child_item(&transfer, 0).unwrap();
+        let packet = reader.child_item(&transaction, 0).unwrap();
+        assert_eq!(reader.export_packets(&Items(vec![packet])).unwrap(),
+                   transfer_packets[..1]);
+
+        // Several items are merged in capture order, without duplicates.
+        let other = reader.item(None, 0).unwrap();
+        let other_packets =
+            reader.export_packets(&Items(vec![other])).unwrap();
+        let merged = reader.export_packets(
+            &Items(vec![transfer, other, transaction, packet])).unwrap();
+        let mut expected = [&transfer_packets[..], &other_packets[..]]
+            .concat();
+        expected.sort_unstable();
+        assert_eq!(merged, expected);
+        assert!(reader.export_packets(&Items(Vec::new()))
+            .unwrap()
+            .is_empty());
+
+        // A time range includes its start, but not its end. Packets may
+        // share a timestamp, so use packets later than those before them.
+        let mut later = Vec::new();
+        for index in 1..reader.packet_index.len() {
+            let previous = reader.packet_time(PacketId::from(index - 1));
+            let time = reader.packet_time(PacketId::from(index));
+            if previous.unwrap() < time.unwrap() {
+                later.push(index);
+            }
+        }
+        let (first, last) = (later[0], later[3]);
+        let start = reader.packet_time(PacketId::from(first)).unwrap();
+        let end = reader.packet_time(PacketId::from(last)).unwrap();
+        assert_eq!(reader.export_packets(&TimeRange(start, end)).unwrap(),
+                   ids(first..last));
+        let after_end = reader.packet_time(PacketId::from(later[4])).unwrap();
+        assert_eq!(reader.export_packets(&TimeRange(start, end + 1))
+                       .unwrap(),
+                   ids(first..later[4]));
+        assert_eq!(reader.export_packets(&TimeRange(start, after_end))
+                       .unwrap(),
+                   ids(first..later[4]));
+        assert!(reader.export_packets(&TimeRange(start, start))
+            .unwrap()
+            .is_empty());
+
+        // A device's packets match those sent to its address.
+        let addresses = reader.device_address_packets().unwrap();
+        for (address, packet_ids) in addresses {
+            if address.0 == 0 {
+                continue;
+            }
+            let device_id = (0..reader.devices.len())
+                .map(DeviceId::from)
+                .find(|id| reader.devices.get(*id).unwrap().address ==
+                      address)
+                .unwrap();
+            assert_eq!(reader.export_packets(&Device(device_id)).unwrap(),
+                       packet_ids);
+        }
+    }
+}
+
+pub mod prelude {
+    #[allow(unused_imports)]
+    pub use super::{
+        create_capture,
+        create_endpoint,
+        CaptureReader,
+        CaptureWriter,
+        Device,
+        DeviceId,
+        DeviceData,
+        Endpoint,
+        EndpointId,
+        EndpointType,
+        EndpointState,
+        EndpointReader,
+        EndpointWriter,
+        EndpointTransactionId,
+        EndpointTransferId,
+        PacketId,
+        TrafficItemId,
+        TransactionId,
+        TransferId,
+        TransferIndexEntry,
+        Timestamp,
+        INVALID_EP_NUM,
+        FRAMING_EP_NUM,
+        UNGROUPED_EP_NUM,
+        CONTROL_EP_NUM,
+        INVALID_EP_ID,
+        FRAMING_EP_ID,
+    };
+}
@@ -0,0 +1,228 @@
+//! Correction of timestamps made by different clocks.
+//!
+//! When captures made by different tools are merged, such as one from an
+//! analyzer and one made on the host, each has timestamps from its own
+//! clock. The clocks differ by an offset, and drift apart over time as
+//! they run at slightly different rates, so the same packet appears at
+//! different times in each capture.
+//!
+//! Each capture's times are mapped onto the clock of the first by a linear
+//! model: an offset, plus a drift in parts per million of the time since
+//! the capture's first packet. The model can be given by hand, or
+//! estimated from packets seen in both captures. Only packets longer than
+//! a token, which appear once in each capture, are used to estimate it,
+//! since shorter packets such as handshakes repeat too often to be paired.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use anyhow::{Context, Error, bail};
+
+use crate::capture::Timestamp;
+
+/// Length of the longest packets which are not used to pair times.
+const MAX_SHORT_PACKET: usize = 3;
+
+/// A linear model of one clock relative to another.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ClockModel {
+    /// Offset added to every time, in nanoseconds.
+    pub offset: i64,
+    /// Drift, in parts per million of the time since the origin.
+    pub drift: f64,
+}
+
+impl ClockModel {
+    /// Parse a model such as `-1.5ms` or `250us,+12.5ppm`.
+    ///
+    /// The offset is given with a unit of s, ms, us or ns, and may be
+    /// followed by a drift in parts per million.
+    pub fn parse(text: &str) -> Result<ClockModel, Error> {
+        let invalid = || format!(
+            "Invalid clock correction '{text}', expected e.g. -1.5ms or \
+             250us,+12.5ppm");
+        let (offset, drift) = match text.split_once(',') {
+            Some((offset, drift)) => (offset, Some(drift)),
+            None => (text, None),
+        };
+        let offset = offset.trim();
+        let split = offset
+            .find(|c: char| c.is_ascii_alphabetic())
+            .with_context(invalid)?;
+        let (value, unit) = offset.split_at(split);
+        let value: f64 = value.parse().ok().with_context(invalid)?;
+        let scale = match unit {
+            "s" => 1e9,
+            "ms" => 1e6,
+            "us" => 1e3,
+            "ns" => 1.0,
+            _ => bail!(invalid()),
+        };
+        let drift = match drift {
+            Some(drift) => {
+                let drift = drift.trim();
+                drift
+                    .strip_suffix("ppm")
+                    .unwrap_or(drift)
+                    .parse()
+                    .ok()
+                    .with_context(invalid)?
+            },
+            None => 0.0,
+        };
+        if !value.is_finite() || !(-1e6..1e6).contains(&drift) {
+            bail!(invalid())
+        }
+        Ok(ClockModel {
+            offset: (value * scale).round() as i64,
+            drift,
+        })
+    }
+
+    /// Fit a model to pairs of times for the same events, each being a
+    /// time on this clock and the time on the reference clock.
+    ///
+    /// The model is a least squares fit, with drift measured from the
+    /// given origin. A single pair gives an offset alone.
+    pub fn fit(origin: Timestamp, pairs: &[(Timestamp, Timestamp)])
+        -> Option<ClockModel>
+    {
+        if pairs.is_empty() {
+            return None;
+        }
+        let count = pairs.len() as f64;
+        let points: Vec<(f64, f64)> = pairs
+            .iter()
+            .map(|&(time, reference)| (
+                time.saturating_sub(origin) as f64,
+                (reference as i128 - time as i128) as f64))
+            .collect();
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let (covariance, variance) = points
+            .iter()
+            .fold((0.0, 0.0), |(covariance, variance), (x, y)| (
+                covariance + (x - mean_x) * (y - mean_y),
+                variance + (x - mean_x) * (x - mean_x)));
+        let slope = if variance > 0.0 { covariance / variance } else { 0.0 };
+        Some(ClockModel {
+            offset: (mean_y - slope * mean_x).round() as i64,
+            drift: slope * 1e6,
+        })
+    }
+
+    /// Map a time on this clock to the reference clock.
+    pub fn apply(&self, origin: Timestamp, time: Timestamp) -> Timestamp {
+        let elapsed = time.saturating_sub(origin) as f64;
+        let shift = self.offset as f64 + elapsed * self.drift / 1e6;
+        (time as i128 + shift.round() as i128).max(0) as Timestamp
+    }
+}
+
+impl fmt::Display for ClockModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:+.6}ms,{:+.3}ppm", self.offset as f64 / 1e6, self.drift)
+    }
+}
+
+/// The times of the packets which appear once in a capture.
+///
+/// Packets are identified by a hash of their bytes, so that the contents
+/// of large captures need not be kept.
+#[derive(Default)]
+pub struct PacketTimes {
+    origin: Option<Timestamp>,
+    times: HashMap<u64, Option<Timestamp>>,
+}
+
+impl PacketTimes {
+    pub fn new() -> Self {
+        PacketTimes::default()
+    }
+
+    /// Add a packet, in order of time.
+    pub fn add(&mut self, bytes: &[u8], time: Timestamp) {
+        self.origin.get_or_insert(time);
+        if bytes.len() <= MAX_SHORT_PACKET {
+            return;
+        }
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        self.times
+            .entry(hasher.finish())
+            .and_modify(|time| *time = None)
+            .or_insert(Some(time));
+    }
+
+    /// Estimate the model mapping these times to those of a reference
+    /// capture, if they have any packets in common.
+    pub fn estimate(&self, reference: &PacketTimes) -> Option<ClockModel> {
+        let mut pairs: Vec<(Timestamp, Timestamp)> = self.times
+            .iter()
+            .filter_map(|(hash, time)| Some(
+                ((*time)?, (*reference.times.get(hash)?)?)))
+            .collect();
+        pairs.sort_unstable();
+        ClockModel::fit(self.origin?, &pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clock_model() {
+        assert_eq!(ClockModel::parse("-1.5ms").unwrap(),
+                   ClockModel { offset: -1_500_000, drift: 0.0 });
+        assert_eq!(ClockModel::parse("250us,+12.5ppm").unwrap(),
+                   ClockModel { offset: 250_000, drift: 12.5 });
+        assert_eq!(ClockModel::parse("2s,-3").unwrap(),
+                   ClockModel { offset: 2_000_000_000, drift: -3.0 });
+        assert!(ClockModel::parse("5").is_err());
+        assert!(ClockModel::parse("5m").is_err());
+        assert!(ClockModel::parse("5ns,fast").is_err());
+        let model = ClockModel { offset: -1_234_567, drift: 20.0 };
+        assert_eq!(model.to_string(), "-1.234567ms,+20.000ppm");
+        assert_eq!(ClockModel::parse(&model.to_string()).unwrap(), model);
+    }
+
+    #[test]
+    fn test_apply_clock_model() {
+        let model = ClockModel { offset: -1000, drift: 50.0 };
+        let origin = 1_000_000_000;
+        assert_eq!(model.apply(origin, origin), origin - 1000);
+        // After one second, 50 ppm is 50 µs.
+        assert_eq!(model.apply(origin, origin + 1_000_000_000),
+                   origin + 1_000_000_000 + 49_000);
+        assert_eq!(model.apply(origin, 0), 0);
+    }
+
+    #[test]
+    fn test_estimate_clock_model() {
+        // The second capture's clock starts 2 ms behind the reference,
+        // and runs 100 ppm slow.
+        let mut reference = PacketTimes::new();
+        let mut other = PacketTimes::new();
+        let origin = 5_000_000_000;
+        for i in 0..100u64 {
+            let time = origin + i * 10_000_000;
+            let data = [0xC3, i as u8, 0x00, 0x00];
+            reference.add(&data, time + 2_000_000 + i * 1000);
+            other.add(&data, time);
+            // Handshakes and repeated packets are not paired.
+            reference.add(&[0xD2], time);
+            other.add(&[0xD2], time + 500);
+            reference.add(&[0xC3, 0xFF, 0x00, 0x00], time);
+            other.add(&[0xC3, 0xFF, 0x00, 0x00], time + 500);
+        }
+        let model = other.estimate(&reference).unwrap();
+        assert_eq!(model.offset, 2_000_000);
+        assert!((model.drift - 100.0).abs() < 1e-6);
+        assert_eq!(model.apply(origin, origin + 990_000_000),
+                   origin + 992_099_000);
+        assert!(other.estimate(&PacketTimes::new()).is_none());
+    }
+}
@@ -0,0 +1,615 @@
+//! Generation of source code from the descriptors of captured devices.
+//!
+//! This lets firmware developers clone the descriptor set of a reference
+//! device, either as C struct initializers or as Rust constants.
+
+use std::fmt::Write;
+
+use anyhow::{Error, bail};
+use bytemuck::bytes_of;
+
+use crate::capture::{CaptureReader, DeviceId};
+use crate::usb::{
+    ControlResult,
+    ControlTransfer,
+    DescriptorType,
+    RequestType,
+    StandardRequest,
+};
+
+/// Languages in which descriptors can be generated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Rust,
+}
+
+impl Language {
+    /// Look up a language by name, as given on the command line.
+    pub fn from_name(name: &str) -> Option<Language> {
+        match name.to_lowercase().as_str() {
+            "c" => Some(Language::C),
+            "rust" | "rs" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    /// Extension for a source file in this language.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Language::C => "h",
+            Language::Rust => "rs",
+        }
+    }
+}
+
+/// Layout of a standard descriptor: a C type name and its fields, with
+/// their sizes in bytes.
+struct Layout {
+    type_name: &'static str,
+    fields: &'static [(&'static str, usize)],
+}
+
+impl Layout {
+    fn length(&self) -> usize {
+        self.fields.iter().map(|(_, size)| size).sum()
+    }
+
+    /// The name, size and value of each field in a descriptor.
+    fn values<'a>(&'a self, bytes: &'a [u8])
+        -> impl Iterator<Item=(&'static str, usize, u16)> + 'a
+    {
+        self.fields.iter().scan(0, move |offset, &(name, size)| {
+            let value = match size {
+                1 => bytes[*offset] as u16,
+                _ => u16::from_le_bytes([bytes[*offset], bytes[*offset + 1]]),
+            };
+            *offset += size;
+            Some((name, size, value))
+        })
+    }
+}
+
+const DEVICE: Layout = Layout {
+    type_name: "usb_device_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("bcdUSB", 2),
+        ("bDeviceClass", 1),
+        ("bDeviceSubClass", 1),
+        ("bDeviceProtocol", 1),
+        ("bMaxPacketSize0", 1),
+        ("idVendor", 2),
+        ("idProduct", 2),
+        ("bcdDevice", 2),
+        ("iManufacturer", 1),
+        ("iProduct", 1),
+        ("iSerialNumber", 1),
+        ("bNumConfigurations", 1),
+    ],
+};
+
+const CONFIGURATION: Layout = Layout {
+    type_name: "usb_configuration_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("wTotalLength", 2),
+        ("bNumInterfaces", 1),
+        ("bConfigurationValue", 1),
+        ("iConfiguration", 1),
+        ("bmAttributes", 1),
+        ("bMaxPower", 1),
+    ],
+};
+
+const INTERFACE: Layout = Layout {
+    type_name: "usb_interface_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("bInterfaceNumber", 1),
+        ("bAlternateSetting", 1),
+        ("bNumEndpoints", 1),
+        ("bInterfaceClass", 1),
+        ("bInterfaceSubClass", 1),
+        ("bInterfaceProtocol", 1),
+        ("iInterface", 1),
+    ],
+};
+
+const ENDPOINT: Layout = Layout {
+    type_name: "usb_endpoint_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("bEndpointAddress", 1),
+        ("bmAttributes", 1),
+        ("wMaxPacketSize", 2),
+        ("bInterval", 1),
+    ],
+};
+
+/// Endpoint descriptor as extended by USB Audio Class 1.0.
+const AUDIO_ENDPOINT: Layout = Layout {
+    type_name: "usb_audio_endpoint_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("bEndpointAddress", 1),
+        ("bmAttributes", 1),
+        ("wMaxPacketSize", 2),
+        ("bInterval", 1),
+        ("bRefresh", 1),
+        ("bSynchAddress", 1),
+    ],
+};
+
+const ASSOCIATION: Layout = Layout {
+    type_name: "usb_interface_association_descriptor_t",
+    fields: &[
+        ("bLength", 1),
+        ("bDescriptorType", 1),
+        ("bFirstInterface", 1),
+        ("bInterfaceCount", 1),
+        ("bFunctionClass", 1),
+        ("bFunctionSubClass", 1),
+        ("bFunctionProtocol", 1),
+        ("iFunction", 1),
+    ],
+};
+
+const LAYOUTS: [&Layout; 6] = [
+    &DEVICE,
+    &CONFIGURATION,
+    &INTERFACE,
+    &ENDPOINT,
+    &AUDIO_ENDPOINT,
+    &ASSOCIATION,
+];
+
+/// The descriptors read from a device.
+struct DescriptorSet {
+    title: String,
+    device: Option<Vec<u8>>,
+    configurations: Vec<ConfigurationBytes>,
+    strings: Vec<(u8, Vec<u16>)>,
+}
+
+/// A complete configuration descriptor set.
+struct ConfigurationBytes {
+    number: u8,
+    bytes: Vec<u8>,
+    /// Whether the bytes were rebuilt from the decoded descriptors, because
+    /// the whole set was not read in one request. If so, class-specific
+    /// descriptors are missing.
+    rebuilt: bool,
+}
+
+/// One descriptor within a configuration descriptor set.
+struct Part<'bytes> {
+    name: String,
+    comment: String,
+    layout: Option<&'static Layout>,
+    bytes: &'bytes [u8],
+}
+
+/// Generate source code defining the descriptors read from a device.
+pub fn descriptor_source(capture: &mut CaptureReader,
+                         device_id: DeviceId,
+                         language: Language)
+    -> Result<String, Error>
+{
+    let set = descriptor_set(capture, device_id)?;
+    if set.device.is_none() && set.configurations.is_empty() {
+        bail!("No descriptors were read from {}", set.title)
+    }
+    Ok(match language {
+        Language::C => c_source(&set)?,
+        Language::Rust => rust_source(&set)?,
+    })
+}
+
+fn descriptor_set(capture: &mut CaptureReader, device_id: DeviceId)
+    -> Result<DescriptorSet, Error>
+{
+    let device = capture.devices.get(device_id)?;
+    let data = capture.device_data(&device_id)?;
+    let title = format!("device {}: {}", device.address, data.description());
+    let device_bytes = data.device_descriptor
+        .load()
+        .as_deref()
+        .map(|descriptor| bytes_of(descriptor).to_vec());
+    let mut configurations: Vec<ConfigurationBytes> = data.configurations
+        .load()
+        .entries()
+        .map(|(number, config)| {
+            let mut bytes = config.to_bytes();
+            let total_length = bytes.len() as u16;
+            bytes[2..4].copy_from_slice(&total_length.to_le_bytes());
+            ConfigurationBytes { number: number.0, bytes, rebuilt: true }
+        })
+        .collect();
+    // The decoder keeps only the standard descriptors, so use the complete
+    // sets as read from the device where possible.
+    for transfer in capture.device_control_transfers(device_id)? {
+        if let Some(bytes) = configuration_read(&transfer) {
+            let config = ConfigurationBytes {
+                number: bytes[5],
+                bytes: bytes.to_vec(),
+                rebuilt: false,
+            };
+            match configurations.iter_mut().find(|c| c.number == bytes[5]) {
+                Some(existing) => *existing = config,
+                None => configurations.push(config),
+            }
+        }
+    }
+    configurations.sort_by_key(|config| config.number);
+    let strings = data.strings
+        .load()
+        .entries()
+        .map(|(id, string)| (id.0, string.chars()))
+        .collect();
+    Ok(DescriptorSet {
+        title,
+        device: device_bytes,
+        configurations,
+        strings,
+    })
+}
+
+/// The complete configuration descriptor set read by a request, if any.
+fn configuration_read(transfer: &ControlTransfer) -> Option<&[u8]> {
+    let fields = &transfer.fields;
+    let data = &transfer.data;
+    if !matches!(transfer.result, ControlResult::Completed) ||
+        !matches!(fields.type_fields.request_type(), RequestType::Standard) ||
+        !matches!(StandardRequest::from(fields.request),
+                  StandardRequest::GetDescriptor) ||
+        DescriptorType::from((fields.value >> 8) as u8) !=
+            DescriptorType::Configuration ||
+        data.len() < CONFIGURATION.length() ||
+        data[1] != DescriptorType::Configuration as u8
+    {
+        return None;
+    }
+    let total_length = u16::from_le_bytes([data[2], data[3]]) as usize;
+    if total_length < CONFIGURATION.length() || data.len() < total_length {
+        return None;
+    }
+    Some(&data[..total_length])
+}
+
+/// Split a configuration descriptor set into its descriptors.
+fn parts(bytes: &[u8]) -> Vec<Part<'_>> {
+    let mut parts: Vec<Part> = Vec::new();
+    let mut offset = 0;
+    let mut other_count = 0;
+    while offset < bytes.len() {
+        let remaining = &bytes[offset..];
+        let length = remaining[0] as usize;
+        if length < 2 || length > remaining.len() {
+            parts.push(Part {
+                name: "trailing".to_string(),
+                comment: "Bytes not forming a complete descriptor".into(),
+                layout: None,
+                bytes: remaining,
+            });
+            break;
+        }
+        let descriptor = &remaining[..length];
+        let (name, comment, layout) = match (descriptor[1], length) {
+            (0x02, 9) => (
+                "configuration".to_string(),
+                format!("Configuration {}", descriptor[5]),
+                Some(&CONFIGURATION)),
+            (0x04, 9) => (
+                format!("interface_{}_{}", descriptor[2], descriptor[3]),
+                format!("Interface {}, alternate setting {}",
+                        descriptor[2], descriptor[3]),
+                Some(&INTERFACE)),
+            (0x05, 7 | 9) => (
+                format!("endpoint_{}_{}", descriptor[2] & 0x0F,
+                        if descriptor[2] & 0x80 != 0 { "in" } else { "out" }),
+                format!("Endpoint 0x{:02X}", descriptor[2]),
+                Some(if length == 7 { &ENDPOINT } else { &AUDIO_ENDPOINT })),
+            (0x0B, 8) => (
+                format!("association_{}", descriptor[2]),
+                format!("Interface association, from interface {}",
+                        descriptor[2]),
+                Some(&ASSOCIATION)),
+            (descriptor_type, _) => {
+                other_count += 1;
+                (format!("descriptor_{other_count}"),
+                 match descriptor_type {
+                     0x20 ..= 0x3F => format!(
+                         "Class-specific descriptor, type 0x{descriptor_type:02X}"),
+                     _ => format!("Descriptor type 0x{descriptor_type:02X}"),
+                 },
+                 None)
+            }
+        };
+        // The same endpoint may appear in several alternate settings.
+        let mut unique_name = name.clone();
+        let mut suffix = 1;
+        while parts.iter().any(|part| part.name == unique_name) {
+            suffix += 1;
+            unique_name = format!("{name}_{suffix}");
+        }
+        parts.push(Part {
+            name: unique_name,
+            comment,
+            layout,
+            bytes: descriptor,
+        });
+        offset += length;
+    }
+    parts
+}
+
+/// Text of a string descriptor, as shown in comments.
+fn string_text(id: u8, chars: &[u16]) -> String {
+    match id {
+        0 => "supported languages".to_string(),
+        _ => format!("\"{}\"",
+                     String::from_utf16_lossy(chars).escape_default()),
+    }
+}
+
+fn string_length(chars: &[u16]) -> usize {
+    2 + chars.len() * 2
+}
+
+/// Bytes as hex literals, several to a line.
+fn hex_lines(bytes: &[u8], indent: &str) -> String {
+    bytes
+        .chunks(8)
+        .map(|chunk| {
+            let line: Vec<String> = chunk
+                .iter()
+                .map(|byte| format!("0x{byte:02X},"))
+                .collect();
+            format!("{indent}{}\n", line.join(" "))
+        })
+        .collect()
+}
+
+fn c_comment(text: &str) -> String {
+    text.replace("*/", "*\\/")
+}
+
+fn c_source(set: &DescriptorSet) -> Result<String, Error> {
+    let mut out = String::new();
+    writeln!(out, "/*")?;
+    writeln!(out, " * USB descriptors of {}.", c_comment(&set.title))?;
+    writeln!(out, " *")?;
+    writeln!(out, " * Generated by Packetry from a capture. Multi-byte fields \
+                   are in host byte")?;
+    writeln!(out, " * order, so these initializers assume a little-endian \
+                   target, and the")?;
+    writeln!(out, " * packed attribute assumes GCC or Clang.")?;
+    writeln!(out, " */")?;
+    writeln!(out)?;
+    writeln!(out, "#include <stdint.h>")?;
+    writeln!(out)?;
+    writeln!(out, "#ifndef PACKETRY_USB_DESCRIPTOR_TYPES")?;
+    writeln!(out, "#define PACKETRY_USB_DESCRIPTOR_TYPES")?;
+    for layout in LAYOUTS {
+        writeln!(out)?;
+        writeln!(out, "typedef struct __attribute__((packed)) {{")?;
+        for (name, size) in layout.fields {
+            let c_type = if *size == 1 { "uint8_t" } else { "uint16_t" };
+            writeln!(out, "    {c_type} {name};")?;
+        }
+        writeln!(out, "}} {};", layout.type_name)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "#endif")?;
+
+    if let Some(bytes) = &set.device {
+        writeln!(out)?;
+        writeln!(out, "static const {} device_descriptor = {{",
+                 DEVICE.type_name)?;
+        c_fields(&mut out, &DEVICE, bytes, "    ")?;
+        writeln!(out, "}};")?;
+    }
+
+    for config in &set.configurations {
+        let parts = parts(&config.bytes);
+        writeln!(out)?;
+        writeln!(out, "/* Configuration {}, {} bytes. */",
+                 config.number, config.bytes.len())?;
+        if config.rebuilt {
+            writeln!(out, "/* Rebuilt from the standard descriptors only, as \
+                           the complete set was not read. */")?;
+        }
+        writeln!(out, "static const struct __attribute__((packed)) {{")?;
+        for part in &parts {
+            match part.layout {
+                Some(layout) =>
+                    writeln!(out, "    {} {};", layout.type_name, part.name)?,
+                None =>
+                    writeln!(out, "    uint8_t {}[{}];",
+                             part.name, part.bytes.len())?,
+            }
+        }
+        writeln!(out, "}} configuration_{} = {{", config.number)?;
+        for part in &parts {
+            writeln!(out, "    /* {} */", c_comment(&part.comment))?;
+            writeln!(out, "    .{} = {{", part.name)?;
+            match part.layout {
+                Some(layout) =>
+                    c_fields(&mut out, layout, part.bytes, "        ")?,
+                None =>
+                    out.push_str(&hex_lines(part.bytes, "        ")),
+            }
+            writeln!(out, "    }},")?;
+        }
+        writeln!(out, "}};")?;
+    }
+
+    for (id, chars) in &set.strings {
+        writeln!(out)?;
+        writeln!(out, "/* String {id}: {} */",
+                 c_comment(&string_text(*id, chars)))?;
+        writeln!(out, "static const struct __attribute__((packed)) {{")?;
+        writeln!(out, "    uint8_t bLength;")?;
+        writeln!(out, "    uint8_t bDescriptorType;")?;
+        if !chars.is_empty() {
+            writeln!(out, "    uint16_t wData[{}];", chars.len())?;
+        }
+        writeln!(out, "}} string_{id} = {{")?;
+        writeln!(out, "    .bLength = 0x{:02X},", string_length(chars))?;
+        writeln!(out, "    .bDescriptorType = 0x03,")?;
+        if !chars.is_empty() {
+            writeln!(out, "    .wData = {{")?;
+            for line in chars.chunks(8) {
+                let values: Vec<String> = line
+                    .iter()
+                    .map(|c| format!("0x{c:04X},"))
+                    .collect();
+                writeln!(out, "        {}", values.join(" "))?;
+            }
+            writeln!(out, "    }},")?;
+        }
+        writeln!(out, "}};")?;
+    }
+    Ok(out)
+}
+
+fn c_fields(out: &mut String, layout: &Layout, bytes: &[u8], indent: &str)
+    -> Result<(), Error>
+{
+    for (name, size, value) in layout.values(bytes) {
+        match size {
+            1 => writeln!(out, "{indent}.{name} = 0x{value:02X},")?,
+            _ => writeln!(out, "{indent}.{name} = 0x{value:04X},")?,
+        }
+    }
+    Ok(())
+}
+
+fn rust_source(set: &DescriptorSet) -> Result<String, Error> {
+    let mut out = String::new();
+    writeln!(out, "// USB descriptors of {}.", set.title)?;
+    writeln!(out, "//")?;
+    writeln!(out, "// Generated by Packetry from a capture.")?;
+
+    if let Some(bytes) = &set.device {
+        writeln!(out)?;
+        writeln!(out, "/// Device descriptor.")?;
+        writeln!(out, "pub const DEVICE_DESCRIPTOR: [u8; {}] = [",
+                 bytes.len())?;
+        rust_fields(&mut out, &DEVICE, bytes)?;
+        writeln!(out, "];")?;
+    }
+
+    for config in &set.configurations {
+        writeln!(out)?;
+        writeln!(out, "/// Configuration {}.", config.number)?;
+        if config.rebuilt {
+            writeln!(out, "///")?;
+            writeln!(out, "/// Rebuilt from the standard descriptors only, \
+                           as the complete set was not read.")?;
+        }
+        writeln!(out, "pub const CONFIGURATION_{}: [u8; {}] = [",
+                 config.number, config.bytes.len())?;
+        for part in parts(&config.bytes) {
+            writeln!(out, "    // {}", part.comment)?;
+            match part.layout {
+                Some(layout) => rust_fields(&mut out, layout, part.bytes)?,
+                None => out.push_str(&hex_lines(part.bytes, "    ")),
+            }
+        }
+        writeln!(out, "];")?;
+    }
+
+    for (id, chars) in &set.strings {
+        writeln!(out)?;
+        if *id == 0 {
+            let languages: Vec<String> = chars
+                .iter()
+                .map(|language| format!("0x{language:04X}"))
+                .collect();
+            writeln!(out, "/// Supported language IDs, from string 0.")?;
+            writeln!(out, "pub const LANGUAGE_IDS: [u16; {}] = [{}];",
+                     chars.len(), languages.join(", "))?;
+        } else {
+            writeln!(out, "/// String {id}.")?;
+            writeln!(out, "pub const STRING_{id}: &str = {:?};",
+                     String::from_utf16_lossy(chars))?;
+        }
+    }
+    Ok(out)
+}
+
+fn rust_fields(out: &mut String, layout: &Layout, bytes: &[u8])
+    -> Result<(), Error>
+{
+    for (name, size, value) in layout.values(bytes) {
+        let values = match size {
+            1 => format!("0x{value:02X},"),
+            _ => format!("0x{:02X}, 0x{:02X},", value & 0xFF, value >> 8),
+        };
+        writeln!(out, "    {values:<12}// {name}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    fn mouse_source(language: Language) -> String {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        descriptor_source(&mut capture, DeviceId::from(1), language).unwrap()
+    }
+
+    #[test]
+    fn test_c_source() {
+        let source = mouse_source(Language::C);
+        assert!(source.contains(
+            "static const usb_device_descriptor_t device_descriptor = {"));
+        assert!(source.contains("    .idVendor = 0x1BCF,\n"));
+        assert!(source.contains("} configuration_1 = {"));
+        // The HID descriptor is not kept by the decoder, so must have been
+        // found in the traffic.
+        assert!(source.contains("/* Class-specific descriptor, type 0x21 */"));
+        assert!(!source.contains("Rebuilt"));
+        assert!(source.contains("/* String 2: \"USB Optical Mouse\" */"));
+    }
+
+    #[test]
+    fn test_rust_source() {
+        let source = mouse_source(Language::Rust);
+        assert!(source.contains("pub const DEVICE_DESCRIPTOR: [u8; 18] = ["));
+        assert!(source.contains("    0xCF, 0x1B, // idVendor\n"));
+        assert!(source.contains("pub const LANGUAGE_IDS: [u16; 1] = [0x0409];"));
+        assert!(source.contains(
+            "pub const STRING_2: &str = \"USB Optical Mouse\";"));
+        // The number of bytes listed must match the declared length.
+        let start = source.find("pub const CONFIGURATION_1").unwrap();
+        let config = &source[start..];
+        let config = &config[..config.find("];").unwrap()];
+        let length: usize = config
+            .split("; ")
+            .nth(1)
+            .and_then(|rest| rest.split(']').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = &config[config.find('\n').unwrap()..];
+        let bytes = body
+            .lines()
+            .map(|line| line.split("//").next().unwrap())
+            .flat_map(|line| line.split(','))
+            .filter(|value| value.trim().starts_with("0x"))
+            .count();
+        assert_eq!(bytes, length);
+    }
+}
@@ -1,5 +1,6 @@
 use std::cmp::max;
 use std::fmt::Debug;
+use std::io::{Read, Write};
 use std::iter::once;
 use std::marker::PhantomData;
 use std::ops::{Add, AddAssign, Range, Sub, SubAssign};
@@ -164,6 +165,47 @@ where Position: Copy + From<u64> + Into<u64>,
     }
 }
 
+impl<Position, Value, const MIN_WIDTH: usize>
+CompactWriter<Position, Value, MIN_WIDTH>
+where Position: Copy + From<u64> + Into<u64>,
+      Value: Copy + From<u64> + Into<u64> + Sub<Output=u64>
+{
+    /// Load the contents of an index saved with `CompactReader::save`.
+    ///
+    /// The index must be empty before loading.
+    pub fn load(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+        if self.length != 0 {
+            bail!("Cannot load into an index which is not empty")
+        }
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+        let field = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[i * 8 .. (i + 1) * 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let (length, base_value, delta_width) = (field(0), field(1), field(2));
+        self.segment_start_writer.load(reader)?;
+        self.segment_base_writer.load(reader)?;
+        self.segment_offset_writer.load(reader)?;
+        self.segment_width_writer.load(reader)?;
+        self.data_writer.load(reader)?;
+        // Restore the state of the current segment, so that further
+        // values can be appended.
+        if self.segment_start_writer.len() > 0 {
+            self.current_base_value = Some(Value::from(base_value));
+            self.current_delta_width = match delta_width {
+                0 => None,
+                width => Some(width as usize),
+            };
+        }
+        self.data_offset = Offset::from(self.data_writer.len());
+        self.length = length;
+        self.shared_length.store(self.length, Release);
+        Ok(())
+    }
+}
+
 impl<Position, Value> CompactReader<Position, Value>
 where
     Position: Copy + From<u64> + Into<u64> + Ord
@@ -188,6 +230,35 @@ where
             self.data_reader.size()
     }
 
+    /// Write the contents of the index, for loading with
+    /// `CompactWriter::load`.
+    pub fn save(&mut self, writer: &mut dyn Write) -> Result<(), Error> {
+        let segment_count = self.segment_start_reader.len();
+        let (base_value, delta_width) = if segment_count > 0 {
+            let last = SegmentId::from(segment_count - 1);
+            let base_value = self.segment_base_reader.get(last)?.into();
+            // A segment has no delta width until its second value.
+            let delta_width =
+                if self.segment_width_reader.len() == segment_count {
+                    self.segment_width_reader.get(last)?
+                } else {
+                    0
+                };
+            (base_value, delta_width as u64)
+        } else {
+            (0, 0)
+        };
+        for field in [self.len(), base_value, delta_width] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        self.segment_start_reader.save(writer)?;
+        self.segment_base_reader.save(writer)?;
+        self.segment_offset_reader.save(writer)?;
+        self.segment_width_reader.save(writer)?;
+        self.data_reader.save(writer)?;
+        Ok(())
+    }
+
     /// Get a single value from the index, by position.
     pub fn get(&mut self, position: Position) -> Result<Value, Error> {
         // Check position is valid.
@@ -426,6 +497,35 @@ mod tests {
         assert!(byte_width(0xFFFFFF) == 3);
     }
 
+    #[test]
+    fn test_compact_index_save_load() {
+        let (mut writer, mut reader) =
+            compact_index::<Id<u8>, Id<u16>, 1>().unwrap();
+        let mut expected = Vec::new();
+        let mut x = 0;
+        for i in 0..5000 {
+            x += if i % 1000 == 999 { 0x10000 } else { i % 7 };
+            expected.push(Id::<u16>::from(x));
+            writer.push(Id::<u16>::from(x)).unwrap();
+        }
+        let mut saved = Vec::new();
+        reader.save(&mut saved).unwrap();
+        let (mut loaded_writer, mut loaded_reader) =
+            compact_index::<Id<u8>, Id<u16>, 1>().unwrap();
+        loaded_writer.load(&mut saved.as_slice()).unwrap();
+        // Values appended after loading must follow on correctly.
+        for i in 0..100 {
+            x += i % 3;
+            expected.push(Id::<u16>::from(x));
+            loaded_writer.push(Id::<u16>::from(x)).unwrap();
+        }
+        let count = expected.len() as u64;
+        assert_eq!(loaded_reader.len(), count);
+        let all = loaded_reader.get_range(
+            &(Id::<u8>::from(0)..Id::<u8>::from(count))).unwrap();
+        assert_eq!(all, expected);
+    }
+
     #[test]
     fn test_compact_index() {
         let (mut writer, mut reader) = index_stream().unwrap();
@@ -0,0 +1,549 @@
+//! Checks of how devices follow the standard device framework of chapter 9
+//! of the USB 2.0 specification, made from a capture of them being
+//! enumerated and tested.
+//!
+//! An analyzer only sees the requests the host makes, so each check stays
+//! untested until the traffic it needs has been captured, and comes with
+//! guidance on how to make the host send it.
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, DeviceId};
+use crate::usb::{
+    ConfigNum,
+    ControlResult,
+    ControlTransfer,
+    Direction,
+    Recipient,
+    RequestType,
+    StandardRequest,
+};
+
+/// Standard request codes added by USB 3, which a USB 2 device may still
+/// be sent.
+const SET_SEL: u8 = 48;
+const SET_ISOCH_DELAY: u8 = 49;
+
+/// Descriptor types which can be read with GET_DESCRIPTOR.
+const DEVICE: u8 = 1;
+const CONFIGURATION: u8 = 2;
+const STRING: u8 = 3;
+
+/// Descriptor types which cannot be read on their own, only as part of a
+/// configuration, or which are not defined at all.
+const INVALID_DESCRIPTOR_TYPES: [u8; 3] = [0, 4, 5];
+
+/// The behaviors checked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    DeviceDescriptor,
+    ConfigurationDescriptor,
+    StringDescriptors,
+    ShortReads,
+    ResponseLengths,
+    SetAddress,
+    SetConfiguration,
+    StatusRequests,
+    InvalidRequests,
+}
+
+impl Check {
+    pub const ALL: [Check; 9] = [
+        Check::DeviceDescriptor,
+        Check::ConfigurationDescriptor,
+        Check::StringDescriptors,
+        Check::ShortReads,
+        Check::ResponseLengths,
+        Check::SetAddress,
+        Check::SetConfiguration,
+        Check::StatusRequests,
+        Check::InvalidRequests,
+    ];
+
+    /// Name of the check, as listed in a report.
+    pub fn name(&self) -> &'static str {
+        use Check::*;
+        match self {
+            DeviceDescriptor => "Device descriptor is valid",
+            ConfigurationDescriptor => "Configuration descriptor is valid",
+            StringDescriptors => "String descriptors are valid",
+            ShortReads => "Descriptors are truncated to the length requested",
+            ResponseLengths => "Responses are no longer than requested",
+            SetAddress => "SET_ADDRESS is accepted",
+            SetConfiguration => "SET_CONFIGURATION is accepted",
+            StatusRequests =>
+                "GET_STATUS, GET_CONFIGURATION and GET_INTERFACE answer \
+                 correctly",
+            InvalidRequests => "Invalid requests are stalled",
+        }
+    }
+
+    /// What the host must be made to do before the check can be made.
+    pub fn guidance(&self) -> &'static str {
+        use Check::*;
+        match self {
+            DeviceDescriptor | ConfigurationDescriptor | SetAddress |
+            SetConfiguration | ResponseLengths =>
+                "Connect the device, so that the host enumerates it.",
+            StringDescriptors =>
+                "Have the host read the device's strings, e.g. by listing \
+                 the device with lsusb -v.",
+            ShortReads =>
+                "Have the host read the first 8 bytes of the device \
+                 descriptor, as most hosts do when enumerating.",
+            StatusRequests =>
+                "Have the host send GET_STATUS, GET_CONFIGURATION or \
+                 GET_INTERFACE, e.g. with a chapter 9 test tool.",
+            InvalidRequests =>
+                "Have a chapter 9 test tool send an undefined standard \
+                 request, or request an interface or endpoint descriptor.",
+        }
+    }
+}
+
+/// The result of a check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The traffic needed for the check has not been seen.
+    Untested,
+    Passed,
+    /// The check failed, for the reasons given.
+    Failed(Vec<String>),
+}
+
+/// The traffic seen for a check, and the problems found in it.
+#[derive(Default)]
+struct Tally {
+    seen: bool,
+    failures: Vec<String>,
+}
+
+impl Tally {
+    fn fail(&mut self, reason: String) {
+        if !self.failures.contains(&reason) {
+            self.failures.push(reason);
+        }
+    }
+
+    fn outcome(self) -> Outcome {
+        if !self.failures.is_empty() {
+            Outcome::Failed(self.failures)
+        } else if self.seen {
+            Outcome::Passed
+        } else {
+            Outcome::Untested
+        }
+    }
+}
+
+/// A tally for each check.
+struct Tallies(Vec<Tally>);
+
+impl Tallies {
+    fn new() -> Tallies {
+        Tallies(Check::ALL.iter().map(|_| Tally::default()).collect())
+    }
+
+    fn get(&mut self, check: Check) -> &mut Tally {
+        &mut self.0[check as usize]
+    }
+
+    fn outcomes(self) -> Vec<(Check, Outcome)> {
+        Check::ALL
+            .iter()
+            .copied()
+            .zip(self.0.into_iter().map(Tally::outcome))
+            .collect()
+    }
+}
+
+/// Check how a device handled the requests seen in a capture.
+pub fn check_device(capture: &mut CaptureReader, device_id: DeviceId)
+    -> Result<Vec<(Check, Outcome)>, Error>
+{
+    let address = capture.devices.get(device_id)?.address;
+    let device_data = capture.device_data(&device_id)?;
+    let transfers = capture.device_control_transfers(device_id)?;
+    let default_transfers =
+        capture.device_control_transfers(DeviceId::from(0))?;
+    // A configuration value is only known to be invalid if all the
+    // device's configurations have been read.
+    let configurations = device_data.configurations.load();
+    let all_read = device_data.device_descriptor
+        .load()
+        .as_ref()
+        .map_or(false, |descriptor|
+            descriptor.num_configurations as usize == configurations.len());
+    let valid_config = |value: u16| value == 0 || !all_read ||
+        configurations.get(ConfigNum(value as u8)).is_some();
+
+    let mut tallies = Tallies::new();
+
+    for transfer in &transfers {
+        let fields = &transfer.fields;
+        let summary = transfer.summary();
+        let completed = matches!(transfer.result, ControlResult::Completed);
+        let length = transfer.data.len();
+        let requested = fields.length as usize;
+        if completed &&
+            matches!(fields.type_fields.direction(), Direction::In)
+        {
+            let check = tallies.get(Check::ResponseLengths);
+            check.seen = true;
+            if length > requested {
+                check.fail(format!(
+                    "{summary}: {length} bytes returned, but only \
+                     {requested} requested"));
+            }
+        }
+        if !matches!(fields.type_fields.request_type(), RequestType::Standard)
+        {
+            continue;
+        }
+        let request = StandardRequest::from(fields.request);
+        if invalid_request(transfer, &valid_config) {
+            let check = tallies.get(Check::InvalidRequests);
+            match transfer.result {
+                ControlResult::Stalled => check.seen = true,
+                ControlResult::Completed => {
+                    check.seen = true;
+                    check.fail(format!(
+                        "{summary}: accepted, but should have been stalled"));
+                },
+                // A request cut short shows nothing of how it was handled.
+                ControlResult::Incomplete | ControlResult::Aborted => {},
+            }
+            continue;
+        }
+        if !completed {
+            if let StandardRequest::SetConfiguration = request {
+                let check = tallies.get(Check::SetConfiguration);
+                check.seen = true;
+                check.fail(format!("{summary}: not completed"));
+            }
+            continue;
+        }
+        match request {
+            StandardRequest::GetDescriptor => {
+                let descriptor_type = (fields.value >> 8) as u8;
+                check_descriptor(transfer, descriptor_type, &mut tallies);
+            },
+            StandardRequest::SetConfiguration =>
+                tallies.get(Check::SetConfiguration).seen = true,
+            StandardRequest::GetStatus |
+            StandardRequest::GetConfiguration |
+            StandardRequest::GetInterface => {
+                let expected = match request {
+                    StandardRequest::GetStatus => 2,
+                    _ => 1,
+                };
+                let check = tallies.get(Check::StatusRequests);
+                check.seen = true;
+                if requested >= expected && length != expected {
+                    check.fail(format!(
+                        "{summary}: {length} bytes returned, expected \
+                         {expected}"));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // The device was given its address while at the default address.
+    for transfer in &default_transfers {
+        let fields = &transfer.fields;
+        let is_set_address =
+            matches!(fields.type_fields.request_type(), RequestType::Standard)
+            && matches!(StandardRequest::from(fields.request),
+                        StandardRequest::SetAddress)
+            && fields.value == address.0 as u16;
+        if !is_set_address {
+            continue;
+        }
+        let check = tallies.get(Check::SetAddress);
+        check.seen = true;
+        match transfer.result {
+            ControlResult::Completed if transfers.is_empty() =>
+                check.fail(format!(
+                    "The device did not respond at address {address} after \
+                     accepting it")),
+            ControlResult::Completed => {},
+            _ => check.fail(format!("{}: not completed", transfer.summary())),
+        }
+    }
+
+    Ok(tallies.outcomes())
+}
+
+/// Whether a standard request is one which a device must stall.
+fn invalid_request(transfer: &ControlTransfer,
+                   valid_config: &dyn Fn(u16) -> bool)
+    -> bool
+{
+    let fields = &transfer.fields;
+    match StandardRequest::from(fields.request) {
+        StandardRequest::Unknown =>
+            !matches!(fields.request, SET_SEL | SET_ISOCH_DELAY),
+        StandardRequest::GetDescriptor =>
+            matches!(fields.type_fields.recipient(), Recipient::Device) &&
+            INVALID_DESCRIPTOR_TYPES.contains(&((fields.value >> 8) as u8)),
+        StandardRequest::SetConfiguration => !valid_config(fields.value),
+        _ => false,
+    }
+}
+
+/// Check the response to a completed GET_DESCRIPTOR request.
+fn check_descriptor(transfer: &ControlTransfer,
+                    descriptor_type: u8,
+                    tallies: &mut Tallies)
+{
+    let summary = transfer.summary();
+    let data = &transfer.data;
+    let requested = transfer.fields.length as usize;
+    if data.len() < 2 {
+        return;
+    }
+    // The full length of the descriptor, as it reports itself.
+    let full_length = match descriptor_type {
+        CONFIGURATION if data.len() >= 4 =>
+            u16::from_le_bytes([data[2], data[3]]) as usize,
+        _ => data[0] as usize,
+    };
+    if full_length > requested {
+        let check = tallies.get(Check::ShortReads);
+        check.seen = true;
+        if data.len() != requested {
+            check.fail(format!(
+                "{summary}: {} bytes returned, but {requested} requested of \
+                 a {full_length} byte descriptor", data.len()));
+        }
+        return;
+    }
+    if data.len() != full_length {
+        let check = tallies.get(match descriptor_type {
+            DEVICE => Check::DeviceDescriptor,
+            CONFIGURATION => Check::ConfigurationDescriptor,
+            STRING => Check::StringDescriptors,
+            _ => return,
+        });
+        check.seen = true;
+        check.fail(format!(
+            "{summary}: {} bytes returned, but the descriptor's length is \
+             {full_length}", data.len()));
+        return;
+    }
+    match descriptor_type {
+        DEVICE => {
+            let check = tallies.get(Check::DeviceDescriptor);
+            check.seen = true;
+            for problem in device_descriptor_problems(data) {
+                check.fail(problem);
+            }
+        },
+        CONFIGURATION => {
+            let check = tallies.get(Check::ConfigurationDescriptor);
+            check.seen = true;
+            for problem in configuration_problems(data) {
+                check.fail(problem);
+            }
+        },
+        STRING => {
+            let check = tallies.get(Check::StringDescriptors);
+            check.seen = true;
+            let index = transfer.fields.value & 0xFF;
+            if data[1] != STRING {
+                check.fail(format!(
+                    "String {index} has descriptor type {}", data[1]));
+            }
+            if data[0] % 2 != 0 {
+                check.fail(format!(
+                    "String {index} has an odd length of {}", data[0]));
+            }
+            if index == 0 && data[0] < 4 {
+                check.fail("String 0 lists no languages".to_string());
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Problems found in a complete device descriptor.
+fn device_descriptor_problems(data: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if data[0] != 18 {
+        problems.push(format!(
+            "Device descriptor has bLength {}, expected 18", data[0]));
+        return problems;
+    }
+    if data[1] != DEVICE {
+        problems.push(format!(
+            "Device descriptor has bDescriptorType {}, expected 1", data[1]));
+    }
+    let usb_major = data[3];
+    let max_packet_size = data[7];
+    if usb_major < 3 && !matches!(max_packet_size, 8 | 16 | 32 | 64) {
+        problems.push(format!(
+            "Device descriptor has bMaxPacketSize0 {max_packet_size}, \
+             expected 8, 16, 32 or 64"));
+    }
+    if data[17] == 0 {
+        problems.push("Device descriptor has no configurations".to_string());
+    }
+    problems
+}
+
+/// Problems found in a complete configuration descriptor, with the
+/// interface and endpoint descriptors which follow it.
+fn configuration_problems(data: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    if data[0] != 9 || data[1] != CONFIGURATION {
+        problems.push(format!(
+            "Configuration descriptor has bLength {} and bDescriptorType {}, \
+             expected 9 and 2", data[0], data[1]));
+        return problems;
+    }
+    let declared_interfaces = data.get(4).copied().unwrap_or(0);
+    let mut interfaces = Vec::new();
+    // Endpoints declared and found for the current interface setting.
+    let mut endpoints: Option<(u8, u8, u8)> = None;
+    let check_endpoints = |endpoints: Option<(u8, u8, u8)>,
+                               problems: &mut Vec<String>|
+    {
+        if let Some((interface, declared, found)) = endpoints {
+            if declared != found {
+                problems.push(format!(
+                    "Interface {interface} declares {declared} endpoints, \
+                     but {found} follow it"));
+            }
+        }
+    };
+    let mut offset = 0;
+    while offset < data.len() {
+        let length = data[offset] as usize;
+        if length < 2 || offset + length > data.len() {
+            problems.push(format!(
+                "Descriptor at offset {offset} has an invalid length of \
+                 {length}"));
+            return problems;
+        }
+        match data[offset + 1] {
+            4 if length >= 9 => {
+                check_endpoints(endpoints, &mut problems);
+                let number = data[offset + 2];
+                if !interfaces.contains(&number) {
+                    interfaces.push(number);
+                }
+                endpoints = Some((number, data[offset + 4], 0));
+            },
+            5 => {
+                if let Some((_, _, found)) = &mut endpoints {
+                    *found += 1;
+                }
+            },
+            _ => {},
+        }
+        offset += length;
+    }
+    check_endpoints(endpoints, &mut problems);
+    if interfaces.len() != declared_interfaces as usize {
+        problems.push(format!(
+            "Configuration declares {declared_interfaces} interfaces, but \
+             {} are described", interfaces.len()));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+
+    #[test]
+    fn test_check_device() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let results = check_device(&mut capture, DeviceId::from(1)).unwrap();
+        let outcome = |check: Check| results
+            .iter()
+            .find(|(c, _)| *c == check)
+            .map(|(_, outcome)| outcome.clone())
+            .unwrap();
+        assert_eq!(results.len(), Check::ALL.len());
+        for check in [
+            Check::DeviceDescriptor,
+            Check::ConfigurationDescriptor,
+            Check::StringDescriptors,
+            Check::ShortReads,
+            Check::ResponseLengths,
+            Check::SetAddress,
+            Check::SetConfiguration,
+        ] {
+            assert_eq!(outcome(check), Outcome::Passed, "{check:?}");
+        }
+        assert_eq!(outcome(Check::InvalidRequests), Outcome::Untested);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let scenario = Scenario::parse("invalid", "
+            speed high
+            device 1
+            endpoint 1 1 in bulk 512
+            enumerate 1
+            # An interface descriptor requested on its own, and an undefined
+            # standard request, which are answered rather than stalled.
+            control 1 0x80 6 0x0400 0 09:04:00:00:01:ff:00:00:00
+            control 1 0x80 2 0 0 00").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        let results = check_device(&mut capture, DeviceId::from(1)).unwrap();
+        let (_, outcome) = results
+            .iter()
+            .find(|(check, _)| *check == Check::InvalidRequests)
+            .unwrap();
+        match outcome {
+            Outcome::Failed(reasons) => {
+                assert_eq!(reasons.len(), 2);
+                assert!(reasons[0].starts_with("Getting interface descriptor"));
+                assert!(reasons[1].ends_with(
+                    "accepted, but should have been stalled"));
+            },
+            _ => panic!("Expected a failure, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn test_descriptor_problems() {
+        let mut device = [
+            18, 1, 0x00, 0x02, 0, 0, 0, 64,
+            0x50, 0x1d, 0xe6, 0x60, 0x00, 0x01, 1, 2, 3, 1];
+        assert!(device_descriptor_problems(&device).is_empty());
+        device[7] = 12;
+        device[17] = 0;
+        assert_eq!(device_descriptor_problems(&device).len(), 2);
+
+        let mut config = vec![
+            9, 2, 32, 0, 1, 1, 0, 0x80, 50,
+            9, 4, 0, 0, 2, 0xFF, 0, 0, 0,
+            7, 5, 0x81, 2, 0x00, 0x02, 0,
+            7, 5, 0x02, 2, 0x00, 0x02, 0];
+        assert!(configuration_problems(&config).is_empty());
+        config[4] = 2;
+        config[13] = 3;
+        assert_eq!(configuration_problems(&config), [
+            "Interface 0 declares 3 endpoints, but 2 follow it",
+            "Configuration declares 2 interfaces, but 1 are described",
+        ]);
+        config[25] = 40;
+        assert_eq!(configuration_problems(&config), [
+            "Descriptor at offset 25 has an invalid length of 40"]);
+    }
+}
@@ -0,0 +1,234 @@
+//! CRC calculations for USB packets.
+//!
+//! These are run on every token and data packet as it is captured, so are
+//! table driven rather than computed a bit at a time. On x86-64 processors
+//! with carry-less multiplication, longer data payloads are first folded
+//! down sixteen bytes at a time using SIMD instructions.
+//!
+//! The CRC-32 used by zlib and Ethernet is also provided, for checking
+//! data reassembled from transfers against copies of it elsewhere.
+
+/// Calculate the 5-bit CRC of the low `bits` bits of a token or SOF packet.
+pub fn crc5(value: u16, bits: u32) -> u8 {
+    if bits == 11 {
+        CRC5_TABLE[(value & 0x7FF) as usize]
+    } else {
+        crc5_bitwise(value, bits)
+    }
+}
+
+const fn crc5_bitwise(value: u16, bits: u32) -> u8 {
+    let mut crc: u8 = 0x1F;
+    let mut i = 0;
+    while i < bits {
+        let bit = ((value >> i) & 1) as u8;
+        let top = (crc >> 4) & 1;
+        crc = (crc << 1) & 0x1F;
+        if top ^ bit != 0 {
+            crc ^= 0x05;
+        }
+        i += 1;
+    }
+    // The CRC is sent inverted, most significant bit first.
+    let mut result = 0;
+    let mut i = 0;
+    while i < 5 {
+        result |= ((crc >> i) & 1) << (4 - i);
+        i += 1;
+    }
+    result ^ 0x1F
+}
+
+/// CRC5 of every possible 11-bit token field.
+static CRC5_TABLE: [u8; 0x800] = crc5_table();
+
+const fn crc5_table() -> [u8; 0x800] {
+    let mut table = [0; 0x800];
+    let mut value = 0;
+    while value < 0x800 {
+        table[value] = crc5_bitwise(value as u16, 11);
+        value += 1;
+    }
+    table
+}
+
+/// Calculate the 16-bit CRC of a data packet payload.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    #[cfg(target_arch = "x86_64")]
+    if bytes.len() >= 32 &&
+        is_x86_feature_detected!("pclmulqdq") &&
+        is_x86_feature_detected!("sse4.1")
+    {
+        // SAFETY: the required CPU features have just been detected.
+        return unsafe { clmul::crc16(bytes) } ^ 0xFFFF;
+    }
+    crc16_update(0xFFFF, bytes) ^ 0xFFFF
+}
+
+/// Tables for calculating CRC16 eight bytes at a time. Entry `[n][b]` is
+/// the CRC contribution of byte `b` followed by `n` zero bytes.
+static CRC16_TABLES: [[u16; 0x100]; 8] = crc16_tables();
+
+const fn crc16_tables() -> [[u16; 0x100]; 8] {
+    let mut tables = [[0; 0x100]; 8];
+    let mut byte = 0;
+    while byte < 0x100 {
+        let mut crc = byte as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][byte] = crc;
+        byte += 1;
+    }
+    let mut n = 1;
+    while n < 8 {
+        let mut byte = 0;
+        while byte < 0x100 {
+            let prev = tables[n - 1][byte];
+            tables[n][byte] = (prev >> 8) ^ tables[0][(prev & 0xFF) as usize];
+            byte += 1;
+        }
+        n += 1;
+    }
+    tables
+}
+
+/// Continue a CRC16 calculation from the given register state.
+fn crc16_update(mut crc: u16, bytes: &[u8]) -> u16 {
+    let t = &CRC16_TABLES;
+    let mut chunks = bytes.chunks_exact(8);
+    for c in &mut chunks {
+        let lo = c[0] ^ (crc as u8);
+        let hi = c[1] ^ ((crc >> 8) as u8);
+        crc = t[7][lo as usize] ^ t[6][hi as usize] ^
+              t[5][c[2] as usize] ^ t[4][c[3] as usize] ^
+              t[3][c[4] as usize] ^ t[2][c[5] as usize] ^
+              t[1][c[6] as usize] ^ t[0][c[7] as usize];
+    }
+    for byte in chunks.remainder() {
+        crc = (crc >> 8) ^ t[0][((crc as u8) ^ byte) as usize];
+    }
+    crc
+}
+
+/// Calculate the CRC-32 of some data, as computed by zlib.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for byte in bytes {
+        crc = (crc >> 8) ^ CRC32_TABLE[((crc as u8) ^ byte) as usize];
+    }
+    crc ^ 0xFFFF_FFFF
+}
+
+/// CRC-32 contribution of each byte value.
+static CRC32_TABLE: [u32; 0x100] = crc32_table();
+
+const fn crc32_table() -> [u32; 0x100] {
+    let mut table = [0; 0x100];
+    let mut byte = 0;
+    while byte < 0x100 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+#[cfg(target_arch = "x86_64")]
+mod clmul {
+    use std::arch::x86_64::*;
+
+    // The payload is folded in 128-bit blocks, in bit-reflected order. The
+    // upper and lower halves of the running value are multiplied by x^191
+    // and x^127 modulo the CRC polynomial, which with the one bit shift of
+    // a reflected carry-less multiply moves them forward by 128 bits.
+    const K_191: i64 = 0xCCD0_0000_0000_0000_u64 as i64;
+    const K_127: i64 = 0xC100_0000_0000_0000_u64 as i64;
+
+    /// Calculate the CRC16 register state after the given bytes, which
+    /// must be at least 16 bytes long, starting from all ones.
+    #[target_feature(enable = "pclmulqdq", enable = "sse4.1")]
+    pub unsafe fn crc16(bytes: &[u8]) -> u16 {
+        let mut blocks = bytes.chunks_exact(16);
+        let load = |block: &[u8]| unsafe {
+            _mm_loadu_si128(block.as_ptr() as *const __m128i)
+        };
+        let constants = _mm_set_epi64x(K_127, K_191);
+        let first = blocks.next().expect("payload too short to fold");
+        // Starting the register from all ones inverts the first 16 bits.
+        let mut value = _mm_xor_si128(load(first), _mm_cvtsi32_si128(0xFFFF));
+        for block in &mut blocks {
+            let upper = _mm_clmulepi64_si128(value, constants, 0x00);
+            let lower = _mm_clmulepi64_si128(value, constants, 0x11);
+            value = _mm_xor_si128(_mm_xor_si128(upper, lower), load(block));
+        }
+        let mut folded = [0u8; 16];
+        _mm_storeu_si128(folded.as_mut_ptr() as *mut __m128i, value);
+        // The folded value leaves the same remainder as the bytes it
+        // replaces, so the rest of the CRC is found from zero.
+        let crc = super::crc16_update(0, &folded);
+        super::crc16_update(crc, blocks.remainder())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    fn crc16_bitwise(bytes: &[u8]) -> u16 {
+        let mut crc: u16 = 0xFFFF;
+        for byte in bytes {
+            crc ^= *byte as u16;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xA001
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        crc ^ 0xFFFF
+    }
+
+    #[test]
+    fn test_crc5_table() {
+        for value in 0..0x800 {
+            assert_eq!(crc5(value, 11), crc5_bitwise(value, 11));
+        }
+    }
+
+    #[test]
+    fn test_crc16_lengths() {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        for length in 0..1100 {
+            let bytes: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            assert_eq!(crc16(&bytes), crc16_bitwise(&bytes),
+                       "CRC mismatch for {length} bytes");
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"),
+                   0x414F_A339);
+    }
+}
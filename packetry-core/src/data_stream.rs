@@ -1,8 +1,9 @@
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::mem::size_of;
 use std::ops::{Deref, Range};
 
-use anyhow::Error;
+use anyhow::{Error, bail};
 use bytemuck::{bytes_of, cast_slice, from_bytes, Pod};
 
 use crate::id::Id;
@@ -91,6 +92,15 @@ where Value: Pod + Default
         let end = Id::<Value>::from_offset(size);
         Ok(start..end)
     }
+
+    /// Append the contents of a stream saved with `DataReader::save`.
+    pub fn load(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+        self.stream_writer.load(reader)?;
+        if self.size() % size_of::<Value>() as u64 != 0 {
+            bail!("Saved stream length is not a whole number of items")
+        }
+        Ok(())
+    }
 }
 
 impl<Value, const S: usize> DataReader<Value, S>
@@ -111,6 +121,11 @@ where Value: Pod + Default
         self.stream_reader.len()
     }
 
+    /// Write the length and contents of the stream.
+    pub fn save(&mut self, writer: &mut dyn Write) -> Result<(), Error> {
+        self.stream_reader.save(writer)
+    }
+
     /// Get a single item from the stream.
     pub fn get(&mut self, id: Id<Value>) -> Result<Value, Error> {
         let byte_range = id.offset_range();
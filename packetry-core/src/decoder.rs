@@ -1,19 +1,59 @@
+//! Decoding of raw packets into transactions, transfers and descriptors.
+
+use std::cmp::max;
+use std::ops::Deref;
 use std::sync::atomic::Ordering::Release;
 use std::sync::Arc;
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::thread::spawn;
 
 use anyhow::{Context, Error, bail};
 
 use crate::capture::prelude::*;
+use crate::overrides::Overrides;
 use crate::rcu::SingleWriterRcu;
 use crate::usb::{self, prelude::*};
 use crate::vec_map::{VecMap, Key};
 
+/// Number of packets which may be waiting between the stages of a
+/// decoding pipeline before the earlier stage is held up, by default.
+pub const PIPELINE_DEPTH: usize = 4096;
+
+/// Number of ungrouped packets stored in each top-level block.
+pub const PACKET_BLOCK_SIZE: u64 = 1000;
+
+/// How far packets are decoded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DecodeDepth {
+    /// Packets are grouped into transactions and transfers, and requests
+    /// and descriptors are decoded.
+    #[default]
+    Transfers,
+    /// Packets are only stored, in blocks of `PACKET_BLOCK_SIZE`, which
+    /// takes much less time and memory for very busy captures.
+    Packets,
+}
+
+/// A packet passed from one stage of a decoding pipeline to the next.
+///
+/// The data may be any type holding the packet's bytes, such as a slice of
+/// the buffer it was captured into.
+pub struct PipelinePacket<P = Vec<u8>> {
+    pub data: P,
+    pub timestamp: Timestamp,
+}
+
 impl PID {
     fn from_packet(packet: &[u8]) -> Result<PID, Error> {
         let first_byte = packet
             .first()
             .context("Packet is empty, cannot retrieve PID")?;
-        Ok(PID::from(*first_byte))
+        let pid = PID::from(*first_byte);
+        if packet.len() < pid.min_length() {
+            Ok(PID::Malformed)
+        } else {
+            Ok(pid)
+        }
     }
 }
 
@@ -35,6 +75,7 @@ struct TransferState {
     id: EndpointTransferId,
     first: PID,
     last: Option<PID>,
+    stalled: bool,
 }
 
 #[derive(PartialEq, Eq)]
@@ -77,6 +118,21 @@ impl EndpointData {
             total_data: 0,
         }
     }
+
+    /// The address being assigned by a SET_ADDRESS request, if that is the
+    /// request of the current control transfer.
+    fn address_request(&self) -> Option<DeviceAddr> {
+        let fields = self.setup.as_ref()?;
+        let standard = matches!(
+            fields.type_fields.request_type(), RequestType::Standard);
+        let set_address = matches!(
+            StandardRequest::from(fields.request), StandardRequest::SetAddress);
+        if standard && set_address {
+            Some(DeviceAddr((fields.value & 0x7F) as u8))
+        } else {
+            None
+        }
+    }
 }
 
 enum TransactionStyle {
@@ -235,12 +291,12 @@ impl TransactionState {
         self.endpoint_id.context("Transaction state has no endpoint ID")
     }
 
-    fn extract_payload(&mut self, packet: &[u8]) {
+    fn extract_payload(&mut self, packet: &[u8]) -> Result<(), Error> {
         use PID::*;
         use TransactionStyle::*;
         use usb::EndpointType::*;
         use StartComplete::*;
-        match (&self.style, PID::from(packet[0])) {
+        match (&self.style, PID::from_packet(packet)?) {
             (Simple(SETUP), DATA0) |
             (Split(Start, Control, Some(SETUP)), DATA0) => {
                 self.setup = Some(SetupFields::from_data_packet(packet));
@@ -251,6 +307,7 @@ impl TransactionState {
             }
             (..) => {},
         }
+        Ok(())
     }
 }
 
@@ -517,6 +574,22 @@ impl Key for EndpointKey {
     }
 }
 
+/// A truncated packet padded back to its original length, with zeros in
+/// place of the payload bytes that were not stored. Only the length of
+/// those bytes is needed to decode the packet again.
+fn restore_length(packet: &[u8], length: usize) -> Vec<u8> {
+    match packet.len().checked_sub(2) {
+        Some(end) if length > packet.len() => {
+            let mut restored = Vec::with_capacity(length);
+            restored.extend_from_slice(&packet[..end]);
+            restored.resize(length - 2, 0);
+            restored.extend_from_slice(&packet[end..]);
+            restored
+        },
+        _ => packet.to_vec(),
+    }
+}
+
 pub struct Decoder {
     capture: CaptureWriter,
     device_index: VecMap<DeviceAddr, DeviceId>,
@@ -525,6 +598,13 @@ pub struct Decoder {
     last_endpoint_state: Vec<u8>,
     last_item_endpoint: Option<EndpointId>,
     transaction_state: Option<TransactionState>,
+    last_timestamp: Timestamp,
+    last_source: Option<u16>,
+    snap_length: Option<usize>,
+    pipeline_depth: usize,
+    overrides: Overrides,
+    /// Endpoint holding packets in blocks, when they are not grouped.
+    ungrouped_endpoint: Option<EndpointId>,
 }
 
 impl Decoder {
@@ -538,6 +618,12 @@ impl Decoder {
             last_endpoint_state: Vec::new(),
             last_item_endpoint: None,
             transaction_state: None,
+            last_timestamp: 0,
+            last_source: None,
+            snap_length: None,
+            pipeline_depth: PIPELINE_DEPTH,
+            overrides: Overrides::default(),
+            ungrouped_endpoint: None,
         };
 
         // Add the default device.
@@ -578,15 +664,221 @@ impl Decoder {
         Ok(decoder)
     }
 
-    pub fn handle_raw_packet(&mut self, packet: &[u8])
+    /// Store at most this many bytes of the payload of each data packet.
+    ///
+    /// Truncated packets keep their PID and CRC bytes, and their original
+    /// length is recorded and used to group transactions into transfers
+    /// and to count the bytes transferred. Packets on control endpoints
+    /// are not truncated, so that requests and descriptors can still be
+    /// decoded, unless packets are not being grouped.
+    pub fn set_snap_length(&mut self, snap_length: Option<usize>) {
+        self.snap_length = snap_length;
+    }
+
+    /// Set how many packets may wait to be decoded in `decode_from`
+    /// before the producer is held up.
+    pub fn set_pipeline_depth(&mut self, depth: usize) {
+        self.pipeline_depth = depth.max(1);
+    }
+
+    /// Choose how far packets are decoded. This must be set before any
+    /// packets are handled.
+    pub fn set_decode_depth(&mut self, depth: DecodeDepth)
         -> Result<(), Error>
     {
-        let data_range = self.capture.packet_data.append(packet)?;
+        if self.capture.packet_index.len() != 0 {
+            bail!("Decode depth must be set before any packets are decoded")
+        }
+        self.ungrouped_endpoint = match (depth, self.ungrouped_endpoint) {
+            (DecodeDepth::Packets, None) => Some(self.add_endpoint(
+                DeviceAddr(0), UNGROUPED_EP_NUM, Direction::Out)?),
+            (DecodeDepth::Packets, existing) => existing,
+            (DecodeDepth::Transfers, _) => None,
+        };
+        Ok(())
+    }
+
+    /// Decode endpoints as the given classes, and devices with the given
+    /// descriptors, when their descriptors are not seen.
+    ///
+    /// Details found in descriptors replace those set for an endpoint, and
+    /// descriptors read in the capture replace those given here.
+    pub fn set_overrides(&mut self, overrides: &Overrides)
+        -> Result<(), Error>
+    {
+        self.overrides = overrides.clone();
+        for (address, device_id) in self.device_index.entries() {
+            self.apply_overrides(address, *device_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn handle_raw_packet(&mut self, packet: &[u8], timestamp: Timestamp)
+        -> Result<(), Error>
+    {
+        match self.truncate(packet) {
+            Some(truncated) => self.store_packet(&truncated, packet, timestamp),
+            None => self.store_packet(packet, packet, timestamp),
+        }
+    }
+
+    /// Decode again a packet stored in another capture.
+    ///
+    /// Packets keep the source file and original length recorded for them,
+    /// so that a capture can be analyzed again, e.g. after the decoder has
+    /// changed, without reading its files again.
+    pub fn handle_stored_packet(&mut self,
+                                capture: &mut CaptureReader,
+                                packet_id: PacketId)
+        -> Result<(), Error>
+    {
+        let packet = capture.packet(packet_id)?;
+        let timestamp = capture.packet_time(packet_id)?;
+        if let Some(source) = capture.packet_source_number(packet_id)? {
+            self.set_source(source)?;
+        }
+        match capture.packet_original_length(packet_id)? {
+            Some(length) => {
+                let original = restore_length(&packet, length as usize);
+                self.store_packet(&packet, &original, timestamp)
+            },
+            None => self.store_packet(&packet, &packet, timestamp),
+        }
+    }
+
+    /// Store a packet, which may have been truncated, and decode it.
+    ///
+    /// Transactions are grouped into transfers by the original packet, so
+    /// that truncation does not change where transfers end or how long
+    /// they are.
+    fn store_packet(&mut self,
+                    stored: &[u8],
+                    packet: &[u8],
+                    timestamp: Timestamp)
+        -> Result<(), Error>
+    {
+        // Timestamps must not go backwards, or the index would be unsorted.
+        let timestamp = max(timestamp, self.last_timestamp);
+        self.last_timestamp = timestamp;
+        let data_range = self.capture.packet_data.append(stored)?;
         let packet_id = self.capture.packet_index.push(data_range.start)?;
-        self.transaction_update(packet_id, packet)?;
+        self.capture.packet_times.push(timestamp)?;
+        if stored.len() != packet.len() {
+            self.capture.packet_lengths.push(&(packet.len() as u64))?;
+            self.capture.packet_length_index.push(packet_id)?;
+        }
+        match self.ungrouped_endpoint {
+            Some(endpoint_id) =>
+                self.ungrouped_packet(endpoint_id, packet_id, packet)?,
+            None => self.transaction_update(packet_id, packet)?,
+        }
+        Ok(())
+    }
+
+    /// Store a packet in a transaction of its own, adding it to the current
+    /// block of ungrouped packets, or starting a new block.
+    fn ungrouped_packet(&mut self,
+                        endpoint_id: EndpointId,
+                        packet_id: PacketId,
+                        packet: &[u8])
+        -> Result<(), Error>
+    {
+        let transaction_id = self.capture.transaction_index.push(packet_id)?;
+        let ep_data = &mut self.endpoint_data[endpoint_id];
+        let ep_transaction_id =
+            ep_data.writer.transaction_ids.push(transaction_id)?;
+        if ep_transaction_id.value % PACKET_BLOCK_SIZE == 0 {
+            self.transfer_close(endpoint_id, false)?;
+            let ep_data = &mut self.endpoint_data[endpoint_id];
+            let ep_transfer_id =
+                ep_data.writer.transfer_index.push(ep_transaction_id)?;
+            let pid = packet.first().map_or(PID::Malformed, |b| PID::from(*b));
+            ep_data.active = Some(TransferState {
+                id: ep_transfer_id,
+                first: pid,
+                last: None,
+                stalled: false,
+            });
+            let transfer_start_id =
+                self.add_transfer_entry(endpoint_id, ep_transfer_id, true)?;
+            self.add_item(endpoint_id, transfer_start_id)?;
+        }
+        Ok(())
+    }
+
+    /// The part of a packet to store, if it is to be truncated.
+    fn truncate(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        use PID::*;
+        let snap_length = self.snap_length?;
+        let pid = PID::from(*packet.first()?);
+        if !matches!(pid, DATA0 | DATA1 | DATA2 | MDATA) ||
+            packet.len() <= snap_length + 3
+        {
+            return None;
+        }
+        // Ungrouped packets are not decoded further, so those on control
+        // endpoints need not be kept in full.
+        if self.ungrouped_endpoint.is_none() {
+            let endpoint_id = self.transaction_state.as_ref()?.endpoint_id?;
+            let ep_data = self.endpoint_data.get(endpoint_id)?;
+            if ep_data.address.number() == CONTROL_EP_NUM {
+                return None;
+            }
+        }
+        let end = packet.len();
+        let mut truncated = Vec::with_capacity(snap_length + 3);
+        truncated.extend_from_slice(&packet[..snap_length + 1]);
+        truncated.extend_from_slice(&packet[end - 2..]);
+        Some(truncated)
+    }
+
+    /// Handle a packet read from one of several merged capture files.
+    pub fn handle_merged_packet(&mut self,
+                                packet: &[u8],
+                                timestamp: Timestamp,
+                                source: u16)
+        -> Result<(), Error>
+    {
+        self.set_source(source)?;
+        self.handle_raw_packet(packet, timestamp)
+    }
+
+    /// Record the source of each run of packets from the same file.
+    fn set_source(&mut self, source: u16) -> Result<(), Error> {
+        if self.last_source != Some(source) {
+            let packet_id = PacketId::from(self.capture.packet_index.len());
+            self.capture.packet_sources.push(&source)?;
+            self.capture.packet_source_index.push(packet_id)?;
+            self.last_source = Some(source);
+        }
         Ok(())
     }
 
+    /// Decode packets produced on another thread.
+    ///
+    /// The producer runs on a new thread, passing packets through a bounded
+    /// channel to be decoded on this one, so that reading, filtering and
+    /// saving packets can proceed while earlier packets are decoded. When
+    /// the decoder falls behind, the producer waits rather than letting
+    /// packets pile up in memory.
+    pub fn decode_from<P, F>(&mut self, producer: F) -> Result<(), Error>
+        where P: Deref<Target=[u8]> + Send + 'static,
+              F: FnOnce(SyncSender<PipelinePacket<P>>) -> Result<(), Error>
+                 + Send + 'static
+    {
+        let (sender, receiver) = sync_channel(self.pipeline_depth);
+        let producer_thread = spawn(move || producer(sender));
+        // The channel closes when the producer finishes. If decoding
+        // fails, the receiver is dropped and the producer's next send fails.
+        for packet in receiver {
+            self.handle_raw_packet(&packet.data, packet.timestamp)?;
+        }
+        match producer_thread.join() {
+            Ok(result) => result,
+            Err(_) => bail!("Packet producer thread panicked"),
+        }
+    }
+
     pub fn finish(mut self) -> Result<CaptureWriter, Error> {
         self.transaction_end(false, false)?;
         self.capture.shared.complete.store(true, Release);
@@ -650,7 +942,7 @@ impl Decoder {
         };
         if status != Invalid {
             if let Some(state) = &mut self.transaction_state {
-                state.extract_payload(packet);
+                state.extract_payload(packet)?;
             }
         }
         match status {
@@ -668,6 +960,9 @@ impl Decoder {
                 self.transaction_end(success, complete)?;
             },
             Invalid => {
+                // End any transaction this packet interrupted, so that a
+                // transfer it started is not left without an end.
+                self.transaction_end(false, false)?;
                 self.transaction_start(packet_id, packet)?;
                 self.transaction_end(false, false)?;
             },
@@ -752,9 +1047,30 @@ impl Decoder {
         self.capture.shared.device_data.update(|device_data| {
             device_data.set(device_id, Arc::new(DeviceData::default()));
         });
+        self.apply_overrides(address, device_id)?;
         Ok(device_id)
     }
 
+    fn apply_overrides(&self, address: DeviceAddr, device_id: DeviceId)
+        -> Result<(), Error>
+    {
+        let dev_data = self.capture.device_data(device_id)?;
+        for ovr in &self.overrides.endpoints {
+            if ovr.device == address {
+                dev_data.endpoint_details.update(|endpoint_details| {
+                    endpoint_details.set(ovr.endpoint,
+                        (ovr.class.endpoint_type(), ovr.max_packet_size));
+                });
+            }
+        }
+        for known in &self.overrides.descriptors {
+            if known.device == address {
+                dev_data.apply_known_descriptors(known);
+            }
+        }
+        Ok(())
+    }
+
     fn add_endpoint(&mut self,
                     dev_addr: DeviceAddr,
                     number: EndpointNum,
@@ -891,7 +1207,20 @@ impl Decoder {
                 self.transfer_end(transaction)?;
             }
         }
-        self.endpoint_data[endpoint_id].apply_effect(transaction, effect)?;
+        let ep_data = &mut self.endpoint_data[endpoint_id];
+        if transaction.last == PID::STALL {
+            if let Some(transfer) = &mut ep_data.active {
+                transfer.stalled = true;
+            }
+        }
+        ep_data.apply_effect(transaction, effect)?;
+        // Once a device is given an address, any control transfer left in
+        // progress with a previous device at that address was cut short.
+        if status == Done {
+            if let Some(address) = ep_data.address_request() {
+                self.device_reset(address)?;
+            }
+        }
         Ok(())
     }
 
@@ -915,6 +1244,7 @@ impl Decoder {
                 id: ep_transfer_id,
                 first: transaction_type,
                 last: if done { Some(transaction_type) } else { None },
+                stalled: false,
             }
         );
         ep_data.payload.clear();
@@ -947,10 +1277,20 @@ impl Decoder {
         -> Result<(), Error>
     {
         let endpoint_id = transaction.endpoint_id()?;
+        self.transfer_close(endpoint_id, false)
+    }
+
+    /// End the transfer in progress on an endpoint, if there is one.
+    fn transfer_close(&mut self, endpoint_id: EndpointId, aborted: bool)
+        -> Result<(), Error>
+    {
         let ep_data = &mut self.endpoint_data[endpoint_id];
         ep_data.payload.clear();
         if let Some(transfer) = ep_data.active.take() {
             let ep_transfer_id = transfer.id;
+            if aborted {
+                ep_data.writer.aborted_transfers.push(ep_transfer_id)?;
+            }
             ep_data.ended = Some(ep_transfer_id);
             let transfer_end_id =
                 self.add_transfer_entry(endpoint_id, ep_transfer_id, false)?;
@@ -966,8 +1306,14 @@ impl Decoder {
                     transaction: &mut TransactionState)
         -> Result<EndpointTransferId, Error>
     {
+        let control = self.is_control(endpoint_id)?;
         let ep_data = &mut self.endpoint_data[endpoint_id];
         if let Some(transfer) = ep_data.active.take() {
+            // A control transfer which is replaced by a new one before its
+            // status stage was aborted, unless it was ended by a STALL.
+            if control && !transfer.stalled {
+                ep_data.writer.aborted_transfers.push(transfer.id)?;
+            }
             ep_data.ended = Some(transfer.id);
             self.add_transfer_entry(endpoint_id, transfer.id, false)?;
         }
@@ -990,6 +1336,49 @@ impl Decoder {
         Ok(ep_transfer_id)
     }
 
+    /// Whether an endpoint is used for control transfers.
+    fn is_control(&self, endpoint_id: EndpointId) -> Result<bool, Error> {
+        use EndpointType::Normal;
+        use usb::EndpointType::Control;
+        let ep_data = &self.endpoint_data[endpoint_id];
+        let dev_data = self.capture.device_data(ep_data.device_id)?;
+        let (ep_type, _) = dev_data.endpoint_details(ep_data.address);
+        Ok(matches!(ep_type, Normal(Control)))
+    }
+
+    /// Abort control transfers left in progress on a device which has
+    /// been reset, as shown by its address being assigned again.
+    ///
+    /// The device given the address may be a different one, reconnected,
+    /// or the same one enumerated again, so later traffic to the address
+    /// is attributed to a new device, with its own endpoints.
+    fn device_reset(&mut self, address: DeviceAddr) -> Result<(), Error> {
+        let device_id = match self.device_index.get(address) {
+            Some(device_id) if address.0 != 0 => *device_id,
+            _ => return Ok(()),
+        };
+        for i in 0..self.capture.endpoints.len() {
+            let endpoint_id = EndpointId::from(i);
+            let ep_data = &self.endpoint_data[endpoint_id];
+            if ep_data.device_id == device_id &&
+                ep_data.active.is_some() &&
+                self.is_control(endpoint_id)?
+            {
+                self.transfer_close(endpoint_id, true)?;
+            }
+        }
+        self.device_index.remove(address);
+        let keys: Vec<EndpointKey> = self.endpoint_index
+            .entries()
+            .map(|(key, _)| key)
+            .filter(|key| key.dev_addr == address)
+            .collect();
+        for key in keys {
+            self.endpoint_index.remove(key);
+        }
+        Ok(())
+    }
+
     fn add_transfer_entry(&mut self,
                           endpoint_id: EndpointId,
                           ep_transfer_id: EndpointTransferId,
@@ -1053,3 +1442,376 @@ impl Decoder {
         Ok(item_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use pcap_file::pcap::PcapReader;
+    use crate::capture::{
+        create_capture,
+        CaptureReader,
+        ItemSource,
+        TrafficItem,
+    };
+    use crate::overrides::{EndpointOverride, KnownDescriptors};
+    use crate::pcap::timestamp;
+    use crate::usb::{crc5, crc16};
+
+    fn packets(name: &str) -> Vec<PipelinePacket> {
+        let file = File::open(format!("../tests/{name}/capture.pcap")).unwrap();
+        let mut pcap_reader = PcapReader::new(file).unwrap();
+        let header = pcap_reader.header();
+        let mut packets = Vec::new();
+        while let Some(result) = pcap_reader.next_raw_packet() {
+            let packet = result.unwrap();
+            packets.push(PipelinePacket {
+                timestamp: timestamp(&header, &packet),
+                data: packet.data.to_vec(),
+            });
+        }
+        packets
+    }
+
+    fn summary(reader: &mut CaptureReader) -> Vec<Vec<u8>> {
+        (0..reader.packet_index.len())
+            .map(|i| reader.packet(PacketId::from(i)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_from() {
+        let (writer, mut sequential) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for packet in packets("hackrf-dfu-enum") {
+            decoder.handle_raw_packet(&packet.data, packet.timestamp).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let (writer, mut pipelined) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        decoder.set_pipeline_depth(1);
+        decoder.decode_from(|sender| {
+            for packet in packets("hackrf-dfu-enum") {
+                sender.send(packet)?;
+            }
+            Ok(())
+        }).unwrap();
+        decoder.finish().unwrap();
+
+        assert_eq!(summary(&mut sequential), summary(&mut pipelined));
+        assert_eq!(sequential.transaction_index.len(),
+                   pipelined.transaction_index.len());
+        assert_eq!(sequential.item_index.len(), pipelined.item_index.len());
+        assert_eq!(sequential.endpoints.len(), pipelined.endpoints.len());
+    }
+
+    #[test]
+    fn test_decode_packets_only() {
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        decoder.set_decode_depth(DecodeDepth::Packets).unwrap();
+        let packets = packets("mouse");
+        for packet in &packets {
+            decoder.handle_raw_packet(&packet.data, packet.timestamp).unwrap();
+        }
+        assert!(decoder.set_decode_depth(DecodeDepth::Transfers).is_err());
+        decoder.finish().unwrap();
+
+        // Packets are stored as they are, in blocks with no other items.
+        let count = packets.len() as u64;
+        assert!(count > PACKET_BLOCK_SIZE);
+        assert_eq!(capture.packet_index.len(), count);
+        assert_eq!(capture.transaction_index.len(), count);
+        let (_, block_count) = ItemSource::<TrafficItem>::item_children(
+            &mut capture, None).unwrap();
+        assert_eq!(block_count, (count + PACKET_BLOCK_SIZE - 1) /
+                   PACKET_BLOCK_SIZE);
+        let block = ItemSource::<TrafficItem>::item(
+            &mut capture, None, 1).unwrap();
+        assert_eq!(capture.summary(&block).unwrap(), "Packets 1001 to 2000");
+        assert!(!capture.item_error(&block).unwrap());
+        let (_, children) = capture.item_children(Some(&block)).unwrap();
+        assert_eq!(children, PACKET_BLOCK_SIZE);
+
+        // Each packet is directly within its block.
+        let packet = capture.child_item(&block, 5).unwrap();
+        assert!(matches!(packet,
+            TrafficItem::Packet(_, _, id) if id.value == 1005));
+        assert_eq!(capture.packet_item(PacketId::from(1005)).unwrap(),
+                   packet);
+        assert_eq!(capture.item_children(Some(&packet)).unwrap().1, 0);
+        assert_eq!(capture.item_path(&packet).unwrap(), Some(vec![1, 5]));
+        assert_eq!(capture.connectors(&packet).unwrap(), "  ├───");
+        assert_eq!(packets[1005].data, capture.packet(PacketId::from(1005))
+                   .unwrap());
+    }
+
+    fn token(pid: PID, value: u16) -> Vec<u8> {
+        let crc = crc5(value, 11);
+        vec![pid.into(), value as u8, (value >> 8) as u8 | (crc << 3)]
+    }
+
+    fn data(pid: PID, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![pid.into()];
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&crc16(payload).to_le_bytes());
+        packet
+    }
+
+    fn decode_packets(packets: &[Vec<u8>]) -> CaptureReader {
+        decode_with_overrides(packets, &Overrides::default())
+    }
+
+    fn decode_with_overrides(packets: &[Vec<u8>], overrides: &Overrides)
+        -> CaptureReader
+    {
+        let (writer, reader) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        decoder.set_overrides(overrides).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        reader
+    }
+
+    fn item_summaries(reader: &mut CaptureReader) -> Vec<(String, bool)> {
+        (0..reader.item_index.len())
+            .map(|i| {
+                let item = reader.item(None, i).unwrap();
+                (reader.summary(&item).unwrap(),
+                 reader.item_error(&item).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aborted_control_transfers() {
+        use PID::*;
+        let get_descriptor = [0x80, 6, 0, 1, 0, 0, 18, 0];
+        let set_address = [0x00, 5, 1, 0, 0, 0, 0, 0];
+        let descriptor = [18, 1, 0, 2, 0, 0, 0, 8];
+        let ack = vec![ACK.into()];
+        let setup = |address, fields: &[u8]| vec![
+            token(SETUP, address), data(DATA0, fields), ack.clone()];
+        let partial_read = || vec![
+            token(IN, 1), data(DATA1, &descriptor), ack.clone()];
+
+        // A transfer interrupted by a new SETUP is aborted, and the new
+        // transfer proceeds normally.
+        let mut packets = setup(1, &get_descriptor);
+        packets.extend(partial_read());
+        packets.extend(setup(1, &set_address));
+        packets.extend([token(IN, 1), data(DATA1, &[]), ack.clone()]);
+        let mut reader = decode_packets(&packets);
+        let items = item_summaries(&mut reader);
+        assert_eq!(items.len(), 2);
+        assert!(items[0].0.ends_with(", aborted"));
+        assert!(items[0].1);
+        assert!(!items[1].0.contains("aborted"));
+        assert!(!items[1].1);
+
+        // A transfer left in progress when the device is reset is aborted
+        // when its address is assigned again.
+        let mut packets = setup(1, &get_descriptor);
+        packets.extend(partial_read());
+        packets.extend(setup(0, &set_address));
+        packets.extend([token(IN, 0), data(DATA1, &[]), ack.clone()]);
+        let mut reader = decode_packets(&packets);
+        let items = item_summaries(&mut reader);
+        assert!(items[0].0.ends_with(", aborted"));
+        assert!(items.iter().any(|(summary, _)|
+            summary == "End of aborted control transfer on device 1"));
+
+        // A transfer ended by a STALL is not aborted by the next SETUP.
+        let mut packets = setup(1, &get_descriptor);
+        packets.extend([token(IN, 1), vec![STALL.into()]]);
+        packets.extend(setup(1, &get_descriptor));
+        let mut reader = decode_packets(&packets);
+        let items = item_summaries(&mut reader);
+        assert!(items[0].0.ends_with(", stalled"));
+        let entry = reader.transfer_index.get(TransferId::from(0)).unwrap();
+        assert!(!reader.transfer_aborted(&entry).unwrap());
+    }
+
+    #[test]
+    fn test_address_reuse() {
+        use crate::backend::emulator::Scenario;
+        use crate::capture::DeviceItem;
+        // A device at address 5 is replaced by another, given the same
+        // address, and then by the first again.
+        let scenario = Scenario::parse("reuse", "
+            speed full
+            device 5 vid=0x1d50 pid=0x6018
+            endpoint 5 1 in bulk 64
+            enumerate 5
+            in 5 1 01:02:03
+            device 5 vid=0x1234 pid=0x5678
+            endpoint 5 2 in bulk 64
+            enumerate 5
+            in 5 2 04:05
+            device 5 vid=0x1d50 pid=0x6018
+            endpoint 5 1 in bulk 64
+            enumerate 5
+            in 5 1 06").unwrap();
+        let (writer, mut reader) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let (_, count) =
+            ItemSource::<DeviceItem>::item_children(&mut reader, None)
+                .unwrap();
+        let summaries: Vec<String> = (0..count)
+            .map(|i| {
+                let item: DeviceItem = reader.item(None, i).unwrap();
+                reader.summary(&item).unwrap()
+            })
+            .collect();
+        assert_eq!(summaries, [
+            "Device 5 #1: 1D50:6018",
+            "Device 5 #2: 1234:5678",
+            "Device 5 #3: 1D50:6018",
+        ]);
+
+        // Each device's traffic is attributed to it, with the endpoint
+        // types given by its own descriptors.
+        let items = item_summaries(&mut reader);
+        let bulk: Vec<&str> = items
+            .iter()
+            .map(|(summary, _)| summary.as_str())
+            .filter(|summary| summary.starts_with("Bulk"))
+            .collect();
+        assert_eq!(bulk.len(), 3);
+        let mut devices = Vec::new();
+        for i in 0..reader.endpoints.len() {
+            let endpoint = reader.endpoints.get(EndpointId::from(i)).unwrap();
+            if endpoint.device_address() == DeviceAddr(5) {
+                devices.push((endpoint.device_id().value,
+                              endpoint.number().0));
+            }
+        }
+        assert_eq!(devices, [(1, 0), (1, 1), (2, 0), (2, 2), (3, 0), (3, 1)]);
+    }
+
+    #[test]
+    fn test_endpoint_overrides() {
+        use PID::*;
+        let ack = vec![ACK.into()];
+        let payload = [0x55; 64];
+        let read = |pid, length| vec![
+            token(IN, 5 | 3 << 7), data(pid, &payload[..length]), ack.clone()];
+        let mut packets = read(DATA0, 64);
+        packets.extend(read(DATA1, 10));
+        packets.extend(read(DATA0, 64));
+        packets.extend(read(DATA1, 0));
+
+        // With no descriptors, the endpoint's transfers cannot be found.
+        let mut reader = decode_packets(&packets);
+        let items = item_summaries(&mut reader);
+        assert_eq!(items.len(), 1);
+        assert!(items[0].0.starts_with("Unidentified transfer of 138 bytes"));
+
+        // Once its class is given, short packets end its transfers.
+        let overrides = Overrides {
+            endpoints: vec![
+                EndpointOverride::parse("5.0x83=cdc-data:64").unwrap()
+            ],
+            ..Overrides::default()
+        };
+        let mut reader = decode_with_overrides(&packets, &overrides);
+        let items = item_summaries(&mut reader);
+        assert_eq!(items.len(), 2);
+        assert!(items[0].0.starts_with("Bulk transfer of 74 bytes"));
+        assert!(items[1].0.starts_with("Bulk transfer of 64 bytes"));
+
+        // Descriptors obtained elsewhere are used in the same way, and
+        // take precedence over the class given for the endpoint.
+        let known = KnownDescriptors {
+            device: DeviceAddr(5),
+            device_descriptor: vec![
+                18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x50, 0x1D, 0x5B, 0x61,
+                0x00, 0x01, 0, 0, 0, 1],
+            configurations: vec![vec![
+                9, 2, 25, 0, 1, 1, 0, 0x80, 50,
+                9, 4, 0, 0, 1, 0x0A, 0, 0, 0,
+                7, 5, 0x83, 0x03, 64, 0, 1]],
+            config_number: Some(1),
+            strings: Vec::new(),
+        };
+        let overrides = Overrides {
+            descriptors: vec![known],
+            ..overrides
+        };
+        let mut reader = decode_with_overrides(&packets, &overrides);
+        let items = item_summaries(&mut reader);
+        assert_eq!(items.len(), 2);
+        assert!(items[0].0.starts_with("Interrupt transfer of 74 bytes"));
+        let device_data = reader.device_data(&DeviceId::from(1)).unwrap();
+        assert_eq!(device_data.description(), "1D50:615B");
+    }
+
+    #[test]
+    fn test_snap_length() {
+        use PID::*;
+        let ack = vec![ACK.into()];
+        let payload = [0x55; 64];
+        let read = |pid, length| vec![
+            token(IN, 5 | 3 << 7), data(pid, &payload[..length]), ack.clone()];
+        let mut packets = read(DATA0, 64);
+        packets.extend(read(DATA1, 64));
+        packets.extend(read(DATA0, 10));
+        let overrides = Overrides {
+            endpoints: vec![
+                EndpointOverride::parse("5.0x83=cdc-data:64").unwrap()
+            ],
+            ..Overrides::default()
+        };
+        let decode = |snap_length| {
+            let (writer, reader) = create_capture().unwrap();
+            let mut decoder = Decoder::new(writer).unwrap();
+            decoder.set_overrides(&overrides).unwrap();
+            decoder.set_snap_length(snap_length);
+            for (i, packet) in packets.iter().enumerate() {
+                decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+            }
+            decoder.finish().unwrap();
+            reader
+        };
+        // Only the length of each transfer is compared, as the data shown
+        // with it is truncated too.
+        let transfers = |reader: &mut CaptureReader| -> Vec<String> {
+            item_summaries(reader)
+                .into_iter()
+                .map(|(summary, _)| summary[..summary.find(':').unwrap()]
+                    .to_string())
+                .collect()
+        };
+        let expected = transfers(&mut decode(None));
+        assert_eq!(expected, ["Bulk transfer of 138 bytes on endpoint 5.3 IN"]);
+
+        // Packets shorter than the endpoint's maximum are stored in part,
+        // but are grouped and counted by their original lengths.
+        let mut truncated = decode(Some(8));
+        assert_eq!(transfers(&mut truncated), expected);
+        let packet_id = PacketId::from(1);
+        assert_eq!(truncated.packet(packet_id).unwrap().len(), 11);
+        assert_eq!(truncated.packet_original_length(packet_id).unwrap(),
+                   Some(67));
+
+        // The same holds when the stored packets are decoded again.
+        let (writer, mut reanalysed) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        decoder.set_overrides(&overrides).unwrap();
+        for i in 0..truncated.packet_index.len() {
+            decoder.handle_stored_packet(&mut truncated, PacketId::from(i))
+                .unwrap();
+        }
+        decoder.finish().unwrap();
+        assert_eq!(transfers(&mut reanalysed), expected);
+        assert_eq!(reanalysed.packet(packet_id).unwrap().len(), 11);
+    }
+}
@@ -0,0 +1,554 @@
+//! Errors found in the traffic of a capture, kept up to date as a capture
+//! grows, so that failures can be found without searching through all the
+//! healthy traffic around them.
+
+use std::sync::atomic::Ordering::Acquire;
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    EndpointTransactionId,
+    EndpointType,
+    PacketId,
+    TransactionId,
+    CONTROL_EP_NUM,
+    FRAMING_EP_ID,
+    FRAMING_EP_NUM,
+    INVALID_EP_ID,
+    INVALID_EP_NUM,
+    UNGROUPED_EP_NUM,
+};
+use crate::speeds::{BusSpeed, BusSpeeds};
+use crate::usb::{
+    self,
+    crc5,
+    crc16,
+    EndpointDescriptor,
+    PacketFields,
+    StartComplete,
+    PID,
+};
+
+/// The most errors listed. Any more are only counted.
+pub const MAX_ERRORS: usize = 10_000;
+
+/// The kinds of error found.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A packet whose CRC does not match its contents.
+    Crc,
+    /// A packet with an invalid PID or length, or an ERR handshake.
+    Protocol,
+    /// A data packet longer than the endpoint's maximum packet size.
+    Babble,
+    /// A STALL handshake, showing the endpoint is halted.
+    Halted,
+    /// An interrupt endpoint polled more often than its descriptor allows.
+    FastPolling,
+    /// Traffic on an endpoint not declared in the device's configuration.
+    Undeclared,
+}
+
+impl ErrorKind {
+    /// Name of this kind of error, as shown in the errors pane.
+    pub fn label(&self) -> &'static str {
+        use ErrorKind::*;
+        match self {
+            Crc => "CRC error",
+            Protocol => "Protocol error",
+            Babble => "Babble",
+            Halted => "Halted",
+            FastPolling => "Fast polling",
+            Undeclared => "Undeclared endpoint",
+        }
+    }
+}
+
+/// An error found in a packet.
+#[derive(Clone, Debug)]
+pub struct CaptureError {
+    pub kind: ErrorKind,
+    pub endpoint_id: EndpointId,
+    pub transaction_id: TransactionId,
+    pub packet_id: PacketId,
+    pub description: String,
+}
+
+/// The errors found in a capture, in the order of their packets.
+#[derive(Clone, Debug, Default)]
+pub struct Errors {
+    pub errors: Vec<CaptureError>,
+    /// Errors found beyond the first `MAX_ERRORS`.
+    pub unlisted: u64,
+    /// What has been checked so far on each endpoint.
+    endpoints: Vec<EndpointCheck>,
+    /// The speeds of the bus, by which polling intervals are measured.
+    speeds: BusSpeeds,
+}
+
+/// What has been checked so far of the traffic on an endpoint.
+#[derive(Clone, Debug, Default)]
+struct EndpointCheck {
+    /// Number of transactions checked.
+    checked: u64,
+    /// The last poll, if the endpoint is an interrupt endpoint.
+    last_poll: Option<TransactionId>,
+    /// Whether the endpoint's traffic has been found not to match its
+    /// descriptor. This is reported once per endpoint, as every poll of a
+    /// misconfigured endpoint would otherwise be listed.
+    mismatch_found: bool,
+}
+
+impl Errors {
+    pub fn new() -> Self {
+        Errors::default()
+    }
+
+    /// Total number of errors found.
+    pub fn count(&self) -> u64 {
+        self.errors.len() as u64 + self.unlisted
+    }
+
+    /// Check the traffic added to a capture since the last update.
+    ///
+    /// Returns the positions at which errors were inserted into the list,
+    /// in the order they were inserted.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<Vec<usize>, Error>
+    {
+        // The last transaction on an endpoint may still be growing, unless
+        // the capture is complete.
+        let complete = capture.shared.complete.load(Acquire);
+        self.speeds.update(capture)?;
+        for index in self.endpoints.len()..capture.endpoints.len() as usize {
+            // An endpoint is listed before its traffic can be read.
+            if capture.endpoint_traffic(EndpointId::from(index as u64))
+                .is_err()
+            {
+                break;
+            }
+            self.endpoints.push(EndpointCheck::default());
+        }
+        let mut found = Vec::new();
+        for (index, state) in self.endpoints.iter_mut().enumerate() {
+            let endpoint_id = EndpointId::from(index as u64);
+            let mut transaction_count =
+                capture.endpoint_traffic(endpoint_id)?.transaction_ids.len();
+            if !complete {
+                transaction_count = transaction_count.saturating_sub(1);
+            }
+            for ep_transaction_index in state.checked..transaction_count {
+                let transaction_id = capture
+                    .endpoint_traffic(endpoint_id)?
+                    .transaction_ids
+                    .get(EndpointTransactionId::from(ep_transaction_index))?;
+                check_transaction(
+                    capture, endpoint_id, transaction_id, &mut found)?;
+                check_descriptor(capture, endpoint_id, transaction_id,
+                                 &self.speeds, state, &mut found)?;
+            }
+            state.checked = state.checked.max(transaction_count);
+        }
+        let mut positions = Vec::new();
+        for error in found {
+            if self.errors.len() >= MAX_ERRORS {
+                self.unlisted += 1;
+                continue;
+            }
+            let position = self.errors
+                .partition_point(|other| other.packet_id <= error.packet_id);
+            self.errors.insert(position, error);
+            positions.push(position);
+        }
+        Ok(positions)
+    }
+}
+
+/// Check the packets of a transaction, adding any errors found.
+fn check_transaction(capture: &mut CaptureReader,
+                     endpoint_id: EndpointId,
+                     transaction_id: TransactionId,
+                     found: &mut Vec<CaptureError>)
+    -> Result<(), Error>
+{
+    let range = capture.transaction_index.target_range(
+        transaction_id, capture.packet_index.len())?;
+    let mut max_packet_size = None;
+    for value in range.start.value..range.end.value {
+        let packet_id = PacketId::from(value);
+        let packet = capture.packet(packet_id)?;
+        let original_length = capture.packet_original_length(packet_id)?;
+        let mut error = stored_packet_error(&packet, original_length);
+        if error.is_none() && matches!(PID::from(packet[0]),
+            PID::DATA0 | PID::DATA1 | PID::DATA2 | PID::MDATA)
+        {
+            if max_packet_size.is_none() {
+                max_packet_size = Some(endpoint_max(capture, endpoint_id)?);
+            }
+            let length = original_length
+                .map_or(packet.len(), |length| length as usize) - 3;
+            if let Some(Some(max)) = max_packet_size {
+                if length > max {
+                    error = Some((ErrorKind::Babble, format!(
+                        "{length} bytes of data, but the maximum packet \
+                         size is {max}")));
+                }
+            }
+        }
+        if let Some((kind, description)) = error {
+            found.push(CaptureError {
+                kind,
+                endpoint_id,
+                transaction_id,
+                packet_id,
+                description,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The maximum packet size of an endpoint, if known.
+fn endpoint_max(capture: &mut CaptureReader, endpoint_id: EndpointId)
+    -> Result<Option<usize>, Error>
+{
+    if endpoint_id == FRAMING_EP_ID || endpoint_id == INVALID_EP_ID {
+        return Ok(None);
+    }
+    let endpoint = capture.endpoints.get(endpoint_id)?;
+    let device_data = capture.device_data(&endpoint.device_id())?;
+    Ok(match device_data.endpoint_details(endpoint.address()) {
+        (EndpointType::Normal(_), max) => max,
+        _ => None,
+    })
+}
+
+/// Check a transaction against the descriptor of its endpoint, adding an
+/// error if the endpoint was not declared, or was polled too often.
+fn check_descriptor(capture: &mut CaptureReader,
+                    endpoint_id: EndpointId,
+                    transaction_id: TransactionId,
+                    speeds: &BusSpeeds,
+                    state: &mut EndpointCheck,
+                    found: &mut Vec<CaptureError>)
+    -> Result<(), Error>
+{
+    if state.mismatch_found {
+        return Ok(());
+    }
+    let descriptor = match endpoint_descriptor(capture, endpoint_id)? {
+        // Without the device's configuration, nothing can be checked.
+        Declaration::Unknown => return Ok(()),
+        Declaration::Undeclared => {
+            let packet_id = capture.transaction_index.get(transaction_id)?;
+            state.mismatch_found = true;
+            found.push(CaptureError {
+                kind: ErrorKind::Undeclared,
+                endpoint_id,
+                transaction_id,
+                packet_id,
+                description: "Traffic on an endpoint which is not declared \
+                              in the device's configuration".into(),
+            });
+            return Ok(());
+        },
+        Declaration::Declared(descriptor) => descriptor,
+    };
+    if descriptor.attributes.endpoint_type() != usb::EndpointType::Interrupt {
+        return Ok(());
+    }
+    // Each split transaction is polled by its start-split, and completed
+    // by complete-splits which follow it more closely.
+    let packet_id = capture.transaction_index.get(transaction_id)?;
+    let split = match PacketFields::from_packet(&capture.packet(packet_id)?) {
+        PacketFields::Split(split) if split.sc() == StartComplete::Complete =>
+            return Ok(()),
+        PacketFields::Split(_) => true,
+        _ => false,
+    };
+    if let Some(last_poll) = state.last_poll.replace(transaction_id) {
+        let frames = match frames_between(capture, last_poll, transaction_id)? {
+            Some(frames) => frames,
+            // Without SOFs, there is no reliable measure of the interval.
+            None => return Ok(()),
+        };
+        let interval = descriptor.interval;
+        let speed = speeds.speed_at(packet_id);
+        let min_frames = min_poll_frames(interval, speed, split);
+        // At high speed, there is a SOF in each microframe.
+        let unit = if speed == Some(BusSpeed::High) {
+            "microframes"
+        } else {
+            "frames"
+        };
+        if frames < min_frames {
+            state.mismatch_found = true;
+            found.push(CaptureError {
+                kind: ErrorKind::FastPolling,
+                endpoint_id,
+                transaction_id,
+                packet_id,
+                description: format!(
+                    "Polled {frames} {unit} after the last poll, but \
+                     bInterval {interval} allows polling every {min_frames} \
+                     {unit} at most"),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Number of SOF packets between two transactions, or None if the capture
+/// has no SOFs.
+fn frames_between(capture: &mut CaptureReader,
+                  first: TransactionId,
+                  second: TransactionId)
+    -> Result<Option<u64>, Error>
+{
+    let packet_count = capture.packet_index.len();
+    let ep_traf = match capture.endpoint_traffic(FRAMING_EP_ID) {
+        Ok(ep_traf) if ep_traf.transaction_ids.len() > 0 => ep_traf,
+        _ => return Ok(None),
+    };
+    let start = ep_traf.transaction_ids.bisect_left(&first)?;
+    let end = ep_traf.transaction_ids.bisect_left(&second)?;
+    let sof_groups = ep_traf.transaction_ids.get_range(&(start..end))?;
+    let mut frames = 0;
+    for transaction_id in sof_groups {
+        // Consecutive SOFs are grouped into one transaction.
+        let range = capture.transaction_index.target_range(
+            transaction_id, packet_count)?;
+        frames += range.end.value - range.start.value;
+    }
+    Ok(Some(frames))
+}
+
+/// How an endpoint is declared in its device's selected configuration.
+enum Declaration {
+    /// The configuration is not known, so neither is the endpoint.
+    Unknown,
+    Undeclared,
+    Declared(EndpointDescriptor),
+}
+
+fn endpoint_descriptor(capture: &mut CaptureReader, endpoint_id: EndpointId)
+    -> Result<Declaration, Error>
+{
+    let endpoint = capture.endpoints.get(endpoint_id)?;
+    // The default control endpoint has no descriptor, and the others are
+    // not real endpoints, but groups of traffic with no endpoint.
+    if matches!(endpoint.number(),
+        CONTROL_EP_NUM | INVALID_EP_NUM | FRAMING_EP_NUM | UNGROUPED_EP_NUM)
+    {
+        return Ok(Declaration::Unknown);
+    }
+    let device_data = capture.device_data(&endpoint.device_id())?;
+    let config_number = match device_data.config_number.load().as_deref() {
+        Some(number) => *number,
+        None => return Ok(Declaration::Unknown),
+    };
+    let configurations = device_data.configurations.load();
+    let config = match configurations.get(config_number) {
+        Some(config) => config,
+        None => return Ok(Declaration::Unknown),
+    };
+    for interface in &config.interfaces {
+        for descriptor in &interface.endpoint_descriptors {
+            if descriptor.endpoint_address == endpoint.address() {
+                return Ok(Declaration::Declared(*descriptor));
+            }
+        }
+    }
+    Ok(Declaration::Undeclared)
+}
+
+/// The fewest SOFs between polls of an interrupt endpoint which its
+/// bInterval allows, at the given speed of the bus.
+///
+/// At full and low speed, bInterval is a number of frames, but hosts may
+/// poll more often, and commonly round it down to a power of two. At high
+/// speed, it is an exponent of microframes, except for full and low speed
+/// devices behind a hub, which are polled by start-splits. Where the speed
+/// is not known, the smaller of the two is taken.
+fn min_poll_frames(interval: u8, speed: Option<BusSpeed>, split: bool)
+    -> u64
+{
+    let frames = 1 << (7 - interval.max(1).leading_zeros());
+    let microframes = match interval {
+        1..=16 => 1 << (interval - 1),
+        _ => frames,
+    };
+    match speed {
+        Some(BusSpeed::High) if split => frames * 8,
+        Some(BusSpeed::High) => microframes,
+        Some(_) => frames,
+        None => frames.min(microframes),
+    }
+}
+
+/// Check a packet as stored in a capture, which may have been truncated
+/// to the given original length.
+///
+/// The CRC of a truncated packet cannot be checked.
+pub fn stored_packet_error(packet: &[u8], original_length: Option<u64>)
+    -> Option<(ErrorKind, String)>
+{
+    match packet_error(packet) {
+        Some((ErrorKind::Crc, _)) if original_length.is_some() => None,
+        error => error,
+    }
+}
+
+/// Check a single packet for errors which can be found from its own bytes.
+pub fn packet_error(packet: &[u8]) -> Option<(ErrorKind, String)> {
+    use PID::*;
+    let first_byte = match packet.first() {
+        Some(byte) => *byte,
+        None => return Some((ErrorKind::Protocol, "Empty packet".into())),
+    };
+    let pid = PID::from(first_byte);
+    let valid_length = match pid {
+        Malformed => return Some((ErrorKind::Protocol,
+            format!("Invalid PID byte 0x{first_byte:02X}"))),
+        SOF | SETUP | IN | OUT | PING => packet.len() == 3,
+        SPLIT => packet.len() == 4,
+        DATA0 | DATA1 | DATA2 | MDATA => packet.len() >= 3,
+        ACK | NAK | STALL | NYET | ERR => packet.len() == 1,
+        RSVD => true,
+    };
+    if !valid_length {
+        return Some((ErrorKind::Protocol,
+            format!("{pid} packet with invalid length of {} bytes",
+                    packet.len())));
+    }
+    match pid {
+        SOF | SETUP | IN | OUT | PING => {
+            let value = u16::from_le_bytes([packet[1], packet[2]]);
+            let expected = crc5(value & 0x7FF, 11);
+            let crc = (value >> 11) as u8;
+            (crc != expected).then(|| (ErrorKind::Crc, format!(
+                "{pid} packet has CRC5 0x{crc:02X}, expected \
+                 0x{expected:02X}")))
+        },
+        DATA0 | DATA1 | DATA2 | MDATA => {
+            let end = packet.len();
+            let expected = crc16(&packet[1..end - 2]);
+            let crc = u16::from_le_bytes([packet[end - 2], packet[end - 1]]);
+            (crc != expected).then(|| (ErrorKind::Crc, format!(
+                "{pid} packet has CRC16 0x{crc:04X}, expected \
+                 0x{expected:04X}")))
+        },
+        STALL => Some((ErrorKind::Halted,
+            "Endpoint responded with STALL".into())),
+        ERR => Some((ErrorKind::Protocol,
+            "Hub reported an error in a split transaction".into())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+
+    #[test]
+    fn test_packet_error() {
+        assert!(packet_error(&[0xa5, 0xde, 0x1e]).is_none());
+        assert_eq!(packet_error(&[0xa5, 0xde, 0x16]).unwrap().0,
+                   ErrorKind::Crc);
+        assert_eq!(packet_error(&[0xa5, 0xde]).unwrap().0,
+                   ErrorKind::Protocol);
+        assert_eq!(packet_error(&[0x00]).unwrap().0, ErrorKind::Protocol);
+        assert_eq!(packet_error(&[]).unwrap().0, ErrorKind::Protocol);
+        assert_eq!(packet_error(&[0x1e]).unwrap().0, ErrorKind::Halted);
+        assert!(packet_error(&[0xd2]).is_none());
+
+        let payload = [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x40, 0x00];
+        let mut packet = vec![0xc3];
+        packet.extend_from_slice(&payload);
+        packet.extend_from_slice(&crc16(&payload).to_le_bytes());
+        assert!(packet_error(&packet).is_none());
+        packet[1] ^= 0x01;
+        assert_eq!(packet_error(&packet).unwrap().0, ErrorKind::Crc);
+    }
+
+    #[test]
+    fn test_errors() {
+        let path = PathBuf::from("../tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut errors = Errors::new();
+        let positions = errors.update(&mut capture).unwrap();
+        assert_eq!(positions.len(), errors.errors.len());
+        assert!(errors.update(&mut capture).unwrap().is_empty());
+        assert_eq!(errors.unlisted, 0);
+
+        // Errors are listed in the order of their packets, and each is
+        // shown as an error in the traffic view.
+        for pair in errors.errors.windows(2) {
+            assert!(pair[0].packet_id <= pair[1].packet_id);
+        }
+        for error in &errors.errors {
+            let item = capture.packet_item(error.packet_id).unwrap();
+            if error.kind != ErrorKind::Babble {
+                assert!(capture.item_error(&item).unwrap());
+            }
+            assert_eq!(capture.item_endpoint_id(&item).unwrap(),
+                       error.endpoint_id);
+        }
+    }
+
+    #[test]
+    fn test_descriptor_checks() {
+        let scenario = Scenario::parse("polling", "
+            speed full
+            device 1
+            endpoint 1 1 in interrupt 8
+            enumerate 1
+            # Polled once per frame, as bInterval 1 allows.
+            sof 1
+            nak 1 1 in
+            sof 1
+            nak 1 1 in
+            # Polled twice in one frame.
+            sof 1
+            nak 1 1 in
+            nak 1 1 in
+            nak 1 1 in
+            # Polled on an endpoint which the device does not declare.
+            sof 1
+            nak 1 2 in
+            nak 1 2 in").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        let mut errors = Errors::new();
+        errors.update(&mut capture).unwrap();
+        // Each mismatch is reported once per endpoint.
+        let kinds: Vec<ErrorKind> = errors.errors
+            .iter()
+            .map(|error| error.kind)
+            .collect();
+        assert_eq!(kinds, [ErrorKind::FastPolling, ErrorKind::Undeclared]);
+        assert_eq!(errors.errors[0].description,
+                   "Polled 0 frames after the last poll, but bInterval 1 \
+                    allows polling every 1 frames at most");
+
+        assert_eq!(min_poll_frames(1, None, false), 1);
+        assert_eq!(min_poll_frames(4, None, false), 4);
+        assert_eq!(min_poll_frames(10, None, false), 8);
+        assert_eq!(min_poll_frames(255, None, false), 128);
+        assert_eq!(min_poll_frames(0, None, false), 1);
+        assert_eq!(min_poll_frames(10, Some(BusSpeed::Full), false), 8);
+        assert_eq!(min_poll_frames(4, Some(BusSpeed::High), false), 8);
+        assert_eq!(min_poll_frames(4, Some(BusSpeed::High), true), 32);
+    }
+}
@@ -0,0 +1,608 @@
+//! Exports of decoded capture contents to other file formats.
+
+use std::io::Write;
+use std::ops::Range;
+
+use anyhow::{Context, Error};
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::capture::{
+    CaptureReader,
+    DeviceItem,
+    EndpointId,
+    ItemSource,
+    PacketId,
+    Timestamp,
+    TrafficItem,
+    TrafficItemId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+use crate::usb::{
+    DeviceAddr,
+    Direction,
+    EndpointAddr,
+    EndpointNum,
+    PacketFields,
+    StartComplete,
+    PID,
+};
+
+/// Write a line of JSON describing each device in the capture.
+pub fn write_json_devices(capture: &mut CaptureReader,
+                          writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    for device in json_devices(capture)? {
+        writeln!(writer, "{device}")?;
+    }
+    Ok(())
+}
+
+/// Describe each device in the capture as JSON, with its descriptors.
+pub fn json_devices(capture: &mut CaptureReader)
+    -> Result<Vec<Value>, Error>
+{
+    let (_, count) =
+        ItemSource::<DeviceItem>::item_children(capture, None)?;
+    let mut devices = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let item: DeviceItem = capture.item(None, index)?;
+        let device_id = item.device_id();
+        let device = capture.devices.get(device_id)?;
+        devices.push(json!({
+            "type": "device",
+            "id": device_id.value,
+            "address": device.address.0,
+            "summary": capture.summary(&item)?,
+            "children": device_children(capture, &item)?,
+        }));
+    }
+    Ok(devices)
+}
+
+/// Write a line of JSON describing a top-level traffic item.
+///
+/// Items marking the end of a transfer are skipped, as the transactions
+/// they refer to are included with the start of the transfer.
+pub fn write_json_transfer(capture: &mut CaptureReader,
+                           item_id: TrafficItemId,
+                           writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    if let Some(transfer) = json_transfer(capture, item_id)? {
+        writeln!(writer, "{transfer}")?;
+    }
+    Ok(())
+}
+
+/// Describe a top-level traffic item as JSON, with its transactions and
+/// their packets.
+///
+/// Returns `None` for items marking the end of a transfer, as the
+/// transactions they refer to are included with the start of the transfer.
+pub fn json_transfer(capture: &mut CaptureReader, item_id: TrafficItemId)
+    -> Result<Option<Value>, Error>
+{
+    let transfer_id = capture.item_index.get(item_id)?;
+    let entry = capture.transfer_index.get(transfer_id)?;
+    if !entry.is_start() {
+        return Ok(None);
+    }
+    let endpoint = capture.endpoints.get(entry.endpoint_id())?;
+    let ep_addr = EndpointAddr::from_parts(
+        endpoint.number(), endpoint.direction());
+    let (ep_type, _) = capture
+        .device_data(&endpoint.device_id())?
+        .endpoint_details(ep_addr);
+    let item = TrafficItem::Transfer(transfer_id);
+    let mut transactions = Vec::new();
+    let (_, count) = capture.item_children(Some(&item))?;
+    for index in 0..count {
+        let transaction = capture.child_item(&item, index)?;
+        let mut packets = Vec::new();
+        let (_, count) = capture.item_children(Some(&transaction))?;
+        for index in 0..count {
+            if let TrafficItem::Packet(.., packet_id) =
+                capture.child_item(&transaction, index)?
+            {
+                packets.push(packet_json(capture, packet_id)?);
+            }
+        }
+        if let TrafficItem::Transaction(_, transaction_id) = transaction {
+            transactions.push(json!({
+                "id": transaction_id.value,
+                "summary": capture.summary(&transaction)?,
+                "packets": packets,
+            }));
+        }
+    }
+    Ok(Some(json!({
+        "type": "transfer",
+        "id": transfer_id.value,
+        "device": endpoint.device_address().0,
+        "endpoint": endpoint.number().0,
+        "direction": endpoint.direction().to_string(),
+        "endpoint_type": ep_type.to_string(),
+        "summary": capture.summary(&item)?,
+        "transactions": transactions,
+    })))
+}
+
+fn device_children(capture: &mut CaptureReader, item: &DeviceItem)
+    -> Result<Vec<Value>, Error>
+{
+    let (_, count) = capture.item_children(Some(item))?;
+    let mut children = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let child = capture.child_item(item, index)?;
+        children.push(json!({
+            "summary": capture.summary(&child)?,
+            "children": device_children(capture, &child)?,
+        }));
+    }
+    Ok(children)
+}
+
+fn packet_json(capture: &mut CaptureReader, packet_id: PacketId)
+    -> Result<Value, Error>
+{
+    let packet = capture.packet(packet_id)?;
+    let timestamp = capture.packet_time(packet_id)?;
+    let first_byte = *packet.first().with_context(|| format!(
+        "Packet {packet_id} is empty, cannot retrieve PID"))?;
+    let pid = PID::from(first_byte);
+    let well_formed = packet.len() >= pid.min_length();
+    let fields = match (pid, well_formed) {
+        (PID::Malformed, _) | (_, false) => Value::Null,
+        _ => match PacketFields::from_packet(&packet) {
+            PacketFields::SOF(sof) => json!({
+                "frame_number": sof.frame_number(),
+                "crc": sof.crc(),
+            }),
+            PacketFields::Token(token) => json!({
+                "device": token.device_address().0,
+                "endpoint": token.endpoint_number().0,
+                "crc": token.crc(),
+            }),
+            PacketFields::Data(data) => json!({
+                "data": hex(&packet[1 .. packet.len() - 2]),
+                "crc": data.crc,
+            }),
+            PacketFields::Split(split) => json!({
+                "hub": split.hub_address().0,
+                "port": split.port(),
+                "complete": matches!(split.sc(), StartComplete::Complete),
+                "speed": format!("{:?}", split.speed()),
+                "endpoint_type": format!("{:?}", split.endpoint_type()),
+                "crc": split.crc(),
+            }),
+            PacketFields::None => Value::Null,
+        }
+    };
+    Ok(json!({
+        "id": packet_id.value,
+        "timestamp": timestamp,
+        "pid": pid.to_string(),
+        "fields": fields,
+        "bytes": hex(&packet),
+    }))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Write the header line for a CSV export of traffic items.
+pub fn write_csv_header(writer: &mut dyn Write) -> Result<(), Error> {
+    writeln!(writer, "Item,Time,Device,Endpoint,Type,Length,Summary")?;
+    Ok(())
+}
+
+/// Write a line of CSV describing a traffic item.
+///
+/// Times are given in seconds, relative to the specified start time.
+pub fn write_csv_item(capture: &mut CaptureReader,
+                      item: &TrafficItem,
+                      start_time: Timestamp,
+                      writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    use TrafficItem::*;
+    let (kind, transfer_id) = match item {
+        Transfer(transfer_id) => ("Transfer", transfer_id),
+        Transaction(transfer_id, _) => ("Transaction", transfer_id),
+        Packet(transfer_id, ..) => ("Packet", transfer_id),
+    };
+    let entry = capture.transfer_index.get(*transfer_id)?;
+    let endpoint = capture.endpoints.get(entry.endpoint_id())?;
+    let ep_addr = EndpointAddr::from_parts(
+        endpoint.number(), endpoint.direction());
+    let (ep_type, _) = capture
+        .device_data(&endpoint.device_id())?
+        .endpoint_details(ep_addr);
+    let time = capture.item_timestamp(item)?.saturating_sub(start_time);
+    writeln!(writer, "{},{}.{:09},{},{} {},{},{},{}",
+        kind,
+        time / 1_000_000_000,
+        time % 1_000_000_000,
+        endpoint.device_address(),
+        endpoint.number(),
+        endpoint.direction(),
+        ep_type,
+        capture.item_length(item)?,
+        csv_quote(&capture.summary(item)?))?;
+    Ok(())
+}
+
+/// Quote a field for CSV, doubling any quotes within it.
+pub fn csv_quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Selection of the data carried on an endpoint, for `write_endpoint_data`.
+#[derive(Clone, Debug)]
+pub struct EndpointData {
+    /// Address of the device.
+    pub device: DeviceAddr,
+    /// Number of the endpoint on the device.
+    pub number: EndpointNum,
+    /// Direction of the data to include, or both if `None`.
+    pub direction: Option<Direction>,
+    /// Range of times at which the data was sent, or all times if `None`.
+    pub time_range: Option<Range<Timestamp>>,
+}
+
+/// Write the raw data payloads carried on an endpoint, in the order they
+/// were sent, returning the number of bytes written.
+///
+/// Data sent in both directions on the endpoint number is included, unless
+/// a direction is chosen. On control endpoints, only the data stages of
+/// requests are included. Data is selected by the time of the transaction
+/// carrying it.
+pub fn write_endpoint_data(capture: &mut CaptureReader,
+                           data: &EndpointData,
+                           writer: &mut dyn Write)
+    -> Result<u64, Error>
+{
+    let mut payloads = Vec::new();
+    for id in 0..capture.endpoints.len() {
+        let endpoint_id = EndpointId::from(id);
+        if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+            continue;
+        }
+        let endpoint = capture.endpoints.get(endpoint_id)?;
+        if endpoint.device_address() == data.device &&
+            endpoint.number() == data.number
+        {
+            payloads.extend(capture.endpoint_payloads(endpoint_id)?);
+        }
+    }
+    payloads.sort_by_key(|(transaction_id, ..)| *transaction_id);
+    let mut total = 0;
+    for (transaction_id, direction, bytes) in payloads {
+        if matches!(data.direction,
+                    Some(wanted) if wanted as u8 != direction as u8)
+        {
+            continue;
+        }
+        if let Some(range) = &data.time_range {
+            let packet_id = capture.transaction_index.get(transaction_id)?;
+            if !range.contains(&capture.packet_time(packet_id)?) {
+                continue;
+            }
+        }
+        writer.write_all(&bytes)?;
+        total += bytes.len() as u64;
+    }
+    Ok(total)
+}
+
+/// Version of the stable text format, written in its first line. This is
+/// changed whenever the format itself changes, rather than the decodes it
+/// shows.
+pub const STABLE_TEXT_VERSION: u32 = 1;
+
+/// Write a deterministic textual rendering of the decoded capture, suitable
+/// for comparison with a reference in automated tests.
+///
+/// Devices and traffic items are listed in separate sections, each item on
+/// its own line and indented by its depth, with no grouping of repeated
+/// items, so that a difference can be traced to a single item. Each item
+/// is identified by its position in the tree, e.g. `3.1.2` for the second
+/// packet of the first transaction of the third item, and traffic items
+/// are given the time of their first packet, in seconds after the first
+/// packet of the capture. The output therefore does not depend on when
+/// the capture was made, or on how its items are stored.
+pub fn write_stable_text(capture: &mut CaptureReader,
+                         writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    writeln!(writer, "packetry stable-text {STABLE_TEXT_VERSION}")?;
+    writeln!(writer, "devices")?;
+    let (_, count) =
+        ItemSource::<DeviceItem>::item_children(capture, None)?;
+    for index in 0..count {
+        let item: DeviceItem = capture.item(None, index)?;
+        write_stable_device(capture, &item, &(index + 1).to_string(), 1,
+                            writer)?;
+    }
+    writeln!(writer, "traffic")?;
+    let origin = match capture.packet_times.len() {
+        0 => 0,
+        _ => capture.packet_time(PacketId::from(0))?,
+    };
+    for index in 0..capture.item_index.len() {
+        let item: TrafficItem = capture.item(None, index)?;
+        write_stable_traffic(capture, &item, &(index + 1).to_string(), 1,
+                             origin, writer)?;
+    }
+    Ok(())
+}
+
+fn write_stable_device(capture: &mut CaptureReader,
+                       item: &DeviceItem,
+                       id: &str,
+                       depth: usize,
+                       writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    writeln!(writer, "{}{id} {}",
+             " ".repeat(depth), capture.summary(item)?)?;
+    let (_, count) = capture.item_children(Some(item))?;
+    for index in 0..count {
+        let child = capture.child_item(item, index)?;
+        write_stable_device(capture, &child, &format!("{id}.{}", index + 1),
+                            depth + 1, writer)?;
+    }
+    Ok(())
+}
+
+fn write_stable_traffic(capture: &mut CaptureReader,
+                        item: &TrafficItem,
+                        id: &str,
+                        depth: usize,
+                        origin: Timestamp,
+                        writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    let time = capture.item_timestamp(item)?.saturating_sub(origin);
+    writeln!(writer, "{}{id} +{}.{:09} {}",
+             " ".repeat(depth),
+             time / 1_000_000_000,
+             time % 1_000_000_000,
+             capture.summary(item)?)?;
+    let (_, count) = capture.item_children(Some(item))?;
+    for index in 0..count {
+        let child = capture.child_item(item, index)?;
+        write_stable_traffic(capture, &child, &format!("{id}.{}", index + 1),
+                             depth + 1, origin, writer)?;
+    }
+    Ok(())
+}
+
+/// Write an indented textual summary of a traffic item and its children.
+///
+/// Each level of children is indented by a further space, and consecutive
+/// children with identical summaries are written once, with a count.
+/// Children deeper than `max_depth` below the item are omitted.
+pub fn write_text_item(capture: &mut CaptureReader,
+                       item: &TrafficItem,
+                       max_depth: Option<usize>,
+                       writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    let summary = summarize_item(capture, item, 0, max_depth)?;
+    writeln!(writer, "{summary}")?;
+    Ok(())
+}
+
+fn summarize_item(capture: &mut CaptureReader,
+                  item: &TrafficItem,
+                  depth: usize,
+                  max_depth: Option<usize>)
+    -> Result<String, Error>
+{
+    let mut summary = capture.summary(item)?;
+    if matches!(max_depth, Some(max_depth) if depth >= max_depth) {
+        return Ok(summary);
+    }
+    let (_completion, num_children) = capture.item_children(Some(item))?;
+    let mut child_summaries = Vec::new();
+    for child_id in 0..num_children {
+        let child = capture.child_item(item, child_id)?;
+        child_summaries.push(
+            summarize_item(capture, &child, depth + 1, max_depth)?);
+    }
+    for (n, child_summary) in child_summaries.into_iter().dedup_with_count() {
+        summary += "\n";
+        summary += &" ".repeat(depth + 1);
+        if n > 1 {
+            summary += &format!("{n} times: {child_summary}");
+        } else {
+            summary += &child_summary;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+    use crate::pcap;
+
+    fn endpoint_data(capture: &mut CaptureReader,
+                     direction: Option<Direction>,
+                     time_range: Option<Range<Timestamp>>)
+        -> Vec<u8>
+    {
+        let data = EndpointData {
+            device: DeviceAddr(4),
+            number: EndpointNum(1),
+            direction,
+            time_range,
+        };
+        let mut output = Vec::new();
+        let total = write_endpoint_data(capture, &data, &mut output).unwrap();
+        assert_eq!(total, output.len() as u64);
+        output
+    }
+
+    #[test]
+    fn test_endpoint_data() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let all = endpoint_data(&mut capture, None, None);
+        assert_eq!(all.len(), 154 + 798 + 154);
+        let inbound = endpoint_data(&mut capture, Some(Direction::In), None);
+        assert_eq!(inbound, all);
+        let outbound = endpoint_data(&mut capture, Some(Direction::Out), None);
+        assert!(outbound.is_empty());
+        let during = endpoint_data(&mut capture, None, Some(0..u64::MAX));
+        assert_eq!(during, all);
+        let start = capture.packet_time(PacketId::from(0)).unwrap();
+        let split = start + 400_000;
+        let before = endpoint_data(&mut capture, None, Some(0..split));
+        let after = endpoint_data(&mut capture, None, Some(split..u64::MAX));
+        assert!(!before.is_empty() && !after.is_empty());
+        assert_eq!([before, after].concat(), all);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut output = Vec::new();
+        write_json_devices(&mut capture, &mut output).unwrap();
+        for index in 0..2 {
+            let item_id = TrafficItemId::from(index);
+            write_json_transfer(&mut capture, item_id, &mut output).unwrap();
+        }
+        let records: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+
+        let device = &records[0];
+        assert_eq!(device["type"], "device");
+        assert_eq!(device["address"], 4);
+        assert_eq!(device["summary"], "Device 4: USB Optical Mouse");
+        assert_eq!(device["children"][0]["summary"], "Device descriptor");
+
+        // The first packet is malformed, so its fields cannot be decoded.
+        let invalid = &records[1];
+        assert_eq!(invalid["type"], "transfer");
+        assert_eq!(invalid["endpoint_type"], "Invalid");
+        let packet = &invalid["transactions"][0]["packets"][0];
+        assert_eq!(packet["pid"], "Malformed");
+        assert_eq!(packet["bytes"], "FF");
+        assert_eq!(packet["fields"], Value::Null);
+
+        let request = &records[2];
+        assert_eq!(request["type"], "transfer");
+        assert_eq!(request["device"], 0);
+        assert_eq!(request["endpoint"], 0);
+        assert_eq!(request["direction"], "OUT");
+        assert_eq!(request["endpoint_type"], "Control");
+        let setup = &request["transactions"][0];
+        assert_eq!(setup["summary"],
+                   "SETUP transaction on 0.0 with 8 data bytes, ACK: \
+                    [80, 06, 00, 01, 00, 00, 40, 00]");
+        let packets = setup["packets"].as_array().unwrap();
+        let pids: Vec<&str> = packets
+            .iter()
+            .map(|packet| packet["pid"].as_str().unwrap())
+            .collect();
+        assert_eq!(pids, ["SETUP", "DATA0", "ACK"]);
+        assert_eq!(packets[0]["fields"],
+                   json!({"device": 0, "endpoint": 0, "crc": 2}));
+        assert_eq!(packets[1]["fields"],
+                   json!({"data": "8006000100004000", "crc": 0x94DD}));
+        assert_eq!(packets[1]["bytes"], "C38006000100004000DD94");
+        assert_eq!(packets[2]["fields"], Value::Null);
+    }
+
+    #[test]
+    fn test_csv() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let start = capture.packet_time(PacketId::from(0)).unwrap();
+        let mut output = Vec::new();
+        write_csv_header(&mut output).unwrap();
+        for index in 0..2 {
+            let item: TrafficItem = capture.item(None, index).unwrap();
+            write_csv_item(&mut capture, &item, start, &mut output).unwrap();
+        }
+        let request: TrafficItem = capture.item(None, 1).unwrap();
+        let setup = capture.child_item(&request, 0).unwrap();
+        write_csv_item(&mut capture, &setup, start - 1_500_000_000,
+                       &mut output).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, [
+            "Item,Time,Device,Endpoint,Type,Length,Summary",
+            "Transfer,0.000000000,0,16 OUT,Invalid,0,\"1 invalid groups\"",
+            "Transfer,0.000002000,0,0 OUT,Control,18,\"Getting device \
+             descriptor #0 for device 0, reading 18 of 64 requested bytes\"",
+            "Transaction,1.500002000,0,0 OUT,Control,8,\"SETUP transaction \
+             on 0.0 with 8 data bytes, ACK: [80, 06, 00, 01, 00, 00, 40, \
+             00]\"",
+        ]);
+
+        // Quotes within a summary are doubled, and commas left as they are.
+        assert_eq!(csv_quote("Read \"Serial\", then stall"),
+                   "\"Read \"\"Serial\"\", then stall\"");
+    }
+
+    #[test]
+    fn test_stable_text() {
+        use pcap_file::pcap::{PcapReader, PcapWriter};
+        let dump = |path: &PathBuf| {
+            let mut capture = load(std::slice::from_ref(path)).unwrap();
+            let mut output = Vec::new();
+            write_stable_text(&mut capture, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let text = dump(&path);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "packetry stable-text 1");
+        assert_eq!(lines[1], "devices");
+        assert!(lines.contains(&"traffic"));
+        assert_eq!(lines[2], " 1 Device 4: USB Optical Mouse");
+        assert_eq!(lines[3], "  1.1 Device descriptor");
+        assert!(lines.contains(&" 1 +0.000000000 1 invalid groups"));
+        assert!(lines.contains(&"  2.1 +0.000002000 SETUP transaction on \
+                                 0.0 with 8 data bytes, ACK: [80, 06, 00, \
+                                 01, 00, 00, 40, 00]"));
+        assert!(lines.contains(&"   2.1.2 +0.000003000 DATA0 packet with \
+                                 CRC 94DD and 8 data bytes: [80, 06, 00, \
+                                 01, 00, 00, 40, 00]"));
+        // Repeated items are listed separately.
+        assert!(lines.iter().any(|line| line.starts_with("  2.3 ")));
+        assert!(!text.contains(" times: "));
+        assert_eq!(dump(&path), text);
+
+        // The same packets captured at another time give the same text.
+        let dir = tempfile::tempdir().unwrap();
+        let later = dir.path().join("later.pcap");
+        let file = std::fs::File::open(&path).unwrap();
+        let mut reader = PcapReader::new(file).unwrap();
+        let header = reader.header();
+        let mut writer = PcapWriter::with_header(
+            std::fs::File::create(&later).unwrap(), pcap::header()).unwrap();
+        while let Some(packet) = reader.next_raw_packet() {
+            let packet = packet.unwrap();
+            let time = pcap::timestamp(&header, &packet) + 86_400_000_000_123;
+            writer.write_raw_packet(&pcap::record(packet.data, time).unwrap())
+                .unwrap();
+        }
+        drop(writer);
+        assert_eq!(dump(&later), text);
+    }
+}
@@ -0,0 +1,905 @@
+//! Display filters, for selecting traffic items by their properties.
+//!
+//! A filter is an expression such as:
+//!
+//! `device.address == 5 && endpoint == 0x81 && data contains 55:AA`
+//!
+//! Comparisons between a field and a value may be combined with `&&`,
+//! `||` and `!` (or `and`, `or` and `not`), and grouped with parentheses.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use anyhow::{Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    Endpoint,
+    EndpointId,
+    EndpointType,
+    ItemSource,
+    TrafficItem,
+    TrafficItemId,
+};
+use crate::traffic_index::TrafficIndex;
+use crate::usb::{self, Direction};
+
+/// Maximum number of items to check each time a filter is updated.
+const FILTER_BATCH_SIZE: u64 = 10000;
+
+/// Maximum number of indexed items to visit each time a filter is updated,
+/// for items whose match is known from the index without checking them.
+const INDEXED_BATCH_SIZE: u64 = 10_000_000;
+
+/// Fields of a traffic item which may be used in a filter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Field {
+    DeviceAddress,
+    EndpointAddress,
+    EndpointNumber,
+    EndpointDirection,
+    TransferType,
+    Length,
+    Data,
+    Summary,
+    Error,
+    Polling,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        use Field::*;
+        Some(match name {
+            "device" | "device.address" => DeviceAddress,
+            "endpoint" | "endpoint.address" => EndpointAddress,
+            "endpoint.number" => EndpointNumber,
+            "endpoint.direction" => EndpointDirection,
+            "transfer.type" | "endpoint.type" => TransferType,
+            "length" | "data.length" => Length,
+            "data" => Data,
+            "summary" => Summary,
+            "error" => Error,
+            "polling" => Polling,
+            _ => return None,
+        })
+    }
+
+    /// Whether this field can be compared using the given operator.
+    fn supports(&self, op: Operator) -> bool {
+        use Field::*;
+        match self {
+            DeviceAddress | EndpointAddress | EndpointNumber | Length =>
+                op != Operator::Contains,
+            EndpointDirection | TransferType | Error | Polling =>
+                op.is_equality(),
+            Data | Summary => op.is_equality() || op == Operator::Contains,
+        }
+    }
+}
+
+/// Ways in which a field may be compared with a value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Contains,
+}
+
+impl Operator {
+    fn is_equality(&self) -> bool {
+        matches!(self, Operator::Equal | Operator::NotEqual)
+    }
+
+    fn compare<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        use Operator::*;
+        match self {
+            Equal => a == b,
+            NotEqual => a != b,
+            Less => a < b,
+            LessOrEqual => a <= b,
+            Greater => a > b,
+            GreaterOrEqual => a >= b,
+            Contains => false,
+        }
+    }
+}
+
+/// Types of traffic that may be selected with `transfer.type`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd)]
+enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+    Framing,
+    Invalid,
+    Ungrouped,
+    Unidentified,
+}
+
+impl From<EndpointType> for TransferType {
+    fn from(ep_type: EndpointType) -> TransferType {
+        use EndpointType::*;
+        match ep_type {
+            Normal(usb::EndpointType::Control) => TransferType::Control,
+            Normal(usb::EndpointType::Isochronous) => TransferType::Isochronous,
+            Normal(usb::EndpointType::Bulk) => TransferType::Bulk,
+            Normal(usb::EndpointType::Interrupt) => TransferType::Interrupt,
+            Framing => TransferType::Framing,
+            Invalid => TransferType::Invalid,
+            Ungrouped => TransferType::Ungrouped,
+            Unidentified => TransferType::Unidentified,
+        }
+    }
+}
+
+/// A value to which a field is compared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Number(u64),
+    Direction(u8),
+    TransferType(TransferType),
+    Bytes(Vec<u8>),
+    Text(String),
+    Bool(bool),
+}
+
+/// A parsed filter expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Operator, Value),
+}
+
+/// A display filter, which can be tested against traffic items.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    /// Parse a filter expression.
+    pub fn parse(text: &str) -> Result<Filter, Error> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expr = parser.expression()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected '{}' at column {}", token.text, token.column)
+        }
+        Ok(Filter { expr })
+    }
+
+    /// Whether a traffic item is selected by this filter.
+    pub fn matches(&self, capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<bool, Error>
+    {
+        evaluate(&self.expr, capture, item)
+    }
+}
+
+/// A field of traffic items, by the name used in filters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ItemField(Field);
+
+impl ItemField {
+    pub fn from_name(name: &str) -> Option<ItemField> {
+        Field::from_name(name.trim()).map(ItemField)
+    }
+
+    /// The value of this field for a traffic item, as text, and also as
+    /// a number for numeric fields.
+    pub fn value(&self, capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<(String, Option<u64>), Error>
+    {
+        let endpoint = capture.item_endpoint(item)?;
+        let number = |number: u64| (number.to_string(), Some(number));
+        Ok(match self.0 {
+            Field::DeviceAddress =>
+                number(endpoint.device_address().0 as u64),
+            Field::EndpointAddress => {
+                let address = endpoint.address().0;
+                (format!("0x{address:02X}"), Some(address as u64))
+            },
+            Field::EndpointNumber => number(endpoint.number().0 as u64),
+            Field::EndpointDirection =>
+                (endpoint.direction().to_string(), None),
+            Field::TransferType => {
+                let (ep_type, _) = capture
+                    .device_data(&endpoint.device_id())?
+                    .endpoint_details(endpoint.address());
+                (ep_type.to_string(), None)
+            },
+            Field::Length => number(capture.item_length(item)?),
+            Field::Data => {
+                let data: Vec<String> = capture
+                    .item_data(item)?
+                    .iter()
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect();
+                (data.join(":"), None)
+            },
+            Field::Summary => (capture.summary(item)?, None),
+            Field::Error => {
+                let error = capture.item_error(item)?;
+                ((if error { "yes" } else { "no" }).to_string(), None)
+            },
+            Field::Polling => {
+                let polling = capture.item_polling(item)?;
+                ((if polling { "yes" } else { "no" }).to_string(), None)
+            },
+        })
+    }
+}
+
+fn evaluate(expr: &Expr, capture: &mut CaptureReader, item: &TrafficItem)
+    -> Result<bool, Error>
+{
+    use Expr::*;
+    Ok(match expr {
+        And(a, b) =>
+            evaluate(a, capture, item)? && evaluate(b, capture, item)?,
+        Or(a, b) =>
+            evaluate(a, capture, item)? || evaluate(b, capture, item)?,
+        Not(a) => !evaluate(a, capture, item)?,
+        Compare(field, op, value) => compare(capture, item, *field, *op, value)?,
+    })
+}
+
+fn compare(capture: &mut CaptureReader,
+           item: &TrafficItem,
+           field: Field,
+           op: Operator,
+           value: &Value)
+    -> Result<bool, Error>
+{
+    let endpoint = capture.item_endpoint(item)?;
+    if let Some(result) =
+        compare_endpoint(capture, &endpoint, field, op, value)?
+    {
+        return Ok(result);
+    }
+    Ok(match (field, value) {
+        (Field::Length, Value::Number(number)) =>
+            op.compare(capture.item_length(item)?, *number),
+        (Field::Data, Value::Bytes(bytes)) => {
+            let data = capture.item_data(item)?;
+            match op {
+                Operator::Contains => contains(&data, bytes),
+                _ => op.compare(&data, bytes),
+            }
+        },
+        (Field::Summary, Value::Text(text)) => {
+            let summary = capture.summary(item)?;
+            match op {
+                Operator::Contains =>
+                    summary.to_lowercase().contains(&text.to_lowercase()),
+                _ => op.compare(&summary, text),
+            }
+        },
+        (Field::Error, Value::Bool(error)) =>
+            op.compare(capture.item_error(item)?, *error),
+        (Field::Polling, Value::Bool(polling)) =>
+            op.compare(capture.item_polling(item)?, *polling),
+        (field, value) => bail!("Cannot compare {field:?} with {value:?}"),
+    })
+}
+
+/// Compare a field which depends only on an item's endpoint.
+///
+/// Returns None if the field is not a property of the endpoint.
+fn compare_endpoint(capture: &mut CaptureReader,
+                    endpoint: &Endpoint,
+                    field: Field,
+                    op: Operator,
+                    value: &Value)
+    -> Result<Option<bool>, Error>
+{
+    Ok(Some(match (field, value) {
+        (Field::DeviceAddress, Value::Number(number)) =>
+            op.compare(endpoint.device_address().0 as u64, *number),
+        (Field::EndpointAddress, Value::Number(number)) =>
+            op.compare(endpoint.address().0 as u64, *number),
+        (Field::EndpointNumber, Value::Number(number)) =>
+            op.compare(endpoint.number().0 as u64, *number),
+        (Field::EndpointDirection, Value::Direction(direction)) =>
+            op.compare(endpoint.direction() as u8, *direction),
+        (Field::TransferType, Value::TransferType(transfer_type)) => {
+            let (ep_type, _) = capture
+                .device_data(&endpoint.device_id())?
+                .endpoint_details(endpoint.address());
+            op.compare(TransferType::from(ep_type), *transfer_type)
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// Whether an expression is true or false for every item on an endpoint,
+/// given whether the item shows an error, if known.
+///
+/// Returns None if the result depends on other fields of each item.
+fn classify(expr: &Expr,
+            capture: &mut CaptureReader,
+            endpoint: &Endpoint,
+            error: Option<bool>)
+    -> Result<Option<bool>, Error>
+{
+    use Expr::*;
+    Ok(match expr {
+        And(a, b) => match (classify(a, capture, endpoint, error)?,
+                            classify(b, capture, endpoint, error)?) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Or(a, b) => match (classify(a, capture, endpoint, error)?,
+                           classify(b, capture, endpoint, error)?) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Not(a) => classify(a, capture, endpoint, error)?.map(|a| !a),
+        Compare(Field::Error, op, Value::Bool(value)) =>
+            error.map(|error| op.compare(error, *value)),
+        Compare(field, op, value) =>
+            compare_endpoint(capture, endpoint, *field, *op, value)?,
+    })
+}
+
+fn contains(data: &[u8], pattern: &[u8]) -> bool {
+    pattern.is_empty() ||
+        data.windows(pattern.len()).any(|window| window == pattern)
+}
+
+/// The items at the top level of a capture which are selected by a filter.
+///
+/// Items are checked in batches, so that filtering a large capture does not
+/// hold up the UI. Items added to an ongoing capture are checked as they
+/// are added.
+#[derive(Clone)]
+pub struct FilteredItems {
+    filter: Arc<Filter>,
+    matches: Vec<TrafficItemId>,
+    checked: u64,
+}
+
+impl FilteredItems {
+    pub fn new(filter: Arc<Filter>) -> FilteredItems {
+        FilteredItems {
+            filter,
+            matches: Vec::new(),
+            checked: 0,
+        }
+    }
+
+    /// Number of matching items found so far.
+    pub fn count(&self) -> u64 {
+        self.matches.len() as u64
+    }
+
+    /// Get the ID of a matching item by its index among the matches.
+    pub fn get(&self, index: u64) -> Option<TrafficItemId> {
+        self.matches.get(index as usize).copied()
+    }
+
+    /// Get the index among the matches of an item, if it matched.
+    pub fn position(&self, item_id: TrafficItemId) -> Option<u64> {
+        self.matches
+            .binary_search(&item_id)
+            .ok()
+            .map(|index| index as u64)
+    }
+
+    /// Check the next batch of items in the capture.
+    ///
+    /// Returns whether all the items currently in the capture are checked.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<bool, Error>
+    {
+        // Use the traffic index for the items it covers so far.
+        let shared = capture.shared.clone();
+        if let Ok(index) = shared.traffic_index.read() {
+            if self.checked < index.indexed() {
+                self.update_indexed(capture, &index)?;
+                return Ok(false);
+            }
+        }
+        let item_count = capture.item_index.len();
+        let end = item_count.min(self.checked + FILTER_BATCH_SIZE);
+        for index in self.checked..end {
+            let item = capture.item(None, index)?;
+            if self.filter.matches(capture, &item)? {
+                self.matches.push(TrafficItemId::from(index));
+            }
+        }
+        self.checked = end;
+        Ok(end == item_count)
+    }
+
+    /// Check the next batch of items covered by the traffic index.
+    ///
+    /// Only the items on endpoints where the filter may match are visited,
+    /// and only those whose match cannot be decided from their endpoint
+    /// and error status are checked individually.
+    fn update_indexed(&mut self,
+                      capture: &mut CaptureReader,
+                      index: &TrafficIndex)
+        -> Result<(), Error>
+    {
+        struct Source<'i> {
+            endpoint_id: EndpointId,
+            without_error: Option<bool>,
+            with_error: Option<bool>,
+            unknown_error: Option<bool>,
+            items: &'i [TrafficItemId],
+        }
+        let start = TrafficItemId::from(self.checked);
+        let expr = &self.filter.expr;
+        let mut sources = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for value in 0..index.endpoint_count() {
+            let endpoint_id = EndpointId::from(value);
+            let endpoint = capture.endpoints.get(endpoint_id)?;
+            let without_error =
+                classify(expr, capture, &endpoint, Some(false))?;
+            let with_error = classify(expr, capture, &endpoint, Some(true))?;
+            if without_error == Some(false) && with_error == Some(false) {
+                continue;
+            }
+            let items = index.endpoint_items(endpoint_id, start);
+            if let Some(first) = items.first() {
+                heap.push(Reverse((*first, sources.len(), 0)));
+                sources.push(Source {
+                    endpoint_id,
+                    without_error,
+                    with_error,
+                    unknown_error: classify(expr, capture, &endpoint, None)?,
+                    items,
+                });
+            }
+        }
+        // Visit the items of those endpoints in capture order.
+        let mut checked = 0;
+        let mut visited = 0;
+        while let Some(Reverse((item_id, source_index, position))) =
+            heap.pop()
+        {
+            if checked >= FILTER_BATCH_SIZE || visited >= INDEXED_BATCH_SIZE {
+                // Continue from this item next time.
+                self.checked = item_id.value;
+                return Ok(());
+            }
+            visited += 1;
+            let source = &sources[source_index];
+            let known = match index.item_error(source.endpoint_id, item_id) {
+                Some(false) => source.without_error,
+                Some(true) => source.with_error,
+                None => source.unknown_error,
+            };
+            let matched = match known {
+                Some(matched) => matched,
+                None => {
+                    checked += 1;
+                    let item = capture.item(None, item_id.value)?;
+                    self.filter.matches(capture, &item)?
+                }
+            };
+            if matched {
+                self.matches.push(item_id);
+            }
+            if let Some(next) = source.items.get(position + 1) {
+                heap.push(Reverse((*next, source_index, position + 1)));
+            }
+        }
+        self.checked = index.indexed();
+        Ok(())
+    }
+}
+
+/// A token of a filter expression.
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    column: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    Quoted,
+    Symbol,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (
+                chars[i].is_ascii_alphanumeric() ||
+                matches!(chars[i], '_' | '.' | ':'))
+            {
+                i += 1;
+            }
+            tokens.push(Token {
+                kind: TokenKind::Word,
+                text: chars[start..i].iter().collect(),
+                column,
+            });
+        } else if c == '"' {
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => bail!("Unterminated string at column {column}"),
+                    Some('"') => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        string.push(chars[i + 1]);
+                        i += 2;
+                    },
+                    Some(c) => {
+                        string.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push(Token {
+                kind: TokenKind::Quoted,
+                text: string,
+                column,
+            });
+        } else {
+            let pair: String = chars[i..chars.len().min(i + 2)]
+                .iter()
+                .collect();
+            let symbol = match pair.as_str() {
+                "==" | "!=" | "<=" | ">=" | "&&" | "||" => pair,
+                _ => match c {
+                    '=' | '<' | '>' | '!' | '(' | ')' => c.to_string(),
+                    _ => bail!("Unexpected character '{c}' at column {column}"),
+                }
+            };
+            i += symbol.len();
+            tokens.push(Token {
+                kind: TokenKind::Symbol,
+                text: symbol,
+                column,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, Error> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            },
+            None => bail!("Expected {expected} at end of filter"),
+        }
+    }
+
+    /// Consume the next token if it is one of the given operators.
+    fn accept(&mut self, operators: &[&str]) -> bool {
+        let found = match self.peek() {
+            Some(token) => token.kind != TokenKind::Quoted &&
+                operators.contains(&token.text.as_str()),
+            None => false,
+        };
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expression(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.conjunction()?;
+        while self.accept(&["||", "or"]) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.conjunction()?));
+        }
+        Ok(expr)
+    }
+
+    fn conjunction(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.term()?;
+        while self.accept(&["&&", "and"]) {
+            expr = Expr::And(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, Error> {
+        if self.accept(&["!", "not"]) {
+            return Ok(Expr::Not(Box::new(self.term()?)));
+        }
+        if self.accept(&["("]) {
+            let expr = self.expression()?;
+            let token = self.next("')'")?;
+            if token.text != ")" {
+                bail!("Expected ')' at column {}, found '{}'",
+                      token.column, token.text)
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Error> {
+        let field_token = self.next("a field name")?;
+        let field = match (field_token.kind, Field::from_name(&field_token.text)) {
+            (TokenKind::Word, Some(field)) => field,
+            _ => bail!("Unknown field '{}' at column {}",
+                       field_token.text, field_token.column),
+        };
+        let token = self.next("an operator")?;
+        let op = match (token.kind, token.text.as_str()) {
+            (TokenKind::Symbol, "==" | "=") => Operator::Equal,
+            (TokenKind::Symbol, "!=") => Operator::NotEqual,
+            (TokenKind::Symbol, "<") => Operator::Less,
+            (TokenKind::Symbol, "<=") => Operator::LessOrEqual,
+            (TokenKind::Symbol, ">") => Operator::Greater,
+            (TokenKind::Symbol, ">=") => Operator::GreaterOrEqual,
+            (TokenKind::Word, "contains") => Operator::Contains,
+            _ => bail!("Expected an operator at column {}, found '{}'",
+                       token.column, token.text),
+        };
+        if !field.supports(op) {
+            bail!("Operator '{}' at column {} cannot be used with '{}'",
+                  token.text, token.column, field_token.text)
+        }
+        let token = self.next("a value")?;
+        let value = parse_value(field, &token)?;
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+fn parse_value(field: Field, token: &Token) -> Result<Value, Error> {
+    use Field::*;
+    let column = token.column;
+    let text = token.text.as_str();
+    Ok(match field {
+        DeviceAddress | EndpointAddress | EndpointNumber | Length =>
+            Value::Number(parse_number(text, column)?),
+        EndpointDirection =>
+            Value::Direction(match text.to_lowercase().as_str() {
+                "in" => Direction::In as u8,
+                "out" => Direction::Out as u8,
+                _ => bail!("Expected 'in' or 'out' at column {column}, \
+                            found '{text}'"),
+            }),
+        TransferType =>
+            Value::TransferType(match text.to_lowercase().as_str() {
+                "control" => self::TransferType::Control,
+                "isochronous" | "iso" => self::TransferType::Isochronous,
+                "bulk" => self::TransferType::Bulk,
+                "interrupt" => self::TransferType::Interrupt,
+                "framing" | "sof" => self::TransferType::Framing,
+                "invalid" => self::TransferType::Invalid,
+                "ungrouped" => self::TransferType::Ungrouped,
+                "unidentified" => self::TransferType::Unidentified,
+                _ => bail!("Unknown transfer type '{text}' at column {column}"),
+            }),
+        Data => match token.kind {
+            TokenKind::Quoted => Value::Bytes(text.as_bytes().to_vec()),
+            _ => Value::Bytes(parse_bytes(text, column)?),
+        },
+        Summary => Value::Text(text.to_string()),
+        Error | Polling => Value::Bool(match text.to_lowercase().as_str() {
+            "yes" | "true" => true,
+            "no" | "false" => false,
+            _ => bail!("Expected 'yes' or 'no' at column {column}, \
+                        found '{text}'"),
+        }),
+    })
+}
+
+fn parse_number(text: &str, column: usize) -> Result<u64, Error> {
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    match result {
+        Ok(number) => Ok(number),
+        Err(_) => bail!("Invalid number '{text}' at column {column}"),
+    }
+}
+
+/// Parse bytes written as hex, e.g. `55:AA`, `55AA` or `0x55AA`.
+fn parse_bytes(text: &str, column: usize) -> Result<Vec<u8>, Error> {
+    let digits: String = text
+        .strip_prefix("0x")
+        .unwrap_or(text)
+        .chars()
+        .filter(|c| *c != ':')
+        .collect();
+    let valid = !digits.is_empty() &&
+        digits.len() % 2 == 0 &&
+        digits.chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        bail!("Invalid bytes '{text}' at column {column}, \
+               expected hex bytes such as 55:AA")
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for i in (0..digits.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&digits[i..i + 2], 16)?);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::capture::CompletionStatus;
+    use crate::loader::load;
+    use crate::traffic_index::update_index;
+
+    fn compare(field: Field, op: Operator, value: Value) -> Box<Expr> {
+        Box::new(Expr::Compare(field, op, value))
+    }
+
+    #[test]
+    fn test_parse() {
+        use Expr::*;
+        use Field::*;
+        use Operator::*;
+        let filter = Filter::parse(
+            "device.address == 5 && endpoint == 0x81 && \
+             transfer.type == bulk && data contains 55:AA").unwrap();
+        assert_eq!(filter.expr,
+            And(
+                Box::new(And(
+                    Box::new(And(
+                        compare(DeviceAddress, Equal, Value::Number(5)),
+                        compare(EndpointAddress, Equal, Value::Number(0x81)))),
+                    compare(Field::TransferType, Equal,
+                            Value::TransferType(super::TransferType::Bulk)))),
+                compare(Data, Contains, Value::Bytes(vec![0x55, 0xAA]))));
+        let filter = Filter::parse(
+            "!(length > 8 or summary contains \"SETUP\") and \
+             endpoint.direction != in").unwrap();
+        assert_eq!(filter.expr,
+            And(
+                Box::new(Not(
+                    Box::new(Or(
+                        compare(Length, Greater, Value::Number(8)),
+                        compare(Summary, Contains,
+                                Value::Text("SETUP".to_string())))))),
+                compare(EndpointDirection, NotEqual,
+                        Value::Direction(Direction::In as u8))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, error) in [
+            ("", "Expected a field name at end of filter"),
+            ("device ==", "Expected a value at end of filter"),
+            ("speed == 5", "Unknown field 'speed' at column 1"),
+            ("device 5", "Expected an operator at column 8, found '5'"),
+            ("device == five", "Invalid number 'five' at column 11"),
+            ("data contains 5", "Invalid bytes '5' at column 15, \
+                                 expected hex bytes such as 55:AA"),
+            ("transfer.type > bulk",
+             "Operator '>' at column 15 cannot be used with 'transfer.type'"),
+            ("(device == 1", "Expected ')' at end of filter"),
+            ("device == 1 device", "Unexpected 'device' at column 13"),
+            ("device == 1 # 2", "Unexpected character '#' at column 13"),
+        ] {
+            let result = Filter::parse(text);
+            assert_eq!(result.unwrap_err().to_string(), error, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(parse_bytes("55:AA", 1).unwrap(), vec![0x55, 0xAA]);
+        assert_eq!(parse_bytes("0x55aa01", 1).unwrap(), vec![0x55, 0xAA, 1]);
+        assert!(contains(&[1, 2, 3, 4], &[2, 3]));
+        assert!(!contains(&[1, 2, 3, 4], &[3, 2]));
+    }
+
+    fn filtered_summaries(capture: &CaptureReader, text: &str) -> Vec<String> {
+        let mut capture = capture.clone();
+        capture.set_filter(Some(Arc::new(Filter::parse(text).unwrap())));
+        let count = loop {
+            match ItemSource::<TrafficItem>::item_children(&mut capture, None)
+                .unwrap()
+            {
+                (CompletionStatus::Complete, count) => break count,
+                (CompletionStatus::Ongoing, _) => continue,
+            }
+        };
+        (0..count)
+            .map(|index| {
+                let item: TrafficItem = capture.item(None, index).unwrap();
+                capture.summary(&item).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_capture() {
+        let path = PathBuf::from("../tests/hackrf-dfu-enum/capture.pcap");
+        let reader = load(&[path]).unwrap();
+        assert_eq!(
+            filtered_summaries(&reader, "transfer.type == control").len(), 9);
+        assert_eq!(
+            filtered_summaries(&reader, "device == 0"),
+            vec!["12 SOF groups"]);
+        assert_eq!(
+            filtered_summaries(&reader, "data contains 12:01"),
+            vec!["Getting device descriptor #0 for device 11, \
+                  reading 18 bytes"]);
+        assert_eq!(
+            filtered_summaries(&reader,
+                "summary contains string && data contains 44:00:46:00"),
+            vec!["Getting string descriptor #4, language 0x0409 for \
+                  device 11, reading 8 of 255 requested bytes: 'DFU'"]);
+        assert!(filtered_summaries(&reader, "length >= 64").is_empty());
+
+        // Filtering with the traffic index gives the same results.
+        let filters = [
+            "transfer.type == control",
+            "device == 0",
+            "!(endpoint == 0x00) && data contains 12:01",
+            "error == yes",
+            "error == no || length > 8",
+            "endpoint.direction == in && error != yes",
+            "polling == no && device == 11",
+        ];
+        let unindexed: Vec<Vec<String>> = filters
+            .iter()
+            .map(|text| filtered_summaries(&reader, text))
+            .collect();
+        let mut indexer = reader.clone();
+        while update_index(&mut indexer).unwrap() {}
+        for (text, expected) in filters.iter().zip(unindexed) {
+            assert_eq!(filtered_summaries(&reader, text), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_filter_polling() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut reader = load(&[path]).unwrap();
+        let polling = filtered_summaries(&reader, "polling == yes");
+        assert_eq!(polling.len(), 4);
+        assert!(polling.iter().all(|summary| summary.starts_with("Polling")));
+        let other = filtered_summaries(&reader, "polling == no");
+        assert!(other.iter().all(|summary| !summary.starts_with("Polling")));
+        assert_eq!(polling.len() + other.len(),
+                   reader.item_index.len() as usize);
+
+        // Polls can be described with the time they span.
+        reader.set_poll_durations(true);
+        let polling = filtered_summaries(&reader, "polling == yes");
+        assert!(polling[0].starts_with("Polling 423 times over "));
+        assert!(polling[0].ends_with(
+            " ms for interrupt transfer on endpoint 4.1 IN"));
+    }
+}
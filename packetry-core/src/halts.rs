@@ -0,0 +1,351 @@
+//! Endpoint halts, from the first STALL seen on an endpoint to the
+//! CLEAR_FEATURE(ENDPOINT_HALT) request that cleared it, kept up to date
+//! as a capture grows.
+
+use std::sync::atomic::Ordering::Acquire;
+
+use anyhow::Error;
+
+use crate::capture::{
+    milliseconds,
+    CaptureReader,
+    DeviceId,
+    EndpointId,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    TransactionId,
+    CONTROL_EP_NUM,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+use crate::usb::{
+    Recipient,
+    RequestType,
+    SetupFields,
+    StandardFeature,
+    StandardRequest,
+    PID,
+};
+
+/// A period during which an endpoint was halted.
+#[derive(Clone, Debug)]
+pub struct Halt {
+    pub endpoint_id: EndpointId,
+    /// Description of the endpoint, e.g. `5.1 IN`.
+    pub name: String,
+    pub first_stall: TransactionId,
+    pub last_stall: TransactionId,
+    /// Number of transactions answered with STALL.
+    pub stalls: u64,
+    /// Time of the first STALL.
+    pub start: Timestamp,
+    /// The SETUP transaction of the request which cleared the halt, and
+    /// its time, if the halt was cleared.
+    pub cleared: Option<(TransactionId, Timestamp)>,
+}
+
+impl Halt {
+    /// Time from the first STALL to the request clearing the halt.
+    pub fn duration(&self) -> Option<Timestamp> {
+        self.cleared.map(|(_, end)| end.saturating_sub(self.start))
+    }
+
+    pub fn description(&self) -> String {
+        let stalls = if self.stalls == 1 {
+            String::from("1 STALL")
+        } else {
+            format!("{} STALLs", self.stalls)
+        };
+        match self.duration() {
+            Some(duration) => format!(
+                "{} halted for {}, {stalls}, cleared by CLEAR_FEATURE",
+                self.name, milliseconds(duration)),
+            None => format!("{} halted, {stalls}, not cleared", self.name),
+        }
+    }
+}
+
+/// A transaction which starts or clears a halt.
+#[derive(Clone, Debug)]
+enum Event {
+    Stall(EndpointId),
+    Clear,
+}
+
+#[derive(Clone, Debug)]
+struct HaltEvent {
+    transaction_id: TransactionId,
+    time: Timestamp,
+    /// The device and endpoint address affected.
+    target: (DeviceId, u8),
+    event: Event,
+}
+
+/// The halts found in a capture, in the order they began.
+#[derive(Clone, Debug, Default)]
+pub struct Halts {
+    pub halts: Vec<Halt>,
+    /// STALLs and clearing requests, in the order of their transactions.
+    events: Vec<HaltEvent>,
+    /// Number of transactions checked on each endpoint.
+    checked: Vec<u64>,
+}
+
+impl Halts {
+    pub fn new() -> Self {
+        Halts::default()
+    }
+
+    /// Find the halts in a whole capture.
+    pub fn find(capture: &mut CaptureReader) -> Result<Halts, Error> {
+        let mut halts = Halts::new();
+        halts.update(capture)?;
+        Ok(halts)
+    }
+
+    /// Check the traffic added to a capture since the last update.
+    ///
+    /// Returns whether any halts changed.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<bool, Error>
+    {
+        // The last transaction on an endpoint may still be growing, unless
+        // the capture is complete.
+        let complete = capture.shared.complete.load(Acquire);
+        for index in self.checked.len()..capture.endpoints.len() as usize {
+            // An endpoint is listed before its traffic can be read.
+            if capture.endpoint_traffic(EndpointId::from(index as u64))
+                .is_err()
+            {
+                break;
+            }
+            self.checked.push(0);
+        }
+        let mut found = Vec::new();
+        for index in 0..self.checked.len() {
+            let endpoint_id = EndpointId::from(index as u64);
+            if endpoint_id == FRAMING_EP_ID || endpoint_id == INVALID_EP_ID {
+                continue;
+            }
+            let mut transaction_count =
+                capture.endpoint_traffic(endpoint_id)?.transaction_ids.len();
+            if !complete {
+                transaction_count = transaction_count.saturating_sub(1);
+            }
+            for ep_transaction_index in self.checked[index]..transaction_count {
+                let transaction_id = capture
+                    .endpoint_traffic(endpoint_id)?
+                    .transaction_ids
+                    .get(EndpointTransactionId::from(ep_transaction_index))?;
+                if let Some(event) =
+                    check_transaction(capture, endpoint_id, transaction_id)?
+                {
+                    found.push(event);
+                }
+            }
+            self.checked[index] = self.checked[index].max(transaction_count);
+        }
+        if found.is_empty() {
+            return Ok(false);
+        }
+        for event in found {
+            let position = self.events.partition_point(|other|
+                other.transaction_id <= event.transaction_id);
+            self.events.insert(position, event);
+        }
+        self.correlate(capture)?;
+        Ok(true)
+    }
+
+    /// Rebuild the list of halts from the events seen.
+    ///
+    /// Each halt begins with a STALL on an endpoint that is not already
+    /// halted, and ends with the next request to clear that endpoint.
+    /// Requests clearing an endpoint that is not halted are ignored.
+    fn correlate(&mut self, capture: &mut CaptureReader)
+        -> Result<(), Error>
+    {
+        let mut halts: Vec<Halt> = Vec::new();
+        let mut open: Vec<((DeviceId, u8), usize)> = Vec::new();
+        for event in &self.events {
+            let position = open
+                .iter()
+                .position(|(target, _)| *target == event.target);
+            match (&event.event, position) {
+                (Event::Stall(_), Some(position)) => {
+                    let halt = &mut halts[open[position].1];
+                    halt.stalls += 1;
+                    halt.last_stall = event.transaction_id;
+                },
+                (Event::Stall(endpoint_id), None) => {
+                    open.push((event.target, halts.len()));
+                    halts.push(Halt {
+                        endpoint_id: *endpoint_id,
+                        name: capture.endpoints.get(*endpoint_id)?.to_string(),
+                        first_stall: event.transaction_id,
+                        last_stall: event.transaction_id,
+                        stalls: 1,
+                        start: event.time,
+                        cleared: None,
+                    });
+                },
+                (Event::Clear, Some(position)) => {
+                    let (_, index) = open.remove(position);
+                    halts[index].cleared =
+                        Some((event.transaction_id, event.time));
+                },
+                (Event::Clear, None) => {},
+            }
+        }
+        self.halts = halts;
+        Ok(())
+    }
+
+    /// The halts of an endpoint overlapping a window of time.
+    ///
+    /// A halt which was not cleared lasts until the end of the window.
+    pub fn in_window(&self,
+                     endpoint_id: EndpointId,
+                     start: Timestamp,
+                     end: Timestamp)
+        -> impl Iterator<Item=&Halt>
+    {
+        self.halts.iter().filter(move |halt|
+            halt.endpoint_id == endpoint_id &&
+            halt.start <= end &&
+            halt.cleared.map_or(true, |(_, cleared)| cleared >= start))
+    }
+}
+
+/// Check whether a transaction starts or clears a halt.
+fn check_transaction(capture: &mut CaptureReader,
+                     endpoint_id: EndpointId,
+                     transaction_id: TransactionId)
+    -> Result<Option<HaltEvent>, Error>
+{
+    let range = capture.transaction_index.target_range(
+        transaction_id, capture.packet_index.len())?;
+    if range.start == range.end {
+        return Ok(None);
+    }
+    let endpoint = capture.endpoints.get(endpoint_id)?;
+    let device_id = endpoint.device_id();
+    let pid_at = |capture: &mut CaptureReader, packet_id: PacketId|
+        -> Result<PID, Error>
+    {
+        Ok(capture.packet(packet_id)?
+            .first()
+            .map_or(PID::Malformed, |byte| PID::from(*byte)))
+    };
+    let last = range.end - 1;
+    let event = if endpoint.number() != CONTROL_EP_NUM {
+        // STALLs on a control endpoint only reject a request, and do not
+        // halt the endpoint.
+        if pid_at(capture, last)? != PID::STALL {
+            return Ok(None);
+        }
+        HaltEvent {
+            transaction_id,
+            time: capture.packet_time(range.start)?,
+            target: (device_id, endpoint.address().0),
+            event: Event::Stall(endpoint_id),
+        }
+    } else {
+        if range.end.value - range.start.value != 3 ||
+            pid_at(capture, range.start)? != PID::SETUP ||
+            pid_at(capture, last)? != PID::ACK
+        {
+            return Ok(None);
+        }
+        let data = capture.packet(range.start + 1)?;
+        if data.len() != 11 || PID::from(data[0]) != PID::DATA0 {
+            return Ok(None);
+        }
+        let fields = SetupFields::from_data_packet(&data);
+        let type_fields = fields.type_fields;
+        let is_clear_halt =
+            matches!(type_fields.request_type(), RequestType::Standard) &&
+            matches!(type_fields.recipient(), Recipient::Endpoint) &&
+            fields.request == StandardRequest::ClearFeature as u8 &&
+            fields.value == StandardFeature::EndpointHalt as u16;
+        if !is_clear_halt {
+            return Ok(None);
+        }
+        HaltEvent {
+            transaction_id,
+            time: capture.packet_time(range.start)?,
+            target: (device_id, fields.index as u8),
+            event: Event::Clear,
+        }
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    fn decode(text: &str) -> CaptureReader {
+        let scenario = Scenario::parse("test", text).unwrap();
+        let (writer, reader) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        reader
+    }
+
+    #[test]
+    fn test_halts() {
+        let mut capture = decode("
+            device 1
+            endpoint 1 1 in bulk 512
+            endpoint 1 2 out bulk 512
+            enumerate 1
+            out 1 2 01:02
+            stall 1 2 out
+            stall 1 2 out
+            in 1 1 03:04
+            control 1 0x02 0x01 0 0x01
+            control 1 0x02 0x01 0 0x02
+            out 1 2 01:02
+            stall 1 1 in
+        ");
+        let halts = Halts::find(&mut capture).unwrap();
+        assert_eq!(halts.halts.len(), 2);
+
+        // The clear for endpoint 0x01 came before it halted, so only that
+        // for endpoint 0x02 ends a halt.
+        let out = &halts.halts[0];
+        assert_eq!(out.name, "1.2 OUT");
+        assert_eq!(out.stalls, 2);
+        assert!(out.first_stall < out.last_stall);
+        let (cleared_by, end) = out.cleared.unwrap();
+        assert!(cleared_by > out.last_stall);
+        assert!(end > out.start);
+        assert!(out.description().starts_with("1.2 OUT halted for "));
+        assert!(out.description().ends_with(
+            ", 2 STALLs, cleared by CLEAR_FEATURE"));
+
+        let halted_in = &halts.halts[1];
+        assert_eq!(halted_in.name, "1.1 IN");
+        assert!(halted_in.cleared.is_none());
+        assert_eq!(halted_in.description(),
+                   "1.1 IN halted, 1 STALL, not cleared");
+
+        // Only halts overlapping a window are found in it.
+        let in_window = |start, end| halts
+            .in_window(out.endpoint_id, start, end)
+            .count();
+        assert_eq!(in_window(out.start, end), 1);
+        assert_eq!(in_window(0, out.start - 1), 0);
+        assert_eq!(in_window(end + 1, u64::MAX), 0);
+        assert_eq!(halts.in_window(halted_in.endpoint_id, u64::MAX - 1,
+                                   u64::MAX).count(), 1);
+    }
+}
@@ -0,0 +1,431 @@
+//! Translation of user-visible messages, from catalogs in the PO format
+//! used by GNU gettext.
+//!
+//! Messages are looked up by their English text. Until a catalog is set
+//! with [`set_catalog`], or for messages missing from it, the English text
+//! is used, so nothing changes unless a translation is chosen.
+//!
+//! Messages with arguments use `{}` placeholders, which are filled in
+//! order, or `{0}`, `{1}` and so on, which let a translation reorder them.
+//! Literal braces are written `{{` and `}}`.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Error, bail};
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+
+/// Catalogs built into Packetry, by language code.
+pub const BUILT_IN: [(&str, &str); 1] = [
+    ("de", include_str!("../po/de.po")),
+];
+
+/// Environment variables choosing the language of messages, in the order
+/// gettext checks them.
+const LANGUAGE_VARIABLES: [&str; 4] =
+    ["LANGUAGE", "LC_ALL", "LC_MESSAGES", "LANG"];
+
+static CATALOG: Lazy<ArcSwap<Catalog>> =
+    Lazy::new(|| ArcSwap::from_pointee(Catalog::default()));
+
+/// Translations of messages into one language.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    messages: BTreeMap<String, String>,
+}
+
+/// The part of a catalog entry that a line of text continues.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Field {
+    Context,
+    Id,
+    Plural,
+    Text,
+}
+
+/// An entry of a catalog, as it is parsed.
+#[derive(Default)]
+struct Entry {
+    context: Option<String>,
+    id: Option<String>,
+    plural: Option<String>,
+    text: Option<String>,
+    fuzzy: bool,
+}
+
+impl Catalog {
+    /// Parse a catalog in the PO format.
+    ///
+    /// Entries marked as fuzzy, with a context, or with plural forms are
+    /// skipped, as are untranslated entries and the header.
+    pub fn parse(text: &str) -> Result<Catalog, Error> {
+        let mut catalog = Catalog::default();
+        let mut entry = Entry::default();
+        let mut field = None;
+        for (number, line) in text.lines().enumerate() {
+            let row = number + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                catalog.add(std::mem::take(&mut entry));
+                field = None;
+            } else if let Some(comment) = line.strip_prefix('#') {
+                // Comments start the next entry.
+                if entry.text.is_some() {
+                    catalog.add(std::mem::take(&mut entry));
+                    field = None;
+                }
+                if let Some(flags) = comment.strip_prefix(',') {
+                    entry.fuzzy |= flags
+                        .split(',')
+                        .any(|flag| flag.trim() == "fuzzy");
+                }
+            } else if line.starts_with('"') {
+                let string = unquote(line, row)?;
+                let target = match field {
+                    Some(Field::Context) => &mut entry.context,
+                    Some(Field::Id) => &mut entry.id,
+                    Some(Field::Plural) => &mut entry.plural,
+                    Some(Field::Text) => &mut entry.text,
+                    None => bail!(
+                        "String without a keyword on line {row} of \
+                         translation catalog"),
+                };
+                target.get_or_insert_with(String::new).push_str(&string);
+            } else {
+                let (keyword, rest) = line
+                    .split_once(char::is_whitespace)
+                    .with_context(|| format!(
+                        "Missing string on line {row} of translation \
+                         catalog"))?;
+                let next = match keyword {
+                    "msgctxt" => Field::Context,
+                    "msgid" => Field::Id,
+                    "msgid_plural" => Field::Plural,
+                    "msgstr" => Field::Text,
+                    plural if plural.starts_with("msgstr[") => Field::Text,
+                    _ => bail!(
+                        "Unknown keyword '{keyword}' on line {row} of \
+                         translation catalog"),
+                };
+                // A new message starts the next entry, even without a
+                // blank line between them.
+                if matches!(next, Field::Context | Field::Id) &&
+                    entry.text.is_some()
+                {
+                    catalog.add(std::mem::take(&mut entry));
+                }
+                let string = unquote(rest.trim(), row)?;
+                let target = match next {
+                    Field::Context => &mut entry.context,
+                    Field::Id => &mut entry.id,
+                    Field::Plural => &mut entry.plural,
+                    Field::Text => &mut entry.text,
+                };
+                target.get_or_insert_with(String::new).push_str(&string);
+                field = Some(next);
+            }
+        }
+        catalog.add(entry);
+        Ok(catalog)
+    }
+
+    /// Read a catalog from a file.
+    pub fn load(path: &Path) -> Result<Catalog, Error> {
+        let text = read_to_string(path).with_context(|| format!(
+            "Failed to read translations from {}", path.display()))?;
+        Catalog::parse(&text).with_context(|| format!(
+            "Failed to parse translations from {}", path.display()))
+    }
+
+    fn add(&mut self, entry: Entry) {
+        if entry.fuzzy || entry.context.is_some() || entry.plural.is_some() {
+            return;
+        }
+        if let (Some(id), Some(text)) = (entry.id, entry.text) {
+            if !id.is_empty() && !text.is_empty() {
+                self.messages.insert(id, text);
+            }
+        }
+    }
+
+    /// Number of messages translated.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// The messages translated, with their translations.
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.messages
+            .iter()
+            .map(|(id, text)| (id.as_str(), text.as_str()))
+    }
+
+    /// The translation of a message, or the message itself if it has none.
+    pub fn translate<'a>(&'a self, message: &'a str) -> &'a str {
+        self.messages.get(message).map_or(message, String::as_str)
+    }
+}
+
+/// Parse a quoted string, with C-style escapes.
+fn unquote(quoted: &str, row: usize) -> Result<String, Error> {
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .with_context(|| format!(
+            "Invalid string on line {row} of translation catalog"))?;
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        string.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            _ => bail!(
+                "Invalid escape on line {row} of translation catalog"),
+        });
+    }
+    Ok(string)
+}
+
+/// Fill the placeholders of a message with arguments.
+///
+/// Placeholders without an argument are left as they are.
+pub fn fill(message: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut next = 0;
+    let mut chars = message.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            },
+            '{' => {
+                let mut spec = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    spec.push(c);
+                }
+                let index = if spec.is_empty() {
+                    next += 1;
+                    Some(next - 1)
+                } else {
+                    spec.parse::<usize>().ok()
+                };
+                match index.and_then(|index| args.get(index)) {
+                    Some(arg) if closed => {
+                        let _ = write!(result, "{arg}");
+                    },
+                    _ => {
+                        result.push('{');
+                        result.push_str(&spec);
+                        if closed {
+                            result.push('}');
+                        }
+                    }
+                }
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Set the catalog used to translate messages.
+pub fn set_catalog(catalog: Catalog) {
+    CATALOG.store(Arc::new(catalog));
+}
+
+/// Translate a message, using the catalog set.
+pub fn tr(message: &str) -> String {
+    CATALOG.load().translate(message).to_string()
+}
+
+/// Translate a message, then fill its placeholders with arguments.
+pub fn trf(message: &str, args: &[&dyn Display]) -> String {
+    fill(CATALOG.load().translate(message), args)
+}
+
+/// Languages chosen by the environment, most preferred first, each
+/// followed by the language without its region, e.g. `de_AT` then `de`.
+///
+/// As in gettext, `LANGUAGE` may list several languages separated by
+/// colons, and the C and POSIX locales choose English.
+pub fn environment_languages() -> Vec<String> {
+    let value = LANGUAGE_VARIABLES
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty());
+    match value {
+        Some(value) => languages(&value),
+        None => Vec::new(),
+    }
+}
+
+fn languages(value: &str) -> Vec<String> {
+    let mut languages = Vec::new();
+    for locale in value.split(':') {
+        // Drop any encoding and modifier, as in `de_DE.UTF-8@euro`.
+        let name = locale
+            .split(['.', '@'])
+            .next()
+            .unwrap_or("");
+        if name.is_empty() || name == "C" || name == "POSIX" {
+            continue;
+        }
+        let mut add = |name: &str| {
+            if !languages.iter().any(|language| language == name) {
+                languages.push(name.to_string());
+            }
+        };
+        add(name);
+        if let Some((language, _)) = name.split_once('_') {
+            add(language);
+        }
+    }
+    languages
+}
+
+/// Translate messages into the language chosen by the environment.
+///
+/// For each language, a catalog named for it in the override directory,
+/// such as `de.po`, takes precedence over one built in, so that teams can
+/// add or correct translations. Returns the language used, if any.
+pub fn use_environment_language(override_dir: &Path)
+    -> Result<Option<String>, Error>
+{
+    for language in environment_languages() {
+        let path = override_dir.join(format!("{language}.po"));
+        let catalog = if path.exists() {
+            Catalog::load(&path)?
+        } else if let Some((_, text)) = BUILT_IN
+            .iter()
+            .find(|(name, _)| *name == language)
+        {
+            Catalog::parse(text)?
+        } else {
+            continue;
+        };
+        set_catalog(catalog);
+        return Ok(Some(language));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let catalog = Catalog::parse(r#"
+# Header
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+#: src/ui.rs
+msgid "Open"
+msgstr "Öffnen"
+
+msgid ""
+"Polling {} times "
+"on endpoint {}"
+msgstr "{1}: {0}-mal abgefragt"
+msgid "Say \"hi\"\n"
+msgstr "Sag \"hallo\"\n"
+
+#, fuzzy
+msgid "Save"
+msgstr "Speichern"
+
+msgctxt "menu"
+msgid "View"
+msgstr "Ansicht"
+
+msgid "Untranslated"
+msgstr ""
+
+msgid "{} packet"
+msgid_plural "{} packets"
+msgstr[0] "{} Paket"
+msgstr[1] "{} Pakete"
+"#).unwrap();
+        assert_eq!(catalog.len(), 3);
+        assert_eq!(catalog.translate("Open"), "Öffnen");
+        assert_eq!(catalog.translate("Say \"hi\"\n"), "Sag \"hallo\"\n");
+        assert_eq!(catalog.translate("Save"), "Save");
+        assert_eq!(catalog.translate("View"), "View");
+        assert_eq!(catalog.translate("Untranslated"), "Untranslated");
+        let polling = catalog.translate("Polling {} times on endpoint {}");
+        assert_eq!(fill(polling, &[&3, &"1.1 IN"]), "1.1 IN: 3-mal abgefragt");
+
+        assert!(Catalog::parse("msgid \"Open\nmsgstr \"\"").is_err());
+        assert!(Catalog::parse("msgid \"\\q\"").is_err());
+        assert!(Catalog::parse("\"stray\"").is_err());
+        assert!(Catalog::parse("msgfoo \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill("{} of {}", &[&1, &2]), "1 of 2");
+        assert_eq!(fill("{1} of {0}", &[&1, &2]), "2 of 1");
+        assert_eq!(fill("{{}} {}", &[&"x"]), "{} x");
+        assert_eq!(fill("{} {} {5} {", &[&1]), "1 {} {5} {");
+    }
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(languages("de_DE.UTF-8"), vec!["de_DE", "de"]);
+        assert_eq!(languages("fr_CA:de@euro"), vec!["fr_CA", "fr", "de"]);
+        assert!(languages("C.UTF-8").is_empty());
+        assert!(languages("POSIX").is_empty());
+    }
+
+    #[test]
+    fn test_built_in() {
+        // Each translation fills the same arguments as its message.
+        let markers = ['①', '②', '③', '④', '⑤', '⑥'];
+        let placeholders = |text: &str| {
+            let args: Vec<&dyn Display> = markers
+                .iter()
+                .map(|marker| marker as &dyn Display)
+                .collect();
+            let mut filled: Vec<char> = fill(text, &args)
+                .chars()
+                .filter(|c| markers.contains(c))
+                .collect();
+            filled.sort_unstable();
+            filled
+        };
+        for (language, text) in BUILT_IN {
+            let catalog = Catalog::parse(text).unwrap();
+            assert!(!catalog.is_empty(), "{language}");
+            for (message, translation) in catalog.iter() {
+                assert_eq!(placeholders(message), placeholders(translation),
+                           "{language}: {message}");
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+//! Typed identifiers for the items stored in a capture.
+
 use std::fmt::{Debug, Display};
 use std::marker::PhantomData;
 use std::mem::size_of;
@@ -40,6 +42,8 @@ impl<T> Debug for Id<T> {
     }
 }
 
+// Ranges already have an is_empty method of their own.
+#[allow(clippy::len_without_is_empty)]
 pub trait HasLength {
    fn len(&self) -> u64;
 }
@@ -1,4 +1,5 @@
 use std::cmp::min;
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::ops::Range;
 
@@ -63,6 +64,11 @@ where Position: From<u64>, Value: Into<u64>
         let position = Position::from(id.into());
         Ok(position)
     }
+
+    /// Append the contents of an index saved with `IndexReader::save`.
+    pub fn load(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+        self.data_writer.load(reader)
+    }
 }
 
 impl<Position, Value> IndexReader<Position, Value>
@@ -79,6 +85,11 @@ where Position: Copy + From<u64> + Into<u64>,
         self.data_reader.size()
     }
 
+    /// Write the length and contents of the index.
+    pub fn save(&mut self, writer: &mut dyn Write) -> Result<(), Error> {
+        self.data_reader.save(writer)
+    }
+
     /// Get a single value from the index, by position.
     pub fn get(&mut self, position: Position) -> Result<Value, Error> {
         let id = Id::<u64>::from(position.into());
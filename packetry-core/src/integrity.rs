@@ -0,0 +1,217 @@
+//! Integrity checks for saved captures.
+//!
+//! When a capture is saved in Packetry's own format, a SHA-256 hash of its
+//! packets is saved with it, and checked when the file is loaded, so that
+//! any change to the packets since they were saved is detected. If a
+//! signing key is given, an HMAC-SHA256 signature made with the key is
+//! saved too, which can only be checked, or made again for altered
+//! packets, by someone holding the same key.
+//!
+//! Each packet is hashed as its timestamp, as 8 bytes, and its length, as
+//! 4 bytes, both little-endian, followed by its bytes as stored.
+
+use std::env::var_os;
+use std::fs::read;
+use std::path::Path;
+
+use anyhow::{Context, Error, bail};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::capture::{CaptureReader, PacketId};
+
+/// Environment variable naming a file holding the key used to sign saved
+/// captures and check their signatures.
+pub const KEY_VARIABLE: &str = "PACKETRY_SIGNING_KEY";
+
+/// Size of a SHA-256 hash, in bytes.
+pub const HASH_SIZE: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Format a hash or signature as hex digits.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The hash of a capture's packets, and their signature if signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Integrity {
+    pub hash: [u8; HASH_SIZE],
+    pub signature: Option<[u8; HASH_SIZE]>,
+}
+
+/// The result of checking a capture's integrity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// The hash matched, and the capture was not signed.
+    Hashed,
+    /// The hash and signature both matched.
+    Signed,
+    /// The hash matched, but the signature could not be checked as no
+    /// key was given.
+    SignatureUnchecked,
+    /// The hash matched, but a key was given and the capture was not
+    /// signed, so it may have been altered and saved again without one.
+    Unsigned,
+}
+
+impl Verification {
+    pub fn description(&self) -> &'static str {
+        use Verification::*;
+        match self {
+            Hashed => "Packet hash verified",
+            Signed => "Packet hash and signature verified",
+            SignatureUnchecked =>
+                "Packet hash verified, signature not checked without a key",
+            Unsigned =>
+                "Packet hash verified, but the capture is not signed",
+        }
+    }
+}
+
+impl Integrity {
+    /// Hash the packets of a capture, and sign them if a key is given.
+    pub fn compute(capture: &mut CaptureReader, key: Option<&[u8]>)
+        -> Result<Integrity, Error>
+    {
+        let (hash, signature) = digest(capture, key)?;
+        Ok(Integrity {
+            hash: hash.finalize().into(),
+            signature: signature.map(|mac| mac.finalize().into_bytes().into()),
+        })
+    }
+
+    /// Check that the packets of a capture match this hash, and signature
+    /// if any and a key is given.
+    pub fn verify(&self, capture: &mut CaptureReader, key: Option<&[u8]>)
+        -> Result<Verification, Error>
+    {
+        let (hash, mac) = digest(capture, key)?;
+        if hash.finalize()[..] != self.hash {
+            bail!("The packets do not match the hash saved with the \
+                   capture, so have been changed since it was saved")
+        }
+        match (&self.signature, mac) {
+            (None, None) => Ok(Verification::Hashed),
+            (None, Some(_)) => Ok(Verification::Unsigned),
+            (Some(_), None) => Ok(Verification::SignatureUnchecked),
+            (Some(signature), Some(mac)) => {
+                if mac.verify_slice(signature).is_err() {
+                    bail!("The capture's signature was not made with the \
+                           signing key given")
+                }
+                Ok(Verification::Signed)
+            },
+        }
+    }
+}
+
+/// Hash the packets of a capture, and start a signature of them if a key
+/// is given.
+fn digest(capture: &mut CaptureReader, key: Option<&[u8]>)
+    -> Result<(Sha256, Option<HmacSha256>), Error>
+{
+    let mut hash = Sha256::new();
+    let mut signature = key
+        .map(HmacSha256::new_from_slice)
+        .transpose()
+        .context("Invalid signing key")?;
+    for index in 0..capture.packet_index.len() {
+        let packet_id = PacketId::from(index);
+        let packet = capture.packet(packet_id)?;
+        let timestamp = capture.packet_time(packet_id)?;
+        let length: u32 = packet.len().try_into()?;
+        for part in [
+            &timestamp.to_le_bytes()[..],
+            &length.to_le_bytes()[..],
+            &packet[..],
+        ] {
+            hash.update(part);
+            if let Some(signature) = signature.as_mut() {
+                signature.update(part);
+            }
+        }
+    }
+    Ok((hash, signature))
+}
+
+/// Read the signing key from the file named by [`KEY_VARIABLE`], if set.
+pub fn signing_key() -> Result<Option<Vec<u8>>, Error> {
+    match var_os(KEY_VARIABLE) {
+        None => Ok(None),
+        Some(path) => {
+            let path = Path::new(&path);
+            let key = read(path).with_context(|| format!(
+                "Failed to read signing key from {}", path.display()))?;
+            if key.is_empty() {
+                bail!("Signing key file {} is empty", path.display())
+            }
+            Ok(Some(key))
+        }
+    }
+}
+
+/// Check a loaded capture against the hash saved with it, if any, using
+/// the signing key if set.
+pub fn verify_capture(capture: &mut CaptureReader)
+    -> Result<Option<Verification>, Error>
+{
+    match capture.shared.integrity.load_full() {
+        None => Ok(None),
+        Some(integrity) => {
+            let key = signing_key()?;
+            integrity.verify(capture, key.as_deref()).map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_signature() {
+        // From RFC 4231, test case 2.
+        let mut mac = HmacSha256::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"what do ya want for nothing?");
+        assert_eq!(hex(&mac.finalize().into_bytes()),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_integrity() {
+        let capture = |packets: &[(&[u8], u64)]| {
+            let (writer, reader) = create_capture().unwrap();
+            let mut decoder = Decoder::new(writer).unwrap();
+            for (packet, timestamp) in packets {
+                decoder.handle_raw_packet(packet, *timestamp).unwrap();
+            }
+            decoder.finish().unwrap();
+            reader
+        };
+        let sof: &[u8] = &[0xa5, 0xde, 0x1e];
+        let mut original = capture(&[(sof, 100), (sof, 200)]);
+        let key = b"secret";
+        let integrity = Integrity::compute(&mut original, Some(key)).unwrap();
+        assert_eq!(integrity.verify(&mut original, Some(key)).unwrap(),
+                   Verification::Signed);
+        assert_eq!(integrity.verify(&mut original, None).unwrap(),
+                   Verification::SignatureUnchecked);
+        assert!(integrity.verify(&mut original, Some(b"other")).is_err());
+
+        // Changing a timestamp changes the hash.
+        let mut altered = capture(&[(sof, 100), (sof, 201)]);
+        assert!(integrity.verify(&mut altered, None).is_err());
+        let unsigned = Integrity::compute(&mut altered, None).unwrap();
+        assert!(unsigned.signature.is_none());
+        assert_eq!(unsigned.verify(&mut altered, None).unwrap(),
+                   Verification::Hashed);
+        // With a key given, a capture that is not signed is reported as
+        // such, rather than as verified.
+        assert_eq!(unsigned.verify(&mut altered, Some(key)).unwrap(),
+                   Verification::Unsigned);
+    }
+}
@@ -0,0 +1,87 @@
+//! The decoding engine of Packetry, without its GUI.
+//!
+//! This crate captures USB traffic from a Cynthion analyzer, decodes it
+//! into transactions, transfers and device descriptors, and stores the
+//! result in a capture which can be read while it is still being written.
+//! It also loads and saves captures in pcap, pcapng, USBPcap and
+//! Packetry's own native format.
+//!
+//! A capture is created with [`capture::create_capture`], which returns a
+//! writer and a reader. Packets are passed through a [`decoder::Decoder`]
+//! that owns the writer, and the decoded traffic is then read through
+//! the [`capture::ItemSource`] trait of the reader:
+//!
+//! ```no_run
+//! use packetry_core::capture::{create_capture, ItemSource, TrafficItem};
+//! use packetry_core::decoder::Decoder;
+//!
+//! # fn main() -> Result<(), anyhow::Error> {
+//! let (writer, mut reader) = create_capture()?;
+//! let mut decoder = Decoder::new(writer)?;
+//! # let packets: Vec<(Vec<u8>, u64)> = Vec::new();
+//! for (packet, timestamp) in packets {
+//!     decoder.handle_raw_packet(&packet, timestamp)?;
+//! }
+//! decoder.finish()?;
+//! let (_, count) = ItemSource::<TrafficItem>::item_children(
+//!     &mut reader, None)?;
+//! for index in 0..count {
+//!     let item = ItemSource::<TrafficItem>::item(&mut reader, None, index)?;
+//!     println!("{}", reader.summary(&item)?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Capture files of any supported format are loaded and decoded with
+//! [`loader::load`], and the same text as `packetry-cli decode` prints
+//! is produced for each item by [`export::write_text_item`].
+
+#[macro_use]
+extern crate bitfield;
+
+pub mod annotations;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod backend;
+pub mod capture;
+pub mod clock;
+pub mod codegen;
+pub mod compliance;
+mod compact_index;
+mod crc;
+mod data_stream;
+pub mod decoder;
+pub mod errors;
+pub mod export;
+pub mod filter;
+pub mod halts;
+pub mod i18n;
+pub mod id;
+mod index_stream;
+pub mod integrity;
+pub mod loader;
+pub mod logic;
+pub mod markers;
+pub mod metadata;
+pub mod native;
+pub mod otg;
+pub mod overrides;
+pub mod pcap;
+mod rcu;
+pub mod report;
+pub mod sanitize;
+pub mod schedule;
+pub mod speeds;
+mod stream;
+pub mod suspend;
+pub mod throughput;
+pub mod traffic_index;
+pub mod trigger;
+pub mod usb;
+pub mod usb_ids;
+pub mod usbpcap;
+pub mod util;
+mod vec_map;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use stream::set_storage_dir;
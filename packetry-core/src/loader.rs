@@ -0,0 +1,301 @@
+//! Loading capture files, in any of the supported formats.
+
+use std::fs::File;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::{Context, Error, bail};
+use pcap_file::DataLink;
+
+use crate::capture::{create_capture, CaptureReader, CaptureWriter, PacketId};
+use crate::clock::{ClockModel, PacketTimes};
+use crate::decoder::Decoder;
+use crate::integrity::{verify_capture, Verification};
+use crate::native;
+use crate::overrides::Overrides;
+use crate::pcap::{self, MergeReader};
+use crate::usbpcap::UsbPcapConverter;
+
+/// Load and decode capture files, merging them if there are several.
+///
+/// A Packetry capture fails to load if its packets do not match the hash
+/// or signature saved with it, or if a signing key is set and it was not
+/// signed.
+pub fn load(paths: &[PathBuf]) -> Result<CaptureReader, Error> {
+    load_with_overrides(paths, &Overrides::default())
+}
+
+/// Load and decode capture files, using the given overrides for devices
+/// whose descriptors are not seen.
+///
+/// Overrides have no effect on Packetry captures, which are already decoded.
+pub fn load_with_overrides(paths: &[PathBuf], overrides: &Overrides)
+    -> Result<CaptureReader, Error>
+{
+    load_with_clocks(paths, overrides, &[])
+}
+
+/// Load and decode capture files, correcting the timestamps of each file
+/// by the clock model at its position before merging them.
+///
+/// Files without a model are used as recorded.
+pub fn load_with_clocks(paths: &[PathBuf],
+                        overrides: &Overrides,
+                        clocks: &[ClockModel])
+    -> Result<CaptureReader, Error>
+{
+    let mut readers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let file = File::open(path).with_context(|| format!(
+            "Failed to open {}", path.display()))?;
+        readers.push(pcap::decompress(file)?);
+    }
+    let (writer, mut reader) = create_capture()?;
+    decode_readers(paths, readers, writer, &mut reader, overrides, clocks,
+                   |_| true)?;
+    Ok(reader)
+}
+
+/// Estimate the clock of each capture file relative to the first, from
+/// the packets seen in both.
+///
+/// Files with no packets in common with the first are left as recorded.
+pub fn estimate_clocks(paths: &[PathBuf]) -> Result<Vec<ClockModel>, Error> {
+    let mut times = Vec::with_capacity(paths.len());
+    for path in paths {
+        let file = File::open(path).with_context(|| format!(
+            "Failed to open {}", path.display()))?;
+        let mut pcap = MergeReader::new(vec![pcap::decompress(file)?])?;
+        let mut converter = match pcap.datalink(0) {
+            DataLink::USB_2_0 => None,
+            DataLink::USBPCAP => Some(UsbPcapConverter::new()),
+            other => bail!("Unsupported link type {other:?} in {}",
+                           path.display()),
+        };
+        let mut packet_times = PacketTimes::new();
+        while let Some(packet) = pcap.next_packet()? {
+            match converter.as_mut() {
+                None => packet_times.add(&packet.data, packet.timestamp),
+                Some(converter) =>
+                    for data in converter.packets(&packet.data)? {
+                        packet_times.add(&data, packet.timestamp);
+                    },
+            }
+        }
+        times.push(packet_times);
+    }
+    Ok(times
+        .iter()
+        .map(|packet_times| times
+            .first()
+            .and_then(|reference| packet_times.estimate(reference))
+            .unwrap_or_default())
+        .collect())
+}
+
+/// Load and decode captures from readers, one for each of the given paths.
+///
+/// The paths are only used to name the inputs.
+pub fn load_readers(paths: &[PathBuf],
+                    readers: Vec<Box<dyn BufRead + Send>>)
+    -> Result<CaptureReader, Error>
+{
+    let (writer, mut reader) = create_capture()?;
+    decode_readers(paths, readers, writer, &mut reader,
+                   &Overrides::default(), &[], |_| true)?;
+    Ok(reader)
+}
+
+/// Decode captures from readers into a new capture, given its writer and
+/// a reader for it.
+///
+/// Clocks and overrides are used as by [`load_with_clocks`]. The progress
+/// function is called before each packet is read, with the number of
+/// packets decoded so far, and decoding stops early if it returns false.
+///
+/// The writer is returned, with the result of checking a Packetry capture
+/// against the hash saved with it, if there is one.
+pub fn decode_readers<F>(paths: &[PathBuf],
+                         mut readers: Vec<Box<dyn BufRead + Send>>,
+                         mut writer: CaptureWriter,
+                         reader: &mut CaptureReader,
+                         overrides: &Overrides,
+                         clocks: &[ClockModel],
+                         mut progress: F)
+    -> Result<(CaptureWriter, Option<Verification>), Error>
+    where F: FnMut(u64) -> bool
+{
+    for (input, path) in readers.iter_mut().zip(paths) {
+        if native::is_native(input.fill_buf()?) {
+            if paths.len() > 1 {
+                bail!("{} is a Packetry capture, which cannot be merged",
+                      path.display());
+            }
+            native::load(&mut writer, input)?;
+            let verification = verify_capture(reader)?;
+            if verification == Some(Verification::Unsigned) {
+                bail!("{} is not signed, but a signing key was given",
+                      path.display());
+            }
+            return Ok((writer, verification));
+        }
+    }
+    let mut pcap = MergeReader::new(readers)?;
+    for (source, clock) in clocks.iter().enumerate() {
+        pcap.set_clock(source, *clock);
+    }
+    let mut converters = Vec::with_capacity(paths.len());
+    for (source, path) in paths.iter().enumerate() {
+        converters.push(match pcap.datalink(source) {
+            DataLink::USB_2_0 => None,
+            DataLink::USBPCAP => {
+                reader.shared.reconstructed.store(true, Ordering::Relaxed);
+                Some(UsbPcapConverter::new())
+            },
+            other => bail!("Unsupported link type {other:?} in {}",
+                           path.display()),
+        });
+    }
+    let merge = paths.len() > 1;
+    if merge {
+        let file_names = paths
+            .iter()
+            .map(|path| path
+                .file_name()
+                .map_or_else(
+                    || path.to_string_lossy().to_string(),
+                    |name| name.to_string_lossy().to_string()))
+            .collect();
+        reader.shared.sources.store(Arc::new(file_names));
+    }
+    let mut decoder = Decoder::new(writer)?;
+    decoder.set_overrides(overrides)?;
+    let mut count = 0;
+    while progress(count) {
+        let packet = match pcap.next_packet()? {
+            Some(packet) => packet,
+            None => break,
+        };
+        let data = match converters[packet.source].as_mut() {
+            None => vec![packet.data],
+            Some(converter) => converter.packets(&packet.data)?,
+        };
+        for data in data {
+            if merge {
+                decoder.handle_merged_packet(
+                    &data, packet.timestamp, packet.source as u16)?;
+            } else {
+                decoder.handle_raw_packet(&data, packet.timestamp)?;
+            }
+        }
+        count += 1;
+    }
+    Ok((decoder.finish()?, None))
+}
+
+/// Decode the packets of a capture again, into a new capture.
+///
+/// The packets already stored are used, so that the capture can be analyzed
+/// again without reading its files. Bookmarks and comments are not kept, as
+/// they refer to the items found by the previous decode, but the details
+/// of the capture as a whole are.
+///
+/// Any overrides are used for devices whose descriptors are not seen.
+///
+/// The progress function is called with the number of packets decoded so
+/// far, and decoding stops early if it returns false.
+pub fn redecode<F>(capture: &mut CaptureReader,
+                   writer: CaptureWriter,
+                   overrides: &Overrides,
+                   mut progress: F)
+    -> Result<CaptureWriter, Error>
+    where F: FnMut(u64) -> bool
+{
+    writer.shared.sources.store(capture.shared.sources.load_full());
+    writer.shared.metadata.store(capture.shared.metadata.load_full());
+    writer.shared.reconstructed.store(
+        capture.shared.reconstructed.load(Ordering::Relaxed),
+        Ordering::Relaxed);
+    let mut decoder = Decoder::new(writer)?;
+    decoder.set_overrides(overrides)?;
+    let packet_count = capture.packet_index.len();
+    for index in 0..packet_count {
+        decoder.handle_stored_packet(capture, PacketId::from(index))?;
+        if !progress(index + 1) {
+            break;
+        }
+    }
+    decoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::write_text_item;
+    use crate::capture::{ItemSource, TrafficItem};
+
+    fn decode_text(capture: &mut CaptureReader) -> String {
+        let mut output = Vec::new();
+        for index in 0..capture.item_index.len() {
+            let item: TrafficItem = capture.item(None, index).unwrap();
+            write_text_item(capture, &item, None, &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_redecode() {
+        let paths = [
+            PathBuf::from("../tests/split-poll/capture.pcap"),
+            PathBuf::from("../tests/mouse/capture.pcap"),
+        ];
+        let mut capture = load(&paths).unwrap();
+        let (writer, mut redecoded) = create_capture().unwrap();
+        redecode(&mut capture, writer, &Overrides::default(), |_| true).unwrap();
+        assert_eq!(redecoded.packet_index.len(), capture.packet_index.len());
+        assert_eq!(decode_text(&mut redecoded), decode_text(&mut capture));
+        let last = PacketId::from(capture.packet_index.len() - 1);
+        assert_eq!(redecoded.packet_source(last).unwrap(),
+                   capture.packet_source(last).unwrap());
+        assert!(redecoded.packet_source(last).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_estimate_clocks() {
+        use pcap_file::pcap::{PcapReader, PcapWriter};
+        // Copy a capture as if made by a clock 3 ms behind, which runs
+        // 50 ppm slow.
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let dir = tempfile::tempdir().unwrap();
+        let copy = dir.path().join("copy.pcap");
+        let mut reader = PcapReader::new(File::open(&path).unwrap()).unwrap();
+        let header = reader.header();
+        let mut writer = PcapWriter::with_header(
+            File::create(&copy).unwrap(), pcap::header()).unwrap();
+        let mut first = None;
+        while let Some(packet) = reader.next_raw_packet() {
+            let packet = packet.unwrap();
+            let time = pcap::timestamp(&header, &packet);
+            let elapsed = time - *first.get_or_insert(time);
+            let time = time - 3_000_000 - elapsed / 20_000;
+            writer.write_raw_packet(
+                &pcap::record(packet.data, time).unwrap()).unwrap();
+        }
+        drop(writer);
+        let paths = [path, copy];
+        let clocks = estimate_clocks(&paths).unwrap();
+        assert_eq!(clocks[0], ClockModel::default());
+        assert!((clocks[1].offset - 3_000_000).abs() <= 2);
+        assert!((clocks[1].drift - 50.0).abs() < 0.5);
+        let mut capture = load_with_clocks(
+            &paths, &Overrides::default(), &clocks).unwrap();
+        // Each packet now appears at the same time from both files.
+        for index in (0..capture.packet_index.len()).step_by(2) {
+            let times = [index, index + 1].map(|index|
+                capture.packet_time(PacketId::from(index)).unwrap());
+            assert!(times[1] - times[0] <= 2);
+        }
+    }
+}
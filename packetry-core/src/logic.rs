@@ -0,0 +1,363 @@
+//! Digital channels recorded by a logic analyzer, imported from a VCD
+//! file or a Saleae Logic CSV export, to be shown alongside USB traffic.
+//!
+//! Times in a trace are relative to its own time zero, in nanoseconds, and
+//! may be negative, as in Saleae exports relative to a trigger. The trace
+//! is placed on the capture's time axis by choosing the capture time at
+//! which its time zero falls.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{Context, Error, bail};
+
+use crate::capture::Timestamp;
+
+/// A digital channel: its initial level, and each change of level after.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub name: String,
+    pub initial: bool,
+    /// Times of each change, with the level changed to, in order of time.
+    pub edges: Vec<(i64, bool)>,
+}
+
+impl Channel {
+    fn new(name: String) -> Channel {
+        Channel { name, initial: false, edges: Vec::new() }
+    }
+
+    /// Record the level at a time, if it differs from the last.
+    fn set(&mut self, time: i64, level: bool) {
+        if self.edges.last().map(|edge| edge.1) != Some(level) {
+            self.edges.push((time, level));
+        }
+    }
+
+    /// Take the first level recorded as the initial level.
+    fn finish(mut self) -> Channel {
+        if !self.edges.is_empty() {
+            self.initial = self.edges.remove(0).1;
+        }
+        self
+    }
+
+    /// The level at a time in the trace.
+    pub fn level_at(&self, time: i64) -> bool {
+        let index = self.edges.partition_point(|edge| edge.0 <= time);
+        match index {
+            0 => self.initial,
+            _ => self.edges[index - 1].1,
+        }
+    }
+}
+
+/// The channels of a logic analyzer trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogicTrace {
+    /// Name of the file the trace was read from.
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+/// A channel's levels within a window of capture time.
+#[derive(Clone, Debug)]
+pub struct ChannelWindow {
+    pub name: String,
+    /// The level at the start of the window.
+    pub initial: bool,
+    /// Changes of level within the window, in capture time.
+    pub edges: Vec<(Timestamp, bool)>,
+}
+
+impl LogicTrace {
+    /// Read a trace from a file, as VCD if its name ends in `.vcd`, or as
+    /// a Saleae Logic CSV export otherwise.
+    pub fn load(path: &Path) -> Result<LogicTrace, Error> {
+        let text = read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let is_vcd = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("vcd"));
+        let mut trace = if is_vcd {
+            parse_vcd(&text)?
+        } else {
+            parse_saleae_csv(&text)?
+        };
+        trace.name = path
+            .file_name()
+            .map_or_else(
+                || path.to_string_lossy().to_string(),
+                |name| name.to_string_lossy().to_string());
+        Ok(trace)
+    }
+
+    /// Times of the first and last edges on any channel.
+    pub fn span(&self) -> Option<(i64, i64)> {
+        let times = self.channels
+            .iter()
+            .flat_map(|channel| channel.edges.iter().map(|edge| edge.0));
+        times.fold(None, |span, time| match span {
+            None => Some((time, time)),
+            Some((first, last)) => Some((first.min(time), last.max(time))),
+        })
+    }
+
+    /// Each channel's levels within a window of capture time, with the
+    /// trace's time zero placed at `zero`, and at most `limit` edges per
+    /// channel.
+    pub fn window(&self,
+                  zero: Timestamp,
+                  start: Timestamp,
+                  end: Timestamp,
+                  limit: usize)
+        -> Vec<ChannelWindow>
+    {
+        let relative = |time: Timestamp| -> i64 {
+            (time as i128 - zero as i128)
+                .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+        };
+        let absolute = |time: i64| -> Timestamp {
+            (zero as i128 + time as i128).max(0) as Timestamp
+        };
+        let (start_rel, end_rel) = (relative(start), relative(end));
+        self.channels
+            .iter()
+            .map(|channel| {
+                let first = channel.edges
+                    .partition_point(|edge| edge.0 <= start_rel);
+                let edges = channel.edges[first..]
+                    .iter()
+                    .take_while(|edge| edge.0 <= end_rel)
+                    .take(limit)
+                    .map(|&(time, level)| (absolute(time), level))
+                    .collect();
+                ChannelWindow {
+                    name: channel.name.clone(),
+                    initial: channel.level_at(start_rel),
+                    edges,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse a Value Change Dump file.
+///
+/// Only single-bit variables are read. Unknown and high impedance values
+/// are read as low.
+pub fn parse_vcd(text: &str) -> Result<LogicTrace, Error> {
+    let mut tokens = text.split_whitespace();
+    let mut scale: i64 = 1;
+    let mut channels: Vec<Channel> = Vec::new();
+    let mut ids: BTreeMap<String, usize> = BTreeMap::new();
+    let mut time: i64 = 0;
+    let mut in_definitions = true;
+    while let Some(token) = tokens.next() {
+        match token {
+            "$timescale" => {
+                let mut text = String::new();
+                for token in tokens.by_ref() {
+                    if token == "$end" {
+                        break;
+                    }
+                    text.push_str(token);
+                }
+                scale = timescale(&text)?;
+            },
+            "$var" => {
+                let fields: Vec<&str> = tokens
+                    .by_ref()
+                    .take_while(|token| *token != "$end")
+                    .collect();
+                if let [_kind, width, id, name, ..] = fields.as_slice() {
+                    if *width == "1" && !ids.contains_key(*id) {
+                        ids.insert(id.to_string(), channels.len());
+                        channels.push(Channel::new(name.to_string()));
+                    }
+                } else {
+                    bail!("Invalid VCD variable definition")
+                }
+            },
+            "$enddefinitions" => {
+                in_definitions = false;
+                tokens.by_ref().find(|token| *token == "$end");
+            },
+            "$dumpvars" | "$dumpon" | "$dumpoff" | "$dumpall" | "$end" => {},
+            keyword if keyword.starts_with('$') => {
+                // Skip sections such as $comment, $date and $version.
+                tokens.by_ref().find(|token| *token == "$end");
+            },
+            _ if in_definitions =>
+                bail!("Unexpected '{token}' in VCD header"),
+            timestamp if timestamp.starts_with('#') => {
+                let value: i64 = timestamp[1..]
+                    .parse()
+                    .with_context(|| format!("Invalid VCD time '{token}'"))?;
+                time = value
+                    .checked_mul(scale)
+                    .context("VCD time out of range")?;
+            },
+            vector if vector.starts_with(['b', 'B', 'r', 'R']) => {
+                // Multi-bit values are not shown; skip the identifier.
+                tokens.next();
+            },
+            change => {
+                let mut chars = change.chars();
+                let level = match chars.next() {
+                    Some('1') => true,
+                    Some('0' | 'x' | 'X' | 'z' | 'Z') => false,
+                    _ => bail!("Invalid VCD value change '{change}'"),
+                };
+                if let Some(&index) = ids.get(chars.as_str()) {
+                    channels[index].set(time, level);
+                }
+            },
+        }
+    }
+    if channels.is_empty() {
+        bail!("No single-bit signals found in VCD file")
+    }
+    let channels = channels.into_iter().map(Channel::finish).collect();
+    Ok(LogicTrace { name: String::new(), channels })
+}
+
+/// Nanoseconds per unit of a VCD timescale, e.g. `10us`.
+fn timescale(text: &str) -> Result<i64, Error> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: i64 = number
+        .parse()
+        .with_context(|| format!("Invalid VCD timescale '{text}'"))?;
+    let nanoseconds = match unit {
+        "s" => 1_000_000_000,
+        "ms" => 1_000_000,
+        "us" => 1_000,
+        "ns" => 1,
+        "ps" | "fs" => bail!("VCD timescale '{text}' is finer than 1ns"),
+        _ => bail!("Invalid VCD timescale '{text}'"),
+    };
+    Ok(number * nanoseconds)
+}
+
+/// Parse a digital export from Saleae Logic, in CSV format.
+///
+/// The first column is a time in seconds, and each other column is the
+/// level of a channel, named in the header row.
+pub fn parse_saleae_csv(text: &str) -> Result<LogicTrace, Error> {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let header = lines.next().context("CSV file is empty")?;
+    let names: Vec<&str> = header.split(',').map(str::trim).collect();
+    if names.len() < 2 {
+        bail!("CSV header has no channels")
+    }
+    let mut channels: Vec<Channel> = names[1..]
+        .iter()
+        .map(|name| Channel::new(name.trim_matches('"').to_string()))
+        .collect();
+    for (number, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let row = number + 2;
+        if fields.len() != names.len() {
+            bail!("CSV row {row} has {} fields, expected {}",
+                  fields.len(), names.len())
+        }
+        let seconds: f64 = fields[0]
+            .parse()
+            .with_context(|| format!("Invalid time in CSV row {row}"))?;
+        let time = (seconds * 1e9).round() as i64;
+        for (channel, field) in channels.iter_mut().zip(&fields[1..]) {
+            let level = match *field {
+                "1" => true,
+                "0" => false,
+                _ => bail!("Invalid level '{field}' in CSV row {row}"),
+            };
+            channel.set(time, level);
+        }
+    }
+    let channels = channels.into_iter().map(Channel::finish).collect();
+    Ok(LogicTrace { name: String::new(), channels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vcd() {
+        let trace = parse_vcd("
+            $date today $end
+            $timescale 10 us $end
+            $scope module top $end
+            $var wire 1 ! button $end
+            $var wire 1 \" led $end
+            $var wire 8 # bus $end
+            $upscope $end
+            $enddefinitions $end
+            $dumpvars
+            0!
+            1\"
+            b00000000 #
+            $end
+            #5
+            1!
+            b00000001 #
+            #7
+            1!
+            0\"
+            #12
+            x!
+        ").unwrap();
+        assert_eq!(trace.channels.len(), 2);
+        let button = &trace.channels[0];
+        assert_eq!(button.name, "button");
+        assert!(!button.initial);
+        assert_eq!(button.edges, [(50_000, true), (120_000, false)]);
+        let led = &trace.channels[1];
+        assert!(led.initial);
+        assert_eq!(led.edges, [(70_000, false)]);
+        assert!(button.level_at(60_000));
+        assert!(!button.level_at(49_999));
+        assert_eq!(trace.span(), Some((50_000, 120_000)));
+
+        assert!(parse_vcd("$timescale 1ps $end").is_err());
+        assert!(parse_vcd("$enddefinitions $end #0").is_err());
+    }
+
+    #[test]
+    fn test_parse_saleae_csv() {
+        let trace = parse_saleae_csv("\
+Time [s],Channel 0,Channel 1
+-0.000001000,0,1
+0.000000000,1,1
+0.000002500,1,0
+").unwrap();
+        let names: Vec<&str> = trace.channels
+            .iter()
+            .map(|channel| channel.name.as_str())
+            .collect();
+        assert_eq!(names, ["Channel 0", "Channel 1"]);
+        assert_eq!(trace.channels[0].edges, [(0, true)]);
+        assert!(trace.channels[1].initial);
+        assert_eq!(trace.channels[1].edges, [(2500, false)]);
+        assert!(parse_saleae_csv("Time [s],A\n0.0,2\n").is_err());
+        assert!(parse_saleae_csv("Time [s],A\n0.0\n").is_err());
+
+        // Placed with time zero at 1000ns, only the edges in the window
+        // are included, with the level at its start.
+        let window = trace.window(1000, 2000, 5000, 10);
+        assert!(window[0].initial);
+        assert!(window[0].edges.is_empty());
+        assert!(window[1].initial);
+        assert_eq!(window[1].edges, [(3500, false)]);
+        let window = trace.window(1000, 0, 5000, 10);
+        assert!(!window[0].initial);
+        assert_eq!(window[0].edges, [(1000, true)]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Labeled markers inserted at points in time during a live capture, e.g.
+//! when a button was pressed, to help relate actions to the traffic seen.
+//!
+//! Markers can be added from a hotkey in the GUI, or read as lines of text
+//! from standard input or a TCP socket, each line being the label for a
+//! marker at the time it was read.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener};
+use std::thread::spawn;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+
+use crate::capture::{CaptureReader, PacketId, Timestamp};
+
+/// Label given to markers added without one.
+pub const DEFAULT_LABEL: &str = "Marker";
+
+/// A labeled point in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Marker {
+    pub time: Timestamp,
+    pub label: String,
+}
+
+impl Marker {
+    /// A marker at the current time, on the clock used by live captures.
+    pub fn now(label: &str) -> Result<Marker, Error> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos()
+            .try_into()?;
+        let label = match label.trim() {
+            "" => DEFAULT_LABEL,
+            label => label,
+        };
+        Ok(Marker { time, label: label.to_string() })
+    }
+}
+
+/// The markers in a capture, in order of time.
+#[derive(Clone, Debug, Default)]
+pub struct Markers {
+    markers: Vec<Marker>,
+}
+
+impl Markers {
+    pub fn new() -> Self {
+        Markers::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    /// Add a marker, after any others at the same time.
+    pub fn add(&mut self, marker: Marker) {
+        let index = self.markers.partition_point(|other|
+            other.time <= marker.time);
+        self.markers.insert(index, marker);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Marker> {
+        self.markers.iter()
+    }
+
+    /// The markers within a window of time.
+    pub fn in_window(&self, start: Timestamp, end: Timestamp)
+        -> impl Iterator<Item=&Marker>
+    {
+        let first = self.markers.partition_point(|marker| marker.time < start);
+        self.markers[first..]
+            .iter()
+            .take_while(move |marker| marker.time <= end)
+    }
+
+    /// Map markers to the packets they should be attached to on export.
+    ///
+    /// Each marker is attached to the first packet at or after its time,
+    /// or to the last packet if there is none. Comments are prefixed with
+    /// `Marker: `, and joined on separate lines where several markers fall
+    /// on one packet.
+    pub fn packet_comments(&self,
+                           capture: &mut CaptureReader,
+                           comments: &mut BTreeMap<PacketId, String>)
+        -> Result<(), Error>
+    {
+        let packet_count = capture.packet_times.len();
+        if packet_count == 0 {
+            return Ok(());
+        }
+        for marker in &self.markers {
+            let mut packet_id = capture.packet_times.bisect_left(&marker.time)?;
+            if packet_id.value >= packet_count {
+                packet_id = PacketId::from(packet_count - 1);
+            }
+            let text = format!("Marker: {}", marker.label);
+            comments
+                .entry(packet_id)
+                .and_modify(|comment| {
+                    comment.push('\n');
+                    comment.push_str(&text);
+                })
+                .or_insert(text);
+        }
+        Ok(())
+    }
+}
+
+/// Read marker labels from lines of text, until the end of the input.
+///
+/// Each line adds a marker at the time it was read. Empty lines add a
+/// marker with the default label.
+pub fn read_markers<R, F>(reader: R, mut add: F) -> Result<(), Error>
+    where R: BufRead, F: FnMut(Marker)
+{
+    for line in reader.lines() {
+        let line = line.context("Failed to read marker")?;
+        add(Marker::now(&line)?);
+    }
+    Ok(())
+}
+
+/// Listen for marker labels on a TCP socket.
+///
+/// Connections are accepted on a new thread, and the lines sent on each
+/// are read as by [`read_markers`], on a thread of their own. Returns the
+/// address listened on, which has the port chosen if it was given as 0.
+pub fn listen<F>(address: &str, add: F) -> Result<SocketAddr, Error>
+    where F: Fn(Marker) + Clone + Send + 'static
+{
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Failed to listen for markers on {address}"))?;
+    let local_address = listener.local_addr()?;
+    spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let add = add.clone();
+            spawn(move || read_markers(BufReader::new(stream), add));
+        }
+    });
+    Ok(local_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_markers() {
+        let marker = |time, label: &str| Marker {
+            time,
+            label: label.to_string(),
+        };
+        let mut markers = Markers::new();
+        markers.add(marker(300, "c"));
+        markers.add(marker(100, "a"));
+        markers.add(marker(300, "d"));
+        markers.add(marker(200, "b"));
+        let labels = |markers: Vec<&Marker>| markers
+            .iter()
+            .map(|marker| marker.label.as_str())
+            .collect::<Vec<_>>()
+            .join("");
+        assert_eq!(labels(markers.iter().collect()), "abcd");
+        assert_eq!(labels(markers.in_window(150, 300).collect()), "bcd");
+        assert_eq!(labels(markers.in_window(0, 99).collect()), "");
+
+        // Markers are attached to the next packet, or the last.
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for time in [50, 100, 250] {
+            decoder.handle_raw_packet(&[0xa5, 0xde, 0x1e], time).unwrap();
+        }
+        decoder.finish().unwrap();
+        markers.add(marker(400, "e"));
+        let mut comments = BTreeMap::new();
+        markers.packet_comments(&mut capture, &mut comments).unwrap();
+        let comments: Vec<_> = comments
+            .into_iter()
+            .map(|(packet_id, text)| (packet_id.value, text))
+            .collect();
+        assert_eq!(comments, vec![
+            (1, String::from("Marker: a")),
+            (2, String::from(
+                "Marker: b\nMarker: c\nMarker: d\nMarker: e")),
+        ]);
+    }
+
+    #[test]
+    fn test_read_markers() {
+        let mut read = Vec::new();
+        read_markers(&b"pressed button\n\n  released \n"[..],
+                     |marker| read.push(marker)).unwrap();
+        let labels: Vec<_> = read
+            .iter()
+            .map(|marker| marker.label.as_str())
+            .collect();
+        assert_eq!(labels, ["pressed button", DEFAULT_LABEL, "released"]);
+        assert!(read.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+        let (sender, receiver) = channel();
+        let address = listen("127.0.0.1:0", move |marker| {
+            let _ = sender.send(marker);
+        }).unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"plugged in\n").unwrap();
+        let marker = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(marker.label, "plugged in");
+    }
+}
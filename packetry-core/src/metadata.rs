@@ -0,0 +1,82 @@
+//! Details of a capture as a whole, entered by the user.
+
+/// Details describing why, how and of what a capture was made.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: String,
+    /// Who made the capture.
+    pub operator: String,
+    /// Description of the device under test.
+    pub device: String,
+    /// Serial number of the device under test.
+    pub serial: String,
+    pub notes: String,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Metadata::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    /// Comments describing the capture, as saved in the section header of
+    /// a pcapng file: one for each of the title and operator given, then
+    /// the notes.
+    pub fn section_comments(&self) -> Vec<String> {
+        let mut comments = Vec::new();
+        for (label, value) in [
+            ("Title", &self.title),
+            ("Operator", &self.operator),
+        ] {
+            if !value.is_empty() {
+                comments.push(format!("{label}: {value}"));
+            }
+        }
+        if !self.notes.is_empty() {
+            comments.push(self.notes.clone());
+        }
+        comments
+    }
+
+    /// Description of the device under test, as saved with the interface
+    /// in a pcapng file, if one is given.
+    pub fn device_description(&self) -> Option<String> {
+        match (self.device.is_empty(), self.serial.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(self.device.clone()),
+            (true, false) => Some(format!("Serial number {}", self.serial)),
+            (false, false) => Some(format!("{} (serial number {})",
+                                           self.device, self.serial)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let mut metadata = Metadata::new();
+        assert!(metadata.is_empty());
+        assert!(metadata.section_comments().is_empty());
+        assert_eq!(metadata.device_description(), None);
+
+        metadata.title = String::from("Enumeration");
+        metadata.notes = String::from("Plugged in twice.\nSecond time failed.");
+        metadata.serial = String::from("0042");
+        assert!(!metadata.is_empty());
+        assert_eq!(metadata.section_comments(), [
+            "Title: Enumeration",
+            "Plugged in twice.\nSecond time failed.",
+        ]);
+        assert_eq!(metadata.device_description().unwrap(),
+                   "Serial number 0042");
+        metadata.device = String::from("HackRF One");
+        assert_eq!(metadata.device_description().unwrap(),
+                   "HackRF One (serial number 0042)");
+    }
+}
@@ -0,0 +1,719 @@
+//! Packetry's native capture file format.
+//!
+//! A native capture file contains the raw packets of a capture together
+//! with all the indexes and device metadata built by the decoder, so that
+//! it can be reopened without decoding the packets again.
+//!
+//! The file consists of a header, followed by the contents of each of the
+//! capture's streams in a fixed order, and any annotations, markers and
+//! details of the capture given by the user, then a hash of the packets,
+//! which may be signed. The
+//! index of top-level traffic items is stored last, so that items only
+//! become visible whilst loading once everything they refer to is
+//! available.
+
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+use anyhow::{Context, Error, bail};
+
+use crate::annotations::{Annotation, Annotations};
+use crate::capture::{
+    create_endpoint,
+    CaptureReader,
+    CaptureWriter,
+    DeviceData,
+    DeviceId,
+    EndpointId,
+    EndpointReader,
+    PacketId,
+    TrafficItem,
+    TrafficItemId,
+    TransactionId,
+    TransferId,
+};
+use crate::integrity::{HASH_SIZE, Integrity};
+use crate::markers::{Marker, Markers};
+use crate::metadata::Metadata;
+use crate::usb::{
+    ConfigNum,
+    Configuration,
+    DeviceDescriptor,
+    EndpointAddr,
+    EndpointType,
+    StringId,
+    UTF16ByteVec,
+};
+use crate::vec_map::{Key, VecMap};
+
+/// Magic bytes at the start of a native capture file.
+pub const MAGIC: &[u8; 8] = b"PKTRYCAP";
+
+/// Version of the format written by this build.
+const VERSION: u32 = 7;
+
+/// Earliest version of the format which can still be loaded.
+///
+/// Version 1 files do not include annotations, files before version 3
+/// do not include the original lengths of truncated packets, and files
+/// before version 4 do not record which transfers were aborted, files
+/// before version 5 do not include markers, files before version 6 do
+/// not include a hash of the packets, and files before version 7 do not
+/// include the details of the capture.
+const MIN_VERSION: u32 = 1;
+
+/// Whether the given bytes are the start of a native capture file.
+pub fn is_native(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Approximate size of the native file that would be saved for a capture.
+pub fn saved_size(capture: &CaptureReader) -> u64 {
+    capture.storage_size()
+}
+
+/// Save a complete capture in the native format.
+pub fn save(capture: &mut CaptureReader, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    save_signed(capture, writer, None)
+}
+
+/// Save a complete capture in the native format, signing its packets
+/// with the given key, if any.
+pub fn save_signed(capture: &mut CaptureReader,
+                   writer: &mut dyn Write,
+                   key: Option<&[u8]>)
+    -> Result<(), Error>
+{
+    let shared = capture.shared.clone();
+    if !shared.complete.load(Acquire) {
+        bail!("Capture must be complete before saving in native format")
+    }
+
+    // Header.
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_u8(writer, shared.reconstructed.load(Acquire) as u8)?;
+    let sources = shared.sources.load();
+    write_u32(writer, sources.len() as u32)?;
+    for source in sources.iter() {
+        write_bytes(writer, source.as_bytes())?;
+    }
+
+    // Packets.
+    capture.packet_data.save(writer)?;
+    capture.packet_index.save(writer)?;
+    capture.packet_times.save(writer)?;
+    capture.packet_sources.save(writer)?;
+    capture.packet_source_index.save(writer)?;
+    capture.packet_lengths.save(writer)?;
+    capture.packet_length_index.save(writer)?;
+
+    // Transactions, transfers and endpoints.
+    capture.transaction_index.save(writer)?;
+    capture.transfer_index.save(writer)?;
+    capture.endpoints.save(writer)?;
+    capture.endpoint_states.save(writer)?;
+    capture.endpoint_state_index.save(writer)?;
+    capture.end_index.save(writer)?;
+
+    // Per-endpoint indexes.
+    let endpoint_readers = shared.endpoint_readers.load();
+    write_u32(writer, endpoint_readers.len() as u32)?;
+    for id in 0..endpoint_readers.len() {
+        match endpoint_readers.get(EndpointId::key(id)) {
+            None => write_u8(writer, 0)?,
+            Some(ep_reader) => {
+                write_u8(writer, 1)?;
+                save_endpoint(&mut ep_reader.as_ref().clone(), writer)?;
+            }
+        }
+    }
+
+    // Devices.
+    let device_data = shared.device_data.load();
+    write_u32(writer, device_data.len() as u32)?;
+    for id in 0..device_data.len() {
+        match device_data.get(DeviceId::key(id)) {
+            None => write_u8(writer, 0)?,
+            Some(data) => {
+                write_u8(writer, 1)?;
+                save_device_data(data, writer)?;
+            }
+        }
+    }
+    capture.devices.save(writer)?;
+
+    // Annotations.
+    save_annotations(&shared.annotations.load(), writer)?;
+
+    // Markers.
+    save_markers(&shared.markers.load(), writer)?;
+
+    // Details of the capture.
+    save_metadata(&shared.metadata.load(), writer)?;
+
+    // Integrity.
+    let integrity = Integrity::compute(capture, key)?;
+    writer.write_all(&integrity.hash)?;
+    match integrity.signature {
+        Some(signature) => {
+            write_u8(writer, 1)?;
+            writer.write_all(&signature)?;
+        },
+        None => write_u8(writer, 0)?,
+    }
+
+    // Top-level items.
+    capture.item_index.save(writer)?;
+
+    Ok(())
+}
+
+/// Load a capture saved in the native format.
+///
+/// The capture written to must be newly created.
+pub fn load(capture: &mut CaptureWriter, reader: &mut dyn Read)
+    -> Result<(), Error>
+{
+    let shared = capture.shared.clone();
+
+    // Header.
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if !is_native(&magic) {
+        bail!("Not a Packetry capture file")
+    }
+    let version = read_u32(reader)?;
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        bail!("Unsupported Packetry capture file version {version}")
+    }
+    shared.reconstructed.store(read_u8(reader)? != 0, Release);
+    let source_count = read_u32(reader)?;
+    // The counts and lengths in a file are not trusted to allocate space,
+    // since a corrupt file could give any value.
+    let mut sources = Vec::new();
+    for _ in 0..source_count {
+        sources.push(String::from_utf8(read_bytes(reader)?)
+            .context("Invalid source file name")?);
+    }
+    shared.sources.store(Arc::new(sources));
+
+    // Packets.
+    capture.packet_data.load(reader)?;
+    capture.packet_index.load(reader)?;
+    capture.packet_times.load(reader)?;
+    capture.packet_sources.load(reader)?;
+    capture.packet_source_index.load(reader)?;
+    if version >= 3 {
+        capture.packet_lengths.load(reader)?;
+        capture.packet_length_index.load(reader)?;
+    }
+
+    // Transactions, transfers and endpoints.
+    capture.transaction_index.load(reader)?;
+    capture.transfer_index.load(reader)?;
+    capture.endpoints.load(reader)?;
+    capture.endpoint_states.load(reader)?;
+    capture.endpoint_state_index.load(reader)?;
+    capture.end_index.load(reader)?;
+
+    // Per-endpoint indexes.
+    let endpoint_count = read_u32(reader)?;
+    let mut endpoint_readers = VecMap::new();
+    for id in 0..endpoint_count as usize {
+        if read_u8(reader)? != 0 {
+            let ep_reader = load_endpoint(reader, version)?;
+            endpoint_readers.set(EndpointId::key(id), Arc::new(ep_reader));
+        }
+    }
+    shared.endpoint_readers.store(Arc::new(endpoint_readers));
+
+    // Devices.
+    let device_count = read_u32(reader)?;
+    let mut device_data = VecMap::new();
+    for id in 0..device_count as usize {
+        if read_u8(reader)? != 0 {
+            let data = load_device_data(reader)?;
+            device_data.set(DeviceId::key(id), Arc::new(data));
+        }
+    }
+    shared.device_data.store(Arc::new(device_data));
+    capture.devices.load(reader)?;
+
+    // Annotations.
+    if version >= 2 {
+        shared.annotations.store(Arc::new(load_annotations(reader)?));
+    }
+
+    // Markers.
+    if version >= 5 {
+        shared.markers.store(Arc::new(load_markers(reader)?));
+    }
+
+    // Details of the capture.
+    if version >= 7 {
+        shared.metadata.store(Arc::new(load_metadata(reader)?));
+    }
+
+    // Integrity.
+    if version >= 6 {
+        let mut hash = [0; HASH_SIZE];
+        reader.read_exact(&mut hash)?;
+        let signature = if read_u8(reader)? != 0 {
+            let mut signature = [0; HASH_SIZE];
+            reader.read_exact(&mut signature)?;
+            Some(signature)
+        } else {
+            None
+        };
+        shared.integrity.store(Some(Arc::new(Integrity { hash, signature })));
+    }
+
+    // Top-level items.
+    capture.item_index.load(reader)?;
+
+    shared.complete.store(true, Release);
+    Ok(())
+}
+
+fn save_endpoint(ep_reader: &mut EndpointReader, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    write_u64(writer, ep_reader.shared.total_data.load(Acquire))?;
+    match ep_reader.shared.first_item_id.load().as_ref() {
+        None => write_u8(writer, 0)?,
+        Some(item_id) => {
+            write_u8(writer, 1)?;
+            write_u64(writer, item_id.value)?;
+        }
+    }
+    ep_reader.transaction_ids.save(writer)?;
+    ep_reader.transfer_index.save(writer)?;
+    ep_reader.data_transactions.save(writer)?;
+    ep_reader.data_byte_counts.save(writer)?;
+    ep_reader.end_index.save(writer)?;
+    ep_reader.aborted_transfers.save(writer)?;
+    Ok(())
+}
+
+fn load_endpoint(reader: &mut dyn Read, version: u32)
+    -> Result<EndpointReader, Error>
+{
+    let (mut ep_writer, ep_reader) = create_endpoint()?;
+    ep_writer.shared.total_data.store(read_u64(reader)?, Release);
+    if read_u8(reader)? != 0 {
+        let item_id = TrafficItemId::from(read_u64(reader)?);
+        ep_writer.shared.first_item_id.store(Some(Arc::new(item_id)));
+    }
+    ep_writer.transaction_ids.load(reader)?;
+    ep_writer.transfer_index.load(reader)?;
+    ep_writer.data_transactions.load(reader)?;
+    ep_writer.data_byte_counts.load(reader)?;
+    ep_writer.end_index.load(reader)?;
+    if version >= 4 {
+        ep_writer.aborted_transfers.load(reader)?;
+    }
+    Ok(ep_reader)
+}
+
+fn save_device_data(data: &DeviceData, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    match data.device_descriptor.load().as_ref() {
+        None => write_u8(writer, 0)?,
+        Some(descriptor) => {
+            write_u8(writer, 1)?;
+            writer.write_all(bytemuck::bytes_of(descriptor.as_ref()))?;
+        }
+    }
+    let configurations = data.configurations.load();
+    write_u32(writer, configurations.entries().count() as u32)?;
+    for (number, config) in configurations.entries() {
+        write_u8(writer, number.0)?;
+        write_bytes(writer, &config.to_bytes())?;
+    }
+    match data.config_number.load().as_ref() {
+        None => write_u8(writer, 0)?,
+        Some(number) => {
+            write_u8(writer, 1)?;
+            write_u8(writer, number.0)?;
+        }
+    }
+    let endpoint_details = data.endpoint_details.load();
+    write_u32(writer, endpoint_details.entries().count() as u32)?;
+    for (addr, (ep_type, ep_max)) in endpoint_details.entries() {
+        write_u8(writer, addr.0)?;
+        write_u8(writer, *ep_type as u8)?;
+        match ep_max {
+            None => write_u8(writer, 0)?,
+            Some(max) => {
+                write_u8(writer, 1)?;
+                write_u64(writer, *max as u64)?;
+            }
+        }
+    }
+    let strings = data.strings.load();
+    write_u32(writer, strings.entries().count() as u32)?;
+    for (id, string) in strings.entries() {
+        write_u8(writer, id.0)?;
+        write_bytes(writer, &string.0)?;
+    }
+    write_u32(writer, data.version.load(Acquire))?;
+    Ok(())
+}
+
+fn load_device_data(reader: &mut dyn Read) -> Result<DeviceData, Error> {
+    let data = DeviceData::default();
+    if read_u8(reader)? != 0 {
+        let mut bytes = [0; size_of::<DeviceDescriptor>()];
+        reader.read_exact(&mut bytes)?;
+        let descriptor = DeviceDescriptor::from_bytes(&bytes);
+        data.device_descriptor.store(Some(Arc::new(descriptor)));
+    }
+    let mut configurations = VecMap::new();
+    for _ in 0..read_u32(reader)? {
+        let number = ConfigNum(read_u8(reader)?);
+        let config = Configuration::from_bytes(&read_bytes(reader)?)
+            .context("Invalid configuration descriptor")?;
+        configurations.set(number, Arc::new(config));
+    }
+    data.configurations.store(Arc::new(configurations));
+    if read_u8(reader)? != 0 {
+        let number = ConfigNum(read_u8(reader)?);
+        data.config_number.store(Some(Arc::new(number)));
+    }
+    let mut endpoint_details = VecMap::new();
+    for _ in 0..read_u32(reader)? {
+        let addr = EndpointAddr(read_u8(reader)?);
+        let ep_type = EndpointType::from(read_u8(reader)?);
+        let ep_max = match read_u8(reader)? {
+            0 => None,
+            _ => Some(read_u64(reader)? as usize),
+        };
+        endpoint_details.set(addr, (ep_type, ep_max));
+    }
+    data.endpoint_details.store(Arc::new(endpoint_details));
+    let mut strings = VecMap::new();
+    for _ in 0..read_u32(reader)? {
+        let id = StringId(read_u8(reader)?);
+        strings.set(id, UTF16ByteVec(read_bytes(reader)?));
+    }
+    data.strings.store(Arc::new(strings));
+    data.version.store(read_u32(reader)?, Release);
+    Ok(data)
+}
+
+fn save_annotations(annotations: &Annotations, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    use TrafficItem::*;
+    let items: Vec<_> = annotations.iter().collect();
+    write_u32(writer, items.len() as u32)?;
+    for (item, annotation) in items {
+        match item {
+            Transfer(transfer_id) => {
+                write_u8(writer, 0)?;
+                write_u64(writer, transfer_id.value)?;
+            },
+            Transaction(transfer_id, transaction_id) => {
+                write_u8(writer, 1)?;
+                write_u64(writer, transfer_id.value)?;
+                write_u64(writer, transaction_id.value)?;
+            },
+            Packet(transfer_id, transaction_id, packet_id) => {
+                write_u8(writer, 2)?;
+                write_u64(writer, transfer_id.value)?;
+                write_u64(writer, transaction_id.value)?;
+                write_u64(writer, packet_id.value)?;
+            },
+        }
+        write_u8(writer, annotation.bookmarked as u8)?;
+        write_bytes(writer, annotation.comment.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn load_annotations(reader: &mut dyn Read) -> Result<Annotations, Error> {
+    use TrafficItem::*;
+    let mut annotations = Annotations::new();
+    let count = read_u32(reader)?;
+    for _ in 0..count {
+        let kind = read_u8(reader)?;
+        let transfer_id = TransferId::from(read_u64(reader)?);
+        let item = match kind {
+            0 => Transfer(transfer_id),
+            1 => Transaction(transfer_id,
+                             TransactionId::from(read_u64(reader)?)),
+            2 => Packet(transfer_id,
+                        TransactionId::from(read_u64(reader)?),
+                        PacketId::from(read_u64(reader)?)),
+            _ => bail!("Invalid annotated item type {kind}"),
+        };
+        let bookmarked = read_u8(reader)? != 0;
+        let comment = String::from_utf8(read_bytes(reader)?)
+            .context("Invalid annotation comment")?;
+        annotations.set(&item, Annotation { bookmarked, comment });
+    }
+    Ok(annotations)
+}
+
+fn save_markers(markers: &Markers, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    write_u32(writer, markers.len() as u32)?;
+    for marker in markers.iter() {
+        write_u64(writer, marker.time)?;
+        write_bytes(writer, marker.label.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn load_markers(reader: &mut dyn Read) -> Result<Markers, Error> {
+    let mut markers = Markers::new();
+    let count = read_u32(reader)?;
+    for _ in 0..count {
+        let time = read_u64(reader)?;
+        let label = String::from_utf8(read_bytes(reader)?)
+            .context("Invalid marker label")?;
+        markers.add(Marker { time, label });
+    }
+    Ok(markers)
+}
+
+fn save_metadata(metadata: &Metadata, writer: &mut dyn Write)
+    -> Result<(), Error>
+{
+    for text in [
+        &metadata.title,
+        &metadata.operator,
+        &metadata.device,
+        &metadata.serial,
+        &metadata.notes,
+    ] {
+        write_bytes(writer, text.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn load_metadata(reader: &mut dyn Read) -> Result<Metadata, Error> {
+    let mut read_text = || String::from_utf8(read_bytes(reader)?)
+        .context("Invalid capture details");
+    Ok(Metadata {
+        title: read_text()?,
+        operator: read_text()?,
+        device: read_text()?,
+        serial: read_text()?,
+        notes: read_text()?,
+    })
+}
+
+fn write_u8(writer: &mut dyn Write, value: u8) -> Result<(), Error> {
+    writer.write_all(&[value])?;
+    Ok(())
+}
+
+fn write_u32(writer: &mut dyn Write, value: u32) -> Result<(), Error> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u64(writer: &mut dyn Write, value: u64) -> Result<(), Error> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_bytes(writer: &mut dyn Write, bytes: &[u8]) -> Result<(), Error> {
+    write_u32(writer, bytes.len().try_into()?)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u8(reader: &mut dyn Read) -> Result<u8, Error> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut dyn Read) -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_bytes(reader: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    let length = read_u32(reader)? as usize;
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        bail!("Capture file is truncated")
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use pcap_file::pcap::PcapReader;
+    use crate::capture::{create_capture, DeviceItem, ItemSource};
+    use crate::decoder::Decoder;
+    use crate::id::Id;
+
+    fn summaries<Item>(capture: &mut CaptureReader,
+                       parent: Option<&Item>,
+                       summaries: &mut Vec<String>)
+        where CaptureReader: ItemSource<Item>
+    {
+        let (_, count) = capture.item_children(parent).unwrap();
+        for index in 0..count {
+            let item = match parent {
+                None => capture.item(None, index).unwrap(),
+                Some(parent) => capture.child_item(parent, index).unwrap(),
+            };
+            summaries.push(capture.summary(&item).unwrap());
+            self::summaries(capture, Some(&item), summaries);
+        }
+    }
+
+    fn all_summaries(capture: &mut CaptureReader) -> Vec<String> {
+        let mut result = Vec::new();
+        summaries::<TrafficItem>(capture, None, &mut result);
+        summaries::<DeviceItem>(capture, None, &mut result);
+        result
+    }
+
+    #[test]
+    fn test_native_round_trip() {
+        let file = File::open("../tests/hackrf-connect/capture.pcap").unwrap();
+        let mut pcap = PcapReader::new(file).unwrap();
+        let header = pcap.header();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        while let Some(result) = pcap.next_raw_packet() {
+            let packet = result.unwrap();
+            let timestamp = crate::pcap::timestamp(&header, &packet);
+            decoder.handle_raw_packet(&packet.data, timestamp).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let transfer: TrafficItem = capture.item(None, 2).unwrap();
+        let transaction = capture.child_item(&transfer, 0).unwrap();
+        let packet = capture.child_item(&transaction, 1).unwrap();
+        let mut annotations = Annotations::new();
+        annotations.set(&transfer, Annotation {
+            bookmarked: true,
+            comment: String::from("Interesting"),
+        });
+        annotations.set(&packet, Annotation {
+            bookmarked: false,
+            comment: String::from("Look here"),
+        });
+        capture.shared.annotations.store(Arc::new(annotations));
+        let mut markers = Markers::new();
+        markers.add(Marker { time: 1000, label: String::from("Pressed") });
+        capture.shared.markers.store(Arc::new(markers));
+        let metadata = Metadata {
+            title: String::from("Connecting a HackRF"),
+            serial: String::from("0042"),
+            notes: String::from("First attempt"),
+            ..Metadata::new()
+        };
+        capture.shared.metadata.store(Arc::new(metadata.clone()));
+
+        let mut saved = Vec::new();
+        save(&mut capture, &mut saved).unwrap();
+        assert!(is_native(&saved));
+
+        let (mut writer, mut loaded) = create_capture().unwrap();
+        load(&mut writer, &mut saved.as_slice()).unwrap();
+        assert_eq!(loaded.packet_index.len(), capture.packet_index.len());
+        assert_eq!(all_summaries(&mut loaded), all_summaries(&mut capture));
+        let saved_annotations: Vec<(TrafficItem, Annotation)> = capture
+            .shared.annotations.load()
+            .iter()
+            .map(|(item, annotation)| (item, annotation.clone()))
+            .collect();
+        let loaded_annotations: Vec<(TrafficItem, Annotation)> = loaded
+            .shared.annotations.load()
+            .iter()
+            .map(|(item, annotation)| (item, annotation.clone()))
+            .collect();
+        assert_eq!(loaded_annotations.len(), 2);
+        assert_eq!(loaded_annotations, saved_annotations);
+        let loaded_markers: Vec<Marker> = loaded
+            .shared.markers.load()
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(loaded_markers,
+                   [Marker { time: 1000, label: String::from("Pressed") }]);
+        assert_eq!(**loaded.shared.metadata.load(), metadata);
+
+        // The packets are hashed, and changing one is detected.
+        let integrity = loaded.shared.integrity.load_full().unwrap();
+        assert!(integrity.signature.is_none());
+        assert!(integrity.verify(&mut loaded, None).is_ok());
+        let packet = (0..capture.packet_index.len())
+            .map(|index| capture.packet(PacketId::from(index)).unwrap())
+            .find(|packet| packet.len() > 8)
+            .unwrap();
+        let offset = saved
+            .windows(packet.len())
+            .position(|bytes| bytes == packet)
+            .unwrap();
+        saved[offset + 1] ^= 0xFF;
+        let (mut writer, mut altered) = create_capture().unwrap();
+        load(&mut writer, &mut saved.as_slice()).unwrap();
+        assert!(integrity.verify(&mut altered, None).is_err());
+    }
+
+    #[test]
+    fn test_snap_length() {
+        let file = File::open("../tests/mouse/capture.pcap").unwrap();
+        let mut pcap = PcapReader::new(file).unwrap();
+        let header = pcap.header();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        decoder.set_snap_length(Some(2));
+        while let Some(result) = pcap.next_raw_packet() {
+            let packet = result.unwrap();
+            let timestamp = crate::pcap::timestamp(&header, &packet);
+            decoder.handle_raw_packet(&packet.data, timestamp).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        // Mouse reports are truncated, keeping their PID and CRC, but
+        // their truncation is not an error.
+        let truncated = capture.packet_length_index.len();
+        assert!(truncated > 0);
+        for index in 0..truncated {
+            let packet_id = capture.packet_length_index
+                .get(Id::from(index))
+                .unwrap();
+            let packet = capture.packet(packet_id).unwrap();
+            let length = capture.packet_original_length(packet_id).unwrap();
+            assert_eq!(packet.len(), 5);
+            assert!(length.unwrap() > 5);
+            let item = capture.packet_item(packet_id).unwrap();
+            assert!(!capture.item_error(&item).unwrap());
+            assert!(capture.summary(&item).unwrap().contains(" of "));
+        }
+
+        let mut saved = Vec::new();
+        save(&mut capture, &mut saved).unwrap();
+        let (mut writer, mut loaded) = create_capture().unwrap();
+        load(&mut writer, &mut saved.as_slice()).unwrap();
+        assert_eq!(loaded.packet_length_index.len(), truncated);
+        assert_eq!(all_summaries(&mut loaded), all_summaries(&mut capture));
+    }
+}
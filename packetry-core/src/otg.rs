@@ -0,0 +1,273 @@
+//! On-The-Go protocol events: the requests by which a host tells a dual
+//! role device about HNP support, enables it, or finds that the device
+//! wants to become host, and the role swaps which follow.
+//!
+//! HNP itself is line signaling, not packets, so a role swap is found from
+//! the traffic around it: after a B-device is given HNP enable, the host
+//! suspends the bus, and if the B-device takes over as host, it resets the
+//! bus and enumerates the former host at the default address. A suspend
+//! followed by enumeration at the default address after HNP was enabled
+//! is therefore shown as a role swap, and the next such one as the roles
+//! being swapped back. A reset by the same host in that place looks alike
+//! and cannot be told apart. SRP and ADP are likewise not visible.
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, DeviceId, Timestamp};
+use crate::suspend::{IdleKind, IdlePeriod, IdlePeriods};
+use crate::usb::{
+    ControlResult,
+    ControlTransfer,
+    DeviceAddr,
+    Recipient,
+    RequestType,
+    StandardFeature,
+    StandardRequest,
+    OTG_STATUS_SELECTOR,
+};
+
+/// Kinds of OTG event.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OtgEventKind {
+    /// SET_FEATURE(a_hnp_support): the host port supports HNP.
+    HnpSupport,
+    /// SET_FEATURE(a_alt_hnp_support): another host port supports HNP.
+    AltHnpSupport,
+    /// SET_FEATURE(b_hnp_enable): the device may now become host.
+    HnpEnable,
+    /// An OTG status read with the host request flag set.
+    HostRequest,
+    /// The host and device swapped roles.
+    RoleSwap,
+}
+
+impl OtgEventKind {
+    /// Name of this kind of event, as shown in the timeline.
+    pub fn label(&self) -> &'static str {
+        use OtgEventKind::*;
+        match self {
+            HnpSupport => "HNP support",
+            AltHnpSupport => "Alt HNP support",
+            HnpEnable => "HNP enabled",
+            HostRequest => "Host request",
+            RoleSwap => "Role swap",
+        }
+    }
+}
+
+/// An OTG event seen in a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OtgEvent {
+    pub kind: OtgEventKind,
+    /// Time of the request, or for a role swap, of the first packet after
+    /// the suspend.
+    pub time: Timestamp,
+    /// Address of the device concerned, which for a role swap is the
+    /// B-device given HNP enable.
+    pub address: DeviceAddr,
+    pub description: String,
+}
+
+/// Find the OTG events in a capture, given its idle periods, in order of
+/// time.
+pub fn find_events(capture: &mut CaptureReader, idle: &IdlePeriods)
+    -> Result<Vec<OtgEvent>, Error>
+{
+    let mut transfers = Vec::new();
+    for index in 0..capture.devices.len() {
+        transfers.extend(
+            capture.device_control_timeline(DeviceId::from(index))?);
+    }
+    transfers.sort_by_key(|(_, start, _)| *start);
+    let mut suspends = idle.periods
+        .iter()
+        .filter(|period| period.kind == IdleKind::Suspended)
+        .peekable();
+    let mut events = Vec::new();
+    // The B-device last given HNP enable, and when.
+    let mut hnp_enabled: Option<(DeviceAddr, Timestamp)> = None;
+    // The B-device which is now host, if the roles are swapped.
+    let mut swapped: Option<DeviceAddr> = None;
+    // The last suspend before the current transfer, if not yet followed
+    // by any other.
+    let mut suspended: Option<IdlePeriod> = None;
+    for (transfer, start, _) in &transfers {
+        while let Some(period) =
+            suspends.next_if(|period| period.end <= *start)
+        {
+            suspended = Some(*period);
+        }
+        let after_suspend = suspended.take();
+        // Enumeration at the default address follows a bus reset.
+        if transfer.address == DeviceAddr(0) {
+            let armed = match (swapped, hnp_enabled) {
+                (Some(address), _) => Some(address),
+                (None, Some((address, time))) => after_suspend
+                    .filter(|period| period.start >= time)
+                    .map(|_| address),
+                (None, None) => None,
+            };
+            if let (Some(address), Some(period)) = (armed, after_suspend) {
+                events.push(OtgEvent {
+                    kind: OtgEventKind::RoleSwap,
+                    time: period.end,
+                    address,
+                    description: if swapped.is_some() {
+                        format!("Roles swapped back: the former host took \
+                                 over from device {address} again")
+                    } else {
+                        format!("Roles swapped: device {address} became \
+                                 host by HNP, and enumerated the former \
+                                 host")
+                    },
+                });
+                swapped = match swapped {
+                    Some(_) => None,
+                    None => Some(address),
+                };
+            }
+            // HNP enable is cleared by a bus reset.
+            hnp_enabled = None;
+            continue;
+        }
+        if let Some(event) = request_event(transfer, *start) {
+            if event.kind == OtgEventKind::HnpEnable {
+                hnp_enabled = Some((event.address, event.time));
+            }
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+/// The OTG event made by a control transfer, if any.
+fn request_event(transfer: &ControlTransfer, time: Timestamp)
+    -> Option<OtgEvent>
+{
+    use OtgEventKind::*;
+    let fields = &transfer.fields;
+    if !matches!(transfer.result, ControlResult::Completed) ||
+        !matches!(fields.type_fields.request_type(), RequestType::Standard) ||
+        !matches!(fields.type_fields.recipient(), Recipient::Device)
+    {
+        return None;
+    }
+    let address = transfer.address;
+    let (kind, description) = match StandardRequest::from(fields.request) {
+        StandardRequest::SetFeature =>
+            match StandardFeature::from(fields.value) {
+                StandardFeature::AHnpSupport => (HnpSupport, format!(
+                    "Host told device {address} that this port supports \
+                     HNP")),
+                StandardFeature::AAltHnpSupport => (AltHnpSupport, format!(
+                    "Host told device {address} that another of its ports \
+                     supports HNP")),
+                StandardFeature::BHnpEnable => (HnpEnable, format!(
+                    "Host enabled HNP on device {address}, which may now \
+                     become host")),
+                _ => return None,
+            },
+        StandardRequest::GetStatus
+            if fields.index == OTG_STATUS_SELECTOR &&
+                transfer.data.first().map_or(false, |flags| flags & 1 != 0)
+            => (HostRequest, format!(
+                "Device {address} asked to become host, with its host \
+                 request flag set")),
+        _ => return None,
+    };
+    Some(OtgEvent {
+        kind,
+        time,
+        address,
+        description,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+    use std::path::PathBuf;
+
+    /// Decode scenarios in turn, each starting 20 ms after the last, with
+    /// 10 µs between packets.
+    fn decode(texts: &[&str]) -> CaptureReader {
+        let (writer, capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (index, text) in texts.iter().enumerate() {
+            let scenario = Scenario::parse("otg", text).unwrap();
+            let offset = index as u64 * 20_000_000;
+            for (i, packet) in scenario.packets().enumerate() {
+                decoder.handle_raw_packet(packet, offset + i as u64 * 10_000)
+                    .unwrap();
+            }
+        }
+        decoder.finish().unwrap();
+        capture
+    }
+
+    #[test]
+    fn test_role_swap() {
+        let mut capture = decode(&["
+            speed full
+            device 1
+            enumerate 1
+            control 1 0x00 3 4 0
+            control 1 0x80 0 0 0xF000 01
+            control 1 0x00 3 3 0
+            sof 3", "
+            speed full
+            device 2
+            sof 3
+            enumerate 2
+            sof 3", "
+            speed full
+            device 1
+            sof 3
+            enumerate 1"]);
+        let idle = IdlePeriods::find(&mut capture).unwrap();
+        let events = find_events(&mut capture, &idle).unwrap();
+        let kinds: Vec<OtgEventKind> =
+            events.iter().map(|event| event.kind).collect();
+        use OtgEventKind::*;
+        assert_eq!(kinds,
+                   [HnpSupport, HostRequest, HnpEnable, RoleSwap, RoleSwap]);
+        assert_eq!(events[3].time, 20_000_000);
+        assert_eq!(events[3].address, DeviceAddr(1));
+        assert_eq!(events[3].description,
+                   "Roles swapped: device 1 became host by HNP, and \
+                    enumerated the former host");
+        assert_eq!(events[4].time, 40_000_000);
+        assert!(events[4].description.starts_with("Roles swapped back"));
+    }
+
+    #[test]
+    fn test_no_role_swap() {
+        // A suspend and reset without HNP enabled is not a role swap, nor
+        // is one with HNP enabled but no suspend after it.
+        let mut capture = decode(&["
+            speed full
+            device 1
+            enumerate 1
+            sof 3", "
+            speed full
+            device 1
+            sof 3
+            enumerate 1
+            control 1 0x00 3 3 0
+            control 1 0x00 9 1 0"]);
+        let idle = IdlePeriods::find(&mut capture).unwrap();
+        let events = find_events(&mut capture, &idle).unwrap();
+        let kinds: Vec<OtgEventKind> =
+            events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, [OtgEventKind::HnpEnable]);
+
+        let mut capture =
+            load(&[PathBuf::from("../tests/mouse/capture.pcap")]).unwrap();
+        let idle = IdlePeriods::find(&mut capture).unwrap();
+        assert!(find_events(&mut capture, &idle).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Information used to decode devices whose descriptors are not captured.
+//!
+//! When a capture starts after a device was enumerated, its descriptors are
+//! not seen, and traffic on its endpoints cannot be grouped into transfers
+//! of the right type. An endpoint override assigns such an endpoint a
+//! class, which implies its transfer type, and optionally its maximum
+//! packet size, so that transfers ending in short packets are found.
+//! Alternatively, the device's descriptors may be obtained by other means,
+//! such as from the host it is attached to, and given to the decoder.
+
+use std::fmt;
+
+use anyhow::{Context, Error, bail};
+
+use crate::usb::{DeviceAddr, EndpointAddr, EndpointType};
+
+/// Classes of traffic which an endpoint can be decoded as.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EndpointClass {
+    CdcData,
+    CdcNotification,
+    Hid,
+    MassStorage,
+    Printer,
+    Audio,
+    Video,
+    Bulk,
+    Interrupt,
+    Isochronous,
+}
+
+impl EndpointClass {
+    /// All classes, in the order they are offered.
+    pub const ALL: [EndpointClass; 10] = [
+        EndpointClass::CdcData,
+        EndpointClass::CdcNotification,
+        EndpointClass::Hid,
+        EndpointClass::MassStorage,
+        EndpointClass::Printer,
+        EndpointClass::Audio,
+        EndpointClass::Video,
+        EndpointClass::Bulk,
+        EndpointClass::Interrupt,
+        EndpointClass::Isochronous,
+    ];
+
+    /// The name used for this class on the command line.
+    pub fn name(&self) -> &'static str {
+        use EndpointClass::*;
+        match self {
+            CdcData => "cdc-data",
+            CdcNotification => "cdc-notification",
+            Hid => "hid",
+            MassStorage => "mass-storage",
+            Printer => "printer",
+            Audio => "audio",
+            Video => "video",
+            Bulk => "bulk",
+            Interrupt => "interrupt",
+            Isochronous => "isochronous",
+        }
+    }
+
+    /// Look up a class by its name.
+    pub fn from_name(name: &str) -> Option<EndpointClass> {
+        let name = name.to_lowercase();
+        EndpointClass::ALL
+            .into_iter()
+            .find(|class| class.name() == name)
+    }
+
+    /// A description of this class, for menus.
+    pub fn description(&self) -> &'static str {
+        use EndpointClass::*;
+        match self {
+            CdcData => "CDC data",
+            CdcNotification => "CDC notifications",
+            Hid => "HID reports",
+            MassStorage => "Mass storage",
+            Printer => "Printer data",
+            Audio => "Audio streaming",
+            Video => "Video streaming",
+            Bulk => "Bulk transfers",
+            Interrupt => "Interrupt transfers",
+            Isochronous => "Isochronous transfers",
+        }
+    }
+
+    /// The type of transfers carried by an endpoint of this class.
+    pub fn endpoint_type(&self) -> EndpointType {
+        use EndpointClass::*;
+        match self {
+            CdcData | MassStorage | Printer | Bulk => EndpointType::Bulk,
+            CdcNotification | Hid | Interrupt => EndpointType::Interrupt,
+            Audio | Video | Isochronous => EndpointType::Isochronous,
+        }
+    }
+}
+
+impl fmt::Display for EndpointClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A class assigned by hand to an endpoint of a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EndpointOverride {
+    pub device: DeviceAddr,
+    pub endpoint: EndpointAddr,
+    pub class: EndpointClass,
+    pub max_packet_size: Option<usize>,
+}
+
+impl EndpointOverride {
+    /// Parse an override such as `5.0x83=cdc-data` or `5.0x02=bulk:512`.
+    ///
+    /// The endpoint is given by its address, including the direction bit,
+    /// and may be followed by a maximum packet size after the class.
+    pub fn parse(text: &str) -> Result<EndpointOverride, Error> {
+        let invalid = || format!(
+            "Invalid override '{text}', expected e.g. 5.0x83=cdc-data \
+             for endpoint 0x83 of device 5");
+        let (target, class) = text.split_once('=').with_context(invalid)?;
+        let (device, endpoint) = target.split_once('.').with_context(invalid)?;
+        let device = parse_number(device).with_context(invalid)?;
+        let endpoint = parse_number(endpoint).with_context(invalid)?;
+        if device > 127 || endpoint & 0x70 != 0 {
+            bail!(invalid())
+        }
+        let endpoint = EndpointAddr(endpoint as u8);
+        if endpoint.number().0 == 0 {
+            bail!("Endpoint 0 is always decoded as a control endpoint")
+        }
+        let (class, max_packet_size) = match class.split_once(':') {
+            Some((class, size)) => (class, Some(size
+                .parse()
+                .with_context(|| format!(
+                    "Invalid maximum packet size '{size}'"))?)),
+            None => (class, None),
+        };
+        let class = EndpointClass::from_name(class)
+            .with_context(|| format!(
+                "Unknown class '{class}', expected one of: {}",
+                EndpointClass::ALL.map(|class| class.name()).join(", ")))?;
+        Ok(EndpointOverride {
+            device: DeviceAddr(device as u8),
+            endpoint,
+            class,
+            max_packet_size,
+        })
+    }
+}
+
+impl fmt::Display for EndpointOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.0x{:02X}={}", self.device, self.endpoint.0, self.class)?;
+        if let Some(size) = self.max_packet_size {
+            write!(f, ":{size}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Descriptors of a device obtained outside the capture.
+///
+/// Each descriptor is held as it would be read from the device, including
+/// its length and type fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KnownDescriptors {
+    pub device: DeviceAddr,
+    pub device_descriptor: Vec<u8>,
+    /// Each configuration descriptor, followed by its other descriptors.
+    pub configurations: Vec<Vec<u8>>,
+    /// The configuration selected, if any.
+    pub config_number: Option<u8>,
+    /// String descriptors, by index.
+    pub strings: Vec<(u8, Vec<u8>)>,
+}
+
+/// All the information given to the decoder for devices whose descriptors
+/// are not captured.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overrides {
+    pub endpoints: Vec<EndpointOverride>,
+    pub descriptors: Vec<KnownDescriptors>,
+}
+
+impl Overrides {
+    /// Set the class of an endpoint, replacing any set before, or clear it
+    /// if no class is given.
+    pub fn set_endpoint(&mut self,
+                        device: DeviceAddr,
+                        endpoint: EndpointAddr,
+                        class: Option<(EndpointClass, Option<usize>)>)
+    {
+        self.endpoints.retain(|ovr|
+            !(ovr.device == device && ovr.endpoint == endpoint));
+        if let Some((class, max_packet_size)) = class {
+            self.endpoints.push(EndpointOverride {
+                device,
+                endpoint,
+                class,
+                max_packet_size,
+            });
+        }
+    }
+
+    /// The class set for an endpoint, if any.
+    pub fn endpoint(&self, device: DeviceAddr, endpoint: EndpointAddr)
+        -> Option<&EndpointOverride>
+    {
+        self.endpoints
+            .iter()
+            .find(|ovr| ovr.device == device && ovr.endpoint == endpoint)
+    }
+
+    /// Set the descriptors of a device, replacing any set before.
+    pub fn set_descriptors(&mut self, descriptors: KnownDescriptors) {
+        self.descriptors.retain(|known| known.device != descriptors.device);
+        self.descriptors.push(descriptors);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty() && self.descriptors.is_empty()
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_override() {
+        let ovr = EndpointOverride::parse("5.0x83=CDC-Data").unwrap();
+        assert_eq!(ovr.device, DeviceAddr(5));
+        assert_eq!(ovr.endpoint, EndpointAddr(0x83));
+        assert_eq!(ovr.class, EndpointClass::CdcData);
+        assert_eq!(ovr.max_packet_size, None);
+        assert_eq!(ovr.class.endpoint_type(), EndpointType::Bulk);
+        assert_eq!(ovr.to_string(), "5.0x83=cdc-data");
+
+        let ovr = EndpointOverride::parse("12.2=hid:64").unwrap();
+        assert_eq!(ovr.endpoint, EndpointAddr(0x02));
+        assert_eq!(ovr.max_packet_size, Some(64));
+        assert_eq!(ovr.to_string(), "12.0x02=hid:64");
+
+        for invalid in ["5=hid", "5.0x83", "128.1=hid", "5.0x90=hid",
+                        "5.0x80=bulk", "5.1=modem", "5.1=bulk:big"]
+        {
+            assert!(EndpointOverride::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,1054 @@
+//! Reading and writing of captured packets in pcap files.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs::{File, remove_file};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use anyhow::{Context, Error, bail};
+use pcap_file::{
+    DataLink,
+    TsResolution,
+    pcap::{PcapHeader, PcapReader, PcapWriter, RawPcapPacket},
+    pcapng::{
+        Block,
+        PcapNgReader,
+        blocks::interface_description::{
+            InterfaceDescriptionBlock,
+            InterfaceDescriptionOption,
+        },
+    },
+};
+
+use crate::capture::Timestamp;
+use crate::clock::ClockModel;
+use crate::metadata::Metadata;
+use crate::usb::DeviceAddr;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+// Magic number at the start of a zstd compressed stream.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+// Compression level used when saving compressed captures.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Header for pcap files written from a capture.
+pub fn header() -> PcapHeader {
+    PcapHeader {
+        datalink: DataLink::USB_2_0,
+        ts_resolution: TsResolution::NanoSecond,
+        .. PcapHeader::default()
+    }
+}
+
+/// Timestamp of a packet read from a pcap file with the given header.
+pub fn timestamp(header: &PcapHeader, packet: &RawPcapPacket) -> Timestamp {
+    let ns_per_frac = match header.ts_resolution {
+        TsResolution::MicroSecond => 1000,
+        TsResolution::NanoSecond => 1,
+    };
+    packet.ts_sec as u64 * NS_PER_SEC + packet.ts_frac as u64 * ns_per_frac
+}
+
+/// Construct a pcap record for a packet, to be written with `header()`.
+pub fn record<'a>(bytes: impl Into<Cow<'a, [u8]>>, timestamp: Timestamp)
+    -> Result<RawPcapPacket<'a>, Error>
+{
+    let bytes = bytes.into();
+    let length: u32 = bytes
+        .len()
+        .try_into()
+        .context("Packet too large for pcap file")?;
+    Ok(RawPcapPacket {
+        ts_sec: (timestamp / NS_PER_SEC)
+            .try_into()
+            .context("Timestamp too large for pcap file")?,
+        ts_frac: (timestamp % NS_PER_SEC) as u32,
+        incl_len: length,
+        orig_len: length,
+        data: bytes,
+    })
+}
+
+// Link type for USB 2.0 packets, in pcapng interface descriptions.
+const LINKTYPE_USB_2_0: u16 = 288;
+
+// Block types used in pcapng files.
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+
+// Options describing a pcapng interface.
+const PCAPNG_IF_DESCRIPTION: u16 = 3;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+// Options common to all pcapng blocks.
+const PCAPNG_OPT_ENDOFOPT: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+
+/// Whether a capture file should be written in pcapng format.
+///
+/// This is the case if its name ends in `.pcapng`, ignoring any `.zst`
+/// compression suffix.
+pub fn is_pcapng_path(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    let name = name.strip_suffix(".zst").unwrap_or(&name);
+    name.ends_with(".pcapng")
+}
+
+/// Path of the file for one device's packets when a capture is split by
+/// device, made by adding the device address to the given path.
+///
+/// For example, the packets of device 5 for `capture.pcapng.zst` are
+/// written to `capture-device-5.pcapng.zst`.
+pub fn device_path(path: &Path, address: DeviceAddr) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    let (name, compressed) = match name.strip_suffix(".zst") {
+        Some(name) => (name, ".zst"),
+        None => (name.as_str(), ""),
+    };
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() =>
+            (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    path.with_file_name(
+        format!("{stem}-device-{address}{extension}{compressed}"))
+}
+
+/// Writes packets to a pcapng file, with nanosecond timestamps.
+pub struct PcapNgWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapNgWriter<W> {
+    /// Start a pcapng file, with a single USB 2.0 interface.
+    pub fn new(writer: W) -> Result<Self, Error> {
+        PcapNgWriter::with_metadata(writer, &Metadata::new())
+    }
+
+    /// Start a pcapng file, with a single USB 2.0 interface, saving the
+    /// details of the capture as comments in the section header and the
+    /// device under test as the description of the interface.
+    pub fn with_metadata(writer: W, metadata: &Metadata)
+        -> Result<Self, Error>
+    {
+        let mut pcapng = PcapNgWriter { writer };
+        let mut section = Vec::new();
+        section.extend_from_slice(&0x1A2B3C4D_u32.to_le_bytes());
+        section.extend_from_slice(&1_u16.to_le_bytes());
+        section.extend_from_slice(&0_u16.to_le_bytes());
+        section.extend_from_slice(&(-1_i64).to_le_bytes());
+        let comments = metadata.section_comments();
+        for comment in &comments {
+            push_option(&mut section, PCAPNG_OPT_COMMENT, comment.as_bytes())?;
+        }
+        if !comments.is_empty() {
+            push_option(&mut section, PCAPNG_OPT_ENDOFOPT, &[])?;
+        }
+        pcapng.write_block(PCAPNG_SECTION_HEADER, &section)?;
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_USB_2_0.to_le_bytes());
+        interface.extend_from_slice(&0_u16.to_le_bytes());
+        interface.extend_from_slice(&0_u32.to_le_bytes());
+        if let Some(device) = metadata.device_description() {
+            push_option(&mut interface, PCAPNG_IF_DESCRIPTION,
+                        device.as_bytes())?;
+        }
+        push_option(&mut interface, PCAPNG_IF_TSRESOL, &[9])?;
+        push_option(&mut interface, PCAPNG_OPT_ENDOFOPT, &[])?;
+        pcapng.write_block(PCAPNG_INTERFACE_DESCRIPTION, &interface)?;
+        Ok(pcapng)
+    }
+
+    /// Write a packet captured at the given time.
+    pub fn write_packet(&mut self, bytes: &[u8], timestamp: Timestamp)
+        -> Result<(), Error>
+    {
+        self.write_packet_with_comment(bytes, timestamp, None)
+    }
+
+    /// Write a packet captured at the given time, with an optional comment.
+    pub fn write_packet_with_comment(&mut self,
+                                     bytes: &[u8],
+                                     timestamp: Timestamp,
+                                     comment: Option<&str>)
+        -> Result<(), Error>
+    {
+        let length: u32 = bytes
+            .len()
+            .try_into()
+            .context("Packet too large for pcapng file")?;
+        let mut packet = Vec::with_capacity(20 + bytes.len() + 3);
+        packet.extend_from_slice(&0_u32.to_le_bytes());
+        packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(bytes);
+        packet.resize((packet.len() + 3) & !3, 0);
+        if let Some(comment) = comment {
+            push_option(&mut packet, PCAPNG_OPT_COMMENT, comment.as_bytes())?;
+            push_option(&mut packet, PCAPNG_OPT_ENDOFOPT, &[])?;
+        }
+        self.write_block(PCAPNG_ENHANCED_PACKET, &packet)
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Consume this writer, returning the underlying writer.
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8])
+        -> Result<(), Error>
+    {
+        let length = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// Append an option to the body of a pcapng block, padding its value to a
+/// multiple of four bytes.
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8])
+    -> Result<(), Error>
+{
+    let length: u16 = value
+        .len()
+        .try_into()
+        .context("Option too long for pcapng file")?;
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&length.to_le_bytes());
+    body.extend_from_slice(value);
+    body.resize((body.len() + 3) & !3, 0);
+    Ok(())
+}
+
+/// A file to which packets are written, in pcap or pcapng format.
+pub enum PacketFileWriter {
+    Pcap(PcapWriter<CaptureFileWriter>),
+    PcapNg(PcapNgWriter<CaptureFileWriter>),
+}
+
+impl PacketFileWriter {
+    /// Create a file, choosing the format from its name.
+    pub fn create(path: &Path) -> Result<PacketFileWriter, Error> {
+        PacketFileWriter::create_with_metadata(path, &Metadata::new())
+    }
+
+    /// Create a file, choosing the format from its name, and saving the
+    /// details of the capture if it is a pcapng file.
+    pub fn create_with_metadata(path: &Path, metadata: &Metadata)
+        -> Result<PacketFileWriter, Error>
+    {
+        let writer = CaptureFileWriter::create(path)?;
+        Ok(if is_pcapng_path(path) {
+            PacketFileWriter::PcapNg(
+                PcapNgWriter::with_metadata(writer, metadata)?)
+        } else {
+            PacketFileWriter::Pcap(PcapWriter::with_header(writer, header())?)
+        })
+    }
+
+    /// Whether comments can be stored with packets in this file.
+    pub fn has_comments(&self) -> bool {
+        matches!(self, PacketFileWriter::PcapNg(_))
+    }
+
+    /// Write a packet, with a comment if the format supports it.
+    pub fn write_packet(&mut self,
+                        bytes: &[u8],
+                        timestamp: Timestamp,
+                        comment: Option<&str>)
+        -> Result<(), Error>
+    {
+        match self {
+            PacketFileWriter::Pcap(writer) => {
+                writer.write_raw_packet(&record(bytes, timestamp)?)?;
+            },
+            PacketFileWriter::PcapNg(writer) => {
+                writer.write_packet_with_comment(bytes, timestamp, comment)?;
+            },
+        };
+        Ok(())
+    }
+
+    /// Complete the file, flushing any remaining data.
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            PacketFileWriter::Pcap(writer) => writer.into_writer().finish(),
+            PacketFileWriter::PcapNg(writer) => writer.into_writer().finish(),
+        }
+    }
+}
+
+/// Wraps a reader, adding the number of bytes read from it to a counter.
+pub struct CountingReader<R> {
+    inner: R,
+    counter: &'static AtomicU64,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, counter: &'static AtomicU64) -> Self {
+        CountingReader { inner, counter }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.counter.fetch_add(count as u64, Ordering::Relaxed);
+        Ok(count)
+    }
+}
+
+/// Wraps a writer, adding the number of bytes written to it to a counter.
+pub struct CountingWriter<W> {
+    inner: W,
+    counter: &'static AtomicU64,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, counter: &'static AtomicU64) -> Self {
+        CountingWriter { inner, counter }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.counter.fetch_add(count as u64, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wrap a reader for a capture file, decompressing it if necessary.
+pub fn decompress<R>(reader: R) -> Result<Box<dyn BufRead + Send>, Error>
+    where R: Read + Send + 'static
+{
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        let decoder = zstd::Decoder::with_buffer(reader)?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Writer for a capture file, which may be compressed.
+pub enum CaptureFileWriter {
+    Plain(BufWriter<File>),
+    Compressed(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl CaptureFileWriter {
+    /// Create a capture file, compressing it if its name ends in `.zst`.
+    pub fn create(path: &Path) -> Result<CaptureFileWriter, Error> {
+        let file = File::create(path).with_context(|| format!(
+            "Failed to create {}", path.display()))?;
+        let writer = BufWriter::new(file);
+        if path.extension() == Some(OsStr::new("zst")) {
+            let encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+            Ok(CaptureFileWriter::Compressed(encoder))
+        } else {
+            Ok(CaptureFileWriter::Plain(writer))
+        }
+    }
+
+    /// Complete the file, flushing any remaining data.
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            CaptureFileWriter::Plain(mut writer) => writer.flush()?,
+            CaptureFileWriter::Compressed(encoder) => encoder.finish()?.flush()?,
+        };
+        Ok(())
+    }
+}
+
+impl Write for CaptureFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            CaptureFileWriter::Plain(writer) => writer.write(buf),
+            CaptureFileWriter::Compressed(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            CaptureFileWriter::Plain(writer) => writer.flush(),
+            CaptureFileWriter::Compressed(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A packet read by a `MergeReader`.
+pub struct MergedPacket {
+    /// Index of the input the packet was read from.
+    pub source: usize,
+    pub timestamp: Timestamp,
+    pub data: Vec<u8>,
+}
+
+// Magic number at the start of a pcapng file, being the type of its
+// section header block.
+const PCAPNG_MAGIC: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+
+// Timestamp resolution of a pcapng interface without an if_tsresol
+// option, as a power of ten.
+const PCAPNG_DEFAULT_TSRESOL: u8 = 6;
+
+/// Timing of a pcapng interface, from its description.
+struct PcapNgInterface {
+    datalink: DataLink,
+    resolution: u8,
+    offset: u64,
+}
+
+impl PcapNgInterface {
+    fn new(description: &InterfaceDescriptionBlock) -> PcapNgInterface {
+        let mut interface = PcapNgInterface {
+            datalink: description.linktype,
+            resolution: PCAPNG_DEFAULT_TSRESOL,
+            offset: 0,
+        };
+        for option in &description.options {
+            match option {
+                InterfaceDescriptionOption::IfTsResol(resolution) =>
+                    interface.resolution = *resolution,
+                InterfaceDescriptionOption::IfTsOffset(offset) =>
+                    interface.offset = *offset,
+                _ => {}
+            }
+        }
+        interface
+    }
+
+    /// Timestamp of a packet, given its time in units of this interface.
+    ///
+    /// The resolution is a negative power of ten, or of two if its top
+    /// bit is set, and the offset is in seconds.
+    fn timestamp(&self, units: u64) -> Result<Timestamp, Error> {
+        let ns_per_sec = NS_PER_SEC as u128;
+        let exponent = (self.resolution & 0x7F) as u32;
+        let units = units as u128;
+        let nanoseconds = if self.resolution & 0x80 != 0 {
+            (units * ns_per_sec).checked_shr(exponent).unwrap_or(0)
+        } else if exponent <= 9 {
+            units * 10u128.pow(9 - exponent)
+        } else {
+            10u128
+                .checked_pow(exponent - 9)
+                .map_or(0, |divisor| units / divisor)
+        };
+        (self.offset as u128 * ns_per_sec + nanoseconds)
+            .try_into()
+            .context("Timestamp too large for capture")
+    }
+}
+
+enum InputReader {
+    Pcap(PcapReader<Box<dyn BufRead + Send>>, PcapHeader),
+    PcapNg {
+        reader: PcapNgReader<Box<dyn BufRead + Send>>,
+        interfaces: Vec<PcapNgInterface>,
+        // Timestamp of the last packet, for simple packet blocks which
+        // have none of their own.
+        last: Timestamp,
+    },
+}
+
+struct MergeInput {
+    reader: InputReader,
+    datalink: Option<DataLink>,
+    next: Option<(Timestamp, Vec<u8>)>,
+    /// Time of the first packet, from which clock drift is measured.
+    origin: Timestamp,
+    clock: ClockModel,
+}
+
+impl MergeInput {
+    fn new(mut reader: Box<dyn BufRead + Send>) -> Result<MergeInput, Error> {
+        let (reader, datalink) =
+            if reader.fill_buf()?.starts_with(&PCAPNG_MAGIC) {
+                let reader = InputReader::PcapNg {
+                    reader: PcapNgReader::new(reader)?,
+                    interfaces: Vec::new(),
+                    last: 0,
+                };
+                (reader, None)
+            } else {
+                let reader = PcapReader::new(reader)?;
+                let header = reader.header();
+                (InputReader::Pcap(reader, header), Some(header.datalink))
+            };
+        let mut input = MergeInput {
+            reader,
+            datalink,
+            next: None,
+            origin: 0,
+            clock: ClockModel::default(),
+        };
+        input.advance()?;
+        if let Some((time, _)) = &input.next {
+            input.origin = *time;
+        }
+        Ok(input)
+    }
+
+    /// Time of the next packet, corrected to the reference clock.
+    fn next_time(&self) -> Option<Timestamp> {
+        self.next
+            .as_ref()
+            .map(|(time, _)| self.clock.apply(self.origin, *time))
+    }
+
+    fn advance(&mut self) -> Result<(), Error> {
+        let MergeInput { reader, datalink, next, .. } = self;
+        *next = match reader {
+            InputReader::Pcap(reader, header) =>
+                match reader.next_raw_packet() {
+                    Some(result) => {
+                        let packet = result?;
+                        let timestamp = timestamp(header, &packet);
+                        Some((timestamp, packet.data.into_owned()))
+                    },
+                    None => None,
+                },
+            InputReader::PcapNg { reader, interfaces, last } => loop {
+                let block = match reader.next_block() {
+                    Some(result) => result?,
+                    None => break None,
+                };
+                match block {
+                    Block::SectionHeader(_) => interfaces.clear(),
+                    Block::InterfaceDescription(description) => {
+                        let interface = PcapNgInterface::new(&description);
+                        match datalink {
+                            Some(link) if *link != interface.datalink =>
+                                bail!("Capture file has interfaces with \
+                                       different link types"),
+                            _ => *datalink = Some(interface.datalink),
+                        }
+                        interfaces.push(interface);
+                    },
+                    Block::EnhancedPacket(packet) => {
+                        let interface = interfaces
+                            .get(packet.interface_id as usize)
+                            .context("Packet from undescribed interface")?;
+                        *last = interface.timestamp(
+                            packet.timestamp.as_nanos() as u64)?;
+                        break Some((*last, packet.data.into_owned()));
+                    },
+                    Block::SimplePacket(packet) =>
+                        break Some((*last, packet.data.into_owned())),
+                    _ => {}
+                }
+            },
+        };
+        Ok(())
+    }
+}
+
+/// Reads packets from several capture files in timestamp order.
+///
+/// Each file may be in pcap or pcapng format. Packets with equal
+/// timestamps are returned in the order of the inputs. The timestamps of
+/// each input may be corrected for the drift of its clock, in which case
+/// packets are ordered and returned by their corrected times.
+pub struct MergeReader {
+    inputs: Vec<MergeInput>,
+}
+
+impl MergeReader {
+    pub fn new(readers: Vec<Box<dyn BufRead + Send>>)
+        -> Result<MergeReader, Error>
+    {
+        let inputs = readers
+            .into_iter()
+            .map(MergeInput::new)
+            .collect::<Result<_, Error>>()?;
+        Ok(MergeReader { inputs })
+    }
+
+    /// Link type of the input with the given index.
+    ///
+    /// A pcapng input with no interfaces described before its first
+    /// packet is taken to hold USB 2.0 packets.
+    pub fn datalink(&self, source: usize) -> DataLink {
+        self.inputs[source].datalink.unwrap_or(DataLink::USB_2_0)
+    }
+
+    /// Set the model correcting the timestamps of an input.
+    pub fn set_clock(&mut self, source: usize, clock: ClockModel) {
+        self.inputs[source].clock = clock;
+    }
+
+    /// Read the next packet from any input.
+    pub fn next_packet(&mut self) -> Result<Option<MergedPacket>, Error> {
+        let source = self.inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, input)|
+                input.next_time().map(|timestamp| (timestamp, i)))
+            .min();
+        let (timestamp, source) = match source {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+        let input = &mut self.inputs[source];
+        let (_, data) = input.next
+            .take()
+            .context("Merge input has no packet")?;
+        input.advance()?;
+        Ok(Some(MergedPacket { source, timestamp, data }))
+    }
+}
+
+// How often buffered packets are flushed to disk while streaming.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Size of the pcap file header.
+const HEADER_SIZE: u64 = 24;
+
+// Size of the pcap record header preceding each packet.
+const RECORD_HEADER_SIZE: u64 = 16;
+
+/// Limits on the files written while streaming a capture to disk.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RotationLimits {
+    /// Size in bytes after which a new file is started.
+    pub max_file_size: Option<u64>,
+    /// Number of files after which the oldest is removed.
+    pub max_files: Option<usize>,
+}
+
+/// Writes packets to disk as they are captured.
+///
+/// If a maximum file size is set, packets are written to a numbered
+/// series of files alongside the requested path, rather than to the
+/// path itself.
+pub struct StreamWriter {
+    path: PathBuf,
+    limits: RotationLimits,
+    writer: PcapWriter<FlushingWriter>,
+    files: VecDeque<PathBuf>,
+    file_number: u32,
+    file_size: u64,
+}
+
+/// A buffered file writer whose buffer is flushed to disk every
+/// `FLUSH_INTERVAL` by a background thread, so that a file being streamed
+/// can be read as it grows, even while no packets are arriving.
+struct FlushingWriter {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl FlushingWriter {
+    fn new(file: File) -> FlushingWriter {
+        let writer = Arc::new(Mutex::new(BufWriter::new(file)));
+        let weak = Arc::downgrade(&writer);
+        // The thread ends once the writer is dropped. Any error is left to
+        // be reported by the next write or flush.
+        spawn(move || loop {
+            sleep(FLUSH_INTERVAL);
+            match weak.upgrade() {
+                Some(writer) => if let Ok(mut writer) = writer.lock() {
+                    let _ = writer.flush();
+                },
+                None => break,
+            }
+        });
+        FlushingWriter { writer }
+    }
+
+    fn lock(&self) -> std::io::Result<MutexGuard<'_, BufWriter<File>>> {
+        self.writer
+            .lock()
+            .map_err(|_| std::io::Error::new(
+                std::io::ErrorKind::Other, "Stream writer lock poisoned"))
+    }
+}
+
+impl Write for FlushingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+impl StreamWriter {
+    pub fn new(path: PathBuf, limits: RotationLimits)
+        -> Result<StreamWriter, Error>
+    {
+        let file_path = Self::file_path(&path, &limits, 0);
+        let writer = Self::create(&file_path)?;
+        Ok(StreamWriter {
+            path,
+            limits,
+            writer,
+            files: VecDeque::from([file_path]),
+            file_number: 0,
+            file_size: HEADER_SIZE,
+        })
+    }
+
+    pub fn write(&mut self, bytes: &[u8], timestamp: Timestamp)
+        -> Result<(), Error>
+    {
+        let size = RECORD_HEADER_SIZE + bytes.len() as u64;
+        if let Some(max_size) = self.limits.max_file_size {
+            if self.file_size > HEADER_SIZE &&
+                self.file_size + size > max_size
+            {
+                self.rotate()?;
+            }
+        }
+        self.writer.write_raw_packet(&record(bytes, timestamp)?)?;
+        self.file_size += size;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        self.writer.into_writer().flush()?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.file_number += 1;
+        let file_path =
+            Self::file_path(&self.path, &self.limits, self.file_number);
+        let writer = std::mem::replace(
+            &mut self.writer, Self::create(&file_path)?);
+        writer.into_writer().flush()?;
+        self.file_size = HEADER_SIZE;
+        self.files.push_back(file_path);
+        if let Some(max_files) = self.limits.max_files {
+            while self.files.len() > max_files.max(1) {
+                if let Some(oldest) = self.files.pop_front() {
+                    remove_file(&oldest).with_context(|| format!(
+                        "Failed to remove {}", oldest.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create(path: &Path) -> Result<PcapWriter<FlushingWriter>, Error> {
+        let file = File::create(path).with_context(|| format!(
+            "Failed to create {}", path.display()))?;
+        let writer = FlushingWriter::new(file);
+        Ok(PcapWriter::with_header(writer, header())?)
+    }
+
+    fn file_path(path: &Path, limits: &RotationLimits, number: u32)
+        -> PathBuf
+    {
+        if limits.max_file_size.is_none() {
+            return path.to_path_buf();
+        }
+        let stem = path
+            .file_stem()
+            .map_or_else(
+                || String::from("capture"),
+                |stem| stem.to_string_lossy().to_string());
+        let extension = path
+            .extension()
+            .map_or_else(
+                || String::from("pcap"),
+                |ext| ext.to_string_lossy().to_string());
+        path.with_file_name(format!("{stem}_{number:05}.{extension}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        let timestamp = 1_700_000_000_123_456_789;
+        let packet = record(vec![0xa5, 0x00, 0x10], timestamp).unwrap();
+        assert_eq!(packet.ts_sec, 1_700_000_000);
+        assert_eq!(packet.ts_frac, 123_456_789);
+        assert_eq!(super::timestamp(&header(), &packet), timestamp);
+        let micro_header = PcapHeader::default();
+        assert_eq!(super::timestamp(&micro_header, &packet),
+                   1_700_000_000_000_000_000 + 123_456_789_000);
+    }
+
+    #[test]
+    fn test_stream_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = RotationLimits {
+            max_file_size: Some(HEADER_SIZE + 2 * (RECORD_HEADER_SIZE + 3)),
+            max_files: Some(2),
+        };
+        let mut writer =
+            StreamWriter::new(dir.path().join("test.pcap"), limits).unwrap();
+        for i in 0..7 {
+            writer.write(&[0xa5, i, 0x00], i as u64).unwrap();
+        }
+        writer.finish().unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["test_00002.pcap", "test_00003.pcap"]);
+        let last = std::fs::read(dir.path().join("test_00003.pcap")).unwrap();
+        assert_eq!(last.len() as u64, HEADER_SIZE + RECORD_HEADER_SIZE + 3);
+    }
+
+    #[test]
+    fn test_stream_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pcap");
+        let mut writer =
+            StreamWriter::new(path.clone(), RotationLimits::default()).unwrap();
+        writer.write(&[0xa5, 0x00, 0x10], 0).unwrap();
+        // The packet reaches the file without any further writes.
+        let length = HEADER_SIZE + RECORD_HEADER_SIZE + 3;
+        let deadline = std::time::Instant::now() + 3 * FLUSH_INTERVAL;
+        while std::fs::metadata(&path).unwrap().len() < length {
+            assert!(std::time::Instant::now() < deadline,
+                    "Packet was not flushed to disk");
+            sleep(Duration::from_millis(10));
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let mut pcapng = PcapNgWriter::new(Vec::new()).unwrap();
+        let timestamp = 0x1234_5678_9ABC_DEF0;
+        pcapng.write_packet(&[0xa5, 0x01, 0x02, 0x03, 0x04], timestamp)
+            .unwrap();
+        pcapng.write_packet_with_comment(&[0xd2], timestamp, Some("Hello"))
+            .unwrap();
+        let bytes = pcapng.into_writer();
+        let word = |offset: usize| u32::from_le_bytes(
+            bytes[offset..offset + 4].try_into().unwrap());
+        // Walk the blocks, checking their types and lengths.
+        let mut offset = 0;
+        let mut types = Vec::new();
+        while offset < bytes.len() {
+            let length = word(offset + 4) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(word(offset + length - 4) as usize, length);
+            types.push((word(offset), offset));
+            offset += length;
+        }
+        assert_eq!(offset, bytes.len());
+        assert_eq!(types.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+                   [PCAPNG_SECTION_HEADER,
+                    PCAPNG_INTERFACE_DESCRIPTION,
+                    PCAPNG_ENHANCED_PACKET,
+                    PCAPNG_ENHANCED_PACKET]);
+        let packet = types[2].1;
+        assert_eq!(word(packet + 12), 0x1234_5678);
+        assert_eq!(word(packet + 16), 0x9ABC_DEF0);
+        assert_eq!(word(packet + 20), 5);
+        assert_eq!(&bytes[packet + 28 .. packet + 33],
+                   &[0xa5, 0x01, 0x02, 0x03, 0x04]);
+        // The comment option follows the padded packet data.
+        let packet = types[3].1;
+        assert_eq!(word(packet + 4), 52);
+        assert_eq!(word(packet + 32), 0x0005_0001);
+        assert_eq!(&bytes[packet + 36 .. packet + 41], b"Hello");
+        assert_eq!(word(packet + 44), 0);
+    }
+
+    #[test]
+    fn test_pcapng_metadata() {
+        let metadata = Metadata {
+            title: String::from("Boot"),
+            device: String::from("Keyboard"),
+            ..Metadata::new()
+        };
+        let bytes = PcapNgWriter::with_metadata(Vec::new(), &metadata)
+            .unwrap()
+            .into_writer();
+        let word = |offset: usize| u32::from_le_bytes(
+            bytes[offset..offset + 4].try_into().unwrap());
+        // The section header's comment follows its fixed fields.
+        assert_eq!(word(0), PCAPNG_SECTION_HEADER);
+        let section_length = word(4) as usize;
+        assert_eq!(section_length, 48);
+        assert_eq!(word(24), 0x000B_0001);
+        assert_eq!(&bytes[28..39], b"Title: Boot");
+        assert_eq!(word(40), 0);
+        // The interface's description comes before its resolution.
+        let interface = section_length;
+        assert_eq!(word(interface), PCAPNG_INTERFACE_DESCRIPTION);
+        assert_eq!(word(interface + 16), 0x0008_0003);
+        assert_eq!(&bytes[interface + 20..interface + 28], b"Keyboard");
+        assert_eq!(word(interface + 28), 0x0001_0009);
+        assert_eq!(bytes[interface + 32], 9);
+    }
+
+    #[test]
+    fn test_pcapng_path() {
+        assert!(is_pcapng_path(Path::new("capture.pcapng")));
+        assert!(is_pcapng_path(Path::new("capture.pcapng.zst")));
+        assert!(!is_pcapng_path(Path::new("capture.pcap")));
+        assert!(!is_pcapng_path(Path::new("capture.pcap.zst")));
+    }
+
+    #[test]
+    fn test_device_path() {
+        let address = DeviceAddr(5);
+        assert_eq!(device_path(Path::new("/tmp/capture.pcapng"), address),
+                   Path::new("/tmp/capture-device-5.pcapng"));
+        assert_eq!(device_path(Path::new("capture.pcap.zst"), address),
+                   Path::new("capture-device-5.pcap.zst"));
+        assert_eq!(device_path(Path::new("bus.2.pcap"), address),
+                   Path::new("bus.2-device-5.pcap"));
+        assert_eq!(device_path(Path::new("capture"), address),
+                   Path::new("capture-device-5"));
+    }
+
+    #[test]
+    fn test_merge_order() {
+        let write = |times: &[u64]| -> Box<dyn BufRead + Send> {
+            let mut pcap = PcapWriter::with_header(Vec::new(), header())
+                .unwrap();
+            for &time in times {
+                pcap.write_raw_packet(&record(vec![time as u8], time)
+                    .unwrap()).unwrap();
+            }
+            Box::new(std::io::Cursor::new(pcap.into_writer()))
+        };
+        let mut merge = MergeReader::new(vec![
+            write(&[1, 4, 4, 9]),
+            write(&[]),
+            write(&[0, 4, 10]),
+        ]).unwrap();
+        let mut order = Vec::new();
+        while let Some(packet) = merge.next_packet().unwrap() {
+            assert_eq!(packet.data, [packet.timestamp as u8]);
+            order.push((packet.source, packet.timestamp));
+        }
+        assert_eq!(order, [
+            (2, 0), (0, 1), (0, 4), (0, 4), (2, 4), (0, 9), (2, 10)]);
+    }
+
+    #[test]
+    fn test_merge_pcapng() {
+        let mut pcap = PcapWriter::with_header(Vec::new(), header()).unwrap();
+        pcap.write_raw_packet(&record(vec![1], 1_000_000_002).unwrap())
+            .unwrap();
+        let mut pcapng = PcapNgWriter::new(Vec::new()).unwrap();
+        pcapng.write_packet(&[0], 1_000_000_001).unwrap();
+        pcapng.write_packet_with_comment(&[2], 1_000_000_003, Some("Hi"))
+            .unwrap();
+        let mut merge = MergeReader::new(vec![
+            Box::new(std::io::Cursor::new(pcap.into_writer())),
+            Box::new(std::io::Cursor::new(pcapng.into_writer())),
+        ]).unwrap();
+        assert_eq!(merge.datalink(1), DataLink::USB_2_0);
+        let mut order = Vec::new();
+        while let Some(packet) = merge.next_packet().unwrap() {
+            order.push((packet.source, packet.timestamp, packet.data[0]));
+        }
+        assert_eq!(order, [
+            (1, 1_000_000_001, 0),
+            (0, 1_000_000_002, 1),
+            (1, 1_000_000_003, 2)]);
+    }
+
+    #[test]
+    fn test_merge_clock() {
+        let write = |times: &[u64]| -> Box<dyn BufRead + Send> {
+            let mut pcap = PcapWriter::with_header(Vec::new(), header())
+                .unwrap();
+            for &time in times {
+                pcap.write_raw_packet(&record(vec![0], time).unwrap())
+                    .unwrap();
+            }
+            Box::new(std::io::Cursor::new(pcap.into_writer()))
+        };
+        let mut merge = MergeReader::new(vec![
+            write(&[1000, 2000, 3000]),
+            write(&[10_000, 10_500, 11_000]),
+        ]).unwrap();
+        merge.set_clock(1, ClockModel { offset: -8_600, drift: 200_000.0 });
+        let mut order = Vec::new();
+        while let Some(packet) = merge.next_packet().unwrap() {
+            order.push((packet.source, packet.timestamp));
+        }
+        assert_eq!(order, [
+            (0, 1000), (1, 1400), (0, 2000), (1, 2000), (1, 2600), (0, 3000)]);
+    }
+
+    #[test]
+    fn test_pcapng_timestamp() {
+        let interface = |resolution, offset| PcapNgInterface {
+            datalink: DataLink::USB_2_0,
+            resolution,
+            offset,
+        };
+        // Microseconds, as when no resolution is given.
+        assert_eq!(interface(6, 0).timestamp(1_500_001).unwrap(),
+                   1_500_001_000);
+        assert_eq!(interface(9, 0).timestamp(1_500_001).unwrap(),
+                   1_500_001);
+        assert_eq!(interface(12, 0).timestamp(1_500_001).unwrap(), 1_500);
+        // Units of 2^-10 seconds, with an offset of 100 seconds.
+        assert_eq!(interface(0x8A, 100).timestamp(1536).unwrap(),
+                   101_500_000_000);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        use pcap_file::pcap::PcapReader;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pcap.zst");
+        let writer = CaptureFileWriter::create(&path).unwrap();
+        let mut pcap = PcapWriter::with_header(writer, header()).unwrap();
+        for i in 0..100 {
+            pcap.write_raw_packet(&record(vec![0xa5, i, 0x00], i as u64)
+                .unwrap()).unwrap();
+        }
+        pcap.into_writer().finish().unwrap();
+        let file = File::open(&path).unwrap();
+        let mut magic = [0; 4];
+        File::open(&path).unwrap().read_exact(&mut magic).unwrap();
+        assert_eq!(magic, ZSTD_MAGIC);
+        let mut pcap = PcapReader::new(decompress(file).unwrap()).unwrap();
+        let header = pcap.header();
+        for i in 0..100 {
+            let packet = pcap.next_raw_packet().unwrap().unwrap();
+            assert_eq!(timestamp(&header, &packet), i as u64);
+            assert_eq!(packet.data.as_ref(), &[0xa5, i, 0x00]);
+        }
+        assert!(pcap.next_raw_packet().is_none());
+    }
+}
@@ -0,0 +1,1037 @@
+//! Reports on how each device in a capture was enumerated.
+//!
+//! A report describes the speed of the bus each device was seen on, the
+//! descriptors and strings read from it, the configuration selected, the
+//! class drivers its interfaces imply, how long each request of its
+//! enumeration took, and anything in its enumeration which looks wrong.
+//! Any OTG events, such as role swaps, are listed before the devices.
+//! It can be written as Markdown or as a standalone HTML page, for
+//! documentation or as evidence for compliance testing.
+//!
+//! A compliance report instead lists, for each device, which of the
+//! chapter 9 checks of [`crate::compliance`] it passed or failed, and a
+//! throughput report whether each endpoint met its target of
+//! [`crate::throughput`].
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Error;
+
+use crate::capture::{
+    milliseconds,
+    CaptureReader,
+    DeviceId,
+    PacketId,
+    Timestamp,
+};
+use crate::compliance::{check_device, Outcome};
+use crate::otg::find_events;
+use crate::speeds::BusSpeeds;
+use crate::suspend::IdlePeriods;
+use crate::throughput::ThroughputResult;
+use crate::usb::{
+    ConfigNum,
+    Configuration,
+    ControlResult,
+    ControlTransfer,
+    DescriptorType,
+    DeviceDescriptor,
+    InterfaceDescriptor,
+    RequestType,
+    StandardRequest,
+    StringId,
+    UTF16ByteVec,
+};
+use crate::util::fmt_size;
+use crate::vec_map::VecMap;
+
+/// Time beyond which a GET_DESCRIPTOR request is reported as slow, in
+/// nanoseconds. Devices usually answer these within a millisecond or two.
+const SLOW_DESCRIPTOR_TIME: u64 = 5_000_000;
+
+/// Formats in which a report can be written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    /// The format to use for a file, which is HTML if its name ends in
+    /// `.html` or `.htm`, and Markdown otherwise.
+    pub fn from_path(path: &Path) -> ReportFormat {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("html" | "htm") => ReportFormat::Html,
+            _ => ReportFormat::Markdown,
+        }
+    }
+}
+
+/// A formatted report, made up of blocks of content shown in order.
+pub struct Report {
+    title: String,
+    blocks: Vec<Block>,
+}
+
+enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    Table(Vec<(String, String)>),
+    List(Vec<String>),
+}
+
+impl Report {
+    /// The report as a Markdown document.
+    pub fn markdown(&self) -> String {
+        let mut output = format!("# {}\n\n", self.title);
+        for block in &self.blocks {
+            match block {
+                Block::Heading(level, text) => {
+                    output += &format!("{} {text}\n\n", "#".repeat(*level));
+                },
+                Block::Paragraph(text) => {
+                    output += &format!("{text}\n\n");
+                },
+                Block::Table(rows) => {
+                    output += "| Field | Value |\n|---|---|\n";
+                    for (field, value) in rows {
+                        output += &format!("| {} | {} |\n",
+                            markdown_cell(field), markdown_cell(value));
+                    }
+                    output += "\n";
+                },
+                Block::List(items) => {
+                    for item in items {
+                        output += &format!("- {item}\n");
+                    }
+                    output += "\n";
+                },
+            }
+        }
+        output
+    }
+
+    /// The report as a standalone HTML page.
+    pub fn html(&self) -> String {
+        let title = html_escape(&self.title);
+        let mut output = format!("\
+<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 1em 2em; }}
+table {{ border-collapse: collapse; margin-bottom: 1em; }}
+th, td {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+");
+        for block in &self.blocks {
+            match block {
+                Block::Heading(level, text) => {
+                    output += &format!("<h{level}>{}</h{level}>\n",
+                                       html_escape(text));
+                },
+                Block::Paragraph(text) => {
+                    output += &format!("<p>{}</p>\n", html_escape(text));
+                },
+                Block::Table(rows) => {
+                    output += "<table>\n<tr><th>Field</th><th>Value</th></tr>\n";
+                    for (field, value) in rows {
+                        output += &format!("<tr><td>{}</td><td>{}</td></tr>\n",
+                            html_escape(field), html_escape(value));
+                    }
+                    output += "</table>\n";
+                },
+                Block::List(items) => {
+                    output += "<ul>\n";
+                    for item in items {
+                        output += &format!("<li>{}</li>\n", html_escape(item));
+                    }
+                    output += "</ul>\n";
+                },
+            }
+        }
+        output += "</body>\n</html>\n";
+        output
+    }
+
+    /// Write the report in the given format.
+    pub fn write(&self, format: ReportFormat, writer: &mut dyn Write)
+        -> Result<(), Error>
+    {
+        let text = match format {
+            ReportFormat::Markdown => self.markdown(),
+            ReportFormat::Html => self.html(),
+        };
+        writer.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn heading(&mut self, level: usize, text: String) {
+        self.blocks.push(Block::Heading(level, text));
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        self.blocks.push(Block::Paragraph(text.to_string()));
+    }
+}
+
+/// Report on the enumeration of each device seen in a capture.
+pub fn enumeration_report(capture: &mut CaptureReader)
+    -> Result<Report, Error>
+{
+    let mut report = Report {
+        title: "USB enumeration report".to_string(),
+        blocks: Vec::new(),
+    };
+    // Device 0 holds traffic to the default address, before devices have
+    // been given their own addresses, so is not reported on.
+    let device_count = capture.devices.len().saturating_sub(1);
+    report.paragraph(&match device_count {
+        0 => "No devices were seen in the capture.".to_string(),
+        1 => "1 device was seen in the capture.".to_string(),
+        n => format!("{n} devices were seen in the capture."),
+    });
+    let speeds = BusSpeeds::find(capture)?;
+    if speeds.changed() {
+        let first_time = capture.packet_time(PacketId::from(0))?;
+        let changes: Vec<String> = speeds.ranges
+            .iter()
+            .enumerate()
+            .map(|(index, range)| match index {
+                0 => format!("{} from the start", range.speed.name()),
+                _ => format!("{} from {:.9} s", range.speed.name(),
+                             range.start_time.saturating_sub(first_time)
+                                 as f64 / 1e9),
+            })
+            .collect();
+        report.paragraph(&format!(
+            "The bus changed speed during the capture, as a device was \
+             reset: {}.", changes.join(", then ")));
+    }
+    let idle = IdlePeriods::find(capture)?;
+    let events = find_events(capture, &idle)?;
+    if !events.is_empty() {
+        let first_time = capture.packet_time(PacketId::from(0))?;
+        report.paragraph("OTG events were seen in the capture:");
+        report.blocks.push(Block::List(events
+            .iter()
+            .map(|event| format!("{:.9} s: {}",
+                                 event.time.saturating_sub(first_time)
+                                     as f64 / 1e9,
+                                 event.description))
+            .collect()));
+    }
+    for index in 1..=device_count {
+        device_report(capture, DeviceId::from(index), &speeds, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// Report on which chapter 9 checks each device seen in a capture passed.
+pub fn compliance_report(capture: &mut CaptureReader)
+    -> Result<Report, Error>
+{
+    let mut report = Report {
+        title: "USB chapter 9 compliance report".to_string(),
+        blocks: Vec::new(),
+    };
+    let device_count = capture.devices.len().saturating_sub(1);
+    if device_count == 0 {
+        report.paragraph("No devices were seen in the capture.");
+    }
+    for index in 1..=device_count {
+        let device_id = DeviceId::from(index);
+        let name = capture.device_name(device_id)?;
+        let data = capture.device_data(&device_id)?;
+        report.heading(2, format!("{name}: {}", data.description()));
+        let results = check_device(capture, device_id)?;
+        let count = |wanted: fn(&Outcome) -> bool| results
+            .iter()
+            .filter(|(_, outcome)| wanted(outcome))
+            .count();
+        let passed = count(|outcome| *outcome == Outcome::Passed);
+        let failed = count(|outcome| matches!(outcome, Outcome::Failed(_)));
+        let untested = count(|outcome| *outcome == Outcome::Untested);
+        report.paragraph(&format!(
+            "{}: {passed} passed, {failed} failed, {untested} untested.",
+            match (failed, untested) {
+                (0, 0) => "PASS",
+                (0, _) => "INCOMPLETE",
+                _ => "FAIL",
+            }));
+        let rows = results
+            .iter()
+            .map(|(check, outcome)| (check.name().to_string(), match outcome {
+                Outcome::Passed => "Passed".to_string(),
+                Outcome::Failed(reasons) =>
+                    format!("Failed: {}", reasons.join("; ")),
+                Outcome::Untested =>
+                    format!("Untested. {}", check.guidance()),
+            }))
+            .collect();
+        report.blocks.push(Block::Table(rows));
+    }
+    Ok(report)
+}
+
+/// Report on whether the throughput on endpoints met their targets.
+pub fn throughput_report(capture: &mut CaptureReader,
+                         results: &[ThroughputResult])
+    -> Result<Report, Error>
+{
+    let mut report = Report {
+        title: "USB throughput report".to_string(),
+        blocks: Vec::new(),
+    };
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    report.paragraph(&match (failed, results.len()) {
+        (0, 1) => "PASS: the target was met.".to_string(),
+        (0, count) => format!("PASS: all {count} targets were met."),
+        (1, 1) => "FAIL: the target was not met.".to_string(),
+        (failed, count) => format!(
+            "FAIL: {failed} of {count} targets were not met."),
+    });
+    let first_time = match capture.packet_times.len() {
+        0 => 0,
+        _ => capture.packet_time(PacketId::from(0))?,
+    };
+    let seconds = |time: Timestamp|
+        format!("{:.9} s", time.saturating_sub(first_time) as f64 / 1e9);
+    let rate = |rate: u64| format!("{}/s", fmt_size(rate));
+    for result in results {
+        let target = &result.target;
+        let mut heading = result.target.to_string();
+        heading[..1].make_ascii_uppercase();
+        report.heading(2, heading);
+        let window = match target.window % 1_000_000_000 {
+            0 => format!("{} s", target.window / 1_000_000_000),
+            _ => milliseconds(target.window),
+        };
+        let mut rows = vec![
+            ("Target".to_string(), format!(
+                "{} over every {window} window", rate(target.rate))),
+            ("Data".to_string(), fmt_size(result.total)),
+        ];
+        if let (Some((first, last)), Some(average)) =
+            (result.active, result.average())
+        {
+            rows.push(("Active".to_string(), format!(
+                "{} from {}", milliseconds(last - first), seconds(first))));
+            rows.push(("Average".to_string(), rate(average)));
+        }
+        if let Some((start, slowest)) = result.slowest {
+            rows.push(("Slowest window".to_string(), format!(
+                "{} from {}", rate(slowest), seconds(start))));
+        }
+        rows.push(("Result".to_string(), match result.slowest {
+            _ if result.passed() => "Passed".to_string(),
+            Some(_) => "Failed: the slowest window fell short".to_string(),
+            None => "Failed: no data was carried".to_string(),
+        }));
+        report.blocks.push(Block::Table(rows));
+    }
+    Ok(report)
+}
+
+fn device_report(capture: &mut CaptureReader,
+                 device_id: DeviceId,
+                 speeds: &BusSpeeds,
+                 report: &mut Report)
+    -> Result<(), Error>
+{
+    let name = capture.device_name(device_id)?;
+    let data = capture.device_data(&device_id)?;
+    report.heading(2, format!("{name}: {}", data.description()));
+    let first_request = capture
+        .device_control_timeline(device_id)?
+        .first()
+        .map(|(_, start, _)| *start);
+    if let Some(speed) =
+        first_request.and_then(|time| speeds.speed_at_time(time))
+    {
+        report.paragraph(&format!("Seen on a {} bus.", speed.name()));
+    }
+    let strings = data.strings.load();
+    let configurations = data.configurations.load();
+    let selected = data.config_number.load().as_deref().copied();
+    let mut warnings = Vec::new();
+
+    // Device descriptor.
+    report.heading(3, "Device descriptor".to_string());
+    match data.device_descriptor.load().as_deref() {
+        Some(descriptor) => {
+            report.blocks.push(Block::Table(
+                device_fields(descriptor, &strings)));
+            device_warnings(descriptor, &strings, &mut warnings);
+        },
+        None => {
+            report.paragraph("The device descriptor was not seen.");
+            warnings.push(
+                "The device descriptor was not read from the device".into());
+        }
+    }
+
+    // Strings.
+    if strings.len() > 0 {
+        report.heading(3, "Strings".to_string());
+        let rows = strings
+            .entries()
+            .map(|(id, string)| match id.0 {
+                // String 0 is the list of supported language IDs.
+                0 => ("Languages".to_string(), string
+                    .chars()
+                    .iter()
+                    .map(|lang| format!("0x{lang:04X}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")),
+                _ => (format!("#{id}"), string_text(string)),
+            })
+            .collect();
+        report.blocks.push(Block::Table(rows));
+    }
+
+    // Configurations.
+    for (number, config) in configurations.entries() {
+        let title = if selected == Some(number) {
+            format!("Configuration {number} (selected)")
+        } else {
+            format!("Configuration {number}")
+        };
+        report.heading(3, title);
+        configuration_report(config, &strings, report, &mut warnings);
+    }
+    if configurations.len() == 0 {
+        warnings.push(
+            "No configuration descriptor was read from the device".into());
+    }
+    match selected {
+        None => warnings.push(
+            "No configuration was selected by a SET_CONFIGURATION request"
+                .into()),
+        Some(ConfigNum(0)) => warnings.push(
+            "The device was left unconfigured, with configuration 0 set"
+                .into()),
+        Some(number) if configurations.get(number).is_none() =>
+            warnings.push(format!(
+                "Configuration {number} was selected, but its descriptor \
+                 was not read")),
+        Some(_) => {},
+    }
+
+    // Class drivers, for the selected configuration if there is one.
+    report.heading(3, "Class drivers".to_string());
+    let config = selected
+        .and_then(|number| configurations.get(number))
+        .or_else(|| configurations.entries().next().map(|(_, c)| c));
+    let drivers = class_drivers(
+        data.device_descriptor.load().as_deref(), config.map(|c| c.as_ref()));
+    if drivers.is_empty() {
+        report.paragraph(
+            "No class drivers are implied, as no interfaces were seen.");
+    } else {
+        report.blocks.push(Block::List(drivers));
+    }
+
+    // How long enumeration took, and which requests slowed it.
+    timing_report(capture, device_id, report, &mut warnings)?;
+
+    // Control requests which did not complete.
+    request_warnings(capture, device_id, &mut warnings)?;
+
+    report.heading(3, "Warnings".to_string());
+    if warnings.is_empty() {
+        report.paragraph("None.");
+    } else {
+        report.blocks.push(Block::List(warnings));
+    }
+    Ok(())
+}
+
+fn device_fields(descriptor: &DeviceDescriptor,
+                 strings: &VecMap<StringId, UTF16ByteVec>)
+    -> Vec<(String, String)>
+{
+    let class = descriptor.device_class;
+    vec![
+        ("USB version".into(), format!("{}", descriptor.usb_version)),
+        ("Class".into(), format!("0x{class:02X} ({})",
+            match class {
+                0x00 => "defined by each interface",
+                _ => class_name(class, descriptor.device_subclass),
+            })),
+        ("Subclass".into(), format!("0x{:02X}", descriptor.device_subclass)),
+        ("Protocol".into(), format!("0x{:02X}", descriptor.device_protocol)),
+        ("Max EP0 packet size".into(),
+            format!("{} bytes", descriptor.max_packet_size_0)),
+        ("Vendor ID".into(), format!("0x{:04X}", descriptor.vendor_id)),
+        ("Product ID".into(), format!("0x{:04X}", descriptor.product_id)),
+        ("Device version".into(), format!("{}", descriptor.device_version)),
+        ("Manufacturer".into(),
+            string_field(strings, descriptor.manufacturer_str_id)),
+        ("Product".into(), string_field(strings, descriptor.product_str_id)),
+        ("Serial number".into(),
+            string_field(strings, descriptor.serial_str_id)),
+        ("Configurations".into(),
+            format!("{}", descriptor.num_configurations)),
+    ]
+}
+
+fn device_warnings(descriptor: &DeviceDescriptor,
+                   strings: &VecMap<StringId, UTF16ByteVec>,
+                   warnings: &mut Vec<String>)
+{
+    if descriptor.num_configurations == 0 {
+        warnings.push("The device descriptor reports no configurations".into());
+    }
+    let max_packet_size = descriptor.max_packet_size_0;
+    if descriptor.usb_version.major < 3 &&
+        !matches!(max_packet_size, 8 | 16 | 32 | 64)
+    {
+        warnings.push(format!(
+            "The max EP0 packet size of {max_packet_size} bytes is not \
+             valid, and must be 8, 16, 32 or 64"));
+    }
+    for (id, name) in [
+        (descriptor.manufacturer_str_id, "manufacturer"),
+        (descriptor.product_str_id, "product"),
+        (descriptor.serial_str_id, "serial number"),
+    ] {
+        missing_string(strings, id, name, warnings);
+    }
+}
+
+fn configuration_report(config: &Configuration,
+                        strings: &VecMap<StringId, UTF16ByteVec>,
+                        report: &mut Report,
+                        warnings: &mut Vec<String>)
+{
+    let descriptor = &config.descriptor;
+    let number = descriptor.config_value;
+    let attributes = descriptor.attributes;
+    let mut features = vec![
+        if attributes & 0x40 != 0 { "self-powered" } else { "bus-powered" }
+    ];
+    if attributes & 0x20 != 0 {
+        features.push("remote wakeup");
+    }
+    let mut rows = vec![
+        ("Configuration string".into(),
+            string_field(strings, descriptor.config_str_id)),
+        ("Attributes".into(),
+            format!("0x{attributes:02X} ({})", features.join(", "))),
+        ("Max power".into(), format!("{}mA", descriptor.max_power as u16 * 2)),
+        ("Interfaces".into(), format!("{}", descriptor.num_interfaces)),
+    ];
+    if let Some(otg) = &config.otg {
+        let protocols = otg.protocols();
+        let mut text = match protocols.len() {
+            0 => "no protocols".to_string(),
+            _ => protocols.join(", "),
+        };
+        if let Some(version) = otg.otg_version {
+            text = format!("{text}, OTG version {version}");
+        }
+        rows.push(("OTG".into(), text));
+    }
+    report.blocks.push(Block::Table(rows));
+    missing_string(strings, descriptor.config_str_id,
+                   &format!("configuration {number}"), warnings);
+    if descriptor.num_interfaces as usize != config.interfaces.len() {
+        warnings.push(format!(
+            "Configuration {number} declares {} interfaces, but {} were \
+             described", descriptor.num_interfaces, config.interfaces.len()));
+    }
+    for interface in &config.interfaces {
+        let iface = &interface.descriptor;
+        let iface_num = iface.interface_number;
+        report.heading(4, format!("Interface {iface_num}"));
+        let mut rows = vec![
+            ("Alternate setting".into(),
+                format!("{}", iface.alternate_setting)),
+            ("Class".into(), format!("0x{:02X} ({})",
+                iface.interface_class,
+                class_name(iface.interface_class, iface.interface_subclass))),
+            ("Subclass".into(), format!("0x{:02X}", iface.interface_subclass)),
+            ("Protocol".into(), format!("0x{:02X}", iface.interface_protocol)),
+            ("Interface string".into(),
+                string_field(strings, iface.interface_str_id)),
+        ];
+        for ep_desc in &interface.endpoint_descriptors {
+            let address = ep_desc.endpoint_address;
+            let max_packet_size = ep_desc.max_packet_size;
+            rows.push((
+                format!("Endpoint {} {}", address.number(), address.direction()),
+                format!("{}, max packet size {max_packet_size} bytes, \
+                         interval {}",
+                        format!("{:?}", ep_desc.attributes.endpoint_type())
+                            .to_lowercase(),
+                        ep_desc.interval)));
+        }
+        report.blocks.push(Block::Table(rows));
+        missing_string(strings, iface.interface_str_id,
+                       &format!("interface {iface_num}"), warnings);
+    }
+}
+
+/// The class drivers implied by a device and its interfaces.
+fn class_drivers(device: Option<&DeviceDescriptor>,
+                 config: Option<&Configuration>)
+    -> Vec<String>
+{
+    let mut drivers = Vec::new();
+    if let Some(device) = device {
+        // Class 0 leaves the class to each interface, and 0xEF is used by
+        // composite devices with interface associations.
+        if !matches!(device.device_class, 0x00 | 0xEF) {
+            drivers.push(format!("Device: {}",
+                driver(device.device_class, device.device_subclass)));
+        }
+    }
+    if let Some(config) = config {
+        for interface in &config.interfaces {
+            let iface: &InterfaceDescriptor = &interface.descriptor;
+            drivers.push(format!("Interface {}: {}",
+                iface.interface_number,
+                driver(iface.interface_class, iface.interface_subclass)));
+        }
+    }
+    drivers
+}
+
+fn driver(class: u8, subclass: u8) -> String {
+    match class {
+        0xFF => "vendor-specific, needs a driver from the vendor".to_string(),
+        _ => format!("{} class driver", class_name(class, subclass)),
+    }
+}
+
+/// Name of a USB class code, as assigned by the USB-IF.
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, _) => "Audio",
+        (0x02, _) => "Communications (CDC)",
+        (0x03, _) => "HID",
+        (0x05, _) => "Physical",
+        (0x06, _) => "Still Image",
+        (0x07, _) => "Printer",
+        (0x08, _) => "Mass Storage",
+        (0x09, _) => "Hub",
+        (0x0A, _) => "CDC Data",
+        (0x0B, _) => "Smart Card",
+        (0x0D, _) => "Content Security",
+        (0x0E, _) => "Video",
+        (0x0F, _) => "Personal Healthcare",
+        (0x10, _) => "Audio/Video",
+        (0x11, _) => "Billboard",
+        (0x12, _) => "USB Type-C Bridge",
+        (0xDC, _) => "Diagnostic",
+        (0xE0, _) => "Wireless Controller",
+        (0xEF, _) => "Miscellaneous",
+        (0xFE, 0x01) => "Device Firmware Upgrade (DFU)",
+        (0xFE, 0x02) => "IrDA Bridge",
+        (0xFE, 0x03) => "Test and Measurement (USBTMC)",
+        (0xFE, _) => "Application Specific",
+        (0xFF, _) => "Vendor Specific",
+        _ => "Unknown",
+    }
+}
+
+/// A control request made while enumerating a device.
+struct TimedRequest {
+    summary: String,
+    /// Time from the start of enumeration to the start of the request, in
+    /// nanoseconds.
+    offset: u64,
+    /// Time taken by the request, in nanoseconds.
+    duration: u64,
+    /// Whether the request was a GET_DESCRIPTOR which took too long.
+    slow: bool,
+}
+
+/// The requests made to enumerate a device, from the first request to the
+/// default address before it was given its own address, up to the end of
+/// the SET_CONFIGURATION which configured it.
+struct EnumerationTiming {
+    requests: Vec<TimedRequest>,
+    /// Time taken by the whole enumeration, in nanoseconds.
+    total: u64,
+    /// Whether the device was configured, rather than timing ending at
+    /// the last request seen.
+    configured: bool,
+}
+
+/// Time each request made to enumerate a device, if any were seen.
+fn enumeration_timing(capture: &mut CaptureReader, device_id: DeviceId)
+    -> Result<Option<EnumerationTiming>, Error>
+{
+    use StandardRequest::*;
+    let address = capture.devices.get(device_id)?.address;
+    let mut own = capture.device_control_timeline(device_id)?;
+    own.sort_by_key(|(_, start, _)| *start);
+    let first_start = match own.first() {
+        Some((_, start, _)) => *start,
+        None => return Ok(None),
+    };
+    // Before being given its address, the device answered at the default
+    // address, which was also used to enumerate any devices before it.
+    let mut default = capture.device_control_timeline(DeviceId::from(0))?;
+    default.sort_by_key(|(_, start, _)| *start);
+    default.retain(|(_, start, _)| *start < first_start);
+    let set_address = default
+        .iter()
+        .rposition(|(transfer, ..)|
+            matches!(standard_request(transfer), Some(SetAddress)) &&
+            transfer.fields.value == address.0 as u16);
+    let mut requests = match set_address {
+        Some(position) => {
+            let begin = default[..position]
+                .iter()
+                .rposition(|(transfer, ..)|
+                    matches!(standard_request(transfer), Some(SetAddress)))
+                .map_or(0, |previous| previous + 1);
+            default.drain(begin..=position).collect()
+        },
+        None => Vec::new(),
+    };
+    let mut configured = false;
+    for (transfer, start, end) in own {
+        configured =
+            matches!(standard_request(&transfer), Some(SetConfiguration)) &&
+            transfer.fields.value != 0 &&
+            matches!(transfer.result, ControlResult::Completed);
+        requests.push((transfer, start, end));
+        if configured {
+            break;
+        }
+    }
+    let begin: Timestamp = requests[0].1;
+    let finish = requests
+        .iter()
+        .map(|(_, _, end)| *end)
+        .max()
+        .unwrap_or(begin);
+    let requests = requests
+        .into_iter()
+        .map(|(transfer, start, end)| {
+            let duration = end.saturating_sub(start);
+            TimedRequest {
+                summary: transfer.summary(),
+                offset: start.saturating_sub(begin),
+                duration,
+                slow: duration > SLOW_DESCRIPTOR_TIME && matches!(
+                    standard_request(&transfer), Some(GetDescriptor)),
+            }
+        })
+        .collect();
+    Ok(Some(EnumerationTiming {
+        requests,
+        total: finish.saturating_sub(begin),
+        configured,
+    }))
+}
+
+fn timing_report(capture: &mut CaptureReader,
+                 device_id: DeviceId,
+                 report: &mut Report,
+                 warnings: &mut Vec<String>)
+    -> Result<(), Error>
+{
+    report.heading(3, "Enumeration timing".to_string());
+    let timing = match enumeration_timing(capture, device_id)? {
+        Some(timing) => timing,
+        None => {
+            report.paragraph("No control requests were seen.");
+            return Ok(());
+        }
+    };
+    let total = milliseconds(timing.total);
+    report.paragraph(&if timing.configured {
+        format!("Enumeration took {total}, from the first request to the \
+                 default address to the end of SET_CONFIGURATION. The bus \
+                 reset before it is not captured, so is not included.")
+    } else {
+        format!("The device was not configured. Requests took {total}, \
+                 from the first request to the default address to the end \
+                 of the last request seen.")
+    });
+    let rows = timing.requests
+        .iter()
+        .map(|request| (
+            format!("+{}", milliseconds(request.offset)),
+            format!("{}{}: {}",
+                milliseconds(request.duration),
+                if request.slow { " (slow)" } else { "" },
+                request.summary)))
+        .collect();
+    report.blocks.push(Block::Table(rows));
+    for request in timing.requests.iter().filter(|request| request.slow) {
+        warnings.push(format!("Slow response, taking {}: {}",
+                              milliseconds(request.duration), request.summary));
+    }
+    Ok(())
+}
+
+fn standard_request(transfer: &ControlTransfer) -> Option<StandardRequest> {
+    let fields = &transfer.fields;
+    match fields.type_fields.request_type() {
+        RequestType::Standard => Some(StandardRequest::from(fields.request)),
+        _ => None,
+    }
+}
+/// Warn about control requests to a device which did not complete.
+///
+/// Repeats of the same failure are counted rather than listed again.
+fn request_warnings(capture: &mut CaptureReader,
+                    device_id: DeviceId,
+                    warnings: &mut Vec<String>)
+    -> Result<(), Error>
+{
+    let mut failures: Vec<(String, usize)> = Vec::new();
+    for transfer in capture.device_control_transfers(device_id)? {
+        if matches!(transfer.result, ControlResult::Completed) ||
+            expected_stall(&transfer)
+        {
+            continue;
+        }
+        let summary = transfer.summary();
+        match failures.iter_mut().find(|(s, _)| *s == summary) {
+            Some((_, count)) => *count += 1,
+            None => failures.push((summary, 1)),
+        }
+    }
+    for (summary, count) in failures {
+        warnings.push(match count {
+            1 => format!("Request failed: {summary}"),
+            n => format!("Request failed {n} times: {summary}"),
+        });
+    }
+    Ok(())
+}
+
+/// Whether a request failed in the way the specification requires.
+///
+/// Full speed devices must stall requests for the descriptors which
+/// describe how they would operate at high speed.
+fn expected_stall(transfer: &ControlTransfer) -> bool {
+    let fields = &transfer.fields;
+    let descriptor_type = DescriptorType::from((fields.value >> 8) as u8);
+    matches!(transfer.result, ControlResult::Stalled) &&
+        matches!(fields.type_fields.request_type(), RequestType::Standard) &&
+        matches!(StandardRequest::from(fields.request),
+                 StandardRequest::GetDescriptor) &&
+        matches!(descriptor_type,
+                 DescriptorType::DeviceQualifier |
+                 DescriptorType::OtherSpeedConfiguration)
+}
+
+fn missing_string(strings: &VecMap<StringId, UTF16ByteVec>,
+                  id: StringId,
+                  name: &str,
+                  warnings: &mut Vec<String>)
+{
+    if id.0 != 0 && strings.get(id).is_none() {
+        warnings.push(format!(
+            "String #{id}, for the {name}, was not read from the device"));
+    }
+}
+
+fn string_field(strings: &VecMap<StringId, UTF16ByteVec>, id: StringId)
+    -> String
+{
+    match id.0 {
+        0 => "(none)".to_string(),
+        _ => match strings.get(id) {
+            Some(string) => format!("#{id}: {}", string_text(string)),
+            None => format!("#{id} (not seen)"),
+        }
+    }
+}
+
+fn string_text(string: &UTF16ByteVec) -> String {
+    String::from_utf16_lossy(&string.chars())
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\n', " ")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+
+    #[test]
+    fn test_enumeration_report() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let report = enumeration_report(&mut capture).unwrap();
+        let markdown = report.markdown();
+        assert!(markdown.starts_with("# USB enumeration report\n"));
+        assert!(markdown.contains("## Device 4: "));
+        assert!(markdown.contains("(selected)"));
+        assert!(markdown.contains("Interface 0: HID class driver"));
+        let html = report.html();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h2>Device 4: "));
+        assert_eq!(html.matches("<table>").count(),
+                   markdown.matches("| Field | Value |").count());
+    }
+
+    #[test]
+    fn test_speed_change() {
+        // A high speed device, reset and coming back at full speed.
+        let scenario = Scenario::parse("speed-change", "
+            speed high
+            device 1
+            sof 16
+            enumerate 1
+            speed full
+            device 2
+            sof 3
+            enumerate 2").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        let markdown = enumeration_report(&mut capture).unwrap().markdown();
+        assert!(markdown.contains(
+            "The bus changed speed during the capture, as a device was \
+             reset: high speed from the start, then full speed from "));
+        let device_1 = markdown.find("## Device 1: ").unwrap();
+        let device_2 = markdown.find("## Device 2: ").unwrap();
+        assert!(markdown[device_1..device_2]
+            .contains("Seen on a high speed bus."));
+        assert!(markdown[device_2..].contains("Seen on a full speed bus."));
+    }
+
+    #[test]
+    fn test_otg_events() {
+        let scenario = Scenario::parse("otg", "
+            speed full
+            device 1
+            enumerate 1
+            control 1 0x00 3 3 0").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        let markdown = enumeration_report(&mut capture).unwrap().markdown();
+        let events = markdown.find("OTG events were seen").unwrap();
+        assert!(events < markdown.find("## Device 1: ").unwrap());
+        assert!(markdown.contains(
+            "s: Host enabled HNP on device 1, which may now become host"));
+    }
+
+    #[test]
+    fn test_enumeration_timing() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let timing = enumeration_timing(&mut capture, DeviceId::from(1))
+            .unwrap()
+            .unwrap();
+        assert!(timing.configured);
+        // Timing starts at the default address, before SET_ADDRESS.
+        let summaries: Vec<&str> = timing.requests
+            .iter()
+            .map(|request| request.summary.as_str())
+            .collect();
+        assert!(summaries[0].ends_with("for device 0, reading 18 of 64 \
+                                        requested bytes"));
+        assert_eq!(summaries[1], "Setting address to 4 for device 0");
+        assert_eq!(summaries.last().unwrap(),
+                   &"Setting configuration 1 for device 4");
+        assert_eq!(timing.requests[0].offset, 0);
+        assert!(timing.requests
+            .windows(2)
+            .all(|pair| pair[0].offset <= pair[1].offset));
+        let last = timing.requests.last().unwrap();
+        assert_eq!(timing.total, last.offset + last.duration);
+        assert!(timing.requests.iter().all(|request| !request.slow));
+
+        let markdown = enumeration_report(&mut capture).unwrap().markdown();
+        assert!(markdown.contains("### Enumeration timing\n\n\
+                                   Enumeration took 0.108 ms,"));
+        assert!(markdown.contains(
+            "| +0.007 ms | 0.002 ms: Setting address to 4 for device 0 |"));
+        assert_eq!(milliseconds(1_234_000), "1.234 ms");
+        assert_eq!(milliseconds(1_234_567), "1.234567 ms");
+    }
+
+    #[test]
+    fn test_throughput_report() {
+        use crate::throughput::{check_target, ThroughputTarget};
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let results: Vec<ThroughputResult> = ["4.0x81=1B/s", "4.0x81=1MB/s"]
+            .iter()
+            .map(|text| ThroughputTarget::parse(text).unwrap())
+            .map(|target| check_target(&mut capture, &target).unwrap())
+            .collect();
+        assert!(results[0].passed());
+        assert!(!results[1].passed());
+        let markdown = throughput_report(&mut capture, &results)
+            .unwrap()
+            .markdown();
+        assert!(markdown.starts_with("# USB throughput report\n\n\
+                                      FAIL: 1 of 2 targets were not met.\n"));
+        assert!(markdown.contains("\n## Device 4, endpoint 0x81 IN\n"));
+        assert!(markdown.contains(
+            "| Target | 1 byte/s over every 1 s window |"));
+        assert!(markdown.contains("| Result | Passed |"));
+        assert!(markdown.contains(
+            "| Result | Failed: the slowest window fell short |"));
+    }
+
+    #[test]
+    fn test_compliance_report() {
+        let path = PathBuf::from("../tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let markdown = compliance_report(&mut capture).unwrap().markdown();
+        assert!(markdown.starts_with("# USB chapter 9 compliance report\n"));
+        assert!(markdown.contains("## Device 4: "));
+        assert!(markdown.contains(
+            "INCOMPLETE: 7 passed, 0 failed, 2 untested."));
+        assert!(markdown.contains("| SET_ADDRESS is accepted | Passed |"));
+        assert!(markdown.contains(
+            "| Invalid requests are stalled | Untested. Have a chapter 9"));
+    }
+
+    #[test]
+    fn test_report_format() {
+        for (name, format) in [
+            ("report.html", ReportFormat::Html),
+            ("report.HTM", ReportFormat::Html),
+            ("report.md", ReportFormat::Markdown),
+            ("report", ReportFormat::Markdown),
+        ] {
+            assert_eq!(ReportFormat::from_path(Path::new(name)), format);
+        }
+    }
+}
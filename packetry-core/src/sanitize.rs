@@ -0,0 +1,234 @@
+//! Sanitizing captures so that they can be shared publicly, without the
+//! data they carry.
+//!
+//! Packets are kept with their lengths, so that the traffic decodes to the
+//! same structure, but the payloads of data packets are overwritten with
+//! zeros. SETUP packets are kept, along with the descriptors read by
+//! GET_DESCRIPTOR requests, so that requests and devices are still
+//! described. The text of string descriptors, which may include serial
+//! numbers, is replaced with `X` characters.
+
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    PacketId,
+    TrafficItem,
+    TrafficItemId,
+    TransactionId,
+};
+use crate::usb::{
+    crc16,
+    DescriptorType,
+    RequestType,
+    StandardRequest,
+    PID,
+};
+
+/// Character that the text of string descriptors is replaced with.
+const STRING_FILL: u8 = b'X';
+
+/// How the payload of a data packet is treated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Treatment {
+    /// Kept as it is.
+    Keep,
+    /// Part of a string descriptor, starting at this offset within it.
+    String(usize),
+    /// Overwritten with zeros.
+    Scrub,
+}
+
+/// Sanitizes the packets of a capture.
+pub struct Sanitizer {
+    /// Treatment of data stage transactions of descriptor reads.
+    descriptors: BTreeMap<TransactionId, Treatment>,
+}
+
+impl Sanitizer {
+    /// Find the descriptors read in a capture.
+    pub fn new(capture: &mut CaptureReader) -> Result<Sanitizer, Error> {
+        let mut descriptors = BTreeMap::new();
+        for index in 0..capture.item_index.len() {
+            let item_id = TrafficItemId::from(index);
+            let transfer_id = capture.item_index.get(item_id)?;
+            let item = TrafficItem::Transfer(transfer_id);
+            let fields = match capture.item_control_transfer(&item)? {
+                Some(transfer) => transfer.fields,
+                None => continue,
+            };
+            let request_type = fields.type_fields.request_type();
+            let request = StandardRequest::from(fields.request);
+            if !matches!(
+                (request_type, request),
+                (RequestType::Standard, StandardRequest::GetDescriptor))
+            {
+                continue;
+            }
+            // String descriptor 0 lists the supported languages, and holds
+            // no text.
+            let descriptor_type = (fields.value >> 8) as u8;
+            let is_text = descriptor_type == DescriptorType::String as u8 &&
+                fields.value & 0xFF != 0;
+            let mut offset = 0;
+            for (transaction_id, length) in
+                capture.transfer_data_transactions(transfer_id)?
+            {
+                let treatment = if is_text {
+                    Treatment::String(offset)
+                } else {
+                    Treatment::Keep
+                };
+                descriptors.insert(transaction_id, treatment);
+                offset += length as usize;
+            }
+        }
+        Ok(Sanitizer { descriptors })
+    }
+
+    /// A packet of the capture, sanitized.
+    pub fn packet(&self, capture: &mut CaptureReader, packet_id: PacketId)
+        -> Result<Vec<u8>, Error>
+    {
+        let mut packet = capture.packet(packet_id)?;
+        let is_data = matches!(
+            packet.first().map(|pid| PID::from(*pid)),
+            Some(PID::DATA0 | PID::DATA1 | PID::DATA2 | PID::MDATA));
+        if !is_data || packet.len() < 3 {
+            return Ok(packet);
+        }
+        match self.treatment(capture, packet_id)? {
+            Treatment::Keep => return Ok(packet),
+            Treatment::String(offset) => {
+                let end = packet.len() - 2;
+                for (i, byte) in packet[1..end].iter_mut().enumerate() {
+                    // Keep the length and type at the start.
+                    let position = offset + i;
+                    if position >= 2 {
+                        *byte = match position % 2 {
+                            0 => STRING_FILL,
+                            _ => 0,
+                        };
+                    }
+                }
+            },
+            Treatment::Scrub => {
+                let end = packet.len() - 2;
+                packet[1..end].fill(0);
+            },
+        }
+        // Packets received with a bad CRC keep it, so that they are still
+        // seen to be in error.
+        let end = packet.len() - 2;
+        let crc = u16::from_le_bytes([packet[end], packet[end + 1]]);
+        let original = capture.packet(packet_id)?;
+        if crc == crc16(&original[1..end]) {
+            let crc = crc16(&packet[1..end]);
+            packet[end..].copy_from_slice(&crc.to_le_bytes());
+        }
+        Ok(packet)
+    }
+
+    /// How the payload of a data packet is treated.
+    fn treatment(&self, capture: &mut CaptureReader, packet_id: PacketId)
+        -> Result<Treatment, Error>
+    {
+        // The data of a SETUP transaction follows the SETUP token.
+        if packet_id.value > 0 {
+            let previous_id = PacketId::from(packet_id.value - 1);
+            let previous = capture.packet(previous_id)?;
+            if previous.first() == Some(&PID::SETUP.into()) {
+                return Ok(Treatment::Keep);
+            }
+        }
+        let transaction_id = match capture.packet_item(packet_id) {
+            Ok(TrafficItem::Packet(_, transaction_id, _)) => transaction_id,
+            _ => return Ok(Treatment::Scrub),
+        };
+        Ok(self.descriptors
+            .get(&transaction_id)
+            .copied()
+            .unwrap_or(Treatment::Scrub))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_sanitize() {
+        let long_string = format!("42:03{}", ":41:00".repeat(32));
+        let scenario = Scenario::parse("sanitize", &format!("
+            device 1 vid=0x1d50 pid=0x615b
+            endpoint 1 1 in bulk 512
+            enumerate 1
+            control 1 0x80 6 0x0300 0 04:03:09:04
+            control 1 0x80 6 0x0303 0x0409 {long_string}
+            control 1 0xC0 1 0 0 55:AA
+            in 1 1 01:02:03
+        ")).unwrap();
+        let packets: Vec<Vec<u8>> = scenario
+            .packets()
+            .map(|packet| packet.to_vec())
+            .collect();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let sanitizer = Sanitizer::new(&mut capture).unwrap();
+        let sanitized: Vec<Vec<u8>> = (0..packets.len())
+            .map(|i| sanitizer
+                .packet(&mut capture, PacketId::from(i as u64))
+                .unwrap())
+            .collect();
+        let payload = |packet: &[u8]| packet[1..packet.len() - 2].to_vec();
+        let data_packets: Vec<(Vec<u8>, Vec<u8>)> = packets
+            .iter()
+            .zip(&sanitized)
+            .filter(|(packet, _)| packet.len() > 3)
+            .map(|(packet, sanitized)| (payload(packet), payload(sanitized)))
+            .collect();
+        for (original, sanitized) in &data_packets {
+            assert_eq!(original.len(), sanitized.len());
+        }
+        for (packet, sanitized) in packets.iter().zip(&sanitized) {
+            assert_eq!(packet.len(), sanitized.len());
+            if packet.len() > 3 {
+                let end = sanitized.len() - 2;
+                let crc = u16::from_le_bytes(
+                    [sanitized[end], sanitized[end + 1]]);
+                assert_eq!(crc, crc16(&sanitized[1..end]));
+            }
+        }
+        let find = |start: &[u8]| data_packets
+            .iter()
+            .find(|(original, _)| original.starts_with(start))
+            .unwrap()
+            .1
+            .clone();
+
+        // SETUP packets and descriptors are kept.
+        assert_eq!(find(&[0xC0, 0x01]), [0xC0, 1, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(find(&[18, 1])[8..12], [0x50, 0x1D, 0x5B, 0x61]);
+        assert_eq!(find(&[4, 3]), [4, 3, 9, 4]);
+
+        // The text of strings is replaced, including where it continues in
+        // another packet.
+        let first = find(&[0x42, 0x03]);
+        assert_eq!(first[..6], [0x42, 0x03, b'X', 0, b'X', 0]);
+        assert_eq!(find(&[0x41, 0x00]), [b'X', 0]);
+
+        // Other data is overwritten with zeros.
+        assert_eq!(find(&[0x55, 0xAA]), [0, 0]);
+        assert_eq!(find(&[1, 2, 3]), [0, 0, 0]);
+    }
+}
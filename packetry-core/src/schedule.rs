@@ -0,0 +1,120 @@
+//! Schedules for unattended captures: when to start, and when to stop.
+
+use std::time::Duration;
+
+use anyhow::{Context, Error, bail};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// When a capture is started, and the limits at which it is stopped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Time of day (UTC) at which to start, in seconds after midnight, if
+    /// not started at once.
+    pub start_at: Option<u64>,
+    /// Time after starting at which to stop.
+    pub duration: Option<Duration>,
+    /// Number of packets after which to stop.
+    pub max_packets: Option<u64>,
+    /// Number of bytes of packets after which to stop.
+    pub max_bytes: Option<u64>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule::default()
+    }
+
+    /// Parse a time of day, as `HH:MM` or `HH:MM:SS`, into seconds after
+    /// midnight.
+    pub fn parse_time_of_day(text: &str) -> Result<u64, Error> {
+        let invalid = || format!(
+            "Invalid time of day '{text}', expected HH:MM or HH:MM:SS");
+        let parts = text
+            .trim()
+            .split(':')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<u64>, _>>()
+            .with_context(invalid)?;
+        let (hours, minutes, seconds) = match parts[..] {
+            [hours, minutes] => (hours, minutes, 0),
+            [hours, minutes, seconds] => (hours, minutes, seconds),
+            _ => bail!(invalid()),
+        };
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            bail!(invalid())
+        }
+        Ok(hours * 3600 + minutes * 60 + seconds)
+    }
+
+    /// Format a time of day, given in seconds after midnight.
+    pub fn format_time_of_day(seconds: u64) -> String {
+        format!("{:02}:{:02}:{:02}",
+                seconds / 3600 % 24,
+                seconds / 60 % 60,
+                seconds % 60)
+    }
+
+    /// Time to wait before starting, given the time since the Unix epoch.
+    ///
+    /// A start time which has already passed today is taken to be the same
+    /// time tomorrow.
+    pub fn start_delay(&self, now: Duration) -> Duration {
+        let start_at = match self.start_at {
+            Some(start_at) => start_at,
+            None => return Duration::ZERO,
+        };
+        let day = Duration::from_secs(SECS_PER_DAY);
+        let time_of_day = Duration::new(
+            now.as_secs() % SECS_PER_DAY, now.subsec_nanos());
+        let start = Duration::from_secs(start_at);
+        if start >= time_of_day {
+            start - time_of_day
+        } else {
+            start + day - time_of_day
+        }
+    }
+
+    /// Whether a capture should stop, once it has captured the given
+    /// numbers of packets and bytes.
+    pub fn limit_reached(&self, packets: u64, bytes: u64) -> bool {
+        let packets_done = matches!(
+            self.max_packets, Some(max_packets) if packets >= max_packets);
+        let bytes_done = matches!(
+            self.max_bytes, Some(max_bytes) if bytes >= max_bytes);
+        packets_done || bytes_done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        assert_eq!(Schedule::parse_time_of_day("02:30").unwrap(), 9000);
+        assert_eq!(Schedule::parse_time_of_day(" 23:59:59").unwrap(),
+                   SECS_PER_DAY - 1);
+        for invalid in ["24:00", "12:60", "12", "1:2:3:4", "noon", "-1:00"] {
+            assert!(Schedule::parse_time_of_day(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(Schedule::format_time_of_day(9005), "02:30:05");
+
+        let mut schedule = Schedule::new();
+        let now = Duration::new(SECS_PER_DAY * 19000 + 3600, 500_000_000);
+        assert_eq!(schedule.start_delay(now), Duration::ZERO);
+        schedule.start_at = Some(7200);
+        assert_eq!(schedule.start_delay(now), Duration::new(3599, 500_000_000));
+        // A time already passed is the next day's.
+        schedule.start_at = Some(1800);
+        assert_eq!(schedule.start_delay(now),
+                   Duration::new(SECS_PER_DAY - 1801, 500_000_000));
+
+        assert!(!schedule.limit_reached(1_000_000, 1_000_000_000));
+        schedule.max_packets = Some(10);
+        schedule.max_bytes = Some(100);
+        assert!(!schedule.limit_reached(9, 99));
+        assert!(schedule.limit_reached(10, 0));
+        assert!(schedule.limit_reached(0, 100));
+    }
+}
@@ -0,0 +1,308 @@
+//! The speed of the bus over the time of a capture, which may change when
+//! a device is reset and negotiates a different speed, e.g. when a high
+//! speed device falls back to full speed.
+//!
+//! The speed is found from the SOF packets captured. At high speed, the
+//! host sends one in each 125 µs microframe, so eight in a row carry the
+//! same frame number, while at full speed there is one in each frame. Low
+//! speed buses carry no SOFs, so their speed cannot be found.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    FRAMING_EP_ID,
+};
+use crate::usb::PacketFields;
+
+/// Speeds at which a bus may run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BusSpeed {
+    High,
+    Full,
+    Low,
+}
+
+impl BusSpeed {
+    /// All speeds, in the order they are offered.
+    pub const ALL: [BusSpeed; 3] = [
+        BusSpeed::High,
+        BusSpeed::Full,
+        BusSpeed::Low,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        use BusSpeed::*;
+        match self {
+            High => "High (480Mbps)",
+            Full => "Full (12Mbps)",
+            Low => "Low (1.5Mbps)",
+        }
+    }
+
+    /// Name of this speed, as used in a sentence.
+    pub fn name(&self) -> &'static str {
+        use BusSpeed::*;
+        match self {
+            High => "high speed",
+            Full => "full speed",
+            Low => "low speed",
+        }
+    }
+
+    pub fn bits_per_second(&self) -> f64 {
+        use BusSpeed::*;
+        match self {
+            High => 480e6,
+            Full => 12e6,
+            Low => 1.5e6,
+        }
+    }
+}
+
+/// A part of a capture during which the bus ran at one speed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpeedRange {
+    pub speed: BusSpeed,
+    /// The first SOF sent at this speed.
+    pub start: PacketId,
+    /// Time of the first SOF sent at this speed.
+    pub start_time: Timestamp,
+}
+
+/// SOFs seen in a row carrying the same frame number.
+#[derive(Copy, Clone, Debug)]
+struct FrameRun {
+    number: u16,
+    first: PacketId,
+    first_time: Timestamp,
+    count: u64,
+    /// The first SOF of the frames before this one which each had a single
+    /// SOF, if the last frame did.
+    singles_from: Option<(PacketId, Timestamp)>,
+}
+
+/// The speeds of the bus found in a capture, kept up to date as it grows.
+#[derive(Clone, Debug, Default)]
+pub struct BusSpeeds {
+    /// The speeds found, in the order the bus ran at them.
+    pub ranges: Vec<SpeedRange>,
+    /// Index of the next SOF group on the framing endpoint to check.
+    next_group: u64,
+    /// Index of the next SOF packet to check.
+    next_packet: u64,
+    /// The SOFs seen most recently.
+    run: Option<FrameRun>,
+}
+
+impl BusSpeeds {
+    pub fn new() -> Self {
+        BusSpeeds::default()
+    }
+
+    /// Find the speeds of the bus over a whole capture.
+    pub fn find(capture: &mut CaptureReader) -> Result<BusSpeeds, Error> {
+        let mut speeds = BusSpeeds::new();
+        speeds.update(capture)?;
+        Ok(speeds)
+    }
+
+    /// Check the SOFs added to a capture since the last update.
+    ///
+    /// Returns whether the speed changed.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<bool, Error>
+    {
+        let ranges_found = self.ranges.len();
+        let packet_count = capture.packet_index.len();
+        let group_count = match capture.endpoint_traffic(FRAMING_EP_ID) {
+            Ok(ep_traf) => ep_traf.transaction_ids.len(),
+            Err(_) => return Ok(false),
+        };
+        for index in self.next_group..group_count {
+            let transaction_id = capture
+                .endpoint_traffic(FRAMING_EP_ID)?
+                .transaction_ids
+                .get(EndpointTransactionId::from(index))?;
+            // Consecutive SOFs are grouped into one transaction.
+            let range = capture.transaction_index.target_range(
+                transaction_id, packet_count)?;
+            let start = range.start.value.max(self.next_packet);
+            for value in start..range.end.value {
+                let packet_id = PacketId::from(value);
+                if let PacketFields::SOF(sof) =
+                    PacketFields::from_packet(&capture.packet(packet_id)?)
+                {
+                    let time = capture.packet_time(packet_id)?;
+                    self.sof(sof.frame_number(), packet_id, time);
+                }
+            }
+            self.next_packet = self.next_packet.max(range.end.value);
+        }
+        // The last group may still be growing.
+        self.next_group = self.next_group.max(group_count.saturating_sub(1));
+        Ok(self.ranges.len() > ranges_found)
+    }
+
+    /// Check the next SOF packet.
+    fn sof(&mut self, number: u16, packet_id: PacketId, time: Timestamp) {
+        let run = match self.run.as_mut() {
+            Some(run) if run.number == number => {
+                run.count += 1;
+                // Only high speed repeats a frame number.
+                if run.count == 2 {
+                    let (first, first_time) = (run.first, run.first_time);
+                    self.found(BusSpeed::High, first, first_time);
+                }
+                return;
+            },
+            Some(run) => *run,
+            None => {
+                self.run = Some(FrameRun {
+                    number,
+                    first: packet_id,
+                    first_time: time,
+                    count: 1,
+                    singles_from: None,
+                });
+                return;
+            },
+        };
+        let next_frame = number == (run.number + 1) & 0x7FF;
+        let singles_from = if next_frame && run.count == 1 {
+            Some(run.singles_from.unwrap_or((run.first, run.first_time)))
+        } else {
+            None
+        };
+        // Two frames in a row with a single SOF each show full speed. One
+        // alone is not enough, as a capture may begin, or packets may be
+        // lost, during the last microframe of a high speed frame.
+        if let (Some(_), Some((first, first_time))) =
+            (run.singles_from, singles_from)
+        {
+            self.found(BusSpeed::Full, first, first_time);
+        }
+        self.run = Some(FrameRun {
+            number,
+            first: packet_id,
+            first_time: time,
+            count: 1,
+            singles_from,
+        });
+    }
+
+    /// Record the speed shown by the SOFs from the given packet on.
+    fn found(&mut self, speed: BusSpeed, start: PacketId, time: Timestamp) {
+        if self.ranges.last().map(|range| range.speed) != Some(speed) {
+            self.ranges.push(SpeedRange {
+                speed,
+                start,
+                start_time: time,
+            });
+        }
+    }
+
+    /// The speed of the bus when the given packet was captured, if known.
+    ///
+    /// The first speed found is taken to apply from the start of the
+    /// capture.
+    pub fn speed_at(&self, packet_id: PacketId) -> Option<BusSpeed> {
+        let position = self.ranges
+            .partition_point(|range| range.start <= packet_id);
+        self.ranges
+            .get(position.saturating_sub(1))
+            .map(|range| range.speed)
+    }
+
+    /// The speed of the bus at the given time, if known.
+    pub fn speed_at_time(&self, time: Timestamp) -> Option<BusSpeed> {
+        let position = self.ranges
+            .partition_point(|range| range.start_time <= time);
+        self.ranges
+            .get(position.saturating_sub(1))
+            .map(|range| range.speed)
+    }
+
+    /// Whether the speed of the bus changed during the capture.
+    pub fn changed(&self) -> bool {
+        self.ranges.len() > 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+    use crate::usb::{crc5, PID};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_speed_change() {
+        // A high speed device reset, coming back at full speed.
+        let scenario = Scenario::parse("speed-change", "
+            speed high
+            sof 20
+            speed full
+            sof 5").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        let speeds = BusSpeeds::find(&mut capture).unwrap();
+        let found: Vec<(BusSpeed, u64)> = speeds.ranges
+            .iter()
+            .map(|range| (range.speed, range.start.value))
+            .collect();
+        assert_eq!(found, [(BusSpeed::High, 0), (BusSpeed::Full, 20)]);
+        assert!(speeds.changed());
+        assert_eq!(speeds.speed_at(PacketId::from(19)), Some(BusSpeed::High));
+        assert_eq!(speeds.speed_at(PacketId::from(20)), Some(BusSpeed::Full));
+        assert_eq!(speeds.speed_at_time(19_000), Some(BusSpeed::High));
+        assert_eq!(speeds.speed_at_time(20_000), Some(BusSpeed::Full));
+    }
+
+    #[test]
+    fn test_update() {
+        // Found a few SOFs at a time, as while capturing.
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        let mut speeds = BusSpeeds::new();
+        let mut changes = 0;
+        let sof = |frame: u16| -> Vec<u8> {
+            let crc = crc5(frame, 11);
+            vec![PID::SOF.into(), frame as u8, (frame >> 8) as u8 | (crc << 3)]
+        };
+        // A capture beginning in the last microframe of frame 7.
+        let frames = [7, 8, 8, 8, 8, 8, 8, 8, 8, 9, 9];
+        for (i, frame) in frames.into_iter().enumerate() {
+            decoder.handle_raw_packet(&sof(frame), i as u64 * 125_000)
+                .unwrap();
+            if speeds.update(&mut capture).unwrap() {
+                changes += 1;
+            }
+        }
+        decoder.finish().unwrap();
+        speeds.update(&mut capture).unwrap();
+        assert_eq!(changes, 1);
+        assert_eq!(speeds.ranges.len(), 1);
+        assert_eq!(speeds.ranges[0].speed, BusSpeed::High);
+        assert_eq!(speeds.ranges[0].start, PacketId::from(1));
+        // The first speed found applies from the start of the capture.
+        assert_eq!(speeds.speed_at(PacketId::from(0)), Some(BusSpeed::High));
+        assert!(!speeds.changed());
+
+        // Without SOFs, the speed is not known.
+        let mut capture =
+            load(&[PathBuf::from("../tests/mouse/capture.pcap")]).unwrap();
+        let speeds = BusSpeeds::find(&mut capture).unwrap();
+        assert_eq!(speeds.speed_at(PacketId::from(0)), None);
+    }
+}
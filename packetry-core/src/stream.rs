@@ -2,29 +2,70 @@
 
 use std::alloc::{GlobalAlloc, Layout, System};
 use std::cmp::min;
-use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::ops::{Deref, Range};
 use std::ptr::copy_nonoverlapping;
 use std::slice;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicU64, Ordering::{Acquire, Release}};
 
 use anyhow::{Context, Error, bail};
-use arc_swap::{ArcSwap, ArcSwapOption};
+use arc_swap::ArcSwap;
 use lrumap::LruBTreeMap;
-use memmap2::{Mmap, MmapOptions};
-use tempfile::tempfile;
+
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    std::fs::File,
+    std::path::PathBuf,
+    arc_swap::ArcSwapOption,
+    memmap2::{Mmap, MmapOptions},
+    once_cell::sync::Lazy,
+    tempfile::{tempfile, tempfile_in},
+};
 
 /// Minimum block size, defined by largest minimum page size on target systems.
 pub const MIN_BLOCK: usize = 0x4000; // 16KB (Apple M1/M2)
 
+/// Directory in which stream files are created, if not the system's
+/// temporary directory.
+#[cfg(not(target_arch = "wasm32"))]
+static STORAGE_DIR: Lazy<RwLock<Option<PathBuf>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/// Set the directory in which stream files are created.
+///
+/// Only the last, partly filled block of each stream is kept in memory,
+/// and the rest is written to an unnamed file in this directory. For very
+/// large captures it should be on a disk with plenty of space, rather than
+/// a filesystem held in memory. The directory applies to files created
+/// after it is set.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_storage_dir(dir: Option<PathBuf>) {
+    if let Ok(mut storage_dir) = STORAGE_DIR.write() {
+        *storage_dir = dir;
+    }
+}
+
+/// Create a file to hold the blocks of a stream.
+#[cfg(not(target_arch = "wasm32"))]
+fn create_file() -> Result<File, Error> {
+    let dir = STORAGE_DIR
+        .read()
+        .ok()
+        .and_then(|dir| dir.clone());
+    match dir {
+        Some(dir) => tempfile_in(&dir).with_context(|| format!(
+            "Failed creating temporary file in {}", dir.display())),
+        None => tempfile().context("Failed creating temporary file"),
+    }
+}
+
 /// Private data shared by the writer and multiple readers.
 struct Shared<const S: usize> {
     /// Available length of the stream, including data in both file and buffer.
     length: AtomicU64,
-    /// File handle used by readers to create mappings.
-    file: ArcSwapOption<File>,
+    /// Storage for blocks which have been written out of the buffer.
+    storage: Storage<S>,
     /// Buffer currently in use for newly appended data.
     current_buffer: ArcSwap<Buffer<S>>,
 }
@@ -39,8 +80,6 @@ pub struct StreamWriter<const S: usize = MIN_BLOCK> {
     buf: *mut u8,
     /// Pointer to current position in the current buffer.
     ptr: *mut u8,
-    /// File handle used to append to the stream.
-    file: Option<File>,
     /// Spare buffer to be potentially used when current buffer is full.
     spare_buffer: Option<Arc<Buffer<S>>>,
 }
@@ -50,7 +89,7 @@ pub struct StreamReader<const S: usize = MIN_BLOCK> {
     /// Shared data.
     shared: Arc<Shared<S>>,
     /// Cache of existing mappings into the file.
-    mappings: LruBTreeMap<u64, Arc<Mmap>>,
+    mappings: LruBTreeMap<u64, Arc<Block>>,
 }
 
 /// Data that is part of a stream and currently in memory.
@@ -64,7 +103,7 @@ struct Buffer<const S: usize> {
 /// A read-only handle to any data that is part of a stream.
 enum Data<const S: usize> {
     /// Data in the file, accessed through a mapping.
-    Mapped(Arc<Mmap>, Range<usize>),
+    Mapped(Arc<Block>, Range<usize>),
     /// Data in memory, accessed within a buffer.
     Buffered(Arc<Buffer<S>>, Range<usize>),
 }
@@ -72,6 +111,109 @@ enum Data<const S: usize> {
 // Number of most recent file mappings retained by each reader.
 const MAP_CACHE_PER_READER: usize = 4;
 
+/// Storage for the blocks of a stream which have been written out of the
+/// buffer. Only the writer adds blocks, and once added they do not change.
+///
+/// Blocks are written to an unnamed file, and mapped into memory to read.
+#[cfg(not(target_arch = "wasm32"))]
+struct Storage<const S: usize> {
+    /// File holding the blocks, created when the first one is written.
+    file: ArcSwapOption<File>,
+}
+
+/// A block retrieved from storage.
+#[cfg(not(target_arch = "wasm32"))]
+type Block = Mmap;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<const S: usize> Storage<S> {
+    fn new() -> Result<Self, Error> {
+        let page_size = page_size::get();
+        if S < page_size {
+            bail!("Block size {S:x} is not a multiple \
+                   of the system page size {page_size:x}")
+        }
+        Ok(Storage { file: ArcSwapOption::empty() })
+    }
+
+    /// Append whole blocks to storage.
+    fn write(&self, data: &[u8]) -> Result<(), Error> {
+        let file = match self.file.load_full() {
+            Some(file) => file,
+            None => {
+                let file = Arc::new(create_file()?);
+                self.file.store(Some(file.clone()));
+                file
+            }
+        };
+        file.as_ref()
+            .write_all(data)
+            .context("Failed writing to stream file")
+    }
+
+    /// Retrieve the block starting at the given position.
+    fn block(&self, block_base: u64) -> Result<Arc<Block>, Error> {
+        // The writer creates the file before writing the first block.
+        let file = self.file
+            .load_full()
+            .context("Stream file has not been created")?;
+        // The block was already written to the file and will not
+        // be modified by the writer, so it is safe to map it.
+        let mmap_result = unsafe {
+            MmapOptions::new()
+                .offset(block_base)
+                .len(S)
+                .map(file.as_ref())
+        };
+        Ok(Arc::new(mmap_result.context("Failed mapping stream file")?))
+    }
+}
+
+/// Storage for the blocks of a stream which have been written out of the
+/// buffer. Only the writer adds blocks, and once added they do not change.
+///
+/// There are no files to map on wasm32, so blocks are kept in memory.
+#[cfg(target_arch = "wasm32")]
+struct Storage<const S: usize> {
+    /// Blocks in order of position.
+    blocks: RwLock<Vec<Arc<Block>>>,
+}
+
+/// A block retrieved from storage.
+#[cfg(target_arch = "wasm32")]
+type Block = Box<[u8]>;
+
+#[cfg(target_arch = "wasm32")]
+impl<const S: usize> Storage<S> {
+    fn new() -> Result<Self, Error> {
+        Ok(Storage { blocks: RwLock::new(Vec::new()) })
+    }
+
+    /// Append whole blocks to storage.
+    fn write(&self, data: &[u8]) -> Result<(), Error> {
+        match self.blocks.write() {
+            Ok(mut blocks) => {
+                blocks.extend(
+                    data.chunks(S).map(|block| Arc::new(Block::from(block))));
+                Ok(())
+            },
+            Err(_) => bail!("Stream storage lock poisoned"),
+        }
+    }
+
+    /// Retrieve the block starting at the given position.
+    fn block(&self, block_base: u64) -> Result<Arc<Block>, Error> {
+        let index = (block_base / S as u64) as usize;
+        match self.blocks.read() {
+            Ok(blocks) => blocks
+                .get(index)
+                .cloned()
+                .context("Stream block has not been stored"),
+            Err(_) => bail!("Stream storage lock poisoned"),
+        }
+    }
+}
+
 type StreamPair<const S: usize> = (StreamWriter<S>, StreamReader<S>);
 
 /// Construct a new stream.
@@ -81,15 +223,11 @@ type StreamPair<const S: usize> = (StreamWriter<S>, StreamReader<S>);
 pub fn stream<const BLOCK_SIZE: usize>()
     -> Result<StreamPair<BLOCK_SIZE>, Error>
 {
-    let page_size = page_size::get();
-    if BLOCK_SIZE < page_size {
-        bail!("Block size {BLOCK_SIZE:x} is not a multiple \
-               of the system page size {page_size:x}")
-    }
+    let storage = Storage::new()?;
     let buffer = Arc::new(Buffer::new(0)?);
     let shared = Arc::new(Shared {
         length: AtomicU64::from(0),
-        file: ArcSwapOption::empty(),
+        storage,
         current_buffer: ArcSwap::new(buffer.clone()),
     });
     let writer = StreamWriter {
@@ -97,7 +235,6 @@ pub fn stream<const BLOCK_SIZE: usize>()
         length: 0,
         buf: buffer.ptr,
         ptr: buffer.ptr,
-        file: None,
         spare_buffer: None,
     };
     let reader = StreamReader {
@@ -141,9 +278,11 @@ impl<const BLOCK_SIZE: usize> StreamWriter<BLOCK_SIZE> {
             // blocks of data directly to the file, bypassing the buffer.
             let direct = data.len() & !Self::block_mask();
             if direct > 0 {
+                // The length is updated first, so that the next buffer
+                // begins after the blocks written.
+                self.length += direct as u64;
                 unsafe { self.write_to_file(&data[..direct])? };
                 data = &data[direct..];
-                self.length += direct as u64;
             }
             let length = data.len();
             if length > 0 {
@@ -156,6 +295,22 @@ impl<const BLOCK_SIZE: usize> StreamWriter<BLOCK_SIZE> {
         Ok(self.length)
     }
 
+    /// Append the contents of a stream saved with `StreamReader::save`.
+    pub fn load(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+        let mut length_bytes = [0; 8];
+        reader.read_exact(&mut length_bytes)?;
+        let mut remaining = u64::from_le_bytes(length_bytes);
+        let mut block = vec![0; Self::block_size()];
+        while remaining > 0 {
+            let length = min(remaining, block.len() as u64) as usize;
+            reader.read_exact(&mut block[..length])
+                .context("Saved stream is truncated")?;
+            self.append(&block[..length])?;
+            remaining -= length as u64;
+        }
+        Ok(())
+    }
+
     /// Helper method for writing data to buffer.
     ///
     /// Safety: The data must fit within the space remaining in the buffer.
@@ -187,19 +342,8 @@ impl<const BLOCK_SIZE: usize> StreamWriter<BLOCK_SIZE> {
     ///
     unsafe fn write_to_file(&mut self, data: &[u8]) -> Result<(), Error> {
 
-        // Create the file if it does not exist yet.
-        let file = match &mut self.file {
-            None => {
-                let file = tempfile().context("Failed creating temporary file")?;
-                self.shared.file.store(Some(Arc::new(
-                    file.try_clone().context("Failed cloning file handle")?)));
-                self.file.insert(file)
-            },
-            Some(file) => file
-        };
-
         // Write the data to file.
-        file.write(data).context("Failed writing to stream file")?;
+        self.shared.storage.write(data)?;
 
         // We must change the stream's current buffer to one for the new block.
         let block_base = self.length;
@@ -251,6 +395,19 @@ impl<const BLOCK_SIZE: usize> StreamReader<BLOCK_SIZE> {
         self.shared.length.load(Acquire)
     }
 
+    /// Write the length and contents of the stream.
+    pub fn save(&mut self, writer: &mut dyn Write) -> Result<(), Error> {
+        let length = self.len();
+        writer.write_all(&length.to_le_bytes())?;
+        let mut position = 0;
+        while position < length {
+            let data = self.access(&(position..length))?;
+            writer.write_all(&data)?;
+            position += data.len() as u64;
+        }
+        Ok(())
+    }
+
     /// Access data in the stream.
     ///
     /// Returns a reference to a slice of data, which may have less than the
@@ -291,22 +448,7 @@ impl<const BLOCK_SIZE: usize> StreamReader<BLOCK_SIZE> {
             let mmap = match existing_mmap {
                 Some(mmap) => Arc::clone(mmap),
                 None => {
-                    // Get the file handle to be used for mapping.
-                    // The writer sets this before writing the first block.
-                    let file_guard = self.shared.file.load();
-                    let file_option = file_guard.deref().as_ref();
-                    let file_arc = file_option.unwrap();
-                    let file = file_arc.deref();
-                    // The block was already written to the file and will not
-                    // be modified by the writer, so it is safe to map it.
-                    let mmap_result = unsafe {
-                        MmapOptions::new()
-                            .offset(block_base)
-                            .len(Self::block_size())
-                            .map(file)
-                    };
-                    let new_mmap = Arc::new(
-                        mmap_result.context("Failed mapping stream file")?);
+                    let new_mmap = self.shared.storage.block(block_base)?;
                     self.mappings.push(block_base, Arc::clone(&new_mmap));
                     new_mmap
                 }
@@ -511,4 +653,29 @@ mod tests {
             thread.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_storage_dir() {
+        const BLOCK_SIZE: usize = 0x4000;
+
+        // Other tests may be creating streams at the same time, so only
+        // a directory which they can also use is chosen.
+        set_storage_dir(Some(std::env::temp_dir()));
+        let (mut writer, mut reader) = stream::<BLOCK_SIZE>().unwrap();
+        let data: Vec<u8> = (0..BLOCK_SIZE * 3 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        writer.append(&data).unwrap();
+        set_storage_dir(None);
+
+        // The whole blocks were written to the file, and can be read back.
+        assert!(reader.shared.storage.file.load().is_some());
+        let mut position = 0;
+        while position < data.len() {
+            let range = position as u64..data.len() as u64;
+            let chunk = reader.access(&range).unwrap();
+            assert_eq!(&chunk[..], &data[position..position + chunk.len()]);
+            position += chunk.len();
+        }
+    }
 }
@@ -0,0 +1,244 @@
+//! Periods during which the bus carried no packets, because it was
+//! suspended or, at low speed, held awake only by keep-alives, kept up to
+//! date as a capture grows.
+//!
+//! The analyzer captures packets, not the bus states between them, so
+//! these periods are found from the gaps between packets. At full and high
+//! speed, the host sends a SOF in every frame while the bus is awake, so
+//! a gap of more than 3 ms, after which devices enter suspend, shows that
+//! the bus was suspended, and the next packet that it was resumed. A low
+//! speed bus carries no SOFs; instead, the host ends each frame with a
+//! keep-alive, which is not a packet and so is not captured. Gaps of a
+//! frame or more in low speed traffic are therefore shown as keep-alive
+//! periods, which cannot be told apart from a suspend.
+
+use anyhow::Error;
+
+use crate::capture::{milliseconds, CaptureReader, PacketId, Timestamp};
+use crate::speeds::{BusSpeed, BusSpeeds};
+
+/// Time without bus activity after which devices enter suspend, in
+/// nanoseconds.
+pub const SUSPEND_TIME: Timestamp = 3_000_000;
+
+/// Length of a full or low speed frame, in nanoseconds.
+const FRAME_TIME: Timestamp = 1_000_000;
+
+/// Number of packet times read from the capture at once.
+const CHUNK_SIZE: u64 = 0x10000;
+
+/// What the bus was doing while it carried no packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleKind {
+    /// A low speed bus, which the host holds awake with keep-alives.
+    KeepAlive,
+    /// A full or high speed bus with no SOFs, so suspended.
+    Suspended,
+}
+
+impl IdleKind {
+    /// Name of this kind of period, as shown in the timeline.
+    pub fn label(&self) -> &'static str {
+        use IdleKind::*;
+        match self {
+            KeepAlive => "Keep-alive",
+            Suspended => "Suspended",
+        }
+    }
+}
+
+/// A period during which the bus carried no packets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IdlePeriod {
+    pub kind: IdleKind,
+    /// Time of the last packet before the period.
+    pub start: Timestamp,
+    /// Time of the first packet after it, when the bus was resumed.
+    pub end: Timestamp,
+    /// The first packet after the period.
+    pub resumed_by: PacketId,
+}
+
+impl IdlePeriod {
+    pub fn duration(&self) -> Timestamp {
+        self.end - self.start
+    }
+
+    /// Number of keep-alives the host should have sent during the period,
+    /// one at the end of each frame.
+    pub fn keep_alives(&self) -> u64 {
+        self.duration() / FRAME_TIME
+    }
+
+    pub fn description(&self) -> String {
+        let duration = milliseconds(self.duration());
+        match self.kind {
+            IdleKind::KeepAlive if self.duration() > SUSPEND_TIME => format!(
+                "No packets for {duration}: held awake by about {} \
+                 keep-alives, or suspended", self.keep_alives()),
+            IdleKind::KeepAlive => format!(
+                "No packets for {duration}: held awake by about {} \
+                 keep-alives", self.keep_alives()),
+            IdleKind::Suspended => format!(
+                "Suspended for {duration}, with no SOFs, then resumed"),
+        }
+    }
+}
+
+/// The idle periods found in a capture, in order of time.
+#[derive(Clone, Debug, Default)]
+pub struct IdlePeriods {
+    pub periods: Vec<IdlePeriod>,
+    /// The speeds of the bus, which decide what a gap means.
+    speeds: BusSpeeds,
+    /// Number of packets checked.
+    checked: u64,
+    /// Time of the last packet checked.
+    last_time: Option<Timestamp>,
+}
+
+impl IdlePeriods {
+    pub fn new() -> Self {
+        IdlePeriods::default()
+    }
+
+    /// Find the idle periods in a whole capture.
+    pub fn find(capture: &mut CaptureReader) -> Result<IdlePeriods, Error> {
+        let mut periods = IdlePeriods::new();
+        periods.update(capture)?;
+        Ok(periods)
+    }
+
+    /// Check the packets added to a capture since the last update.
+    ///
+    /// Returns whether any idle periods were found.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<bool, Error>
+    {
+        self.speeds.update(capture)?;
+        let found = self.periods.len();
+        let packet_count = capture.packet_times.len();
+        while self.checked < packet_count {
+            let end = packet_count.min(self.checked + CHUNK_SIZE);
+            let range = PacketId::from(self.checked)..PacketId::from(end);
+            let times = capture.packet_times.get_range(&range)?;
+            for (index, time) in (self.checked..end).zip(times) {
+                let packet_id = PacketId::from(index);
+                if let Some(last_time) = self.last_time.replace(time) {
+                    self.check_gap(last_time, time, packet_id);
+                }
+            }
+            self.checked = end;
+        }
+        Ok(self.periods.len() > found)
+    }
+
+    /// Check the gap between a packet and the one before it.
+    fn check_gap(&mut self,
+                 start: Timestamp,
+                 end: Timestamp,
+                 packet_id: PacketId)
+    {
+        let gap = end.saturating_sub(start);
+        // A capture with no SOFs is taken to be of a low speed bus.
+        let kind = match self.speeds.speed_at(packet_id) {
+            Some(BusSpeed::High | BusSpeed::Full) if gap > SUSPEND_TIME =>
+                IdleKind::Suspended,
+            None | Some(BusSpeed::Low) if gap >= FRAME_TIME =>
+                IdleKind::KeepAlive,
+            _ => return,
+        };
+        self.periods.push(IdlePeriod {
+            kind,
+            start,
+            end,
+            resumed_by: packet_id,
+        });
+    }
+
+    /// The periods overlapping a window of time.
+    pub fn in_window(&self, start: Timestamp, end: Timestamp)
+        -> impl Iterator<Item=&IdlePeriod>
+    {
+        let first = self.periods.partition_point(|period| period.end < start);
+        self.periods[first..]
+            .iter()
+            .take_while(move |period| period.start <= end)
+    }
+
+    /// Total time spent in periods of the given kind.
+    pub fn total(&self, kind: IdleKind) -> Timestamp {
+        self.periods
+            .iter()
+            .filter(|period| period.kind == kind)
+            .map(IdlePeriod::duration)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    fn decode(text: &str, times: &[Timestamp]) -> CaptureReader {
+        let scenario = Scenario::parse("idle", text).unwrap();
+        let (writer, capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (packet, time) in scenario.packets().zip(times) {
+            decoder.handle_raw_packet(packet, *time).unwrap();
+        }
+        decoder.finish().unwrap();
+        capture
+    }
+
+    #[test]
+    fn test_suspend() {
+        // SOFs every 125 µs, stopping for 10 ms while suspended.
+        let mut times: Vec<Timestamp> = (0..16).map(|i| i * 125_000).collect();
+        times.extend((0..8).map(|i| 12_000_000 + i * 125_000));
+        let mut capture = decode("
+            speed high
+            sof 24", &times);
+        let periods = IdlePeriods::find(&mut capture).unwrap();
+        assert_eq!(periods.periods, [IdlePeriod {
+            kind: IdleKind::Suspended,
+            start: 1_875_000,
+            end: 12_000_000,
+            resumed_by: PacketId::from(16),
+        }]);
+        assert_eq!(periods.periods[0].description(),
+                   "Suspended for 10.125 ms, with no SOFs, then resumed");
+        assert_eq!(periods.total(IdleKind::Suspended), 10_125_000);
+        assert_eq!(periods.in_window(0, 1_000_000).count(), 0);
+        assert_eq!(periods.in_window(11_000_000, 13_000_000).count(), 1);
+    }
+
+    #[test]
+    fn test_keep_alives() {
+        // A low speed device polled every 10 ms, with no SOFs.
+        let text = "
+            speed low
+            device 1
+            endpoint 1 1 in interrupt 8
+            nak 1 1 in 3";
+        let times = [0, 1000, 10_000_000, 10_001_000, 20_000_000, 20_001_000];
+        let mut capture = decode(text, &times);
+        let mut periods = IdlePeriods::new();
+        assert!(periods.update(&mut capture).unwrap());
+        assert_eq!(periods.periods.len(), 2);
+        let period = periods.periods[0];
+        assert_eq!(period.kind, IdleKind::KeepAlive);
+        assert_eq!(period.keep_alives(), 9);
+        assert_eq!(period.description(),
+                   "No packets for 9.999 ms: held awake by about 9 \
+                    keep-alives, or suspended");
+        assert!(!periods.update(&mut capture).unwrap());
+
+        // Nothing is found in gaps shorter than a frame.
+        let mut capture = decode(text, &[0, 1000, 500_000, 501_000]);
+        assert!(IdlePeriods::find(&mut capture).unwrap().periods.is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+//! Checks of the throughput achieved on endpoints against targets, for
+//! performance acceptance testing.
+//!
+//! A target gives the lowest rate at which data should be carried on an
+//! endpoint, in one direction, over a window of time. The rate is measured
+//! over every window of that length within the time the endpoint was
+//! active, from its first data to its last, and the target is met if no
+//! window falls short of it. An endpoint active for less than a window is
+//! measured over a single window starting at its first data.
+
+use std::fmt;
+
+use anyhow::{Context, Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    Timestamp,
+    INVALID_EP_ID,
+    FRAMING_EP_ID,
+};
+use crate::usb::{DeviceAddr, EndpointAddr};
+
+const NS_PER_SEC: u128 = 1_000_000_000;
+
+/// Window over which throughput is measured if none is given.
+pub const DEFAULT_WINDOW: u64 = 1_000_000_000;
+
+/// An expected throughput on an endpoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ThroughputTarget {
+    pub device: DeviceAddr,
+    /// Address of the endpoint, including the direction of the data.
+    pub endpoint: EndpointAddr,
+    /// Lowest acceptable rate, in bytes per second.
+    pub rate: u64,
+    /// Length of the windows over which the rate is measured, in
+    /// nanoseconds.
+    pub window: u64,
+}
+
+impl ThroughputTarget {
+    /// Parse a target such as `5.0x81=1.5MB/s` or `5.0x02=400KiB/s@100ms`.
+    ///
+    /// The endpoint is given by its address, including the direction bit.
+    /// The rate is in bytes per second, with an optional decimal or binary
+    /// multiplier, and may be followed by the window to measure it over,
+    /// with a unit of s, ms or us.
+    pub fn parse(text: &str) -> Result<ThroughputTarget, Error> {
+        let invalid = || format!(
+            "Invalid throughput target '{text}', expected e.g. \
+             5.0x81=1.5MB/s for endpoint 0x81 of device 5");
+        let (target, rate) = text.split_once('=').with_context(invalid)?;
+        let (device, endpoint) = target.split_once('.').with_context(invalid)?;
+        let device = parse_number(device).with_context(invalid)?;
+        let endpoint = parse_number(endpoint).with_context(invalid)?;
+        if device > 127 || endpoint & 0x70 != 0 {
+            bail!(invalid())
+        }
+        let (rate, window) = match rate.split_once('@') {
+            Some((rate, window)) => (rate, parse_window(window)?),
+            None => (rate, DEFAULT_WINDOW),
+        };
+        Ok(ThroughputTarget {
+            device: DeviceAddr(device as u8),
+            endpoint: EndpointAddr(endpoint as u8),
+            rate: parse_rate(rate)?,
+            window,
+        })
+    }
+}
+
+impl fmt::Display for ThroughputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "device {}, endpoint 0x{:02X} {}",
+               self.device, self.endpoint.0, self.endpoint.direction())
+    }
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parse a rate in bytes per second, e.g. `1.5MB/s` or `400KiB/s`.
+fn parse_rate(text: &str) -> Result<u64, Error> {
+    let invalid = || format!(
+        "Invalid rate '{text}', expected e.g. 1.5MB/s or 400KiB/s");
+    let unit = text.trim().strip_suffix("/s").with_context(invalid)?;
+    let split = unit
+        .find(|c: char| c.is_ascii_alphabetic())
+        .with_context(invalid)?;
+    let (value, unit) = unit.split_at(split);
+    let value: f64 = value.parse().ok().with_context(invalid)?;
+    let scale = match unit {
+        "B" => 1.0,
+        "kB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => bail!(invalid()),
+    };
+    if !value.is_finite() || value <= 0.0 {
+        bail!(invalid())
+    }
+    Ok((value * scale).round() as u64)
+}
+
+/// Parse the length of a window, e.g. `100ms`, in nanoseconds.
+fn parse_window(text: &str) -> Result<u64, Error> {
+    let invalid = || format!(
+        "Invalid window '{text}', expected e.g. 100ms or 2s");
+    let split = text
+        .find(|c: char| c.is_ascii_alphabetic())
+        .with_context(invalid)?;
+    let (value, unit) = text.split_at(split);
+    let value: f64 = value.parse().ok().with_context(invalid)?;
+    let scale = match unit {
+        "s" => 1e9,
+        "ms" => 1e6,
+        "us" => 1e3,
+        _ => bail!(invalid()),
+    };
+    let window = (value * scale).round();
+    if !window.is_finite() || window < 1.0 {
+        bail!(invalid())
+    }
+    Ok(window as u64)
+}
+
+/// The throughput found on an endpoint, compared with its target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThroughputResult {
+    pub target: ThroughputTarget,
+    /// Total bytes of data carried.
+    pub total: u64,
+    /// Times of the first and last data, if any was carried.
+    pub active: Option<(Timestamp, Timestamp)>,
+    /// Start of the window with the lowest rate, and that rate in bytes
+    /// per second, if any data was carried.
+    pub slowest: Option<(Timestamp, u64)>,
+}
+
+impl ThroughputResult {
+    /// Whether the target was met.
+    pub fn passed(&self) -> bool {
+        matches!(self.slowest, Some((_, rate)) if rate >= self.target.rate)
+    }
+
+    /// Rate over the whole time the endpoint was active, or over one
+    /// window if it was active for less, in bytes per second.
+    pub fn average(&self) -> Option<u64> {
+        let (first, last) = self.active?;
+        let span = (last - first).max(self.target.window);
+        Some(rate(self.total, span))
+    }
+}
+
+/// Rate at which a number of bytes are carried in a length of time, in
+/// bytes per second.
+fn rate(bytes: u64, duration: u64) -> u64 {
+    (bytes as u128 * NS_PER_SEC / duration.max(1) as u128) as u64
+}
+
+/// Measure the throughput on an endpoint against a target.
+pub fn check_target(capture: &mut CaptureReader, target: &ThroughputTarget)
+    -> Result<ThroughputResult, Error>
+{
+    let mut payloads = Vec::new();
+    for id in 0..capture.endpoints.len() {
+        let endpoint_id = EndpointId::from(id);
+        if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+            continue;
+        }
+        let endpoint = capture.endpoints.get(endpoint_id)?;
+        if endpoint.device_address() != target.device ||
+            endpoint.number() != target.endpoint.number()
+        {
+            continue;
+        }
+        let wanted = target.endpoint.direction() as u8;
+        for (transaction_id, direction, bytes) in
+            capture.endpoint_payloads(endpoint_id)?
+        {
+            if direction as u8 == wanted {
+                let packet_id = capture.transaction_index.get(transaction_id)?;
+                let time = capture.packet_time(packet_id)?;
+                payloads.push((time, bytes.len() as u64));
+            }
+        }
+    }
+    payloads.sort_unstable();
+    let total = payloads.iter().map(|(_, length)| length).sum();
+    let slowest = slowest_window(&payloads, target.window);
+    Ok(ThroughputResult {
+        target: *target,
+        total,
+        active: payloads
+            .first()
+            .zip(payloads.last())
+            .map(|((first, _), (last, _))| (*first, *last)),
+        slowest: slowest.map(|(start, bytes)|
+            (start, rate(bytes, target.window))),
+    })
+}
+
+/// Find the window with the fewest bytes within the span of some data,
+/// given in order of time, returning its start and the bytes within it.
+fn slowest_window(payloads: &[(Timestamp, u64)], window: u64)
+    -> Option<(Timestamp, u64)>
+{
+    let first = payloads.first()?.0;
+    let last = payloads.last()?.0;
+    let mut sums = Vec::with_capacity(payloads.len() + 1);
+    sums.push(0);
+    for (_, length) in payloads {
+        sums.push(sums.last().unwrap() + length);
+    }
+    // Windows start from the first data, until the last window which
+    // ends just after the last data.
+    let latest = (last + 1).saturating_sub(window).max(first);
+    // The bytes in a window only fall as its start passes some data, so
+    // the slowest window starts at the first data, just after some data,
+    // or as late as possible.
+    let starts = std::iter::once(first)
+        .chain(payloads
+            .iter()
+            .map(|(time, _)| time + 1)
+            .filter(|start| *start < latest))
+        .chain(std::iter::once(latest));
+    starts
+        .map(|start| {
+            let begin = payloads.partition_point(|(time, _)| *time < start);
+            let end = payloads.partition_point(|(time, _)|
+                *time < start.saturating_add(window));
+            (sums[end] - sums[begin], start)
+        })
+        .min()
+        .map(|(bytes, start)| (start, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(ThroughputTarget::parse("5.0x81=1.5MB/s").unwrap(),
+                   ThroughputTarget {
+                       device: DeviceAddr(5),
+                       endpoint: EndpointAddr(0x81),
+                       rate: 1_500_000,
+                       window: DEFAULT_WINDOW,
+                   });
+        let target = ThroughputTarget::parse("12.2=400KiB/s@100ms").unwrap();
+        assert_eq!(target.endpoint, EndpointAddr(2));
+        assert_eq!(target.rate, 409_600);
+        assert_eq!(target.window, 100_000_000);
+        assert_eq!(target.to_string(), "device 12, endpoint 0x02 OUT");
+        assert!(ThroughputTarget::parse("5.0x81=1.5MB").is_err());
+        assert!(ThroughputTarget::parse("5.0x81=fast/s").is_err());
+        assert!(ThroughputTarget::parse("5.0x81=1MB/s@0ms").is_err());
+        assert!(ThroughputTarget::parse("5.0x91=1MB/s").is_err());
+    }
+
+    #[test]
+    fn test_slowest_window() {
+        let payloads = [(0, 100), (10, 100), (20, 100), (50, 100), (60, 100)];
+        // The window from just after 20 to 40 holds nothing.
+        assert_eq!(slowest_window(&payloads, 20), Some((21, 0)));
+        assert_eq!(slowest_window(&payloads, 40), Some((1, 200)));
+        // A window longer than the data is measured once.
+        assert_eq!(slowest_window(&payloads, 100), Some((0, 500)));
+        assert_eq!(slowest_window(&[], 100), None);
+    }
+
+    #[test]
+    fn test_check_target() {
+        // Transfers of 63 bytes each millisecond, with a gap of 3 ms.
+        let times = [0, 1, 2, 3, 6, 7];
+        let mut text = String::from("
+            speed full
+            device 3
+            endpoint 3 1 in bulk 64
+            enumerate 3");
+        for _ in times {
+            text += &format!("\nin 3 1 {}", "55".repeat(63));
+        }
+        let scenario = Scenario::parse("throughput", &text).unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        let packets: Vec<&[u8]> = scenario.packets().collect();
+        // Each transfer is an IN, a DATA and an ACK packet, after the
+        // enumeration, which is given the time of the first.
+        let start = packets.len() - times.len() * 3;
+        for (index, packet) in packets.into_iter().enumerate() {
+            let time = times[index.saturating_sub(start) / 3] * 1_000_000;
+            decoder.handle_raw_packet(packet, time).unwrap();
+        }
+        decoder.finish().unwrap();
+        let target = ThroughputTarget::parse("3.0x81=30kB/s@4ms").unwrap();
+        let result = check_target(&mut capture, &target).unwrap();
+        assert_eq!(result.total, 6 * 63);
+        assert_eq!(result.active, Some((0, 7_000_000)));
+        // The slowest 4 ms windows hold two transfers.
+        assert_eq!(result.slowest, Some((1_000_001, 31_500)));
+        assert_eq!(result.average(), Some(54_000));
+        assert!(result.passed());
+        // Any 2 ms window in the gap holds nothing.
+        let target = ThroughputTarget::parse("3.0x81=30kB/s@2ms").unwrap();
+        let result = check_target(&mut capture, &target).unwrap();
+        assert_eq!(result.slowest, Some((3_000_001, 0)));
+        assert!(!result.passed());
+        let target = ThroughputTarget::parse("3.0x01=1B/s").unwrap();
+        let result = check_target(&mut capture, &target).unwrap();
+        assert_eq!((result.total, result.slowest), (0, None));
+        assert!(!result.passed());
+    }
+}
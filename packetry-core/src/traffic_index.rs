@@ -0,0 +1,186 @@
+//! An index of the top-level traffic items of a capture by endpoint and by
+//! error, built on a background thread as the capture grows.
+//!
+//! Display filters on endpoint, device, transfer type and error use this
+//! to go straight to the items which may match, rather than checking every
+//! item in the capture.
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Acquire;
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use anyhow::{Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    TrafficItem,
+    TrafficItemId,
+};
+
+/// Maximum number of items to index at a time.
+const INDEX_BATCH_SIZE: u64 = 100_000;
+
+/// Time to wait for more items once all those in a capture are indexed.
+const INDEX_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The top-level items of a capture, by endpoint and by error.
+#[derive(Clone, Debug, Default)]
+pub struct TrafficIndex {
+    /// Items on each endpoint, in capture order.
+    endpoint_items: Vec<Vec<TrafficItemId>>,
+    /// Items showing an error on each endpoint, in capture order.
+    endpoint_errors: Vec<Vec<TrafficItemId>>,
+    /// Number of items whose endpoints are indexed.
+    indexed: u64,
+    /// Number of items whose errors are indexed. An item's error is only
+    /// indexed once its transfer is complete, since until then it may
+    /// still change.
+    errors_indexed: u64,
+}
+
+/// Items indexed in one batch, to be added to the index.
+struct Batch {
+    items: Vec<(EndpointId, TrafficItemId)>,
+    errors: Vec<(EndpointId, TrafficItemId)>,
+    errors_end: u64,
+}
+
+impl TrafficIndex {
+    pub fn new() -> Self {
+        TrafficIndex::default()
+    }
+
+    /// Number of items whose endpoints are indexed.
+    pub fn indexed(&self) -> u64 {
+        self.indexed
+    }
+
+    /// Number of endpoints with items in the index.
+    pub fn endpoint_count(&self) -> u64 {
+        self.endpoint_items.len() as u64
+    }
+
+    /// Indexed items on an endpoint, starting from the given item.
+    pub fn endpoint_items(&self, endpoint_id: EndpointId, start: TrafficItemId)
+        -> &[TrafficItemId]
+    {
+        match self.endpoint_items.get(endpoint_id.value as usize) {
+            Some(items) => &items[items.partition_point(|id| *id < start)..],
+            None => &[],
+        }
+    }
+
+    /// Whether an item on an endpoint shows an error, if yet known.
+    pub fn item_error(&self, endpoint_id: EndpointId, item_id: TrafficItemId)
+        -> Option<bool>
+    {
+        if item_id.value >= self.errors_indexed {
+            return None;
+        }
+        Some(self.endpoint_errors
+            .get(endpoint_id.value as usize)
+            .map_or(false, |errors| errors.binary_search(&item_id).is_ok()))
+    }
+
+    /// Index the next batch of items in a capture.
+    fn next_batch(&self, capture: &mut CaptureReader)
+        -> Result<Batch, Error>
+    {
+        // Check whether the capture is complete before looking at the
+        // items, so that the last transfers are not wrongly taken as
+        // complete.
+        let complete = capture.shared.complete.load(Acquire);
+        let item_count = capture.item_index.len();
+        let items_end = item_count.min(self.indexed + INDEX_BATCH_SIZE);
+        let mut items = Vec::new();
+        for index in self.indexed..items_end {
+            let item_id = TrafficItemId::from(index);
+            let item = TrafficItem::Transfer(capture.item_index.get(item_id)?);
+            items.push((capture.item_endpoint_id(&item)?, item_id));
+        }
+        let mut errors = Vec::new();
+        let mut errors_end = self.errors_indexed;
+        while errors_end < items_end {
+            let item_id = TrafficItemId::from(errors_end);
+            let transfer_id = capture.item_index.get(item_id)?;
+            let entry = capture.transfer_index.get(transfer_id)?;
+            let endpoint_id = entry.endpoint_id();
+            if !complete && entry.is_start() {
+                let ep_traf = capture.endpoint_traffic(endpoint_id)?;
+                if entry.transfer_id().value >= ep_traf.end_index.len() {
+                    // This transfer is ongoing; index its error later.
+                    break;
+                }
+            }
+            if capture.item_error(&TrafficItem::Transfer(transfer_id))? {
+                errors.push((endpoint_id, item_id));
+            }
+            errors_end += 1;
+        }
+        Ok(Batch { items, errors, errors_end })
+    }
+
+    /// Add a batch of indexed items.
+    fn add(&mut self, batch: Batch) {
+        for (endpoint_id, item_id) in batch.items {
+            let index = endpoint_id.value as usize;
+            if index >= self.endpoint_items.len() {
+                self.endpoint_items.resize(index + 1, Vec::new());
+                self.endpoint_errors.resize(index + 1, Vec::new());
+            }
+            self.endpoint_items[index].push(item_id);
+            self.indexed = item_id.value + 1;
+        }
+        for (endpoint_id, item_id) in batch.errors {
+            self.endpoint_errors[endpoint_id.value as usize].push(item_id);
+        }
+        self.errors_indexed = batch.errors_end;
+    }
+}
+
+/// Index the next batch of items in a capture.
+///
+/// Returns whether any more items were indexed.
+pub fn update_index(capture: &mut CaptureReader) -> Result<bool, Error> {
+    let shared = capture.shared.clone();
+    // Only this function changes the index, so the batch can be found
+    // without holding the write lock, which is only taken to add it.
+    let batch = match shared.traffic_index.read() {
+        Ok(index) => index.next_batch(capture)?,
+        Err(_) => bail!("Traffic index lock poisoned"),
+    };
+    let progress = match shared.traffic_index.write() {
+        Ok(mut index) => {
+            let progress = !batch.items.is_empty() ||
+                batch.errors_end > index.errors_indexed;
+            index.add(batch);
+            progress
+        },
+        Err(_) => bail!("Traffic index lock poisoned"),
+    };
+    Ok(progress)
+}
+
+/// Start indexing a capture on a background thread.
+///
+/// The thread stops once the capture is complete and fully indexed, or
+/// when no other handles to the capture remain.
+pub fn start_indexing(capture: &CaptureReader) {
+    let mut capture = capture.clone();
+    spawn(move || {
+        while Arc::strong_count(&capture.shared) > 1 {
+            let complete = capture.shared.complete.load(Acquire);
+            match update_index(&mut capture) {
+                Ok(true) => {},
+                Ok(false) if complete => return,
+                Ok(false) => sleep(INDEX_INTERVAL),
+                Err(e) => {
+                    eprintln!("Stopped indexing capture: {e}");
+                    return;
+                }
+            }
+        }
+    });
+}
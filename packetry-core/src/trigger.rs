@@ -0,0 +1,154 @@
+//! Triggers fired when traffic matching a display filter is captured, so
+//! that an analyzer output can start an oscilloscope or logic analyzer on
+//! a USB protocol event.
+
+use std::sync::atomic::Ordering::Acquire;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, ItemSource, TrafficItem};
+use crate::filter::Filter;
+
+/// Time to wait for more traffic before checking a live capture again.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A filter checked against each top-level item as it is captured.
+pub struct Trigger {
+    filter: Filter,
+    /// Number of top-level items checked.
+    checked: u64,
+    /// Number of items which matched.
+    matched: u64,
+}
+
+impl Trigger {
+    pub fn new(filter: Filter) -> Self {
+        Trigger {
+            filter,
+            checked: 0,
+            matched: 0,
+        }
+    }
+
+    /// Number of items which have matched so far.
+    pub fn matched(&self) -> u64 {
+        self.matched
+    }
+
+    /// Check the top-level items added since the last update.
+    ///
+    /// The last item may still be growing, so it is only checked once
+    /// another follows it, or the capture is complete. Returns the number
+    /// of items which matched.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<u64, Error>
+    {
+        let complete = capture.shared.complete.load(Acquire);
+        let mut item_count = capture.item_index.len();
+        if !complete {
+            item_count = item_count.saturating_sub(1);
+        }
+        let mut matched = 0;
+        for index in self.checked..item_count {
+            let item: TrafficItem = capture.item(None, index)?;
+            if self.filter.matches(capture, &item)? {
+                matched += 1;
+            }
+        }
+        self.checked = self.checked.max(item_count);
+        self.matched += matched;
+        Ok(matched)
+    }
+
+    /// Watch a live capture until it is complete, firing the trigger when
+    /// new items match.
+    ///
+    /// Items found to match in the same check fire the trigger only once,
+    /// so that it is not fired faster than it can be driven. Returns the
+    /// number of times it was fired.
+    pub fn watch<F>(&mut self, capture: &mut CaptureReader, mut fire: F)
+        -> Result<u64, Error>
+        where F: FnMut() -> Result<(), Error>
+    {
+        let mut fired = 0;
+        loop {
+            // Check whether the capture was complete before the final
+            // update, so that no items are missed.
+            let complete = capture.shared.complete.load(Acquire);
+            if self.update(capture)? > 0 {
+                fire()?;
+                fired += 1;
+            }
+            if complete {
+                return Ok(fired);
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::spawn;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_trigger() {
+        let scenario = Scenario::parse("trigger", "
+            device 1
+            endpoint 1 1 in bulk 512
+            enumerate 1
+            in 1 1 01:02
+            in 1 1 03:04
+            in 1 1 01:02
+        ").unwrap();
+        let packets: Vec<Vec<u8>> = scenario
+            .packets()
+            .map(|packet| packet.to_vec())
+            .collect();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        let filter = Filter::parse("data contains 01:02").unwrap();
+        let mut trigger = Trigger::new(filter.clone());
+
+        // The last item is not checked until the capture is complete.
+        let last_transfer = packets.len() - 3;
+        for (i, packet) in packets[..last_transfer].iter().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        trigger.update(&mut capture).unwrap();
+        assert_eq!(trigger.matched(), 1);
+        for (i, packet) in packets.iter().enumerate().skip(last_transfer) {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+        assert_eq!(trigger.update(&mut capture).unwrap(), 1);
+        assert_eq!(trigger.matched(), 2);
+        assert_eq!(trigger.update(&mut capture).unwrap(), 0);
+
+        // A watched capture fires until it is complete.
+        let (writer, mut capture) = create_capture().unwrap();
+        let decoding = spawn(move || {
+            let mut decoder = Decoder::new(writer).unwrap();
+            for (i, packet) in packets.iter().enumerate() {
+                decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+            }
+            decoder.finish().unwrap();
+        });
+        let mut trigger = Trigger::new(filter);
+        let mut pulses = 0;
+        let fired = trigger.watch(&mut capture, || {
+            pulses += 1;
+            Ok(())
+        }).unwrap();
+        decoding.join().unwrap();
+        assert!(fired >= 1);
+        assert_eq!(fired, pulses);
+        assert_eq!(trigger.matched(), 2);
+    }
+}
@@ -1,10 +1,13 @@
+//! USB protocol definitions: PIDs, packet fields, requests and descriptors.
+
 use std::mem::size_of;
 
 use bytemuck_derive::{Pod, Zeroable};
-use bytemuck::pod_read_unaligned;
+use bytemuck::{bytes_of, pod_read_unaligned};
 use num_enum::{IntoPrimitive, FromPrimitive};
 use derive_more::{From, Into, Display};
 
+use crate::usb_ids::{database, with_name};
 use crate::vec_map::VecMap;
 
 #[allow(clippy::upper_case_acronyms)]
@@ -31,6 +34,19 @@ pub enum PID {
     Malformed = 0,
 }
 
+impl PID {
+    /// Minimum length of a packet with this PID, below which it is too
+    /// short to hold the fields for its PID and is treated as malformed.
+    pub fn min_length(&self) -> usize {
+        use PID::*;
+        match self {
+            SOF | SETUP | IN | OUT | PING | DATA0 | DATA1 | DATA2 | MDATA => 3,
+            SPLIT => 4,
+            _ => 1,
+        }
+    }
+}
+
 impl std::fmt::Display for PID {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{self:?}")
@@ -221,20 +237,25 @@ pub enum PacketFields {
 
 impl PacketFields {
     pub fn from_packet(packet: &[u8]) -> Self {
-        let end = packet.len();
         use PID::*;
-        match PID::from(packet[0]) {
-            SOF => PacketFields::SOF(
-                SOFFields(
-                    u16::from_le_bytes([packet[1], packet[2]]))),
-            SETUP | IN | OUT | PING => PacketFields::Token(
-                TokenFields(
-                    u16::from_le_bytes([packet[1], packet[2]]))),
-            DATA0 | DATA1 => PacketFields::Data(
-                DataFields{
-                    crc: u16::from_le_bytes(
-                        [packet[end - 2], packet[end - 1]])}),
-            SPLIT => PacketFields::Split(SplitFields::from_packet(packet)),
+        // Packets too short to hold the fields for their PID have none.
+        match packet {
+            [pid, b1, b2, ..] => match PID::from(*pid) {
+                SOF => PacketFields::SOF(
+                    SOFFields(u16::from_le_bytes([*b1, *b2]))),
+                SETUP | IN | OUT | PING => PacketFields::Token(
+                    TokenFields(u16::from_le_bytes([*b1, *b2]))),
+                DATA0 | DATA1 => {
+                    let end = packet.len();
+                    PacketFields::Data(
+                        DataFields{
+                            crc: u16::from_le_bytes(
+                                [packet[end - 2], packet[end - 1]])})
+                },
+                SPLIT if packet.len() >= 4 =>
+                    PacketFields::Split(SplitFields::from_packet(packet)),
+                _ => PacketFields::None
+            },
             _ => PacketFields::None
         }
     }
@@ -330,6 +351,8 @@ impl StandardRequest {
     pub fn description(&self, fields: &SetupFields) -> String {
         use StandardRequest::*;
         match self {
+            GetStatus if fields.index == OTG_STATUS_SELECTOR =>
+                format!("Getting OTG status"),
             GetStatus => format!("Getting status"),
             ClearFeature | SetFeature => {
                 let feature = StandardFeature::from(fields.value);
@@ -384,8 +407,9 @@ pub enum DescriptorType {
     DeviceQualifier = 6,
     OtherSpeedConfiguration = 7,
     InterfacePower = 8,
+    Otg = 9,
     #[default]
-    Unknown = 9
+    Unknown = 10
 }
 
 impl DescriptorType {
@@ -408,7 +432,7 @@ impl DescriptorType {
 
 impl DescriptorType {
     pub fn description(self) -> &'static str {
-        const STRINGS: [&str; 10] = [
+        const STRINGS: [&str; 11] = [
             "invalid",
             "device",
             "configuration",
@@ -418,28 +442,39 @@ impl DescriptorType {
             "device qualifier",
             "other speed configuration",
             "interface power",
+            "OTG",
             "unknown",
         ];
         STRINGS[self as usize]
     }
 }
 
+/// The wIndex of a GET_STATUS request for a device's OTG status, which
+/// holds the host request flag.
+pub const OTG_STATUS_SELECTOR: u16 = 0xF000;
+
 #[derive(Copy, Clone, Debug, FromPrimitive)]
 #[repr(u16)]
 pub enum StandardFeature {
     EndpointHalt = 0,
     DeviceRemoteWakeup = 1,
     TestMode = 2,
+    BHnpEnable = 3,
+    AHnpSupport = 4,
+    AAltHnpSupport = 5,
     #[default]
-    Unknown = 3
+    Unknown = 6
 }
 
 impl StandardFeature {
     pub fn description(self) -> &'static str {
-        const STRINGS: [&str; 4] = [
+        const STRINGS: [&str; 7] = [
             "endpoint halt",
             "device remote wakeup",
             "test mode",
+            "B-device HNP enable",
+            "A-device HNP support",
+            "A-device alternate HNP support",
             "unknown standard feature",
         ];
         STRINGS[self as usize]
@@ -475,16 +510,24 @@ impl DeviceDescriptor {
                       strings: &VecMap<StringId, UTF16ByteVec>)
         -> String
     {
+        let ids = database();
+        let (class, subclass, protocol) =
+            (self.device_class, self.device_subclass, self.device_protocol);
+        let (vendor_id, product_id) = (self.vendor_id, self.product_id);
         match id.0 {
         0  => format!("Length: {} bytes", self.length),
         1  => format!("Type: 0x{:02X}", self.descriptor_type),
         2  => format!("USB Version: {}", self.usb_version),
-        3  => format!("Class: 0x{:02X}", self.device_class),
-        4  => format!("Subclass: 0x{:02X}", self.device_subclass),
-        5  => format!("Protocol: 0x{:02X}", self.device_protocol),
+        3  => with_name(format!("Class: 0x{class:02X}"), ids.class(class)),
+        4  => with_name(format!("Subclass: 0x{subclass:02X}"),
+                        ids.subclass(class, subclass)),
+        5  => with_name(format!("Protocol: 0x{protocol:02X}"),
+                        ids.protocol(class, subclass, protocol)),
         6  => format!("Max EP0 packet size: {} bytes", self.max_packet_size_0),
-        7  => format!("Vendor ID: 0x{:04X}", self.vendor_id),
-        8  => format!("Product ID: 0x{:04X}", self.product_id),
+        7  => with_name(format!("Vendor ID: 0x{vendor_id:04X}"),
+                        ids.vendor(vendor_id)),
+        8  => with_name(format!("Product ID: 0x{product_id:04X}"),
+                        ids.product(vendor_id, product_id)),
         9  => format!("Version: {}", self.device_version),
         10 => format!("Manufacturer string: {}",
                       fmt_str_id(strings, self.manufacturer_str_id)),
@@ -556,15 +599,22 @@ impl InterfaceDescriptor {
                       strings: &VecMap<StringId, UTF16ByteVec>)
         -> String
     {
+        let ids = database();
+        let (class, subclass, protocol) = (
+            self.interface_class,
+            self.interface_subclass,
+            self.interface_protocol);
         match id.0 {
         0 => format!("Length: {} bytes", self.length),
         1 => format!("Type: 0x{:02X}", self.descriptor_type),
         2 => format!("Interface number: {}", self.interface_number),
         3 => format!("Alternate setting: {}", self.alternate_setting),
         4 => format!("Number of endpoints: {}", self.num_endpoints),
-        5 => format!("Class: 0x{:02X}", self.interface_class),
-        6 => format!("Subclass: 0x{:02X}", self.interface_subclass),
-        7 => format!("Protocol: 0x{:02X}", self.interface_protocol),
+        5 => with_name(format!("Class: 0x{class:02X}"), ids.class(class)),
+        6 => with_name(format!("Subclass: 0x{subclass:02X}"),
+                       ids.subclass(class, subclass)),
+        7 => with_name(format!("Protocol: 0x{protocol:02X}"),
+                       ids.protocol(class, subclass, protocol)),
         8 => format!("Interface string: {}",
                       fmt_str_id(strings, self.interface_str_id)),
         i => format!("Error: Invalid field ID {i}")
@@ -603,11 +653,56 @@ impl EndpointDescriptor {
     pub const NUM_FIELDS: usize = 6;
 }
 
+/// The OTG descriptor, which a device returns with its configuration to
+/// show which On-The-Go protocols it supports.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OtgDescriptor {
+    pub attributes: u8,
+    /// The OTG version, given only by devices to OTG 2.0 or later.
+    pub otg_version: Option<BCDVersion>,
+}
+
+impl OtgDescriptor {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        OtgDescriptor {
+            attributes: bytes[2],
+            otg_version: bytes.get(3..5).map(|version| BCDVersion {
+                minor: version[0],
+                major: version[1],
+            }),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![3, DescriptorType::Otg as u8, self.attributes];
+        if let Some(version) = self.otg_version {
+            bytes[0] = 5;
+            bytes.extend_from_slice(bytes_of(&version));
+        }
+        bytes
+    }
+
+    /// The protocols supported: SRP, HNP and ADP.
+    pub fn protocols(&self) -> Vec<&'static str> {
+        ["SRP", "HNP", "ADP"]
+            .into_iter()
+            .enumerate()
+            .filter(|(bit, _)| self.attributes & (1 << bit) != 0)
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    pub fn supports_hnp(&self) -> bool {
+        self.attributes & 0x02 != 0
+    }
+}
+
 pub enum Descriptor {
     Device(DeviceDescriptor),
     Configuration(ConfigDescriptor),
     Interface(InterfaceDescriptor),
     Endpoint(EndpointDescriptor),
+    Otg(OtgDescriptor),
     Other(DescriptorType)
 }
 
@@ -629,13 +724,32 @@ impl Iterator for DescriptorIterator<'_> {
     type Item = Descriptor;
 
     fn next(&mut self) -> Option<Descriptor> {
-        while self.offset < self.bytes.len() - 2 {
+        while self.offset + 2 < self.bytes.len() {
             let remaining_bytes = &self.bytes[self.offset .. self.bytes.len()];
             let desc_length = remaining_bytes[0] as usize;
             let desc_type = DescriptorType::from(remaining_bytes[1]);
+            if desc_length < 2 {
+                // No descriptor can be this short, and without a valid
+                // length the rest of the data cannot be parsed.
+                self.offset = self.bytes.len();
+                break;
+            }
             self.offset += desc_length;
+            // The OTG descriptor grew two bytes in OTG 2.0.
+            if desc_type == DescriptorType::Otg {
+                if matches!(desc_length, 3 | 5) &&
+                    desc_length <= remaining_bytes.len()
+                {
+                    let bytes = &remaining_bytes[0 .. desc_length];
+                    return Some(Descriptor::Otg(
+                        OtgDescriptor::from_bytes(bytes)));
+                }
+                continue
+            }
             if let Some(expected) = desc_type.expected_length() {
-                if desc_length != expected {
+                if desc_length != expected ||
+                    desc_length > remaining_bytes.len()
+                {
                     continue
                 }
                 let bytes = &remaining_bytes[0 .. desc_length];
@@ -668,9 +782,25 @@ pub struct Interface {
 pub struct Configuration {
     pub descriptor: ConfigDescriptor,
     pub interfaces: VecMap<InterfaceNum, Interface>,
+    pub otg: Option<OtgDescriptor>,
 }
 
 impl Configuration {
+    /// Descriptor bytes from which this configuration can be reconstructed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bytes_of(&self.descriptor).to_vec();
+        if let Some(otg) = &self.otg {
+            bytes.extend_from_slice(&otg.to_bytes());
+        }
+        for iface in &self.interfaces {
+            bytes.extend_from_slice(bytes_of(&iface.descriptor));
+            for ep_desc in &iface.endpoint_descriptors {
+                bytes.extend_from_slice(bytes_of(ep_desc));
+            }
+        }
+        bytes
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
         let mut result: Option<Configuration> = None;
         let mut iface_num: Option<InterfaceNum> = None;
@@ -682,6 +812,7 @@ impl Configuration {
                         interfaces:
                             VecMap::with_capacity(
                                 config_desc.num_interfaces),
+                        otg: None,
                     });
                 },
                 Descriptor::Interface(iface_desc) => {
@@ -709,6 +840,11 @@ impl Configuration {
                         }
                     }
                 },
+                Descriptor::Otg(otg_desc) => {
+                    if let Some(config) = result.as_mut() {
+                        config.otg = Some(otg_desc);
+                    }
+                },
                 _ => {},
             };
         }
@@ -720,6 +856,7 @@ pub enum ControlResult {
     Completed,
     Incomplete,
     Stalled,
+    Aborted,
 }
 
 pub struct ControlTransfer {
@@ -797,6 +934,7 @@ impl ControlTransfer {
             ControlResult::Completed => summary,
             ControlResult::Incomplete => format!("{}, incomplete", summary),
             ControlResult::Stalled => format!("{}, stalled", summary),
+            ControlResult::Aborted => format!("{}, aborted", summary),
         }
     }
 }
@@ -850,6 +988,8 @@ impl std::fmt::Display for UTF16ByteVec {
     }
 }
 
+pub use crate::crc::{crc5, crc16, crc32};
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -901,6 +1041,93 @@ mod tests {
             panic!("Expected Data but got {:?}", p);
         }
     }
+
+    #[test]
+    fn test_parse_short() {
+        for packet in [
+            vec![],
+            vec![0xa5, 0xde],
+            vec![0x2d],
+            vec![0xc3, 0x40],
+            vec![0x78, 0x00, 0x00],
+        ] {
+            let p = PacketFields::from_packet(&packet);
+            if !matches!(p, PacketFields::None) {
+                panic!("Expected None but got {:?}", p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_bad_descriptors() {
+        // Too short to hold a descriptor, or longer than the data.
+        for bytes in [
+            vec![0x09],
+            vec![0x09, 0x02, 0x00, 0x00],
+        ] {
+            assert_eq!(DescriptorIterator::from(&bytes).count(), 0);
+        }
+        // A zero length ends parsing rather than repeating forever.
+        let bytes = [0x00, 0x02, 0x00, 0x09, 0x02, 0x19, 0x00, 0x01, 0x01];
+        assert!(Configuration::from_bytes(&bytes).is_none());
+    }
+
+    #[test]
+    fn test_parse_otg_descriptor() {
+        let bytes = [
+            // Configuration descriptor.
+            0x09, 0x02, 0x17, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32,
+            // OTG 2.0 descriptor, with SRP and HNP.
+            0x05, 0x09, 0x03, 0x00, 0x02,
+            // Interface descriptor.
+            0x09, 0x04, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00,
+        ];
+        let config = Configuration::from_bytes(&bytes).unwrap();
+        let otg = config.otg.unwrap();
+        assert_eq!(otg.protocols(), ["SRP", "HNP"]);
+        assert!(otg.supports_hnp());
+        assert_eq!(format!("{}", otg.otg_version.unwrap()), "2.00");
+        assert_eq!(config.interfaces.len(), 1);
+        assert_eq!(config.to_bytes(), bytes);
+
+        // The shorter descriptor from OTG 1.3, which has no version.
+        let otg = OtgDescriptor::from_bytes(&[0x03, 0x09, 0x01]);
+        assert_eq!(otg.protocols(), ["SRP"]);
+        assert!(otg.otg_version.is_none());
+        assert_eq!(otg.to_bytes(), [0x03, 0x09, 0x01]);
+    }
+
+    #[test]
+    fn test_otg_requests() {
+        let describe = |bytes: [u8; 8]| {
+            let mut packet = vec![0xC3];
+            packet.extend_from_slice(&bytes);
+            let fields = SetupFields::from_data_packet(&packet);
+            StandardRequest::from(fields.request).description(&fields)
+        };
+        assert_eq!(describe([0x00, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00]),
+                   "Setting B-device HNP enable");
+        assert_eq!(describe([0x00, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]),
+                   "Setting A-device HNP support");
+        assert_eq!(describe([0x80, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x01, 0x00]),
+                   "Getting OTG status");
+        assert_eq!(describe([0x80, 0x06, 0x00, 0x09, 0x00, 0x00, 0x05, 0x00]),
+                   "Getting OTG descriptor #0");
+    }
+
+    #[test]
+    fn test_crc5() {
+        assert!(crc5(0x002, 11) == 0x15);
+        assert!(crc5(0x082, 11) == 0x03);
+        assert!(crc5(1758, 11) == 0x03);
+    }
+
+    #[test]
+    fn test_crc16() {
+        let payload = [0x40, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(crc16(&payload) == 0xd5aa);
+        assert!(crc16(&[]) == 0x0000);
+    }
 }
 
 pub mod prelude {
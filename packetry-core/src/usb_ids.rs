@@ -0,0 +1,259 @@
+//! Names of USB vendors, products and classes, from a database in the
+//! format of the `usb.ids` file maintained at <http://www.linux-usb.org/>.
+//!
+//! The database is read from the copy installed on the system, if any,
+//! with entries from a local override file taking precedence, so that
+//! custom devices can be named. Until a database is set with
+//! [`set_database`], no names are known.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Error, bail};
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+
+/// Places where the database is installed by common distributions.
+pub const SYSTEM_PATHS: [&str; 4] = [
+    "/usr/share/hwdata/usb.ids",
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+];
+
+static DATABASE: Lazy<ArcSwap<UsbIds>> =
+    Lazy::new(|| ArcSwap::from_pointee(UsbIds::default()));
+
+/// A database of USB ID names.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsbIds {
+    vendors: BTreeMap<u16, String>,
+    products: BTreeMap<(u16, u16), String>,
+    classes: BTreeMap<u8, String>,
+    subclasses: BTreeMap<(u8, u8), String>,
+    protocols: BTreeMap<(u8, u8, u8), String>,
+}
+
+/// The section of the file being parsed, with the enclosing entries.
+enum Section {
+    Vendor(u16),
+    Class(u8, Option<u8>),
+    Other,
+}
+
+impl UsbIds {
+    /// Parse a database in the format of `usb.ids`.
+    ///
+    /// Vendors, products and classes are read, and other sections of the
+    /// file, such as HID usages and language IDs, are skipped.
+    pub fn parse(text: &str) -> Result<UsbIds, Error> {
+        let mut ids = UsbIds::default();
+        let mut section = Section::Other;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let row = number + 1;
+            let depth = line.len() - line.trim_start_matches('\t').len();
+            let (id, name) = split_entry(&line[depth..]);
+            let hex = |digits: usize| -> Result<u16, Error> {
+                if id.len() != digits {
+                    bail!("Invalid ID '{id}' on line {row} of USB ID database")
+                }
+                u16::from_str_radix(id, 16).with_context(|| format!(
+                    "Invalid ID '{id}' on line {row} of USB ID database"))
+            };
+            match (depth, &mut section) {
+                (0, _) => {
+                    section = match (id.strip_prefix("C "), name) {
+                        (Some(class), Some(name)) => {
+                            let class = u8::from_str_radix(class, 16)
+                                .with_context(|| format!(
+                                    "Invalid class on line {row} of \
+                                     USB ID database"))?;
+                            ids.classes.insert(class, name.to_string());
+                            Section::Class(class, None)
+                        },
+                        (None, Some(name)) if id.len() == 4 => {
+                            let vendor = hex(4)?;
+                            ids.vendors.insert(vendor, name.to_string());
+                            Section::Vendor(vendor)
+                        },
+                        _ => Section::Other,
+                    };
+                },
+                (1, Section::Vendor(vendor)) => {
+                    let product = hex(4)?;
+                    ids.products.insert(
+                        (*vendor, product), name.unwrap_or("").to_string());
+                },
+                (1, Section::Class(class, subclass)) => {
+                    let value = hex(2)? as u8;
+                    *subclass = Some(value);
+                    ids.subclasses.insert(
+                        (*class, value), name.unwrap_or("").to_string());
+                },
+                (2, Section::Class(class, Some(subclass))) => {
+                    let protocol = hex(2)? as u8;
+                    ids.protocols.insert(
+                        (*class, *subclass, protocol),
+                        name.unwrap_or("").to_string());
+                },
+                // Interfaces of products, and entries in other sections.
+                _ => {},
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Load a database from a file.
+    pub fn load(path: &Path) -> Result<UsbIds, Error> {
+        let text = read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        UsbIds::parse(&text)
+            .with_context(|| format!("Failed to load {}", path.display()))
+    }
+
+    /// Load the database installed on the system, if one is found.
+    pub fn load_system() -> Result<Option<UsbIds>, Error> {
+        for path in SYSTEM_PATHS.iter().map(Path::new) {
+            if path.exists() {
+                return UsbIds::load(path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Add the entries from another database, replacing any for the
+    /// same IDs.
+    pub fn merge(&mut self, other: UsbIds) {
+        self.vendors.extend(other.vendors);
+        self.products.extend(other.products);
+        self.classes.extend(other.classes);
+        self.subclasses.extend(other.subclasses);
+        self.protocols.extend(other.protocols);
+    }
+
+    pub fn vendor(&self, vendor: u16) -> Option<&str> {
+        self.vendors.get(&vendor).map(String::as_str)
+    }
+
+    pub fn product(&self, vendor: u16, product: u16) -> Option<&str> {
+        self.products.get(&(vendor, product)).map(String::as_str)
+    }
+
+    pub fn class(&self, class: u8) -> Option<&str> {
+        self.classes.get(&class).map(String::as_str)
+    }
+
+    pub fn subclass(&self, class: u8, subclass: u8) -> Option<&str> {
+        self.subclasses.get(&(class, subclass)).map(String::as_str)
+    }
+
+    pub fn protocol(&self, class: u8, subclass: u8, protocol: u8)
+        -> Option<&str>
+    {
+        self.protocols
+            .get(&(class, subclass, protocol))
+            .map(String::as_str)
+    }
+
+    /// Name of a device from its vendor and product IDs, if known.
+    pub fn device_name(&self, vendor: u16, product: u16) -> Option<String> {
+        match (self.vendor(vendor), self.product(vendor, product)) {
+            (Some(vendor), Some(product)) =>
+                Some(format!("{vendor} {product}")),
+            (None, Some(product)) => Some(product.to_string()),
+            (Some(vendor), None) => Some(format!("{vendor} device")),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Split an entry into its ID and name, which are separated by spaces.
+fn split_entry(text: &str) -> (&str, Option<&str>) {
+    match text.split_once("  ") {
+        Some((id, name)) => (id.trim(), Some(name.trim())),
+        None => (text.trim(), None),
+    }
+}
+
+/// Set the database used to name devices and classes.
+pub fn set_database(ids: UsbIds) {
+    DATABASE.store(Arc::new(ids));
+}
+
+/// The database used to name devices and classes.
+pub fn database() -> Arc<UsbIds> {
+    DATABASE.load_full()
+}
+
+/// Load the system's database, with the entries from an override file,
+/// if it exists, taking precedence, and use the result from now on.
+pub fn load_database(override_path: &Path) -> Result<(), Error> {
+    let mut ids = UsbIds::load_system()?.unwrap_or_default();
+    if override_path.exists() {
+        ids.merge(UsbIds::load(override_path)?);
+    }
+    set_database(ids);
+    Ok(())
+}
+
+/// Text followed by a name in brackets, if there is one.
+pub fn with_name(text: String, name: Option<&str>) -> String {
+    match name {
+        Some(name) if !name.is_empty() => format!("{text} ({name})"),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_ids() {
+        let mut ids = UsbIds::parse("\
+# Comment
+1d50  OpenMoko, Inc.
+\t615b  Cynthion USB analyzer
+\t6089  Great Scott Gadgets HackRF One SDR
+\t\t01  Some interface
+
+# Classes
+C 02  Communications
+\t02  Abstract (modem)
+\t\t01  AT-commands (v.25ter)
+C 03  Human Interface Device
+
+HUT 01  Generic Desktop Controls
+\t002  Mouse
+").unwrap();
+        assert_eq!(ids.vendor(0x1D50), Some("OpenMoko, Inc."));
+        assert_eq!(ids.product(0x1D50, 0x615B), Some("Cynthion USB analyzer"));
+        assert_eq!(ids.class(0x03), Some("Human Interface Device"));
+        assert_eq!(ids.subclass(0x02, 0x02), Some("Abstract (modem)"));
+        assert_eq!(ids.protocol(0x02, 0x02, 0x01),
+                   Some("AT-commands (v.25ter)"));
+        assert_eq!(ids.device_name(0x1D50, 0x0001).unwrap(),
+                   "OpenMoko, Inc. device");
+        assert!(ids.device_name(0x1234, 0x0001).is_none());
+
+        // Entries from an override file take precedence.
+        ids.merge(UsbIds::parse("\
+1d50  Great Scott Gadgets
+\t1234  Prototype
+").unwrap());
+        assert_eq!(ids.device_name(0x1D50, 0x1234).unwrap(),
+                   "Great Scott Gadgets Prototype");
+        assert_eq!(ids.product(0x1D50, 0x615B), Some("Cynthion USB analyzer"));
+
+        assert_eq!(with_name(String::from("0x03"), ids.class(0x03)),
+                   "0x03 (Human Interface Device)");
+        assert_eq!(with_name(String::from("0x04"), ids.class(0x04)), "0x04");
+        assert!(UsbIds::parse("1d50  Vendor\n\tzzzz  Product\n").is_err());
+    }
+}
@@ -0,0 +1,629 @@
+//! Reconstruction of USB packets from USBPcap captures.
+//!
+//! USBPcap records traffic as USB Request Blocks (URBs) passing through the
+//! Windows host stack, not as packets on the bus. For each completed request
+//! we generate the sequence of packets that would most plausibly have carried
+//! it, so that the normal decoder can be used on the result.
+//!
+//! Some information does not exist at the URB level at all. NAKed and retried
+//! transactions, bus timing, SOF packets, data toggle errors and CRC errors
+//! are never seen. Data toggles and CRCs in the generated packets are
+//! computed rather than captured, and max packet sizes are taken from any
+//! descriptors seen in the capture, or assumed otherwise.
+
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use anyhow::{Error, bail};
+
+use crate::usb::{self, crc5, crc16, prelude::*};
+
+/// Size of the basic USBPcap packet header.
+const HEADER_LENGTH: usize = 27;
+
+/// Size of the header for isochronous transfers, excluding packet entries.
+const ISO_HEADER_LENGTH: usize = 39;
+
+/// Size of each isochronous packet entry following the header.
+const ISO_PACKET_LENGTH: usize = 12;
+
+const TRANSFER_ISOCHRONOUS: u8 = 0;
+const TRANSFER_INTERRUPT: u8 = 1;
+const TRANSFER_CONTROL: u8 = 2;
+const TRANSFER_BULK: u8 = 3;
+
+const STAGE_SETUP: u8 = 0;
+const STAGE_DATA: u8 = 1;
+
+const INFO_FROM_DEVICE: u8 = 0x01;
+
+const USBD_STATUS_SUCCESS: u32 = 0x00000000;
+const USBD_STATUS_STALL_PID: u32 = 0xC0000004;
+const USBD_STATUS_ENDPOINT_HALTED: u32 = 0xC0000030;
+
+/// Max packet size to assume if no descriptor has been seen.
+const DEFAULT_MAX_PACKET_SIZE: usize = 64;
+
+/// A single record from a USBPcap capture.
+struct Record<'data> {
+    irp_id: u64,
+    status: u32,
+    from_device: bool,
+    device: DeviceAddr,
+    endpoint: EndpointAddr,
+    transfer: u8,
+    stage: Option<u8>,
+    iso_packets: Vec<(usize, usize)>,
+    data: &'data [u8],
+}
+
+impl<'data> Record<'data> {
+    fn parse(bytes: &'data [u8]) -> Result<Record<'data>, Error> {
+        if bytes.len() < HEADER_LENGTH {
+            bail!("USBPcap record too short: {} bytes", bytes.len())
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(
+            [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let header_length = u16_at(0) as usize;
+        let data_length = u32_at(23) as usize;
+        if header_length < HEADER_LENGTH ||
+           header_length + data_length > bytes.len()
+        {
+            bail!("USBPcap record has invalid header length {} \
+                   for data length {} in {} bytes",
+                  header_length, data_length, bytes.len())
+        }
+        let transfer = bytes[22];
+        let stage = match transfer {
+            TRANSFER_CONTROL if header_length > HEADER_LENGTH =>
+                Some(bytes[HEADER_LENGTH]),
+            _ => None,
+        };
+        let iso_packets = match transfer {
+            TRANSFER_ISOCHRONOUS if header_length >= ISO_HEADER_LENGTH => {
+                let count = u32_at(31) as usize;
+                (0..count)
+                    .map(|i| ISO_HEADER_LENGTH + i * ISO_PACKET_LENGTH)
+                    .take_while(|entry| entry + ISO_PACKET_LENGTH <= header_length)
+                    .map(|entry| (u32_at(entry) as usize,
+                                  u32_at(entry + 4) as usize))
+                    .collect()
+            },
+            _ => Vec::new(),
+        };
+        let mut irp_id = [0; 8];
+        irp_id.copy_from_slice(&bytes[2..10]);
+        Ok(Record {
+            irp_id: u64::from_le_bytes(irp_id),
+            status: u32_at(10),
+            from_device: bytes[16] & INFO_FROM_DEVICE != 0,
+            device: DeviceAddr(u16_at(19) as u8 & 0x7F),
+            endpoint: EndpointAddr(bytes[21]),
+            transfer,
+            stage,
+            iso_packets,
+            data: &bytes[header_length .. header_length + data_length],
+        })
+    }
+
+    fn stalled(&self) -> bool {
+        matches!(self.status,
+                 USBD_STATUS_STALL_PID | USBD_STATUS_ENDPOINT_HALTED)
+    }
+
+    fn successful(&self) -> bool {
+        self.status == USBD_STATUS_SUCCESS
+    }
+}
+
+/// Converts USBPcap records into sequences of USB packets.
+#[derive(Default)]
+pub struct UsbPcapConverter {
+    /// Setup data of control transfers awaiting completion, by IRP ID.
+    pending_setup: HashMap<u64, Vec<u8>>,
+    /// OUT data of transfers awaiting completion, by IRP ID.
+    pending_data: HashMap<u64, Vec<u8>>,
+    /// Next data toggle for each endpoint.
+    toggles: HashMap<(u8, u8), bool>,
+    /// Max packet sizes learned from descriptors.
+    max_packet_sizes: HashMap<(u8, u8), usize>,
+}
+
+impl UsbPcapConverter {
+    pub fn new() -> UsbPcapConverter {
+        UsbPcapConverter::default()
+    }
+
+    /// Generate the packets corresponding to one USBPcap record.
+    pub fn packets(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let record = Record::parse(bytes)?;
+        let mut packets = Vec::new();
+        match record.transfer {
+            TRANSFER_CONTROL =>
+                self.control(&record, &mut packets),
+            TRANSFER_BULK | TRANSFER_INTERRUPT =>
+                self.bulk_or_interrupt(&record, &mut packets),
+            TRANSFER_ISOCHRONOUS =>
+                self.isochronous(&record, &mut packets),
+            // IRP information and unknown records carry no traffic.
+            _ => {}
+        };
+        Ok(packets)
+    }
+
+    fn control(&mut self, record: &Record, packets: &mut Vec<Vec<u8>>) {
+        use Direction::*;
+        use PID::*;
+        if !record.from_device {
+            // Submission: save the setup and any OUT data until completion.
+            match record.stage {
+                Some(STAGE_SETUP) if record.data.len() >= 8 => {
+                    let (setup, rest) = record.data.split_at(8);
+                    self.pending_setup.insert(record.irp_id, setup.to_vec());
+                    if !rest.is_empty() {
+                        self.pending_data.insert(record.irp_id, rest.to_vec());
+                    }
+                },
+                Some(STAGE_DATA) => {
+                    self.pending_data
+                        .entry(record.irp_id)
+                        .or_default()
+                        .extend_from_slice(record.data);
+                },
+                _ => {}
+            }
+            return;
+        }
+        let setup = match self.pending_setup.remove(&record.irp_id) {
+            Some(setup) => setup,
+            // We never saw this request submitted, so cannot reconstruct it.
+            None => return,
+        };
+        let out_data = self.pending_data.remove(&record.irp_id);
+        let mut setup_packet = vec![DATA0 as u8];
+        setup_packet.extend_from_slice(&setup);
+        let fields = SetupFields::from_data_packet(&setup_packet);
+        let device = record.device;
+        let ep0 = EndpointAddr::from_parts(EndpointNum(0), Out);
+        let direction = fields.type_fields.direction();
+
+        // Setup stage.
+        packets.push(token(SETUP, device, EndpointNum(0)));
+        packets.push(data(DATA0, &setup));
+        packets.push(vec![ACK as u8]);
+
+        // Data stage.
+        let payload = match direction {
+            In => record.data.to_vec(),
+            Out => out_data.unwrap_or_default(),
+        };
+        if fields.length > 0 {
+            let token_pid = match direction { In => IN, Out => OUT };
+            if record.stalled() && payload.is_empty() {
+                // The request was stalled at the data stage.
+                packets.push(token(token_pid, device, EndpointNum(0)));
+                packets.push(vec![STALL as u8]);
+                return;
+            }
+            if !record.successful() && !record.stalled() {
+                // The request failed or was cancelled, so we cannot know
+                // what data was transferred, if any.
+                packets.push(token(token_pid, device, EndpointNum(0)));
+                return;
+            }
+            let max = self.max_packet_size(device, ep0);
+            let mut toggle = true;
+            for chunk in chunks(&payload, max) {
+                packets.push(token(token_pid, device, EndpointNum(0)));
+                packets.push(data(if toggle { DATA1 } else { DATA0 }, chunk));
+                packets.push(vec![ACK as u8]);
+                toggle = !toggle;
+            }
+        }
+
+        // Status stage.
+        let status_pid = match (direction, fields.length) {
+            (In, len) if len > 0 => OUT,
+            _ => IN,
+        };
+        packets.push(token(status_pid, device, EndpointNum(0)));
+        if record.stalled() {
+            packets.push(vec![STALL as u8]);
+        } else if record.successful() {
+            packets.push(data(DATA1, &[]));
+            packets.push(vec![ACK as u8]);
+            if matches!(direction, In) {
+                self.learn_descriptor(device, &fields, &payload);
+            }
+        }
+    }
+
+    fn bulk_or_interrupt(&mut self,
+                         record: &Record,
+                         packets: &mut Vec<Vec<u8>>)
+    {
+        use Direction::*;
+        use PID::*;
+        let device = record.device;
+        let endpoint = record.endpoint;
+        let direction = endpoint.direction();
+        let payload = match (record.from_device, direction) {
+            // OUT data is submitted before the request completes.
+            (false, Out) => {
+                self.pending_data.insert(record.irp_id, record.data.to_vec());
+                return;
+            },
+            (false, In) => return,
+            (true, In) => record.data.to_vec(),
+            (true, Out) => self.pending_data
+                .remove(&record.irp_id)
+                .unwrap_or_default(),
+        };
+        let token_pid = match direction { In => IN, Out => OUT };
+        if !record.successful() {
+            // A stall is the only failure we know the handshake for. For a
+            // failed or cancelled request, we cannot know what data was
+            // transferred, so leave the data toggle unchanged.
+            packets.push(token(token_pid, device, endpoint.number()));
+            if record.stalled() {
+                packets.push(vec![STALL as u8]);
+            }
+            return;
+        }
+        let max = self.max_packet_size(device, endpoint);
+        for chunk in chunks(&payload, max) {
+            let toggle = self.toggles.entry((device.0, endpoint.0)).or_default();
+            let data_pid = if *toggle { DATA1 } else { DATA0 };
+            *toggle = !*toggle;
+            packets.push(token(token_pid, device, endpoint.number()));
+            packets.push(data(data_pid, chunk));
+            packets.push(vec![ACK as u8]);
+        }
+    }
+
+    fn isochronous(&mut self, record: &Record, packets: &mut Vec<Vec<u8>>) {
+        use Direction::*;
+        use PID::*;
+        let direction = record.endpoint.direction();
+        // Isochronous data is present on completion for IN transfers, and on
+        // submission for OUT transfers. There are no handshakes.
+        match (record.from_device, direction) {
+            (true, In) | (false, Out) => {},
+            _ => return,
+        };
+        let token_pid = match direction { In => IN, Out => OUT };
+        for &(offset, length) in &record.iso_packets {
+            let end = offset.saturating_add(length);
+            if end > record.data.len() {
+                break;
+            }
+            packets.push(
+                token(token_pid, record.device, record.endpoint.number()));
+            packets.push(data(DATA0, &record.data[offset..end]));
+        }
+    }
+
+    fn max_packet_size(&self, device: DeviceAddr, endpoint: EndpointAddr)
+        -> usize
+    {
+        self.max_packet_sizes
+            .get(&(device.0, endpoint.0))
+            .copied()
+            .unwrap_or(DEFAULT_MAX_PACKET_SIZE)
+    }
+
+    /// Learn max packet sizes from descriptors read by the host.
+    fn learn_descriptor(&mut self,
+                        device: DeviceAddr,
+                        fields: &SetupFields,
+                        payload: &[u8])
+    {
+        let req_type = fields.type_fields.request_type();
+        let request = StandardRequest::from(fields.request);
+        let desc_type = DescriptorType::from((fields.value >> 8) as u8);
+        match (req_type, request, desc_type) {
+            (RequestType::Standard,
+             StandardRequest::GetDescriptor,
+             DescriptorType::Device) if payload.len() >= 8 => {
+                let ep0 = EndpointAddr::from_parts(
+                    EndpointNum(0), Direction::Out);
+                self.max_packet_sizes.insert(
+                    (device.0, ep0.0), payload[7] as usize);
+            },
+            (RequestType::Standard,
+             StandardRequest::GetDescriptor,
+             DescriptorType::Configuration)
+                if payload.len() >= size_of::<usb::ConfigDescriptor>() =>
+            {
+                if let Some(config) = Configuration::from_bytes(payload) {
+                    for iface in &config.interfaces {
+                        for ep_desc in &iface.endpoint_descriptors {
+                            let size = ep_desc.max_packet_size & 0x7FF;
+                            let ep_addr = ep_desc.endpoint_address;
+                            self.max_packet_sizes.insert(
+                                (device.0, ep_addr.0), size as usize);
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Split a payload into packet-sized chunks, with at least one chunk.
+fn chunks(payload: &[u8], max: usize) -> Vec<&[u8]> {
+    if payload.is_empty() || max == 0 {
+        vec![payload]
+    } else {
+        payload.chunks(max).collect()
+    }
+}
+
+/// Construct a token packet.
+fn token(pid: PID, device: DeviceAddr, endpoint: EndpointNum) -> Vec<u8> {
+    let fields = (device.0 as u16 & 0x7F) | (endpoint.0 as u16 & 0x0F) << 7;
+    let value = fields | (crc5(fields, 11) as u16) << 11;
+    let [low, high] = value.to_le_bytes();
+    vec![pid as u8, low, high]
+}
+
+/// Construct a data packet.
+fn data(pid: PID, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(payload.len() + 3);
+    packet.push(pid as u8);
+    packet.extend_from_slice(payload);
+    packet.extend_from_slice(&crc16(payload).to_le_bytes());
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PID::*;
+
+    const USBD_STATUS_CANCELED: u32 = 0xC0010000;
+
+    const DEVICE: DeviceAddr = DeviceAddr(5);
+
+    /// Construct a USBPcap record, with the given extra header bytes.
+    fn record(irp_id: u64,
+              status: u32,
+              from_device: bool,
+              endpoint: u8,
+              transfer: u8,
+              extra: &[u8],
+              data: &[u8])
+        -> Vec<u8>
+    {
+        let header_length = (HEADER_LENGTH + extra.len()) as u16;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&header_length.to_le_bytes());
+        bytes.extend_from_slice(&irp_id.to_le_bytes());
+        bytes.extend_from_slice(&status.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.push(from_device as u8);
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&(DEVICE.0 as u16).to_le_bytes());
+        bytes.push(endpoint);
+        bytes.push(transfer);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(extra);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn control(irp_id: u64, status: u32, from_device: bool, data: &[u8])
+        -> Vec<u8>
+    {
+        let stage = if from_device { STAGE_DATA } else { STAGE_SETUP };
+        record(irp_id, status, from_device, 0x80, TRANSFER_CONTROL,
+               &[stage], data)
+    }
+
+    fn bulk(irp_id: u64, status: u32, from_device: bool, endpoint: u8,
+            data: &[u8])
+        -> Vec<u8>
+    {
+        record(irp_id, status, from_device, endpoint, TRANSFER_BULK,
+               &[], data)
+    }
+
+    fn ep(number: u8) -> EndpointNum {
+        EndpointNum(number)
+    }
+
+    fn ack() -> Vec<u8> {
+        vec![ACK as u8]
+    }
+
+    fn stall() -> Vec<u8> {
+        vec![STALL as u8]
+    }
+
+    #[test]
+    fn test_control_in() {
+        let mut converter = UsbPcapConverter::new();
+        let get_device = [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00];
+        let descriptor = [
+            0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x08,
+            0x50, 0x1d, 0x18, 0x60, 0x00, 0x01, 0x01, 0x02,
+            0x00, 0x01,
+        ];
+        let submit = control(1, USBD_STATUS_SUCCESS, false, &get_device);
+        assert!(converter.packets(&submit).unwrap().is_empty());
+        let complete = control(1, USBD_STATUS_SUCCESS, true, &descriptor);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(SETUP, DEVICE, ep(0)), data(DATA0, &get_device), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA1, &descriptor), ack(),
+            token(OUT, DEVICE, ep(0)), data(DATA1, &[]), ack(),
+        ]);
+
+        // The max packet size of 8 in the descriptor is used from now on.
+        let submit = control(2, USBD_STATUS_SUCCESS, false, &get_device);
+        converter.packets(&submit).unwrap();
+        let complete = control(2, USBD_STATUS_SUCCESS, true, &descriptor);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(SETUP, DEVICE, ep(0)), data(DATA0, &get_device), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA1, &descriptor[0..8]), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA0, &descriptor[8..16]), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA1, &descriptor[16..]), ack(),
+            token(OUT, DEVICE, ep(0)), data(DATA1, &[]), ack(),
+        ]);
+    }
+
+    #[test]
+    fn test_control_out() {
+        let mut converter = UsbPcapConverter::new();
+        let set_report = [0x21, 0x09, 0x00, 0x02, 0x00, 0x00, 0x02, 0x00];
+        let report = [0x12, 0x34];
+        let submitted = [&set_report[..], &report[..]].concat();
+        let submit = control(1, USBD_STATUS_SUCCESS, false, &submitted);
+        assert!(converter.packets(&submit).unwrap().is_empty());
+        let complete = control(1, USBD_STATUS_SUCCESS, true, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(SETUP, DEVICE, ep(0)), data(DATA0, &set_report), ack(),
+            token(OUT, DEVICE, ep(0)), data(DATA1, &report), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA1, &[]), ack(),
+        ]);
+
+        // A request with no data stage.
+        let set_config = [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+        converter.packets(
+            &control(2, USBD_STATUS_SUCCESS, false, &set_config)).unwrap();
+        let complete = control(2, USBD_STATUS_SUCCESS, true, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(SETUP, DEVICE, ep(0)), data(DATA0, &set_config), ack(),
+            token(IN, DEVICE, ep(0)), data(DATA1, &[]), ack(),
+        ]);
+    }
+
+    #[test]
+    fn test_control_failed() {
+        let mut converter = UsbPcapConverter::new();
+        let get_config = [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0x09, 0x00];
+        let setup_stage = || vec![
+            token(SETUP, DEVICE, ep(0)), data(DATA0, &get_config), ack()];
+
+        // Stalled at the data stage.
+        converter.packets(
+            &control(1, USBD_STATUS_SUCCESS, false, &get_config)).unwrap();
+        let complete = control(1, USBD_STATUS_STALL_PID, true, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), [
+            setup_stage(),
+            vec![token(IN, DEVICE, ep(0)), stall()],
+        ].concat());
+
+        // Cancelled, with no data or handshake generated.
+        converter.packets(
+            &control(2, USBD_STATUS_SUCCESS, false, &get_config)).unwrap();
+        let complete = control(2, USBD_STATUS_CANCELED, true, &[0; 9]);
+        assert_eq!(converter.packets(&complete).unwrap(), [
+            setup_stage(),
+            vec![token(IN, DEVICE, ep(0))],
+        ].concat());
+
+        // Completion of a request never seen submitted.
+        let complete = control(3, USBD_STATUS_SUCCESS, true, &[0; 9]);
+        assert!(converter.packets(&complete).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bulk() {
+        let mut converter = UsbPcapConverter::new();
+        let payload: Vec<u8> = (0..100).collect();
+        assert!(converter.packets(
+            &bulk(1, USBD_STATUS_SUCCESS, false, 0x81, &[])).unwrap()
+            .is_empty());
+        let complete = bulk(1, USBD_STATUS_SUCCESS, true, 0x81, &payload);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(IN, DEVICE, ep(1)), data(DATA0, &payload[..64]), ack(),
+            token(IN, DEVICE, ep(1)), data(DATA1, &payload[64..]), ack(),
+        ]);
+
+        // OUT data is taken from the submission, with a separate toggle.
+        let submit = bulk(2, USBD_STATUS_SUCCESS, false, 0x02, &[1, 2, 3]);
+        assert!(converter.packets(&submit).unwrap().is_empty());
+        let complete = bulk(2, USBD_STATUS_SUCCESS, true, 0x02, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(OUT, DEVICE, ep(2)), data(DATA0, &[1, 2, 3]), ack(),
+        ]);
+
+        // A zero length transfer is a single empty packet.
+        let complete = bulk(3, USBD_STATUS_SUCCESS, true, 0x81, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(IN, DEVICE, ep(1)), data(DATA0, &[]), ack(),
+        ]);
+    }
+
+    #[test]
+    fn test_bulk_failed() {
+        let mut converter = UsbPcapConverter::new();
+
+        // A stall gives a STALL handshake, and no data.
+        let complete = bulk(1, USBD_STATUS_ENDPOINT_HALTED, true, 0x81, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(IN, DEVICE, ep(1)), stall(),
+        ]);
+
+        // A cancelled request gives only a token, even for OUT data that
+        // was submitted.
+        let submit = bulk(2, USBD_STATUS_SUCCESS, false, 0x02, &[1, 2, 3]);
+        converter.packets(&submit).unwrap();
+        let complete = bulk(2, USBD_STATUS_CANCELED, true, 0x02, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(OUT, DEVICE, ep(2)),
+        ]);
+
+        // Neither changed the data toggles.
+        let complete = bulk(3, USBD_STATUS_SUCCESS, true, 0x81, &[4]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(IN, DEVICE, ep(1)), data(DATA0, &[4]), ack(),
+        ]);
+        let submit = bulk(4, USBD_STATUS_SUCCESS, false, 0x02, &[5]);
+        converter.packets(&submit).unwrap();
+        let complete = bulk(4, USBD_STATUS_SUCCESS, true, 0x02, &[]);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(OUT, DEVICE, ep(2)), data(DATA0, &[5]), ack(),
+        ]);
+    }
+
+    #[test]
+    fn test_isochronous() {
+        let mut converter = UsbPcapConverter::new();
+        let payload = [1, 2, 3, 4, 5];
+        let mut extra = Vec::new();
+        // Start frame, packet count and error count.
+        for value in [0u32, 3, 0] {
+            extra.extend_from_slice(&value.to_le_bytes());
+        }
+        // Offset, length and status of each packet. The last does not fit
+        // in the data, so is ignored.
+        for (offset, length) in [(0u32, 2u32), (2, 3), (5, 1)] {
+            for value in [offset, length, USBD_STATUS_SUCCESS] {
+                extra.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let complete = record(1, USBD_STATUS_SUCCESS, true, 0x83,
+                              TRANSFER_ISOCHRONOUS, &extra, &payload);
+        assert_eq!(converter.packets(&complete).unwrap(), vec![
+            token(IN, DEVICE, ep(3)), data(DATA0, &payload[..2]),
+            token(IN, DEVICE, ep(3)), data(DATA0, &payload[2..]),
+        ]);
+
+        // IN data is only present on completion.
+        let submit = record(2, USBD_STATUS_SUCCESS, false, 0x83,
+                            TRANSFER_ISOCHRONOUS, &extra, &payload);
+        assert!(converter.packets(&submit).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_record() {
+        let mut converter = UsbPcapConverter::new();
+        assert!(converter.packets(&[0; 10]).is_err());
+        let mut bytes = bulk(1, USBD_STATUS_SUCCESS, true, 0x81, &[1, 2]);
+        bytes.pop();
+        assert!(converter.packets(&bytes).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+//! Formatting of counts and sizes for display.
+
 use num_format::{Locale, ToFormattedString};
 use humansize::{SizeFormatter, BINARY};
 
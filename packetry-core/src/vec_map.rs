@@ -62,6 +62,13 @@ impl<K, V> VecMap<K, V> where K: Key {
         }
     }
 
+    pub fn entries(&self) -> impl Iterator<Item=(K, &V)> {
+        self.vec
+            .iter()
+            .enumerate()
+            .filter_map(|(id, opt)| opt.as_ref().map(|value| (K::key(id), value)))
+    }
+
     pub fn set(&mut self, index: K, value: V) {
         let id = index.id();
         if id >= self.vec.len() {
@@ -69,6 +76,13 @@ impl<K, V> VecMap<K, V> where K: Key {
         }
         self.vec[id] = Some(value);
     }
+
+    pub fn remove(&mut self, index: K) -> Option<V> {
+        match self.vec.get_mut(index.id()) {
+            Some(opt) => opt.take(),
+            None => None
+        }
+    }
 }
 
 impl<K, V> Default for VecMap<K, V> where K: Key {
@@ -0,0 +1,47 @@
+/*
+ * Print the transfers in a capture as JSON Lines, optionally filtered.
+ *
+ * Usage: transfers CAPTURE [FILTER]
+ */
+
+#include <stdio.h>
+
+#include "packetry.h"
+
+int main(int argc, char *argv[])
+{
+	if (argc < 2 || argc > 3) {
+		fprintf(stderr, "Usage: %s CAPTURE [FILTER]\n", argv[0]);
+		return 2;
+	}
+
+	PacketryCapture *capture = packetry_open(argv[1]);
+	if (!capture) {
+		fprintf(stderr, "%s\n", packetry_last_error());
+		return 1;
+	}
+
+	PacketryTransfers *transfers =
+		packetry_transfers(capture, argc == 3 ? argv[2] : NULL);
+	if (!transfers) {
+		fprintf(stderr, "%s\n", packetry_last_error());
+		packetry_close(capture);
+		return 1;
+	}
+
+	char *transfer;
+	while ((transfer = packetry_next_transfer(transfers))) {
+		printf("%s\n", transfer);
+		packetry_free_string(transfer);
+	}
+
+	int status = 0;
+	if (packetry_last_error()) {
+		fprintf(stderr, "%s\n", packetry_last_error());
+		status = 1;
+	}
+
+	packetry_transfers_free(transfers);
+	packetry_close(capture);
+	return status;
+}
@@ -0,0 +1,107 @@
+#ifndef PACKETRY_H
+#define PACKETRY_H
+
+/* Generated by cbindgen from packetry-ffi/src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A loaded capture.
+typedef struct PacketryCapture PacketryCapture;
+
+// An iterator over the transfers in a capture.
+typedef struct PacketryTransfers PacketryTransfers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Load and decode a capture file.
+//
+// Returns NULL if the file cannot be loaded. The capture must be closed
+// with `packetry_close`.
+//
+// # Safety
+//
+// The path must be a NUL-terminated string.
+struct PacketryCapture *packetry_open(const char *path);
+
+// Close a capture opened with `packetry_open`.
+//
+// Iterators over the capture's transfers remain valid, and must still be
+// freed separately.
+//
+// # Safety
+//
+// The capture must be NULL, or have been returned by `packetry_open` and
+// not already closed.
+void packetry_close(struct PacketryCapture *capture);
+
+// Get the devices seen in a capture, with their descriptors, as a JSON
+// array.
+//
+// Returns NULL on failure. The string must be freed with
+// `packetry_free_string`.
+//
+// # Safety
+//
+// The capture must have been returned by `packetry_open` and not closed.
+char *packetry_devices(struct PacketryCapture *capture);
+
+// Start iterating over the transfers in a capture.
+//
+// If a display filter is given, e.g. "device == 5 && length > 0", only
+// the transfers matching it are included. Otherwise, the filter may be
+// NULL. Returns NULL if the filter is invalid. The iterator must be freed
+// with `packetry_transfers_free`.
+//
+// # Safety
+//
+// The capture must have been returned by `packetry_open` and not closed.
+// The filter must be NULL or a NUL-terminated string.
+struct PacketryTransfers *packetry_transfers(struct PacketryCapture *capture, const char *filter);
+
+// Get the next transfer from an iterator, as a JSON object.
+//
+// The object has the transfer's fields, including its summary, endpoint,
+// timestamp, error status and length, and its transactions and their
+// packets. Returns NULL at the end of the capture, or on failure, in which
+// case `packetry_last_error` returns the error. The string must be freed
+// with `packetry_free_string`.
+//
+// # Safety
+//
+// The iterator must have been returned by `packetry_transfers` and not
+// freed.
+char *packetry_next_transfer(struct PacketryTransfers *transfers);
+
+// Free an iterator returned by `packetry_transfers`.
+//
+// # Safety
+//
+// The iterator must be NULL, or have been returned by `packetry_transfers`
+// and not already freed.
+void packetry_transfers_free(struct PacketryTransfers *transfers);
+
+// Free a string returned by the library.
+//
+// # Safety
+//
+// The string must be NULL, or have been returned by the library and not
+// already freed.
+void packetry_free_string(char *string);
+
+// Get a description of the last error to occur on this thread.
+//
+// Returns NULL if the last call on this thread succeeded. The string is
+// valid until the next call to the library on this thread, and must not
+// be freed.
+const char *packetry_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PACKETRY_H */
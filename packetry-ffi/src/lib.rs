@@ -0,0 +1,390 @@
+//! C API for the Packetry decoder.
+//!
+//! This lets C and C++ tools link against the decoder, to load a capture
+//! and iterate over the transfers in it. The declarations are in
+//! `include/packetry.h`, which is generated from this file by cbindgen.
+//!
+//! Transfers, and the devices in a capture, are returned as JSON strings,
+//! in the same form as `packetry-cli decode --json` prints them. Strings
+//! returned by the library must be freed with `packetry_free_string`.
+//!
+//! Functions which can fail return NULL on failure, after which
+//! `packetry_last_error` describes the error.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr::null_mut;
+
+use anyhow::{Context, Error, bail};
+use serde_json::{json, Value};
+
+use packetry_core::capture::{CaptureReader, TrafficItem, TrafficItemId};
+use packetry_core::export::{json_devices, json_transfer};
+use packetry_core::filter::Filter;
+use packetry_core::loader;
+
+thread_local! {
+    /// The last error to occur on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> =
+        const { RefCell::new(None) };
+}
+
+/// A loaded capture.
+pub struct PacketryCapture {
+    reader: CaptureReader,
+}
+
+/// An iterator over the transfers in a capture.
+pub struct PacketryTransfers {
+    reader: CaptureReader,
+    next: u64,
+    filter: Option<Filter>,
+}
+
+/// Run an API function, catching any error or panic.
+///
+/// Any previous error is cleared first, so that a NULL result with no
+/// error set can be used to indicate the end of iteration.
+fn call<T>(function: impl FnOnce() -> Result<*mut T, Error>) -> *mut T {
+    set_error(None);
+    let error = match catch_unwind(AssertUnwindSafe(function)) {
+        Ok(Ok(result)) => return result,
+        Ok(Err(error)) => format!("{error:#}"),
+        Err(_) => String::from("Internal error in decoder"),
+    };
+    // The message cannot contain a NUL as it is built from Rust strings,
+    // other than from a path given to us, which came from a C string.
+    set_error(CString::new(error).ok());
+    null_mut()
+}
+
+fn set_error(error: Option<CString>) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Convert a JSON value to a string to be returned.
+fn json_string(value: &Value) -> Result<*mut c_char, Error> {
+    Ok(CString::new(value.to_string())?.into_raw())
+}
+
+/// Convert a string argument.
+///
+/// # Safety
+///
+/// The pointer must be NULL or point to a NUL-terminated string.
+unsafe fn string_arg<'a>(string: *const c_char, name: &str)
+    -> Result<Option<&'a str>, Error>
+{
+    if string.is_null() {
+        return Ok(None);
+    }
+    let string = unsafe { CStr::from_ptr(string) };
+    Ok(Some(string.to_str().with_context(|| format!(
+        "The {name} is not valid UTF-8"))?))
+}
+
+/// Load and decode a capture file.
+///
+/// Returns NULL if the file cannot be loaded. The capture must be closed
+/// with `packetry_close`.
+///
+/// # Safety
+///
+/// The path must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_open(path: *const c_char)
+    -> *mut PacketryCapture
+{
+    call(|| {
+        let path = match unsafe { string_arg(path, "path")? } {
+            Some(path) => PathBuf::from(path),
+            None => bail!("No path was given"),
+        };
+        let reader = loader::load(&[path])?;
+        Ok(Box::into_raw(Box::new(PacketryCapture { reader })))
+    })
+}
+
+/// Close a capture opened with `packetry_open`.
+///
+/// Iterators over the capture's transfers remain valid, and must still be
+/// freed separately.
+///
+/// # Safety
+///
+/// The capture must be NULL, or have been returned by `packetry_open` and
+/// not already closed.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_close(capture: *mut PacketryCapture) {
+    if !capture.is_null() {
+        drop(unsafe { Box::from_raw(capture) });
+    }
+}
+
+/// Get the devices seen in a capture, with their descriptors, as a JSON
+/// array.
+///
+/// Returns NULL on failure. The string must be freed with
+/// `packetry_free_string`.
+///
+/// # Safety
+///
+/// The capture must have been returned by `packetry_open` and not closed.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_devices(capture: *mut PacketryCapture)
+    -> *mut c_char
+{
+    call(|| {
+        let capture = match unsafe { capture.as_mut() } {
+            Some(capture) => capture,
+            None => bail!("No capture was given"),
+        };
+        json_string(&Value::Array(json_devices(&mut capture.reader)?))
+    })
+}
+
+/// Start iterating over the transfers in a capture.
+///
+/// If a display filter is given, e.g. "device == 5 && length > 0", only
+/// the transfers matching it are included. Otherwise, the filter may be
+/// NULL. Returns NULL if the filter is invalid. The iterator must be freed
+/// with `packetry_transfers_free`.
+///
+/// # Safety
+///
+/// The capture must have been returned by `packetry_open` and not closed.
+/// The filter must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_transfers(capture: *mut PacketryCapture,
+                                            filter: *const c_char)
+    -> *mut PacketryTransfers
+{
+    call(|| {
+        let capture = match unsafe { capture.as_ref() } {
+            Some(capture) => capture,
+            None => bail!("No capture was given"),
+        };
+        let filter = unsafe { string_arg(filter, "filter")? }
+            .map(Filter::parse)
+            .transpose()?;
+        Ok(Box::into_raw(Box::new(PacketryTransfers {
+            reader: capture.reader.clone(),
+            next: 0,
+            filter,
+        })))
+    })
+}
+
+/// Get the next transfer from an iterator, as a JSON object.
+///
+/// The object has the transfer's fields, including its summary, endpoint,
+/// timestamp, error status and length, and its transactions and their
+/// packets. Returns NULL at the end of the capture, or on failure, in which
+/// case `packetry_last_error` returns the error. The string must be freed
+/// with `packetry_free_string`.
+///
+/// # Safety
+///
+/// The iterator must have been returned by `packetry_transfers` and not
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_next_transfer(
+    transfers: *mut PacketryTransfers) -> *mut c_char
+{
+    call(|| {
+        let transfers = match unsafe { transfers.as_mut() } {
+            Some(transfers) => transfers,
+            None => bail!("No iterator was given"),
+        };
+        match transfers.next_transfer()? {
+            Some(transfer) => json_string(&transfer),
+            None => Ok(null_mut()),
+        }
+    })
+}
+
+/// Free an iterator returned by `packetry_transfers`.
+///
+/// # Safety
+///
+/// The iterator must be NULL, or have been returned by `packetry_transfers`
+/// and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_transfers_free(
+    transfers: *mut PacketryTransfers)
+{
+    if !transfers.is_null() {
+        drop(unsafe { Box::from_raw(transfers) });
+    }
+}
+
+/// Free a string returned by the library.
+///
+/// # Safety
+///
+/// The string must be NULL, or have been returned by the library and not
+/// already freed.
+#[no_mangle]
+pub unsafe extern "C" fn packetry_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+/// Get a description of the last error to occur on this thread.
+///
+/// Returns NULL if the last call on this thread succeeded. The string is
+/// valid until the next call to the library on this thread, and must not
+/// be freed.
+#[no_mangle]
+pub extern "C" fn packetry_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(error) => error.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+impl PacketryTransfers {
+    /// Find the next transfer which matches the filter.
+    fn next_transfer(&mut self) -> Result<Option<Value>, Error> {
+        while self.next < self.reader.item_index.len() {
+            let item_id = TrafficItemId::from(self.next);
+            self.next += 1;
+            let mut json = match json_transfer(&mut self.reader, item_id)? {
+                Some(json) => json,
+                None => continue,
+            };
+            let item = TrafficItem::Transfer(
+                self.reader.item_index.get(item_id)?);
+            if let Some(filter) = &self.filter {
+                if !filter.matches(&mut self.reader, &item)? {
+                    continue;
+                }
+            }
+            json["timestamp"] = json!(self.reader.item_timestamp(&item)?);
+            json["error"] = json!(self.reader.item_error(&item)?);
+            json["length"] = json!(self.reader.item_length(&item)?);
+            return Ok(Some(json));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{read_to_string, write};
+
+    const HEADER: &str = "include/packetry.h";
+
+    fn path(path: &str) -> CString {
+        CString::new(path).unwrap()
+    }
+
+    fn take_string(string: *mut c_char) -> String {
+        assert!(!string.is_null());
+        let result = unsafe { CStr::from_ptr(string) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        unsafe { packetry_free_string(string) };
+        result
+    }
+
+    fn last_error() -> Option<String> {
+        let error = packetry_last_error();
+        if error.is_null() {
+            None
+        } else {
+            let error = unsafe { CStr::from_ptr(error) };
+            Some(error.to_str().unwrap().to_string())
+        }
+    }
+
+    fn transfers(capture: *mut PacketryCapture, filter: Option<&str>)
+        -> Vec<Value>
+    {
+        let filter = filter.map(|filter| CString::new(filter).unwrap());
+        let filter_ptr = filter
+            .as_ref()
+            .map_or(std::ptr::null(), |filter| filter.as_ptr());
+        let iterator = unsafe { packetry_transfers(capture, filter_ptr) };
+        assert!(!iterator.is_null());
+        let mut transfers = Vec::new();
+        loop {
+            let transfer = unsafe { packetry_next_transfer(iterator) };
+            if transfer.is_null() {
+                assert_eq!(last_error(), None);
+                break;
+            }
+            let transfer = take_string(transfer);
+            transfers.push(serde_json::from_str(&transfer).unwrap());
+        }
+        unsafe { packetry_transfers_free(iterator) };
+        transfers
+    }
+
+    #[test]
+    fn test_transfers() {
+        let capture = unsafe {
+            packetry_open(path("../tests/mouse/capture.pcap").as_ptr())
+        };
+        assert!(!capture.is_null());
+        let all = transfers(capture, None);
+        assert_eq!(all.len(), 18);
+        assert!(all[1]["summary"]
+            .as_str()
+            .unwrap()
+            .starts_with("Getting device descriptor #0"));
+        assert_eq!(all[1]["error"], false);
+        let interrupt = transfers(capture, Some("endpoint.type == interrupt"));
+        assert_eq!(interrupt.len(), 7);
+        let devices: Value = serde_json::from_str(
+            &take_string(unsafe { packetry_devices(capture) })).unwrap();
+        assert!(devices
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|device| device["address"] == 4));
+        unsafe { packetry_close(capture) };
+    }
+
+    #[test]
+    fn test_errors() {
+        let capture = unsafe { packetry_open(path("no_such_file").as_ptr()) };
+        assert!(capture.is_null());
+        assert!(last_error().unwrap().contains("no_such_file"));
+        let capture = unsafe {
+            packetry_open(path("../tests/mouse/capture.pcap").as_ptr())
+        };
+        let filter = path("no_such_field == 1");
+        let iterator = unsafe { packetry_transfers(capture, filter.as_ptr()) };
+        assert!(iterator.is_null());
+        assert!(last_error().unwrap().contains("no_such_field"));
+        unsafe { packetry_close(capture) };
+        assert!(unsafe { packetry_open(std::ptr::null()) }.is_null());
+        assert!(last_error().is_some());
+    }
+
+    #[test]
+    fn test_header() {
+        let mut header = Vec::new();
+        cbindgen::generate(env!("CARGO_MANIFEST_DIR"))
+            .expect("Failed to generate header")
+            .write(&mut header);
+        let header = String::from_utf8(header).unwrap();
+        if env::var_os("PACKETRY_UPDATE_REFERENCE").is_some() {
+            write(HEADER, &header).unwrap();
+        } else {
+            let existing = read_to_string(HEADER).unwrap();
+            assert!(existing == header,
+                    "{HEADER} is out of date, regenerate it by running \
+                     this test with PACKETRY_UPDATE_REFERENCE set");
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Python bindings for the Packetry decoder.
+//!
+//! These let device validation suites load a capture and make assertions
+//! about the traffic in it:
+//!
+//! ```python
+//! import packetry
+//!
+//! capture = packetry.load("capture.pcap")
+//! for transfer in capture.transfers("endpoint.type == bulk"):
+//!     assert not transfer.error, transfer.summary
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyList};
+use serde_json::Value;
+
+use packetry_core::capture::{CaptureReader, TrafficItem, TrafficItemId};
+use packetry_core::export::{json_devices, json_transfer};
+use packetry_core::filter::Filter;
+use packetry_core::loader;
+
+create_exception!(packetry, CaptureError, PyException,
+                  "An error loading, decoding or filtering a capture.");
+
+fn capture_error(error: Error) -> PyErr {
+    CaptureError::new_err(format!("{error:#}"))
+}
+
+/// One capture file, or several to be merged.
+#[derive(FromPyObject)]
+enum Paths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+/// Load and decode capture files, merging them if there are several.
+#[pyfunction]
+fn load(paths: Paths) -> PyResult<Capture> {
+    let paths = match paths {
+        Paths::One(path) => vec![path],
+        Paths::Many(paths) => paths,
+    };
+    let reader = loader::load(&paths).map_err(capture_error)?;
+    Ok(Capture { reader })
+}
+
+/// A decoded capture.
+#[pyclass(module = "packetry")]
+struct Capture {
+    reader: CaptureReader,
+}
+
+#[pymethods]
+impl Capture {
+    /// Iterate over the transfers in the capture, in capture order.
+    ///
+    /// If a display filter is given, e.g. "device == 5 && length > 0", only
+    /// the transfers matching it are included.
+    #[pyo3(signature = (filter=None))]
+    fn transfers(slf: Py<Self>, filter: Option<&str>)
+        -> PyResult<TransferIterator>
+    {
+        let filter = filter
+            .map(Filter::parse)
+            .transpose()
+            .map_err(capture_error)?;
+        Ok(TransferIterator { capture: slf, next: 0, filter })
+    }
+
+    fn __iter__(slf: Py<Self>) -> PyResult<TransferIterator> {
+        Capture::transfers(slf, None)
+    }
+
+    /// The devices seen in the capture, with their descriptors, as dicts.
+    #[getter]
+    fn devices(&mut self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let devices = json_devices(&mut self.reader).map_err(capture_error)?;
+        devices.iter().map(|device| to_python(py, device)).collect()
+    }
+}
+
+/// Iterator over the transfers in a capture.
+#[pyclass(module = "packetry")]
+struct TransferIterator {
+    capture: Py<Capture>,
+    next: u64,
+    filter: Option<Filter>,
+}
+
+#[pymethods]
+impl TransferIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Transfer>> {
+        let mut capture = self.capture.borrow_mut(py);
+        let reader = &mut capture.reader;
+        while self.next < reader.item_index.len() {
+            let item_id = TrafficItemId::from(self.next);
+            self.next += 1;
+            let transfer = self.transfer(py, reader, item_id)
+                .map_err(capture_error)?;
+            if let Some(transfer) = transfer {
+                return transfer.map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl TransferIterator {
+    /// The transfer started by a top-level item, if it is the start of a
+    /// transfer and matches the filter.
+    fn transfer(&self,
+                py: Python<'_>,
+                reader: &mut CaptureReader,
+                item_id: TrafficItemId)
+        -> Result<Option<PyResult<Transfer>>, Error>
+    {
+        let json = match json_transfer(reader, item_id)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let item = TrafficItem::Transfer(reader.item_index.get(item_id)?);
+        if let Some(filter) = &self.filter {
+            if !filter.matches(reader, &item)? {
+                return Ok(None);
+            }
+        }
+        let string = |key: &str| json[key].as_str().unwrap_or("").to_string();
+        let number = |key: &str| json[key].as_u64().unwrap_or(0);
+        let transactions = match to_python(py, &json["transactions"]) {
+            Ok(transactions) => transactions,
+            Err(error) => return Ok(Some(Err(error))),
+        };
+        Ok(Some(Ok(Transfer {
+            id: number("id"),
+            device: number("device") as u8,
+            endpoint: number("endpoint") as u8,
+            direction: string("direction"),
+            endpoint_type: string("endpoint_type"),
+            summary: string("summary"),
+            timestamp: reader.item_timestamp(&item)?,
+            error: reader.item_error(&item)?,
+            data: PyBytes::new(py, &reader.item_data(&item)?).unbind(),
+            transactions,
+        })))
+    }
+}
+
+/// A transfer, with its transactions and their packets.
+#[pyclass(module = "packetry", frozen, get_all)]
+struct Transfer {
+    /// ID of the transfer within the capture.
+    id: u64,
+    /// Address of the device the transfer was to or from.
+    device: u8,
+    /// Number of the endpoint the transfer was to or from.
+    endpoint: u8,
+    /// Direction of the endpoint, "IN" or "OUT".
+    direction: String,
+    /// Type of the endpoint, e.g. "Bulk".
+    endpoint_type: String,
+    /// Description of the transfer, as shown in Packetry.
+    summary: String,
+    /// Time of the transfer's first packet, in nanoseconds.
+    timestamp: u64,
+    /// Whether the transfer shows an error.
+    error: bool,
+    /// Data payload carried by the transfer.
+    data: Py<PyBytes>,
+    /// The transfer's transactions, each a dict with its packets and
+    /// their fields.
+    transactions: PyObject,
+}
+
+#[pymethods]
+impl Transfer {
+    fn __repr__(&self) -> String {
+        format!("<Transfer {}: {}>", self.id, self.summary)
+    }
+}
+
+/// Convert a JSON value to the equivalent Python object.
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(value) =>
+            PyBool::new(py, *value).to_owned().into_any().unbind(),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                value.into_pyobject(py)?.into_any().unbind()
+            } else if let Some(value) = number.as_i64() {
+                value.into_pyobject(py)?.into_any().unbind()
+            } else {
+                number.as_f64().into_pyobject(py)?.into_any().unbind()
+            }
+        },
+        Value::String(string) => string.into_pyobject(py)?.into_any().unbind(),
+        Value::Array(values) => {
+            let list = PyList::empty(py);
+            for value in values {
+                list.append(to_python(py, value)?)?;
+            }
+            list.into_any().unbind()
+        },
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+    })
+}
+
+#[pymodule]
+fn packetry(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(load, module)?)?;
+    module.add_class::<Capture>()?;
+    module.add_class::<Transfer>()?;
+    module.add("CaptureError", module.py().get_type::<CaptureError>())?;
+    Ok(())
+}
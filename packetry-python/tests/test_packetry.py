@@ -0,0 +1,49 @@
+from pathlib import Path
+
+import pytest
+
+import packetry
+
+TESTS = Path(__file__).parents[2] / "tests"
+MOUSE = TESTS / "mouse" / "capture.pcap"
+
+
+@pytest.fixture
+def capture():
+    return packetry.load(MOUSE)
+
+
+def test_transfers(capture):
+    transfers = list(capture.transfers())
+    assert len(transfers) == 18
+    assert len(list(capture)) == len(transfers)
+    setup = transfers[1]
+    assert setup.device == 0
+    assert setup.endpoint == 0
+    assert setup.endpoint_type == "Control"
+    assert setup.summary.startswith("Getting device descriptor #0")
+    assert not setup.error
+    assert setup.data[:2] == bytes([0x12, 0x01])
+    packets = setup.transactions[0]["packets"]
+    assert packets[0]["pid"] == "SETUP"
+
+
+def test_filter(capture):
+    transfers = list(
+        capture.transfers("endpoint.number == 1 && endpoint.direction == in"))
+    assert len(transfers) == 7
+    for transfer in transfers:
+        assert transfer.endpoint_type == "Interrupt"
+        assert transfer.direction == "IN"
+
+
+def test_devices(capture):
+    addresses = [device["address"] for device in capture.devices]
+    assert 4 in addresses
+
+
+def test_errors(capture):
+    with pytest.raises(packetry.CaptureError):
+        capture.transfers("no_such_field == 1")
+    with pytest.raises(packetry.CaptureError):
+        packetry.load(Path(__file__))
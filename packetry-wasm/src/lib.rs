@@ -0,0 +1,187 @@
+//! The Packetry decoder built for WebAssembly, with a JavaScript API.
+//!
+//! This lets a web page load and browse a capture file without anything
+//! being installed:
+//!
+//! ```js
+//! import init, { Capture } from "./pkg/packetry_wasm.js";
+//!
+//! await init();
+//! const data = new Uint8Array(await file.arrayBuffer());
+//! const capture = Capture.load(file.name, data);
+//! const { transfers, next } = capture.transfers(0, 100, "length > 0");
+//! ```
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use anyhow::Error;
+use js_sys::JSON;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+use packetry_core::capture::{CaptureReader, TrafficItem, TrafficItemId};
+use packetry_core::export::{json_devices, json_transfer};
+use packetry_core::filter::Filter;
+use packetry_core::{loader, pcap};
+
+fn js_error(error: Error) -> JsError {
+    JsError::new(&format!("{error:#}"))
+}
+
+/// Convert a JSON value to the equivalent JavaScript object.
+fn to_js(value: &Value) -> Result<JsValue, JsError> {
+    JSON::parse(&value.to_string())
+        .map_err(|_| JsError::new("Failed to convert result"))
+}
+
+/// A decoded capture.
+#[wasm_bindgen]
+pub struct Capture {
+    reader: CaptureReader,
+}
+
+#[wasm_bindgen]
+impl Capture {
+    /// Load and decode a capture file from its contents.
+    ///
+    /// The name is only used in error messages.
+    pub fn load(name: &str, data: Vec<u8>) -> Result<Capture, JsError> {
+        load_bytes(name, data).map_err(js_error)
+    }
+
+    /// Get up to `count` transfers, starting from the item at `start`.
+    ///
+    /// If a display filter is given, e.g. "device == 5 && length > 0", only
+    /// the transfers matching it are included. Returns an object holding
+    /// the array of `transfers`, and the position to continue from in
+    /// `next`. Fewer transfers are returned only at the end of the capture.
+    pub fn transfers(&mut self,
+                     start: u32,
+                     count: u32,
+                     filter: Option<String>)
+        -> Result<JsValue, JsError>
+    {
+        let filter = filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(js_error)?;
+        let page = self.page(start as u64, count as usize, filter.as_ref())
+            .map_err(js_error)?;
+        to_js(&page)
+    }
+
+    /// Get the devices seen in the capture, with their descriptors.
+    pub fn devices(&mut self) -> Result<JsValue, JsError> {
+        let devices = json_devices(&mut self.reader).map_err(js_error)?;
+        to_js(&Value::Array(devices))
+    }
+
+    /// Number of top-level items in the capture.
+    #[wasm_bindgen(getter, js_name = itemCount)]
+    pub fn item_count(&self) -> u32 {
+        self.reader.item_index.len() as u32
+    }
+}
+
+impl Capture {
+    /// Collect a page of transfers, as returned by `transfers`.
+    fn page(&mut self, start: u64, count: usize, filter: Option<&Filter>)
+        -> Result<Value, Error>
+    {
+        let reader = &mut self.reader;
+        let mut transfers = Vec::with_capacity(count);
+        let mut next = start;
+        while transfers.len() < count && next < reader.item_index.len() {
+            let item_id = TrafficItemId::from(next);
+            next += 1;
+            if let Some(transfer) = transfer(reader, item_id, filter)? {
+                transfers.push(transfer);
+            }
+        }
+        Ok(json!({
+            "transfers": transfers,
+            "next": next,
+        }))
+    }
+}
+
+/// Load a capture from the contents of a file.
+fn load_bytes(name: &str, data: Vec<u8>) -> Result<Capture, Error> {
+    let input = pcap::decompress(Cursor::new(data))?;
+    let reader = loader::load_readers(&[PathBuf::from(name)], vec![input])?;
+    Ok(Capture { reader })
+}
+
+/// The transfer started by a top-level item, if it is the start of a
+/// transfer and matches the filter.
+fn transfer(reader: &mut CaptureReader,
+            item_id: TrafficItemId,
+            filter: Option<&Filter>)
+    -> Result<Option<Value>, Error>
+{
+    let mut json = match json_transfer(reader, item_id)? {
+        Some(json) => json,
+        None => return Ok(None),
+    };
+    let item = TrafficItem::Transfer(reader.item_index.get(item_id)?);
+    if let Some(filter) = filter {
+        if !filter.matches(reader, &item)? {
+            return Ok(None);
+        }
+    }
+    json["timestamp"] = json!(reader.item_timestamp(&item)?);
+    json["error"] = json!(reader.item_error(&item)?);
+    json["length"] = json!(reader.item_length(&item)?);
+    Ok(Some(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_mouse() -> Capture {
+        let data = std::fs::read("../tests/mouse/capture.pcap").unwrap();
+        load_bytes("capture.pcap", data).unwrap()
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut capture = load_mouse();
+        let items = capture.reader.item_index.len();
+        let mut summaries = Vec::new();
+        let mut next = 0;
+        loop {
+            let page = capture.page(next, 5, None).unwrap();
+            let transfers = page["transfers"].as_array().unwrap();
+            summaries.extend(transfers
+                .iter()
+                .map(|transfer| transfer["summary"].to_string()));
+            next = page["next"].as_u64().unwrap();
+            if transfers.len() < 5 {
+                break;
+            }
+        }
+        assert_eq!(next, items);
+        assert_eq!(summaries.len(), 18);
+        assert!(summaries[1].contains("Getting device descriptor #0"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut capture = load_mouse();
+        let filter = Filter::parse("endpoint.type == interrupt").unwrap();
+        let page = capture.page(0, 100, Some(&filter)).unwrap();
+        let transfers = page["transfers"].as_array().unwrap();
+        assert_eq!(transfers.len(), 7);
+        for transfer in transfers {
+            assert_eq!(transfer["endpoint_type"], "Interrupt");
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(load_bytes("bad.pcap", b"not a capture".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Browser-based capture viewer, using the decoder built with wasm-pack.
+
+import init, { Capture } from "./pkg/packetry_wasm.js";
+
+// Number of transfers shown at a time.
+const PAGE_SIZE = 200;
+
+const status = document.getElementById("status");
+const viewer = document.getElementById("viewer");
+const traffic = document.getElementById("traffic");
+const devices = document.getElementById("devices");
+const filter = document.getElementById("filter");
+const more = document.getElementById("more");
+
+let capture = null;
+let filterText = null;
+let next = 0;
+
+function showStatus(text, error = false) {
+  status.textContent = text;
+  status.classList.toggle("error", error);
+}
+
+function element(tag, text, className) {
+  const node = document.createElement(tag);
+  if (text !== undefined) node.textContent = text;
+  if (className) node.className = className;
+  return node;
+}
+
+// A collapsible node whose children are only created when first opened.
+function expander(label, className, children) {
+  const details = element("details");
+  details.append(element("summary", label, className));
+  details.addEventListener("toggle", () => {
+    if (details.open && details.children.length == 1) {
+      children(details);
+    }
+  });
+  return details;
+}
+
+function showDevice(parent, node) {
+  if (node.children.length == 0) {
+    parent.append(element("div", node.summary, "packet"));
+  } else {
+    parent.append(expander(node.summary, null, details =>
+      node.children.forEach(child => showDevice(details, child))));
+  }
+}
+
+function showTransfer(transfer) {
+  return expander(transfer.summary, transfer.error ? "error" : null, details => {
+    for (const transaction of transfer.transactions) {
+      details.append(expander(transaction.summary, null, details => {
+        for (const packet of transaction.packets) {
+          details.append(element("div",
+            `${packet.pid} packet: ${packet.bytes}`, "packet"));
+        }
+      }));
+    }
+  });
+}
+
+function showPage() {
+  try {
+    const page = capture.transfers(next, PAGE_SIZE, filterText);
+    page.transfers.forEach(transfer => traffic.append(showTransfer(transfer)));
+    next = page.next;
+    more.hidden = page.transfers.length < PAGE_SIZE;
+  } catch (error) {
+    showStatus(error.message, true);
+  }
+}
+
+function showTraffic() {
+  traffic.replaceChildren();
+  next = 0;
+  showPage();
+}
+
+async function open(file) {
+  showStatus(`Loading ${file.name}...`);
+  viewer.hidden = true;
+  if (capture) capture.free();
+  capture = null;
+  try {
+    const data = new Uint8Array(await file.arrayBuffer());
+    capture = Capture.load(file.name, data);
+  } catch (error) {
+    showStatus(error.message, true);
+    return;
+  }
+  showStatus(`${file.name}: ${capture.itemCount} items`);
+  devices.replaceChildren();
+  capture.devices().forEach(device => showDevice(devices, device));
+  filterText = null;
+  filter.value = "";
+  showTraffic();
+  viewer.hidden = false;
+}
+
+function applyFilter() {
+  filterText = filter.value.trim() || null;
+  showStatus("");
+  showTraffic();
+}
+
+await init();
+
+const drop = document.getElementById("drop");
+drop.addEventListener("dragover", event => {
+  event.preventDefault();
+  drop.classList.add("over");
+});
+drop.addEventListener("dragleave", () => drop.classList.remove("over"));
+drop.addEventListener("drop", event => {
+  event.preventDefault();
+  drop.classList.remove("over");
+  if (event.dataTransfer.files.length > 0) {
+    open(event.dataTransfer.files[0]);
+  }
+});
+document.getElementById("file").addEventListener("change", event => {
+  if (event.target.files.length > 0) {
+    open(event.target.files[0]);
+  }
+});
+document.getElementById("apply").addEventListener("click", applyFilter);
+filter.addEventListener("keydown", event => {
+  if (event.key == "Enter") applyFilter();
+});
+more.addEventListener("click", showPage);
@@ -0,0 +1,206 @@
+//! Counts of the traffic on each endpoint, kept up to date as a capture
+//! grows, and the endpoints whose traffic is hidden.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    EndpointTransactionId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+
+/// The traffic seen on one endpoint.
+#[derive(Clone, Debug)]
+pub struct EndpointActivity {
+    pub endpoint_id: EndpointId,
+    /// Description of the endpoint, e.g. `5.1 IN`.
+    pub name: String,
+    /// Filter expression selecting the traffic on this endpoint.
+    pub condition: String,
+    pub transactions: u64,
+    pub packets: u64,
+    /// Whether this endpoint's traffic is hidden in the traffic view.
+    pub hidden: bool,
+    /// Packets in all but the last transaction counted, which may still
+    /// be growing.
+    settled_packets: u64,
+}
+
+/// The traffic seen on each endpoint of a capture.
+#[derive(Clone, Debug, Default)]
+pub struct Activity {
+    /// Endpoints in the order they were first seen.
+    pub endpoints: Vec<EndpointActivity>,
+    /// Whether SOF packets are hidden, including before any are seen.
+    pub hide_framing: bool,
+    /// Whether groups of unanswered polls are hidden, on every endpoint.
+    pub hide_polling: bool,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Activity::default()
+    }
+
+    /// Count the traffic added to a capture since the last update.
+    ///
+    /// Returns whether any endpoints or counts changed.
+    pub fn update(&mut self, capture: &mut CaptureReader)
+        -> Result<bool, Error>
+    {
+        let mut changed = false;
+        for index in self.endpoints.len()..capture.endpoints.len() as usize {
+            let endpoint_id = EndpointId::from(index as u64);
+            // An endpoint is listed before its traffic can be read.
+            if capture.endpoint_traffic(endpoint_id).is_err() {
+                break;
+            }
+            let (name, condition) = if endpoint_id == FRAMING_EP_ID {
+                ("SOF".to_string(), "transfer.type == framing".to_string())
+            } else if endpoint_id == INVALID_EP_ID {
+                ("Invalid".to_string(), "transfer.type == invalid".to_string())
+            } else {
+                let endpoint = capture.endpoints.get(endpoint_id)?;
+                (endpoint.to_string(),
+                 format!("device == {} && endpoint == 0x{:02X}",
+                         endpoint.device_address(),
+                         endpoint.address().0))
+            };
+            self.endpoints.push(EndpointActivity {
+                endpoint_id,
+                name,
+                condition,
+                transactions: 0,
+                packets: 0,
+                hidden: endpoint_id == FRAMING_EP_ID && self.hide_framing,
+                settled_packets: 0,
+            });
+            changed = true;
+        }
+        for endpoint in self.endpoints.iter_mut() {
+            changed |= count(capture, endpoint)?;
+        }
+        Ok(changed)
+    }
+
+    /// A filter expression selecting the traffic matched by a display
+    /// filter, excluding that of the hidden endpoints, and any polling.
+    pub fn filter_text(&self, text: &str) -> String {
+        let text = text.trim();
+        let mut clauses = Vec::new();
+        if !text.is_empty() {
+            clauses.push(format!("({text})"));
+        }
+        for endpoint in self.endpoints.iter().filter(|ep| ep.hidden) {
+            clauses.push(format!("!({})", endpoint.condition));
+        }
+        let framing_listed = self.endpoints
+            .iter()
+            .any(|ep| ep.endpoint_id == FRAMING_EP_ID);
+        if self.hide_framing && !framing_listed {
+            clauses.push(String::from("!(transfer.type == framing)"));
+        }
+        if self.hide_polling {
+            clauses.push(String::from("!(polling == yes)"));
+        }
+        clauses.join(" && ")
+    }
+}
+
+/// Update the counts for one endpoint, returning whether they changed.
+fn count(capture: &mut CaptureReader, endpoint: &mut EndpointActivity)
+    -> Result<bool, Error>
+{
+    let endpoint_id = endpoint.endpoint_id;
+    let transaction_count =
+        capture.endpoint_traffic(endpoint_id)?.transaction_ids.len();
+    if transaction_count == 0 {
+        return Ok(false);
+    }
+    let mut packets = |index: u64| -> Result<u64, Error> {
+        let transaction_id = capture
+            .endpoint_traffic(endpoint_id)?
+            .transaction_ids
+            .get(EndpointTransactionId::from(index))?;
+        let range = capture.transaction_index.target_range(
+            transaction_id, capture.packet_index.len())?;
+        Ok(range.end.value - range.start.value)
+    };
+    let settled = endpoint.transactions.saturating_sub(1);
+    for index in settled..(transaction_count - 1) {
+        endpoint.settled_packets += packets(index)?;
+    }
+    let total = endpoint.settled_packets + packets(transaction_count - 1)?;
+    let changed = endpoint.transactions != transaction_count ||
+        endpoint.packets != total;
+    endpoint.transactions = transaction_count;
+    endpoint.packets = total;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::filter::Filter;
+    use crate::loader::load;
+
+    #[test]
+    fn test_activity() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut activity = Activity::new();
+
+        // SOFs can be hidden before any are seen.
+        activity.hide_framing = true;
+        let hidden_text = activity.filter_text("device == 11");
+        activity.hide_framing = false;
+        assert!(activity.update(&mut capture).unwrap());
+        assert!(!activity.update(&mut capture).unwrap());
+        assert_eq!(activity.endpoints.len(), capture.endpoints.len() as usize);
+        assert_eq!(activity.endpoints[FRAMING_EP_ID.value as usize].name,
+                   "SOF");
+        assert!(activity.endpoints.iter().any(|ep| ep.name == "11.0 OUT"));
+
+        // No transaction or packet is counted twice.
+        let transactions: u64 = activity.endpoints
+            .iter()
+            .map(|ep| ep.transactions)
+            .sum();
+        let packets: u64 = activity.endpoints
+            .iter()
+            .map(|ep| ep.packets)
+            .sum();
+        assert!(transactions > 0);
+        assert!(transactions <= capture.transaction_index.len());
+        assert!(packets >= transactions);
+        assert!(packets <= capture.packet_index.len());
+
+        // Each condition is a valid filter, and can be combined with others.
+        for endpoint in &activity.endpoints {
+            Filter::parse(&endpoint.condition).unwrap();
+        }
+        assert_eq!(activity.filter_text(" length > 8 "), "(length > 8)");
+        activity.endpoints[FRAMING_EP_ID.value as usize].hidden = true;
+        assert_eq!(activity.filter_text(""), "!(transfer.type == framing)");
+        let text = activity.filter_text("device == 11");
+        assert_eq!(text, "(device == 11) && !(transfer.type == framing)");
+        assert_eq!(text, hidden_text);
+        Filter::parse(&text).unwrap();
+
+        let mut activity = Activity {
+            hide_framing: true,
+            ..Activity::new()
+        };
+        activity.update(&mut capture).unwrap();
+        assert!(activity.endpoints[FRAMING_EP_ID.value as usize].hidden);
+        assert_eq!(activity.filter_text(""), "!(transfer.type == framing)");
+        activity.hide_polling = true;
+        let text = activity.filter_text("");
+        assert_eq!(text,
+                   "!(transfer.type == framing) && !(polling == yes)");
+        Filter::parse(&text).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Rules raising an alert when traffic matching a display filter is
+//! captured, for catching rare failures during long captures.
+
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{enabled_by_default, JsonFile};
+use crate::filter::Filter;
+
+/// Shortest time between alerts raised by the same rule, so that a burst
+/// of matching traffic raises one alert rather than many.
+pub const ALERT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What is done when traffic matches a rule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertAction {
+    Sound,
+    Notify,
+    Stop,
+}
+
+impl AlertAction {
+    pub const ALL: [AlertAction; 3] = [
+        AlertAction::Sound,
+        AlertAction::Notify,
+        AlertAction::Stop,
+    ];
+
+    /// Description of this action, as offered in the alerts editor.
+    pub fn label(&self) -> &'static str {
+        use AlertAction::*;
+        match self {
+            Sound => "Play a sound",
+            Notify => "Show a notification",
+            Stop => "Stop the capture",
+        }
+    }
+
+}
+
+/// A display filter, and what to do when captured traffic matches it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "SavedAlertRule", into = "SavedAlertRule")]
+pub struct AlertRule {
+    pub filter_text: String,
+    pub action: AlertAction,
+    pub enabled: bool,
+    filter: Filter,
+}
+
+impl AlertRule {
+    pub fn new(filter_text: &str, action: AlertAction)
+        -> Result<AlertRule, Error>
+    {
+        let filter_text = filter_text.trim();
+        let filter = Filter::parse(filter_text)
+            .with_context(|| format!("Invalid filter '{filter_text}'"))?;
+        Ok(AlertRule {
+            filter_text: filter_text.to_string(),
+            action,
+            enabled: true,
+            filter,
+        })
+    }
+
+    /// The filter which captured traffic is checked against.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Description of the rule, as listed in the alerts editor.
+    pub fn description(&self) -> String {
+        format!("{}: {}", self.action.label(), self.filter_text)
+    }
+}
+
+/// An alert rule as saved, with its filter as entered.
+#[derive(Serialize, Deserialize)]
+struct SavedAlertRule {
+    filter: String,
+    action: AlertAction,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+}
+
+impl TryFrom<SavedAlertRule> for AlertRule {
+    type Error = Error;
+
+    fn try_from(saved: SavedAlertRule) -> Result<AlertRule, Error> {
+        let mut rule = AlertRule::new(&saved.filter, saved.action)?;
+        rule.enabled = saved.enabled;
+        Ok(rule)
+    }
+}
+
+impl From<AlertRule> for SavedAlertRule {
+    fn from(rule: AlertRule) -> SavedAlertRule {
+        SavedAlertRule {
+            filter: rule.filter_text,
+            action: rule.action,
+            enabled: rule.enabled,
+        }
+    }
+}
+
+/// A list of alert rules.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AlertRules {
+    rules: Vec<AlertRule>,
+}
+
+impl AlertRules {
+    pub fn new() -> Self {
+        AlertRules::default()
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&AlertRule> {
+        self.rules.iter()
+    }
+
+    /// The rules which are checked during a capture.
+    pub fn enabled(&self) -> impl Iterator<Item=&AlertRule> {
+        self.rules.iter().filter(|rule| rule.enabled)
+    }
+
+    pub fn push(&mut self, rule: AlertRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.rules.len() {
+            self.rules.remove(index);
+        }
+    }
+
+    /// Enable or disable a rule.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(rule) = self.rules.get_mut(index) {
+            rule.enabled = enabled;
+        }
+    }
+}
+
+impl JsonFile for AlertRules {
+    const CONTENTS: &'static str = "alert rules";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_rules() {
+        let mut rules = AlertRules::new();
+        for (filter, action) in [
+            (" error == yes ", AlertAction::Stop),
+            ("transfer.type == bulk", AlertAction::Notify),
+            ("device == 5", AlertAction::Sound),
+        ] {
+            rules.push(AlertRule::new(filter, action).unwrap());
+        }
+        rules.set_enabled(1, false);
+        assert_eq!(rules.iter().next().unwrap().description(),
+                   "Stop the capture: error == yes");
+        let enabled: Vec<&str> = rules
+            .enabled()
+            .map(|rule| rule.filter_text.as_str())
+            .collect();
+        assert_eq!(enabled, ["error == yes", "device == 5"]);
+
+        let loaded = AlertRules::from_json(&rules.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.len(), 3);
+        for (rule, loaded) in rules.iter().zip(loaded.iter()) {
+            assert_eq!(loaded.filter_text, rule.filter_text);
+            assert_eq!(loaded.action, rule.action);
+            assert_eq!(loaded.enabled, rule.enabled);
+            assert_eq!(loaded.filter(), rule.filter());
+        }
+        rules.remove(0);
+        rules.remove(5);
+        assert_eq!(rules.len(), 2);
+
+        assert!(AlertRule::new("device ==", AlertAction::Sound).is_err());
+        assert!(AlertRules::from_json(
+            "{\"rules\": [{\"filter\": \"error == yes\", \"action\": \"x\"}]}")
+            .is_err());
+        assert!(AlertRules::from_json(
+            "{\"rules\": [{\"filter\": \"device == 5\"}]}")
+            .is_err());
+    }
+}
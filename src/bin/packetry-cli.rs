@@ -0,0 +1,9 @@
+use packetry::cli::run;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("Error: {error:#}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,1119 @@
+//! Command line interface for use without the GUI.
+
+use std::fs::File;
+use std::io::{stdin, BufRead, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error, bail};
+
+use crate::backend::cynthion::{
+    CynthionDevice,
+    CynthionStop,
+    CynthionTrigger,
+    CynthionUsability::*,
+    Speed,
+};
+use crate::backend::emulator::{Playback, Scenario};
+use crate::backend::filter::{CaptureFilter, FilteredPackets};
+use crate::backend::host::fetch_bus_descriptors;
+use crate::capture::{
+    create_capture, DeviceId, ItemSource, PacketId, TrafficItem};
+use crate::clock::ClockModel;
+use crate::codegen::{descriptor_source, Language};
+use crate::decoder::Decoder;
+use crate::export::{self, EndpointData};
+use crate::filter::Filter;
+use crate::integrity::{hex, verify_capture};
+use crate::loader::{
+    estimate_clocks,
+    load,
+    load_with_clocks,
+    load_with_overrides,
+};
+use crate::markers::{listen, read_markers, Marker};
+use crate::overrides::{EndpointOverride, Overrides};
+use crate::pcap::{device_path, PacketFileWriter};
+use crate::report::{
+    compliance_report,
+    enumeration_report,
+    throughput_report,
+    ReportFormat,
+};
+use crate::schedule::Schedule;
+use crate::throughput::{check_target, ThroughputTarget};
+use crate::trigger::Trigger;
+use crate::usb::{DeviceAddr, Direction, EndpointNum};
+use crate::util::{fmt_count, fmt_size};
+
+const USAGE: &str = "\
+Usage: packetry-cli capture [OPTIONS] OUTPUT
+       packetry-cli decode [OPTIONS] FILE...
+       packetry-cli extract [OPTIONS] FILE OUTPUT
+       packetry-cli split FILE OUTPUT
+       packetry-cli report [OPTIONS] FILE...
+       packetry-cli dump [OPTIONS] FILE...
+       packetry-cli descriptors [OPTIONS] FILE...
+       packetry-cli verify FILE
+
+capture: Capture USB traffic from a Cynthion device, without the GUI.
+
+The capture is written to OUTPUT, in pcapng format if its name ends in
+.pcapng, or in pcap format otherwise. Names ending in .zst are compressed.
+
+Options:
+  --serial SERIAL     Use the analyzer with this serial number
+  --speed SPEED       Capture at this speed: auto, high, full or low
+                      (default: high)
+  --start-at TIME     Wait until this time of day (UTC), given as HH:MM
+                      or HH:MM:SS, before starting the capture
+  --duration SECONDS  Stop after this many seconds
+  --packets COUNT     Stop after capturing this many packets
+  --bytes COUNT       Stop after capturing this many bytes of packets
+  --device LIST       Keep only packets to or from these device addresses
+  --endpoint LIST     Keep only packets to or from these endpoint numbers
+  --pid LIST          Keep only packets with these PIDs, e.g. SETUP,DATA0
+                      Values in each LIST are separated by commas, and
+                      values prefixed with ! are dropped instead
+  --markers           Read marker labels from standard input, one per
+                      line, and mark the time each was read
+  --marker-port PORT  Accept connections on this TCP port of localhost,
+                      and read marker labels from each as for --markers
+                      Markers are saved as comments on the next packet,
+                      so OUTPUT must be a pcapng file to use them
+  --trigger FILTER    Pulse the analyzer's trigger output when traffic
+                      matching a display filter is captured, e.g. to
+                      start an oscilloscope on \"summary contains STALL\"
+  --list              List the available analyzers, then exit
+  --scenario FILE     Capture from an emulated analyzer, replaying the
+                      traffic described in a scenario file
+  --replay-speed X    Replay the scenario's delays X times faster, from
+                      0.1 to 100 (default: 1)
+  --step              Replay the scenario one packet at a time, sending
+                      the next each time Enter is pressed
+
+decode: Print the decoded traffic in one or more capture files.
+
+The transfers and other top-level items are printed one per line, grouped
+as in the GUI's hierarchical view. If several files are given, they are
+merged into a single timeline.
+
+Options:
+  --depth DEPTH       Also print children of each item, to this depth
+  --all               Print all children of each item
+  --filter FILTER     Print only the items matching a display filter,
+                      e.g. \"device == 5 && data contains 55:AA\"
+  --json              Print devices and transfers as JSON Lines instead
+  --idle-frames MIN   Print runs of at least MIN SOF packets with no other
+                      traffic as idle frames, with the time they span
+  --poll-durations    Print the time spanned by each group of polls which
+                      were not answered with data, e.g. NAKed INs
+  --decode-as DEV.EP=CLASS[:SIZE]
+                      Decode an endpoint as this class if its descriptors
+                      are not in the capture, e.g. 5.0x83=cdc-data. The
+                      endpoint is given by its address, and SIZE is its
+                      maximum packet size. CLASS is one of cdc-data,
+                      cdc-notification, hid, mass-storage, printer, audio,
+                      video, bulk, interrupt or isochronous. May be repeated
+  --host-descriptors BUS
+                      Use the descriptors of the devices attached to this
+                      computer on this bus, for devices which enumerated
+                      before the capture began
+  --clock N=OFFSET[,DRIFT]
+                      Correct the timestamps of the Nth file for the
+                      difference between its clock and the first file's,
+                      before merging, e.g. 2=-1.5ms,+12.5ppm. OFFSET has
+                      a unit of s, ms, us or ns, and DRIFT is in parts per
+                      million of the time since its first packet
+  --align-clocks      Estimate the correction for each file from packets
+                      also seen in the first file, and print it, before
+                      merging. Any --clock options are used instead for
+                      the files they are given for
+
+extract: Write the data carried on an endpoint to a binary file.
+
+The data payloads on the endpoint are written to OUTPUT in the order they
+were sent, e.g. to recover a firmware image or audio data from a capture.
+On control endpoints, only the data stages of requests are included.
+
+Options:
+  --endpoint DEV.EP   The endpoint, given as device address and endpoint
+                      number, e.g. 5.1 (required)
+  --direction DIR     Write only data sent in this direction: in or out
+  --start SECONDS     Write only data sent at least this long after the
+                      first packet in the capture
+  --end SECONDS       Write only data sent less than this long after the
+                      first packet in the capture
+  --decode-as DEV.EP=CLASS[:SIZE]
+                      Decode an endpoint as this class, as for decode
+  --host-descriptors BUS
+                      Use the descriptors of attached devices, as for decode
+
+split: Write the packets exchanged with each device to a separate file.
+
+For each device address seen in the capture, the packets sent to and from
+that address are written to a file named after OUTPUT with the address
+added, e.g. capture-device-5.pcapng for OUTPUT capture.pcapng, in pcapng
+format if OUTPUT ends in .pcapng, or in pcap format otherwise. Packets not
+sent to any address, such as SOF packets, are left out, and packets sent
+to address 0 before devices are given their addresses are written to a
+file of their own.
+
+report: Print a report on how each device in the capture was enumerated.
+
+The report lists the speed of the bus each device was seen on, its
+descriptors and strings, the configuration selected, the class drivers
+implied, the time taken by each request of its enumeration, and any
+problems seen, in Markdown.
+
+With --compliance, the report instead lists which chapter 9 checks each
+device passed or failed, and how to make the host exercise those untested.
+
+With --throughput, the report instead lists whether the data carried on
+each endpoint given met its target rate, over every window of time while
+the endpoint was active, and the command fails if any target was missed.
+
+Options:
+  --compliance        Report on chapter 9 compliance
+  --throughput DEV.EP=RATE[@WINDOW]
+                      Report on whether an endpoint, given by its address,
+                      carried at least RATE bytes per second over every
+                      WINDOW (default: 1s), e.g. 5.0x81=1.5MB/s@100ms.
+                      RATE may use kB, MB, KiB or MiB. May be repeated
+  --html              Write the report as an HTML page instead
+  --output FILE       Write the report to FILE instead of printing it, as
+                      HTML if the name ends in .html or .htm
+
+dump: Print the decoded capture in a stable format, for comparison with a
+reference in tests.
+
+Every device and traffic item is printed on its own line, indented by its
+depth, with no grouping of repeated items. Each item is identified by its
+position in the tree, e.g. 3.1.2, and traffic items are given the time of
+their first packet, in seconds after the first packet of the capture, so
+that the output depends only on the traffic captured. The first line gives
+the version of the format, which changes only if the format itself does.
+If several files are given, they are merged into a single timeline.
+
+Options:
+  --format FORMAT     Print in this format: stable-text
+                      (default: stable-text)
+  --decode-as DEV.EP=CLASS[:SIZE]
+                      Decode an endpoint as this class, as for decode
+  --output FILE       Write the dump to FILE instead of printing it
+
+descriptors: Print a device's descriptors as source code.
+
+The device, configuration and string descriptors read from the device are
+printed as C struct initializers or Rust constants, so that firmware can
+present the same descriptors.
+
+Options:
+  --device ADDR       The address of the device (required). If several
+                      devices used the address, the last is chosen
+  --language LANG     Generate code in this language: c or rust
+                      (default: c)
+  --output FILE       Write the code to FILE instead of printing it
+
+verify: Check that the packets in a Packetry capture are unchanged.
+
+The packets are checked against the hash saved with the capture, and its
+signature if it was signed, using the key in the file named by the
+PACKETRY_SIGNING_KEY environment variable. The hash is printed, and the
+command fails if the packets or signature do not match, or if a key is
+given and the capture is not signed.
+";
+
+/// Options for a capture made from the command line.
+struct CaptureOptions {
+    serial: Option<String>,
+    speed: Speed,
+    schedule: Schedule,
+    filter: CaptureFilter,
+    markers: bool,
+    marker_port: Option<u16>,
+    trigger: Option<Filter>,
+    list: bool,
+    scenario: Option<PathBuf>,
+    replay_speed: f64,
+    step: bool,
+    output: Option<PathBuf>,
+}
+
+/// Where the packets of a capture come from.
+enum Source {
+    Analyzer(CynthionDevice),
+    Emulator(Scenario),
+}
+
+/// Options for decoding capture files from the command line.
+struct DecodeOptions {
+    max_depth: Option<usize>,
+    filter: Option<Filter>,
+    json: bool,
+    idle_frames: Option<u64>,
+    poll_durations: bool,
+    overrides: Overrides,
+    host_bus: Option<u8>,
+    /// Clock corrections for files, by their index.
+    clocks: Vec<(usize, ClockModel)>,
+    align_clocks: bool,
+    inputs: Vec<PathBuf>,
+}
+
+/// Options for extracting endpoint data from the command line.
+struct ExtractOptions {
+    endpoint: Option<(DeviceAddr, EndpointNum)>,
+    direction: Option<Direction>,
+    start: Option<u64>,
+    end: Option<u64>,
+    overrides: Overrides,
+    host_bus: Option<u8>,
+    paths: Vec<PathBuf>,
+}
+
+/// Options for writing an enumeration report from the command line.
+struct ReportOptions {
+    format: Option<ReportFormat>,
+    /// Whether to report on chapter 9 compliance, instead of enumeration.
+    compliance: bool,
+    /// Targets to report on throughput against, instead of enumeration.
+    throughput: Vec<ThroughputTarget>,
+    output: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+/// Options for dumping capture files from the command line.
+struct DumpOptions {
+    overrides: Overrides,
+    output: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+/// Options for generating descriptor source code from the command line.
+struct DescriptorOptions {
+    device: Option<DeviceAddr>,
+    language: Language,
+    output: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+/// Run the command line interface with the given arguments.
+///
+/// Arguments exclude the program name.
+pub fn run(args: &[String]) -> Result<(), Error> {
+    match args.first().map(String::as_str) {
+        Some("capture") => capture(parse_capture_options(&args[1..])?),
+        Some("decode") => decode(parse_decode_options(&args[1..])?),
+        Some("extract") => extract(parse_extract_options(&args[1..])?),
+        Some("split") => {
+            let (input, output) = parse_split_options(&args[1..])?;
+            split(input, output)
+        },
+        Some("report") => report(parse_report_options(&args[1..])?),
+        Some("dump") => dump(parse_dump_options(&args[1..])?),
+        Some("descriptors") =>
+            descriptors(parse_descriptor_options(&args[1..])?),
+        Some("verify") => verify(parse_verify_options(&args[1..])?),
+        Some("help" | "--help" | "-h") | None => {
+            print!("{USAGE}");
+            Ok(())
+        },
+        Some(other) => bail!("Unknown command '{other}'\n\n{USAGE}"),
+    }
+}
+
+fn parse_capture_options(args: &[String]) -> Result<CaptureOptions, Error> {
+    let mut options = CaptureOptions {
+        serial: None,
+        speed: Speed::High,
+        schedule: Schedule::new(),
+        filter: CaptureFilter::default(),
+        markers: false,
+        marker_port: None,
+        trigger: None,
+        list: false,
+        scenario: None,
+        replay_speed: 1.0,
+        step: false,
+        output: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args
+            .next()
+            .with_context(|| format!("Option {arg} requires a value"));
+        match arg.as_str() {
+            "--serial" => options.serial = Some(value()?.clone()),
+            "--speed" => options.speed = parse_speed(value()?)?,
+            "--start-at" => options.schedule.start_at =
+                Some(Schedule::parse_time_of_day(value()?)?),
+            "--duration" => {
+                let seconds: f64 = value()?
+                    .parse()
+                    .context("Invalid duration")?;
+                if !seconds.is_finite() || seconds <= 0.0 {
+                    bail!("Duration must be a positive number of seconds")
+                }
+                options.schedule.duration =
+                    Some(Duration::from_secs_f64(seconds));
+            },
+            "--packets" => options.schedule.max_packets =
+                Some(value()?.parse().context("Invalid packet count")?),
+            "--bytes" => options.schedule.max_bytes =
+                Some(value()?.parse().context("Invalid byte count")?),
+            "--device" | "--endpoint" | "--pid" =>
+                options.filter.add(&arg[2..], value()?)?,
+            "--markers" => options.markers = true,
+            "--marker-port" => options.marker_port =
+                Some(value()?.parse().context("Invalid marker port")?),
+            "--trigger" => options.trigger = Some(
+                Filter::parse(value()?).context("Invalid trigger filter")?),
+            "--list" => options.list = true,
+            "--scenario" => options.scenario = Some(PathBuf::from(value()?)),
+            "--replay-speed" => options.replay_speed = value()?
+                .trim_end_matches('x')
+                .parse()
+                .context("Invalid replay speed")?,
+            "--step" => options.step = true,
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => {
+                if options.output.is_some() {
+                    bail!("Only one output file may be given")
+                }
+                options.output = Some(PathBuf::from(path));
+            }
+        }
+    }
+    if options.output.is_none() && !options.list {
+        bail!("No output file given\n\n{USAGE}")
+    }
+    let replaying = options.replay_speed != 1.0 || options.step;
+    if replaying && options.scenario.is_none() {
+        bail!("Replay options can only be used with --scenario")
+    }
+    if options.step && options.markers {
+        bail!("--step and --markers cannot both read standard input")
+    }
+    Ok(options)
+}
+
+fn parse_decode_options(args: &[String]) -> Result<DecodeOptions, Error> {
+    let mut options = DecodeOptions {
+        max_depth: Some(0),
+        filter: None,
+        json: false,
+        idle_frames: None,
+        poll_durations: false,
+        overrides: Overrides::default(),
+        host_bus: None,
+        clocks: Vec::new(),
+        align_clocks: false,
+        inputs: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => options.max_depth = Some(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?
+                .parse()
+                .context("Invalid depth")?),
+            "--all" => options.max_depth = None,
+            "--filter" => options.filter = Some(Filter::parse(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?)
+                .context("Invalid filter")?),
+            "--json" => options.json = true,
+            "--idle-frames" => options.idle_frames = Some(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?
+                .parse()
+                .context("Invalid number of frames")?),
+            "--poll-durations" => options.poll_durations = true,
+            "--decode-as" => options.overrides.endpoints.push(
+                EndpointOverride::parse(args
+                    .next()
+                    .with_context(|| format!(
+                        "Option {arg} requires a value"))?)?),
+            "--host-descriptors" => options.host_bus = Some(parse_bus(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?)?),
+            "--clock" => options.clocks.push(parse_clock(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?)?),
+            "--align-clocks" => options.align_clocks = true,
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => options.inputs.push(PathBuf::from(path)),
+        }
+    }
+    if options.inputs.is_empty() {
+        bail!("No capture file given\n\n{USAGE}")
+    }
+    if let Some((index, _)) = options.clocks
+        .iter()
+        .find(|(index, _)| *index >= options.inputs.len())
+    {
+        bail!("No file {} to correct the clock of", index + 1)
+    }
+    Ok(options)
+}
+
+fn parse_extract_options(args: &[String]) -> Result<ExtractOptions, Error> {
+    let mut options = ExtractOptions {
+        endpoint: None,
+        direction: None,
+        start: None,
+        end: None,
+        overrides: Overrides::default(),
+        host_bus: None,
+        paths: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args
+            .next()
+            .with_context(|| format!("Option {arg} requires a value"));
+        match arg.as_str() {
+            "--endpoint" => options.endpoint = Some(parse_endpoint(value()?)?),
+            "--direction" => options.direction =
+                Some(match value()?.to_lowercase().as_str() {
+                    "in" => Direction::In,
+                    "out" => Direction::Out,
+                    other => bail!(
+                        "Unknown direction '{other}', expected in or out"),
+                }),
+            "--start" => options.start = Some(parse_offset(value()?)?),
+            "--end" => options.end = Some(parse_offset(value()?)?),
+            "--decode-as" => options.overrides.endpoints.push(
+                EndpointOverride::parse(value()?)?),
+            "--host-descriptors" =>
+                options.host_bus = Some(parse_bus(value()?)?),
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => options.paths.push(PathBuf::from(path)),
+        }
+    }
+    if options.endpoint.is_none() {
+        bail!("No endpoint given\n\n{USAGE}")
+    }
+    if options.paths.len() != 2 {
+        bail!("Expected a capture file and an output file\n\n{USAGE}")
+    }
+    Ok(options)
+}
+
+fn parse_split_options(args: &[String])
+    -> Result<(PathBuf, PathBuf), Error>
+{
+    match args {
+        [option, ..] | [_, option] if option.starts_with("--") =>
+            bail!("Unknown option '{option}'\n\n{USAGE}"),
+        [input, output] => Ok((PathBuf::from(input), PathBuf::from(output))),
+        _ => bail!("Expected a capture file and an output file\n\n{USAGE}"),
+    }
+}
+
+fn parse_report_options(args: &[String]) -> Result<ReportOptions, Error> {
+    let mut options = ReportOptions {
+        format: None,
+        compliance: false,
+        throughput: Vec::new(),
+        output: None,
+        inputs: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--html" => options.format = Some(ReportFormat::Html),
+            "--compliance" => options.compliance = true,
+            "--throughput" => options.throughput.push(
+                ThroughputTarget::parse(args
+                    .next()
+                    .with_context(|| format!(
+                        "Option {arg} requires a value"))?)?),
+            "--output" => options.output = Some(PathBuf::from(args
+                .next()
+                .with_context(|| format!("Option {arg} requires a value"))?)),
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => options.inputs.push(PathBuf::from(path)),
+        }
+    }
+    if options.inputs.is_empty() {
+        bail!("No capture file given\n\n{USAGE}")
+    }
+    if options.compliance && !options.throughput.is_empty() {
+        bail!("--compliance and --throughput cannot be used together")
+    }
+    Ok(options)
+}
+
+fn parse_verify_options(args: &[String]) -> Result<PathBuf, Error> {
+    match args {
+        [] => bail!("No capture file given\n\n{USAGE}"),
+        [option] if option.starts_with("--") =>
+            bail!("Unknown option '{option}'\n\n{USAGE}"),
+        [path] => Ok(PathBuf::from(path)),
+        _ => bail!("Only one capture file can be verified\n\n{USAGE}"),
+    }
+}
+
+fn parse_dump_options(args: &[String]) -> Result<DumpOptions, Error> {
+    let mut options = DumpOptions {
+        overrides: Overrides::default(),
+        output: None,
+        inputs: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args
+            .next()
+            .with_context(|| format!("Option {arg} requires a value"));
+        match arg.as_str() {
+            "--format" => match value()?.as_str() {
+                "stable-text" => {},
+                other => bail!(
+                    "Unknown dump format '{other}', expected stable-text"),
+            },
+            "--decode-as" => options.overrides.endpoints.push(
+                EndpointOverride::parse(value()?)?),
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => options.inputs.push(PathBuf::from(path)),
+        }
+    }
+    if options.inputs.is_empty() {
+        bail!("No capture file given\n\n{USAGE}")
+    }
+    Ok(options)
+}
+
+fn parse_descriptor_options(args: &[String])
+    -> Result<DescriptorOptions, Error>
+{
+    let mut options = DescriptorOptions {
+        device: None,
+        language: Language::C,
+        output: None,
+        inputs: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args
+            .next()
+            .with_context(|| format!("Option {arg} requires a value"));
+        match arg.as_str() {
+            "--device" => options.device = Some(DeviceAddr(value()?
+                .parse()
+                .context("Invalid device address")?)),
+            "--language" => {
+                let name = value()?;
+                options.language = Language::from_name(name)
+                    .with_context(|| format!(
+                        "Unknown language '{name}', expected c or rust"))?;
+            },
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            option if option.starts_with("--") =>
+                bail!("Unknown option '{option}'\n\n{USAGE}"),
+            path => options.inputs.push(PathBuf::from(path)),
+        }
+    }
+    if options.device.is_none() {
+        bail!("No device given\n\n{USAGE}")
+    }
+    if options.inputs.is_empty() {
+        bail!("No capture file given\n\n{USAGE}")
+    }
+    Ok(options)
+}
+
+/// Parse an endpoint given as device address and number, e.g. `5.1`.
+fn parse_endpoint(text: &str) -> Result<(DeviceAddr, EndpointNum), Error> {
+    let invalid = || format!(
+        "Invalid endpoint '{text}', expected e.g. 5.1 for endpoint 1 \
+         of device 5");
+    let (device, number) = text.split_once('.').with_context(invalid)?;
+    let device: u8 = device.parse().with_context(invalid)?;
+    let number: u8 = number.parse().with_context(invalid)?;
+    if device > 127 || number > 15 {
+        bail!(invalid())
+    }
+    Ok((DeviceAddr(device), EndpointNum(number)))
+}
+
+/// Parse a time in seconds after the first packet, returning nanoseconds.
+fn parse_offset(text: &str) -> Result<u64, Error> {
+    let seconds: f64 = text.parse().context("Invalid time")?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        bail!("Times must be a non-negative number of seconds")
+    }
+    Ok((seconds * 1e9) as u64)
+}
+
+/// Parse a clock correction for a file, given as its position counting
+/// from 1 and a model, e.g. `2=-1.5ms,+12.5ppm`.
+fn parse_clock(text: &str) -> Result<(usize, ClockModel), Error> {
+    let (number, model) = text
+        .split_once('=')
+        .with_context(|| format!(
+            "Invalid clock correction '{text}', expected e.g. 2=-1.5ms"))?;
+    let index = match number.parse::<usize>() {
+        Ok(number) if number > 0 => number - 1,
+        _ => bail!("Invalid file number '{number}'"),
+    };
+    Ok((index, ClockModel::parse(model)?))
+}
+
+fn parse_bus(text: &str) -> Result<u8, Error> {
+    text.parse().with_context(|| format!("Invalid bus number '{text}'"))
+}
+
+fn parse_speed(name: &str) -> Result<Speed, Error> {
+    Ok(match name.to_lowercase().as_str() {
+        "auto" => Speed::Auto,
+        "high" => Speed::High,
+        "full" => Speed::Full,
+        "low" => Speed::Low,
+        _ => bail!("Unknown speed '{name}', expected auto, high, full or low"),
+    })
+}
+
+/// Add the descriptors of the devices on a bus of this computer to the
+/// overrides used for decoding.
+fn fetch_host_descriptors(bus: u8, overrides: &mut Overrides)
+    -> Result<(), Error>
+{
+    let (descriptors, errors) = fetch_bus_descriptors(bus)?;
+    for error in errors {
+        eprintln!("Warning: {error:#}");
+    }
+    if descriptors.is_empty() {
+        bail!("No descriptors could be read from devices on bus {bus}")
+    }
+    for known in descriptors {
+        overrides.set_descriptors(known);
+    }
+    Ok(())
+}
+
+fn capture(options: CaptureOptions) -> Result<(), Error> {
+    if options.list {
+        return list_analyzers();
+    }
+
+    let source = match &options.scenario {
+        Some(path) => Source::Emulator(Scenario::load(path)?),
+        None => Source::Analyzer(select_analyzer(&options)?),
+    };
+
+    let path = options.output.context("No output file given")?;
+    let mut output = PacketFileWriter::create(&path)?;
+    let using_markers = options.markers || options.marker_port.is_some();
+    if using_markers && !output.has_comments() {
+        bail!("Markers can only be saved in pcapng files")
+    }
+
+    // Wait for the scheduled start time, if any.
+    if let Some(start_at) = options.schedule.start_at {
+        eprintln!("Waiting until {} UTC to start capturing",
+                  Schedule::format_time_of_day(start_at));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        sleep(options.schedule.start_delay(now));
+    }
+
+    // Start capturing.
+    let result_handler = |result: Result<(), Error>| {
+        if let Err(error) = result {
+            eprintln!("Capture failed: {error:?}");
+        }
+    };
+    let (stream, stop, trigger_output) = match source {
+        Source::Analyzer(device) => {
+            let handle = device.open()?;
+            let trigger_output = handle.trigger();
+            let (stream, stop) = handle.start(options.speed, result_handler)?;
+            (stream, stop, trigger_output)
+        },
+        Source::Emulator(scenario) => {
+            let playback = Playback::new();
+            playback.set_speed(options.replay_speed)?;
+            playback.set_stepping(options.step);
+            if options.step {
+                eprintln!("Press Enter to send each packet");
+                let playback = playback.clone();
+                spawn(move || {
+                    for _ in stdin().lock().lines() {
+                        playback.step();
+                    }
+                });
+            }
+            let (stream, stop) =
+                scenario.start_with_playback(playback, result_handler)?;
+            (stream, stop, CynthionTrigger::emulated())
+        },
+    };
+    let stop = Arc::new(Mutex::new(Some(stop)));
+    let mut packets = FilteredPackets::new(stream, options.filter);
+
+    // Collect markers from standard input and the marker port, if used.
+    let (marker_sender, markers) = channel::<Marker>();
+    if options.markers {
+        let sender = marker_sender.clone();
+        spawn(move || read_markers(stdin().lock(), |marker| {
+            let _ = sender.send(marker);
+        }));
+    }
+    if let Some(port) = options.marker_port {
+        let address = listen(&format!("127.0.0.1:{port}"), move |marker| {
+            let _ = marker_sender.send(marker);
+        })?;
+        eprintln!("Listening for markers on {address}");
+    }
+
+    // Stop after the requested duration, if any.
+    if let Some(duration) = options.schedule.duration {
+        let stop = stop.clone();
+        spawn(move || {
+            sleep(duration);
+            if let Err(error) = stop_capture(&stop) {
+                eprintln!("Failed to stop capture: {error:?}");
+            }
+        });
+    }
+
+    // If a trigger is used, decode the packets as they are captured, and
+    // watch the decoded traffic on another thread.
+    let mut trigger_decoder = None;
+    let mut trigger_watcher = None;
+    if let Some(filter) = options.trigger {
+        let (writer, mut reader) = create_capture()?;
+        trigger_decoder = Some(Decoder::new(writer)?);
+        trigger_watcher = Some(spawn(move || {
+            Trigger::new(filter).watch(&mut reader, || trigger_output.pulse())
+        }));
+    }
+
+    let start = Instant::now();
+    let mut count = 0;
+    let mut bytes = 0;
+    // The stream is kept until the capture is stopped, so that the capture
+    // thread can still deliver data whilst it is shutting down.
+    for packet in packets.by_ref() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos() as u64;
+        // Markers received since the last packet are saved with this one.
+        let comment = markers
+            .try_iter()
+            .map(|marker| format!("Marker: {}", marker.label))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let comment = Some(comment.as_str()).filter(|text| !text.is_empty());
+        output.write_packet(&packet, timestamp, comment)?;
+        if let Some(decoder) = trigger_decoder.as_mut() {
+            decoder.handle_raw_packet(&packet, timestamp)?;
+        }
+        count += 1;
+        bytes += packet.len() as u64;
+        if options.schedule.limit_reached(count, bytes) {
+            break;
+        }
+    }
+    stop_capture(&stop)?;
+    let dropped = packets.dropped();
+    drop(packets);
+    output.finish()?;
+    let fired = match (trigger_decoder, trigger_watcher) {
+        (Some(decoder), Some(watcher)) => {
+            decoder.finish()?;
+            match watcher.join() {
+                Ok(result) => Some(result?),
+                Err(_) => bail!("Trigger thread panicked"),
+            }
+        },
+        _ => None,
+    };
+
+    eprintln!("Captured {} packets, {} in {:.1}s to {}",
+              fmt_count(count),
+              fmt_size(bytes),
+              start.elapsed().as_secs_f64(),
+              path.display());
+    if dropped > 0 {
+        eprintln!("Dropped {} packets not matching the capture filter",
+                  fmt_count(dropped));
+    }
+    if let Some(fired) = fired {
+        eprintln!("Fired the trigger {} times", fmt_count(fired));
+    }
+    Ok(())
+}
+
+/// Print the available analyzers and whether each can be used.
+fn list_analyzers() -> Result<(), Error> {
+    let devices = CynthionDevice::scan()?;
+    if devices.is_empty() {
+        println!("No analyzers found");
+    }
+    for device in &devices {
+        let serial = device.device_info
+            .serial_number()
+            .unwrap_or("(no serial number)");
+        match &device.usability {
+            Usable(_, speeds) => println!("{serial}: {}",
+                speeds
+                    .iter()
+                    .map(Speed::description)
+                    .collect::<Vec<_>>()
+                    .join(", ")),
+            Unusable(reason) => println!("{serial}: unusable: {reason}"),
+        }
+    }
+    Ok(())
+}
+
+/// Find the analyzer to capture with, and check it supports the speed.
+fn select_analyzer(options: &CaptureOptions)
+    -> Result<CynthionDevice, Error>
+{
+    let devices = CynthionDevice::scan()?;
+    let device = match &options.serial {
+        Some(serial) => devices
+            .into_iter()
+            .find(|device|
+                device.device_info.serial_number() == Some(serial.as_str()))
+            .with_context(|| format!(
+                "No analyzer found with serial number {serial}"))?,
+        None => devices
+            .into_iter()
+            .find(|device| matches!(device.usability, Usable(..)))
+            .context("No usable analyzer found")?,
+    };
+    if let Usable(_, speeds) = &device.usability {
+        if !speeds.iter().any(|speed| speed.mask() == options.speed.mask()) {
+            bail!("Analyzer does not support {} speed",
+                  options.speed.description())
+        }
+    }
+    Ok(device)
+}
+
+/// Stop the capture, if it has not already been stopped.
+fn stop_capture(stop: &Mutex<Option<CynthionStop>>) -> Result<(), Error> {
+    let handle = match stop.lock() {
+        Ok(mut guard) => guard.take(),
+        Err(_) => bail!("Capture stop handle was poisoned"),
+    };
+    match handle {
+        Some(handle) => handle.stop(),
+        None => Ok(()),
+    }
+}
+
+fn decode(mut options: DecodeOptions) -> Result<(), Error> {
+    if let Some(bus) = options.host_bus {
+        fetch_host_descriptors(bus, &mut options.overrides)?;
+    }
+    let mut clocks = if options.align_clocks {
+        let clocks = estimate_clocks(&options.inputs)?;
+        for (path, clock) in options.inputs.iter().zip(&clocks).skip(1) {
+            eprintln!("Clock of {}: {clock}", path.display());
+        }
+        clocks
+    } else {
+        vec![ClockModel::default(); options.inputs.len()]
+    };
+    for (index, clock) in &options.clocks {
+        clocks[*index] = *clock;
+    }
+    let mut capture = load_with_clocks(
+        &options.inputs, &options.overrides, &clocks)?;
+    capture.set_idle_frames(options.idle_frames);
+    capture.set_poll_durations(options.poll_durations);
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    let item_count = capture.item_index.len();
+    if options.json {
+        export::write_json_devices(&mut capture, &mut writer)?;
+    }
+    for index in 0..item_count {
+        let item: TrafficItem = capture.item(None, index)?;
+        if let Some(filter) = &options.filter {
+            if !filter.matches(&mut capture, &item)? {
+                continue;
+            }
+        }
+        if options.json {
+            export::write_json_transfer(
+                &mut capture, index.into(), &mut writer)?;
+        } else {
+            export::write_text_item(
+                &mut capture, &item, options.max_depth, &mut writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn extract(mut options: ExtractOptions) -> Result<(), Error> {
+    let (device, number) = options.endpoint.context("No endpoint given")?;
+    if let Some(bus) = options.host_bus {
+        fetch_host_descriptors(bus, &mut options.overrides)?;
+    }
+    let mut capture = load_with_overrides(
+        &options.paths[..1], &options.overrides)?;
+    let time_range = if options.start.is_some() || options.end.is_some() {
+        if capture.packet_times.len() == 0 {
+            bail!("The capture has no packets")
+        }
+        let first = capture.packet_time(PacketId::from(0))?;
+        let start = first + options.start.unwrap_or(0);
+        let end = options.end.map_or(u64::MAX, |end| first + end);
+        Some(start..end)
+    } else {
+        None
+    };
+    let data = EndpointData {
+        device,
+        number,
+        direction: options.direction,
+        time_range,
+    };
+    let path = &options.paths[1];
+    let file = File::create(path).with_context(|| format!(
+        "Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let total = export::write_endpoint_data(&mut capture, &data, &mut writer)?;
+    writer.flush()?;
+    eprintln!("Wrote {} from endpoint {}.{} to {}",
+              fmt_size(total), device, number, path.display());
+    Ok(())
+}
+
+fn split(input: PathBuf, output: PathBuf) -> Result<(), Error> {
+    let mut capture = load(std::slice::from_ref(&input))?;
+    let metadata = capture.shared.metadata.load_full();
+    let comments = capture.packet_comments()?;
+    let addresses = capture.device_address_packets()?;
+    if addresses.is_empty() {
+        bail!("No packets sent to any device in {}", input.display())
+    }
+    for (address, packet_ids) in addresses {
+        let path = device_path(&output, address);
+        let mut writer = PacketFileWriter::create_with_metadata(
+            &path, &metadata)?;
+        for packet_id in &packet_ids {
+            let bytes = capture.packet(*packet_id)?;
+            let timestamp = capture.packet_time(*packet_id)?;
+            let comment = comments.get(packet_id).map(String::as_str);
+            writer.write_packet(&bytes, timestamp, comment)?;
+        }
+        writer.finish()?;
+        eprintln!("Wrote {} packets for device {address} to {}",
+                  fmt_count(packet_ids.len() as u64), path.display());
+    }
+    Ok(())
+}
+
+fn report(options: ReportOptions) -> Result<(), Error> {
+    let mut capture = load(&options.inputs)?;
+    let mut missed = 0;
+    let report = if options.compliance {
+        compliance_report(&mut capture)?
+    } else if !options.throughput.is_empty() {
+        let results = options.throughput
+            .iter()
+            .map(|target| check_target(&mut capture, target))
+            .collect::<Result<Vec<_>, Error>>()?;
+        missed = results.iter().filter(|result| !result.passed()).count();
+        throughput_report(&mut capture, &results)?
+    } else {
+        enumeration_report(&mut capture)?
+    };
+    match &options.output {
+        Some(path) => {
+            let format = options.format
+                .unwrap_or_else(|| ReportFormat::from_path(path));
+            let file = File::create(path).with_context(|| format!(
+                "Failed to create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            report.write(format, &mut writer)?;
+            writer.flush()?;
+        },
+        None => {
+            let format = options.format.unwrap_or(ReportFormat::Markdown);
+            let stdout = std::io::stdout();
+            let mut writer = BufWriter::new(stdout.lock());
+            report.write(format, &mut writer)?;
+            writer.flush()?;
+        }
+    }
+    if missed > 0 {
+        bail!("{missed} of {} throughput targets were not met",
+              options.throughput.len())
+    }
+    Ok(())
+}
+
+fn verify(path: PathBuf) -> Result<(), Error> {
+    // Loading fails if the packets have changed, or if a key is given and
+    // the capture is not signed. They are checked again here to find
+    // whether the signature was checked too.
+    let mut capture = load(std::slice::from_ref(&path))?;
+    let integrity = capture.shared.integrity
+        .load_full()
+        .with_context(|| format!(
+            "{} was not saved with a hash of its packets", path.display()))?;
+    let verification = verify_capture(&mut capture)?
+        .context("No hash to verify")?;
+    println!("SHA-256: {}", hex(&integrity.hash));
+    println!("{}", verification.description());
+    Ok(())
+}
+
+fn dump(options: DumpOptions) -> Result<(), Error> {
+    let mut capture = load_with_overrides(&options.inputs, &options.overrides)?;
+    match &options.output {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!(
+                "Failed to create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            export::write_stable_text(&mut capture, &mut writer)?;
+            writer.flush()?;
+        },
+        None => {
+            let stdout = std::io::stdout();
+            let mut writer = BufWriter::new(stdout.lock());
+            export::write_stable_text(&mut capture, &mut writer)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn descriptors(options: DescriptorOptions) -> Result<(), Error> {
+    let address = options.device.context("No device given")?;
+    let mut capture = load(&options.inputs)?;
+    // Device 0 is the default address, used before devices are addressed.
+    let device_id = (1..capture.devices.len())
+        .map(DeviceId::from)
+        .filter(|id| capture.devices
+            .get(*id)
+            .map_or(false, |device| device.address == address))
+        .last()
+        .with_context(|| format!("No device with address {address} found"))?;
+    let source = descriptor_source(&mut capture, device_id, options.language)?;
+    match &options.output {
+        Some(path) => std::fs::write(path, source).with_context(|| format!(
+            "Failed to write {}", path.display()))?,
+        None => print!("{source}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,753 @@
+//! Coloring rules, for highlighting traffic items in the traffic view.
+//!
+//! Each rule has a condition and a color. Items are shown in the color of
+//! the first enabled rule whose condition they match.
+//!
+//! Other parts of the interface drawn in colors of their own take them
+//! from a theme, with defaults for both light and dark GTK themes.
+//!
+//! The default colors come from a palette, which can be chosen to give
+//! higher contrast, or to avoid colors that are hard to tell apart with
+//! the common red-green forms of color blindness.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Error, bail};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::capture::{CaptureReader, TrafficItem};
+use crate::config::{enabled_by_default, JsonFile};
+use crate::filter::Filter;
+
+/// Names of the kinds of condition, as shown in the rules editor.
+pub const CONDITION_KINDS: [&str; 5] =
+    ["Device", "Endpoint", "Transfer type", "Error", "Filter"];
+
+/// A condition which selects the traffic items a rule applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// Traffic to or from a device address.
+    Device(u8),
+    /// Traffic on an endpoint address of a device address.
+    Endpoint(u8, u8),
+    /// Traffic on endpoints of a type, as named in filters.
+    TransferType(String),
+    /// Traffic showing an error.
+    Error,
+    /// Traffic selected by a filter expression.
+    Expression(String),
+}
+
+impl Condition {
+    /// Parse a condition, given its kind and value.
+    ///
+    /// Endpoints are given as `device.endpoint`, e.g. `5.0x81`.
+    pub fn parse(kind: &str, value: &str) -> Result<Condition, Error> {
+        let value = value.trim();
+        Ok(match kind {
+            "Device" => Condition::Device(parse_u8(value)?),
+            "Endpoint" => match value.split_once('.') {
+                Some((device, endpoint)) => Condition::Endpoint(
+                    parse_u8(device)?, parse_u8(endpoint)?),
+                None => bail!(
+                    "Expected an endpoint such as 5.0x81, found '{value}'"),
+            },
+            "Transfer type" => {
+                if value.is_empty() ||
+                    !value.chars().all(|c| c.is_ascii_alphabetic())
+                {
+                    bail!("Expected a transfer type such as 'bulk', \
+                           found '{value}'")
+                }
+                Condition::TransferType(value.to_lowercase())
+            },
+            "Error" => Condition::Error,
+            "Filter" => Condition::Expression(value.to_string()),
+            _ => bail!("Unknown condition '{kind}'"),
+        })
+    }
+
+    /// The kind of this condition.
+    pub fn kind(&self) -> &'static str {
+        use Condition::*;
+        match self {
+            Device(_) => "Device",
+            Endpoint(..) => "Endpoint",
+            TransferType(_) => "Transfer type",
+            Error => "Error",
+            Expression(_) => "Filter",
+        }
+    }
+
+    /// The value of this condition, in the form accepted by `parse`.
+    pub fn value(&self) -> String {
+        use Condition::*;
+        match self {
+            Device(device) => format!("{device}"),
+            Endpoint(device, endpoint) => format!("{device}.0x{endpoint:02X}"),
+            TransferType(name) => name.clone(),
+            Error => String::new(),
+            Expression(text) => text.clone(),
+        }
+    }
+
+    /// The filter equivalent to this condition, if there is one.
+    fn filter(&self) -> Result<Option<Filter>, Error> {
+        use Condition::*;
+        let text = match self {
+            Device(device) => format!("device == {device}"),
+            Endpoint(device, endpoint) =>
+                format!("device == {device} && endpoint == {endpoint}"),
+            TransferType(name) => format!("transfer.type == {name}"),
+            Error => return Ok(None),
+            Expression(text) => text.clone(),
+        };
+        Ok(Some(Filter::parse(&text)?))
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Condition::*;
+        match self {
+            Device(device) => write!(f, "device {device}"),
+            Endpoint(device, endpoint) =>
+                write!(f, "endpoint {device}.0x{endpoint:02X}"),
+            TransferType(name) => write!(f, "{name} transfers"),
+            Error => write!(f, "errors"),
+            Expression(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+fn parse_u8(text: &str) -> Result<u8, Error> {
+    let text = text.trim();
+    let result = match text.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    result.with_context(|| format!("Invalid number '{text}'"))
+}
+
+/// Check that a color is given as `#RRGGBB`.
+fn check_color(color: &str) -> Result<(), Error> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 &&
+            hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+        _ => bail!("Invalid color '{color}', expected #RRGGBB"),
+    }
+}
+
+/// Red, green and blue components of a `#RRGGBB` color, from 0 to 1.
+///
+/// Invalid colors are treated as black.
+pub fn rgb(color: &str) -> (f64, f64, f64) {
+    let component = |range: std::ops::Range<usize>| color
+        .get(range)
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        .map_or(0.0, |value| value as f64 / 255.0);
+    (component(1..3), component(3..5), component(5..7))
+}
+
+/// Color for text shown on a background color, black or white, whichever
+/// is easier to read.
+pub fn text_color(background: &str) -> &'static str {
+    let (red, green, blue) = rgb(background);
+    let luminance = 0.299 * red + 0.587 * green + 0.114 * blue;
+    if luminance > 0.5 { "#000000" } else { "#FFFFFF" }
+}
+
+/// A rule giving the color of the traffic items matching a condition.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "SavedColorRule", into = "SavedColorRule")]
+pub struct ColorRule {
+    pub condition: Condition,
+    pub color: String,
+    pub enabled: bool,
+    filter: Option<Filter>,
+}
+
+impl ColorRule {
+    pub fn new(condition: Condition, color: &str) -> Result<ColorRule, Error> {
+        check_color(color)?;
+        let filter = condition.filter()
+            .with_context(|| format!("Invalid condition '{condition}'"))?;
+        Ok(ColorRule {
+            condition,
+            color: color.to_string(),
+            enabled: true,
+            filter,
+        })
+    }
+
+    /// Whether a traffic item matches this rule's condition.
+    pub fn matches(&self, capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<bool, Error>
+    {
+        match &self.filter {
+            Some(filter) => filter.matches(capture, item),
+            None => capture.item_error(item),
+        }
+    }
+}
+
+/// A coloring rule as saved, with its condition given by kind and value.
+#[derive(Serialize, Deserialize)]
+struct SavedColorRule {
+    condition: String,
+    value: String,
+    color: String,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+}
+
+impl TryFrom<SavedColorRule> for ColorRule {
+    type Error = Error;
+
+    fn try_from(saved: SavedColorRule) -> Result<ColorRule, Error> {
+        let condition = Condition::parse(&saved.condition, &saved.value)?;
+        let mut rule = ColorRule::new(condition, &saved.color)?;
+        rule.enabled = saved.enabled;
+        Ok(rule)
+    }
+}
+
+impl From<ColorRule> for SavedColorRule {
+    fn from(rule: ColorRule) -> SavedColorRule {
+        SavedColorRule {
+            condition: rule.condition.kind().to_string(),
+            value: rule.condition.value(),
+            color: rule.color,
+            enabled: rule.enabled,
+        }
+    }
+}
+
+/// An ordered list of coloring rules.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ColorRules {
+    rules: Vec<ColorRule>,
+}
+
+impl ColorRules {
+    pub fn new() -> Self {
+        ColorRules::default()
+    }
+
+    /// The rules used before any are configured, which highlight errors
+    /// in the palette's error color.
+    pub fn defaults(palette: Palette) -> Self {
+        let mut rules = ColorRules::new();
+        rules.push(
+            ColorRule::new(Condition::Error, palette.error_color()).unwrap());
+        rules
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Change the colors of rules using one palette's colors to the
+    /// equivalent colors of another. Colors chosen by the user are kept.
+    pub fn recolor(&mut self, from: Palette, to: Palette) {
+        for rule in self.rules.iter_mut() {
+            if rule.condition == Condition::Error &&
+                rule.color == from.error_color()
+            {
+                rule.color = to.error_color().to_string();
+            } else if let Some(index) = from
+                .rule_colors()
+                .iter()
+                .position(|color| *color == rule.color)
+            {
+                rule.color = to.rule_colors()[index].to_string();
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&ColorRule> {
+        self.rules.iter()
+    }
+
+    /// Add a rule, after the existing rules.
+    pub fn push(&mut self, rule: ColorRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.rules.len() {
+            self.rules.remove(index);
+        }
+    }
+
+    /// Enable or disable a rule.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(rule) = self.rules.get_mut(index) {
+            rule.enabled = enabled;
+        }
+    }
+
+    /// Move a rule one place earlier, giving it precedence over the rule
+    /// before it.
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.rules.len() {
+            self.rules.swap(index - 1, index);
+        }
+    }
+
+    /// Name of the CSS class used to show items matching a rule.
+    pub fn class_name(index: usize) -> String {
+        format!("color-rule-{index}")
+    }
+
+    /// CSS defining the class for each rule.
+    ///
+    /// The text color is chosen to be readable on the rule's color, as
+    /// the theme's own text color may not be. The expander and connecting
+    /// lines of an item are drawn in the same color as its text.
+    pub fn css(&self) -> String {
+        let mut css = String::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            css.push_str(&format!(
+                ".{} {{ background-color: {}; color: {}; }}\n",
+                ColorRules::class_name(index),
+                rule.color,
+                text_color(&rule.color)));
+        }
+        css
+    }
+
+    /// Find the first enabled rule matching a traffic item.
+    pub fn find(&self, capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<Option<usize>, Error>
+    {
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.enabled && rule.matches(capture, item)? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl JsonFile for ColorRules {
+    const CONTENTS: &'static str = "coloring rules";
+}
+
+/// Parts of the interface drawn in colors of their own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Element {
+    Highlight,
+    Removed,
+    Added,
+    #[serde(rename = "in")]
+    Inbound,
+    #[serde(rename = "out")]
+    Outbound,
+    PlotBackground,
+    PlotStripe,
+    PlotText,
+    PlotBars,
+}
+
+impl Element {
+    pub const ALL: [Element; 9] = [
+        Element::Highlight,
+        Element::Removed,
+        Element::Added,
+        Element::Inbound,
+        Element::Outbound,
+        Element::PlotBackground,
+        Element::PlotStripe,
+        Element::PlotText,
+        Element::PlotBars,
+    ];
+
+    /// Description of this element, as shown in the theme editor.
+    pub fn label(&self) -> &'static str {
+        use Element::*;
+        match self {
+            Highlight => "Highlighted bytes and changed steps",
+            Removed => "Steps only in this capture",
+            Added => "Steps only in the other capture",
+            Inbound => "IN data in followed streams",
+            Outbound => "OUT data in followed streams",
+            PlotBackground => "Graph and timeline background",
+            PlotStripe => "Alternate timeline lanes",
+            PlotText => "Graph and timeline text",
+            PlotBars => "Graph bars",
+        }
+    }
+
+}
+
+/// A set of default colors for the interface and for coloring rules.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    Standard,
+    HighContrast,
+    ColorblindSafe,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 3] = [
+        Palette::Standard,
+        Palette::HighContrast,
+        Palette::ColorblindSafe,
+    ];
+
+    /// Description of this palette, as shown in the theme editor.
+    pub fn label(&self) -> &'static str {
+        use Palette::*;
+        match self {
+            Standard => "Standard",
+            HighContrast => "High contrast",
+            ColorblindSafe => "Safe for red-green color blindness",
+        }
+    }
+
+    /// Color for the rule highlighting items with errors.
+    pub fn error_color(&self) -> &'static str {
+        use Palette::*;
+        match self {
+            Standard => "#F6C3C3",
+            HighContrast => "#FF6666",
+            ColorblindSafe => "#E69F00",
+        }
+    }
+
+    /// Colors offered in turn for new coloring rules, distinct from each
+    /// other and from the error color.
+    pub fn rule_colors(&self) -> [&'static str; 6] {
+        use Palette::*;
+        match self {
+            Standard => [
+                "#C2D9F5", "#C3F6C3", "#FFE08A",
+                "#E0C8F5", "#F8D3A8", "#C8F0F0",
+            ],
+            HighContrast => [
+                "#80B0FF", "#80FF80", "#FFFF00",
+                "#D090FF", "#FFB050", "#80FFFF",
+            ],
+            // Based on the Okabe-Ito palette, leaving out its red and
+            // green, which are confused with each other.
+            ColorblindSafe => [
+                "#A6D4F2", "#F0E442", "#D7A9C7",
+                "#8FD3C1", "#C9C9C9", "#56B4E9",
+            ],
+        }
+    }
+
+    /// Color offered for the next coloring rule, after a number of rules.
+    pub fn rule_color(&self, count: usize) -> &'static str {
+        let colors = self.rule_colors();
+        colors[count % colors.len()]
+    }
+
+    /// The color used for an element unless another is chosen.
+    pub fn color(&self, element: Element, dark: bool) -> &'static str {
+        use Element::*;
+        use Palette::*;
+        match (self, element, dark) {
+            (Standard, Highlight, false) => "#FFE08A",
+            (Standard, Highlight, true) => "#7A6200",
+            (Standard, Removed, false) => "#F6C3C3",
+            (Standard, Removed, true) => "#7A2E2E",
+            (Standard, Added, false) => "#C3F6C3",
+            (Standard, Added, true) => "#2E6B2E",
+            (Standard, Inbound, false) => "#1A5FB4",
+            (Standard, Inbound, true) => "#99C1F1",
+            (Standard, Outbound, false) => "#C01C28",
+            (Standard, Outbound, true) => "#F66151",
+            (Standard, PlotBackground, false) => "#FFFFFF",
+            (Standard, PlotBackground, true) => "#1E1E1E",
+            (Standard, PlotStripe, false) => "#F2F2F2",
+            (Standard, PlotStripe, true) => "#2A2A2A",
+            (Standard, PlotText, false) => "#000000",
+            (Standard, PlotText, true) => "#FFFFFF",
+            (Standard, PlotBars, false) => "#3366CC",
+            (Standard, PlotBars, true) => "#62A0EA",
+            (HighContrast, Highlight, false) => "#FFFF00",
+            (HighContrast, Highlight, true) => "#806000",
+            (HighContrast, Removed, false) => "#FF8080",
+            (HighContrast, Removed, true) => "#A00000",
+            (HighContrast, Added, false) => "#80FF80",
+            (HighContrast, Added, true) => "#006000",
+            (HighContrast, Inbound, false) => "#0000C0",
+            (HighContrast, Inbound, true) => "#80C0FF",
+            (HighContrast, Outbound, false) => "#B00000",
+            (HighContrast, Outbound, true) => "#FF8080",
+            (HighContrast, PlotBackground, false) => "#FFFFFF",
+            (HighContrast, PlotBackground, true) => "#000000",
+            (HighContrast, PlotStripe, false) => "#E0E0E0",
+            (HighContrast, PlotStripe, true) => "#303030",
+            (HighContrast, PlotText, false) => "#000000",
+            (HighContrast, PlotText, true) => "#FFFFFF",
+            (HighContrast, PlotBars, false) => "#000000",
+            (HighContrast, PlotBars, true) => "#FFFF00",
+            // Removed and added steps, and OUT and IN data, are told
+            // apart by orange and blue rather than red and green.
+            (ColorblindSafe, Highlight, false) => "#F0E442",
+            (ColorblindSafe, Highlight, true) => "#7A6F00",
+            (ColorblindSafe, Removed, false) => "#F5C18A",
+            (ColorblindSafe, Removed, true) => "#8A4A00",
+            (ColorblindSafe, Added, false) => "#A6D4F2",
+            (ColorblindSafe, Added, true) => "#1F4E79",
+            (ColorblindSafe, Inbound, false) => "#0072B2",
+            (ColorblindSafe, Inbound, true) => "#56B4E9",
+            (ColorblindSafe, Outbound, false) => "#D55E00",
+            (ColorblindSafe, Outbound, true) => "#E69F00",
+            (ColorblindSafe, PlotBackground, false) => "#FFFFFF",
+            (ColorblindSafe, PlotBackground, true) => "#1E1E1E",
+            (ColorblindSafe, PlotStripe, false) => "#F2F2F2",
+            (ColorblindSafe, PlotStripe, true) => "#2A2A2A",
+            (ColorblindSafe, PlotText, false) => "#000000",
+            (ColorblindSafe, PlotText, true) => "#FFFFFF",
+            (ColorblindSafe, PlotBars, false) => "#0072B2",
+            (ColorblindSafe, PlotBars, true) => "#56B4E9",
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::Standard
+    }
+}
+
+/// Colors chosen for elements of the interface, for light and dark themes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    palette: Palette,
+    #[serde(deserialize_with = "deserialize_colors")]
+    light: BTreeMap<Element, String>,
+    #[serde(deserialize_with = "deserialize_colors")]
+    dark: BTreeMap<Element, String>,
+}
+
+/// Read the colors chosen for elements, checking each is valid.
+fn deserialize_colors<'de, D>(deserializer: D)
+    -> Result<BTreeMap<Element, String>, D::Error>
+    where D: Deserializer<'de>
+{
+    let colors = BTreeMap::<Element, String>::deserialize(deserializer)?;
+    for color in colors.values() {
+        check_color(color).map_err(de::Error::custom)?;
+    }
+    Ok(colors)
+}
+
+impl Theme {
+    pub fn new() -> Self {
+        Theme::default()
+    }
+
+    /// The color of an element, in a light or dark theme.
+    pub fn color(&self, element: Element, dark: bool) -> &str {
+        let colors = if dark { &self.dark } else { &self.light };
+        match colors.get(&element) {
+            Some(color) => color,
+            None => self.palette.color(element, dark),
+        }
+    }
+
+    /// The palette giving the colors of elements not chosen individually.
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Choose the color of an element, or return it to its default.
+    pub fn set_color(&mut self,
+                     element: Element,
+                     dark: bool,
+                     color: Option<&str>)
+        -> Result<(), Error>
+    {
+        let colors = if dark { &mut self.dark } else { &mut self.light };
+        match color {
+            Some(color) => {
+                check_color(color)?;
+                colors.insert(element, color.to_string());
+            },
+            None => {
+                colors.remove(&element);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return all elements to their default colors, in a light or dark
+    /// theme.
+    pub fn reset(&mut self, dark: bool) {
+        if dark {
+            self.dark.clear();
+        } else {
+            self.light.clear();
+        }
+    }
+}
+
+impl JsonFile for Theme {
+    const CONTENTS: &'static str = "theme";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::capture::ItemSource;
+    use crate::loader::load;
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(Condition::parse("Device", "5").unwrap(),
+                   Condition::Device(5));
+        assert_eq!(Condition::parse("Endpoint", "5.0x81").unwrap(),
+                   Condition::Endpoint(5, 0x81));
+        assert_eq!(Condition::parse("Transfer type", "Bulk").unwrap(),
+                   Condition::TransferType(String::from("bulk")));
+        assert!(Condition::parse("Device", "300").is_err());
+        assert!(Condition::parse("Endpoint", "5").is_err());
+        assert!(Condition::parse("Transfer type", "bulk || 1").is_err());
+        assert!(ColorRule::new(Condition::Device(1), "red").is_err());
+        assert!(ColorRule::new(
+            Condition::TransferType(String::from("sideways")),
+            "#FF0000").is_err());
+        assert!(ColorRule::new(
+            Condition::Expression(String::from("device ==")),
+            "#FF0000").is_err());
+        for kind in CONDITION_KINDS {
+            let value = match kind {
+                "Endpoint" => "1.0x02",
+                "Transfer type" => "bulk",
+                _ => "1",
+            };
+            let condition = Condition::parse(kind, value).unwrap();
+            assert_eq!(condition.kind(), kind);
+            assert_eq!(Condition::parse(kind, &condition.value()).unwrap(),
+                       condition);
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let mut rules = ColorRules::defaults(Palette::Standard);
+        rules.push(ColorRule::new(
+            Condition::Endpoint(5, 0x81), "#C3D8F6").unwrap());
+        rules.set_enabled(0, false);
+        rules.move_up(1);
+        let loaded = ColorRules::from_json(&rules.to_json().unwrap()).unwrap();
+        let summary: Vec<_> = loaded
+            .iter()
+            .map(|rule| (rule.condition.clone(),
+                         rule.color.as_str(),
+                         rule.enabled))
+            .collect();
+        assert_eq!(summary, vec![
+            (Condition::Endpoint(5, 0x81), "#C3D8F6", true),
+            (Condition::Error, "#F6C3C3", false),
+        ]);
+        assert_eq!(loaded.css(),
+            ".color-rule-0 { background-color: #C3D8F6; color: #000000; }\n\
+             .color-rule-1 { background-color: #F6C3C3; color: #000000; }\n");
+        assert!(ColorRules::from_json("{\"rules\": [{}]}").is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut rules = ColorRules::new();
+        rules.push(ColorRule::new(Condition::Device(11), "#FF0000").unwrap());
+        rules.push(ColorRule::new(
+            Condition::Expression(String::from("summary contains \"SOF\"")),
+            "#00FF00").unwrap());
+        let sof_groups: TrafficItem = capture.item(None, 0).unwrap();
+        let request: TrafficItem = capture.item(None, 1).unwrap();
+        assert_eq!(rules.find(&mut capture, &sof_groups).unwrap(), Some(1));
+        assert_eq!(rules.find(&mut capture, &request).unwrap(), Some(0));
+        assert!(!capture.item_error(&request).unwrap());
+
+        // A disabled rule no longer applies.
+        rules.set_enabled(0, false);
+        assert_eq!(rules.find(&mut capture, &request).unwrap(), None);
+    }
+    #[test]
+    fn test_theme() {
+        assert_eq!(rgb("#FF8000"), (1.0, 128.0 / 255.0, 0.0));
+        assert_eq!(text_color("#F6C3C3"), "#000000");
+        assert_eq!(text_color("#1E1E1E"), "#FFFFFF");
+
+        let mut theme = Theme::new();
+        assert_eq!(theme.color(Element::Highlight, false), "#FFE08A");
+        assert_eq!(theme.color(Element::Highlight, true), "#7A6200");
+        theme.set_color(Element::Highlight, true, Some("#404040")).unwrap();
+        theme.set_color(Element::PlotBars, false, Some("#00FF00")).unwrap();
+        assert!(theme.set_color(Element::Added, false, Some("green")).is_err());
+        assert_eq!(theme.color(Element::Highlight, true), "#404040");
+        assert_eq!(theme.color(Element::Highlight, false), "#FFE08A");
+        assert_eq!(Theme::from_json(&theme.to_json().unwrap()).unwrap(), theme);
+
+        theme.set_color(Element::PlotBars, false, None).unwrap();
+        theme.reset(true);
+        assert_eq!(theme, Theme::new());
+        let json = serde_json::to_value(Element::ALL).unwrap();
+        assert_eq!(json[3], "in");
+        assert_eq!(json[5], "plot-background");
+        assert!(Theme::from_json(r##"{"dark": {"nothing": "#000000"}}"##)
+            .is_err());
+    }
+
+    #[test]
+    fn test_palettes() {
+        let json = serde_json::to_value(Palette::ALL).unwrap();
+        assert_eq!(json[1], "high-contrast");
+        for palette in Palette::ALL {
+            let rule_colors = palette.rule_colors();
+            assert!(!rule_colors.contains(&palette.error_color()));
+            for color in rule_colors {
+                check_color(color).unwrap();
+            }
+            check_color(palette.error_color()).unwrap();
+            for element in Element::ALL {
+                check_color(palette.color(element, false)).unwrap();
+                check_color(palette.color(element, true)).unwrap();
+            }
+        }
+        assert_eq!(Palette::Standard.rule_color(7), "#C3F6C3");
+
+        // Colors chosen individually take precedence over the palette's.
+        let mut theme = Theme::new();
+        theme.set_palette(Palette::ColorblindSafe);
+        theme.set_color(Element::Added, false, Some("#00FF00")).unwrap();
+        assert_eq!(theme.color(Element::Removed, false), "#F5C18A");
+        assert_eq!(theme.color(Element::Added, false), "#00FF00");
+        assert_eq!(Theme::from_json(&theme.to_json().unwrap()).unwrap(), theme);
+        assert!(Theme::from_json(r#"{"palette": "sepia"}"#).is_err());
+        assert!(Theme::from_json(r#"{"light": {"added": "green"}}"#).is_err());
+
+        let mut rules = ColorRules::defaults(Palette::Standard);
+        rules.push(ColorRule::new(
+            Condition::Device(5), Palette::Standard.rule_color(1)).unwrap());
+        rules.push(ColorRule::new(Condition::Device(6), "#123456").unwrap());
+        rules.recolor(Palette::Standard, Palette::ColorblindSafe);
+        let colors: Vec<&str> = rules
+            .iter()
+            .map(|rule| rule.color.as_str())
+            .collect();
+        assert_eq!(colors, vec!["#E69F00", "#F0E442", "#123456"]);
+    }
+}
@@ -0,0 +1,589 @@
+//! Optional columns of the traffic view, and sorting items by them.
+//!
+//! The traffic view always shows the summary of each item, in a tree.
+//! Further columns may be added, each showing a value for every item.
+
+use anyhow::{Context, Error, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{
+    CaptureReader,
+    PacketId,
+    Timestamp,
+    TrafficItem,
+    TrafficItemId,
+};
+use crate::config::JsonFile;
+use crate::filter::{Filter, ItemField};
+use crate::frames::Frames;
+
+/// Names of the kinds of column, as shown in the column editor.
+pub const COLUMN_KINDS: [&str; 8] = [
+    "Timestamp",
+    "Delta time",
+    "Frame",
+    "Device",
+    "Endpoint",
+    "Length",
+    "Data",
+    "Custom",
+];
+
+/// Number of bytes shown in the data column.
+const DATA_PREVIEW_BYTES: usize = 8;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+const NS_PER_DAY: u64 = 86_400 * NS_PER_SEC;
+
+/// Ways of showing the time of each item in the timestamp column.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampMode {
+    /// Time of day, in UTC.
+    Absolute,
+    /// Seconds since the first packet of the capture.
+    Relative,
+    /// Seconds since the previous item shown at the same level.
+    #[serde(rename = "previous")]
+    SincePrevious,
+    /// Seconds since an item chosen by the user.
+    #[serde(rename = "reference")]
+    SinceReference,
+}
+
+impl TimestampMode {
+    /// All modes, in the order they are offered.
+    pub const ALL: [TimestampMode; 4] = [
+        TimestampMode::Absolute,
+        TimestampMode::Relative,
+        TimestampMode::SincePrevious,
+        TimestampMode::SinceReference,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        use TimestampMode::*;
+        match self {
+            Absolute => "Time of day (UTC)",
+            Relative => "Seconds since capture start",
+            SincePrevious => "Seconds since previous item",
+            SinceReference => "Seconds since reference item",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use TimestampMode::*;
+        match self {
+            Absolute => "absolute",
+            Relative => "relative",
+            SincePrevious => "previous",
+            SinceReference => "reference",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TimestampMode> {
+        TimestampMode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == name)
+    }
+}
+
+impl Default for TimestampMode {
+    fn default() -> Self {
+        TimestampMode::Relative
+    }
+}
+
+/// What the times of items are measured from.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TimeBase {
+    pub mode: TimestampMode,
+    /// Time of the reference item, if one has been chosen. Until then,
+    /// times are measured from the start of the capture.
+    pub reference: Option<Timestamp>,
+    /// The item shown before this one, at the same level of the view.
+    pub previous: Option<TrafficItem>,
+}
+
+/// Content of a custom column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CustomContent {
+    /// The value of a field, named as in filters.
+    Field(ItemField),
+    /// Whether the item matches a filter expression.
+    Expression(Filter),
+}
+
+/// A column of the traffic view.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "SavedColumn", into = "SavedColumn")]
+pub enum Column {
+    /// Time of the item's first packet, from the start of the capture.
+    Timestamp,
+    /// Time from the packet before the item's first packet.
+    Delta,
+    /// Number of the (micro)frame the item's first packet was sent in.
+    Frame,
+    /// Address of the device the item was sent to or from.
+    Device,
+    /// The endpoint the item was sent to or from.
+    Endpoint,
+    /// Length of the item's data, or of a packet.
+    Length,
+    /// The first bytes of the item's data, or of a packet.
+    Data,
+    /// A filter field or expression, as entered.
+    Custom(String, CustomContent),
+}
+
+/// A column as saved, by its kind and value.
+#[derive(Serialize, Deserialize)]
+struct SavedColumn {
+    kind: String,
+    #[serde(default)]
+    value: String,
+}
+
+impl TryFrom<SavedColumn> for Column {
+    type Error = Error;
+
+    fn try_from(saved: SavedColumn) -> Result<Column, Error> {
+        Column::parse(&saved.kind, &saved.value)
+    }
+}
+
+impl From<Column> for SavedColumn {
+    fn from(column: Column) -> SavedColumn {
+        SavedColumn {
+            kind: column.kind().to_string(),
+            value: column.value(),
+        }
+    }
+}
+
+/// The value shown in a column, and the key by which it sorts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub text: String,
+    pub key: SortKey,
+}
+
+/// Key by which a column's values sort. Numbers sort before text.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey {
+    Number(u64),
+    Text(String),
+}
+
+impl Cell {
+    fn number(number: u64) -> Cell {
+        Cell { text: number.to_string(), key: SortKey::Number(number) }
+    }
+
+    fn text(text: String) -> Cell {
+        Cell { key: SortKey::Text(text.clone()), text }
+    }
+
+    fn time(nanoseconds: u64) -> Cell {
+        Cell {
+            text: seconds(nanoseconds as i128),
+            key: SortKey::Number(nanoseconds),
+        }
+    }
+}
+
+/// Format a time difference in seconds, to the nanosecond.
+fn seconds(nanoseconds: i128) -> String {
+    let sign = if nanoseconds < 0 { "-" } else { "" };
+    let magnitude = nanoseconds.unsigned_abs();
+    let ns_per_sec = NS_PER_SEC as u128;
+    format!("{sign}{}.{:09}", magnitude / ns_per_sec, magnitude % ns_per_sec)
+}
+
+/// Format a timestamp as a time of day in UTC, as accepted by "go to".
+fn time_of_day(timestamp: Timestamp) -> String {
+    let time = timestamp % NS_PER_DAY;
+    let whole_seconds = time / NS_PER_SEC;
+    format!("{:02}:{:02}:{:02}.{:09}",
+            whole_seconds / 3600,
+            whole_seconds / 60 % 60,
+            whole_seconds % 60,
+            time % NS_PER_SEC)
+}
+
+impl Column {
+    /// Parse a column, given its kind and, for custom columns, a filter
+    /// field name or expression.
+    pub fn parse(kind: &str, value: &str) -> Result<Column, Error> {
+        let value = value.trim();
+        Ok(match kind {
+            "Timestamp" => Column::Timestamp,
+            "Delta time" => Column::Delta,
+            "Frame" => Column::Frame,
+            "Device" => Column::Device,
+            "Endpoint" => Column::Endpoint,
+            "Length" => Column::Length,
+            "Data" => Column::Data,
+            "Custom" => {
+                if value.is_empty() {
+                    bail!("Expected a filter field such as 'transfer.type', \
+                           or a filter expression")
+                }
+                let custom = match ItemField::from_name(value) {
+                    Some(field) => CustomContent::Field(field),
+                    None => CustomContent::Expression(Filter::parse(value)
+                        .with_context(|| format!(
+                            "Invalid column expression '{value}'"))?),
+                };
+                Column::Custom(value.to_string(), custom)
+            },
+            _ => bail!("Unknown column '{kind}'"),
+        })
+    }
+
+    /// The kind of this column.
+    pub fn kind(&self) -> &'static str {
+        use Column::*;
+        match self {
+            Timestamp => "Timestamp",
+            Delta => "Delta time",
+            Frame => "Frame",
+            Device => "Device",
+            Endpoint => "Endpoint",
+            Length => "Length",
+            Data => "Data",
+            Custom(..) => "Custom",
+        }
+    }
+
+    /// The value of this column, in the form accepted by `parse`.
+    pub fn value(&self) -> String {
+        match self {
+            Column::Custom(text, _) => text.clone(),
+            _ => String::new(),
+        }
+    }
+
+    /// The title shown at the head of this column.
+    pub fn title(&self) -> String {
+        match self {
+            Column::Custom(text, _) => text.clone(),
+            _ => self.kind().to_string(),
+        }
+    }
+
+    /// The value of this column for a traffic item.
+    ///
+    /// Timestamps are shown as set by the time base, but always sort in
+    /// the order of the capture.
+    pub fn cell(&self,
+                capture: &mut CaptureReader,
+                item: &TrafficItem,
+                base: &TimeBase)
+        -> Result<Cell, Error>
+    {
+        use Column::*;
+        Ok(match self {
+            Timestamp => {
+                let time = capture.item_timestamp(item)?;
+                let start = capture.packet_time(PacketId::from(0))?;
+                let since = |origin: u64| seconds(time as i128 - origin as i128);
+                let text = match (base.mode, base.previous, base.reference) {
+                    (TimestampMode::Absolute, ..) => time_of_day(time),
+                    (TimestampMode::SincePrevious, Some(previous), _) =>
+                        since(capture.item_timestamp(&previous)?),
+                    (TimestampMode::SincePrevious, None, _) => since(time),
+                    (TimestampMode::SinceReference, _, Some(reference)) =>
+                        since(reference),
+                    _ => since(start),
+                };
+                Cell { text, key: SortKey::Number(time) }
+            },
+            Delta => {
+                let packet_id = capture.item_first_packet(item)?;
+                if packet_id.value == 0 {
+                    Cell::time(0)
+                } else {
+                    let time = capture.packet_time(packet_id)?;
+                    let previous = capture.packet_time(packet_id - 1)?;
+                    Cell::time(time.saturating_sub(previous))
+                }
+            },
+            Frame => {
+                let packet_id = capture.item_first_packet(item)?;
+                let text = Frames::new(capture)
+                    .number_at(packet_id)?
+                    .map_or_else(String::new, |number| number.to_string());
+                Cell { text, key: SortKey::Number(packet_id.value) }
+            },
+            Device => Cell::number(
+                capture.item_endpoint(item)?.device_address().0 as u64),
+            Endpoint => {
+                let endpoint = capture.item_endpoint(item)?;
+                let device = endpoint.device_address().0 as u64;
+                let address = endpoint.address().0 as u64;
+                Cell {
+                    text: endpoint.to_string(),
+                    key: SortKey::Number(device << 8 | address),
+                }
+            },
+            Length => Cell::number(capture.item_length(item)?),
+            Data => {
+                let data = capture.item_data(item)?;
+                let mut text: Vec<String> = data
+                    .iter()
+                    .take(DATA_PREVIEW_BYTES)
+                    .map(|byte| format!("{byte:02X}"))
+                    .collect();
+                if data.len() > DATA_PREVIEW_BYTES {
+                    text.push(String::from("…"));
+                }
+                Cell::text(text.join(" "))
+            },
+            Custom(_, CustomContent::Field(field)) => {
+                let (text, number) = field.value(capture, item)?;
+                match number {
+                    Some(number) => Cell { text, key: SortKey::Number(number) },
+                    None => Cell::text(text),
+                }
+            },
+            Custom(_, CustomContent::Expression(filter)) => {
+                if filter.matches(capture, item)? {
+                    Cell { text: String::from("✓"), key: SortKey::Number(1) }
+                } else {
+                    Cell { text: String::new(), key: SortKey::Number(0) }
+                }
+            },
+        })
+    }
+
+    /// Whether items can be sorted by this column.
+    ///
+    /// Data can be too long to compare quickly, so is not sorted.
+    pub fn sortable(&self) -> bool {
+        !matches!(self, Column::Data)
+    }
+}
+
+/// Order the top-level items of a capture by their values in a column,
+/// keeping the capture order of items with equal values.
+///
+/// If a filter is given, only the items it matches are included.
+pub fn sort_items(capture: &mut CaptureReader,
+                  filter: Option<&Filter>,
+                  column: &Column,
+                  descending: bool)
+    -> Result<Vec<TrafficItemId>, Error>
+{
+    if !column.sortable() {
+        bail!("Cannot sort by {}", column.title())
+    }
+    let mut keyed = Vec::new();
+    for index in 0..capture.item_index.len() {
+        let item_id = TrafficItemId::from(index);
+        let item = TrafficItem::Transfer(capture.item_index.get(item_id)?);
+        if let Some(filter) = filter {
+            if !filter.matches(capture, &item)? {
+                continue;
+            }
+        }
+        let cell = column.cell(capture, &item, &TimeBase::default())?;
+        keyed.push((cell.key, item_id));
+    }
+    keyed.sort_by(|(a, _), (b, _)| {
+        let order = a.cmp(b);
+        if descending { order.reverse() } else { order }
+    });
+    Ok(keyed.into_iter().map(|(_, item_id)| item_id).collect())
+}
+
+/// An ordered list of columns.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Columns {
+    columns: Vec<Column>,
+    /// How the timestamp column shows times.
+    #[serde(rename = "timestamps", default)]
+    pub timestamp_mode: TimestampMode,
+}
+
+impl Columns {
+    pub fn new() -> Self {
+        Columns::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Column> {
+        self.columns.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Column> {
+        self.columns.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Add a column, after the existing columns.
+    pub fn push(&mut self, column: Column) {
+        self.columns.push(column);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.columns.len() {
+            self.columns.remove(index);
+        }
+    }
+
+    /// Move a column one place to the left.
+    pub fn move_left(&mut self, index: usize) {
+        if index > 0 && index < self.columns.len() {
+            self.columns.swap(index - 1, index);
+        }
+    }
+}
+
+impl JsonFile for Columns {
+    const CONTENTS: &'static str = "columns";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(Column::parse("Length", "").unwrap(), Column::Length);
+        assert!(Column::parse("Custom", "").is_err());
+        assert!(Column::parse("Custom", "length >").is_err());
+        assert!(Column::parse("Sideways", "").is_err());
+        let mut columns = Columns::new();
+        columns.push(Column::Timestamp);
+        columns.push(Column::parse("Custom", "transfer.type").unwrap());
+        columns.push(Column::parse("Custom", "device == 11").unwrap());
+        columns.move_left(2);
+        columns.timestamp_mode = TimestampMode::SinceReference;
+        assert_eq!(columns.get(1).unwrap().title(), "device == 11");
+        let restored = Columns::from_json(&columns.to_json().unwrap()).unwrap();
+        assert_eq!(restored, columns);
+    }
+
+    #[test]
+    fn test_cells() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let first = TrafficItem::Transfer(
+            capture.item_index.get(TrafficItemId::from(0)).unwrap());
+        let base = TimeBase::default();
+        let cell = Column::Timestamp.cell(&mut capture, &first, &base)
+            .unwrap();
+        assert_eq!(cell.text, "0.000000000");
+        let cell = Column::Delta.cell(&mut capture, &first, &base).unwrap();
+        assert_eq!(cell.key, SortKey::Number(0));
+        let cell = Column::Frame.cell(&mut capture, &first, &base).unwrap();
+        assert_eq!(cell.text, "186.0");
+
+        let column = Column::parse("Custom", "transfer.type").unwrap();
+        let mut types = Vec::new();
+        for index in 0..capture.item_index.len() {
+            let item = TrafficItem::Transfer(
+                capture.item_index.get(TrafficItemId::from(index)).unwrap());
+            types.push(column.cell(&mut capture, &item, &base).unwrap().text);
+        }
+        assert!(types.iter().any(|text| text == "Control"));
+    }
+
+    #[test]
+    fn test_timestamp_modes() {
+        assert_eq!(seconds(-1500), "-0.000001500");
+        assert_eq!(seconds(61_000_000_001), "61.000000001");
+        assert_eq!(time_of_day(NS_PER_DAY * 3 + 3_723_000_000_042),
+                   "01:02:03.000000042");
+        for mode in TimestampMode::ALL {
+            assert_eq!(TimestampMode::from_name(mode.name()), Some(mode));
+        }
+
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut items = Vec::new();
+        for index in 0..2 {
+            items.push(TrafficItem::Transfer(
+                capture.item_index.get(TrafficItemId::from(index)).unwrap()));
+        }
+        let mut text = |item: &TrafficItem, base: TimeBase|
+            Column::Timestamp.cell(&mut capture, item, &base).unwrap().text;
+        let relative = text(&items[1], TimeBase::default());
+        let since_previous = text(&items[1], TimeBase {
+            mode: TimestampMode::SincePrevious,
+            previous: Some(items[0]),
+            ..TimeBase::default()
+        });
+        // The first item is at the start of the capture.
+        assert_eq!(since_previous, relative);
+        let absolute = text(&items[1], TimeBase {
+            mode: TimestampMode::Absolute,
+            ..TimeBase::default()
+        });
+        assert_eq!(absolute.len(), 18);
+        let second_time = capture.item_timestamp(&items[1]).unwrap();
+        let since_reference = Column::Timestamp.cell(
+            &mut capture, &items[1], &TimeBase {
+                mode: TimestampMode::SinceReference,
+                reference: Some(second_time),
+                ..TimeBase::default()
+            }).unwrap();
+        assert_eq!(since_reference.text, "0.000000000");
+        assert_eq!(since_reference.key, SortKey::Number(second_time));
+    }
+
+    #[test]
+    fn test_sort() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let count = capture.item_index.len() as usize;
+        let order = sort_items(&mut capture, None, &Column::Length, true)
+            .unwrap();
+        assert_eq!(order.len(), count);
+        let mut lengths = Vec::new();
+        for item_id in &order {
+            let item = TrafficItem::Transfer(
+                capture.item_index.get(*item_id).unwrap());
+            lengths.push(capture.item_length(&item).unwrap());
+        }
+        assert!(lengths.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        // Items with the same value stay in capture order.
+        let order = sort_items(&mut capture, None, &Column::Device, false)
+            .unwrap();
+        let device_zero: Vec<_> = order
+            .iter()
+            .take_while(|item_id| {
+                let item = TrafficItem::Transfer(
+                    capture.item_index.get(**item_id).unwrap());
+                Column::Device.cell(&mut capture, &item, &TimeBase::default())
+                    .unwrap().key ==
+                    SortKey::Number(0)
+            })
+            .collect();
+        assert!(device_zero.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let filter = Filter::parse("device == 11").unwrap();
+        let filtered = sort_items(
+            &mut capture, Some(&filter), &Column::Timestamp, false).unwrap();
+        assert!(!filtered.is_empty());
+        assert!(filtered.len() < count);
+        let mut times = Vec::new();
+        for item_id in &filtered {
+            let item = TrafficItem::Transfer(
+                capture.item_index.get(*item_id).unwrap());
+            assert!(filter.matches(&mut capture, &item).unwrap());
+            times.push(capture.item_timestamp(&item).unwrap());
+        }
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(sort_items(&mut capture, None, &Column::Data, false).is_err());
+    }
+}
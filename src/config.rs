@@ -0,0 +1,44 @@
+//! Settings saved between runs as JSON files.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Settings which are saved to, and loaded from, a JSON file.
+pub trait JsonFile: Serialize + DeserializeOwned {
+    /// What the file holds, as described in error messages.
+    const CONTENTS: &'static str;
+
+    /// Load settings saved in JSON format.
+    fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path).with_context(|| format!(
+            "Failed to read {} from {}", Self::CONTENTS, path.display()))?;
+        Self::from_json(&text).with_context(|| format!(
+            "Failed to load {} from {}", Self::CONTENTS, path.display()))
+    }
+
+    /// Save settings in JSON format, creating their directory if needed.
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!(
+                "Failed to create directory {}", dir.display()))?;
+        }
+        fs::write(path, self.to_json()?).with_context(|| format!(
+            "Failed to save {} to {}", Self::CONTENTS, path.display()))
+    }
+
+    fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    fn from_json(text: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+/// Default for a saved rule's `enabled` flag, as rules are added enabled.
+pub fn enabled_by_default() -> bool {
+    true
+}
@@ -0,0 +1,123 @@
+//! Formatting of traffic data for copying into source code.
+
+/// Number of bytes on each line of a copied array.
+const BYTES_PER_LINE: usize = 12;
+
+/// Formats in which data may be copied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CopyFormat {
+    /// Hex bytes separated by spaces, e.g. `55 AA`.
+    Hex,
+    /// A C array initializer.
+    CArray,
+    /// A Rust array constant.
+    RustArray,
+    /// A Python `bytes` literal.
+    PythonBytes,
+}
+
+impl CopyFormat {
+    /// All formats, in the order they are offered.
+    pub const ALL: [CopyFormat; 4] = [
+        CopyFormat::Hex,
+        CopyFormat::CArray,
+        CopyFormat::RustArray,
+        CopyFormat::PythonBytes,
+    ];
+
+    /// Name used to identify this format in actions.
+    pub fn name(&self) -> &'static str {
+        use CopyFormat::*;
+        match self {
+            Hex => "hex",
+            CArray => "c",
+            RustArray => "rust",
+            PythonBytes => "python",
+        }
+    }
+
+    /// Description of this format, as shown in menus.
+    pub fn label(&self) -> &'static str {
+        use CopyFormat::*;
+        match self {
+            Hex => "Copy as hex",
+            CArray => "Copy as C array",
+            RustArray => "Copy as Rust array",
+            PythonBytes => "Copy as Python bytes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CopyFormat> {
+        CopyFormat::ALL
+            .iter()
+            .find(|format| format.name() == name)
+            .copied()
+    }
+
+    /// Format data for copying.
+    pub fn format(&self, data: &[u8]) -> String {
+        use CopyFormat::*;
+        match self {
+            Hex => data
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            CArray => format!(
+                "const uint8_t data[{}] = {{\n{}}};\n",
+                data.len(), array_lines(data)),
+            RustArray => format!(
+                "const DATA: [u8; {}] = [\n{}];\n",
+                data.len(), array_lines(data)),
+            PythonBytes => {
+                let mut text = String::from("b\"");
+                for byte in data {
+                    text.push_str(&format!("\\x{byte:02x}"));
+                }
+                text.push('"');
+                text
+            },
+        }
+    }
+}
+
+/// Indented lines of comma-separated hex bytes.
+fn array_lines(data: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(BYTES_PER_LINE) {
+        text.push_str("    ");
+        let bytes: Vec<String> = chunk
+            .iter()
+            .map(|byte| format!("0x{byte:02X},"))
+            .collect();
+        text.push_str(&bytes.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let data = [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x40, 0x00,
+                    0x55, 0xAA, 0x01, 0x02, 0x03];
+        assert_eq!(CopyFormat::Hex.format(&data[..3]), "80 06 00");
+        assert_eq!(CopyFormat::CArray.format(&data),
+            "const uint8_t data[13] = {\n    \
+             0x80, 0x06, 0x00, 0x01, 0x00, 0x00, \
+             0x40, 0x00, 0x55, 0xAA, 0x01, 0x02,\n    \
+             0x03,\n};\n");
+        assert_eq!(CopyFormat::RustArray.format(&data[..2]),
+            "const DATA: [u8; 2] = [\n    0x80, 0x06,\n];\n");
+        assert_eq!(CopyFormat::PythonBytes.format(&data[8..10]),
+            "b\"\\x55\\xaa\"");
+        assert_eq!(CopyFormat::RustArray.format(&[]),
+            "const DATA: [u8; 0] = [\n];\n");
+        for format in CopyFormat::ALL {
+            assert_eq!(CopyFormat::from_name(format.name()), Some(format));
+        }
+    }
+}
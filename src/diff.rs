@@ -0,0 +1,306 @@
+//! Structural comparison of the traffic in two captures.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointType,
+    TrafficItem,
+    TrafficItemId,
+};
+use crate::usb::{
+    ControlResult,
+    ControlTransfer,
+    RequestType,
+    StandardRequest,
+};
+
+/// Maximum number of steps compared from each capture.
+pub const MAX_STEPS: usize = 2000;
+
+/// A step in the traffic of a capture: a control transfer, or a run of
+/// transfers of another type on one endpoint.
+#[derive(Clone, Debug)]
+pub struct Step {
+    /// The first transfer in the step.
+    pub item: TrafficItem,
+    /// Description used to match steps between captures.
+    ///
+    /// Devices are numbered in the order they were addressed, so that
+    /// steps match even if the host assigned different addresses.
+    pub key: String,
+    /// Description of the step, as shown.
+    pub summary: String,
+    /// What was transferred, compared between matching steps.
+    pub content: String,
+}
+
+/// How a step in one capture relates to those in the other.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Matching steps, with the same content.
+    Same(usize, usize),
+    /// Matching steps, with different content.
+    Changed(usize, usize),
+    /// A step only in the first capture.
+    Removed(usize),
+    /// A step only in the second capture.
+    Added(usize),
+}
+
+/// The result of comparing two captures.
+#[derive(Clone, Debug, Default)]
+pub struct Comparison {
+    pub left: Vec<Step>,
+    pub right: Vec<Step>,
+    pub changes: Vec<Change>,
+    /// Whether either capture had more than `MAX_STEPS` steps.
+    pub truncated: bool,
+}
+
+impl Comparison {
+    pub fn new(left: &mut CaptureReader, right: &mut CaptureReader)
+        -> Result<Comparison, Error>
+    {
+        let (left, left_truncated) = steps(left)?;
+        let (right, right_truncated) = steps(right)?;
+        let left_keys: Vec<&str> =
+            left.iter().map(|step| step.key.as_str()).collect();
+        let right_keys: Vec<&str> =
+            right.iter().map(|step| step.key.as_str()).collect();
+        let changes = align(&left_keys, &right_keys)
+            .into_iter()
+            .map(|pair| match pair {
+                (Some(i), Some(j)) if left[i].content == right[j].content =>
+                    Change::Same(i, j),
+                (Some(i), Some(j)) => Change::Changed(i, j),
+                (Some(i), None) => Change::Removed(i),
+                (None, Some(j)) => Change::Added(j),
+                (None, None) => unreachable!(),
+            })
+            .collect();
+        Ok(Comparison {
+            left,
+            right,
+            changes,
+            truncated: left_truncated || right_truncated,
+        })
+    }
+
+    /// Whether the two captures have no differences.
+    pub fn identical(&self) -> bool {
+        self.changes
+            .iter()
+            .all(|change| matches!(change, Change::Same(..)))
+    }
+}
+
+/// Device addresses, numbered in the order they were first seen.
+#[derive(Default)]
+struct DeviceNumbers(Vec<u8>);
+
+impl DeviceNumbers {
+    fn number(&mut self, address: u8) -> u8 {
+        // The default address is used by every device during enumeration.
+        if address == 0 {
+            return 0;
+        }
+        let index = match self.0.iter().position(|a| *a == address) {
+            Some(index) => index,
+            None => {
+                self.0.push(address);
+                self.0.len() - 1
+            }
+        };
+        (index + 1) as u8
+    }
+}
+
+/// Divide the traffic in a capture into steps.
+fn steps(capture: &mut CaptureReader) -> Result<(Vec<Step>, bool), Error> {
+    let mut steps: Vec<Step> = Vec::new();
+    let mut numbers = DeviceNumbers::default();
+    let mut run_length = 0;
+    let mut run_bytes = 0;
+    for index in 0..capture.item_index.len() {
+        let transfer_id = capture.item_index.get(TrafficItemId::from(index))?;
+        let entry = capture.transfer_index.get(transfer_id)?;
+        if !entry.is_start() {
+            continue;
+        }
+        let item = TrafficItem::Transfer(transfer_id);
+        let step = match capture.item_control_transfer(&item)? {
+            Some(transfer) => control_step(item, transfer, &mut numbers),
+            None => {
+                let endpoint = capture.endpoints.get(entry.endpoint_id())?;
+                let (ep_type, _) = capture
+                    .device_data(&endpoint.device_id())?
+                    .endpoint_details(endpoint.address());
+                if !matches!(ep_type, EndpointType::Normal(_)) {
+                    // SOFs and invalid packets are not compared.
+                    continue;
+                }
+                let number = numbers.number(endpoint.device_address().0);
+                let bytes = capture.item_length(&item)?;
+                let key = format!("{ep_type} transfers on endpoint {}.{} {}",
+                    number, endpoint.number(), endpoint.direction());
+                // Consecutive transfers on the same endpoint form one step.
+                if let Some(last) = steps.last_mut() {
+                    if last.key == key {
+                        run_length += 1;
+                        run_bytes += bytes;
+                        last.summary = format!("{run_length} {ep_type} \
+                            transfers on endpoint {endpoint}");
+                        last.content = format!(
+                            "transfers: {run_length}, bytes: {run_bytes}");
+                        continue;
+                    }
+                }
+                run_length = 1;
+                run_bytes = bytes;
+                Step {
+                    item,
+                    key,
+                    summary: format!("1 {ep_type} transfer on endpoint \
+                        {endpoint}"),
+                    content: format!("transfers: 1, bytes: {bytes}"),
+                }
+            },
+        };
+        if steps.len() == MAX_STEPS {
+            return Ok((steps, true));
+        }
+        steps.push(step);
+    }
+    Ok((steps, false))
+}
+
+fn control_step(item: TrafficItem,
+                transfer: ControlTransfer,
+                numbers: &mut DeviceNumbers)
+    -> Step
+{
+    let summary = transfer.summary();
+    let mut fields = transfer.fields;
+    let setting_address =
+        matches!(fields.type_fields.request_type(), RequestType::Standard) &&
+        matches!(StandardRequest::from(fields.request),
+                 StandardRequest::SetAddress);
+    if setting_address {
+        fields.value = numbers.number(fields.value as u8) as u16;
+    }
+    let normalized = ControlTransfer {
+        address: numbers.number(transfer.address.0).into(),
+        fields,
+        data: Vec::new(),
+        result: ControlResult::Completed,
+    };
+    let result = match transfer.result {
+        ControlResult::Completed => "completed",
+        ControlResult::Incomplete => "incomplete",
+        ControlResult::Stalled => "stalled",
+        ControlResult::Aborted => "aborted",
+    };
+    let data: Vec<String> = transfer.data
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect();
+    Step {
+        item,
+        key: normalized.summary(),
+        summary,
+        content: format!("{result}: {}", data.join(" ")),
+    }
+}
+
+/// Align two sequences, pairing up as many equal elements as possible
+/// whilst keeping their order.
+fn align<T: PartialEq>(left: &[T], right: &[T])
+    -> Vec<(Option<usize>, Option<usize>)>
+{
+    // Length of the longest common subsequence of each pair of suffixes.
+    let (n, m) = (left.len(), right.len());
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[at(i, j)] = if left[i] == right[j] {
+                lengths[at(i + 1, j + 1)] + 1
+            } else {
+                lengths[at(i + 1, j)].max(lengths[at(i, j + 1)])
+            };
+        }
+    }
+    let mut pairs = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && left[i] == right[j] {
+            pairs.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        } else if j == m ||
+            (i < n && lengths[at(i + 1, j)] >= lengths[at(i, j + 1)])
+        {
+            pairs.push((Some(i), None));
+            i += 1;
+        } else {
+            pairs.push((None, Some(j)));
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_align() {
+        let pairs = align(&["a", "b", "c", "d"], &["a", "c", "x", "d"]);
+        assert_eq!(pairs, [
+            (Some(0), Some(0)),
+            (Some(1), None),
+            (Some(2), Some(1)),
+            (None, Some(2)),
+            (Some(3), Some(3)),
+        ]);
+        assert_eq!(align::<u8>(&[], &[1]), [(None, Some(0))]);
+    }
+
+    #[test]
+    fn test_device_numbers() {
+        let mut numbers = DeviceNumbers::default();
+        assert_eq!(numbers.number(0), 0);
+        assert_eq!(numbers.number(11), 1);
+        assert_eq!(numbers.number(5), 2);
+        assert_eq!(numbers.number(11), 1);
+    }
+
+    #[test]
+    fn test_compare() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut left = load(std::slice::from_ref(&path)).unwrap();
+        let mut right = load(&[path]).unwrap();
+        let same = Comparison::new(&mut left, &mut right).unwrap();
+        assert!(same.identical());
+        assert!(!same.left.is_empty());
+        assert!(same.left.iter().any(|step|
+            step.key.contains("for device 1")));
+
+        let path = PathBuf::from("./tests/hackrf-connect/capture.pcap");
+        let mut other = load(&[path]).unwrap();
+        let different = Comparison::new(&mut left, &mut other).unwrap();
+        assert!(!different.identical());
+        for change in &different.changes {
+            match *change {
+                Change::Same(i, j) | Change::Changed(i, j) =>
+                    assert_eq!(different.left[i].key, different.right[j].key),
+                Change::Removed(i) => assert!(i < different.left.len()),
+                Change::Added(j) => assert!(j < different.right.len()),
+            }
+        }
+    }
+}
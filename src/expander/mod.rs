@@ -5,13 +5,17 @@ use gtk::{
     self,
     prelude::*,
     subclass::prelude::*,
+    accessible::{Property, State},
     glib::{self, SignalHandlerId},
     pango::EllipsizeMode,
+    AccessibleRole,
     Expander,
     Label,
     Orientation,
 };
 
+use crate::i18n::tr;
+
 glib::wrapper! {
     pub struct ExpanderWrapper(ObjectSubclass<imp::ExpanderWrapper>)
     @extends gtk::Box, gtk::Widget,
@@ -20,13 +24,31 @@ glib::wrapper! {
 
 impl ExpanderWrapper {
     pub fn new() -> ExpanderWrapper {
-        let wrapper: ExpanderWrapper = glib::Object::new::<ExpanderWrapper>();
+        // Screen readers see each row as an item in a tree, named by its
+        // summary. The connecting lines are only decoration.
+        let wrapper: ExpanderWrapper = glib::Object::builder()
+            .property("accessible-role", AccessibleRole::TreeItem)
+            .build();
         wrapper.imp().text_label.replace(
             Label::builder()
                 .ellipsize(EllipsizeMode::End)
                 .build());
-        wrapper.imp().conn_label.replace(Label::new(None));
-        wrapper.imp().expander.replace(Expander::new(None));
+        wrapper.imp().conn_label.replace(
+            Label::builder()
+                .accessible_role(AccessibleRole::Presentation)
+                .build());
+        let expander = Expander::new(None);
+        expander.update_property(&[Property::Label(&tr("Expand"))]);
+        let weak = wrapper.downgrade();
+        expander.connect_expanded_notify(move |expander| {
+            if let Some(wrapper) = weak.upgrade() {
+                if expander.is_visible() {
+                    wrapper.update_state(
+                        &[State::Expanded(Some(expander.is_expanded()))]);
+                }
+            }
+        });
+        wrapper.imp().expander.replace(expander);
         wrapper.append(&wrapper.imp().conn_label.borrow().clone());
         wrapper.append(&wrapper.imp().expander.borrow().clone());
         wrapper.append(&wrapper.imp().text_label.borrow().clone());
@@ -48,9 +70,35 @@ impl ExpanderWrapper {
     }
 
     pub fn set_text(&self, text: String) {
+        self.update_property(&[Property::Label(&text)]);
         self.imp().text_label.borrow_mut().set_text(&text);
     }
 
+    /// Show or hide the expander, and report whether the item can be
+    /// expanded, and is, to assistive technologies.
+    pub fn set_expandable(&self, expandable: bool, expanded: bool) {
+        let expander = self.imp().expander.borrow().clone();
+        expander.set_visible(expandable);
+        expander.set_expanded(expanded);
+        self.update_state(&[State::Expanded(
+            if expandable { Some(expanded) } else { None })]);
+    }
+
+    /// Report the depth of the item in the tree, where 1 is the top level.
+    pub fn set_level(&self, level: usize) {
+        self.update_property(&[Property::Level(level as i32)]);
+    }
+
+    pub fn set_style(&self, style: Option<String>) {
+        if let Some(class) = self.imp().style.take() {
+            self.remove_css_class(&class);
+        }
+        if let Some(class) = &style {
+            self.add_css_class(class);
+        }
+        self.imp().style.replace(style);
+    }
+
     pub fn set_connectors(&self, connectors: String) {
         self.imp().conn_label.borrow_mut().set_markup(
                 format!("<tt>{connectors}</tt>").as_str());
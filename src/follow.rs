@@ -0,0 +1,180 @@
+//! Following the data stream on an endpoint.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    Timestamp,
+    TrafficItem,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+use crate::usb::Direction;
+
+/// Data carried in one transaction.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub direction: Direction,
+    pub timestamp: Timestamp,
+    pub data: Vec<u8>,
+}
+
+/// All the data carried on an endpoint, in both directions.
+#[derive(Clone, Debug, Default)]
+pub struct FollowedStream {
+    /// Description of the endpoint, e.g. `5.1`.
+    pub name: String,
+    pub chunks: Vec<Chunk>,
+}
+
+impl FollowedStream {
+    /// Collect the data on the endpoint of a traffic item.
+    ///
+    /// Data sent in the other direction on the same endpoint number of the
+    /// device is included, in the order it was sent. On control endpoints,
+    /// only the data stages of requests are included.
+    pub fn new(capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<FollowedStream, Error>
+    {
+        use TrafficItem::*;
+        let transfer_id = match item {
+            Transfer(transfer_id) |
+            Transaction(transfer_id, _) |
+            Packet(transfer_id, ..) => *transfer_id,
+        };
+        let endpoint_id = capture.transfer_index.get(transfer_id)?.endpoint_id();
+        if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+            bail!("The selected item is not on a device endpoint")
+        }
+        let endpoint = capture.endpoints.get(endpoint_id)?;
+        let mut payloads = Vec::new();
+        for id in 0..capture.endpoints.len() {
+            let other_id = EndpointId::from(id);
+            let other = capture.endpoints.get(other_id)?;
+            if other.device_id() == endpoint.device_id() &&
+                other.number() == endpoint.number() &&
+                other_id != INVALID_EP_ID &&
+                other_id != FRAMING_EP_ID
+            {
+                payloads.extend(capture.endpoint_payloads(other_id)?);
+            }
+        }
+        payloads.sort_by_key(|(transaction_id, ..)| *transaction_id);
+        let mut chunks = Vec::with_capacity(payloads.len());
+        for (transaction_id, direction, data) in payloads {
+            let packet_id = capture.transaction_index.get(transaction_id)?;
+            let timestamp = capture.packet_time(packet_id)?;
+            chunks.push(Chunk { direction, timestamp, data });
+        }
+        Ok(FollowedStream {
+            name: format!("{}.{}",
+                          endpoint.device_address(), endpoint.number()),
+            chunks,
+        })
+    }
+
+    /// The chunks sent in a direction, or in both if none is given.
+    pub fn chunks(&self, direction: Option<Direction>)
+        -> impl Iterator<Item=&Chunk>
+    {
+        self.chunks.iter().filter(move |chunk|
+            direction.map_or(true, |direction|
+                chunk.direction as u8 == direction as u8))
+    }
+
+    /// Total number of bytes sent in a direction, or in both.
+    pub fn total(&self, direction: Option<Direction>) -> u64 {
+        self.chunks(direction)
+            .map(|chunk| chunk.data.len() as u64)
+            .sum()
+    }
+
+    /// Write the data sent in a direction, or in both, to a file.
+    pub fn save(&self, path: &Path, direction: Option<Direction>)
+        -> Result<(), Error>
+    {
+        let file = File::create(path).with_context(|| format!(
+            "Failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        for chunk in self.chunks(direction) {
+            writer.write_all(&chunk.data)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Chunk {
+    /// The data as text, with unprintable characters shown as dots.
+    pub fn text(&self) -> String {
+        self.data
+            .iter()
+            .map(|byte| match byte {
+                b'\n' | b'\t' | 0x20..=0x7E => *byte as char,
+                _ => '.',
+            })
+            .collect()
+    }
+
+    /// The data as hex bytes, sixteen to a line.
+    pub fn hex(&self) -> String {
+        let mut text = String::new();
+        for line in self.data.chunks(16) {
+            let bytes: Vec<String> = line
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect();
+            text.push_str(&bytes.join(" "));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::capture::ItemSource;
+    use crate::loader::load;
+
+    #[test]
+    fn test_follow() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        // Getting the device descriptor for device 11, on endpoint 0.
+        let request: TrafficItem = capture.item(None, 1).unwrap();
+        let stream = FollowedStream::new(&mut capture, &request).unwrap();
+        assert_eq!(stream.name, "11.0");
+
+        // The stream starts with the device descriptor that was read.
+        let first = &stream.chunks[0];
+        assert_eq!(first.direction as u8, Direction::In as u8);
+        assert_eq!(first.data[..2], [18, 1]);
+        assert_eq!(stream.total(None),
+                   stream.total(Some(Direction::In)) +
+                   stream.total(Some(Direction::Out)));
+        assert!(stream.chunks.windows(2).all(|pair|
+            pair[0].timestamp <= pair[1].timestamp));
+
+        // SOFs are not on a device endpoint.
+        let sof_groups: TrafficItem = capture.item(None, 0).unwrap();
+        assert!(FollowedStream::new(&mut capture, &sof_groups).is_err());
+    }
+
+    #[test]
+    fn test_formats() {
+        let chunk = Chunk {
+            direction: Direction::In,
+            timestamp: 0,
+            data: b"OK\r\n\x00".to_vec(),
+        };
+        assert_eq!(chunk.text(), "OK.\n.");
+        assert_eq!(chunk.hex(), "4F 4B 0D 0A 00\n");
+    }
+}
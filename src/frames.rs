@@ -0,0 +1,359 @@
+//! Bus frames, as numbered by the SOF packets in a capture.
+//!
+//! At full speed, a host sends a SOF packet at the start of each 1ms
+//! frame, carrying an 11-bit frame number. At high speed, it sends one in
+//! each of the eight 125µs microframes, all with the number of the frame.
+//! Traffic is placed in the frame of the last SOF before it.
+
+use std::fmt;
+
+use anyhow::{Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    TransactionId,
+    FRAMING_EP_ID,
+};
+use crate::usb::{PacketFields, PID};
+
+/// Frame numbers wrap around after this many frames.
+const FRAME_COUNT: u16 = 0x800;
+
+/// Number of microframes in a frame at high speed.
+const MICROFRAMES: u8 = 8;
+
+/// A frame, and the microframe within it at high speed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameNumber {
+    pub frame: u16,
+    pub microframe: Option<u8>,
+}
+
+impl fmt::Display for FrameNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.microframe {
+            Some(microframe) => write!(f, "{}.{microframe}", self.frame),
+            None => write!(f, "{}", self.frame),
+        }
+    }
+}
+
+/// A frame begun by a SOF, and the transactions sent during it.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub number: FrameNumber,
+    /// The group of SOFs containing the frame's SOF.
+    pub group: u64,
+    /// The SOF packet.
+    pub sof: PacketId,
+    /// The transaction of the SOF group.
+    pub transaction: TransactionId,
+    /// Time of the SOF.
+    pub time: Timestamp,
+    /// The other transactions sent before the next SOF.
+    pub transactions: Vec<TransactionId>,
+    /// Frame numbers skipped between the previous SOF and this one.
+    pub missed: u16,
+}
+
+impl Frame {
+    /// The first and last frame numbers skipped before this frame, if any.
+    pub fn missing(&self) -> Option<(u16, u16)> {
+        if self.missed == 0 {
+            return None;
+        }
+        let first = (self.number.frame + FRAME_COUNT - self.missed) %
+            FRAME_COUNT;
+        let last = (self.number.frame + FRAME_COUNT - 1) % FRAME_COUNT;
+        Some((first, last))
+    }
+}
+
+/// The SOFs of a capture, from which frames are found.
+///
+/// Consecutive SOFs are decoded as one transaction, a SOF group, so each
+/// transaction on the framing endpoint may begin several frames.
+pub struct Frames<'c> {
+    capture: &'c mut CaptureReader,
+}
+
+impl<'c> Frames<'c> {
+    pub fn new(capture: &'c mut CaptureReader) -> Frames<'c> {
+        Frames { capture }
+    }
+
+    /// Number of SOF groups in the capture.
+    pub fn group_count(&mut self) -> Result<u64, Error> {
+        Ok(self.capture
+            .endpoint_traffic(FRAMING_EP_ID)?
+            .transaction_ids
+            .len())
+    }
+
+    /// The SOF group containing a packet, or the last one before it.
+    pub fn group_at(&mut self, packet_id: PacketId)
+        -> Result<Option<u64>, Error>
+    {
+        let transaction_id = match self.transaction_at(packet_id)? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        // Count the SOF groups up to and including that transaction.
+        let group_count = self.capture
+            .endpoint_traffic(FRAMING_EP_ID)?
+            .transaction_ids
+            .bisect_left(&(transaction_id + 1))?;
+        Ok(group_count.value.checked_sub(1))
+    }
+
+    /// The number of the frame which a packet was sent in, if any SOF came
+    /// before it.
+    pub fn number_at(&mut self, packet_id: PacketId)
+        -> Result<Option<FrameNumber>, Error>
+    {
+        let group = match self.group_at(packet_id)? {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+        let sofs = self.group_sofs(group)?;
+        // A SOF is in its own frame; other packets are in the frame of
+        // the last SOF of the group before them.
+        let offset = sofs
+            .iter()
+            .rposition(|(sof_id, _)| *sof_id <= packet_id)
+            .unwrap_or(sofs.len() - 1);
+        Ok(Some(self.numbers(group, &sofs)?[offset]))
+    }
+
+    /// The frames begun by the SOFs in up to `count` groups, starting from
+    /// the group at `start`.
+    pub fn frames(&mut self, start: u64, count: u64)
+        -> Result<Vec<Frame>, Error>
+    {
+        let group_count = self.group_count()?;
+        let end = group_count.min(start.saturating_add(count));
+        let transaction_count = self.capture.transaction_index.len();
+        let mut frames = Vec::new();
+        let mut previous = match start.checked_sub(1) {
+            Some(group) => self.group_sofs(group)?.last().map(|(_, n)| *n),
+            None => None,
+        };
+        for group in start..end {
+            let transaction = self.group_transaction(group)?;
+            let next = if group + 1 < group_count {
+                self.group_transaction(group + 1)?
+            } else {
+                TransactionId::from(transaction_count)
+            };
+            let sofs = self.group_sofs(group)?;
+            let numbers = self.numbers(group, &sofs)?;
+            let last = sofs.len() - 1;
+            for (offset, ((sof, frame), number)) in
+                sofs.into_iter().zip(numbers).enumerate()
+            {
+                let missed = match previous {
+                    Some(previous) => {
+                        let step = frame.wrapping_sub(previous) % FRAME_COUNT;
+                        step.saturating_sub(1)
+                    },
+                    None => 0,
+                };
+                previous = Some(frame);
+                let transactions = if offset == last {
+                    ((transaction.value + 1)..next.value)
+                        .map(TransactionId::from)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                frames.push(Frame {
+                    number,
+                    group,
+                    sof,
+                    transaction,
+                    time: self.capture.packet_time(sof)?,
+                    transactions,
+                    missed,
+                });
+            }
+        }
+        Ok(frames)
+    }
+
+    fn transaction_at(&mut self, packet_id: PacketId)
+        -> Result<Option<TransactionId>, Error>
+    {
+        let capture = &mut *self.capture;
+        let transaction_count = capture.transaction_index.len();
+        if transaction_count == 0 {
+            return Ok(None);
+        }
+        let mut transaction_id =
+            capture.transaction_index.bisect_left(&packet_id)?;
+        if transaction_id.value == transaction_count ||
+            capture.transaction_index.get(transaction_id)? > packet_id
+        {
+            if transaction_id.value == 0 {
+                return Ok(None);
+            }
+            transaction_id -= 1;
+        }
+        Ok(Some(transaction_id))
+    }
+
+    fn group_transaction(&mut self, group: u64)
+        -> Result<TransactionId, Error>
+    {
+        self.capture
+            .endpoint_traffic(FRAMING_EP_ID)?
+            .transaction_ids
+            .get(EndpointTransactionId::from(group))
+    }
+
+    /// The SOF packets of a group, with the frame numbers they carry.
+    fn group_sofs(&mut self, group: u64)
+        -> Result<Vec<(PacketId, u16)>, Error>
+    {
+        let transaction_id = self.group_transaction(group)?;
+        let capture = &mut *self.capture;
+        let packets = capture.transaction_index.target_range(
+            transaction_id, capture.packet_index.len())?;
+        let mut sofs = Vec::new();
+        for value in packets.start.value..packets.end.value {
+            let packet_id = PacketId::from(value);
+            let packet = capture.packet(packet_id)?;
+            if let PacketFields::SOF(sof) = PacketFields::from_packet(&packet) {
+                sofs.push((packet_id, sof.frame_number()));
+            }
+        }
+        if sofs.is_empty() {
+            bail!("SOF group {group} contains no SOF packets");
+        }
+        Ok(sofs)
+    }
+
+    /// The frame numbers of the SOFs in a group, with their microframes if
+    /// the SOFs around them show the bus is at high speed.
+    ///
+    /// At high speed the same number is sent in each microframe, so the
+    /// microframe is found by counting the SOFs before it with that number.
+    fn numbers(&mut self, group: u64, sofs: &[(PacketId, u16)])
+        -> Result<Vec<FrameNumber>, Error>
+    {
+        // Collect enough SOFs from earlier groups to count back through a
+        // whole frame, and the SOF following this group.
+        let limit = MICROFRAMES as usize - 1;
+        let mut earlier: Vec<u16> = Vec::new();
+        let mut previous = group;
+        while earlier.len() < limit && previous > 0 {
+            previous -= 1;
+            let mut numbers: Vec<u16> = self
+                .group_sofs(previous)?
+                .into_iter()
+                .map(|(_, frame)| frame)
+                .collect();
+            numbers.append(&mut earlier);
+            earlier = numbers;
+        }
+        let following = if group + 1 < self.group_count()? {
+            Some(self.group_sofs(group + 1)?[0].1)
+        } else {
+            None
+        };
+        let mut all = earlier;
+        let first = all.len();
+        all.extend(sofs.iter().map(|(_, frame)| *frame));
+        all.extend(following);
+        let high_speed = all.windows(2).any(|pair| pair[0] == pair[1]);
+        Ok((first..(first + sofs.len()))
+            .map(|i| {
+                let frame = all[i];
+                let microframe = all[..i]
+                    .iter()
+                    .rev()
+                    .take(limit)
+                    .take_while(|number| **number == frame)
+                    .count() as u8;
+                FrameNumber {
+                    frame,
+                    microframe: high_speed.then(|| microframe),
+                }
+            })
+            .collect())
+    }
+
+    /// A short description of a transaction, e.g. `IN 5.1 NAK`.
+    pub fn describe(&mut self, transaction_id: TransactionId)
+        -> Result<String, Error>
+    {
+        let capture = &mut *self.capture;
+        let packets = capture.transaction_index.target_range(
+            transaction_id, capture.packet_index.len())?;
+        let first = capture.packet(packets.start)?;
+        let last = capture.packet(packets.end - 1)?;
+        let first_pid = first.first().map_or(PID::Malformed, |b| PID::from(*b));
+        let last_pid = last.first().map_or(PID::Malformed, |b| PID::from(*b));
+        let mut text = match PacketFields::from_packet(&first) {
+            PacketFields::Token(token) => format!("{first_pid} {}.{}",
+                token.device_address(), token.endpoint_number()),
+            _ => first_pid.to_string(),
+        };
+        if packets.end.value - packets.start.value > 1 {
+            text.push(' ');
+            text.push_str(&last_pid.to_string());
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_frames() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let mut frames = Frames::new(&mut capture);
+        let group_count = frames.group_count().unwrap();
+        assert_eq!(group_count, 12);
+        let all = frames.frames(0, group_count).unwrap();
+        assert_eq!(all.len(), 50);
+        assert_eq!(all[0].number.to_string(), "186.0");
+        assert_eq!(all[2].number.to_string(), "187.0");
+
+        // This capture is at high speed, with eight microframes per frame.
+        for pair in all.windows(2) {
+            let (a, b) = (pair[0].number, pair[1].number);
+            let (a_micro, b_micro) =
+                (a.microframe.unwrap(), b.microframe.unwrap());
+            if b.frame == a.frame {
+                assert_eq!(b_micro, a_micro + 1);
+            } else {
+                assert_eq!(b.frame, (a.frame + 1) % FRAME_COUNT);
+                assert_eq!(pair[1].missed, 0);
+            }
+        }
+
+        let mut gap = all[1].clone();
+        assert_eq!(gap.missing(), None);
+        gap.number.frame = 2;
+        gap.missed = 4;
+        assert_eq!(gap.missing(), Some((2046, 1)));
+
+        // Other traffic is placed in the frame of the last SOF before it.
+        let frame = all.iter().find(|f| !f.transactions.is_empty()).unwrap();
+        let transaction_id = frame.transactions[0];
+        let packet_id = capture.transaction_index.get(transaction_id).unwrap();
+        let mut frames = Frames::new(&mut capture);
+        assert_eq!(frames.group_at(packet_id).unwrap(), Some(frame.group));
+        assert_eq!(frames.number_at(packet_id).unwrap(), Some(frame.number));
+        assert_eq!(frames.number_at(all[1].sof).unwrap(), Some(all[1].number));
+        assert!(frames.describe(transaction_id).unwrap().starts_with("SETUP"));
+    }
+}
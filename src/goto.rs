@@ -0,0 +1,219 @@
+//! Locating traffic by packet or transaction number, or by time.
+
+use anyhow::{Context, Error, bail};
+
+use crate::capture::{
+    CaptureReader,
+    PacketId,
+    Timestamp,
+    TrafficItem,
+    TransactionId,
+};
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+const NS_PER_DAY: u64 = 86_400 * NS_PER_SEC;
+
+/// A position in a capture to go to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A packet, numbered from 1.
+    Packet(u64),
+    /// A transaction, numbered from 1.
+    Transaction(u64),
+    /// A time in nanoseconds after the first packet.
+    RelativeTime(u64),
+    /// A time in nanoseconds since the Unix epoch.
+    AbsoluteTime(Timestamp),
+    /// A time of day (UTC) in nanoseconds, on or after the capture start.
+    TimeOfDay(u64),
+}
+
+impl Target {
+    /// Parse a target, in one of these forms:
+    ///
+    /// - `123` or `packet 123`: a packet number.
+    /// - `transaction 45`: a transaction number.
+    /// - `1.5s`, `250ms`, `10us` or `100ns`: a time after the first packet.
+    /// - `@1700000000.25`: a Unix time in seconds.
+    /// - `12:34:56.789`: a time of day, in UTC.
+    pub fn parse(text: &str) -> Result<Target, Error> {
+        let text = text.trim();
+        if let Some(number) = text.strip_prefix("packet") {
+            return Ok(Target::Packet(parse_number(number.trim())?));
+        }
+        if let Some(number) = text.strip_prefix("transaction") {
+            return Ok(Target::Transaction(parse_number(number.trim())?));
+        }
+        if let Some(seconds) = text.strip_prefix('@') {
+            return Ok(Target::AbsoluteTime(parse_fraction(seconds, NS_PER_SEC)?));
+        }
+        if text.contains(':') {
+            return Ok(Target::TimeOfDay(parse_time_of_day(text)?));
+        }
+        let text = text.strip_prefix('+').unwrap_or(text);
+        for (suffix, scale) in [
+            ("ns", 1),
+            ("us", 1_000),
+            ("ms", 1_000_000),
+            ("s", NS_PER_SEC),
+        ] {
+            if let Some(number) = text.strip_suffix(suffix) {
+                return Ok(Target::RelativeTime(
+                    parse_fraction(number.trim(), scale)?));
+            }
+        }
+        Ok(Target::Packet(parse_number(text)?))
+    }
+
+    /// Find the traffic item at this target.
+    ///
+    /// For times, this is the first packet at or after the time given.
+    pub fn find(&self, capture: &mut CaptureReader)
+        -> Result<TrafficItem, Error>
+    {
+        use Target::*;
+        match *self {
+            Packet(number) =>
+                capture.packet_item(PacketId::from(number - 1)),
+            Transaction(number) =>
+                capture.transaction_item(TransactionId::from(number - 1)),
+            RelativeTime(offset) => {
+                let start = start_time(capture)?;
+                packet_at(capture, start + offset)
+            },
+            AbsoluteTime(timestamp) => packet_at(capture, timestamp),
+            TimeOfDay(time) => {
+                let start = start_time(capture)?;
+                let mut timestamp = start - start % NS_PER_DAY + time;
+                if timestamp < start {
+                    timestamp += NS_PER_DAY;
+                }
+                packet_at(capture, timestamp)
+            },
+        }
+    }
+}
+
+fn start_time(capture: &mut CaptureReader) -> Result<Timestamp, Error> {
+    if capture.packet_times.len() == 0 {
+        bail!("The capture has no packets")
+    }
+    capture.packet_time(PacketId::from(0))
+}
+
+fn packet_at(capture: &mut CaptureReader, timestamp: Timestamp)
+    -> Result<TrafficItem, Error>
+{
+    let packet_id = capture.packet_times.bisect_left(&timestamp)?;
+    if packet_id.value == capture.packet_times.len() {
+        bail!("There are no packets after that time")
+    }
+    capture.packet_item(packet_id)
+}
+
+fn parse_number(text: &str) -> Result<u64, Error> {
+    match text.strip_prefix('#').unwrap_or(text).parse() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => bail!("Invalid number '{text}', expected a number from 1"),
+    }
+}
+
+/// Parse a decimal number, scaling it to an integer number of units.
+fn parse_fraction(text: &str, scale: u64) -> Result<u64, Error> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let digits_valid = |digits: &str|
+        digits.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) ||
+        !digits_valid(whole) || !digits_valid(fraction)
+    {
+        bail!("Invalid number '{text}'")
+    }
+    let whole: u64 = if whole.is_empty() { 0 } else { whole.parse()? };
+    let mut value = whole
+        .checked_mul(scale)
+        .context("Time is too large")?;
+    let mut unit = scale;
+    for digit in fraction.chars() {
+        unit /= 10;
+        if unit == 0 {
+            break;
+        }
+        value += unit * digit.to_digit(10).unwrap_or(0) as u64;
+    }
+    Ok(value)
+}
+
+fn parse_time_of_day(text: &str) -> Result<u64, Error> {
+    let parts: Vec<&str> = text.split(':').collect();
+    let invalid = || format!(
+        "Invalid time of day '{text}', expected HH:MM:SS or HH:MM:SS.sss");
+    if parts.len() != 3 {
+        bail!(invalid())
+    }
+    let hours: u64 = parts[0].parse().with_context(invalid)?;
+    let minutes: u64 = parts[1].parse().with_context(invalid)?;
+    let seconds = parse_fraction(parts[2], NS_PER_SEC).with_context(invalid)?;
+    if hours > 23 || minutes > 59 || seconds >= 60 * NS_PER_SEC {
+        bail!(invalid())
+    }
+    Ok((hours * 3600 + minutes * 60) * NS_PER_SEC + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::capture::ItemSource;
+    use crate::loader::load;
+
+    #[test]
+    fn test_parse() {
+        use Target::*;
+        for (text, target) in [
+            ("42", Packet(42)),
+            ("packet #7", Packet(7)),
+            ("transaction 3", Transaction(3)),
+            ("1.5s", RelativeTime(1_500_000_000)),
+            ("+250ms", RelativeTime(250_000_000)),
+            ("10 us", RelativeTime(10_000)),
+            (".5ns", RelativeTime(0)),
+            ("@1700000000.25", AbsoluteTime(1_700_000_000_250_000_000)),
+            ("01:02:03.5", TimeOfDay(3_723_500_000_000)),
+        ] {
+            assert_eq!(Target::parse(text).unwrap(), target, "{text}");
+        }
+        for text in ["0", "packet", "frame 3", "1.2.3s", "25:00:00", "x"] {
+            assert!(Target::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        // Every packet can be found, at the position it is displayed.
+        let packet_count = capture.packet_index.len();
+        for number in 1..=packet_count {
+            let item = Target::Packet(number).find(&mut capture).unwrap();
+            let path = capture.item_path(&item).unwrap().unwrap();
+            let mut found: TrafficItem = capture.item(None, path[0]).unwrap();
+            for index in &path[1..] {
+                found = capture.child_item(&found, *index).unwrap();
+            }
+            assert_eq!(found, item);
+            assert!(matches!(item,
+                TrafficItem::Packet(_, _, id) if id.value == number - 1));
+        }
+        assert!(Target::Packet(packet_count + 1).find(&mut capture).is_err());
+
+        // Going to the start time finds the first packet.
+        let start = capture.packet_time(PacketId::from(0)).unwrap();
+        let first = Target::Packet(1).find(&mut capture).unwrap();
+        for target in [
+            Target::RelativeTime(0),
+            Target::AbsoluteTime(start),
+        ] {
+            assert_eq!(target.find(&mut capture).unwrap(), first);
+        }
+    }
+}
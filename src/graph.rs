@@ -0,0 +1,209 @@
+//! Throughput and bus utilization over the time of a capture.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    DeviceId,
+    EndpointId,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    TrafficItemId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+use crate::id::HasLength;
+use crate::speeds::BusSpeed;
+
+/// Part of the traffic in a capture to plot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// All packets, including SOFs and invalid packets.
+    All,
+    /// Packets to and from one device.
+    Device(DeviceId),
+    /// Packets on one endpoint.
+    Endpoint(EndpointId),
+}
+
+impl Selection {
+    /// The parts of a capture that may be plotted, with their names.
+    pub fn options(capture: &mut CaptureReader)
+        -> Result<Vec<(String, Selection)>, Error>
+    {
+        let mut devices = Vec::new();
+        let mut endpoints = Vec::new();
+        for id in 0..capture.endpoints.len() {
+            let endpoint_id = EndpointId::from(id);
+            if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+                continue;
+            }
+            let endpoint = capture.endpoints.get(endpoint_id)?;
+            let device = (
+                format!("Device {}", endpoint.device_address()),
+                Selection::Device(endpoint.device_id()));
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+            endpoints.push((
+                format!("Endpoint {endpoint}"),
+                Selection::Endpoint(endpoint_id)));
+        }
+        let mut options = vec![("All traffic".to_string(), Selection::All)];
+        options.extend(devices);
+        options.extend(endpoints);
+        Ok(options)
+    }
+
+    fn includes(&self, capture: &mut CaptureReader, endpoint_id: EndpointId)
+        -> Result<bool, Error>
+    {
+        use Selection::*;
+        Ok(match self {
+            All => true,
+            Device(device_id) => {
+                endpoint_id != INVALID_EP_ID &&
+                endpoint_id != FRAMING_EP_ID &&
+                capture.endpoints.get(endpoint_id)?.device_id() == *device_id
+            },
+            Endpoint(selected_id) => endpoint_id == *selected_id,
+        })
+    }
+}
+
+/// Bytes on the bus in each interval of a capture.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    /// Time of the first packet in the capture.
+    pub start: Timestamp,
+    /// Length of each interval, in nanoseconds.
+    pub interval: Timestamp,
+    /// Bytes in the packets selected, in each interval.
+    pub bytes: Vec<u64>,
+}
+
+impl Graph {
+    /// Count the bytes of the selected packets, in a number of equal
+    /// intervals covering the capture.
+    pub fn new(capture: &mut CaptureReader,
+               selection: Selection,
+               interval_count: usize)
+        -> Result<Graph, Error>
+    {
+        let packet_count = capture.packet_times.len();
+        if packet_count == 0 || interval_count == 0 {
+            return Ok(Graph::default());
+        }
+        let start = capture.packet_time(PacketId::from(0))?;
+        let end = capture.packet_time(PacketId::from(packet_count - 1))?;
+        let interval = end.saturating_sub(start) / interval_count as u64 + 1;
+        let mut bytes = vec![0; interval_count];
+        for index in 0..capture.item_index.len() {
+            let transfer_id = capture.item_index.get(TrafficItemId::from(index))?;
+            let entry = capture.transfer_index.get(transfer_id)?;
+            // Each transfer is counted from its start.
+            if !entry.is_start() {
+                continue;
+            }
+            let endpoint_id = entry.endpoint_id();
+            if !selection.includes(capture, endpoint_id)? {
+                continue;
+            }
+            let range = capture.transfer_range(&entry)?;
+            for id in range.start.value..range.end.value {
+                let transaction_id = capture
+                    .endpoint_traffic(endpoint_id)?
+                    .transaction_ids
+                    .get(EndpointTransactionId::from(id))?;
+                let packets = capture.transaction_index.target_range(
+                    transaction_id, capture.packet_index.len())?;
+                for id in packets.start.value..packets.end.value {
+                    let packet_id = PacketId::from(id);
+                    let packet_bytes = capture.packet_index.target_range(
+                        packet_id, capture.packet_data.len())?;
+                    let time = capture.packet_time(packet_id)?;
+                    let index = (time.saturating_sub(start) / interval) as usize;
+                    bytes[index.min(interval_count - 1)] += packet_bytes.len();
+                }
+            }
+        }
+        Ok(Graph { start, interval, bytes })
+    }
+
+    /// Throughput in an interval, in bytes per second.
+    pub fn throughput(&self, index: usize) -> f64 {
+        self.bytes[index] as f64 * 1e9 / self.interval as f64
+    }
+
+    /// Highest throughput in any interval, in bytes per second.
+    pub fn peak(&self) -> f64 {
+        (0..self.bytes.len())
+            .map(|index| self.throughput(index))
+            .fold(0.0, f64::max)
+    }
+
+    /// Fraction of the bus bandwidth used in an interval.
+    ///
+    /// This counts only the bits of the packets themselves, so it is lower
+    /// than the true utilization, which includes sync patterns, EOPs,
+    /// bit stuffing and inter-packet gaps.
+    pub fn utilization(&self, index: usize, speed: BusSpeed) -> f64 {
+        self.throughput(index) * 8.0 / speed.bits_per_second()
+    }
+
+    /// Start time of the interval at a position across the graph, where
+    /// 0.0 is the start of the capture and 1.0 is its end.
+    pub fn time_at(&self, position: f64) -> Timestamp {
+        let count = self.bytes.len();
+        if count == 0 {
+            return self.start;
+        }
+        let index = ((position * count as f64) as usize).min(count - 1);
+        self.start + index as u64 * self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_graph() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let options = Selection::options(&mut capture).unwrap();
+        assert_eq!(options[0], ("All traffic".to_string(), Selection::All));
+
+        let all = Graph::new(&mut capture, Selection::All, 100).unwrap();
+        let total: u64 = all.bytes.iter().sum();
+        assert_eq!(all.bytes.len(), 100);
+        assert!(total > 0);
+        assert!(total <= capture.packet_data.len());
+        assert!(all.peak() > 0.0);
+        assert!(all.utilization(0, BusSpeed::Low) >
+                all.utilization(0, BusSpeed::High));
+        assert_eq!(all.time_at(0.0), all.start);
+        assert_eq!(all.time_at(1.0), all.start + 99 * all.interval);
+
+        // The traffic of a device is the sum of that on its endpoints.
+        let mut sum = |prefix: &str| {
+            let mut sum = 0;
+            for (name, selection) in &options {
+                if name.starts_with(prefix) {
+                    let graph = Graph::new(&mut capture, *selection, 100)
+                        .unwrap();
+                    sum += graph.bytes.iter().sum::<u64>();
+                }
+            }
+            sum
+        };
+        let device_total = sum("Device 11");
+        let endpoint_total = sum("Endpoint 11.");
+        assert!(device_total > 0);
+        assert!(device_total < total);
+        assert_eq!(endpoint_total, device_total);
+    }
+}
@@ -0,0 +1,198 @@
+//! Packet rates on each endpoint over the time of a capture, for drawing
+//! as a heat map.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    TransactionId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+
+/// The packets on one endpoint, in each interval.
+#[derive(Clone, Debug)]
+pub struct HeatRow {
+    /// Description of the endpoint, e.g. `5.1 IN`.
+    pub name: String,
+    /// Filter expression selecting the traffic on this endpoint.
+    pub condition: String,
+    /// Packets in each interval.
+    pub packets: Vec<u64>,
+    /// First transaction starting in each interval, if any.
+    pub first: Vec<Option<TransactionId>>,
+}
+
+/// Packets on each endpoint in each interval of a capture.
+///
+/// SOF packets are left out, since they are sent at a steady rate
+/// regardless of the traffic.
+#[derive(Clone, Debug, Default)]
+pub struct HeatMap {
+    /// Time of the first packet in the capture.
+    pub start: Timestamp,
+    /// Length of each interval, in nanoseconds.
+    pub interval: Timestamp,
+    /// Rows for endpoints, in the order they were first seen.
+    pub rows: Vec<HeatRow>,
+}
+
+impl HeatMap {
+    /// Count the packets on each endpoint, in a number of equal intervals
+    /// covering the capture.
+    pub fn new(capture: &mut CaptureReader, interval_count: usize)
+        -> Result<HeatMap, Error>
+    {
+        let packet_count = capture.packet_times.len();
+        if packet_count == 0 || interval_count == 0 {
+            return Ok(HeatMap::default());
+        }
+        let start = capture.packet_time(PacketId::from(0))?;
+        let end = capture.packet_time(PacketId::from(packet_count - 1))?;
+        let interval = end.saturating_sub(start) / interval_count as u64 + 1;
+        let mut rows = Vec::new();
+        for index in 0..capture.endpoints.len() {
+            let endpoint_id = EndpointId::from(index);
+            if endpoint_id == FRAMING_EP_ID {
+                continue;
+            }
+            let transaction_count = capture
+                .endpoint_traffic(endpoint_id)?
+                .transaction_ids
+                .len();
+            let (name, condition) = if endpoint_id == INVALID_EP_ID {
+                ("Invalid".to_string(), "transfer.type == invalid".to_string())
+            } else {
+                let endpoint = capture.endpoints.get(endpoint_id)?;
+                (endpoint.to_string(),
+                 format!("device == {} && endpoint == 0x{:02X}",
+                         endpoint.device_address(),
+                         endpoint.address().0))
+            };
+            let mut row = HeatRow {
+                name,
+                condition,
+                packets: vec![0; interval_count],
+                first: vec![None; interval_count],
+            };
+            for id in 0..transaction_count {
+                let transaction_id = capture
+                    .endpoint_traffic(endpoint_id)?
+                    .transaction_ids
+                    .get(EndpointTransactionId::from(id))?;
+                let packets = capture.transaction_index.target_range(
+                    transaction_id, capture.packet_index.len())?;
+                let time = capture.packet_time(packets.start)?;
+                let column = ((time.saturating_sub(start) / interval) as usize)
+                    .min(interval_count - 1);
+                row.packets[column] += packets.end.value - packets.start.value;
+                row.first[column].get_or_insert(transaction_id);
+            }
+            if transaction_count > 0 {
+                rows.push(row);
+            }
+        }
+        Ok(HeatMap { start, interval, rows })
+    }
+
+    /// Most packets on any endpoint in any interval.
+    pub fn peak(&self) -> u64 {
+        self.rows
+            .iter()
+            .flat_map(|row| row.packets.iter())
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Brightness of a cell, from 0.0 to 1.0, on a logarithmic scale up
+    /// to the peak, so that low rates are still visible.
+    pub fn intensity(&self, row: usize, column: usize, peak: u64) -> f64 {
+        let packets = self.rows
+            .get(row)
+            .and_then(|row| row.packets.get(column))
+            .copied()
+            .unwrap_or(0);
+        if packets == 0 || peak == 0 {
+            0.0
+        } else {
+            (packets as f64).ln_1p() / (peak as f64).ln_1p()
+        }
+    }
+
+    /// Number of intervals in each row.
+    pub fn column_count(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.packets.len())
+    }
+
+    /// The interval at a position across the map, where 0.0 is the start
+    /// of the capture and 1.0 is its end.
+    pub fn column_at(&self, position: f64) -> Option<usize> {
+        let count = self.column_count();
+        if count == 0 || !(0.0..=1.0).contains(&position) {
+            return None;
+        }
+        Some(((position * count as f64) as usize).min(count - 1))
+    }
+
+    /// The first transaction on an endpoint at or after the start of an
+    /// interval, if any.
+    pub fn transaction_from(&self, row: usize, column: usize)
+        -> Option<TransactionId>
+    {
+        self.rows
+            .get(row)?
+            .first
+            .get(column..)?
+            .iter()
+            .find_map(|first| *first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_heat_map() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let map = HeatMap::new(&mut capture, 100).unwrap();
+        assert!(!map.rows.is_empty());
+        assert_eq!(map.column_count(), 100);
+        assert!(map.rows.iter().all(|row| row.name != "SOF"));
+
+        // Every packet not on the framing endpoint is counted once.
+        let total: u64 = map.rows
+            .iter()
+            .flat_map(|row| row.packets.iter())
+            .sum();
+        assert!(total > 0);
+        assert!(total < capture.packet_index.len());
+
+        let peak = map.peak();
+        assert!(peak > 0);
+        for (index, row) in map.rows.iter().enumerate() {
+            for (column, packets) in row.packets.iter().enumerate() {
+                let intensity = map.intensity(index, column, peak);
+                assert!((0.0..=1.0).contains(&intensity));
+                assert_eq!(intensity > 0.0, *packets > 0);
+                assert_eq!(row.first[column].is_some(), *packets > 0);
+            }
+        }
+
+        // The first transaction on an endpoint is found from the start.
+        let row = &map.rows[0];
+        let first_column = row.packets.iter().position(|n| *n > 0).unwrap();
+        assert_eq!(map.transaction_from(0, 0), row.first[first_column]);
+        assert_eq!(map.column_at(0.0), Some(0));
+        assert_eq!(map.column_at(1.0), Some(99));
+        assert_eq!(map.column_at(1.5), None);
+    }
+}
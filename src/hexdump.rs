@@ -0,0 +1,417 @@
+//! Hex dumps of traffic item data, with the bytes of each decoded field.
+
+use std::ops::Range;
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, TrafficItem, TransferId};
+use crate::usb::{
+    crc32,
+    PacketFields,
+    SetupFields,
+    StartComplete,
+    PID,
+};
+
+/// Number of bytes shown on each line of a hex dump.
+pub const BYTES_PER_LINE: usize = 16;
+
+/// Maximum number of bytes shown in a hex dump.
+pub const MAX_DUMP_BYTES: usize = 0x10000;
+
+/// Width of the offset at the start of each line, including spacing.
+const OFFSET_WIDTH: usize = 6;
+
+/// Column at which the ASCII part of each line starts.
+const ASCII_COLUMN: usize = OFFSET_WIDTH + BYTES_PER_LINE * 3 + 2;
+
+/// A decoded field, and the bytes it was decoded from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: String,
+    pub range: Range<usize>,
+}
+
+impl Field {
+    fn new(name: &'static str, value: String, range: Range<usize>) -> Field {
+        Field { name, value, range }
+    }
+}
+
+/// The data of a traffic item, with its decoded fields.
+#[derive(Clone, Debug, Default)]
+pub struct Detail {
+    pub data: Vec<u8>,
+    pub fields: Vec<Field>,
+}
+
+impl Detail {
+    /// Decode the data of a traffic item.
+    ///
+    /// Packets are decoded field by field. For transfers and transactions
+    /// the payload is shown, with the fields of any SETUP request. The
+    /// payload of a transfer is reassembled from those of its transactions,
+    /// with a field for each transaction's part and a CRC-32 of the whole.
+    pub fn new(capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<Detail, Error>
+    {
+        use TrafficItem::*;
+        let data = capture.item_data(item)?;
+        let mut fields = Vec::new();
+        match item {
+            Packet(_, transaction_id, packet_id) => {
+                let first = capture.transaction_index.get(*transaction_id)?;
+                let after_setup = *packet_id > first &&
+                    capture.packet(*packet_id - 1)?.first() ==
+                        Some(&u8::from(PID::SETUP));
+                packet_fields(&data, after_setup, &mut fields);
+            },
+            Transaction(_, transaction_id) => {
+                let first = capture.transaction_index.get(*transaction_id)?;
+                let setup = capture.packet(first)?.first() ==
+                    Some(&u8::from(PID::SETUP));
+                if !data.is_empty() {
+                    fields.push(Field::new("Payload",
+                        format!("{} bytes", data.len()), 0..data.len()));
+                }
+                if setup && data.len() == 8 {
+                    setup_fields(&data, 0, &mut fields);
+                }
+            },
+            Transfer(transfer_id) => {
+                if !data.is_empty() {
+                    transfer_fields(capture, *transfer_id, &data, &mut fields)?;
+                }
+            },
+        }
+        Ok(Detail { data, fields })
+    }
+
+    /// Whether the data was too long to show in full.
+    pub fn truncated(&self) -> bool {
+        self.data.len() > MAX_DUMP_BYTES
+    }
+
+    /// The data formatted as lines of offset, hex bytes and ASCII.
+    pub fn hex_dump(&self) -> String {
+        let data = &self.data[..self.data.len().min(MAX_DUMP_BYTES)];
+        let mut text = String::new();
+        for (line, bytes) in data.chunks(BYTES_PER_LINE).enumerate() {
+            if line > 0 {
+                text.push('\n');
+            }
+            text.push_str(&format!("{:04X}  ", line * BYTES_PER_LINE));
+            for i in 0..BYTES_PER_LINE {
+                if i == BYTES_PER_LINE / 2 {
+                    text.push(' ');
+                }
+                match bytes.get(i) {
+                    Some(byte) => text.push_str(&format!("{byte:02X} ")),
+                    None => text.push_str("   "),
+                }
+            }
+            text.push(' ');
+            for byte in bytes {
+                text.push(match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                });
+            }
+        }
+        text
+    }
+
+    /// Find the innermost field containing a byte.
+    pub fn field_at(&self, offset: usize) -> Option<usize> {
+        self.fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.range.contains(&offset))
+            .min_by_key(|(_, field)| field.range.len())
+            .map(|(index, _)| index)
+    }
+}
+
+/// Column of the first hex digit of a byte, within its line.
+pub fn hex_column(offset: usize) -> usize {
+    let i = offset % BYTES_PER_LINE;
+    OFFSET_WIDTH + i * 3 + usize::from(i >= BYTES_PER_LINE / 2)
+}
+
+/// Column of the ASCII character for a byte, within its line.
+pub fn ascii_column(offset: usize) -> usize {
+    ASCII_COLUMN + offset % BYTES_PER_LINE
+}
+
+/// Find the byte shown at a line and column of a hex dump, if any.
+pub fn byte_at(line: usize, column: usize) -> Option<usize> {
+    let i = if column >= ASCII_COLUMN {
+        column - ASCII_COLUMN
+    } else {
+        (0..BYTES_PER_LINE).find(|i| {
+            let start = hex_column(*i);
+            (start..start + 2).contains(&column)
+        })?
+    };
+    if i < BYTES_PER_LINE {
+        Some(line * BYTES_PER_LINE + i)
+    } else {
+        None
+    }
+}
+
+fn packet_fields(packet: &[u8], after_setup: bool, fields: &mut Vec<Field>) {
+    use PID::*;
+    let first_byte = match packet.first() {
+        Some(byte) => *byte,
+        None => return,
+    };
+    let pid = PID::from(first_byte);
+    fields.push(Field::new("PID", format!("{pid} (0x{first_byte:02X})"), 0..1));
+    if pid == Malformed {
+        return;
+    }
+    let valid_length = match pid {
+        SOF | SETUP | IN | OUT | PING => packet.len() == 3,
+        SPLIT => packet.len() == 4,
+        DATA0 | DATA1 => packet.len() >= 3,
+        _ => true,
+    };
+    if !valid_length {
+        return;
+    }
+    match PacketFields::from_packet(packet) {
+        PacketFields::SOF(sof) => {
+            fields.push(Field::new("Frame number",
+                format!("{}", sof.frame_number()), 1..3));
+            fields.push(Field::new("CRC5",
+                format!("0x{:02X}", sof.crc()), 2..3));
+        },
+        PacketFields::Token(token) => {
+            fields.push(Field::new("Device address",
+                format!("{}", token.device_address()), 1..2));
+            fields.push(Field::new("Endpoint number",
+                format!("{}", token.endpoint_number()), 1..3));
+            fields.push(Field::new("CRC5",
+                format!("0x{:02X}", token.crc()), 2..3));
+        },
+        PacketFields::Data(data) => {
+            let end = packet.len();
+            if end > 3 {
+                fields.push(Field::new("Data",
+                    format!("{} bytes", end - 3), 1..end - 2));
+                if after_setup && end == 11 {
+                    setup_fields(&packet[1..9], 1, fields);
+                }
+            }
+            fields.push(Field::new("CRC16",
+                format!("0x{:04X}", data.crc), end - 2..end));
+        },
+        PacketFields::Split(split) => {
+            let split_type = match split.sc() {
+                StartComplete::Start => "Start",
+                StartComplete::Complete => "Complete",
+            };
+            fields.push(Field::new("Hub address",
+                format!("{}", split.hub_address()), 1..2));
+            fields.push(Field::new("Start/complete",
+                split_type.to_string(), 1..2));
+            fields.push(Field::new("Port",
+                format!("{}", split.port()), 2..3));
+            fields.push(Field::new("Speed",
+                format!("{:?}", split.speed()), 2..3));
+            fields.push(Field::new("Endpoint type",
+                format!("{:?}", split.endpoint_type()), 3..4));
+            fields.push(Field::new("CRC5",
+                format!("0x{:02X}", split.crc()), 3..4));
+        },
+        PacketFields::None => {},
+    }
+}
+
+/// Add the fields of a transfer's reassembled payload.
+fn transfer_fields(capture: &mut CaptureReader,
+                   transfer_id: TransferId,
+                   data: &[u8],
+                   fields: &mut Vec<Field>)
+    -> Result<(), Error>
+{
+    let transactions = capture.transfer_data_transactions(transfer_id)?;
+    let count = transactions.len();
+    let payload = if count > 1 {
+        format!("{} bytes in {count} transactions", data.len())
+    } else {
+        format!("{} bytes", data.len())
+    };
+    fields.push(Field::new("Payload", payload, 0..data.len()));
+    fields.push(Field::new("CRC-32",
+        format!("0x{:08X}", crc32(data)), 0..data.len()));
+    if count < 2 {
+        return Ok(());
+    }
+    // Only the parts within the hex dump are listed.
+    let mut offset = 0;
+    for (index, (_, length)) in transactions.into_iter().enumerate() {
+        if offset >= data.len().min(MAX_DUMP_BYTES) {
+            break;
+        }
+        let end = (offset + length as usize).min(data.len());
+        fields.push(Field::new("Transaction",
+            format!("{} of {count}, {} bytes", index + 1, end - offset),
+            offset..end));
+        offset = end;
+    }
+    Ok(())
+}
+
+/// Add the fields of a SETUP request held in 8 bytes at an offset.
+fn setup_fields(bytes: &[u8], offset: usize, fields: &mut Vec<Field>) {
+    // SetupFields expects the bytes of a DATA packet, starting with a PID.
+    let mut packet = vec![0];
+    packet.extend_from_slice(bytes);
+    let setup = SetupFields::from_data_packet(&packet);
+    let type_fields = setup.type_fields;
+    for (name, value, range) in [
+        ("bmRequestType", format!("0x{:02X} ({}, {:?}, {:?})",
+            bytes[0],
+            type_fields.direction(),
+            type_fields.request_type(),
+            type_fields.recipient()), 0..1),
+        ("bRequest", format!("{}", setup.request), 1..2),
+        ("wValue", format!("0x{:04X}", setup.value), 2..4),
+        ("wIndex", format!("0x{:04X}", setup.index), 4..6),
+        ("wLength", format!("{}", setup.length), 6..8),
+    ] {
+        fields.push(Field::new(name, value,
+            range.start + offset..range.end + offset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::{create_capture, ItemSource};
+    use crate::decoder::Decoder;
+
+    #[test]
+    fn test_hex_dump() {
+        let detail = Detail {
+            data: (0x30..0x51).collect(),
+            fields: Vec::new(),
+        };
+        let dump = detail.hex_dump();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0],
+            "0000  30 31 32 33 34 35 36 37  38 39 3A 3B 3C 3D 3E 3F  \
+             0123456789:;<=>?");
+        assert_eq!(lines[2],
+            "0020  50                                                P");
+        for offset in [0, 7, 8, 15, 16, 32] {
+            let line = offset / BYTES_PER_LINE;
+            let text = lines[line];
+            let hex = hex_column(offset);
+            assert_eq!(&text[hex..hex + 2],
+                       format!("{:02X}", detail.data[offset]));
+            assert_eq!(text.as_bytes()[ascii_column(offset)],
+                       detail.data[offset]);
+            assert_eq!(byte_at(line, hex), Some(offset));
+            assert_eq!(byte_at(line, hex + 1), Some(offset));
+            assert_eq!(byte_at(line, ascii_column(offset)), Some(offset));
+        }
+        assert_eq!(byte_at(0, 0), None);
+        assert_eq!(byte_at(0, hex_column(0) + 2), None);
+    }
+
+    #[test]
+    fn test_packet_fields() {
+        // IN token to 5.1.
+        let mut fields = Vec::new();
+        packet_fields(&[0x69, 0x85, 0x58], false, &mut fields);
+        let names: Vec<_> = fields.iter().map(|field| field.name).collect();
+        assert_eq!(names,
+            ["PID", "Device address", "Endpoint number", "CRC5"]);
+        assert_eq!(fields[1].value, "5");
+        assert_eq!(fields[2].value, "1");
+
+        // DATA0 packet carrying GET_DESCRIPTOR.
+        let mut fields = Vec::new();
+        let packet = [0xC3, 0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x40, 0x00,
+                      0xDD, 0x94];
+        packet_fields(&packet, true, &mut fields);
+        let detail = Detail { data: packet.to_vec(), fields };
+        let request = detail.field_at(2).unwrap();
+        assert_eq!(detail.fields[request].name, "bRequest");
+        assert_eq!(detail.fields[request].value, "6");
+        let length = detail.field_at(8).unwrap();
+        assert_eq!(detail.fields[length].name, "wLength");
+        assert_eq!(detail.fields[length].value, "64");
+        let crc = detail.field_at(10).unwrap();
+        assert_eq!(detail.fields[crc].value, "0x94DD");
+
+        // Truncated packets have only their PID decoded.
+        let mut fields = Vec::new();
+        packet_fields(&[0x69, 0x85], false, &mut fields);
+        assert_eq!(fields.len(), 1);
+    }
+
+    #[test]
+    fn test_transfer_fields() {
+        let payload: Vec<String> = (0..100)
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        let scenario = Scenario::parse("transfer", &format!("
+            device 1
+            endpoint 1 1 in bulk 64
+            enumerate 1
+            in 1 1 {}
+            in 1 1 aa:bb
+        ", payload.join(":"))).unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let (_, count) = ItemSource::<TrafficItem>::item_children(
+            &mut capture, None).unwrap();
+        let details: Vec<Detail> = (0..count)
+            .map(|index| {
+                let item = ItemSource::<TrafficItem>::item(
+                    &mut capture, None, index).unwrap();
+                Detail::new(&mut capture, &item).unwrap()
+            })
+            .collect();
+
+        // The payload split over two transactions is shown as one.
+        let detail = details
+            .iter()
+            .find(|detail| detail.data.len() == 100)
+            .unwrap();
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(detail.data, expected);
+        let values: Vec<(&str, &str)> = detail.fields
+            .iter()
+            .map(|field| (field.name, field.value.as_str()))
+            .collect();
+        let crc = format!("0x{:08X}", crc32(&expected));
+        assert_eq!(values, [
+            ("Payload", "100 bytes in 2 transactions"),
+            ("CRC-32", crc.as_str()),
+            ("Transaction", "1 of 2, 64 bytes"),
+            ("Transaction", "2 of 2, 36 bytes"),
+        ]);
+        assert_eq!(detail.fields[detail.field_at(63).unwrap()].range, 0..64);
+        assert_eq!(detail.fields[detail.field_at(64).unwrap()].range, 64..100);
+
+        // A single transaction's payload is not split.
+        let detail = details
+            .iter()
+            .find(|detail| detail.data == [0xAA, 0xBB])
+            .unwrap();
+        assert_eq!(detail.fields.len(), 2);
+        assert_eq!(detail.fields[0].value, "2 bytes");
+    }
+}
@@ -0,0 +1,202 @@
+//! Placement of the panes around the traffic view.
+//!
+//! Each pane can be placed to the left of, to the right of or below the
+//! traffic view, or detached into a window of its own. Panes in the same
+//! place are stacked in a fixed order.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// A pane which can be placed around the traffic view.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pane {
+    Devices,
+    Endpoints,
+    Bookmarks,
+    Fields,
+    Hex,
+    Errors,
+}
+
+/// Where a pane is placed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Place {
+    Left,
+    Right,
+    Bottom,
+    Window,
+}
+
+impl Pane {
+    pub const ALL: [Pane; 6] = [
+        Pane::Devices,
+        Pane::Endpoints,
+        Pane::Bookmarks,
+        Pane::Fields,
+        Pane::Hex,
+        Pane::Errors,
+    ];
+
+    /// Name of this pane, as shown in menus and window titles.
+    pub fn label(&self) -> &'static str {
+        use Pane::*;
+        match self {
+            Devices => "Devices",
+            Endpoints => "Endpoints",
+            Bookmarks => "Bookmarks",
+            Fields => "Fields",
+            Hex => "Hex",
+            Errors => "Errors",
+        }
+    }
+
+    /// Name of this pane, as used in actions.
+    pub fn name(&self) -> &'static str {
+        use Pane::*;
+        match self {
+            Devices => "devices",
+            Endpoints => "endpoints",
+            Bookmarks => "bookmarks",
+            Fields => "fields",
+            Hex => "hex",
+            Errors => "errors",
+        }
+    }
+
+    /// Where this pane is placed unless moved.
+    pub fn default_place(&self) -> Place {
+        use Pane::*;
+        match self {
+            Devices | Endpoints | Bookmarks => Place::Right,
+            Fields | Hex | Errors => Place::Bottom,
+        }
+    }
+}
+
+impl Place {
+    pub const ALL: [Place; 4] =
+        [Place::Left, Place::Right, Place::Bottom, Place::Window];
+
+    /// Description of this place, as shown in menus.
+    pub fn label(&self) -> &'static str {
+        use Place::*;
+        match self {
+            Left => "Left",
+            Right => "Right",
+            Bottom => "Bottom",
+            Window => "Separate window",
+        }
+    }
+
+    /// Name of this place, as used in actions.
+    pub fn name(&self) -> &'static str {
+        use Place::*;
+        match self {
+            Left => "left",
+            Right => "right",
+            Bottom => "bottom",
+            Window => "window",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Place> {
+        Place::ALL.into_iter().find(|place| place.name() == name)
+    }
+}
+
+/// The places of the panes, saved as the place of each pane by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<Pane, Place>", into = "BTreeMap<Pane, Place>")]
+pub struct Layout {
+    /// Panes moved from their default places.
+    places: BTreeMap<Pane, Place>,
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        Layout::default()
+    }
+
+    /// Where a pane is placed.
+    pub fn place(&self, pane: Pane) -> Place {
+        self.places
+            .get(&pane)
+            .copied()
+            .unwrap_or_else(|| pane.default_place())
+    }
+
+    /// Move a pane to a place.
+    pub fn set_place(&mut self, pane: Pane, place: Place) {
+        if place == pane.default_place() {
+            self.places.remove(&pane);
+        } else {
+            self.places.insert(pane, place);
+        }
+    }
+
+    /// The panes in a place, in the order they are stacked.
+    pub fn panes(&self, place: Place) -> Vec<Pane> {
+        Pane::ALL
+            .into_iter()
+            .filter(|pane| self.place(*pane) == place)
+            .collect()
+    }
+}
+
+impl From<BTreeMap<Pane, Place>> for Layout {
+    fn from(places: BTreeMap<Pane, Place>) -> Layout {
+        let mut layout = Layout::new();
+        for (pane, place) in places {
+            layout.set_place(pane, place);
+        }
+        layout
+    }
+}
+
+impl From<Layout> for BTreeMap<Pane, Place> {
+    fn from(layout: Layout) -> BTreeMap<Pane, Place> {
+        Pane::ALL
+            .into_iter()
+            .map(|pane| (pane, layout.place(pane)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{from_value, json, to_value};
+
+    #[test]
+    fn test_layout() {
+        let mut layout = Layout::new();
+        assert_eq!(layout.panes(Place::Right),
+                   vec![Pane::Devices, Pane::Endpoints, Pane::Bookmarks]);
+        assert_eq!(layout.panes(Place::Bottom),
+                   vec![Pane::Fields, Pane::Hex, Pane::Errors]);
+        assert!(layout.panes(Place::Left).is_empty());
+
+        layout.set_place(Pane::Hex, Place::Left);
+        layout.set_place(Pane::Devices, Place::Window);
+        assert_eq!(layout.panes(Place::Left), vec![Pane::Hex]);
+        assert_eq!(layout.panes(Place::Right),
+                   vec![Pane::Endpoints, Pane::Bookmarks]);
+        assert_eq!(layout.panes(Place::Window), vec![Pane::Devices]);
+        let json = to_value(&layout).unwrap();
+        assert_eq!(json["devices"], json!("window"));
+        assert_eq!(from_value::<Layout>(json).unwrap(), layout);
+
+        // Returning panes to their default places restores the default.
+        layout.set_place(Pane::Hex, Place::Bottom);
+        layout.set_place(Pane::Devices, Place::Right);
+        assert_eq!(layout, Layout::new());
+        assert_eq!(from_value::<Layout>(json!({})).unwrap(), layout);
+        assert!(from_value::<Layout>(json!({"hex": "top"})).is_err());
+        assert!(from_value::<Layout>(json!({"status": "left"})).is_err());
+    }
+}
@@ -1,23 +1,54 @@
-#[macro_use]
-extern crate bitfield;
+// The decoding engine is in the packetry-core crate. Its modules are
+// imported here so that the modules below can refer to them as if local.
+pub use packetry_core::{backend, capture, decoder, pcap, traffic_index};
+use packetry_core::{
+    clock, codegen, export, filter, halts, id, integrity, loader, logic,
+    markers, otg, overrides, report, schedule, speeds, suspend, throughput,
+    trigger, usb, util};
+#[cfg(feature="gui")]
+use packetry_core::{
+    annotations, errors, i18n, native, sanitize, usb_ids, usbpcap};
 
-mod backend;
-mod capture;
-mod compact_index;
-mod data_stream;
-pub mod decoder;
+pub mod cli;
+
+pub mod activity;
+pub mod alerts;
+pub mod coloring;
+pub mod columns;
+pub mod config;
+pub mod copy;
+pub mod diff;
+pub mod follow;
+pub mod frames;
+pub mod goto;
+pub mod graph;
+pub mod heatmap;
+pub mod hexdump;
+pub mod layout;
+pub mod palette;
+pub mod plot;
+pub mod preferences;
+pub mod profiles;
+pub mod recent;
+pub mod requests;
+pub mod search;
+pub mod sequence;
+pub mod session;
+pub mod splits;
+pub mod stats;
+pub mod timeline;
+
+// Everything below is only needed by the GUI.
+#[cfg(feature="gui")]
 mod expander;
-mod id;
-mod index_stream;
+#[cfg(feature="gui")]
 pub mod model;
-mod rcu;
+#[cfg(feature="gui")]
 pub mod row_data;
-mod stream;
+#[cfg(feature="gui")]
 mod tree_list_model;
+#[cfg(feature="gui")]
 pub mod ui;
-mod usb;
-mod util;
-mod vec_map;
 
 #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
 pub mod record_ui;
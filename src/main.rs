@@ -3,6 +3,7 @@ use gtk::gio::ApplicationFlags;
 
 use packetry::ui::{
     activate,
+    discard_autosave,
     display_error,
     stop_cynthion
 };
@@ -15,4 +16,5 @@ fn main() {
     application.connect_activate(|app| display_error(activate(app)));
     application.run_with_args::<&str>(&[]);
     display_error(stop_cynthion());
+    display_error(discard_autosave());
 }
@@ -4,13 +4,16 @@ use gio::subclass::prelude::*;
 use gtk::{gio, glib, prelude::*};
 
 use std::cell::RefCell;
+use std::sync::Arc;
 use crate::capture::{TrafficItem, DeviceItem};
+use crate::coloring::ColorRules;
 use crate::row_data::{TrafficRowData, DeviceRowData};
 use crate::tree_list_model::TreeListModel;
 
 #[derive(Default)]
 pub struct TrafficModel {
     pub(super) tree: RefCell<Option<TreeListModel<TrafficItem, super::TrafficModel, TrafficRowData>>>,
+    pub(super) color_rules: RefCell<Arc<ColorRules>>,
 }
 
 #[derive(Default)]
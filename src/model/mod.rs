@@ -13,8 +13,12 @@ use gtk::{gio, glib};
 
 use anyhow::Error;
 
-use crate::capture::{CaptureReader, TrafficItem, DeviceItem};
-use crate::tree_list_model::{TreeListModel, ItemNodeRc};
+use std::sync::Arc;
+
+use crate::capture::{CaptureReader, ItemSource, TrafficItem, DeviceItem};
+use crate::coloring::ColorRules;
+use crate::columns::{Column, TimeBase, TimestampMode};
+use crate::tree_list_model::{TreeListModel, ItemNode, ItemNodeRc};
 
 // Public part of the Model type.
 glib::wrapper! {
@@ -37,6 +41,51 @@ pub trait GenericModel<Item> where Self: Sized {
     fn update(&self) -> Result<bool, Error>;
     fn summary(&self, item: &Item) -> String;
     fn connectors(&self, item: &Item) -> String;
+    fn style(&self, item: &Item) -> Option<String>;
+    fn reveal(&self, path: &[u64]) -> Result<u32, Error>;
+    fn capture(&self) -> CaptureReader;
+}
+
+impl TrafficModel {
+    /// Set the rules used to color traffic items.
+    pub fn set_color_rules(&self, rules: Arc<ColorRules>) {
+        self.imp().color_rules.replace(rules);
+    }
+
+    /// Text shown in a column for the traffic item at a node.
+    pub fn column_text(&self,
+                       column: &Column,
+                       node: &ItemNode<TrafficItem>,
+                       base: &TimeBase)
+        -> String
+    {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        let result = tree.with_capture(|capture| {
+            let mut base = *base;
+            if matches!(column, Column::Timestamp) &&
+                base.mode == TimestampMode::SincePrevious
+            {
+                let (parent, index) = node.position()?;
+                if index > 0 {
+                    base.previous =
+                        Some(capture.item(parent.as_ref(), index - 1)?);
+                }
+            }
+            column.cell(capture, &node.item, &base)
+        });
+        match result {
+            Ok(cell) => cell.text,
+            Err(e) => format!("Error: {e}"),
+        }
+    }
+
+    /// Paths of indices to all expanded items, with parents first.
+    pub fn expanded_paths(&self) -> Vec<Vec<u64>> {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        tree.expanded_paths()
+    }
 }
 
 impl GenericModel<TrafficItem> for TrafficModel {
@@ -74,7 +123,11 @@ impl GenericModel<TrafficItem> for TrafficModel {
     fn summary(&self, item: &TrafficItem) -> String {
         let tree_opt = self.imp().tree.borrow();
         let tree = tree_opt.as_ref().unwrap();
-        tree.summary(item)
+        let summary = tree.summary(item);
+        match tree.annotations().get(item) {
+            Some(annotation) => annotation.describe(&summary),
+            None => summary,
+        }
     }
 
     fn connectors(&self, item: &TrafficItem) -> String {
@@ -82,6 +135,28 @@ impl GenericModel<TrafficItem> for TrafficModel {
         let tree = tree_opt.as_ref().unwrap();
         tree.connectors(item)
     }
+
+    fn style(&self, item: &TrafficItem) -> Option<String> {
+        let rules = self.imp().color_rules.borrow();
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        match tree.with_capture(|capture| rules.find(capture, item)) {
+            Ok(Some(index)) => Some(ColorRules::class_name(index)),
+            _ => None
+        }
+    }
+
+    fn reveal(&self, path: &[u64]) -> Result<u32, Error> {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        Ok(u32::try_from(tree.reveal(self, path)?)?)
+    }
+
+    fn capture(&self) -> CaptureReader {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        tree.capture()
+    }
 }
 
 impl GenericModel<DeviceItem> for DeviceModel {
@@ -127,4 +202,20 @@ impl GenericModel<DeviceItem> for DeviceModel {
         let tree = tree_opt.as_ref().unwrap();
         tree.connectors(item)
     }
+
+    fn style(&self, _item: &DeviceItem) -> Option<String> {
+        None
+    }
+
+    fn reveal(&self, path: &[u64]) -> Result<u32, Error> {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        Ok(u32::try_from(tree.reveal(self, path)?)?)
+    }
+
+    fn capture(&self) -> CaptureReader {
+        let tree_opt = self.imp().tree.borrow();
+        let tree = tree_opt.as_ref().unwrap();
+        tree.capture()
+    }
 }
@@ -0,0 +1,90 @@
+//! Matching of commands in the command palette against a search.
+
+use std::cmp::Reverse;
+
+/// Display filters offered in the command palette, with their names.
+pub const FILTER_PRESETS: [(&str, &str); 7] = [
+    ("Items with errors", "error == yes"),
+    ("Control transfers", "transfer.type == control"),
+    ("Bulk transfers", "transfer.type == bulk"),
+    ("Interrupt transfers", "transfer.type == interrupt"),
+    ("Isochronous transfers", "transfer.type == isochronous"),
+    ("Everything but polling", "polling == no"),
+    ("Everything but SOFs", "transfer.type != framing"),
+];
+
+/// Score how well a search matches a command's label, if it matches at
+/// all.
+///
+/// The characters of the search must appear in the label in order,
+/// ignoring case and spaces. Matches at the starts of words, and runs of
+/// consecutive characters, score higher.
+pub fn score(search: &str, label: &str) -> Option<u32> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut start = 0;
+    let mut previous = None;
+    for wanted in search
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+    {
+        let found = (start..label.len()).find(|&i| label[i] == wanted)?;
+        score += 1;
+        if found == 0 || !label[found - 1].is_alphanumeric() {
+            score += 8;
+        }
+        if found > 0 && previous == Some(found - 1) {
+            score += 4;
+        }
+        previous = Some(found);
+        start = found + 1;
+    }
+    Some(score)
+}
+
+/// Indices of the labels matching a search, best first. Labels which
+/// score equally stay in their original order.
+pub fn rank<S: AsRef<str>>(search: &str, labels: &[S]) -> Vec<usize> {
+    let mut matches: Vec<(u32, usize)> = labels
+        .iter()
+        .enumerate()
+        .filter_map(|(index, label)|
+            score(search, label.as_ref()).map(|score| (score, index)))
+        .collect();
+    matches.sort_by_key(|(score, index)| (Reverse(*score), *index));
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Filter;
+
+    #[test]
+    fn test_rank() {
+        let labels = [
+            "Open capture…",
+            "Hide SOF packets",
+            "Select the next item with an error",
+            "Statistics…",
+        ];
+        assert_eq!(rank("", &labels), vec![0, 1, 2, 3]);
+        assert_eq!(rank("sof", &labels), vec![1]);
+        assert_eq!(rank("opn", &labels), vec![0]);
+        assert_eq!(rank("next error", &labels), vec![2]);
+        assert!(rank("xyz", &labels).is_empty());
+
+        // Starts of words outrank letters within them.
+        assert_eq!(rank("st", &labels)[0], 3);
+        assert!(score("hs", "Hide SOF").unwrap() >
+                score("hs", "Whose").unwrap());
+    }
+
+    #[test]
+    fn test_filter_presets() {
+        for (_, filter) in FILTER_PRESETS {
+            assert!(Filter::parse(filter).is_ok(), "{filter}");
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Values read from the same bytes of each payload on an endpoint, to be
+//! plotted over the time of a capture.
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, EndpointId, Timestamp};
+
+/// How the bytes of a value are read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueFormat {
+    U8,
+    I8,
+    U16Le,
+    I16Le,
+    U16Be,
+    I16Be,
+    U32Le,
+    I32Le,
+    U32Be,
+    I32Be,
+}
+
+impl ValueFormat {
+    pub const ALL: [ValueFormat; 10] = [
+        ValueFormat::U8,
+        ValueFormat::I8,
+        ValueFormat::U16Le,
+        ValueFormat::I16Le,
+        ValueFormat::U16Be,
+        ValueFormat::I16Be,
+        ValueFormat::U32Le,
+        ValueFormat::I32Le,
+        ValueFormat::U32Be,
+        ValueFormat::I32Be,
+    ];
+
+    /// Description of this format, as offered in the plot window.
+    pub fn label(&self) -> &'static str {
+        use ValueFormat::*;
+        match self {
+            U8 => "Unsigned 8-bit",
+            I8 => "Signed 8-bit",
+            U16Le => "Unsigned 16-bit, little-endian",
+            I16Le => "Signed 16-bit, little-endian",
+            U16Be => "Unsigned 16-bit, big-endian",
+            I16Be => "Signed 16-bit, big-endian",
+            U32Le => "Unsigned 32-bit, little-endian",
+            I32Le => "Signed 32-bit, little-endian",
+            U32Be => "Unsigned 32-bit, big-endian",
+            I32Be => "Signed 32-bit, big-endian",
+        }
+    }
+
+    /// Number of bytes read.
+    pub fn width(&self) -> usize {
+        use ValueFormat::*;
+        match self {
+            U8 | I8 => 1,
+            U16Le | I16Le | U16Be | I16Be => 2,
+            U32Le | I32Le | U32Be | I32Be => 4,
+        }
+    }
+
+    fn signed(&self) -> bool {
+        use ValueFormat::*;
+        matches!(self, I8 | I16Le | I16Be | I32Le | I32Be)
+    }
+
+    fn big_endian(&self) -> bool {
+        use ValueFormat::*;
+        matches!(self, U16Be | I16Be | U32Be | I32Be)
+    }
+
+    /// The format first offered for a field of the given length. USB
+    /// values are little-endian.
+    pub fn for_length(length: usize) -> ValueFormat {
+        match length {
+            2 => ValueFormat::U16Le,
+            4 => ValueFormat::U32Le,
+            _ => ValueFormat::U8,
+        }
+    }
+
+    /// Read a value at an offset in a payload, if the payload is long
+    /// enough to hold it.
+    pub fn read(&self, payload: &[u8], offset: usize) -> Option<f64> {
+        let width = self.width();
+        let bytes = payload.get(offset..offset.checked_add(width)?)?;
+        let mut value = 0_u64;
+        if self.big_endian() {
+            for byte in bytes {
+                value = value << 8 | *byte as u64;
+            }
+        } else {
+            for byte in bytes.iter().rev() {
+                value = value << 8 | *byte as u64;
+            }
+        }
+        let bits = width * 8;
+        Some(if self.signed() && value >> (bits - 1) != 0 {
+            value as f64 - (1_u64 << bits) as f64
+        } else {
+            value as f64
+        })
+    }
+}
+
+/// Values read from each payload carried on an endpoint, with the times
+/// they were sent.
+pub struct FieldPlot {
+    pub endpoint_id: EndpointId,
+    /// Offset of the value in each payload.
+    pub offset: usize,
+    pub format: ValueFormat,
+    pub samples: Vec<(Timestamp, f64)>,
+    /// Number of the endpoint's payloads read so far.
+    payloads_read: u64,
+}
+
+impl FieldPlot {
+    pub fn new(endpoint_id: EndpointId, offset: usize, format: ValueFormat)
+        -> FieldPlot
+    {
+        FieldPlot {
+            endpoint_id,
+            offset,
+            format,
+            samples: Vec::new(),
+            payloads_read: 0,
+        }
+    }
+
+    /// Read values from up to `limit` payloads seen since the last update.
+    /// Payloads too short to hold the value are skipped. Returns whether
+    /// any payloads were read.
+    pub fn update(&mut self, capture: &mut CaptureReader, limit: u64)
+        -> Result<bool, Error>
+    {
+        let count = capture.endpoint_payload_count(self.endpoint_id)?;
+        let end = count.min(self.payloads_read.saturating_add(limit));
+        let start = self.payloads_read;
+        for index in start..end {
+            let (transaction_id, _, payload) =
+                capture.endpoint_payload(self.endpoint_id, index)?;
+            if let Some(value) = self.format.read(&payload, self.offset) {
+                let packet_id = capture.transaction_index.get(transaction_id)?;
+                let time = capture.packet_time(packet_id)?;
+                self.samples.push((time, value));
+            }
+        }
+        self.payloads_read = end;
+        Ok(end > start)
+    }
+
+    /// The lowest and highest values read, if any.
+    pub fn value_range(&self) -> Option<(f64, f64)> {
+        let mut values = self.samples.iter().map(|(_, value)| *value);
+        let first = values.next()?;
+        Some(values.fold((first, first), |(low, high), value|
+            (low.min(value), high.max(value))))
+    }
+
+    /// The times of the first and last values read, if any.
+    pub fn time_range(&self) -> Option<(Timestamp, Timestamp)> {
+        let first = self.samples.first()?.0;
+        let last = self.samples.last()?.0;
+        Some((first, last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{ItemSource, TrafficItem};
+    use crate::loader::load;
+    use crate::usb::EndpointNum;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_value_formats() {
+        let payload = [0x01, 0x80, 0xFF, 0xFF, 0x7F];
+        assert_eq!(ValueFormat::U8.read(&payload, 1), Some(128.0));
+        assert_eq!(ValueFormat::I8.read(&payload, 1), Some(-128.0));
+        assert_eq!(ValueFormat::U16Le.read(&payload, 0), Some(32769.0));
+        assert_eq!(ValueFormat::U16Be.read(&payload, 0), Some(384.0));
+        assert_eq!(ValueFormat::I16Le.read(&payload, 2), Some(-1.0));
+        assert_eq!(ValueFormat::I16Be.read(&payload, 3), Some(-129.0));
+        assert_eq!(ValueFormat::I32Le.read(&payload, 1), Some(2147483520.0));
+        assert_eq!(ValueFormat::I32Be.read(&payload, 1), Some(-2130706561.0));
+        assert_eq!(ValueFormat::U32Be.read(&payload, 1), Some(2164260735.0));
+        assert_eq!(ValueFormat::U32Le.read(&payload, 2), None);
+        assert_eq!(ValueFormat::U8.read(&payload, usize::MAX), None);
+        assert_eq!(ValueFormat::for_length(2), ValueFormat::U16Le);
+        assert_eq!(ValueFormat::for_length(8), ValueFormat::U8);
+    }
+
+    #[test]
+    fn test_field_plot() {
+        let mut capture =
+            load(&[PathBuf::from("./tests/mouse/capture.pcap")]).unwrap();
+        // Find the endpoint of the mouse's reports.
+        let mut endpoint_id = None;
+        for index in 0..capture.item_index.len() {
+            let item: TrafficItem = capture.item(None, index).unwrap();
+            let endpoint = capture.item_endpoint(&item).unwrap();
+            if endpoint.number() == EndpointNum(1) {
+                endpoint_id = Some(capture.item_endpoint_id(&item).unwrap());
+                break;
+            }
+        }
+        let endpoint_id = endpoint_id.unwrap();
+        let count = capture.endpoint_payload_count(endpoint_id).unwrap();
+        assert!(count > 10);
+
+        // Values are read a few payloads at a time.
+        let mut plot = FieldPlot::new(endpoint_id, 1, ValueFormat::I8);
+        assert!(plot.update(&mut capture, 10).unwrap());
+        assert_eq!(plot.samples.len(), 10);
+        while plot.update(&mut capture, 10).unwrap() {}
+        assert_eq!(plot.samples.len() as u64, count);
+        let (first, last) = plot.time_range().unwrap();
+        assert!(first < last);
+        assert!(plot.samples.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        let (low, high) = plot.value_range().unwrap();
+        assert!((-128.0..=127.0).contains(&low));
+        assert!(low <= high && high <= 127.0);
+
+        // Payloads too short for the value are skipped.
+        let mut plot = FieldPlot::new(endpoint_id, 1000, ValueFormat::U8);
+        while plot.update(&mut capture, 100).unwrap() {}
+        assert!(plot.samples.is_empty());
+        assert_eq!(plot.value_range(), None);
+    }
+}
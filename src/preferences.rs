@@ -0,0 +1,121 @@
+//! Preferences for how the application behaves by default, saved between
+//! runs.
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::config::JsonFile;
+use crate::decoder::PIPELINE_DEPTH;
+
+/// Which set of interface colors is used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    /// Follow the GTK theme in use.
+    Automatic,
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub const ALL: [ColorScheme; 3] = [
+        ColorScheme::Automatic,
+        ColorScheme::Light,
+        ColorScheme::Dark,
+    ];
+
+    /// Description of this scheme, as shown in the preferences window.
+    pub fn label(&self) -> &'static str {
+        use ColorScheme::*;
+        match self {
+            Automatic => "Follow the system theme",
+            Light => "Light",
+            Dark => "Dark",
+        }
+    }
+}
+
+/// The user's preferences. Preferences not saved keep their defaults.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// The speed selected when a device is found, as listed in the speed
+    /// selector, or empty to select the first listed.
+    pub speed: String,
+    /// Number of captured packets which may wait to be decoded before
+    /// reading from the device is held up.
+    #[serde(deserialize_with = "deserialize_depth")]
+    pub pipeline_depth: usize,
+    /// Whether SOF packets are hidden when the application starts.
+    pub hide_sof: bool,
+    /// Whether polling answered with NAKs is hidden when the application
+    /// starts.
+    pub hide_polling: bool,
+    pub color_scheme: ColorScheme,
+    /// Whether captured packets are saved to a recovery file while
+    /// capturing.
+    pub autosave: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            speed: String::new(),
+            pipeline_depth: PIPELINE_DEPTH,
+            hide_sof: false,
+            hide_polling: false,
+            color_scheme: ColorScheme::Automatic,
+            autosave: true,
+        }
+    }
+}
+
+impl Preferences {
+    pub fn new() -> Self {
+        Preferences::default()
+    }
+}
+
+impl JsonFile for Preferences {
+    const CONTENTS: &'static str = "preferences";
+}
+
+fn deserialize_depth<'de, D>(deserializer: D) -> Result<usize, D::Error>
+    where D: Deserializer<'de>
+{
+    match usize::deserialize(deserializer)? {
+        0 => Err(de::Error::custom(
+            "The buffer must hold at least one packet")),
+        depth => Ok(depth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences() {
+        let preferences = Preferences {
+            speed: String::from("Full (12Mbps)"),
+            pipeline_depth: 256,
+            hide_sof: true,
+            hide_polling: true,
+            color_scheme: ColorScheme::Dark,
+            autosave: false,
+        };
+        let loaded = Preferences::from_json(&preferences.to_json().unwrap())
+            .unwrap();
+        assert_eq!(loaded, preferences);
+
+        // Preferences missing from the file keep their defaults.
+        let loaded = Preferences::from_json("{\"hide_sof\": true}").unwrap();
+        assert_eq!(loaded, Preferences {
+            hide_sof: true,
+            ..Preferences::new()
+        });
+        assert!(loaded.autosave);
+
+        assert!(Preferences::from_json("{\"pipeline_depth\": 0}").is_err());
+        assert!(Preferences::from_json("{\"color_scheme\": \"x\"}").is_err());
+    }
+}
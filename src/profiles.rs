@@ -0,0 +1,132 @@
+//! Named capture profiles, holding the settings used to start a capture,
+//! for switching quickly between test setups.
+
+use std::path::PathBuf;
+
+use anyhow::{Error, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::config::JsonFile;
+
+/// Settings for saving packets to files while capturing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamProfile {
+    pub path: PathBuf,
+    /// Maximum size of each file in MB, or 0 for no limit.
+    #[serde(default)]
+    pub max_file_size: u64,
+    /// Maximum number of files kept, or 0 for no limit.
+    #[serde(default)]
+    pub max_files: u64,
+}
+
+/// The settings used to start a capture.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// The device, as listed in the device selector.
+    #[serde(default)]
+    pub device: String,
+    /// The speed, as listed in the speed selector.
+    #[serde(default)]
+    pub speed: String,
+    #[serde(default)]
+    pub capture_filter: String,
+    /// Snap length in bytes, or 0 to store packets in full.
+    #[serde(default)]
+    pub snap_length: u32,
+    #[serde(default)]
+    pub packets_only: bool,
+    #[serde(default)]
+    pub trigger: String,
+    #[serde(default)]
+    pub stream: Option<StreamProfile>,
+}
+
+/// The saved capture profiles, in the order they were first saved.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+}
+
+impl Profiles {
+    pub fn new() -> Self {
+        Profiles::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Profile> {
+        self.profiles.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Add a profile, replacing any with the same name.
+    pub fn set(&mut self, profile: Profile) -> Result<(), Error> {
+        if profile.name.trim().is_empty() {
+            bail!("A capture profile must have a name")
+        }
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.profiles.retain(|profile| profile.name != name);
+    }
+}
+
+impl JsonFile for Profiles {
+    const CONTENTS: &'static str = "capture profiles";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let mut profiles = Profiles::new();
+        profiles.set(Profile {
+            name: String::from("Keyboard"),
+            device: String::from("Cynthion"),
+            speed: String::from("Full (12Mbps)"),
+            capture_filter: String::from("pid=!SOF"),
+            ..Profile::default()
+        }).unwrap();
+        profiles.set(Profile {
+            name: String::from("Soak test"),
+            snap_length: 16,
+            packets_only: true,
+            trigger: String::from("summary contains STALL"),
+            stream: Some(StreamProfile {
+                path: PathBuf::from("/tmp/soak.pcap"),
+                max_file_size: 100,
+                max_files: 10,
+            }),
+            ..Profile::default()
+        }).unwrap();
+        assert!(profiles.set(Profile::default()).is_err());
+
+        // A profile saved again under the same name is replaced.
+        let mut keyboard = profiles.get("Keyboard").unwrap().clone();
+        keyboard.speed = String::from("Low (1.5Mbps)");
+        profiles.set(keyboard).unwrap();
+        let names: Vec<&str> = profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect();
+        assert_eq!(names, ["Keyboard", "Soak test"]);
+        assert_eq!(profiles.get("Keyboard").unwrap().speed, "Low (1.5Mbps)");
+
+        let json = profiles.to_json().unwrap();
+        let restored = Profiles::from_json(&json).unwrap();
+        assert_eq!(restored, profiles);
+        profiles.remove("Keyboard");
+        assert!(profiles.get("Keyboard").is_none());
+        assert!(Profiles::from_json("{\"profiles\": [{}]}").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! The captures opened recently, with details of each, and captures pinned
+//! to stay in the list.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::capture::{CaptureReader, PacketId};
+use crate::config::JsonFile;
+use crate::util::{fmt_count, fmt_size};
+
+/// Number of captures kept in the list, besides those pinned.
+pub const MAX_RECENT: usize = 10;
+
+/// A capture file opened recently.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentCapture {
+    pub path: PathBuf,
+    /// Whether the capture stays in the list however many others are
+    /// opened after it.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Size of the file in bytes.
+    #[serde(default)]
+    pub size: u64,
+    /// Time from the first packet to the last, in nanoseconds.
+    #[serde(default)]
+    pub duration: u64,
+    /// Number of devices seen, not counting the default address.
+    #[serde(default)]
+    pub devices: u64,
+    #[serde(default)]
+    pub packets: u64,
+}
+
+impl RecentCapture {
+    /// Describe a capture that has been loaded from a file.
+    pub fn new(path: &Path, capture: &mut CaptureReader)
+        -> Result<RecentCapture, Error>
+    {
+        let size = fs::metadata(path).with_context(|| format!(
+            "Failed to read details of {}", path.display()))?.len();
+        let packets = capture.packet_times.len();
+        let duration = if packets > 0 {
+            let first = capture.packet_time(PacketId::from(0))?;
+            let last = capture.packet_time(PacketId::from(packets - 1))?;
+            last.saturating_sub(first)
+        } else {
+            0
+        };
+        Ok(RecentCapture {
+            path: path.to_path_buf(),
+            pinned: false,
+            size,
+            duration,
+            devices: capture.devices.len().saturating_sub(1),
+            packets,
+        })
+    }
+
+    /// Name of the file, without its directory.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map_or_else(
+                || self.path.to_string_lossy().to_string(),
+                |name| name.to_string_lossy().to_string())
+    }
+
+    /// Summary of the capture's size, duration and contents.
+    pub fn details(&self) -> String {
+        format!("{}, {:.1} s, {} {}, {} {}",
+            fmt_size(self.size),
+            self.duration as f64 / 1e9,
+            self.devices,
+            if self.devices == 1 { "device" } else { "devices" },
+            fmt_count(self.packets),
+            if self.packets == 1 { "packet" } else { "packets" })
+    }
+}
+
+/// The captures opened recently, pinned captures first, then the others
+/// from the most recently opened.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentCaptures {
+    #[serde(deserialize_with = "deserialize_captures")]
+    captures: Vec<RecentCapture>,
+}
+
+/// Read a saved list, putting pinned captures first and keeping at most
+/// `MAX_RECENT` others.
+fn deserialize_captures<'de, D>(deserializer: D)
+    -> Result<Vec<RecentCapture>, D::Error>
+    where D: Deserializer<'de>
+{
+    let mut captures = Vec::<RecentCapture>::deserialize(deserializer)?;
+    captures.sort_by_key(|capture| !capture.pinned);
+    let pinned = captures.iter().filter(|capture| capture.pinned).count();
+    captures.truncate(pinned + MAX_RECENT);
+    Ok(captures)
+}
+
+impl RecentCaptures {
+    pub fn new() -> Self {
+        RecentCaptures::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&RecentCapture> {
+        self.captures.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+
+    /// Record that a capture was opened, updating its details if it was
+    /// already listed. The oldest unpinned captures are dropped to keep
+    /// at most `MAX_RECENT` of them.
+    pub fn add(&mut self, capture: RecentCapture) {
+        match self.captures.iter().position(|c| c.path == capture.path) {
+            Some(index) if self.captures[index].pinned => {
+                self.captures[index] = RecentCapture {
+                    pinned: true,
+                    ..capture
+                };
+            },
+            existing => {
+                if let Some(index) = existing {
+                    self.captures.remove(index);
+                }
+                let first_unpinned = self.pinned_count();
+                self.captures.insert(first_unpinned, RecentCapture {
+                    pinned: false,
+                    ..capture
+                });
+            }
+        }
+        let limit = self.pinned_count() + MAX_RECENT;
+        self.captures.truncate(limit);
+    }
+
+    /// Pin a capture to keep it in the list, or unpin it.
+    ///
+    /// Pinned captures are listed in the order they were pinned. A capture
+    /// unpinned is listed as the most recent of the others.
+    pub fn set_pinned(&mut self, path: &Path, pinned: bool) {
+        let index = match self.captures.iter().position(|c| c.path == path) {
+            Some(index) if self.captures[index].pinned != pinned => index,
+            _ => return,
+        };
+        let mut capture = self.captures.remove(index);
+        capture.pinned = pinned;
+        let position = self.pinned_count();
+        self.captures.insert(position, capture);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.captures.retain(|capture| capture.path != path);
+    }
+
+    /// Remove all captures which are not pinned.
+    pub fn clear(&mut self) {
+        self.captures.retain(|capture| capture.pinned);
+    }
+
+    fn pinned_count(&self) -> usize {
+        self.captures.iter().filter(|capture| capture.pinned).count()
+    }
+}
+
+impl JsonFile for RecentCaptures {
+    const CONTENTS: &'static str = "recent captures";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recent(name: &str) -> RecentCapture {
+        RecentCapture {
+            path: PathBuf::from(name),
+            size: 2048,
+            duration: 1_500_000_000,
+            devices: 2,
+            packets: 1234,
+            ..RecentCapture::default()
+        }
+    }
+
+    fn names(recent: &RecentCaptures) -> Vec<String> {
+        recent.iter().map(RecentCapture::name).collect()
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut list = RecentCaptures::new();
+        for i in 0..12 {
+            list.add(recent(&format!("{i}.pcap")));
+        }
+        assert_eq!(list.iter().count(), MAX_RECENT);
+        assert_eq!(names(&list)[0], "11.pcap");
+        assert_eq!(names(&list)[9], "2.pcap");
+
+        // Pinned captures come first, and are kept when others are added.
+        list.set_pinned(Path::new("5.pcap"), true);
+        list.set_pinned(Path::new("2.pcap"), true);
+        for i in 20..30 {
+            list.add(recent(&format!("{i}.pcap")));
+        }
+        let expected_start = ["5.pcap", "2.pcap", "29.pcap"];
+        assert_eq!(&names(&list)[..3], expected_start);
+        assert_eq!(list.iter().count(), MAX_RECENT + 2);
+
+        // Opening a listed capture again moves it to the top, unless it is
+        // pinned.
+        list.add(recent("25.pcap"));
+        list.add(recent("2.pcap"));
+        assert_eq!(&names(&list)[..3], ["5.pcap", "2.pcap", "25.pcap"]);
+        assert!(list.iter().nth(1).unwrap().pinned);
+
+        let json = list.to_json().unwrap();
+        let loaded = RecentCaptures::from_json(&json).unwrap();
+        assert_eq!(loaded, list);
+
+        list.set_pinned(Path::new("5.pcap"), false);
+        assert_eq!(&names(&list)[..2], ["2.pcap", "5.pcap"]);
+        list.remove(Path::new("5.pcap"));
+        list.clear();
+        assert_eq!(names(&list), ["2.pcap"]);
+
+        assert_eq!(recent("a.pcap").details(),
+                   "2 KiB, 1.5 s, 2 devices, 1,234 packets");
+        assert!(RecentCaptures::from_json("{\"captures\": [{}]}").is_err());
+    }
+}
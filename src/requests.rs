@@ -0,0 +1,218 @@
+//! Statistics on the control requests made in a capture, and how devices
+//! responded to them.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, TrafficItem, TrafficItemId};
+use crate::export::csv_quote;
+use crate::usb::{ControlResult, RequestType, StandardRequest};
+
+/// A kind of control request made to a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RequestKind {
+    /// Address of the device the request was made to.
+    pub device: u8,
+    /// Type of the request: standard, class, vendor or reserved.
+    pub request_type: u8,
+    /// The request code, as sent in `bRequest`.
+    pub request: u8,
+}
+
+impl RequestKind {
+    pub fn name(&self) -> String {
+        let request_type = RequestType::from(self.request_type);
+        match (request_type, StandardRequest::from(self.request)) {
+            (RequestType::Standard, StandardRequest::Unknown) =>
+                format!("Standard request #{}", self.request),
+            (RequestType::Standard, request) => format!("{request:?}"),
+            (request_type, _) =>
+                format!("{request_type:?} request #{}", self.request),
+        }
+    }
+}
+
+/// Counts of the outcomes of one kind of request.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestCounts {
+    pub completed: u64,
+    pub stalled: u64,
+    /// Requests which were incomplete or aborted.
+    pub unfinished: u64,
+    /// Total time from the SETUP packet to the end of the status stage,
+    /// of requests which completed or stalled, in nanoseconds.
+    pub total_latency: u64,
+}
+
+impl RequestCounts {
+    fn add(&mut self, other: &RequestCounts) {
+        self.completed += other.completed;
+        self.stalled += other.stalled;
+        self.unfinished += other.unfinished;
+        self.total_latency += other.total_latency;
+    }
+
+    /// Number of requests made.
+    pub fn count(&self) -> u64 {
+        self.completed + self.stalled + self.unfinished
+    }
+
+    /// Fraction of the requests which completed or stalled that stalled.
+    pub fn stall_ratio(&self) -> f64 {
+        match self.completed + self.stalled {
+            0 => 0.0,
+            finished => self.stalled as f64 / finished as f64,
+        }
+    }
+
+    /// Average time taken by requests which completed or stalled, in
+    /// nanoseconds.
+    pub fn average_latency(&self) -> Option<u64> {
+        match self.completed + self.stalled {
+            0 => None,
+            finished => Some(self.total_latency / finished),
+        }
+    }
+}
+
+/// Statistics on the control requests made in a capture.
+#[derive(Clone, Debug, Default)]
+pub struct RequestStatistics {
+    pub requests: BTreeMap<RequestKind, RequestCounts>,
+}
+
+impl RequestStatistics {
+    /// Count the control requests made in a capture, by kind.
+    pub fn new(capture: &mut CaptureReader)
+        -> Result<RequestStatistics, Error>
+    {
+        let mut stats = RequestStatistics::default();
+        for index in 0..capture.item_index.len() {
+            let item_id = TrafficItemId::from(index);
+            let transfer_id = capture.item_index.get(item_id)?;
+            let item = TrafficItem::Transfer(transfer_id);
+            let transfer = match capture.item_control_transfer(&item)? {
+                Some(transfer) => transfer,
+                None => continue,
+            };
+            let kind = RequestKind {
+                device: transfer.address.0,
+                request_type:
+                    transfer.fields.type_fields.request_type() as u8,
+                request: transfer.fields.request,
+            };
+            let mut counts = RequestCounts::default();
+            match transfer.result {
+                ControlResult::Completed => counts.completed = 1,
+                ControlResult::Stalled => counts.stalled = 1,
+                ControlResult::Incomplete | ControlResult::Aborted =>
+                    counts.unfinished = 1,
+            }
+            if counts.unfinished == 0 {
+                counts.total_latency = capture.transfer_duration(transfer_id)?;
+            }
+            stats.requests.entry(kind).or_default().add(&counts);
+        }
+        Ok(stats)
+    }
+
+    /// The addresses of the devices which requests were made to.
+    pub fn devices(&self) -> Vec<u8> {
+        let mut devices: Vec<u8> = self.requests
+            .keys()
+            .map(|kind| kind.device)
+            .collect();
+        devices.dedup();
+        devices
+    }
+
+    /// The requests made to one device, or to all devices.
+    pub fn requests(&self, device: Option<u8>)
+        -> impl Iterator<Item=(&RequestKind, &RequestCounts)>
+    {
+        self.requests
+            .iter()
+            .filter(move |(kind, _)| device.map_or(true, |d| kind.device == d))
+    }
+
+    /// Totals over the requests made to one device, or to all devices.
+    pub fn total(&self, device: Option<u8>) -> RequestCounts {
+        let mut total = RequestCounts::default();
+        for (_, counts) in self.requests(device) {
+            total.add(counts);
+        }
+        total
+    }
+
+    /// Write the requests made to one device, or to all devices, as CSV.
+    pub fn write_csv(&self, device: Option<u8>, writer: &mut dyn Write)
+        -> Result<(), Error>
+    {
+        writeln!(writer, "Device,Request,Count,Completed,Stalled,\
+                          Unfinished,Stall rate,Average time (ns)")?;
+        for (kind, counts) in self.requests(device) {
+            writeln!(writer, "{},{},{},{},{},{},{:.4},{}",
+                kind.device,
+                csv_quote(&kind.name()),
+                counts.count(),
+                counts.completed,
+                counts.stalled,
+                counts.unfinished,
+                counts.stall_ratio(),
+                counts.average_latency()
+                    .map_or_else(String::new, |latency| latency.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_request_statistics() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let stats = RequestStatistics::new(&mut capture).unwrap();
+        assert!(stats.devices().contains(&11));
+        let get_descriptor = RequestKind {
+            device: 11,
+            request_type: 0,
+            request: 6,
+        };
+        assert_eq!(get_descriptor.name(), "GetDescriptor");
+        let counts = stats.requests[&get_descriptor];
+        assert!(counts.completed > 0);
+        assert!(counts.average_latency().unwrap() > 0);
+
+        // Totals for one device are part of the totals for all.
+        let device = stats.total(Some(11));
+        let all = stats.total(None);
+        assert!(device.count() > 0);
+        assert!(device.count() <= all.count());
+        assert!(stats.requests(Some(11)).all(|(kind, _)| kind.device == 11));
+        let mut csv = Vec::new();
+        stats.write_csv(Some(11), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), stats.requests(Some(11)).count() + 1);
+        assert!(csv.contains("\n11,\"GetDescriptor\","));
+
+        let vendor = RequestKind { device: 1, request_type: 2, request: 1 };
+        assert_eq!(vendor.name(), "Vendor request #1");
+        let counts = RequestCounts {
+            completed: 3,
+            stalled: 1,
+            unfinished: 1,
+            total_latency: 4000,
+        };
+        assert_eq!(counts.count(), 5);
+        assert_eq!(counts.stall_ratio(), 0.25);
+        assert_eq!(counts.average_latency(), Some(1000));
+        assert_eq!(RequestCounts::default().average_latency(), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! Searching the traffic in a capture, by text, by bytes, for errors, or
+//! for items on an endpoint.
+
+use anyhow::{Context, Error, bail};
+use regex::Regex;
+use regex::bytes::Regex as BytesRegex;
+
+use crate::capture::{CaptureReader, EndpointId, ItemSource, TrafficItem};
+
+/// How the text entered for a search is interpreted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchKind {
+    /// Text to find in summaries or payloads, ignoring case.
+    Text,
+    /// A regular expression to match against summaries or payloads.
+    Regex,
+    /// Hex bytes to find in payloads, e.g. `55:AA`.
+    Bytes,
+}
+
+enum Matcher {
+    Text(String),
+    Regex(Regex, BytesRegex),
+    Bytes(Vec<u8>),
+    Error,
+    Endpoint(EndpointId),
+}
+
+/// A search through the traffic items of a capture.
+pub struct Search {
+    matcher: Matcher,
+    /// Whether to search only the siblings of the starting item.
+    same_level: bool,
+}
+
+impl Search {
+    pub fn new(pattern: &str, kind: SearchKind) -> Result<Search, Error> {
+        if pattern.is_empty() {
+            bail!("Nothing to search for")
+        }
+        let matcher = match kind {
+            SearchKind::Text => Matcher::Text(pattern.to_lowercase()),
+            SearchKind::Regex => Matcher::Regex(
+                Regex::new(pattern).context("Invalid regular expression")?,
+                BytesRegex::new(pattern)
+                    .context("Invalid regular expression")?),
+            SearchKind::Bytes => Matcher::Bytes(parse_hex(pattern)?),
+        };
+        Ok(Search { matcher, same_level: false })
+    }
+
+    /// A search for items showing errors.
+    pub fn errors() -> Search {
+        Search { matcher: Matcher::Error, same_level: false }
+    }
+
+    /// A search for items on an endpoint, at the same level as the item
+    /// the search starts from.
+    pub fn endpoint(endpoint_id: EndpointId) -> Search {
+        Search { matcher: Matcher::Endpoint(endpoint_id), same_level: true }
+    }
+
+    /// Whether a traffic item matches this search.
+    pub fn matches(&self, capture: &mut CaptureReader, item: &TrafficItem)
+        -> Result<bool, Error>
+    {
+        let summary_match = match &self.matcher {
+            Matcher::Text(text) =>
+                capture.summary(item)?.to_lowercase().contains(text),
+            Matcher::Regex(regex, _) => regex.is_match(&capture.summary(item)?),
+            Matcher::Bytes(_) => false,
+            Matcher::Error => return capture.item_error(item),
+            Matcher::Endpoint(endpoint_id) =>
+                return Ok(capture.item_endpoint_id(item)? == *endpoint_id),
+        };
+        if summary_match {
+            return Ok(true);
+        }
+        let data = capture.item_data(item)?;
+        Ok(match &self.matcher {
+            Matcher::Text(text) => {
+                let pattern = text.as_bytes();
+                !pattern.is_empty() && data
+                    .windows(pattern.len())
+                    .any(|window| window.eq_ignore_ascii_case(pattern))
+            },
+            Matcher::Regex(_, regex) => regex.is_match(&data),
+            Matcher::Bytes(bytes) =>
+                data.windows(bytes.len()).any(|window| window == bytes),
+            Matcher::Error | Matcher::Endpoint(_) => false,
+        })
+    }
+
+    /// Find the next matching item in the order items are displayed.
+    ///
+    /// Items are identified by their path of indices through the tree of
+    /// traffic items. The search begins after the given item, or at the
+    /// start of the capture if None, and goes backwards if requested.
+    /// Searches at the same level begin at the top level if there is no
+    /// starting item.
+    pub fn find(&self,
+                capture: &mut CaptureReader,
+                start: Option<&[u64]>,
+                backwards: bool)
+        -> Result<Option<Vec<u64>>, Error>
+    {
+        let mut path = match start {
+            Some(path) => path.to_vec(),
+            None => {
+                let first = if backwards {
+                    last_item(capture, self.same_level)?
+                } else {
+                    first_item(capture)?
+                };
+                match first {
+                    Some(path) => {
+                        let item = item_at(capture, &path)?;
+                        if self.matches(capture, &item)? {
+                            return Ok(Some(path));
+                        }
+                        path
+                    },
+                    None => return Ok(None),
+                }
+            }
+        };
+        loop {
+            let next = match (backwards, self.same_level) {
+                (false, false) => next_item(capture, &path)?,
+                (true, false) => previous_item(capture, &path)?,
+                (false, true) => next_sibling(capture, &path)?,
+                (true, true) => previous_sibling(&path),
+            };
+            path = match next {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            let item = item_at(capture, &path)?;
+            if self.matches(capture, &item)? {
+                return Ok(Some(path));
+            }
+        }
+    }
+}
+
+/// Parse bytes written as hex, e.g. `55:AA`, `55 AA` or `55AA`.
+fn parse_hex(text: &str) -> Result<Vec<u8>, Error> {
+    let digits: String = text
+        .chars()
+        .filter(|c| !matches!(c, ':' | ' ' | ','))
+        .collect();
+    let valid = !digits.is_empty() &&
+        digits.len() % 2 == 0 &&
+        digits.chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        bail!("Invalid bytes '{text}', expected hex bytes such as 55:AA")
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for i in (0..digits.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&digits[i..i + 2], 16)?);
+    }
+    Ok(bytes)
+}
+
+fn item_at(capture: &mut CaptureReader, path: &[u64])
+    -> Result<TrafficItem, Error>
+{
+    let (first, rest) = path.split_first().context("Empty item path")?;
+    let mut item = capture.item(None, *first)?;
+    for index in rest {
+        item = capture.child_item(&item, *index)?;
+    }
+    Ok(item)
+}
+
+fn child_count(capture: &mut CaptureReader, path: &[u64])
+    -> Result<u64, Error>
+{
+    let (_, count) = if path.is_empty() {
+        ItemSource::<TrafficItem>::item_children(capture, None)?
+    } else {
+        let item = item_at(capture, path)?;
+        capture.item_children(Some(&item))?
+    };
+    Ok(count)
+}
+
+fn first_item(capture: &mut CaptureReader)
+    -> Result<Option<Vec<u64>>, Error>
+{
+    Ok(match child_count(capture, &[])? {
+        0 => None,
+        _ => Some(vec![0]),
+    })
+}
+
+fn last_item(capture: &mut CaptureReader, top_level: bool)
+    -> Result<Option<Vec<u64>>, Error>
+{
+    let mut path = match child_count(capture, &[])? {
+        0 => return Ok(None),
+        count => vec![count - 1],
+    };
+    if !top_level {
+        last_descendant(capture, &mut path)?;
+    }
+    Ok(Some(path))
+}
+
+/// Extend a path to the last descendant of the item it leads to.
+fn last_descendant(capture: &mut CaptureReader, path: &mut Vec<u64>)
+    -> Result<(), Error>
+{
+    loop {
+        match child_count(capture, path)? {
+            0 => return Ok(()),
+            count => path.push(count - 1),
+        }
+    }
+}
+
+/// The item after the given one, in depth-first order.
+fn next_item(capture: &mut CaptureReader, path: &[u64])
+    -> Result<Option<Vec<u64>>, Error>
+{
+    let mut path = path.to_vec();
+    if child_count(capture, &path)? > 0 {
+        path.push(0);
+        return Ok(Some(path));
+    }
+    while let Some(index) = path.pop() {
+        if index + 1 < child_count(capture, &path)? {
+            path.push(index + 1);
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// The item before the given one, in depth-first order.
+fn previous_item(capture: &mut CaptureReader, path: &[u64])
+    -> Result<Option<Vec<u64>>, Error>
+{
+    let mut path = path.to_vec();
+    match path.pop() {
+        None => Ok(None),
+        Some(0) if path.is_empty() => Ok(None),
+        Some(0) => Ok(Some(path)),
+        Some(index) => {
+            // Go to the last descendant of the previous sibling.
+            path.push(index - 1);
+            last_descendant(capture, &mut path)?;
+            Ok(Some(path))
+        }
+    }
+}
+
+/// The item after the given one with the same parent.
+fn next_sibling(capture: &mut CaptureReader, path: &[u64])
+    -> Result<Option<Vec<u64>>, Error>
+{
+    let mut path = path.to_vec();
+    let index = path.pop().context("Empty item path")?;
+    if index + 1 < child_count(capture, &path)? {
+        path.push(index + 1);
+        Ok(Some(path))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The item before the given one with the same parent.
+fn previous_sibling(path: &[u64]) -> Option<Vec<u64>> {
+    match path.split_last() {
+        Some((&index, parent)) if index > 0 => {
+            let mut path = parent.to_vec();
+            path.push(index - 1);
+            Some(path)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    fn find_all(capture: &mut CaptureReader, search: &Search, backwards: bool)
+        -> Vec<Vec<u64>>
+    {
+        let mut found = Vec::new();
+        let mut start: Option<Vec<u64>> = None;
+        while let Some(path) = search
+            .find(capture, start.as_deref(), backwards)
+            .unwrap()
+        {
+            found.push(path.clone());
+            start = Some(path);
+        }
+        found
+    }
+
+    #[test]
+    fn test_search() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+
+        let search = Search::new("STRING descriptor #2", SearchKind::Text)
+            .unwrap();
+        let found = find_all(&mut capture, &search, false);
+        assert_eq!(found.len(), 1);
+        let item = item_at(&mut capture, &found[0]).unwrap();
+        assert!(capture.summary(&item).unwrap()
+            .starts_with("Getting string descriptor #2"));
+
+        // Searching backwards finds the same items in reverse order.
+        let search = Search::new("12:01", SearchKind::Bytes).unwrap();
+        let mut forwards = find_all(&mut capture, &search, false);
+        let backwards = find_all(&mut capture, &search, true);
+        assert!(!forwards.is_empty());
+        forwards.reverse();
+        assert_eq!(forwards, backwards);
+
+        // The device descriptor is found in the transfer, its data stage
+        // transaction and the data packets carrying it.
+        assert_eq!(forwards.iter().filter(|path| path.len() == 1).count(), 1);
+
+        let search = Search::new(r"'(NXP|LPC)'$", SearchKind::Regex).unwrap();
+        assert_eq!(find_all(&mut capture, &search, false).len(), 2);
+
+        assert!(Search::new("5", SearchKind::Bytes).is_err());
+        assert!(Search::new("(", SearchKind::Regex).is_err());
+    }
+
+    #[test]
+    fn test_navigation() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+
+        let errors = find_all(&mut capture, &Search::errors(), false);
+        for path in &errors {
+            let item = item_at(&mut capture, path).unwrap();
+            assert!(capture.item_error(&item).unwrap());
+        }
+
+        // Items on the same endpoint are found at the same level. The first
+        // item is the only one on the framing endpoint, so start from the
+        // second.
+        let first = item_at(&mut capture, &[1]).unwrap();
+        let endpoint_id = capture.item_endpoint_id(&first).unwrap();
+        let search = Search::endpoint(endpoint_id);
+        let mut found = vec![vec![1]];
+        let mut start = vec![1];
+        while let Some(path) = search
+            .find(&mut capture, Some(start.as_slice()), false)
+            .unwrap()
+        {
+            assert_eq!(path.len(), 1);
+            let item = item_at(&mut capture, &path).unwrap();
+            assert_eq!(capture.item_endpoint_id(&item).unwrap(), endpoint_id);
+            found.push(path.clone());
+            start = path;
+        }
+        assert!(found.len() > 1);
+        let back = search
+            .find(&mut capture, Some(start.as_slice()), true)
+            .unwrap();
+        assert_eq!(back.as_ref(), found.iter().rev().nth(1));
+    }
+}
@@ -0,0 +1,200 @@
+//! Checking sequence numbers carried in the data on an endpoint, to find
+//! transfers which were lost or repeated.
+
+use anyhow::{Error, bail};
+
+use crate::follow::Chunk;
+
+/// Byte order of a sequence number field.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+/// Where a sequence number is found in the data of each transaction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FieldLocation {
+    /// Offset of the field from the start of the data, in bytes.
+    pub offset: usize,
+    /// Width of the field in bytes, from 1 to 8.
+    pub width: usize,
+    pub order: ByteOrder,
+}
+
+impl FieldLocation {
+    pub fn new(offset: usize, width: usize, order: ByteOrder)
+        -> Result<FieldLocation, Error>
+    {
+        if !(1..=8).contains(&width) {
+            bail!("Sequence number width must be from 1 to 8 bytes")
+        }
+        Ok(FieldLocation { offset, width, order })
+    }
+
+    /// Read the field from some data, if it is long enough.
+    pub fn read(&self, data: &[u8]) -> Option<u64> {
+        let bytes = data.get(self.offset..self.offset + self.width)?;
+        let fold = |value: u64, byte: &u8| (value << 8) | *byte as u64;
+        Some(match self.order {
+            ByteOrder::Big => bytes.iter().fold(0, fold),
+            ByteOrder::Little => bytes.iter().rev().fold(0, fold),
+        })
+    }
+
+    /// Mask of the values the field can hold.
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - 8 * self.width)
+    }
+}
+
+/// A break in the sequence, at a chunk of data numbered from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Finding {
+    /// Numbers were skipped.
+    Gap { chunk: usize, expected: u64, found: u64 },
+    /// The previous number was repeated.
+    Duplicate { chunk: usize, value: u64 },
+    /// The number went back, as when reordered or after a reset.
+    Backwards { chunk: usize, previous: u64, found: u64 },
+}
+
+impl Finding {
+    pub fn description(&self) -> String {
+        use Finding::*;
+        match self {
+            Gap { chunk, expected, found } => format!(
+                "Chunk {chunk}: expected {expected}, found {found}, \
+                 {} missing", found.wrapping_sub(*expected)),
+            Duplicate { chunk, value } => format!(
+                "Chunk {chunk}: {value} repeated"),
+            Backwards { chunk, previous, found } => format!(
+                "Chunk {chunk}: went back from {previous} to {found}"),
+        }
+    }
+}
+
+/// The result of checking the sequence numbers in a stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceCheck {
+    /// Number of chunks with a sequence number.
+    pub checked: usize,
+    /// Number of chunks too short to hold one.
+    pub short: usize,
+    /// Number of values skipped over by all gaps.
+    pub missing: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl SequenceCheck {
+    /// Check that the number in each chunk follows on from the last.
+    ///
+    /// Each number is expected to be one more than the previous, wrapping
+    /// around at the width of the field. A number further ahead than half
+    /// the range of the field is taken to have gone back.
+    pub fn new<'c>(chunks: impl Iterator<Item=&'c Chunk>,
+                   location: &FieldLocation)
+        -> SequenceCheck
+    {
+        let mask = location.mask();
+        let mut check = SequenceCheck::default();
+        let mut previous: Option<u64> = None;
+        for (index, chunk) in chunks.enumerate() {
+            let chunk_number = index + 1;
+            let value = match location.read(&chunk.data) {
+                Some(value) => value,
+                None => {
+                    check.short += 1;
+                    continue;
+                }
+            };
+            check.checked += 1;
+            if let Some(previous) = previous {
+                let expected = previous.wrapping_add(1) & mask;
+                let step = value.wrapping_sub(previous) & mask;
+                if step == 0 {
+                    check.findings.push(Finding::Duplicate {
+                        chunk: chunk_number,
+                        value,
+                    });
+                } else if step > mask / 2 {
+                    check.findings.push(Finding::Backwards {
+                        chunk: chunk_number,
+                        previous,
+                        found: value,
+                    });
+                } else if step > 1 {
+                    check.missing += step - 1;
+                    check.findings.push(Finding::Gap {
+                        chunk: chunk_number,
+                        expected,
+                        found: value,
+                    });
+                }
+            }
+            previous = Some(value);
+        }
+        check
+    }
+
+    /// A one-line summary of the check.
+    pub fn summary(&self) -> String {
+        let gaps = self.findings
+            .iter()
+            .filter(|finding| matches!(finding, Finding::Gap {..}))
+            .count();
+        let duplicates = self.findings
+            .iter()
+            .filter(|finding| matches!(finding, Finding::Duplicate {..}))
+            .count();
+        let backwards = self.findings.len() - gaps - duplicates;
+        let mut summary = format!(
+            "{} numbers checked, {gaps} gaps ({} missing), \
+             {duplicates} duplicates, {backwards} out of order",
+            self.checked, self.missing);
+        if self.short > 0 {
+            summary.push_str(&format!(
+                ", {} chunks too short", self.short));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::Direction;
+
+    #[test]
+    fn test_sequence_check() {
+        let location = FieldLocation::new(1, 2, ByteOrder::Little).unwrap();
+        assert_eq!(location.read(&[0xAA, 0x34, 0x12]), Some(0x1234));
+        assert_eq!(location.read(&[0xAA, 0x34]), None);
+        let big = FieldLocation::new(0, 2, ByteOrder::Big).unwrap();
+        assert_eq!(big.read(&[0x12, 0x34]), Some(0x1234));
+        assert!(FieldLocation::new(0, 9, ByteOrder::Big).is_err());
+
+        let chunks: Vec<Chunk> = [
+            vec![0xFE], vec![0xFF], vec![0x00], vec![0x03], vec![0x03],
+            vec![], vec![0x04], vec![0x02], vec![0x03],
+        ]
+            .into_iter()
+            .map(|data| Chunk { direction: Direction::In, timestamp: 0, data })
+            .collect();
+        let location = FieldLocation::new(0, 1, ByteOrder::Little).unwrap();
+        let check = SequenceCheck::new(chunks.iter(), &location);
+        assert_eq!(check.checked, 8);
+        assert_eq!(check.short, 1);
+        assert_eq!(check.missing, 2);
+        assert_eq!(check.findings, [
+            Finding::Gap { chunk: 4, expected: 1, found: 3 },
+            Finding::Duplicate { chunk: 5, value: 3 },
+            Finding::Backwards { chunk: 8, previous: 4, found: 2 },
+        ]);
+        assert_eq!(check.findings[0].description(),
+                   "Chunk 4: expected 1, found 3, 2 missing");
+        assert_eq!(check.summary(),
+                   "8 numbers checked, 1 gaps (2 missing), 1 duplicates, \
+                    1 out of order, 1 chunks too short");
+    }
+}
@@ -0,0 +1,73 @@
+//! The state of the user interface, saved on exit and restored on start.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::JsonFile;
+use crate::layout::Layout;
+
+/// The state of the user interface, as saved between runs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// Capture files open, more than one if they were merged.
+    pub files: Vec<PathBuf>,
+    /// Text of the display filter.
+    pub filter: String,
+    /// Paths of the expanded items in the traffic view, parents first.
+    pub expanded: Vec<Vec<u64>>,
+    /// Path of the selected item in the traffic view.
+    pub selected: Option<Vec<u64>>,
+    /// Position of the traffic view's vertical scrollbar.
+    pub scroll: f64,
+    /// Width and height of the main window.
+    pub window_size: Option<(i32, i32)>,
+    /// Positions of the dividers between panes, by pane name.
+    pub panes: BTreeMap<String, i32>,
+    /// Places of the panes around the traffic view.
+    pub layout: Layout,
+    /// Size in bytes at which a live capture is stopped.
+    pub storage_limit: Option<u64>,
+    /// Directory for the files holding captures, if not the default.
+    pub storage_dir: Option<PathBuf>,
+}
+
+impl JsonFile for Session {
+    const CONTENTS: &'static str = "session";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Pane, Place};
+
+    #[test]
+    fn test_json() {
+        let mut session = Session {
+            files: vec![PathBuf::from("/tmp/a.pcap"),
+                        PathBuf::from("/tmp/b.pcap")],
+            filter: String::from("device == 5"),
+            expanded: vec![vec![3], vec![3, 1], vec![7]],
+            selected: Some(vec![3, 1, 0]),
+            scroll: 120.5,
+            window_size: Some((1024, 768)),
+            panes: BTreeMap::from([
+                (String::from("main"), 700),
+                (String::from("traffic"), 400),
+            ]),
+            layout: Layout::default(),
+            storage_limit: Some(4_000_000_000),
+            storage_dir: Some(PathBuf::from("/var/tmp")),
+        };
+        session.layout.set_place(Pane::Hex, Place::Window);
+        let restored = Session::from_json(&session.to_json().unwrap()).unwrap();
+        assert_eq!(restored, session);
+
+        // Anything missing is left at its default.
+        let empty = Session::from_json("{}").unwrap();
+        assert_eq!(empty, Session::default());
+        assert!(Session::from_json(r#"{"selected": ["x"]}"#).is_err());
+    }
+}
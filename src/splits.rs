@@ -0,0 +1,321 @@
+//! Timing of split transactions, from each start-split sent to a high
+//! speed hub to the complete-split which finished it, by hub and port.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Error;
+
+use crate::capture::{CaptureReader, PacketId, Timestamp, TransactionId};
+use crate::usb::{
+    PacketFields, SplitFields, StartComplete, TokenFields, PID};
+
+/// Number of complete-splits answered with NYET, beyond which a split
+/// transaction is flagged as retried excessively.
+pub const MAX_EXPECTED_RETRIES: u64 = 3;
+
+/// A port of a high speed hub, which split transactions are sent through.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HubPort {
+    /// Address of the hub.
+    pub hub: u8,
+    /// Port number on the hub.
+    pub port: u8,
+}
+
+/// Counts and times of the split transactions sent through one port.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SplitCounts {
+    pub completed: u64,
+    /// Splits which were started but never completed.
+    pub unfinished: u64,
+    /// Complete-splits answered with NYET, over all completed splits.
+    pub retries: u64,
+    /// Most complete-splits answered with NYET in one split.
+    pub max_retries: u64,
+    /// Splits with more retries than expected.
+    pub excessive: u64,
+    /// Total time from the start-split to the complete-split which
+    /// finished it, over all completed splits, in nanoseconds.
+    pub total_latency: u64,
+    /// Longest time taken by one split, in nanoseconds.
+    pub max_latency: u64,
+}
+
+impl SplitCounts {
+    fn add(&mut self, other: &SplitCounts) {
+        self.completed += other.completed;
+        self.unfinished += other.unfinished;
+        self.retries += other.retries;
+        self.max_retries = self.max_retries.max(other.max_retries);
+        self.excessive += other.excessive;
+        self.total_latency += other.total_latency;
+        self.max_latency = self.max_latency.max(other.max_latency);
+    }
+
+    /// Number of splits started.
+    pub fn count(&self) -> u64 {
+        self.completed + self.unfinished
+    }
+
+    /// Average time taken by completed splits, in nanoseconds.
+    pub fn average_latency(&self) -> Option<u64> {
+        match self.completed {
+            0 => None,
+            completed => Some(self.total_latency / completed),
+        }
+    }
+
+    /// Average number of retries of completed splits.
+    pub fn average_retries(&self) -> f64 {
+        match self.completed {
+            0 => 0.0,
+            completed => self.retries as f64 / completed as f64,
+        }
+    }
+}
+
+/// A split transaction which was retried more than expected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlaggedSplit {
+    pub hub_port: HubPort,
+    /// Address of the device behind the hub.
+    pub device: u8,
+    pub endpoint: u8,
+    /// The first packet of the start-split.
+    pub packet_id: PacketId,
+    pub retries: u64,
+    /// Time taken, if the split completed, in nanoseconds.
+    pub latency: Option<u64>,
+}
+
+/// The transfer a split transaction is part of, which its start-split and
+/// complete-splits share.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct SplitKey {
+    hub_port: HubPort,
+    device: u8,
+    endpoint: u8,
+    token: u8,
+}
+
+impl SplitKey {
+    fn new(split: &SplitFields, token: &TokenFields, token_pid: u8)
+        -> SplitKey
+    {
+        SplitKey {
+            hub_port: HubPort {
+                hub: split.hub_address().0,
+                port: split.port(),
+            },
+            device: token.device_address().0,
+            endpoint: token.endpoint_number().0,
+            token: token_pid,
+        }
+    }
+}
+
+/// A split transaction which has been started but not yet completed.
+struct PendingSplit {
+    packet_id: PacketId,
+    start_time: Timestamp,
+    /// Whether the hub accepted the start-split, rather than NAKing it.
+    accepted: bool,
+    retries: u64,
+}
+
+/// Statistics on the split transactions in a capture.
+#[derive(Clone, Debug, Default)]
+pub struct SplitStatistics {
+    pub ports: BTreeMap<HubPort, SplitCounts>,
+    pub flagged: Vec<FlaggedSplit>,
+}
+
+impl SplitStatistics {
+    /// Time the split transactions in a capture, by hub and port.
+    pub fn new(capture: &mut CaptureReader)
+        -> Result<SplitStatistics, Error>
+    {
+        let mut stats = SplitStatistics::default();
+        let mut pending: BTreeMap<SplitKey, PendingSplit> = BTreeMap::new();
+        let packet_count = capture.packet_index.len();
+        for index in 0..capture.transaction_index.len() {
+            let transaction_id = TransactionId::from(index);
+            let range = capture.transaction_index.target_range(
+                transaction_id, packet_count)?;
+            if range.end <= range.start + 1 {
+                continue;
+            }
+            let split = match PacketFields::from_packet(
+                &capture.packet(range.start)?)
+            {
+                PacketFields::Split(split) => split,
+                _ => continue,
+            };
+            let token_packet = capture.packet(range.start + 1)?;
+            let token = match PacketFields::from_packet(&token_packet) {
+                PacketFields::Token(token) => token,
+                _ => continue,
+            };
+            let key = SplitKey::new(&split, &token, token_packet[0]);
+            let last_id = range.end - 1;
+            let end_pid = PID::from(capture.packet(last_id)?[0]);
+            match split.sc() {
+                StartComplete::Start => {
+                    let accepted = end_pid != PID::NAK;
+                    if let Some(split) = pending.get_mut(&key) {
+                        // The hub NAKed the last start-split, so this is
+                        // the host trying again to start the same split.
+                        if !split.accepted {
+                            split.accepted = accepted;
+                            continue;
+                        }
+                    }
+                    if let Some(old) = pending.remove(&key) {
+                        stats.finish(key, old, None);
+                    }
+                    pending.insert(key, PendingSplit {
+                        packet_id: range.start,
+                        start_time: capture.packet_time(range.start)?,
+                        accepted,
+                        retries: 0,
+                    });
+                },
+                StartComplete::Complete => match end_pid {
+                    PID::NYET => {
+                        if let Some(split) = pending.get_mut(&key) {
+                            split.retries += 1;
+                        }
+                    },
+                    // More data follows in the next complete-split.
+                    PID::MDATA => {},
+                    _ => {
+                        if let Some(split) = pending.remove(&key) {
+                            let end_time = capture.packet_time(last_id)?;
+                            let latency = end_time.saturating_sub(
+                                split.start_time);
+                            stats.finish(key, split, Some(latency));
+                        }
+                    },
+                },
+            }
+        }
+        for (key, split) in pending {
+            stats.finish(key, split, None);
+        }
+        Ok(stats)
+    }
+
+    /// Count a split which completed after some time, or never did.
+    fn finish(&mut self,
+              key: SplitKey,
+              split: PendingSplit,
+              latency: Option<u64>)
+    {
+        let counts = self.ports.entry(key.hub_port).or_default();
+        match latency {
+            Some(latency) => {
+                counts.completed += 1;
+                counts.retries += split.retries;
+                counts.max_retries = counts.max_retries.max(split.retries);
+                counts.total_latency += latency;
+                counts.max_latency = counts.max_latency.max(latency);
+            },
+            None => counts.unfinished += 1,
+        }
+        if split.retries > MAX_EXPECTED_RETRIES {
+            counts.excessive += 1;
+            self.flagged.push(FlaggedSplit {
+                hub_port: key.hub_port,
+                device: key.device,
+                endpoint: key.endpoint,
+                packet_id: split.packet_id,
+                retries: split.retries,
+                latency,
+            });
+        }
+    }
+
+    /// Totals over all ports.
+    pub fn total(&self) -> SplitCounts {
+        let mut total = SplitCounts::default();
+        for counts in self.ports.values() {
+            total.add(counts);
+        }
+        total
+    }
+
+    /// Write the counts for each port as CSV.
+    pub fn write_csv(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        writeln!(writer, "Hub,Port,Count,Completed,Unfinished,\
+                          Average retries,Most retries,Excessive retries,\
+                          Average time (ns),Longest time (ns)")?;
+        for (hub_port, counts) in &self.ports {
+            writeln!(writer, "{},{},{},{},{},{:.2},{},{},{},{}",
+                hub_port.hub,
+                hub_port.port,
+                counts.count(),
+                counts.completed,
+                counts.unfinished,
+                counts.average_retries(),
+                counts.max_retries,
+                counts.excessive,
+                counts.average_latency()
+                    .map_or_else(String::new, |latency| latency.to_string()),
+                counts.max_latency)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load;
+    use std::path::PathBuf;
+
+    fn split_statistics(name: &str) -> SplitStatistics {
+        let path = PathBuf::from(format!("./tests/{name}/capture.pcap"));
+        let mut capture = load(&[path]).unwrap();
+        SplitStatistics::new(&mut capture).unwrap()
+    }
+
+    #[test]
+    fn test_split_statistics() {
+        let stats = split_statistics("split-poll");
+        let port = HubPort { hub: 12, port: 2 };
+        assert_eq!(stats.ports.keys().collect::<Vec<_>>(), [&port]);
+        let counts = stats.ports[&port];
+        assert!(counts.completed > 0);
+        assert!(counts.average_latency().unwrap() > 0);
+        assert!(counts.max_latency >= counts.average_latency().unwrap());
+        assert_eq!(stats.total(), counts);
+
+        let stats = split_statistics("split-nyet");
+        let port = HubPort { hub: 23, port: 2 };
+        let counts = stats.ports[&port];
+        assert!(counts.completed > 0);
+        assert!(counts.retries > 0);
+        assert!(counts.max_retries > 0);
+        assert_eq!(counts.excessive, stats.flagged.len() as u64);
+        assert!(stats.flagged.iter().all(|split|
+            split.retries > MAX_EXPECTED_RETRIES && split.hub_port == port));
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), stats.ports.len() + 1);
+        assert!(csv.contains("\n23,2,"));
+
+        let counts = SplitCounts {
+            completed: 4,
+            unfinished: 1,
+            retries: 6,
+            total_latency: 4000,
+            ..SplitCounts::default()
+        };
+        assert_eq!(counts.count(), 5);
+        assert_eq!(counts.average_latency(), Some(1000));
+        assert_eq!(counts.average_retries(), 1.5);
+        assert_eq!(SplitCounts::default().average_latency(), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Statistics on the traffic in a capture.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointTransactionId,
+    EndpointType,
+    Timestamp,
+    TrafficItem,
+    TrafficItemId,
+};
+use crate::export::csv_quote;
+use crate::filter::Filter;
+
+/// Counts of the traffic in some part of a capture.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub transfers: u64,
+    pub transactions: u64,
+    pub packets: u64,
+    /// Bytes in all packets, including PIDs and CRCs.
+    pub bytes: u64,
+    /// Transactions showing an error.
+    pub errors: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.transfers += other.transfers;
+        self.transactions += other.transactions;
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+        self.errors += other.errors;
+    }
+
+    /// Average bandwidth in bytes per second, over a duration.
+    pub fn bandwidth(&self, duration: Timestamp) -> f64 {
+        if duration == 0 {
+            0.0
+        } else {
+            self.bytes as f64 * 1e9 / duration as f64
+        }
+    }
+}
+
+/// Statistics on the traffic in a capture, broken down in several ways.
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    pub total: Counts,
+    /// Counts for each device address.
+    pub devices: BTreeMap<u8, Counts>,
+    /// Counts for each endpoint, by device address and endpoint address.
+    pub endpoints: BTreeMap<(u8, u8), Counts>,
+    /// Counts for each type of transfer.
+    pub transfer_types: BTreeMap<String, Counts>,
+    /// Time from the first to the last packet counted, in nanoseconds.
+    pub duration: Timestamp,
+}
+
+impl Statistics {
+    /// Count the traffic in a capture, or only the transfers selected by
+    /// a filter.
+    pub fn new(capture: &mut CaptureReader, filter: Option<&Filter>)
+        -> Result<Statistics, Error>
+    {
+        let mut stats = Statistics::default();
+        let mut first_time: Option<Timestamp> = None;
+        let mut last_time: Option<Timestamp> = None;
+        for index in 0..capture.item_index.len() {
+            let transfer_id = capture.item_index.get(TrafficItemId::from(index))?;
+            let entry = capture.transfer_index.get(transfer_id)?;
+            // Each transfer is counted at its start.
+            if !entry.is_start() {
+                continue;
+            }
+            let item = TrafficItem::Transfer(transfer_id);
+            if let Some(filter) = filter {
+                if !filter.matches(capture, &item)? {
+                    continue;
+                }
+            }
+            let endpoint_id = entry.endpoint_id();
+            let endpoint = capture.endpoints.get(endpoint_id)?;
+            let (ep_type, _) = capture
+                .device_data(&endpoint.device_id())?
+                .endpoint_details(endpoint.address());
+            let mut counts = Counts { transfers: 1, ..Counts::default() };
+            let range = capture.transfer_range(&entry)?;
+            for id in range.start.value..range.end.value {
+                let transaction_id = capture
+                    .endpoint_traffic(endpoint_id)?
+                    .transaction_ids
+                    .get(EndpointTransactionId::from(id))?;
+                let packets = capture.transaction_index.target_range(
+                    transaction_id, capture.packet_index.len())?;
+                let start_byte = capture.packet_index.get(packets.start)?;
+                let end_byte = capture.packet_index.target_range(
+                    packets.end - 1, capture.packet_data.len())?.end;
+                counts.transactions += 1;
+                counts.packets += packets.end.value - packets.start.value;
+                counts.bytes += end_byte.value - start_byte.value;
+                let transaction = TrafficItem::Transaction(
+                    transfer_id, transaction_id);
+                if capture.item_error(&transaction)? {
+                    counts.errors += 1;
+                }
+                let start_time = capture.packet_time(packets.start)?;
+                let end_time = capture.packet_time(packets.end - 1)?;
+                first_time = Some(first_time.map_or(start_time,
+                    |time| time.min(start_time)));
+                last_time = Some(last_time.map_or(end_time,
+                    |time| time.max(end_time)));
+            }
+            stats.total.add(&counts);
+            stats.transfer_types
+                .entry(ep_type.to_string())
+                .or_default()
+                .add(&counts);
+            if let EndpointType::Normal(_) | EndpointType::Unidentified =
+                ep_type
+            {
+                let address = endpoint.device_address().0;
+                stats.devices.entry(address).or_default().add(&counts);
+                stats.endpoints
+                    .entry((address, endpoint.address().0))
+                    .or_default()
+                    .add(&counts);
+            }
+        }
+        if let (Some(first), Some(last)) = (first_time, last_time) {
+            stats.duration = last - first;
+        }
+        Ok(stats)
+    }
+
+    /// The ways of breaking down the traffic, each with a title and rows
+    /// of named counts, ending with the total.
+    pub fn tables(&self) -> [(&'static str, Vec<(String, Counts)>); 3] {
+        let total = ("Total".to_string(), self.total);
+        let devices = self.devices
+            .iter()
+            .map(|(address, counts)| (format!("Device {address}"), *counts));
+        let endpoints = self.endpoints
+            .iter()
+            .map(|((device, endpoint), counts)|
+                (format!("{device}.{} {}",
+                         endpoint & 0x7F,
+                         if endpoint & 0x80 == 0 { "OUT" } else { "IN" }),
+                 *counts));
+        let transfer_types = self.transfer_types
+            .iter()
+            .map(|(name, counts)| (name.clone(), *counts));
+        [
+            ("Devices", devices.chain([total.clone()]).collect()),
+            ("Endpoints", endpoints.chain([total.clone()]).collect()),
+            ("Transfer types", transfer_types.chain([total]).collect()),
+        ]
+    }
+
+    /// Write all the tables as CSV, with a column naming the table of each
+    /// row.
+    pub fn write_csv(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        writeln!(writer, "Table,Name,Transfers,Transactions,Packets,Bytes,\
+                          Errors,Bandwidth (bytes/s)")?;
+        for (title, rows) in self.tables() {
+            for (name, counts) in rows {
+                writeln!(writer, "{},{},{},{},{},{},{},{:.0}",
+                    csv_quote(title),
+                    csv_quote(&name),
+                    counts.transfers,
+                    counts.transactions,
+                    counts.packets,
+                    counts.bytes,
+                    counts.errors,
+                    counts.bandwidth(self.duration))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::loader::load;
+
+    #[test]
+    fn test_statistics() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let stats = Statistics::new(&mut capture, None).unwrap();
+
+        // No packet or byte in the capture is counted twice.
+        assert!(stats.total.packets <= capture.packet_index.len());
+        assert!(stats.total.bytes <= capture.packet_data.len());
+        assert!(stats.total.packets > 0);
+        let mut by_type = Counts::default();
+        for counts in stats.transfer_types.values() {
+            by_type.add(counts);
+        }
+        assert_eq!(by_type, stats.total);
+        assert!(stats.transfer_types.contains_key("Control"));
+        assert!(stats.transfer_types.contains_key("Framing"));
+        assert!(stats.duration > 0);
+
+        // SOFs are not counted against any device.
+        let device = stats.devices[&11];
+        assert!(device.transfers > 0);
+        assert!(device.packets < stats.total.packets);
+        assert!(stats.total.bandwidth(stats.duration) > 0.0);
+
+        // A filter selects only some of the traffic.
+        let filter = Filter::parse("device == 11").unwrap();
+        let filtered = Statistics::new(&mut capture, Some(&filter)).unwrap();
+        assert_eq!(filtered.total, device);
+        assert_eq!(filtered.devices.len(), 1);
+        assert!(!filtered.transfer_types.contains_key("Framing"));
+
+        // Each table has a CSV row for each of its rows.
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: usize = stats.tables()
+            .iter()
+            .map(|(_, rows)| rows.len())
+            .sum();
+        assert_eq!(csv.lines().count(), rows + 1);
+        assert!(csv.starts_with("Table,Name,Transfers,"));
+        assert!(csv.contains(&format!("\"Devices\",\"Device 11\",{},",
+                                      device.transfers)));
+    }
+}
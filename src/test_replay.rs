@@ -10,6 +10,7 @@ use serde_json::Deserializer;
 
 use packetry::decoder::Decoder;
 use packetry::model::GenericModel;
+use packetry::pcap::timestamp;
 use packetry::row_data::{GenericRowData, TrafficRowData, DeviceRowData};
 use packetry::record_ui::UiAction;
 use packetry::ui::{
@@ -110,8 +111,9 @@ fn check_replays() {
                             .next_raw_packet()
                             .expect("No next pcap packet")
                             .expect("Error in pcap reader");
+                        let timestamp = timestamp(&pcap.header(), &packet);
                         decoder
-                            .handle_raw_packet(&packet.data)
+                            .handle_raw_packet(&packet.data, timestamp)
                             .expect("Failed to decode packet");
                     }
                     update_view()
@@ -0,0 +1,406 @@
+//! Transactions laid out on a time axis, in a lane for each endpoint,
+//! with the periods during which each endpoint was halted, the periods
+//! during which the bus was suspended or carried only keep-alives, any
+//! markers added during the capture, OTG events such as role swaps, and
+//! the channels of a logic analyzer trace.
+
+use anyhow::Error;
+
+use crate::capture::{
+    CaptureReader,
+    EndpointId,
+    EndpointTransactionId,
+    PacketId,
+    Timestamp,
+    TransactionId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
+};
+use crate::halts::{Halt, Halts};
+use crate::logic::{ChannelWindow, LogicTrace};
+use crate::markers::Marker;
+use crate::otg::OtgEvent;
+use crate::suspend::{IdlePeriod, IdlePeriods};
+use crate::usb::PID;
+
+/// Maximum number of transactions placed in each lane.
+pub const MAX_SPANS: usize = 10000;
+
+/// A transaction, placed on the time axis.
+#[derive(Copy, Clone, Debug)]
+pub struct Span {
+    pub transaction_id: TransactionId,
+    /// Time of the first packet.
+    pub start: Timestamp,
+    /// Time of the last packet.
+    pub end: Timestamp,
+    /// PID of the last packet, e.g. ACK, NAK or STALL.
+    pub outcome: PID,
+}
+
+/// The transactions on one endpoint.
+#[derive(Clone, Debug)]
+pub struct Lane {
+    /// Description of the endpoint, e.g. `5.1 IN`.
+    pub name: String,
+    pub spans: Vec<Span>,
+    /// Whether there were more than `MAX_SPANS` transactions in the window.
+    pub truncated: bool,
+    /// Halts of the endpoint overlapping the window.
+    pub halts: Vec<Halt>,
+}
+
+/// The transactions in a window of time, in a lane for each endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub lanes: Vec<Lane>,
+    /// Markers within the window, in order of time.
+    pub markers: Vec<Marker>,
+    /// Channels of an imported logic analyzer trace, drawn below the
+    /// lanes for endpoints.
+    pub channels: Vec<ChannelWindow>,
+    /// Periods overlapping the window during which the bus carried no
+    /// packets, drawn across all the lanes.
+    pub idle: Vec<IdlePeriod>,
+    /// OTG events within the window, in order of time.
+    pub otg: Vec<OtgEvent>,
+}
+
+/// Times of the first and last packets in a capture.
+pub fn capture_span(capture: &mut CaptureReader)
+    -> Result<Option<(Timestamp, Timestamp)>, Error>
+{
+    let packet_count = capture.packet_times.len();
+    if packet_count == 0 {
+        return Ok(None);
+    }
+    let first = capture.packet_time(PacketId::from(0))?;
+    let last = capture.packet_time(PacketId::from(packet_count - 1))?;
+    Ok(Some((first, last)))
+}
+
+impl Timeline {
+    /// Lay out the transactions overlapping a window of time.
+    ///
+    /// There is a lane for each endpoint that has seen any traffic, so
+    /// that lanes stay in place as the window moves. SOFs are in the
+    /// first lane.
+    pub fn new(capture: &mut CaptureReader,
+               halts: &Halts,
+               start: Timestamp,
+               end: Timestamp)
+        -> Result<Timeline, Error>
+    {
+        // Find the transactions starting from the one in progress at the
+        // start of the window, up to the first starting after its end.
+        let first_packet = capture.packet_times.bisect_left(&start)?;
+        let mut first_transaction =
+            capture.transaction_index.bisect_left(&first_packet)?;
+        if first_transaction.value > 0 &&
+            (first_transaction.value == capture.transaction_index.len() ||
+             capture.transaction_index.get(first_transaction)? > first_packet)
+        {
+            first_transaction -= 1;
+        }
+        let end_packet = capture.packet_times.bisect_left(&(end + 1))?;
+        let end_transaction =
+            capture.transaction_index.bisect_left(&end_packet)?;
+
+        let mut endpoint_ids: Vec<EndpointId> = (0..capture.endpoints.len())
+            .map(EndpointId::from)
+            .filter(|id| *id != INVALID_EP_ID)
+            .collect();
+        endpoint_ids.push(INVALID_EP_ID);
+
+        let mut lanes = Vec::new();
+        for endpoint_id in endpoint_ids {
+            let transaction_ids = &mut capture
+                .endpoint_traffic(endpoint_id)?
+                .transaction_ids;
+            if transaction_ids.len() == 0 {
+                continue;
+            }
+            let range_start = transaction_ids.bisect_left(&first_transaction)?;
+            let range_end = transaction_ids.bisect_left(&end_transaction)?;
+            let mut ids = Vec::new();
+            let mut truncated = false;
+            for id in range_start.value..range_end.value {
+                if ids.len() == MAX_SPANS {
+                    truncated = true;
+                    break;
+                }
+                ids.push(transaction_ids.get(EndpointTransactionId::from(id))?);
+            }
+            let mut spans = Vec::with_capacity(ids.len());
+            for transaction_id in ids {
+                spans.push(span(capture, transaction_id)?);
+            }
+            let name = if endpoint_id == FRAMING_EP_ID {
+                "SOF".to_string()
+            } else if endpoint_id == INVALID_EP_ID {
+                "Invalid".to_string()
+            } else {
+                capture.endpoints.get(endpoint_id)?.to_string()
+            };
+            let halts = halts
+                .in_window(endpoint_id, start, end)
+                .cloned()
+                .collect();
+            lanes.push(Lane { name, spans, truncated, halts });
+        }
+        let markers = capture.shared.markers
+            .load()
+            .in_window(start, end)
+            .cloned()
+            .collect();
+        Ok(Timeline {
+            start,
+            end,
+            lanes,
+            markers,
+            channels: Vec::new(),
+            idle: Vec::new(),
+            otg: Vec::new(),
+        })
+    }
+
+    /// Find the transaction drawn at a time in a lane, within a tolerance.
+    pub fn span_at(&self, lane: usize, time: Timestamp, tolerance: Timestamp)
+        -> Option<&Span>
+    {
+        let spans = &self.lanes.get(lane)?.spans;
+        let index = spans.partition_point(|span|
+            span.end.saturating_add(tolerance) < time);
+        spans
+            .get(index)
+            .filter(|span| span.start.saturating_sub(tolerance) <= time)
+    }
+
+    /// Add the channels of a logic analyzer trace, with the trace's time
+    /// zero placed at the given capture time.
+    pub fn add_trace(&mut self, trace: &LogicTrace, zero: Timestamp) {
+        self.channels = trace.window(zero, self.start, self.end, MAX_SPANS);
+    }
+
+    /// Add the periods during which the bus carried no packets.
+    pub fn add_idle(&mut self, periods: &IdlePeriods) {
+        self.idle = periods
+            .in_window(self.start, self.end)
+            .copied()
+            .collect();
+    }
+
+    /// Add the OTG events within the window.
+    pub fn add_otg(&mut self, events: &[OtgEvent]) {
+        self.otg = events
+            .iter()
+            .filter(|event| (self.start..=self.end).contains(&event.time))
+            .cloned()
+            .collect();
+    }
+
+    /// Find the idle period in progress at a time.
+    pub fn idle_at(&self, time: Timestamp) -> Option<&IdlePeriod> {
+        self.idle.iter().find(|period|
+            period.start <= time && time <= period.end)
+    }
+
+    /// Find the halt in progress at a time in a lane.
+    pub fn halt_at(&self, lane: usize, time: Timestamp) -> Option<&Halt> {
+        self.lanes.get(lane)?.halts.iter().find(|halt|
+            halt.start <= time &&
+            halt.cleared.map_or(true, |(_, end)| time <= end))
+    }
+}
+
+fn span(capture: &mut CaptureReader, transaction_id: TransactionId)
+    -> Result<Span, Error>
+{
+    let packets = capture.transaction_index.target_range(
+        transaction_id, capture.packet_index.len())?;
+    let last = packets.end - 1;
+    let start = capture.packet_time(packets.start)?;
+    let end = capture.packet_time(last)?;
+    let outcome = match capture.packet(last)?.first() {
+        Some(byte) => PID::from(*byte),
+        None => PID::Malformed,
+    };
+    Ok(Span { transaction_id, start, end, outcome })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use crate::backend::emulator::Scenario;
+    use crate::capture::create_capture;
+    use crate::decoder::Decoder;
+    use crate::loader::load;
+    use crate::logic::parse_saleae_csv;
+    use crate::markers::Markers;
+    use crate::otg::OtgEventKind;
+    use crate::usb::DeviceAddr;
+
+    #[test]
+    fn test_timeline() {
+        let path = PathBuf::from("./tests/hackrf-dfu-enum/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let (first, last) = capture_span(&mut capture).unwrap().unwrap();
+        let halts = Halts::find(&mut capture).unwrap();
+        assert!(halts.halts.is_empty());
+        let whole = Timeline::new(&mut capture, &halts, first, last).unwrap();
+        assert_eq!(whole.lanes[0].name, "SOF");
+        assert!(whole.lanes.iter().any(|lane| lane.name == "11.0 OUT"));
+        for lane in &whole.lanes {
+            assert!(lane.spans.windows(2).all(|pair|
+                pair[0].start <= pair[1].start));
+            for span in &lane.spans {
+                assert!(span.start <= span.end);
+            }
+        }
+
+        // A narrower window holds only the transactions overlapping it.
+        let middle = first + (last - first) / 2;
+        let window = Timeline::new(
+            &mut capture, &halts, middle, middle + 1000000).unwrap();
+        assert_eq!(window.lanes.len(), whole.lanes.len());
+        let count = |timeline: &Timeline| timeline.lanes
+            .iter()
+            .map(|lane| lane.spans.len())
+            .sum::<usize>();
+        assert!(count(&window) < count(&whole));
+        for lane in &window.lanes {
+            for span in &lane.spans {
+                assert!(span.start <= middle + 1000000);
+            }
+        }
+
+        // Each transaction can be found by its time.
+        let span = whole.lanes[0].spans[0];
+        let found = whole.span_at(0, span.start, 0).unwrap();
+        assert_eq!(found.transaction_id, span.transaction_id);
+
+        // Only the markers within the window are included.
+        let mut markers = Markers::new();
+        for (time, label) in [(first, "Start"), (middle, "Middle")] {
+            markers.add(Marker { time, label: label.to_string() });
+        }
+        capture.shared.markers.store(Arc::new(markers));
+        let window = Timeline::new(
+            &mut capture, &halts, middle, middle + 1000000).unwrap();
+        let labels: Vec<&str> = window.markers
+            .iter()
+            .map(|marker| marker.label.as_str())
+            .collect();
+        assert_eq!(labels, ["Middle"]);
+
+        // A logic trace is placed relative to the time chosen as its zero.
+        let trace = parse_saleae_csv(
+            "Time [s],Button\n0.0,0\n0.0005,1\n0.002,0\n").unwrap();
+        let mut window = window;
+        window.add_trace(&trace, middle);
+        assert_eq!(window.channels.len(), 1);
+        assert_eq!(window.channels[0].name, "Button");
+        assert!(!window.channels[0].initial);
+        assert_eq!(window.channels[0].edges, [(middle + 500000, true)]);
+    }
+
+    #[test]
+    fn test_timeline_halts() {
+        let scenario = Scenario::parse("halt", "
+            device 1
+            endpoint 1 1 in bulk 512
+            enumerate 1
+            in 1 1 01:02
+            stall 1 1 in
+            stall 1 1 in
+            control 1 0x02 0x01 0 0x81
+            in 1 1 03:04
+        ").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        for (i, packet) in scenario.packets().enumerate() {
+            decoder.handle_raw_packet(packet, i as u64 * 1000).unwrap();
+        }
+        decoder.finish().unwrap();
+
+        let halts = Halts::find(&mut capture).unwrap();
+        let (first, last) = capture_span(&mut capture).unwrap().unwrap();
+        let timeline = Timeline::new(&mut capture, &halts, first, last)
+            .unwrap();
+        let lane = timeline.lanes
+            .iter()
+            .position(|lane| lane.name == "1.1 IN")
+            .unwrap();
+        assert_eq!(timeline.lanes[lane].halts.len(), 1);
+        let halt = &timeline.lanes[lane].halts[0];
+        assert_eq!(halt.stalls, 2);
+        let (_, end) = halt.cleared.unwrap();
+        assert!(timeline.halt_at(lane, halt.start).is_some());
+        assert!(timeline.halt_at(lane, end).is_some());
+        assert!(timeline.halt_at(lane, end + 1).is_none());
+        assert!(timeline.halt_at(0, halt.start).is_none());
+
+        // Halts outside the window are not placed in it.
+        let timeline = Timeline::new(&mut capture, &halts, end + 1, last)
+            .unwrap();
+        assert!(timeline.lanes[lane].halts.is_empty());
+    }
+
+    #[test]
+    fn test_timeline_idle() {
+        let scenario = Scenario::parse("suspend", "
+            speed full
+            sof 4
+            sof 4").unwrap();
+        let (writer, mut capture) = create_capture().unwrap();
+        let mut decoder = Decoder::new(writer).unwrap();
+        // A millisecond between SOFs, and a gap of 20 ms in the middle.
+        for (i, packet) in scenario.packets().enumerate() {
+            let gap = if i < 4 { 0 } else { 20_000_000 };
+            decoder.handle_raw_packet(packet, i as u64 * 1_000_000 + gap)
+                .unwrap();
+        }
+        decoder.finish().unwrap();
+        let periods = IdlePeriods::find(&mut capture).unwrap();
+        let halts = Halts::new();
+        let mut timeline = Timeline::new(
+            &mut capture, &halts, 0, 30_000_000).unwrap();
+        timeline.add_idle(&periods);
+        assert_eq!(timeline.idle.len(), 1);
+        let period = timeline.idle_at(10_000_000).unwrap();
+        assert_eq!((period.start, period.end), (3_000_000, 24_000_000));
+        assert_eq!(period.resumed_by, PacketId::from(4));
+        assert!(timeline.idle_at(1_000_000).is_none());
+
+        // Periods outside the window are not placed in it.
+        let mut timeline = Timeline::new(
+            &mut capture, &halts, 25_000_000, 30_000_000).unwrap();
+        timeline.add_idle(&periods);
+        assert!(timeline.idle.is_empty());
+    }
+
+    #[test]
+    fn test_timeline_otg() {
+        let path = PathBuf::from("./tests/mouse/capture.pcap");
+        let mut capture = load(&[path]).unwrap();
+        let (first, _) = capture_span(&mut capture).unwrap().unwrap();
+        let event = |time: Timestamp| OtgEvent {
+            kind: OtgEventKind::RoleSwap,
+            time: first + time,
+            address: DeviceAddr(1),
+            description: String::new(),
+        };
+        let events = [event(1_000), event(500_000), event(2_000_000)];
+        let halts = Halts::new();
+        let mut timeline = Timeline::new(
+            &mut capture, &halts, first, first + 1_000_000).unwrap();
+        timeline.add_otg(&events);
+        let times: Vec<Timestamp> =
+            timeline.otg.iter().map(|event| event.time - first).collect();
+        assert_eq!(times, [1_000, 500_000]);
+    }
+}
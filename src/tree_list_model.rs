@@ -5,6 +5,7 @@ use std::collections::btree_map::Entry;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::rc::{Rc, Weak};
+use std::sync::Arc;
 
 use anyhow::{Context, Error, bail};
 
@@ -15,6 +16,7 @@ use gtk::gio::prelude::ListModelExt;
 use derive_more::AddAssign;
 use itertools::Itertools;
 
+use crate::annotations::Annotations;
 use crate::capture::{CaptureReader, ItemSource};
 use crate::model::GenericModel;
 use crate::row_data::GenericRowData;
@@ -32,6 +34,9 @@ trait Node<Item> {
     /// Parent of this node, or None if the root.
     fn parent(&self) -> Result<Option<AnyNodeRc<Item>>, Error>;
 
+    /// Index of this node below its parent, or None if the root.
+    fn item_index(&self) -> Option<u64>;
+
     /// Access the children of this node.
     fn children(&self) -> &Children<Item>;
 
@@ -140,6 +145,10 @@ impl<Item> Node<Item> for RootNode<Item> {
         Ok(None)
     }
 
+    fn item_index(&self) -> Option<u64> {
+        None
+    }
+
     fn children(&self) -> &Children<Item> {
         &self.children
     }
@@ -169,6 +178,10 @@ impl<Item> Node<Item> for ItemNode<Item> where Item: Copy {
         ))
     }
 
+    fn item_index(&self) -> Option<u64> {
+        Some(self.item_index)
+    }
+
     fn children(&self) -> &Children<Item> {
         &self.children
     }
@@ -272,6 +285,59 @@ impl<Item> ItemNode<Item> where Item: Copy {
     pub fn remove_widget(&self, widget: &ExpanderWrapper) {
         self.widgets.borrow_mut().remove(widget);
     }
+
+    /// The widgets currently showing this item.
+    pub fn widgets(&self) -> Vec<ExpanderWrapper> {
+        self.widgets.borrow().iter().cloned().collect()
+    }
+
+    /// Update the text shown for this item.
+    pub fn set_text(&self, text: String) {
+        for widget in self.widgets.borrow().iter() {
+            widget.set_text(text.clone());
+        }
+    }
+
+    /// The item of this node's parent, or None at the top level, and the
+    /// index of this node below it.
+    pub fn position(&self) -> Result<(Option<Item>, u64), Error> {
+        let parent_item = match Node::<Item>::parent(self)? {
+            Some(node_rc) => {
+                let node = node_rc.borrow();
+                node.item().copied()
+            },
+            None => None,
+        };
+        Ok((parent_item, self.item_index))
+    }
+
+    /// Indices of this node and its ancestors, from the top level down.
+    pub fn path(&self) -> Result<Vec<u64>, Error> {
+        let mut path = vec![self.item_index];
+        let mut parent = Node::<Item>::parent(self)?;
+        while let Some(node_rc) = parent {
+            let node = node_rc.borrow();
+            if let Some(index) = node.item_index() {
+                path.push(index);
+            }
+            parent = node.parent()?;
+        }
+        path.reverse();
+        Ok(path)
+    }
+}
+
+/// Add the paths of expanded children, and of their expanded children.
+fn collect_expanded<Item>(children: &Children<Item>,
+                          parent_path: &[u64],
+                          paths: &mut Vec<Vec<u64>>)
+{
+    for (index, node_rc) in &children.expanded {
+        let mut path = parent_path.to_vec();
+        path.push(*index);
+        paths.push(path.clone());
+        collect_expanded(&node_rc.borrow().children, &path, paths);
+    }
 }
 
 #[derive(Clone)]
@@ -401,6 +467,61 @@ where Item: 'static + Copy + Debug,
         }
     }
 
+    /// Get the annotations made on the capture.
+    pub fn annotations(&self) -> Arc<Annotations> {
+        self.capture.borrow().shared.annotations.load_full()
+    }
+
+    /// Run a function with access to the capture reader used by this model.
+    pub fn with_capture<F, T>(&self, f: F) -> T
+        where F: FnOnce(&mut CaptureReader) -> T
+    {
+        f(&mut self.capture.borrow_mut())
+    }
+
+    /// Get a clone of the capture reader used by this model.
+    pub fn capture(&self) -> CaptureReader {
+        self.capture.borrow().clone()
+    }
+
+    /// Paths of indices to all expanded items, with parents before their
+    /// children.
+    pub fn expanded_paths(&self) -> Vec<Vec<u64>> {
+        let mut paths = Vec::new();
+        collect_expanded(&self.root.borrow().children, &[], &mut paths);
+        paths
+    }
+
+    /// Expand the ancestors of the item at the given path of indices, so
+    /// that it is displayed, and return its row position.
+    pub fn reveal(&self, model: &Model, path: &[u64]) -> Result<u64, Error> {
+        let mut parent_rc: AnyNodeRc<Item> = self.root.clone();
+        let mut position = 0;
+        for (depth, &index) in path.iter().enumerate() {
+            let row = {
+                let parent = parent_rc.borrow();
+                let children = parent.children();
+                if index >= children.direct_count {
+                    bail!("Item {index} at depth {depth} is not in the model")
+                }
+                position + children.rows_between(0, index)
+            };
+            if depth + 1 == path.len() {
+                return Ok(row);
+            }
+            let node_rc = self.fetch(row)?;
+            if !node_rc.borrow().expanded() {
+                self.set_expanded(model, &node_rc, row, true)?;
+                // Rebind the row, so its expander shows the new state.
+                let row = u32::try_from(row)?;
+                model.items_changed(row, 1, 1);
+            }
+            parent_rc = node_rc;
+            position = row + 1;
+        }
+        bail!("Empty item path")
+    }
+
     pub fn set_expanded(&self,
                         model: &Model,
                         node_ref: &ItemNodeRc<Item>,
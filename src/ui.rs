@@ -1,36 +1,51 @@
-use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
-use std::mem::size_of;
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature="step-decoder")]
 use std::{io::Read, net::TcpListener};
 
 use anyhow::{Context as ErrorContext, Error, bail};
 
-use gtk::gio::ListModel;
+use gtk::accessible::{Property, Relation};
+use gtk::gio::{self, ListModel};
 use gtk::glib::{Object, SignalHandlerId};
 use gtk::{
     prelude::*,
+    AccessibleRole,
     Align,
     Application,
     ApplicationWindow,
     Button,
+    CheckButton,
+    ColorButton,
+    CssProvider,
     DropDown,
+    Entry,
     Label,
+    MenuButton,
+    ListBox,
     ListItem,
     ColumnView,
     ColumnViewColumn,
     ProgressBar,
     ScrolledWindow,
+    SearchEntry,
     Separator,
     SignalListItemFactory,
     SingleSelection,
+    SpinButton,
     StringList,
+    TextView,
+    ToggleButton,
     Orientation,
 };
 
@@ -42,64 +57,532 @@ use gtk::{
     ButtonsType,
 };
 
-use pcap_file::{
-    DataLink,
-    pcap::{PcapReader, PcapWriter, PcapHeader, RawPcapPacket},
-};
-
 use crate::backend::cynthion::{
     CynthionDevice,
-    CynthionHandle,
     CynthionStop,
+    CynthionStream,
+    CynthionTester,
+    CynthionTrigger,
     CynthionUsability::*,
-    Speed};
+    Speed,
+    TestFeature};
+use crate::backend::emulator::{Playback, Scenario, MAX_SPEED, MIN_SPEED};
+use crate::activity::Activity;
+use crate::alerts::{AlertAction, AlertRule, AlertRules, ALERT_INTERVAL};
+use crate::annotations::{Annotation, Annotations};
+use crate::coloring::{
+    rgb,
+    ColorRule,
+    ColorRules,
+    Condition,
+    Element,
+    Palette,
+    Theme,
+    CONDITION_KINDS,
+};
+use crate::columns::{
+    Column,
+    Columns,
+    TimeBase,
+    TimestampMode,
+    COLUMN_KINDS,
+    sort_items,
+};
+use crate::codegen::{descriptor_source, Language};
+use crate::config::JsonFile;
+use crate::copy::CopyFormat;
+use crate::backend::filter::{CaptureFilter, FilteredPackets};
+use crate::backend::host::HostDevice;
 
 use crate::capture::{
     create_capture,
     CaptureReader,
     CaptureWriter,
+    DetailLevel,
     ItemSource,
     TrafficItem,
     DeviceItem,
+    ExportScope,
     PacketId,
+    Timestamp,
+    TrafficItemId,
+    FRAMING_EP_ID,
+    INVALID_EP_ID,
 };
-use crate::decoder::Decoder;
+use crate::decoder::{DecodeDepth, Decoder, PipelinePacket};
+use crate::diff::{Change, Comparison, Step};
+use crate::errors::{Errors, MAX_ERRORS};
+use crate::export::{self, EndpointData};
 use crate::expander::ExpanderWrapper;
+use crate::filter::Filter;
+use crate::follow::FollowedStream;
+use crate::frames::{Frame, Frames};
+use crate::halts::Halts;
+use crate::i18n::{tr, trf, use_environment_language};
+use crate::integrity::{signing_key, Verification};
+use crate::logic::LogicTrace;
+use crate::markers::{Marker, DEFAULT_LABEL};
+use crate::metadata::Metadata;
+use crate::goto::Target;
+use crate::layout::{Layout, Pane, Place};
+use crate::graph::{Graph, Selection};
+use crate::palette::{rank, FILTER_PRESETS};
+use crate::plot::{FieldPlot, ValueFormat};
+use crate::preferences::{ColorScheme, Preferences};
+use crate::heatmap::HeatMap;
+use crate::hexdump::{
+    Detail,
+    BYTES_PER_LINE,
+    MAX_DUMP_BYTES,
+    ascii_column,
+    byte_at,
+    hex_column,
+};
 use crate::model::{GenericModel, TrafficModel, DeviceModel};
+use crate::native;
+use crate::otg::{self, OtgEvent, OtgEventKind};
+use crate::overrides::{EndpointClass, Overrides};
+use crate::pcap::{
+    self,
+    CaptureFileWriter,
+    CountingReader,
+    CountingWriter,
+    PacketFileWriter,
+    RotationLimits,
+    StreamWriter,
+};
+use crate::profiles::{Profile, Profiles, StreamProfile};
+use crate::recent::{RecentCapture, RecentCaptures};
+use crate::report::{
+    compliance_report, enumeration_report, Report, ReportFormat};
+use crate::requests::RequestStatistics;
 use crate::row_data::{
     GenericRowData,
     ToGenericRowData,
     TrafficRowData,
     DeviceRowData};
+use crate::sanitize::Sanitizer;
+use crate::schedule::Schedule;
+use crate::search::{Search, SearchKind};
+use crate::sequence::{ByteOrder, FieldLocation, SequenceCheck};
+use crate::session::Session;
+use crate::speeds::BusSpeed;
+use crate::splits::{SplitStatistics, MAX_EXPECTED_RETRIES};
+use crate::stats::Statistics;
+use crate::suspend::{IdleKind, IdlePeriods};
+use packetry_core::set_storage_dir;
+use crate::timeline::{Timeline, capture_span};
+use crate::traffic_index::start_indexing;
+use crate::trigger::Trigger;
+use crate::tree_list_model::ItemNodeRc;
+use crate::usb::{DeviceAddr, Direction, EndpointNum, PID};
+use crate::usb_ids::load_database;
 use crate::util::{fmt_count, fmt_size};
 
 #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
-use {
-    std::rc::Rc,
-    crate::record_ui::Recording,
-};
+use crate::record_ui::Recording;
 
 static TOTAL: AtomicU64 = AtomicU64::new(0);
 static CURRENT: AtomicU64 = AtomicU64::new(0);
 static STOP: AtomicBool = AtomicBool::new(false);
 static UPDATE_INTERVAL: Duration = Duration::from_millis(10);
 
+/// Maximum number of bytes shown in the follow stream window.
+const FOLLOW_DISPLAY_LIMIT: usize = 0x100000;
+
+/// Maximum number of breaks listed by a sequence number check.
+const SEQUENCE_DISPLAY_LIMIT: usize = 1000;
+
+/// Keyboard shortcuts for window actions, with their descriptions.
+const SHORTCUTS: [(&str, &str, &str); 12] = [
+    ("win.focus-filter", "<Ctrl>l", "Edit the display filter"),
+    ("win.find", "<Ctrl>f", "Find"),
+    ("win.go-to", "<Ctrl>g", "Go to a packet, transaction or time"),
+    ("win.next-error", "F8", "Select the next item with an error"),
+    ("win.previous-error", "<Shift>F8",
+     "Select the previous item with an error"),
+    ("win.next-on-endpoint", "<Alt>Down",
+     "Select the next item on the same endpoint"),
+    ("win.previous-on-endpoint", "<Alt>Up",
+     "Select the previous item on the same endpoint"),
+    ("win.start-capture", "<Ctrl>e", "Start capturing"),
+    ("win.stop-capture", "<Ctrl><Shift>e", "Stop capturing"),
+    ("win.insert-marker", "<Ctrl>m", "Mark the current time in a capture"),
+    ("win.shortcuts", "<Ctrl>question", "Show keyboard shortcuts"),
+    ("win.command-palette", "<Ctrl><Shift>p", "Search for a command to run"),
+];
+
+/// A command offered in the command palette: its label, and a function
+/// running it.
+type PaletteCommand = (String, Rc<dyn Fn()>);
+
+/// Keys handled by the traffic view, with their descriptions.
+const TRAFFIC_VIEW_KEYS: [(&str, &str); 2] = [
+    ("Right", "Expand the selected item"),
+    ("Left", "Collapse the selected item, or select its parent"),
+];
+
+/// Number of intervals plotted in the bandwidth graph.
+const GRAPH_INTERVALS: usize = 400;
+
+/// Number of intervals shown across the endpoint heat map.
+const HEATMAP_INTERVALS: usize = 200;
+
+/// Height of each endpoint's row in the heat map, in pixels.
+const HEATMAP_ROW_HEIGHT: f64 = 12.0;
+
+/// Width of the endpoint names at the left of the heat map, in pixels.
+const HEATMAP_LABEL_WIDTH: f64 = 90.0;
+
+/// Height of each lane in the timeline, in pixels.
+const TIMELINE_LANE_HEIGHT: f64 = 22.0;
+
+/// Width of the endpoint names at the left of the timeline, in pixels.
+const TIMELINE_LABEL_WIDTH: f64 = 90.0;
+
+/// Number of SOF groups listed at once in the frames window.
+const FRAME_PAGE_GROUPS: u64 = 16;
+
+/// Shortest runs of SOFs which can be chosen to show as idle frames.
+const IDLE_FRAME_RUNS: [u64; 3] = [2, 8, 64];
+
 #[cfg(feature="record-ui-test")]
 static UPDATE_LOCK: Mutex<()> = Mutex::new(());
 
 thread_local!(
     static WINDOW: RefCell<Option<ApplicationWindow>> = RefCell::new(None);
     static UI: RefCell<Option<UserInterface>> = RefCell::new(None);
+    static PREFERENCES: RefCell<Preferences> =
+        RefCell::new(Preferences::new());
 );
 
 #[derive(Copy, Clone, PartialEq)]
 enum FileAction {
     Load,
-    Save,
+    Merge,
+    Save(SaveFormat),
+    /// Save the packets chosen by the pending export scope.
+    Export,
+    /// Save the packets of each device address to a separate file.
+    Split,
+    Reanalyze,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SaveFormat {
+    Pcap,
+    Native,
+    JsonLines,
+    Csv,
+    /// Packets with their data scrubbed, for sharing publicly.
+    Sanitized,
+}
+
+struct StreamOptions {
+    path: Option<PathBuf>,
+    enable_check: CheckButton,
+    file_button: Button,
+    max_size_spin: SpinButton,
+    max_files_spin: SpinButton,
+    menu_button: MenuButton,
+}
+
+impl StreamOptions {
+    fn new() -> StreamOptions {
+        let enable_check = CheckButton::builder()
+            .label("Save to file while capturing")
+            .build();
+        let file_button = Button::builder()
+            .label("Choose file…")
+            .build();
+        let max_size_spin = SpinButton::with_range(0.0, 1e6, 1.0);
+        let max_files_spin = SpinButton::with_range(0.0, 1e4, 1.0);
+        let grid = gtk::Grid::builder()
+            .row_spacing(4)
+            .column_spacing(4)
+            .build();
+        let size_label = Label::builder()
+            .label("Maximum file size in MB (0 for no limit):")
+            .halign(Align::Start)
+            .build();
+        let files_label = Label::builder()
+            .label("Maximum number of files (0 for no limit):")
+            .halign(Align::Start)
+            .build();
+        grid.attach(&enable_check, 0, 0, 2, 1);
+        grid.attach(&file_button, 0, 1, 2, 1);
+        grid.attach(&size_label, 0, 2, 1, 1);
+        grid.attach(&max_size_spin, 1, 2, 1, 1);
+        grid.attach(&files_label, 0, 3, 1, 1);
+        grid.attach(&max_files_spin, 1, 3, 1, 1);
+        let popover = gtk::Popover::builder()
+            .child(&grid)
+            .build();
+        let menu_button = MenuButton::builder()
+            .icon_name("drive-harddisk")
+            .tooltip_text("Save to file while capturing")
+            .popover(&popover)
+            .build();
+        file_button.connect_clicked(|_| display_error(choose_stream_file()));
+        StreamOptions {
+            path: None,
+            enable_check,
+            file_button,
+            max_size_spin,
+            max_files_spin,
+            menu_button,
+        }
+    }
+
+    fn set_path(&mut self, path: PathBuf) {
+        let name = path
+            .file_name()
+            .map_or_else(
+                || path.to_string_lossy().to_string(),
+                |name| name.to_string_lossy().to_string());
+        self.file_button.set_label(&name);
+        self.enable_check.set_active(true);
+        self.path = Some(path);
+    }
+
+    fn settings(&self) -> Result<Option<(PathBuf, RotationLimits)>, Error> {
+        if !self.enable_check.is_active() {
+            return Ok(None);
+        }
+        let path = self.path
+            .clone()
+            .context("No file chosen for saving while capturing")?;
+        let max_size = self.max_size_spin.value_as_int() as u64;
+        let max_files = self.max_files_spin.value_as_int() as usize;
+        let limits = RotationLimits {
+            max_file_size: match max_size {
+                0 => None,
+                size => Some(size * 1_000_000),
+            },
+            max_files: match max_files {
+                0 => None,
+                count => Some(count),
+            },
+        };
+        Ok(Some((path, limits)))
+    }
+
+    /// The current settings, as saved in a capture profile.
+    fn profile(&self) -> Option<StreamProfile> {
+        if !self.enable_check.is_active() {
+            return None;
+        }
+        Some(StreamProfile {
+            path: self.path.clone()?,
+            max_file_size: self.max_size_spin.value_as_int() as u64,
+            max_files: self.max_files_spin.value_as_int() as u64,
+        })
+    }
+
+    /// Apply the settings saved in a capture profile.
+    fn apply(&mut self, stream: Option<&StreamProfile>) {
+        match stream {
+            Some(stream) => {
+                self.set_path(stream.path.clone());
+                self.max_size_spin.set_value(stream.max_file_size as f64);
+                self.max_files_spin.set_value(stream.max_files as f64);
+            },
+            None => self.enable_check.set_active(false),
+        }
+    }
+}
+
+/// Options for starting a capture at a later time, and stopping it once
+/// it has run for long enough.
+struct ScheduleOptions {
+    start_check: CheckButton,
+    start_entry: Entry,
+    duration_spin: SpinButton,
+    packets_spin: SpinButton,
+    size_spin: SpinButton,
+    menu_button: MenuButton,
+}
+
+impl ScheduleOptions {
+    fn new() -> ScheduleOptions {
+        let start_check = CheckButton::builder()
+            .label("Start at this time (UTC):")
+            .build();
+        let start_entry = Entry::builder()
+            .placeholder_text("HH:MM")
+            .width_chars(8)
+            .build();
+        let duration_spin = SpinButton::with_range(0.0, 1e6, 1.0);
+        let packets_spin = SpinButton::with_range(0.0, 1e12, 1000.0);
+        let size_spin = SpinButton::with_range(0.0, 1e7, 1.0);
+        let grid = gtk::Grid::builder()
+            .row_spacing(4)
+            .column_spacing(4)
+            .build();
+        grid.attach(&start_check, 0, 0, 1, 1);
+        grid.attach(&start_entry, 1, 0, 1, 1);
+        for (row, (label, spin)) in [
+            ("Stop after minutes (0 for no limit):", &duration_spin),
+            ("Stop after packets (0 for no limit):", &packets_spin),
+            ("Stop after MB of packets (0 for no limit):", &size_spin),
+        ].into_iter().enumerate() {
+            let label = Label::builder()
+                .label(label)
+                .halign(Align::Start)
+                .build();
+            grid.attach(&label, 0, row as i32 + 1, 1, 1);
+            grid.attach(spin, 1, row as i32 + 1, 1, 1);
+        }
+        let popover = gtk::Popover::builder()
+            .child(&grid)
+            .build();
+        let menu_button = MenuButton::builder()
+            .icon_name("alarm-symbolic")
+            .tooltip_text("Schedule capture")
+            .popover(&popover)
+            .build();
+        let check = start_check.clone();
+        start_entry.connect_changed(move |entry|
+            check.set_active(!entry.text().is_empty()));
+        ScheduleOptions {
+            start_check,
+            start_entry,
+            duration_spin,
+            packets_spin,
+            size_spin,
+            menu_button,
+        }
+    }
+
+    /// The schedule chosen for the next capture.
+    fn schedule(&self) -> Result<Schedule, Error> {
+        let limit = |spin: &SpinButton| match spin.value() as u64 {
+            0 => None,
+            value => Some(value),
+        };
+        let start_at = if self.start_check.is_active() {
+            Some(Schedule::parse_time_of_day(&self.start_entry.text())?)
+        } else {
+            None
+        };
+        Ok(Schedule {
+            start_at,
+            duration: limit(&self.duration_spin)
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            max_packets: limit(&self.packets_spin),
+            max_bytes: limit(&self.size_spin)
+                .map(|megabytes| megabytes * 1_000_000),
+        })
+    }
+}
+
+/// The advanced panel, with buttons for the test features of the analyzer
+/// capturing, or else of the one selected.
+struct TestOptions {
+    menu_button: MenuButton,
+}
+
+impl TestOptions {
+    fn new() -> TestOptions {
+        let status = Label::builder()
+            .halign(Align::Start)
+            .wrap(true)
+            .max_width_chars(40)
+            .build();
+        let vbox = gtk::Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(4)
+            .build();
+        vbox.append(&Label::builder()
+            .label("Analyzer test features")
+            .halign(Align::Start)
+            .css_classes(["heading"])
+            .build());
+        vbox.append(&status);
+        let buttons: Vec<(TestFeature, Button)> = TestFeature::ALL
+            .into_iter()
+            .map(|feature| (feature, Button::with_label(feature.description())))
+            .collect();
+        let end_button = Button::with_label("End test");
+        for (feature, button) in &buttons {
+            vbox.append(button);
+            let feature = *feature;
+            let status = status.clone();
+            button.connect_clicked(move |_| {
+                match current_tester().and_then(|t| t.perform(feature)) {
+                    Ok(()) if feature.is_held() => status.set_text(&format!(
+                        "Holding until ended: {}", feature.description())),
+                    Ok(()) => status.set_text(&format!(
+                        "Done: {}", feature.description())),
+                    Err(error) => display_error(Err(error)),
+                }
+            });
+        }
+        vbox.append(&end_button);
+        let end_status = status.clone();
+        end_button.connect_clicked(move |_| {
+            match current_tester().and_then(|tester| tester.end()) {
+                Ok(()) => end_status.set_text("Test ended"),
+                Err(error) => display_error(Err(error)),
+            }
+        });
+        let popover = gtk::Popover::builder()
+            .child(&vbox)
+            .build();
+        // Find which features are supported each time the panel is shown,
+        // as the analyzer selected may have changed.
+        popover.connect_show(move |_| {
+            let features = current_tester()
+                .map(|tester| tester.features().to_vec());
+            let supported = features.as_deref().unwrap_or(&[]);
+            for (feature, button) in &buttons {
+                button.set_sensitive(supported.contains(feature));
+            }
+            end_button.set_sensitive(
+                supported.iter().any(TestFeature::is_held));
+            status.set_text(&match &features {
+                Ok(features) if features.is_empty() =>
+                    "This analyzer's gateware has no test features."
+                        .to_string(),
+                Ok(_) =>
+                    "These act on the target port, disrupting traffic to \
+                     the device under test.".to_string(),
+                Err(error) => format!("{error}"),
+            });
+        });
+        let menu_button = MenuButton::builder()
+            .label("Advanced")
+            .tooltip_text("Test features of the analyzer, for electrical \
+                           validation")
+            .popover(&popover)
+            .build();
+        TestOptions { menu_button }
+    }
+}
+
+/// The tester of the analyzer capturing, or else of the one selected.
+fn current_tester() -> Result<CynthionTester, Error> {
+    let mut tester = None;
+    with_ui(|ui| {
+        tester = Some(match &ui.tester {
+            Some(tester) => tester.clone(),
+            None => ui.selector.tester()?,
+        });
+        Ok(())
+    })?;
+    tester.context("No analyzer available")
 }
 
+/// Environment variable naming a scenario file for an emulated analyzer.
+const SCENARIO_VARIABLE: &str = "PACKETRY_SCENARIO";
+
 struct DeviceSelector {
     devices: Vec<CynthionDevice>,
+    scenario: Option<Scenario>,
+    /// Pace of replay for the emulated analyzer.
+    playback: Playback,
+    /// Controls for the pace of replay, shown when emulating.
+    replay_box: gtk::Box,
     dev_strings: Vec<String>,
     dev_speeds: Vec<Vec<&'static str>>,
     dev_dropdown: DropDown,
@@ -112,6 +595,12 @@ impl DeviceSelector {
     fn new() -> Result<Self, Error> {
         let selector = DeviceSelector {
             devices: vec![],
+            scenario: None,
+            playback: Playback::new(),
+            replay_box: gtk::Box::builder()
+                .orientation(Orientation::Horizontal)
+                .visible(false)
+                .build(),
             dev_strings: vec![],
             dev_speeds: vec![],
             dev_dropdown: DropDown::from_strings(&[]),
@@ -135,20 +624,67 @@ impl DeviceSelector {
         selector.container.append(&selector.dev_dropdown);
         selector.container.append(&speed_label);
         selector.container.append(&selector.speed_dropdown);
+
+        // The pace of replay can be changed while capturing, so these
+        // controls act on the playback directly.
+        let replay_label = Label::builder()
+            .label(" Replay: ")
+            .margin_start(2)
+            .margin_end(2)
+            .build();
+        let replay_spin = SpinButton::with_range(MIN_SPEED, MAX_SPEED, 0.1);
+        replay_spin.set_digits(1);
+        replay_spin.set_value(1.0);
+        replay_spin.set_tooltip_text(Some(
+            "Speed of the scenario's delays, as a multiple of those written"));
+        let step_check = CheckButton::with_label("Step");
+        step_check.set_tooltip_text(Some(
+            "Hold back each packet until Next is clicked"));
+        let next_button = Button::with_label("Next");
+        next_button.set_tooltip_text(Some("Send the next packet"));
+        next_button.set_sensitive(false);
+        let playback = selector.playback.clone();
+        replay_spin.connect_value_changed(move |spin|
+            display_error(playback.set_speed(spin.value())));
+        let playback = selector.playback.clone();
+        let button = next_button.clone();
+        step_check.connect_toggled(move |check| {
+            playback.set_stepping(check.is_active());
+            button.set_sensitive(check.is_active());
+        });
+        let playback = selector.playback.clone();
+        next_button.connect_clicked(move |_| playback.step());
+        selector.replay_box.append(&replay_label);
+        selector.replay_box.append(&replay_spin);
+        selector.replay_box.append(&step_check);
+        selector.replay_box.append(&next_button);
+        selector.container.append(&selector.replay_box);
         Ok(selector)
     }
 
+    /// Show the replay controls only when the emulator is selected.
+    fn update_replay_controls(&self) {
+        self.replay_box.set_visible(self.emulator_selected());
+    }
+
     fn current_device(&self) -> Option<&CynthionDevice> {
-        if self.devices.is_empty() {
-            None
-        } else {
-            Some(&self.devices[self.dev_dropdown.selected() as usize])
-        }
+        self.devices.get(self.dev_dropdown.selected() as usize)
+    }
+
+    /// Whether the emulated analyzer, listed after any real ones, is
+    /// selected.
+    fn emulator_selected(&self) -> bool {
+        self.scenario.is_some() &&
+            self.dev_dropdown.selected() as usize == self.devices.len()
+    }
+
+    fn device_count(&self) -> usize {
+        self.devices.len() + usize::from(self.scenario.is_some())
     }
 
     fn device_available(&self) -> bool {
         match self.current_device() {
-            None => false,
+            None => self.emulator_selected(),
             Some(device) => match device.usability {
                 Usable(..) => true,
                 Unusable(..) => false,
@@ -158,7 +694,7 @@ impl DeviceSelector {
 
     fn set_sensitive(&mut self, sensitive: bool) {
         if sensitive {
-            self.dev_dropdown.set_sensitive(!self.devices.is_empty());
+            self.dev_dropdown.set_sensitive(self.device_count() > 0);
             self.speed_dropdown.set_sensitive(self.device_available());
         } else {
             self.dev_dropdown.set_sensitive(false);
@@ -171,6 +707,10 @@ impl DeviceSelector {
             self.dev_dropdown.disconnect(handler);
         }
         self.devices = CynthionDevice::scan()?;
+        self.scenario = match std::env::var_os(SCENARIO_VARIABLE) {
+            Some(path) => Some(Scenario::load(Path::new(&path))?),
+            None => None,
+        };
         let count = self.devices.len();
         self.dev_strings = Vec::with_capacity(count);
         self.dev_speeds = Vec::with_capacity(count);
@@ -197,12 +737,19 @@ impl DeviceSelector {
                 self.dev_speeds.push(vec![]);
             }
         }
+        if let Some(scenario) = &self.scenario {
+            self.dev_strings.push(
+                format!("Emulated Cynthion ({})", scenario.name));
+            self.dev_speeds.push(vec![scenario.speed.description()]);
+        }
         let no_speeds = vec![];
         let speed_strings = self.dev_speeds.first().unwrap_or(&no_speeds);
         self.replace_dropdown(&self.dev_dropdown, &self.dev_strings);
         self.replace_dropdown(&self.speed_dropdown, speed_strings);
-        self.dev_dropdown.set_sensitive(!self.devices.is_empty());
+        self.select_preferred_speed(speed_strings);
+        self.dev_dropdown.set_sensitive(self.device_count() > 0);
         self.speed_dropdown.set_sensitive(!speed_strings.is_empty());
+        self.update_replay_controls();
         self.change_handler = Some(
             self.dev_dropdown.connect_selected_notify(
                 |_| display_error(device_selection_changed())));
@@ -213,18 +760,88 @@ impl DeviceSelector {
         let index = self.dev_dropdown.selected() as usize;
         let speed_strings = &self.dev_speeds[index];
         self.replace_dropdown(&self.speed_dropdown, speed_strings);
+        self.select_preferred_speed(speed_strings);
         self.speed_dropdown.set_sensitive(!speed_strings.is_empty());
     }
 
-    fn open(&self) -> Result<(CynthionHandle, Speed), Error> {
-        let device_id = self.dev_dropdown.selected();
-        let device = &self.devices[device_id as usize];
+    /// Select the speed chosen in the preferences, if it is listed.
+    fn select_preferred_speed(&self, speed_strings: &[&str]) {
+        let preferred = preferences().speed;
+        if let Some(index) = speed_strings
+            .iter()
+            .position(|name| *name == preferred)
+        {
+            self.speed_dropdown.set_selected(index as u32);
+        }
+    }
+
+    /// The selected device, as listed.
+    fn device_name(&self) -> String {
+        self.dev_strings
+            .get(self.dev_dropdown.selected() as usize)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The selected speed, as listed.
+    fn speed_name(&self) -> String {
+        self.dev_speeds
+            .get(self.dev_dropdown.selected() as usize)
+            .and_then(|speeds|
+                speeds.get(self.speed_dropdown.selected() as usize))
+            .map(|speed| speed.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Select a device and speed by the names listed, where available.
+    fn select(&self, device: &str, speed: &str) {
+        if let Some(index) = self.dev_strings
+            .iter()
+            .position(|name| name == device)
+        {
+            // The change handler would borrow the UI, which is already
+            // borrowed here, so the speeds are updated directly instead.
+            if let Some(handler) = &self.change_handler {
+                self.dev_dropdown.block_signal(handler);
+            }
+            self.dev_dropdown.set_selected(index as u32);
+            if let Some(handler) = &self.change_handler {
+                self.dev_dropdown.unblock_signal(handler);
+            }
+            self.update_speeds();
+            self.update_replay_controls();
+        }
+        if let Some(index) = self.dev_speeds
+            .get(self.dev_dropdown.selected() as usize)
+            .and_then(|speeds| speeds.iter().position(|name| *name == speed))
+        {
+            self.speed_dropdown.set_selected(index as u32);
+        }
+    }
+
+    fn start(&self)
+        -> Result<(CynthionStream, CynthionStop, CynthionTrigger,
+                   CynthionTester), Error>
+    {
+        let device = match (self.current_device(), &self.scenario) {
+            (Some(device), _) => device,
+            (None, Some(scenario)) => {
+                let (stream, stop) = scenario.start_with_playback(
+                    self.playback.clone(), display_error)?;
+                return Ok((stream, stop, CynthionTrigger::emulated(),
+                           CynthionTester::emulated()));
+            },
+            (None, None) => bail!("No device selected"),
+        };
         match &device.usability {
             Usable(_, speeds) => {
                 let speed_id = self.speed_dropdown.selected() as usize;
                 let speed = speeds[speed_id];
                 let cynthion = device.open()?;
-                Ok((cynthion, speed))
+                let trigger = cynthion.trigger();
+                let tester = cynthion.tester()?;
+                let (stream, stop) = cynthion.start(speed, display_error)?;
+                Ok((stream, stop, trigger, tester))
             },
             Unusable(reason) => {
                 bail!("Device not usable: {}", reason)
@@ -232,6 +849,15 @@ impl DeviceSelector {
         }
     }
 
+    /// A tester for the selected analyzer, while not capturing.
+    fn tester(&self) -> Result<CynthionTester, Error> {
+        match (self.current_device(), &self.scenario) {
+            (Some(device), _) => device.open()?.tester(),
+            (None, Some(_)) => Ok(CynthionTester::emulated()),
+            (None, None) => bail!("No device selected"),
+        }
+    }
+
     fn replace_dropdown<T: AsRef<str>>(
         &self, dropdown: &DropDown, strings: &[T])
     {
@@ -248,25 +874,228 @@ impl DeviceSelector {
     }
 }
 
+struct ProfileOptions {
+    profiles: Profiles,
+    dropdown: DropDown,
+    name_entry: Entry,
+    select_handler: SignalHandlerId,
+    menu_button: MenuButton,
+    container: gtk::Box,
+}
+
+impl ProfileOptions {
+    fn new(profiles: Profiles) -> ProfileOptions {
+        let dropdown = DropDown::from_strings(&["None"]);
+        dropdown.set_tooltip_text(Some(
+            "Apply the capture settings saved in a profile"));
+        let name_entry = Entry::builder()
+            .placeholder_text("Profile name")
+            .build();
+        let save_button = Button::builder()
+            .label("Save")
+            .tooltip_text("Save the current capture settings as a profile")
+            .build();
+        let delete_button = Button::builder()
+            .label("Delete")
+            .tooltip_text("Delete the selected profile")
+            .build();
+        let buttons = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(4)
+            .homogeneous(true)
+            .build();
+        buttons.append(&save_button);
+        buttons.append(&delete_button);
+        let popover_box = gtk::Box::builder()
+            .orientation(Orientation::Vertical)
+            .spacing(4)
+            .build();
+        popover_box.append(&name_entry);
+        popover_box.append(&buttons);
+        let popover = gtk::Popover::builder()
+            .child(&popover_box)
+            .build();
+        let menu_button = MenuButton::builder()
+            .icon_name("document-save")
+            .tooltip_text("Save or delete capture profiles")
+            .popover(&popover)
+            .build();
+        let label = Label::builder()
+            .label("Profile: ")
+            .margin_start(2)
+            .margin_end(2)
+            .build();
+        let container = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .build();
+        container.append(&label);
+        container.append(&dropdown);
+        container.append(&menu_button);
+        let select_handler = dropdown.connect_selected_notify(
+            |_| display_error(profile_selected()));
+        save_button.connect_clicked(|_| display_error(save_profile()));
+        delete_button.connect_clicked(|_| display_error(delete_profile()));
+        let options = ProfileOptions {
+            profiles,
+            dropdown,
+            name_entry,
+            select_handler,
+            menu_button,
+            container,
+        };
+        options.refresh(None);
+        options
+    }
+
+    /// The selected profile, if any.
+    fn selected(&self) -> Option<&Profile> {
+        match self.dropdown.selected() {
+            0 => None,
+            index => self.profiles.iter().nth(index as usize - 1),
+        }
+    }
+
+    /// List the profiles, selecting one by name without applying it.
+    fn refresh(&self, selected: Option<&str>) {
+        self.dropdown.block_signal(&self.select_handler);
+        let mut names = vec!["None"];
+        names.extend(self.profiles.iter().map(|p| p.name.as_str()));
+        if let Some(model) = self.dropdown.model() {
+            let num_items = model.n_items();
+            if let Ok(list) = model.downcast::<StringList>() {
+                list.splice(0, num_items, &names);
+            }
+        }
+        let index = selected
+            .and_then(|name| names.iter().skip(1).position(|n| *n == name))
+            .map_or(0, |index| index + 1);
+        self.dropdown.set_selected(index as u32);
+        self.dropdown.unblock_signal(&self.select_handler);
+    }
+
+    fn set_sensitive(&self, sensitive: bool) {
+        self.dropdown.set_sensitive(sensitive);
+        self.menu_button.set_sensitive(sensitive);
+    }
+}
+
 pub struct UserInterface {
     pub capture: CaptureReader,
     selector: DeviceSelector,
+    stream_options: StreamOptions,
+    schedule_options: ScheduleOptions,
+    /// The pending start of a scheduled capture, and the handler which
+    /// cancels it when the stop button is clicked.
+    scheduled_start: Option<(gtk::glib::SourceId, SignalHandlerId)>,
+    profile_options: ProfileOptions,
     file_name: Option<String>,
+    file_paths: Vec<PathBuf>,
+    /// Classes and descriptors chosen for devices in the capture shown,
+    /// applied when it is analyzed again.
+    overrides: Overrides,
+    /// Whether the clocks of the files next merged are aligned first.
+    align_clocks: bool,
+    pending_session: Option<Session>,
+    autosave_path: Option<PathBuf>,
     stop_handle: Option<CynthionStop>,
+    /// Performs the test features of the analyzer while capturing.
+    tester: Option<CynthionTester>,
+    filter: Option<Arc<Filter>>,
+    filter_entry: Entry,
+    capture_filter_entry: Entry,
+    trigger_entry: Entry,
+    snap_spin: SpinButton,
+    packets_only_check: gtk::CheckButton,
+    /// Size in bytes at which a live capture is stopped.
+    storage_limit: Option<u64>,
+    /// Whether the last capture was stopped at the storage limit.
+    storage_limit_reached: bool,
+    /// Whether the last capture was stopped at a limit of its schedule.
+    schedule_done: bool,
+    /// Rules raising alerts when live traffic matches a filter.
+    alert_rules: AlertRules,
+    /// Filter of the last alert rule matched in this capture, if any.
+    last_alert: Option<String>,
+    /// Result of checking a loaded Packetry capture against its hash.
+    verification: Option<Verification>,
+    /// Directory for the files holding captures, if not the default.
+    storage_dir: Option<PathBuf>,
+    find_entry: SearchEntry,
+    find_kind: DropDown,
+    find_label: Label,
+    find_model: Option<TrafficModel>,
+    goto_label: Label,
+    goto_popover: gtk::Popover,
+    bookmark_button: ToggleButton,
+    comment_entry: Entry,
+    bookmark_list: ListBox,
+    bookmark_items: Vec<TrafficItem>,
+    field_list: ListBox,
+    hex_view: TextView,
+    detail: Detail,
+    color_rules: Arc<ColorRules>,
+    color_css: CssProvider,
+    theme: Rc<RefCell<Theme>>,
+    columns: Columns,
+    sort_order: Option<Arc<Vec<TrafficItemId>>>,
+    time_reference: Option<Timestamp>,
+    /// Shortest run of SOFs described as idle frames, if any.
+    idle_frames: Option<u64>,
+    /// Whether groups of polls are described with the time they span.
+    poll_durations: bool,
+    /// How far down the traffic tree items are shown.
+    detail_level: DetailLevel,
+    graph_button: ToggleButton,
+    graph_selection: DropDown,
+    graph_speed: DropDown,
+    graph_label: Label,
+    graph_area: gtk::DrawingArea,
+    graph_options: Vec<Selection>,
+    graph: Rc<RefCell<Graph>>,
+    heatmap_button: ToggleButton,
+    heatmap_label: Label,
+    heatmap_area: gtk::DrawingArea,
+    heatmap: Rc<RefCell<HeatMap>>,
     traffic_window: ScrolledWindow,
     device_window: ScrolledWindow,
+    endpoint_box: gtk::Box,
+    endpoint_list: ListBox,
+    /// Labels showing the traffic counted on each endpoint listed.
+    endpoint_counts: Vec<Label>,
+    endpoint_checks: Vec<CheckButton>,
+    activity: Activity,
+    error_box: gtk::Box,
+    error_label: Label,
+    error_list: ListBox,
+    errors: Errors,
+    bookmark_box: gtk::Box,
+    field_window: ScrolledWindow,
+    hex_window: ScrolledWindow,
     pub traffic_model: Option<TrafficModel>,
     pub device_model: Option<DeviceModel>,
+    traffic_selection: Option<SingleSelection>,
+    device_selection: Option<SingleSelection>,
     endpoint_count: u16,
     show_progress: Option<FileAction>,
+    /// Packets chosen for export, until the export is started.
+    export_scope: Option<ExportScope>,
     progress_bar: ProgressBar,
     separator: Separator,
     vbox: gtk::Box,
-    paned: gtk::Paned,
+    panes_box: gtk::Box,
+    layout: Layout,
+    /// The dividers between panes, named for saving their positions.
+    paneds: Vec<(String, gtk::Paned)>,
+    /// Windows holding detached panes.
+    pane_windows: Vec<gtk::Window>,
     open_button: Button,
+    merge_button: Button,
+    reanalyze_button: Button,
     save_button: Button,
+    export_button: Button,
     scan_button: Button,
     capture_button: Button,
+    autoscroll_button: ToggleButton,
     stop_button: Button,
     status_label: Label,
     #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
@@ -288,6 +1117,11 @@ pub fn with_ui<F>(f: F) -> Result<(), Error>
 pub fn activate(application: &Application) -> Result<(), Error> {
     use FileAction::*;
 
+    display_error(
+        use_environment_language(&translations_path()).map(|_| ()));
+    let preferences = load_preferences();
+    PREFERENCES.with(|cell| cell.replace(preferences.clone()));
+
     let window = gtk::ApplicationWindow::builder()
         .default_width(320)
         .default_height(480)
@@ -299,39 +1133,286 @@ pub fn activate(application: &Application) -> Result<(), Error> {
 
     let open_button = gtk::Button::builder()
         .icon_name("document-open")
-        .tooltip_text("Open")
+        .tooltip_text(tr("Open"))
+        .build();
+    let recent_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let recent_popover = gtk::Popover::builder()
+        .child(&recent_list)
+        .build();
+    recent_popover.connect_show(move |popover|
+        fill_recent_list(popover, &recent_list));
+    let recent_button = MenuButton::builder()
+        .icon_name("document-open-recent")
+        .tooltip_text(tr("Recent captures"))
+        .popover(&recent_popover)
+        .build();
+    open_button
+        .bind_property("sensitive", &recent_button, "sensitive")
+        .build();
+    let merge_button = gtk::Button::builder()
+        .icon_name("list-add")
+        .tooltip_text(tr("Merge captures"))
+        .build();
+    let reanalyze_button = gtk::Button::builder()
+        .icon_name("view-refresh")
+        .tooltip_text(tr("Re-run analysis on the loaded packets"))
         .build();
     let save_button = gtk::Button::builder()
         .icon_name("document-save")
-        .tooltip_text("Save")
+        .tooltip_text(tr("Save"))
+        .build();
+    let export_button = gtk::Button::builder()
+        .icon_name("document-save-as")
+        .tooltip_text(tr("Export selection"))
         .build();
     let scan_button = gtk::Button::builder()
         .icon_name("view-refresh")
-        .tooltip_text("Scan for devices")
+        .tooltip_text(tr("Scan for devices"))
         .build();
     let capture_button = gtk::Button::builder()
         .icon_name("media-record")
-        .tooltip_text("Capture")
+        .tooltip_text(tr("Capture"))
         .build();
     let stop_button = gtk::Button::builder()
         .icon_name("media-playback-stop")
-        .tooltip_text("Stop")
+        .tooltip_text(tr("Stop"))
+        .build();
+    let autoscroll_button = gtk::ToggleButton::builder()
+        .icon_name("go-bottom")
+        .tooltip_text(tr("Follow new traffic during capture"))
+        .active(true)
+        .sensitive(false)
+        .build();
+    let color_button = gtk::Button::builder()
+        .icon_name("applications-graphics")
+        .tooltip_text(tr("Coloring rules"))
+        .build();
+    let columns_button = gtk::Button::builder()
+        .icon_name("view-list")
+        .tooltip_text(tr("Columns and sorting"))
+        .build();
+    let follow_button = gtk::Button::builder()
+        .icon_name("format-justify-left")
+        .tooltip_text(tr("Follow stream on the selected item's endpoint"))
+        .build();
+    let stats_button = gtk::Button::builder()
+        .icon_name("x-office-spreadsheet")
+        .tooltip_text(tr("Statistics"))
+        .build();
+    let report_button = gtk::Button::builder()
+        .icon_name("x-office-document")
+        .tooltip_text(tr("Device enumeration report"))
+        .build();
+    let compare_button = gtk::Button::builder()
+        .icon_name("view-dual")
+        .tooltip_text(tr("Compare with another capture"))
+        .build();
+    let storage_button = gtk::Button::builder()
+        .icon_name("drive-multidisk")
+        .tooltip_text(tr("Capture storage"))
+        .build();
+    let properties_button = gtk::Button::builder()
+        .icon_name("document-properties")
+        .tooltip_text(tr("Capture properties"))
+        .build();
+    let timeline_button = gtk::Button::builder()
+        .icon_name("preferences-system-time")
+        .tooltip_text(tr("Timeline"))
+        .build();
+    let graph_button = gtk::ToggleButton::builder()
+        .icon_name("utilities-system-monitor")
+        .tooltip_text(tr("Bandwidth graph"))
+        .build();
+    let heatmap_button = gtk::ToggleButton::builder()
+        .icon_name("view-grid-symbolic")
+        .tooltip_text(tr("Endpoint heat map"))
+        .build();
+    let find_button = gtk::ToggleButton::builder()
+        .icon_name("edit-find")
+        .tooltip_text(tr("Find"))
+        .build();
+    let timestamp_menu = gio::Menu::new();
+    for mode in TimestampMode::ALL {
+        timestamp_menu.append(
+            Some(&tr(mode.label())),
+            Some(&format!("win.timestamp-mode::{}", mode.name())));
+    }
+    let reference_menu = gio::Menu::new();
+    reference_menu.append(
+        Some(&tr("Use selected item as time reference")),
+        Some("win.set-time-reference"));
+    reference_menu.append(
+        Some(&tr("Clear time reference")),
+        Some("win.clear-time-reference"));
+    let view_menu = gio::Menu::new();
+    view_menu.append_section(Some(&tr("Timestamps")), &timestamp_menu);
+    view_menu.append_section(None, &reference_menu);
+    let detail_menu = gio::Menu::new();
+    for level in DetailLevel::ALL {
+        detail_menu.append(
+            Some(&tr(level.label())),
+            Some(&format!("win.detail-level::{}", level.name())));
+    }
+    view_menu.append_section(Some(&tr("Level of detail")), &detail_menu);
+    let panes_menu = gio::Menu::new();
+    for pane in Pane::ALL {
+        let place_menu = gio::Menu::new();
+        for place in Place::ALL {
+            place_menu.append(
+                Some(&tr(place.label())),
+                Some(&format!("win.place-{}::{}", pane.name(), place.name())));
+        }
+        panes_menu.append_submenu(Some(&tr(pane.label())), &place_menu);
+    }
+    view_menu.append_section(Some(&tr("Panes")), &panes_menu);
+    let frames_menu = gio::Menu::new();
+    frames_menu.append(Some(&tr("Hide SOF packets")), Some("win.hide-sof"));
+    frames_menu.append(Some(&tr("Frames…")), Some("win.show-frames"));
+    view_menu.append_section(Some(&tr("Frames")), &frames_menu);
+    let idle_menu = gio::Menu::new();
+    idle_menu.append(
+        Some(&tr("Count SOF packets")), Some("win.idle-frames::0"));
+    for run in IDLE_FRAME_RUNS {
+        idle_menu.append(
+            Some(&trf("Aggregate {} or more idle frames", &[&run])),
+            Some(&format!("win.idle-frames::{run}")));
+    }
+    view_menu.append_section(Some(&tr("Runs of SOFs")), &idle_menu);
+    let polling_menu = gio::Menu::new();
+    polling_menu.append(
+        Some(&tr("Hide polling")), Some("win.hide-polling"));
+    polling_menu.append(
+        Some(&tr("Show polling durations")), Some("win.poll-durations"));
+    view_menu.append_section(Some(&tr("Polling")), &polling_menu);
+    let requests_menu = gio::Menu::new();
+    requests_menu.append(
+        Some(&tr("Control requests…")), Some("win.show-requests"));
+    requests_menu.append(
+        Some(&tr("Split transactions…")), Some("win.show-splits"));
+    requests_menu.append(
+        Some(&tr("Compliance checks…")), Some("win.show-compliance"));
+    view_menu.append_section(None, &requests_menu);
+    let preferences_menu = gio::Menu::new();
+    preferences_menu.append(
+        Some(&tr("Alert rules…")), Some("win.show-alerts"));
+    preferences_menu.append(
+        Some(&tr("Preferences…")), Some("win.show-preferences"));
+    view_menu.append_section(None, &preferences_menu);
+    let view_button = MenuButton::builder()
+        .label(tr("View"))
+        .menu_model(&view_menu)
         .build();
 
     open_button.set_sensitive(true);
+    merge_button.set_sensitive(true);
+    reanalyze_button.set_sensitive(false);
     save_button.set_sensitive(false);
+    export_button.set_sensitive(false);
     scan_button.set_sensitive(true);
 
     let selector = DeviceSelector::new()?;
     capture_button.set_sensitive(selector.device_available());
 
+    let stream_options = StreamOptions::new();
+    let schedule_options = ScheduleOptions::new();
+    let test_options = TestOptions::new();
+    let profile_options = ProfileOptions::new(load_profiles());
+
+    let capture_filter_entry = gtk::Entry::builder()
+        .placeholder_text("Capture filter")
+        .tooltip_text(CAPTURE_FILTER_HELP)
+        .width_chars(24)
+        .build();
+    let trigger_entry = gtk::Entry::builder()
+        .placeholder_text("Trigger filter")
+        .tooltip_text(TRIGGER_HELP)
+        .width_chars(16)
+        .build();
+
+    let snap_label = Label::builder()
+        .label("Snap length: ")
+        .margin_start(2)
+        .margin_end(2)
+        .build();
+    let snap_spin = SpinButton::with_range(0.0, 65536.0, 1.0);
+    snap_spin.set_tooltip_text(Some(
+        "Bytes of payload to store from each data packet, \
+         or 0 to store them in full"));
+    let packets_only_check = gtk::CheckButton::with_label("Packets only");
+    packets_only_check.set_tooltip_text(Some(
+        "Store packets without grouping them into transactions and \
+         transfers, to keep up with busy buses. Use Reanalyze afterwards \
+         to decode them fully."));
+
     action_bar.pack_start(&open_button);
+    action_bar.pack_start(&recent_button);
+    action_bar.pack_start(&merge_button);
+    action_bar.pack_start(&reanalyze_button);
     action_bar.pack_start(&save_button);
+    action_bar.pack_start(&export_button);
     action_bar.pack_start(&gtk::Separator::new(Orientation::Vertical));
     action_bar.pack_start(&scan_button);
     action_bar.pack_start(&capture_button);
     action_bar.pack_start(&stop_button);
+    action_bar.pack_start(&autoscroll_button);
+    action_bar.pack_start(&stream_options.menu_button);
+    action_bar.pack_start(&schedule_options.menu_button);
+    action_bar.pack_start(&test_options.menu_button);
+    action_bar.pack_start(&capture_filter_entry);
+    action_bar.pack_start(&trigger_entry);
+    action_bar.pack_start(&snap_label);
+    action_bar.pack_start(&snap_spin);
+    action_bar.pack_start(&packets_only_check);
     action_bar.pack_start(&selector.container);
+    action_bar.pack_start(&profile_options.container);
+    let goto_entry = gtk::Entry::builder()
+        .placeholder_text("Packet, transaction or time")
+        .tooltip_text(GOTO_HELP)
+        .width_chars(28)
+        .build();
+    let goto_label = Label::builder()
+        .halign(Align::Start)
+        .build();
+    let goto_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(4)
+        .build();
+    goto_box.append(&goto_entry);
+    goto_box.append(&goto_label);
+    let goto_popover = gtk::Popover::builder()
+        .child(&goto_box)
+        .build();
+    let goto_button = MenuButton::builder()
+        .icon_name("go-jump")
+        .tooltip_text("Go to…")
+        .popover(&goto_popover)
+        .build();
+
+    action_bar.pack_end(&find_button);
+    action_bar.pack_end(&view_button);
+    action_bar.pack_end(&goto_button);
+    action_bar.pack_end(&color_button);
+    action_bar.pack_end(&columns_button);
+    action_bar.pack_end(&follow_button);
+    action_bar.pack_end(&stats_button);
+    action_bar.pack_end(&report_button);
+    action_bar.pack_end(&storage_button);
+    action_bar.pack_end(&properties_button);
+    action_bar.pack_end(&heatmap_button);
+    action_bar.pack_end(&graph_button);
+    action_bar.pack_end(&timeline_button);
+    action_bar.pack_end(&compare_button);
+
+    let theme = Rc::new(RefCell::new(load_theme()));
+    let color_rules = load_color_rules(theme.borrow().palette());
+    let color_css = CssProvider::new();
+    color_css.load_from_data(&color_rules.css());
+    gtk::style_context_add_provider_for_display(
+        &WidgetExt::display(&window),
+        &color_css,
+        gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
 
     #[cfg(not(feature="test-ui-replay"))]
     window.show();
@@ -340,62 +1421,820 @@ pub fn activate(application: &Application) -> Result<(), Error> {
     let args: Vec<_> = std::env::args().collect();
     let (_, capture) = create_capture()?;
 
-    let traffic_window = gtk::ScrolledWindow::builder()
-        .hscrollbar_policy(gtk::PolicyType::Automatic)
-        .min_content_height(480)
-        .min_content_width(640)
+    let filter_entry = gtk::Entry::builder()
+        .placeholder_text(
+            "Filter, e.g. device == 5 && endpoint == 0x81 && data contains 55:AA")
+        .primary_icon_name("edit-find-symbolic")
+        .hexpand(true)
+        .margin_top(2)
+        .margin_bottom(2)
+        .margin_start(3)
+        .margin_end(3)
         .build();
 
-    let device_window = gtk::ScrolledWindow::builder()
-        .hscrollbar_policy(gtk::PolicyType::Automatic)
-        .min_content_height(480)
-        .min_content_width(240)
+    let find_entry = gtk::SearchEntry::builder()
+        .placeholder_text("Find in summaries and data")
+        .hexpand(true)
         .build();
-
-    let paned = gtk::Paned::builder()
+    let find_kind = gtk::DropDown::from_strings(
+        &["Text", "Regex", "Hex bytes"]);
+    let find_previous = gtk::Button::builder()
+        .icon_name("go-up")
+        .tooltip_text("Find previous")
+        .build();
+    let find_next = gtk::Button::builder()
+        .icon_name("go-down")
+        .tooltip_text("Find next")
+        .build();
+    let find_label = gtk::Label::new(None);
+    let find_box = gtk::Box::builder()
         .orientation(Orientation::Horizontal)
-        .wide_handle(true)
-        .start_child(&traffic_window)
-        .end_child(&device_window)
-        .vexpand(true)
+        .spacing(6)
         .build();
-
-    let separator = gtk::Separator::new(Orientation::Horizontal);
-
-    let progress_bar = gtk::ProgressBar::builder()
-        .show_text(true)
-        .text("")
-        .hexpand(true)
+    find_box.append(&find_entry);
+    find_box.append(&find_kind);
+    find_box.append(&find_previous);
+    find_box.append(&find_next);
+    find_box.append(&find_label);
+    let find_bar = gtk::SearchBar::builder()
+        .child(&find_box)
+        .show_close_button(true)
+        .build();
+    find_bar.connect_entry(&find_entry);
+    find_button
+        .bind_property("active", &find_bar, "search-mode-enabled")
+        .bidirectional()
         .build();
 
-    let status_label = gtk::Label::builder()
-        .label("Ready")
-        .single_line_mode(true)
+    let graph = Rc::new(RefCell::new(Graph::default()));
+    let graph_selection = gtk::DropDown::from_strings(&["All traffic"]);
+    let speed_names: Vec<&str> = BusSpeed::ALL
+        .iter()
+        .map(BusSpeed::description)
+        .collect();
+    let graph_speed = gtk::DropDown::from_strings(&speed_names);
+    let graph_refresh = gtk::Button::builder()
+        .icon_name("view-refresh")
+        .tooltip_text("Update graph")
+        .build();
+    let graph_save = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save graph as an image")
+        .build();
+    let graph_label = gtk::Label::builder()
         .halign(Align::Start)
         .hexpand(true)
-        .margin_top(2)
-        .margin_bottom(2)
+        .build();
+    let graph_header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .margin_top(3)
+        .margin_bottom(3)
         .margin_start(3)
         .margin_end(3)
         .build();
-
-    let vbox = gtk::Box::builder()
+    graph_header.append(&graph_selection);
+    graph_header.append(&Label::new(Some("Bus speed:")));
+    graph_header.append(&graph_speed);
+    graph_header.append(&graph_refresh);
+    graph_header.append(&graph_save);
+    graph_header.append(&graph_label);
+    let graph_area = gtk::DrawingArea::builder()
+        .content_height(120)
+        .hexpand(true)
+        .tooltip_text("Click to go to the traffic at that time")
+        .accessible_role(AccessibleRole::Img)
+        .build();
+    graph_area.update_property(&[Property::Label(&tr("Bandwidth graph"))]);
+    graph_area.update_relation(
+        &[Relation::DescribedBy(&[graph_label.upcast_ref()])]);
+    let graph_box = gtk::Box::builder()
         .orientation(Orientation::Vertical)
         .build();
+    graph_box.append(&graph_header);
+    graph_box.append(&graph_area);
+    let graph_revealer = gtk::Revealer::builder()
+        .child(&graph_box)
+        .build();
+    graph_button
+        .bind_property("active", &graph_revealer, "reveal-child")
+        .build();
+
+    let heatmap = Rc::new(RefCell::new(HeatMap::default()));
+    let heatmap_refresh = gtk::Button::builder()
+        .icon_name("view-refresh")
+        .tooltip_text("Update heat map")
+        .build();
+    let heatmap_label = gtk::Label::builder()
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let heatmap_header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .margin_top(3)
+        .margin_bottom(3)
+        .margin_start(3)
+        .margin_end(3)
+        .build();
+    heatmap_header.append(&heatmap_refresh);
+    heatmap_header.append(&heatmap_label);
+    let heatmap_area = gtk::DrawingArea::builder()
+        .content_height(HEATMAP_ROW_HEIGHT as i32)
+        .hexpand(true)
+        .tooltip_text("Click to go to the traffic on an endpoint at that \
+                       time, or right-click to show only that endpoint")
+        .accessible_role(AccessibleRole::Img)
+        .build();
+    heatmap_area.update_property(
+        &[Property::Label(&tr("Endpoint heat map"))]);
+    heatmap_area.update_relation(
+        &[Relation::DescribedBy(&[heatmap_label.upcast_ref()])]);
+    let heatmap_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    heatmap_box.append(&heatmap_header);
+    heatmap_box.append(&heatmap_area);
+    let heatmap_revealer = gtk::Revealer::builder()
+        .child(&heatmap_box)
+        .build();
+    heatmap_button
+        .bind_property("active", &heatmap_revealer, "reveal-child")
+        .build();
+
+    let traffic_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Automatic)
+        .min_content_height(480)
+        .min_content_width(640)
+        .build();
+
+    let device_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Automatic)
+        .min_content_height(480)
+        .min_content_width(240)
+        .build();
+
+    let field_list = gtk::ListBox::new();
+    let field_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Automatic)
+        .min_content_width(240)
+        .child(&field_list)
+        .build();
+    let hex_view = gtk::TextView::builder()
+        .editable(false)
+        .cursor_visible(false)
+        .monospace(true)
+        .build();
+    hex_view.buffer().create_tag(
+        Some("highlight"),
+        &[("background", &theme.borrow().color(Element::Highlight,
+                                               dark_theme()))]);
+    let hex_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Automatic)
+        .min_content_height(160)
+        .hexpand(true)
+        .child(&hex_view)
+        .build();
+    let bookmark_button = gtk::ToggleButton::builder()
+        .icon_name("starred")
+        .tooltip_text("Bookmark the selected item")
+        .sensitive(false)
+        .build();
+    let comment_entry = gtk::Entry::builder()
+        .placeholder_text("Comment on the selected item")
+        .hexpand(true)
+        .sensitive(false)
+        .build();
+    let annotation_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(3)
+        .margin_top(3)
+        .margin_bottom(3)
+        .margin_start(3)
+        .margin_end(3)
+        .build();
+    annotation_box.append(&bookmark_button);
+    annotation_box.append(&comment_entry);
+
+    let bookmark_list = gtk::ListBox::new();
+    let bookmark_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(120)
+        .vexpand(true)
+        .child(&bookmark_list)
+        .build();
+
+    let bookmark_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    bookmark_box.append(&annotation_box);
+    bookmark_box.append(&bookmark_window);
+
+    let endpoint_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let endpoint_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(120)
+        .vexpand(true)
+        .child(&endpoint_list)
+        .build();
+    let endpoint_help = Label::builder()
+        .label("Packets on each endpoint. Uncheck to hide its traffic.")
+        .wrap(true)
+        .xalign(0.0)
+        .margin_top(3)
+        .margin_bottom(3)
+        .margin_start(3)
+        .margin_end(3)
+        .build();
+    let endpoint_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    endpoint_box.append(&endpoint_help);
+    endpoint_box.append(&endpoint_window);
+
+    let error_list = gtk::ListBox::new();
+    let error_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(120)
+        .vexpand(true)
+        .child(&error_list)
+        .build();
+    let error_label = Label::builder()
+        .label("No errors found")
+        .xalign(0.0)
+        .margin_top(3)
+        .margin_bottom(3)
+        .margin_start(3)
+        .margin_end(3)
+        .build();
+    let error_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    error_box.append(&error_label);
+    error_box.append(&error_window);
+
+    // The panes are placed in this box by arrange_panes.
+    let panes_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .vexpand(true)
+        .build();
+
+    let separator = gtk::Separator::new(Orientation::Horizontal);
+
+    let progress_bar = gtk::ProgressBar::builder()
+        .show_text(true)
+        .text("")
+        .hexpand(true)
+        .build();
+
+    let status_label = gtk::Label::builder()
+        .label("Ready")
+        .single_line_mode(true)
+        .halign(Align::Start)
+        .hexpand(true)
+        .margin_top(2)
+        .margin_bottom(2)
+        .margin_start(3)
+        .margin_end(3)
+        .build();
+
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+
+    vbox.append(&action_bar);
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
+    vbox.append(&filter_entry);
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
+    vbox.append(&find_bar);
+    vbox.append(&graph_revealer);
+    vbox.append(&heatmap_revealer);
+    vbox.append(&panes_box);
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
+    vbox.append(&status_label);
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
 
-    vbox.append(&action_bar);
-    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
-    vbox.append(&paned);
-    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
-    vbox.append(&status_label);
-    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
-
     window.set_child(Some(&vbox));
+    window.connect_close_request(|_| {
+        display_error(save_session());
+        gtk::glib::Propagation::Proceed
+    });
 
     scan_button.connect_clicked(|_| display_error(detect_hardware()));
-    capture_button.connect_clicked(|_| display_error(start_cynthion()));
+    capture_button.connect_clicked(|_| display_error(schedule_capture()));
     open_button.connect_clicked(|_| display_error(choose_file(Load)));
-    save_button.connect_clicked(|_| display_error(choose_file(Save)));
+    merge_button.connect_clicked(|_| display_error(choose_file(Merge)));
+    reanalyze_button.connect_clicked(|_|
+        display_error(start_pcap(Reanalyze, Vec::new())));
+    save_button.connect_clicked(|_|
+        display_error(choose_file(Save(SaveFormat::Pcap))));
+    export_button.connect_clicked(|_| display_error(choose_export()));
+    color_button.connect_clicked(|_| display_error(show_color_rules()));
+    columns_button.connect_clicked(|_| display_error(show_columns()));
+    follow_button.connect_clicked(|_| display_error(follow_stream()));
+    stats_button.connect_clicked(|_| display_error(show_statistics()));
+    report_button.connect_clicked(|_| display_error(show_report()));
+    storage_button.connect_clicked(|_| display_error(show_storage()));
+    properties_button.connect_clicked(|_| display_error(show_properties()));
+    timeline_button.connect_clicked(|_| display_error(show_timeline()));
+    compare_button.connect_clicked(|_| display_error(choose_comparison()));
+    filter_entry.connect_changed(check_filter);
+    capture_filter_entry.connect_changed(check_capture_filter);
+    trigger_entry.connect_changed(check_trigger_filter);
+    filter_entry.connect_activate(|_| display_error(apply_filter()));
+    find_entry.connect_activate(|_| display_error(find(false)));
+    find_entry.connect_next_match(|_| display_error(find(false)));
+    find_entry.connect_previous_match(|_| display_error(find(true)));
+    find_next.connect_clicked(|_| display_error(find(false)));
+    find_previous.connect_clicked(|_| display_error(find(true)));
+    bookmark_button.connect_clicked(|button| {
+        let bookmarked = button.is_active();
+        display_error(edit_annotation(move |annotation|
+            annotation.bookmarked = bookmarked))
+    });
+    comment_entry.connect_activate(|entry| {
+        let comment = entry.text().trim().to_string();
+        display_error(edit_annotation(move |annotation|
+            annotation.comment = comment))
+    });
+    goto_entry.connect_activate(|entry| {
+        let text = entry.text().to_string();
+        display_error(go_to(&text))
+    });
+    bookmark_list.connect_row_activated(|_, row|
+        display_error(go_to_bookmark(row.index())));
+    error_list.connect_row_activated(|_, row|
+        display_error(go_to_error(row.index())));
+    // The list may be changing when a row is selected, so defer updates.
+    field_list.connect_row_selected(|_, row| {
+        let index = row.map(|row| row.index());
+        gtk::glib::idle_add_local_once(move ||
+            display_error(highlight_field(index)));
+    });
+    let hex_click = gtk::GestureClick::new();
+    let clicked_view = hex_view.clone();
+    hex_click.connect_released(move |_, _, x, y| {
+        let (x, y) = clicked_view.window_to_buffer_coords(
+            gtk::TextWindowType::Widget, x as i32, y as i32);
+        if let Some(iter) = clicked_view.iter_at_location(x, y) {
+            display_error(select_byte(iter.line(), iter.line_offset()));
+        }
+    });
+    hex_view.add_controller(hex_click);
+
+    graph_button.connect_toggled(|_| display_error(update_graph()));
+    graph_refresh.connect_clicked(|_| display_error(update_graph()));
+    // The options may be changing when one is selected, so defer updates.
+    graph_selection.connect_selected_notify(|_| {
+        gtk::glib::idle_add_local_once(|| display_error(update_graph()));
+    });
+    let drawn_graph = graph.clone();
+    let drawn_speed = graph_speed.clone();
+    let drawn_theme = theme.clone();
+    graph_area.set_draw_func(move |_, context, width, height|
+        draw_graph(&drawn_graph.borrow(),
+                   graph_bus_speed(&drawn_speed),
+                   &drawn_theme.borrow(),
+                   context, width, height));
+    // Follow changes between light and dark themes.
+    if let Some(settings) = gtk::Settings::default() {
+        settings.connect_gtk_application_prefer_dark_theme_notify(|_| {
+            gtk::glib::idle_add_local_once(|| {
+                display_error(with_ui(|ui| { apply_theme(ui); Ok(()) }));
+            });
+        });
+        settings.connect_gtk_theme_name_notify(|_| {
+            gtk::glib::idle_add_local_once(|| {
+                display_error(with_ui(|ui| { apply_theme(ui); Ok(()) }));
+            });
+        });
+    }
+    let speed_area = graph_area.clone();
+    let speed_graph = graph.clone();
+    let speed_label = graph_label.clone();
+    graph_speed.connect_selected_notify(move |dropdown| {
+        speed_label.set_text(
+            &graph_summary(&speed_graph.borrow(), graph_bus_speed(dropdown)));
+        speed_area.queue_draw();
+    });
+    let graph_click = gtk::GestureClick::new();
+    let clicked_area = graph_area.clone();
+    let clicked_graph = graph.clone();
+    graph_click.connect_released(move |_, _, x, _| {
+        let width = clicked_area.width();
+        let time = {
+            let graph = clicked_graph.borrow();
+            if width == 0 || graph.bytes.is_empty() {
+                return;
+            }
+            graph.time_at(x / width as f64)
+        };
+        display_error(go_to_time(time));
+    });
+    graph_area.add_controller(graph_click);
+    let saved_area = graph_area.clone();
+    let saved_graph = graph.clone();
+    let saved_speed = graph_speed.clone();
+    let saved_theme = theme.clone();
+    graph_save.connect_clicked(move |_| {
+        let graph = saved_graph.borrow().clone();
+        let speed = graph_bus_speed(&saved_speed);
+        let theme = saved_theme.clone();
+        display_error(choose_image_save(
+            "Save bandwidth graph",
+            saved_area.width(),
+            saved_area.height(),
+            move |context, width, height|
+                draw_graph(&graph, speed, &theme.borrow(),
+                           context, width, height)));
+    });
+
+    heatmap_button.connect_toggled(|_| display_error(update_heatmap()));
+    heatmap_refresh.connect_clicked(|_| display_error(update_heatmap()));
+    let drawn_heatmap = heatmap.clone();
+    let drawn_theme = theme.clone();
+    heatmap_area.set_draw_func(move |_, context, width, height|
+        draw_heatmap(&drawn_heatmap.borrow(),
+                     &drawn_theme.borrow(),
+                     context, width, height));
+    let heatmap_click = gtk::GestureClick::new();
+    // Respond to both primary and secondary clicks.
+    heatmap_click.set_button(0);
+    let clicked_area = heatmap_area.clone();
+    let clicked_heatmap = heatmap.clone();
+    heatmap_click.connect_released(move |gesture, _, x, y| {
+        let plot_width = clicked_area.width() as f64 - HEATMAP_LABEL_WIDTH;
+        let heatmap = clicked_heatmap.borrow();
+        let row = (y / HEATMAP_ROW_HEIGHT) as usize;
+        let column = if x < HEATMAP_LABEL_WIDTH || plot_width <= 0.0 {
+            None
+        } else {
+            heatmap.column_at((x - HEATMAP_LABEL_WIDTH) / plot_width)
+        };
+        if gesture.current_button() == 3 {
+            if let Some(heat_row) = heatmap.rows.get(row) {
+                display_error(set_view_filter(&heat_row.condition));
+            }
+        } else if let Some(transaction_id) = column
+            .and_then(|column| heatmap.transaction_from(row, column))
+        {
+            let target = Target::Transaction(transaction_id.value + 1);
+            display_error(with_ui(|ui| select_target(ui, target)));
+        }
+    });
+    heatmap_area.add_controller(heatmap_click);
+
+    let copy_item = gio::SimpleAction::new(
+        "copy-item", Some(gtk::glib::VariantTy::STRING));
+    copy_item.connect_activate(|_, format|
+        display_error(copy_data(false, format)));
+    window.add_action(&copy_item);
+    let copy_field = gio::SimpleAction::new(
+        "copy-field", Some(gtk::glib::VariantTy::STRING));
+    copy_field.connect_activate(|_, format|
+        display_error(copy_data(true, format)));
+    window.add_action(&copy_field);
+    let export_data = gio::SimpleAction::new("export-endpoint-data", None);
+    export_data.connect_activate(|_, _|
+        display_error(choose_endpoint_export()));
+    window.add_action(&export_data);
+    let traffic_menu = copy_menu("copy-item");
+    let export_menu = gio::Menu::new();
+    export_menu.append(
+        Some("Export endpoint data…"),
+        Some("win.export-endpoint-data"));
+    traffic_menu.append_section(None, &export_menu);
+    let decode_as = gio::SimpleAction::new("decode-endpoint-as", None);
+    decode_as.connect_activate(|_, _|
+        display_error(choose_endpoint_class()));
+    window.add_action(&decode_as);
+    let decode_menu = gio::Menu::new();
+    decode_menu.append(
+        Some("Decode endpoint as…"),
+        Some("win.decode-endpoint-as"));
+    traffic_menu.append_section(None, &decode_menu);
+    add_context_menu(&traffic_window, &traffic_menu);
+    let plot_field_action = gio::SimpleAction::new("plot-field", None);
+    plot_field_action.connect_activate(|_, _| display_error(plot_field()));
+    window.add_action(&plot_field_action);
+    let field_menu = copy_menu("copy-field");
+    let plot_menu = gio::Menu::new();
+    plot_menu.append(
+        Some("Plot over time…"),
+        Some("win.plot-field"));
+    field_menu.append_section(None, &plot_menu);
+    add_context_menu(&field_window, &field_menu);
+    let export_descriptors = gio::SimpleAction::new(
+        "export-descriptors", Some(gtk::glib::VariantTy::STRING));
+    export_descriptors.connect_activate(|_, language|
+        display_error(choose_descriptor_export(language)));
+    window.add_action(&export_descriptors);
+    let device_menu = gio::Menu::new();
+    device_menu.append(
+        Some("Export descriptors as C…"),
+        Some("win.export-descriptors::c"));
+    device_menu.append(
+        Some("Export descriptors as Rust…"),
+        Some("win.export-descriptors::rust"));
+    let fetch_descriptors = gio::SimpleAction::new(
+        "fetch-host-descriptors", None);
+    fetch_descriptors.connect_activate(|_, _|
+        display_error(choose_host_device()));
+    window.add_action(&fetch_descriptors);
+    let host_menu = gio::Menu::new();
+    host_menu.append(
+        Some("Fetch descriptors from this computer…"),
+        Some("win.fetch-host-descriptors"));
+    device_menu.append_section(None, &host_menu);
+    add_context_menu(&device_window, &device_menu);
+
+    let traffic_keys = gtk::EventControllerKey::new();
+    traffic_keys.set_propagation_phase(gtk::PropagationPhase::Capture);
+    traffic_keys.connect_key_pressed(|_, key, _, modifiers| {
+        use gtk::gdk::{Key, ModifierType};
+        let expand = match key {
+            Key::Right => true,
+            Key::Left => false,
+            _ => return gtk::glib::Propagation::Proceed,
+        };
+        let modified = modifiers.intersects(
+            ModifierType::CONTROL_MASK |
+            ModifierType::SHIFT_MASK |
+            ModifierType::ALT_MASK);
+        if modified {
+            return gtk::glib::Propagation::Proceed;
+        }
+        display_error(expand_selected(expand));
+        gtk::glib::Propagation::Stop
+    });
+    traffic_window.add_controller(traffic_keys);
+
+    // Whilst following new traffic, keep the traffic view scrolled to the
+    // end as items are added. Scrolling away from the end pauses.
+    let adjustment = traffic_window.vadjustment();
+    let button = autoscroll_button.clone();
+    adjustment.connect_changed(move |adjustment| {
+        if button.is_sensitive() && button.is_active() {
+            adjustment.set_value(adjustment.upper() - adjustment.page_size());
+        }
+    });
+    let button = autoscroll_button.clone();
+    adjustment.connect_value_changed(move |adjustment| {
+        let end = adjustment.upper() - adjustment.page_size();
+        if button.is_sensitive() && adjustment.value() < end - 1.0 {
+            button.set_active(false);
+        }
+    });
+    autoscroll_button.connect_toggled(move |button| {
+        if button.is_active() {
+            adjustment.set_value(adjustment.upper() - adjustment.page_size());
+        }
+    });
+
+    let shortcut_action = |name: &str, activate: Box<dyn Fn()>| {
+        let action = gio::SimpleAction::new(name, None);
+        action.connect_activate(move |_, _| activate());
+        window.add_action(&action);
+    };
+    let entry = filter_entry.clone();
+    shortcut_action("focus-filter", Box::new(move || {
+        entry.grab_focus();
+    }));
+    let button = find_button.clone();
+    let entry = find_entry.clone();
+    shortcut_action("find", Box::new(move || {
+        button.set_active(true);
+        entry.grab_focus();
+    }));
+    let button = goto_button.clone();
+    shortcut_action("go-to", Box::new(move || button.popup()));
+    shortcut_action("next-error", Box::new(||
+        display_error(find_error(false))));
+    shortcut_action("previous-error", Box::new(||
+        display_error(find_error(true))));
+    shortcut_action("next-on-endpoint", Box::new(||
+        display_error(find_on_endpoint(false))));
+    shortcut_action("previous-on-endpoint", Box::new(||
+        display_error(find_on_endpoint(true))));
+    for (name, button) in [("start-capture", &capture_button),
+                           ("stop-capture", &stop_button)]
+    {
+        let button = button.clone();
+        shortcut_action(name, Box::new(move || {
+            if button.is_sensitive() {
+                button.emit_clicked();
+            }
+        }));
+    }
+    shortcut_action("insert-marker", Box::new(||
+        display_error(insert_marker())));
+    shortcut_action("shortcuts", Box::new(show_shortcuts));
+    let mut commands: Vec<PaletteCommand> = Vec::new();
+    for (label, button) in [
+        ("Open capture…", &open_button),
+        ("Merge captures…", &merge_button),
+        ("Re-run analysis on the loaded packets", &reanalyze_button),
+        ("Save capture…", &save_button),
+        ("Export selection…", &export_button),
+        ("Scan for devices", &scan_button),
+        ("Coloring rules…", &color_button),
+        ("Columns and sorting…", &columns_button),
+        ("Follow stream on the selected item's endpoint", &follow_button),
+        ("Statistics…", &stats_button),
+        ("Device enumeration report…", &report_button),
+        ("Compare with another capture…", &compare_button),
+        ("Capture storage…", &storage_button),
+        ("Capture properties…", &properties_button),
+        ("Timeline…", &timeline_button),
+    ] {
+        let button = button.clone();
+        commands.push((tr(label), Rc::new(move || {
+            if button.is_sensitive() {
+                button.emit_clicked();
+            }
+        })));
+    }
+    for (label, button) in [
+        ("Show or hide the bandwidth graph", &graph_button),
+        ("Show or hide the endpoint heat map", &heatmap_button),
+    ] {
+        let button = button.clone();
+        commands.push((tr(label), Rc::new(move ||
+            button.set_active(!button.is_active()))));
+    }
+    let menu_actions = [
+        ("win.hide-sof", "Show or hide SOF packets"),
+        ("win.show-frames", "Frames…"),
+        ("win.hide-polling", "Show or hide polling"),
+        ("win.poll-durations", "Show or hide polling durations"),
+        ("win.set-time-reference", "Use selected item as time reference"),
+        ("win.clear-time-reference", "Clear time reference"),
+        ("win.show-requests", "Control requests…"),
+        ("win.show-splits", "Split transactions…"),
+        ("win.show-compliance", "Compliance checks…"),
+        ("win.show-alerts", "Alert rules…"),
+        ("win.show-preferences", "Preferences…"),
+    ];
+    let actions = SHORTCUTS
+        .iter()
+        .map(|(action, _, description)| (*action, *description))
+        .filter(|(action, _)| *action != "win.command-palette")
+        .chain(menu_actions);
+    for (action, description) in actions {
+        let window = window.clone();
+        commands.push((tr(description), Rc::new(move ||
+            display_error(window
+                .activate_action(action, None)
+                .with_context(|| format!("Failed to run {action}"))))));
+    }
+    for (name, filter) in FILTER_PRESETS {
+        commands.push((trf("Filter: {}", &[&tr(name)]), Rc::new(move ||
+            display_error(set_view_filter(filter)))));
+    }
+    commands.push((trf("Filter: {}", &[&tr("Everything")]), Rc::new(||
+        display_error(set_view_filter("")))));
+    let commands = Rc::new(commands);
+    shortcut_action("command-palette", Box::new(move ||
+        show_command_palette(&commands)));
+    for (action, accel, _) in SHORTCUTS {
+        application.set_accels_for_action(action, &[accel]);
+    }
+
+    display_error(load_database(&usb_ids_path()));
+    let columns = load_columns();
+    let timestamp_mode = gio::SimpleAction::new_stateful(
+        "timestamp-mode",
+        Some(gtk::glib::VariantTy::STRING),
+        &columns.timestamp_mode.name().to_variant());
+    timestamp_mode.connect_activate(|action, name| {
+        let mode = name
+            .and_then(|name| name.str())
+            .and_then(TimestampMode::from_name);
+        if let (Some(name), Some(mode)) = (name, mode) {
+            action.set_state(name);
+            display_error(set_timestamp_mode(mode));
+        }
+    });
+    window.add_action(&timestamp_mode);
+    let set_reference = gio::SimpleAction::new("set-time-reference", None);
+    let reference_mode = timestamp_mode.clone();
+    set_reference.connect_activate(move |_, _| {
+        let result = set_time_reference(true);
+        if result.is_ok() {
+            reference_mode.activate(Some(
+                &TimestampMode::SinceReference.name().to_variant()));
+        }
+        display_error(result);
+    });
+    window.add_action(&set_reference);
+    let clear_reference = gio::SimpleAction::new("clear-time-reference", None);
+    clear_reference.connect_activate(|_, _|
+        display_error(set_time_reference(false)));
+    window.add_action(&clear_reference);
+    for pane in Pane::ALL {
+        let place_action = gio::SimpleAction::new_stateful(
+            &format!("place-{}", pane.name()),
+            Some(gtk::glib::VariantTy::STRING),
+            &pane.default_place().name().to_variant());
+        place_action.connect_activate(move |action, name| {
+            let place = name
+                .and_then(|name| name.str())
+                .and_then(Place::from_name);
+            if let (Some(name), Some(place)) = (name, place) {
+                action.set_state(name);
+                display_error(move_pane(pane, place));
+            }
+        });
+        window.add_action(&place_action);
+    }
+
+    let detail_level_action = gio::SimpleAction::new_stateful(
+        "detail-level",
+        Some(gtk::glib::VariantTy::STRING),
+        &DetailLevel::default().name().to_variant());
+    detail_level_action.connect_activate(|action, name| {
+        let level = name
+            .and_then(|name| name.str())
+            .and_then(DetailLevel::from_name);
+        if let (Some(name), Some(level)) = (name, level) {
+            action.set_state(name);
+            display_error(set_detail_level(level));
+        }
+    });
+    window.add_action(&detail_level_action);
+    let hide_sof_action = gio::SimpleAction::new_stateful(
+        "hide-sof", None, &preferences.hide_sof.to_variant());
+    hide_sof_action.connect_activate(|action, _| {
+        let hidden = !action
+            .state()
+            .and_then(|state| state.get::<bool>())
+            .unwrap_or(false);
+        action.set_state(&hidden.to_variant());
+        display_error(hide_sof(hidden));
+    });
+    window.add_action(&hide_sof_action);
+    let idle_frames_action = gio::SimpleAction::new_stateful(
+        "idle-frames",
+        Some(gtk::glib::VariantTy::STRING),
+        &"0".to_variant());
+    idle_frames_action.connect_activate(|action, run| {
+        let min = run
+            .and_then(|run| run.str())
+            .and_then(|run| run.parse::<u64>().ok());
+        if let (Some(run), Some(min)) = (run, min) {
+            action.set_state(run);
+            display_error(set_idle_frames((min > 0).then(|| min)));
+        }
+    });
+    window.add_action(&idle_frames_action);
+    let hide_polling_action = gio::SimpleAction::new_stateful(
+        "hide-polling", None, &preferences.hide_polling.to_variant());
+    hide_polling_action.connect_activate(|action, _| {
+        let hidden = !action
+            .state()
+            .and_then(|state| state.get::<bool>())
+            .unwrap_or(false);
+        action.set_state(&hidden.to_variant());
+        display_error(hide_polling(hidden));
+    });
+    window.add_action(&hide_polling_action);
+    let poll_durations_action = gio::SimpleAction::new_stateful(
+        "poll-durations", None, &false.to_variant());
+    poll_durations_action.connect_activate(|action, _| {
+        let shown = !action
+            .state()
+            .and_then(|state| state.get::<bool>())
+            .unwrap_or(false);
+        action.set_state(&shown.to_variant());
+        display_error(set_poll_durations(shown));
+    });
+    window.add_action(&poll_durations_action);
+    let show_frames_action = gio::SimpleAction::new("show-frames", None);
+    show_frames_action.connect_activate(|_, _| display_error(show_frames()));
+    window.add_action(&show_frames_action);
+    let show_requests_action = gio::SimpleAction::new("show-requests", None);
+    show_requests_action.connect_activate(|_, _|
+        display_error(show_requests()));
+    window.add_action(&show_requests_action);
+    let show_splits_action = gio::SimpleAction::new("show-splits", None);
+    show_splits_action.connect_activate(|_, _| display_error(show_splits()));
+    window.add_action(&show_splits_action);
+    let show_compliance_action =
+        gio::SimpleAction::new("show-compliance", None);
+    show_compliance_action.connect_activate(|_, _|
+        display_error(show_compliance()));
+    window.add_action(&show_compliance_action);
+    let show_alerts_action = gio::SimpleAction::new("show-alerts", None);
+    show_alerts_action.connect_activate(|_, _|
+        display_error(show_alert_rules()));
+    window.add_action(&show_alerts_action);
+    let show_preferences_action =
+        gio::SimpleAction::new("show-preferences", None);
+    show_preferences_action.connect_activate(|_, _|
+        display_error(show_preferences()));
+    window.add_action(&show_preferences_action);
 
     UI.with(|cell| {
         cell.borrow_mut().replace(
@@ -405,38 +2244,153 @@ pub fn activate(application: &Application) -> Result<(), Error> {
                     Recording::new(capture.clone()))),
                 capture,
                 selector,
+                stream_options,
+                schedule_options,
+                scheduled_start: None,
+                profile_options,
                 file_name: None,
+                file_paths: Vec::new(),
+                overrides: Overrides::default(),
+                align_clocks: false,
+                pending_session: None,
+                autosave_path: None,
                 stop_handle: None,
+                tester: None,
+                filter: None,
+                filter_entry,
+                capture_filter_entry,
+                trigger_entry,
+                snap_spin,
+                packets_only_check,
+                storage_limit: None,
+                storage_limit_reached: false,
+                schedule_done: false,
+                alert_rules: load_alert_rules(),
+                last_alert: None,
+                verification: None,
+                storage_dir: None,
+                find_entry,
+                find_kind,
+                find_label,
+                find_model: None,
+                goto_label,
+                goto_popover,
+                bookmark_button,
+                comment_entry,
+                bookmark_list,
+                bookmark_items: Vec::new(),
+                field_list,
+                hex_view,
+                detail: Detail::default(),
+                color_rules: Arc::new(color_rules),
+                color_css,
+                theme,
+                columns,
+                sort_order: None,
+                time_reference: None,
+                idle_frames: None,
+                poll_durations: false,
+                detail_level: DetailLevel::default(),
+                graph_button,
+                graph_selection,
+                graph_speed,
+                graph_label,
+                graph_area,
+                graph_options: vec![Selection::All],
+                graph,
+                heatmap_button,
+                heatmap_label,
+                heatmap_area,
+                heatmap,
                 traffic_window,
                 device_window,
+                endpoint_box,
+                endpoint_list,
+                endpoint_counts: Vec::new(),
+                endpoint_checks: Vec::new(),
+                activity: Activity {
+                    hide_framing: preferences.hide_sof,
+                    hide_polling: preferences.hide_polling,
+                    ..Activity::new()
+                },
+                error_box,
+                error_label,
+                error_list,
+                errors: Errors::new(),
+                bookmark_box,
+                field_window,
+                hex_window,
                 traffic_model: None,
                 device_model: None,
+                traffic_selection: None,
+                device_selection: None,
                 endpoint_count: 2,
                 show_progress: None,
+                export_scope: None,
                 progress_bar,
                 separator,
                 vbox,
-                paned,
+                panes_box,
+                layout: Layout::new(),
+                paneds: Vec::new(),
+                pane_windows: Vec::new(),
                 scan_button,
                 open_button,
+                merge_button,
+                reanalyze_button,
                 save_button,
+                export_button,
                 capture_button,
+                autoscroll_button,
                 stop_button,
                 status_label,
             }
         )
     });
 
+    with_ui(|ui| {
+        arrange_panes(ui);
+        Ok(())
+    })?;
+
     reset_capture()?;
 
     if args.len() > 1 {
-        let filename = args[1].clone();
-        let path = PathBuf::from(filename);
-        start_pcap(Load, path)?;
+        let paths: Vec<PathBuf> = args[1..]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let action = if paths.len() > 1 { Merge } else { Load };
+        start_pcap(action, paths)?;
     }
 
+    let drop_target = gtk::DropTarget::new(
+        gtk::gdk::FileList::static_type(), gtk::gdk::DragAction::COPY);
+    drop_target.connect_drop(|_, value, _, _| {
+        let paths: Vec<PathBuf> = match value.get::<gtk::gdk::FileList>() {
+            Ok(files) => files
+                .files()
+                .iter()
+                .filter_map(|file| file.path())
+                .collect(),
+            Err(_) => return false,
+        };
+        if paths.is_empty() {
+            return false;
+        }
+        display_error(open_dropped(paths));
+        true
+    });
+    window.add_controller(drop_target);
+
     gtk::glib::idle_add_once(|| display_error(detect_hardware()));
 
+    #[cfg(not(feature="test-ui-replay"))]
+    if args.len() <= 1 {
+        display_error(restore_session());
+        offer_recovery()?;
+    }
+
     Ok(())
 }
 
@@ -445,7 +2399,7 @@ fn create_view<Item, Model, RowData>(
         capture: &CaptureReader,
         #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
         recording_args: (&Rc<RefCell<Recording>>, &'static str))
-    -> (Model, ColumnView)
+    -> (Model, SingleSelection, ColumnView)
     where
         Item: Copy + 'static,
         Model: GenericModel<Item> + IsA<ListModel> + IsA<Object>,
@@ -489,7 +2443,6 @@ fn create_view<Item, Model, RowData>(
             .downcast::<ExpanderWrapper>()
             .or_else(|_| bail!("Child widget is not an ExpanderWrapper"))?;
 
-        let expander = expander_wrapper.expander();
         match row.node() {
             Ok(node_ref) => {
                 let node = node_ref.borrow();
@@ -497,8 +2450,11 @@ fn create_view<Item, Model, RowData>(
                 let connectors = bind_model.connectors(&node.item);
                 expander_wrapper.set_text(summary);
                 expander_wrapper.set_connectors(connectors);
-                expander.set_visible(node.expandable());
-                expander.set_expanded(node.expanded());
+                expander_wrapper.set_style(bind_model.style(&node.item));
+                expander_wrapper.set_expandable(
+                    node.expandable(), node.expanded());
+                expander_wrapper.set_level(node.path()?.len());
+                let expander = expander_wrapper.expander();
                 let model = bind_model.clone();
                 let node_ref = node_ref.clone();
                 let list_item = list_item.clone();
@@ -521,7 +2477,8 @@ fn create_view<Item, Model, RowData>(
             Err(msg) => {
                 expander_wrapper.set_connectors("".to_string());
                 expander_wrapper.set_text(format!("Error: {msg}"));
-                expander.set_visible(false);
+                expander_wrapper.set_style(None);
+                expander_wrapper.set_expandable(false, false);
             }
         };
         Ok(())
@@ -553,30 +2510,68 @@ fn create_view<Item, Model, RowData>(
     factory.connect_bind(move |_, item| display_error(bind(item)));
     factory.connect_unbind(move |_, item| display_error(unbind(item)));
 
-    let view = ColumnView::new(Some(selection_model));
+    let view = ColumnView::new(Some(selection_model.clone()));
     let column = ColumnViewColumn::new(Some(title), Some(factory));
+    column.set_expand(true);
     view.append_column(&column);
     view.add_css_class("data-table");
+    view.update_property(&[Property::Label(title)]);
 
     #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
     model.connect_items_changed(move |model, position, removed, added|
         changed_rec.borrow_mut().log_items_changed(
             name, model, position, removed, added));
 
-    (model, view)
+    (model, selection_model, view)
 }
 
 pub fn reset_capture() -> Result<CaptureWriter, Error> {
+    discard_autosave()?;
+    with_ui(|ui| {
+        ui.overrides = Overrides::default();
+        Ok(())
+    })?;
+    replace_capture()
+}
+
+/// Replace the capture shown with a new, empty one, to be written to.
+///
+/// Unlike `reset_capture`, any autosaved copy of the packets is kept, for
+/// when the same packets are to be decoded again.
+fn replace_capture() -> Result<CaptureWriter, Error> {
     let (writer, reader) = create_capture()?;
+    start_indexing(&reader);
     with_ui(|ui| {
-        let (traffic_model, traffic_view) =
-            create_view::<TrafficItem, TrafficModel, TrafficRowData>(
-                "Traffic",
-                &reader,
-                #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
-                (&ui.recording, "traffic")
-            );
-        let (device_model, device_view) =
+        // Endpoints hidden in the previous capture no longer apply, but
+        // SOFs and polling stay hidden if chosen from the View menu.
+        let hiding = ui.activity.endpoints.iter().any(|ep| ep.hidden);
+        let hide_framing = ui.activity.hide_framing;
+        let hide_polling = ui.activity.hide_polling;
+        ui.storage_limit_reached = false;
+        ui.schedule_done = false;
+        ui.last_alert = None;
+        ui.verification = None;
+        ui.activity = Activity {
+            hide_framing,
+            hide_polling,
+            ..Activity::new()
+        };
+        ui.endpoint_counts.clear();
+        ui.endpoint_checks.clear();
+        while let Some(row) = ui.endpoint_list.first_child() {
+            ui.endpoint_list.remove(&row);
+        }
+        ui.errors = Errors::new();
+        while let Some(row) = ui.error_list.first_child() {
+            ui.error_list.remove(&row);
+        }
+        ui.error_label.set_text("No errors found");
+        if hiding {
+            ui.filter = view_filter(ui).unwrap_or_default();
+        }
+        let (traffic_model, traffic_selection, traffic_view) =
+            create_traffic_view(ui, &reader);
+        let (device_model, device_selection, device_view) =
             create_view::<DeviceItem, DeviceModel, DeviceRowData>(
                 "Devices",
                 &reader,
@@ -586,116 +2581,5694 @@ pub fn reset_capture() -> Result<CaptureWriter, Error> {
         ui.capture = reader;
         ui.traffic_model = Some(traffic_model);
         ui.device_model = Some(device_model);
+        ui.traffic_selection = Some(traffic_selection);
+        ui.device_selection = Some(device_selection);
         ui.endpoint_count = 2;
         ui.traffic_window.set_child(Some(&traffic_view));
         ui.device_window.set_child(Some(&device_view));
         ui.stop_button.set_sensitive(false);
+        ui.file_paths.clear();
+        ui.sort_order = None;
+        ui.time_reference = None;
+        refresh_bookmarks(ui);
         Ok(())
     })?;
+    display_error(show_annotation());
+    display_error(show_detail());
+    display_error(update_graph());
+    display_error(update_heatmap());
     Ok(writer)
 }
 
-pub fn update_view() -> Result<(), Error> {
-    with_ui(|ui| {
-        use FileAction::*;
-        #[cfg(feature="record-ui-test")]
-        let guard = {
-            let guard = UPDATE_LOCK.lock();
-            let packet_count = ui.capture.packet_index.len();
-            ui.recording
-                .borrow_mut()
-                .log_update(packet_count);
-            guard
-        };
-        let mut more_updates = false;
-        if ui.show_progress == Some(Save) {
-            more_updates = true;
-        } else {
-            let (devices, endpoints, transactions, packets) = {
-                let cap = &ui.capture;
-                let devices = cap.devices.len() - 1;
-                let endpoints = cap.endpoints.len() - 2;
-                let transactions = cap.transaction_index.len();
-                let packets = cap.packet_index.len();
-                (devices, endpoints, transactions, packets)
-            };
-            ui.status_label.set_text(&format!(
-                "{}: {} devices, {} endpoints, {} transactions, {} packets",
-                ui.file_name.as_deref().unwrap_or("Unsaved capture"),
-                fmt_count(devices),
-                fmt_count(endpoints),
-                fmt_count(transactions),
-                fmt_count(packets)
-            ));
-            if let Some(model) = &ui.traffic_model {
-                let old_count = model.n_items();
-                more_updates |= model.update()?;
-                let new_count = model.n_items();
-                // If any endpoints were added, we need to redraw the rows above
-                // to add the additional columns of the connecting lines.
-                if new_count > old_count {
-                    let new_ep_count = ui.capture.endpoints.len() as u16;
-                    if new_ep_count > ui.endpoint_count {
-                        model.items_changed(0, old_count, old_count);
-                        ui.endpoint_count = new_ep_count;
-                    }
-                }
-            }
-            if let Some(model) = &ui.device_model {
-                more_updates |= model.update()?;
-            }
-        }
-        if let Some(action) = ui.show_progress {
-            let total = TOTAL.load(Ordering::Relaxed);
-            let current = CURRENT.load(Ordering::Relaxed);
-            let fraction = (current as f64) / (total as f64);
-            let text = match action {
-                Load => format!("Loaded {} / {}",
-                                fmt_size(current), fmt_size(total)),
-                Save => format!("Saved {} / {} packets",
-                                fmt_count(current), fmt_count(total)),
-            };
-            ui.progress_bar.set_text(Some(&text));
-            ui.progress_bar.set_fraction(fraction);
-        }
-        if more_updates {
-            gtk::glib::timeout_add_once(
-                UPDATE_INTERVAL,
-                || display_error(update_view())
-            );
+/// Create the traffic view, showing the items selected by the current filter.
+fn create_traffic_view(ui: &UserInterface, capture: &CaptureReader)
+    -> (TrafficModel, SingleSelection, ColumnView)
+{
+    let mut capture = capture.clone();
+    capture.set_filter(ui.filter.clone());
+    capture.set_order(ui.sort_order.clone());
+    capture.set_idle_frames(ui.idle_frames);
+    capture.set_poll_durations(ui.poll_durations);
+    capture.set_detail(ui.detail_level);
+    let (model, selection, view) =
+        create_view::<TrafficItem, TrafficModel, TrafficRowData>(
+            "Traffic",
+            &capture,
+            #[cfg(any(feature="test-ui-replay", feature="record-ui-test"))]
+            (&ui.recording, "traffic")
+        );
+    model.set_color_rules(ui.color_rules.clone());
+    let base = TimeBase {
+        mode: ui.columns.timestamp_mode,
+        reference: ui.time_reference,
+        previous: None,
+    };
+    for column in ui.columns.iter() {
+        let view_column = ColumnViewColumn::new(
+            Some(&column.title()),
+            Some(&column_factory(&model, column, base)));
+        view_column.set_resizable(true);
+        view.append_column(&view_column);
+    }
+    // Selecting an item during capture stops following new traffic, but
+    // the first item being selected automatically does not.
+    let autoscroll = ui.autoscroll_button.clone();
+    let previous = Cell::new(gtk::INVALID_LIST_POSITION);
+    // The selection may change whilst the UI is in use, so defer updates.
+    selection.connect_selected_notify(move |selection| {
+        if previous.replace(selection.selected()) !=
+            gtk::INVALID_LIST_POSITION && autoscroll.is_sensitive()
+        {
+            autoscroll.set_active(false);
+        }
+        gtk::glib::idle_add_local_once(|| {
+            display_error(show_annotation());
+            display_error(show_detail());
+        });
+    });
+    (model, selection, view)
+}
+
+fn parse_filter(text: &str) -> Result<Option<Arc<Filter>>, Error> {
+    let text = text.trim();
+    if text.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Arc::new(Filter::parse(text)?)))
+    }
+}
+
+/// Highlight the filter entry if its contents are not a valid filter.
+fn check_filter(entry: &Entry) {
+    match parse_filter(&entry.text()) {
+        Ok(_) => {
+            entry.remove_css_class("error");
+            entry.set_tooltip_text(None);
+        },
+        Err(error) => {
+            entry.add_css_class("error");
+            entry.set_tooltip_text(Some(&error.to_string()));
+        }
+    }
+}
+
+const CAPTURE_FILTER_HELP: &str = "\
+Packets to keep while capturing, e.g. device=5 endpoint=0,1 pid=!SOF,!NAK
+Values prefixed with ! are dropped instead.";
+
+const TRIGGER_HELP: &str = "\
+Display filter for traffic which pulses the analyzer's trigger output
+while capturing, e.g. summary contains STALL";
+
+const GOTO_HELP: &str = "\
+A packet number, e.g. 1234, or a transaction number, e.g. transaction 56
+A time after the first packet, e.g. 1.5s, 250ms or 40us
+A Unix time in seconds, e.g. @1700000000.25
+A time of day in UTC, e.g. 12:34:56.789";
+
+/// Highlight the capture filter entry if it is not a valid capture filter.
+fn check_capture_filter(entry: &Entry) {
+    match CaptureFilter::parse(&entry.text()) {
+        Ok(_) => {
+            entry.remove_css_class("error");
+            entry.set_tooltip_text(Some(CAPTURE_FILTER_HELP));
+        },
+        Err(error) => {
+            entry.add_css_class("error");
+            entry.set_tooltip_text(Some(&error.to_string()));
+        }
+    }
+}
+
+/// Highlight the trigger entry if it is not a valid display filter.
+fn check_trigger_filter(entry: &Entry) {
+    match parse_filter(&entry.text()) {
+        Ok(_) => {
+            entry.remove_css_class("error");
+            entry.set_tooltip_text(Some(TRIGGER_HELP));
+        },
+        Err(error) => {
+            entry.add_css_class("error");
+            entry.set_tooltip_text(Some(&error.to_string()));
+        }
+    }
+}
+
+/// Show only the traffic matching the filter entered.
+fn apply_filter() -> Result<(), Error> {
+    with_ui(refilter)
+}
+
+/// Replace the display filter, and show the traffic matching it.
+fn set_view_filter(text: &str) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.filter_entry.set_text(text);
+        refilter(ui)
+    })
+}
+
+/// Update the traffic view, if the filter for it has changed.
+fn refilter(ui: &mut UserInterface) -> Result<(), Error> {
+    let filter = view_filter(ui)?;
+    if filter == ui.filter {
+        return Ok(());
+    }
+    ui.filter = filter;
+    // The sorted items were those matching the previous filter.
+    ui.sort_order = None;
+    replace_traffic_view(ui);
+    Ok(())
+}
+
+/// The filter for the traffic view: the display filter entered, excluding
+/// the traffic on any endpoints hidden in the endpoints pane.
+fn view_filter(ui: &UserInterface) -> Result<Option<Arc<Filter>>, Error> {
+    let text = ui.filter_entry.text();
+    parse_filter(&text).context("Invalid filter")?;
+    parse_filter(&ui.activity.filter_text(&text))
+}
+
+/// Show the traffic counted on each endpoint, adding rows for endpoints
+/// seen since the last update.
+fn show_activity(ui: &mut UserInterface) {
+    for index in ui.endpoint_counts.len()..ui.activity.endpoints.len() {
+        let endpoint = &ui.activity.endpoints[index];
+        let check = CheckButton::builder()
+            .label(endpoint.name.as_str())
+            .active(!endpoint.hidden)
+            .tooltip_text("Show the traffic on this endpoint")
+            .hexpand(true)
+            .build();
+        let count = Label::builder()
+            .halign(Align::End)
+            .build();
+        let row = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .margin_start(3)
+            .margin_end(3)
+            .build();
+        row.append(&check);
+        row.append(&count);
+        ui.endpoint_list.append(&row);
+        check.connect_toggled(move |check| {
+            let shown = check.is_active();
+            display_error(show_endpoint(index, shown));
+        });
+        ui.endpoint_counts.push(count);
+        ui.endpoint_checks.push(check);
+    }
+    for (endpoint, label) in ui.activity.endpoints
+        .iter()
+        .zip(&ui.endpoint_counts)
+    {
+        label.set_text(&fmt_count(endpoint.packets));
+        label.set_tooltip_text(Some(&format!(
+            "{} transactions, {} packets",
+            fmt_count(endpoint.transactions),
+            fmt_count(endpoint.packets))));
+    }
+}
+
+/// Show or hide the traffic on an endpoint listed in the endpoints pane.
+fn show_endpoint(index: usize, shown: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        if let Some(endpoint) = ui.activity.endpoints.get_mut(index) {
+            endpoint.hidden = !shown;
+            if endpoint.endpoint_id == FRAMING_EP_ID {
+                ui.activity.hide_framing = !shown;
+                WINDOW.with(|win_opt| {
+                    if let Some(window) = win_opt.borrow().as_ref() {
+                        if let Some(action) = window.lookup_action("hide-sof") {
+                            action.change_state(&(!shown).to_variant());
+                        }
+                    }
+                });
+            }
+        }
+        refilter(ui)
+    })
+}
+
+/// Hide or show groups of unanswered polls, as chosen from the View menu.
+fn hide_polling(hidden: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.activity.hide_polling = hidden;
+        refilter(ui)
+    })
+}
+
+/// Hide or show SOF packets, as chosen from the View menu.
+fn hide_sof(hidden: bool) -> Result<(), Error> {
+    let mut check = None;
+    with_ui(|ui| {
+        ui.activity.hide_framing = hidden;
+        check = ui.endpoint_checks
+            .get(FRAMING_EP_ID.value as usize)
+            .cloned();
+        if check.is_none() {
+            refilter(ui)?;
+        }
+        Ok(())
+    })?;
+    // Once SOFs are listed in the endpoints pane, toggling their row
+    // hides them. This must be done outside `with_ui`, as the row's
+    // handler uses it too.
+    if let Some(check) = check {
+        check.set_active(!hidden);
+    }
+    Ok(())
+}
+
+/// Add rows to the errors pane for errors found since the last update.
+fn show_errors(ui: &mut UserInterface, positions: &[usize]) {
+    for position in positions {
+        let error = &ui.errors.errors[*position];
+        let label = Label::builder()
+            .label(format!("Packet {}: {}",
+                           error.packet_id.value + 1,
+                           error.kind.label()))
+            .tooltip_text(error.description.as_str())
+            .xalign(0.0)
+            .margin_start(3)
+            .margin_end(3)
+            .build();
+        ui.error_list.insert(&label, *position as i32);
+    }
+    let count = ui.errors.count();
+    let text = if ui.errors.unlisted > 0 {
+        format!("{} errors, first {} listed",
+                fmt_count(count), fmt_count(MAX_ERRORS as u64))
+    } else if count == 1 {
+        "1 error".to_string()
+    } else {
+        format!("{} errors", fmt_count(count))
+    };
+    ui.error_label.set_text(&text);
+}
+
+/// Select the packet of an error listed in the errors pane.
+fn go_to_error(index: i32) -> Result<(), Error> {
+    with_ui(|ui| {
+        let packet_id = usize::try_from(index)
+            .ok()
+            .and_then(|index| ui.errors.errors.get(index))
+            .map(|error| error.packet_id)
+            .context("Error not found")?;
+        let model = match &ui.traffic_model {
+            Some(model) => model.clone(),
+            None => return Ok(()),
+        };
+        let mut capture = model.capture();
+        let item = capture.packet_item(packet_id)?;
+        match capture.item_path(&item)? {
+            Some(path) => select_path(ui, &model, &path),
+            None => bail!("That packet is hidden by the filter"),
+        }
+    })
+}
+
+/// Replace the traffic view, after a change to how it shows items.
+fn replace_traffic_view(ui: &mut UserInterface) {
+    let (traffic_model, traffic_selection, traffic_view) =
+        create_traffic_view(ui, &ui.capture);
+    ui.traffic_model = Some(traffic_model);
+    ui.traffic_selection = Some(traffic_selection);
+    ui.traffic_window.set_child(Some(&traffic_view));
+    // If the capture is complete, no updates are scheduled, but they are
+    // needed to check the remaining items against the filter.
+    let complete = ui.capture.shared.complete.load(Ordering::Acquire);
+    if complete && ui.show_progress.is_none() {
+        gtk::glib::timeout_add_once(
+            UPDATE_INTERVAL,
+            || display_error(update_view()));
+    }
+}
+
+/// Find the next or previous item matching the search entered, starting
+/// from the selected item.
+fn find(backwards: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        let kind = match ui.find_kind.selected() {
+            1 => SearchKind::Regex,
+            2 => SearchKind::Bytes,
+            _ => SearchKind::Text,
+        };
+        let search = match Search::new(&ui.find_entry.text(), kind) {
+            Ok(search) => search,
+            Err(error) => {
+                ui.find_label.set_text(&format!("{error:#}"));
+                return Ok(());
+            }
+        };
+        start_search(ui, search, backwards)
+    })
+}
+
+/// Select the next or previous item showing an error.
+fn find_error(backwards: bool) -> Result<(), Error> {
+    with_ui(|ui| start_search(ui, Search::errors(), backwards))
+}
+
+/// Add a marker at the current time, if capturing.
+fn insert_marker() -> Result<(), Error> {
+    with_ui(|ui| {
+        if ui.stop_handle.is_none() {
+            return Ok(());
+        }
+        let markers = &ui.capture.shared.markers;
+        let mut updated = markers.load().as_ref().clone();
+        let label = format!("{DEFAULT_LABEL} {}", updated.len() + 1);
+        updated.add(Marker::now(&label)?);
+        markers.store(Arc::new(updated));
+        Ok(())
+    })
+}
+
+/// Select the next or previous item on the same endpoint as the selected
+/// item, at the same level of the traffic view.
+fn find_on_endpoint(backwards: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        let item = selected_traffic_item(ui)?;
+        let endpoint_id = ui.capture.item_endpoint_id(&item)?;
+        start_search(ui, Search::endpoint(endpoint_id), backwards)
+    })
+}
+
+/// Search in the background, starting from the selected item, then
+/// select the item found.
+fn start_search(ui: &mut UserInterface, search: Search, backwards: bool)
+    -> Result<(), Error>
+{
+    if ui.find_model.is_some() {
+        // A search is already running.
+        return Ok(());
+    }
+    let model = match &ui.traffic_model {
+        Some(model) => model.clone(),
+        None => return Ok(()),
+    };
+    let start = match selected_traffic_node(ui) {
+        Some(node_rc) => Some(node_rc.borrow().path()?),
+        None => None,
+    };
+    let mut capture = model.capture();
+    ui.find_model = Some(model);
+    ui.find_label.set_text("Searching…");
+    std::thread::spawn(move || {
+        let result = search.find(&mut capture, start.as_deref(), backwards);
+        gtk::glib::idle_add_once(move || display_error(show_found(result)));
+    });
+    Ok(())
+}
+
+/// Expand or collapse the selected traffic item. Collapsing an item which
+/// is not expanded selects its parent instead.
+fn expand_selected(expand: bool) -> Result<(), Error> {
+    let mut widgets = Vec::new();
+    with_ui(|ui| {
+        let node_ref = match selected_traffic_node(ui) {
+            Some(node_ref) => node_ref,
+            None => return Ok(()),
+        };
+        let node = node_ref.borrow();
+        if expand || node.expanded() {
+            if node.expandable() {
+                widgets = node.widgets();
+            }
+            return Ok(());
+        }
+        let path = node.path()?;
+        drop(node);
+        match (path.split_last(), ui.traffic_model.clone()) {
+            (Some((_, parent_path)), Some(model))
+                if !parent_path.is_empty() =>
+                select_path(ui, &model, parent_path),
+            _ => Ok(()),
+        }
+    })?;
+    // Changing the expander updates the model, as if it were clicked.
+    for widget in widgets {
+        let expander = widget.expander().clone();
+        expander.set_expanded(expand);
+    }
+    Ok(())
+}
+
+/// Show a window listing the keyboard shortcuts.
+fn show_shortcuts() {
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(18)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    let mut keys: Vec<(String, &str)> = SHORTCUTS
+        .iter()
+        .map(|(_, accel, description)| {
+            let label = gtk::accelerator_parse(accel)
+                .map(|(key, modifiers)|
+                    gtk::accelerator_get_label(key, modifiers).to_string())
+                .unwrap_or_else(|| accel.to_string());
+            (label, *description)
+        })
+        .collect();
+    keys.extend(TRAFFIC_VIEW_KEYS
+        .iter()
+        .map(|(key, description)| (key.to_string(), *description)));
+    for (row, (key, description)) in keys.iter().enumerate() {
+        let key_label = Label::builder()
+            .use_markup(true)
+            .label(format!("<b>{}</b>",
+                           gtk::glib::markup_escape_text(key.as_str())))
+            .halign(Align::End)
+            .build();
+        let description_label = Label::builder()
+            .label(tr(description))
+            .halign(Align::Start)
+            .build();
+        grid.attach(&key_label, 0, row as i32, 1, 1);
+        grid.attach(&description_label, 1, row as i32, 1, 1);
+    }
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Keyboard shortcuts"))
+            .child(&grid)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+}
+
+/// Show a window listing the commands matching a search, best first, and
+/// run the one chosen.
+fn show_command_palette(commands: &Rc<Vec<PaletteCommand>>) {
+    use gtk::gdk::Key;
+    let entry = SearchEntry::builder()
+        .placeholder_text("Search for a command")
+        .build();
+    let list = ListBox::builder()
+        .selection_mode(gtk::SelectionMode::Browse)
+        .build();
+    let scrolled = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(320)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&entry);
+    vbox.append(&scrolled);
+    let window = WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Commands"))
+            .default_width(480)
+            .modal(true)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window
+    });
+
+    // Indices of the commands listed, in the order shown.
+    let shown = Rc::new(RefCell::new(Vec::new()));
+    let update = {
+        let commands = commands.clone();
+        let list = list.clone();
+        let shown = shown.clone();
+        move |search: &str| {
+            while let Some(child) = list.first_child() {
+                list.remove(&child);
+            }
+            let labels: Vec<&str> = commands
+                .iter()
+                .map(|(label, _)| label.as_str())
+                .collect();
+            let ranked = rank(search, &labels);
+            for index in &ranked {
+                let label = Label::builder()
+                    .label(labels[*index])
+                    .halign(Align::Start)
+                    .margin_top(3)
+                    .margin_bottom(3)
+                    .margin_start(6)
+                    .build();
+                list.append(&label);
+            }
+            list.select_row(list.row_at_index(0).as_ref());
+            shown.replace(ranked);
+        }
+    };
+    update("");
+    entry.connect_search_changed(move |entry| update(&entry.text()));
+
+    let run = {
+        let commands = commands.clone();
+        let window = window.clone();
+        Rc::new(move |position: i32| {
+            let index = usize::try_from(position)
+                .ok()
+                .and_then(|position| shown.borrow().get(position).copied());
+            if let Some(index) = index {
+                window.close();
+                (commands[index].1)();
+            }
+        })
+    };
+    let activated = run.clone();
+    list.connect_row_activated(move |_, row| activated(row.index()));
+    let chosen = list.clone();
+    entry.connect_activate(move |_| {
+        if let Some(row) = chosen.selected_row() {
+            run(row.index());
+        }
+    });
+    let closed = window.clone();
+    entry.connect_stop_search(move |_| closed.close());
+
+    // Move through the list with the arrow keys, whilst typing a search.
+    let keys = gtk::EventControllerKey::new();
+    keys.set_propagation_phase(gtk::PropagationPhase::Capture);
+    keys.connect_key_pressed(move |_, key, _, _| {
+        let step = match key {
+            Key::Down => 1,
+            Key::Up => -1,
+            _ => return gtk::glib::Propagation::Proceed,
+        };
+        let index = list.selected_row().map_or(0, |row| row.index() + step);
+        if let Some(row) = list.row_at_index(index) {
+            list.select_row(Some(&row));
+            let adjustment = scrolled.vadjustment();
+            let top = row
+                .compute_point(&list, &gtk::graphene::Point::new(0.0, 0.0))
+                .map_or(0.0, |point| point.y() as f64);
+            let bottom = top + row.height() as f64;
+            if top < adjustment.value() {
+                adjustment.set_value(top);
+            } else if bottom > adjustment.value() + adjustment.page_size() {
+                adjustment.set_value(bottom - adjustment.page_size());
+            }
+        }
+        gtk::glib::Propagation::Stop
+    });
+    window.add_controller(keys);
+    window.show();
+    entry.grab_focus();
+}
+
+/// Select and scroll to the item found by a search.
+fn show_found(result: Result<Option<Vec<u64>>, Error>) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.find_label.set_text("");
+        // Ignore the result if the view was replaced during the search.
+        let model = match ui.find_model.take() {
+            Some(model) if ui.traffic_model.as_ref() == Some(&model) => model,
+            _ => return Ok(()),
+        };
+        let path = match result? {
+            Some(path) => path,
+            None => {
+                ui.find_label.set_text("No more matches");
+                return Ok(());
+            }
+        };
+        select_path(ui, &model, &path)
+    })
+}
+
+/// Expand the traffic view to show the item at a path, and select it.
+fn select_path(ui: &UserInterface, model: &TrafficModel, path: &[u64])
+    -> Result<(), Error>
+{
+    let row = model.reveal(path)?;
+    if let Some(selection) = &ui.traffic_selection {
+        selection.set_selected(row);
+    }
+    scroll_to_row(&ui.traffic_window, row, model.n_items());
+    Ok(())
+}
+
+/// Show the annotation on the selected traffic item, ready for editing.
+fn show_annotation() -> Result<(), Error> {
+    with_ui(|ui| {
+        let (selected, annotation) = match selected_traffic_item(ui) {
+            Ok(item) => (true, ui.capture.shared.annotations
+                .load()
+                .get(&item)
+                .cloned()
+                .unwrap_or_default()),
+            Err(_) => (false, Annotation::default()),
+        };
+        ui.bookmark_button.set_sensitive(selected);
+        ui.comment_entry.set_sensitive(selected);
+        ui.bookmark_button.set_active(annotation.bookmarked);
+        ui.comment_entry.set_text(&annotation.comment);
+        Ok(())
+    })
+}
+
+/// Create a menu offering to copy data in each format.
+fn copy_menu(action: &str) -> gio::Menu {
+    let menu = gio::Menu::new();
+    for format in CopyFormat::ALL {
+        menu.append(
+            Some(format.label()),
+            Some(&format!("win.{action}::{}", format.name())));
+    }
+    menu
+}
+
+/// Add a context menu to a widget, shown when it is right-clicked.
+fn add_context_menu(widget: &ScrolledWindow, menu: &gio::Menu) {
+    let popover = gtk::PopoverMenu::from_model(Some(menu));
+    popover.set_parent(widget);
+    popover.set_has_arrow(false);
+    let click = gtk::GestureClick::new();
+    click.set_button(3);
+    click.connect_pressed(move |_, _, x, y| {
+        popover.set_pointing_to(
+            Some(&gtk::gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        popover.popup();
+    });
+    widget.add_controller(click);
+}
+
+/// Copy the data of the selected traffic item or field to the clipboard.
+fn copy_data(field: bool, format: Option<&gtk::glib::Variant>)
+    -> Result<(), Error>
+{
+    let format = format
+        .and_then(|format| format.str())
+        .and_then(CopyFormat::from_name)
+        .context("Unknown copy format")?;
+    with_ui(|ui| {
+        let data = if field {
+            let index = ui.field_list
+                .selected_row()
+                .context("No field selected")?
+                .index();
+            let field = usize::try_from(index)
+                .ok()
+                .and_then(|index| ui.detail.fields.get(index))
+                .context("No field selected")?;
+            ui.detail.data
+                .get(field.range.clone())
+                .context("Field is outside the item's data")?
+                .to_vec()
+        } else {
+            let item = selected_traffic_item(ui)?;
+            ui.capture.item_data(&item)?
+        };
+        ui.traffic_window.clipboard().set_text(&format.format(&data));
+        Ok(())
+    })
+}
+
+/// Plot the value of the selected field over time, reading it from each
+/// payload on the same endpoint. The plot follows the capture as it grows.
+fn plot_field() -> Result<(), Error> {
+    let mut selection = None;
+    with_ui(|ui| {
+        let index = ui.field_list
+            .selected_row()
+            .context("No field selected")?
+            .index();
+        let field = usize::try_from(index)
+            .ok()
+            .and_then(|index| ui.detail.fields.get(index))
+            .context("No field selected")?;
+        let item = selected_traffic_item(ui)?;
+        let endpoint_id = ui.capture.item_endpoint_id(&item)?;
+        // The data of a packet starts with its PID, which is not part of
+        // the payload.
+        let offset = if let TrafficItem::Packet(..) = item {
+            use PID::*;
+            let pid = ui.detail.data.first().map(|byte| PID::from(*byte));
+            match (pid, field.range.start) {
+                (Some(DATA0 | DATA1 | DATA2 | MDATA), start) if start > 0 =>
+                    start - 1,
+                _ => bail!("Only fields of a data payload can be plotted"),
+            }
+        } else {
+            field.range.start
+        };
+        let format = ValueFormat::for_length(field.range.len());
+        selection = Some((
+            ui.capture.clone(),
+            ui.theme.clone(),
+            field.name.to_string(),
+            FieldPlot::new(endpoint_id, offset, format)));
+        Ok(())
+    })?;
+    let (mut capture, theme, name, plot) =
+        selection.context("No field selected")?;
+    let plot = Rc::new(RefCell::new(plot));
+
+    let offset_spin = SpinButton::with_range(0.0, 65535.0, 1.0);
+    offset_spin.set_value(plot.borrow().offset as f64);
+    let format_labels: Vec<&str> = ValueFormat::ALL
+        .iter()
+        .map(|format| format.label())
+        .collect();
+    let format_dropdown = DropDown::from_strings(&format_labels);
+    let format_index = ValueFormat::ALL
+        .iter()
+        .position(|format| *format == plot.borrow().format)
+        .unwrap_or(0);
+    format_dropdown.set_selected(format_index as u32);
+    let status_label = Label::builder()
+        .halign(Align::End)
+        .hexpand(true)
+        .build();
+    let controls = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    controls.append(&Label::new(Some("Byte offset:")));
+    controls.append(&offset_spin);
+    controls.append(&format_dropdown);
+    controls.append(&status_label);
+    let area = gtk::DrawingArea::builder()
+        .content_width(640)
+        .content_height(240)
+        .hexpand(true)
+        .vexpand(true)
+        .accessible_role(AccessibleRole::Img)
+        .build();
+    area.update_property(&[Property::Label(&tr("Value plot"))]);
+    let drawn_plot = plot.clone();
+    area.set_draw_func(move |_, context, width, height|
+        draw_field_plot(&drawn_plot.borrow(), &theme.borrow(),
+                        context, width, height));
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    vbox.append(&controls);
+    vbox.append(&area);
+
+    // Start again from the first payload when the value is read
+    // differently.
+    let reset = {
+        let plot = plot.clone();
+        let offset_spin = offset_spin.clone();
+        let format_dropdown = format_dropdown.clone();
+        move || {
+            let endpoint_id = plot.borrow().endpoint_id;
+            let offset = offset_spin.value_as_int() as usize;
+            let format = ValueFormat::ALL[format_dropdown.selected() as usize];
+            *plot.borrow_mut() = FieldPlot::new(endpoint_id, offset, format);
+        }
+    };
+    let reset_offset = reset.clone();
+    offset_spin.connect_value_changed(move |_| reset_offset());
+    format_dropdown.connect_selected_notify(move |_| reset());
+
+    let closed = Rc::new(Cell::new(false));
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(trf("Plot of {}", &[&name]))
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        let window_closed = closed.clone();
+        window.connect_close_request(move |_| {
+            window_closed.set(true);
+            gtk::glib::Propagation::Proceed
+        });
+        window.show();
+    });
+
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        if closed.get() {
+            return gtk::glib::ControlFlow::Break;
+        }
+        let result = plot.borrow_mut().update(&mut capture, 1000);
+        match result {
+            Ok(true) => {
+                let count = plot.borrow().samples.len() as u64;
+                status_label.set_text(&format!("{} values", fmt_count(count)));
+                area.queue_draw();
+            },
+            Ok(false) => {},
+            Err(error) => {
+                display_error(Err(error));
+                return gtk::glib::ControlFlow::Break;
+            },
+        }
+        gtk::glib::ControlFlow::Continue
+    });
+    Ok(())
+}
+
+/// Show the data of the selected traffic item, with its decoded fields.
+fn show_detail() -> Result<(), Error> {
+    with_ui(|ui| {
+        let detail = match selected_traffic_item(ui) {
+            Ok(item) => Detail::new(&mut ui.capture, &item)?,
+            Err(_) => Detail::default(),
+        };
+        while let Some(row) = ui.field_list.first_child() {
+            ui.field_list.remove(&row);
+        }
+        for field in &detail.fields {
+            let label = Label::builder()
+                .label(format!("{}: {}", field.name, field.value))
+                .halign(Align::Start)
+                .build();
+            ui.field_list.append(&label);
+        }
+        let mut text = detail.hex_dump();
+        if detail.truncated() {
+            text.push_str(&format!("\n… {} more bytes not shown",
+                                   detail.data.len() - MAX_DUMP_BYTES));
+        }
+        ui.hex_view.buffer().set_text(&text);
+        ui.detail = detail;
+        Ok(())
+    })
+}
+
+/// Highlight the bytes of a field in the hex dump.
+fn highlight_field(index: Option<i32>) -> Result<(), Error> {
+    with_ui(|ui| {
+        let buffer = ui.hex_view.buffer();
+        buffer.remove_tag_by_name(
+            "highlight", &buffer.start_iter(), &buffer.end_iter());
+        let field = index
+            .and_then(|index| usize::try_from(index).ok())
+            .and_then(|index| ui.detail.fields.get(index));
+        let range = match field {
+            Some(field) =>
+                field.range.start..field.range.end.min(MAX_DUMP_BYTES),
+            None => return Ok(()),
+        };
+        let mut start = range.start;
+        while start < range.end {
+            let line = start / BYTES_PER_LINE;
+            let last = range.end.min((line + 1) * BYTES_PER_LINE) - 1;
+            for columns in [
+                hex_column(start)..hex_column(last) + 2,
+                ascii_column(start)..ascii_column(last) + 1,
+            ] {
+                let line = line as i32;
+                if let (Some(from), Some(to)) = (
+                    buffer.iter_at_line_offset(line, columns.start as i32),
+                    buffer.iter_at_line_offset(line, columns.end as i32))
+                {
+                    buffer.apply_tag_by_name("highlight", &from, &to);
+                }
+            }
+            start = last + 1;
+        }
+        let first_line = (range.start / BYTES_PER_LINE) as i32;
+        if let Some(mut iter) = buffer.iter_at_line(first_line) {
+            ui.hex_view.scroll_to_iter(&mut iter, 0.0, false, 0.0, 0.0);
+        }
+        Ok(())
+    })
+}
+
+/// Select the field containing a byte clicked in the hex dump.
+fn select_byte(line: i32, column: i32) -> Result<(), Error> {
+    with_ui(|ui| {
+        let field = byte_at(line as usize, column as usize)
+            .and_then(|offset| ui.detail.field_at(offset));
+        match field {
+            Some(index) => {
+                let row = ui.field_list.row_at_index(index as i32);
+                ui.field_list.select_row(row.as_ref());
+            },
+            None => ui.field_list.unselect_all(),
+        }
+        Ok(())
+    })
+}
+
+/// Change the annotation on the selected traffic item.
+fn edit_annotation<F>(edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut Annotation)
+{
+    with_ui(|ui| {
+        let node_rc = selected_traffic_node(ui)
+            .context("No traffic item selected")?;
+        let node = node_rc.borrow();
+        let shared = ui.capture.shared.clone();
+        let mut annotations = Annotations::clone(&shared.annotations.load());
+        let mut annotation = annotations
+            .get(&node.item)
+            .cloned()
+            .unwrap_or_default();
+        edit(&mut annotation);
+        annotations.set(&node.item, annotation);
+        shared.annotations.store(Arc::new(annotations));
+        if let Some(model) = &ui.traffic_model {
+            node.set_text(model.summary(&node.item));
+        }
+        refresh_bookmarks(ui);
+        Ok(())
+    })
+}
+
+/// Rebuild the list of bookmarked items.
+fn refresh_bookmarks(ui: &mut UserInterface) {
+    while let Some(row) = ui.bookmark_list.first_child() {
+        ui.bookmark_list.remove(&row);
+    }
+    ui.bookmark_items.clear();
+    let annotations = ui.capture.shared.annotations.load_full();
+    for (item, annotation) in annotations.bookmarks() {
+        let summary = match ui.capture.summary(&item) {
+            Ok(summary) => summary,
+            Err(e) => format!("Error: {e}"),
+        };
+        let label = gtk::Label::builder()
+            .label(annotation.describe(&summary))
+            .halign(Align::Start)
+            .margin_start(3)
+            .margin_end(3)
+            .build();
+        ui.bookmark_list.append(&label);
+        ui.bookmark_items.push(item);
+    }
+}
+
+/// Path of the file in which coloring rules are saved.
+fn color_rules_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("color-rules.json");
+    path
+}
+
+/// Path of the file in which the interface colors are saved.
+fn theme_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("theme.json");
+    path
+}
+
+/// Path of the file in which the traffic view's columns are saved.
+fn columns_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("columns.json");
+    path
+}
+
+/// Path of the file in which names for custom USB IDs are given.
+fn usb_ids_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("usb.ids");
+    path
+}
+
+/// Path of the file in which capture profiles are saved.
+fn profiles_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("profiles.json");
+    path
+}
+
+/// Path of the directory in which translation catalogs may be placed, to
+/// override the built-in ones.
+fn translations_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("translations");
+    path
+}
+
+/// Path of the file in which preferences are saved.
+fn preferences_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("preferences.json");
+    path
+}
+
+/// Path of the file in which the recently opened captures are listed.
+fn recent_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("recent.json");
+    path
+}
+
+/// Path of the file in which the session is saved on exit.
+fn session_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("session.json");
+    path
+}
+
+/// Save the state of the user interface, to be restored on the next run.
+fn save_session() -> Result<(), Error> {
+    let mut session = Session::default();
+    with_ui(|ui| {
+        // An autosaved capture is offered for recovery instead.
+        if ui.autosave_path.is_none() {
+            session.files = ui.file_paths.clone();
+        }
+        session.filter = ui.filter_entry.text().to_string();
+        // Sorting is not restored, so paths in a sorted view are not kept.
+        if ui.sort_order.is_none() {
+            if let Some(model) = &ui.traffic_model {
+                session.expanded = model.expanded_paths();
+            }
+            session.selected = selected_traffic_node(ui)
+                .and_then(|node| node.borrow().path().ok());
+            session.scroll = ui.traffic_window.vadjustment().value();
+        }
+        for (name, paned) in &ui.paneds {
+            session.panes.insert(name.clone(), paned.position());
+        }
+        session.layout = ui.layout.clone();
+        session.storage_limit = ui.storage_limit;
+        session.storage_dir = ui.storage_dir.clone();
+        Ok(())
+    })?;
+    WINDOW.with(|win_opt| {
+        if let Some(window) = win_opt.borrow().as_ref() {
+            session.window_size = Some((window.width(), window.height()));
+        }
+    });
+    session.save(&session_path())
+}
+
+/// Move a pane to a place, and rearrange the panes.
+fn move_pane(pane: Pane, place: Place) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.layout.set_place(pane, place);
+        arrange_panes(ui);
+        Ok(())
+    })
+}
+
+/// Place the panes around the traffic view, according to the layout.
+fn arrange_panes(ui: &mut UserInterface) {
+    // Take every pane out of the containers it was placed in before.
+    for (_, paned) in ui.paneds.drain(..) {
+        paned.set_start_child(None::<&gtk::Widget>);
+        paned.set_end_child(None::<&gtk::Widget>);
+    }
+    for window in ui.pane_windows.drain(..) {
+        window.set_child(None::<&gtk::Widget>);
+        window.destroy();
+    }
+    while let Some(child) = ui.panes_box.first_child() {
+        ui.panes_box.remove(&child);
+    }
+    let mut paneds = Vec::new();
+    let mut view: gtk::Widget = ui.traffic_window.clone().upcast();
+    let bottom = stack_panes(ui, Place::Bottom, &mut paneds);
+    if let Some(bottom) = bottom {
+        view = split_panes("traffic", Orientation::Vertical,
+                           &view, &bottom, &mut paneds);
+    }
+    let right = stack_panes(ui, Place::Right, &mut paneds);
+    if let Some(right) = right {
+        view = split_panes("main", Orientation::Horizontal,
+                           &view, &right, &mut paneds);
+    }
+    let left = stack_panes(ui, Place::Left, &mut paneds);
+    if let Some(left) = left {
+        view = split_panes("left", Orientation::Horizontal,
+                           &left, &view, &mut paneds);
+    }
+    view.set_vexpand(true);
+    ui.panes_box.append(&view);
+    ui.paneds = paneds;
+
+    for pane in ui.layout.panes(Place::Window) {
+        let window = gtk::Window::builder()
+            .title(pane.label())
+            .default_width(400)
+            .default_height(300)
+            .child(&pane_widget(ui, pane))
+            .build();
+        // Closing the window returns the pane to its usual place.
+        window.connect_close_request(move |_| {
+            gtk::glib::idle_add_local_once(move || {
+                WINDOW.with(|win_opt| {
+                    if let Some(window) = win_opt.borrow().as_ref() {
+                        let place = pane.default_place().name().to_variant();
+                        let _ = WidgetExt::activate_action(
+                            window,
+                            &format!("win.place-{}", pane.name()),
+                            Some(&place));
+                    }
+                });
+            });
+            gtk::glib::Propagation::Stop
+        });
+        WINDOW.with(|win_opt| {
+            window.set_transient_for(win_opt.borrow().as_ref());
+        });
+        window.show();
+        ui.pane_windows.push(window);
+    }
+}
+
+/// The widget showing a pane.
+fn pane_widget(ui: &UserInterface, pane: Pane) -> gtk::Widget {
+    use Pane::*;
+    match pane {
+        Devices => ui.device_window.clone().upcast(),
+        Endpoints => ui.endpoint_box.clone().upcast(),
+        Bookmarks => ui.bookmark_box.clone().upcast(),
+        Fields => ui.field_window.clone().upcast(),
+        Hex => ui.hex_window.clone().upcast(),
+        Errors => ui.error_box.clone().upcast(),
+    }
+}
+
+/// Stack the panes in a place, dividing the space between them.
+fn stack_panes(ui: &UserInterface,
+               place: Place,
+               paneds: &mut Vec<(String, gtk::Paned)>)
+    -> Option<gtk::Widget>
+{
+    let orientation = match place {
+        Place::Bottom => Orientation::Horizontal,
+        _ => Orientation::Vertical,
+    };
+    let mut panes = ui.layout.panes(place).into_iter();
+    let mut stack = pane_widget(ui, panes.next()?);
+    for (index, pane) in panes.enumerate() {
+        let name = format!("{}-{}", place.name(), index + 1);
+        stack = split_panes(&name, orientation,
+                            &stack, &pane_widget(ui, pane), paneds);
+    }
+    Some(stack)
+}
+
+/// Divide space between two widgets, with a named divider.
+fn split_panes(name: &str,
+               orientation: Orientation,
+               start: &gtk::Widget,
+               end: &gtk::Widget,
+               paneds: &mut Vec<(String, gtk::Paned)>)
+    -> gtk::Widget
+{
+    let paned = gtk::Paned::builder()
+        .orientation(orientation)
+        .wide_handle(true)
+        .start_child(start)
+        .end_child(end)
+        .build();
+    paneds.push((name.to_string(), paned.clone()));
+    paned.upcast()
+}
+
+/// Open capture files dropped onto the window, merging them if there are
+/// several. If a capture is already open, they are opened in a new window
+/// rather than replacing it.
+fn open_dropped(paths: Vec<PathBuf>) -> Result<(), Error> {
+    use FileAction::*;
+    let mut in_use = false;
+    with_ui(|ui| {
+        in_use = !ui.file_paths.is_empty() ||
+            ui.capture.packet_index.len() > 0;
+        Ok(())
+    })?;
+    if in_use {
+        let program = std::env::current_exe()
+            .context("Failed to find the Packetry program")?;
+        std::process::Command::new(program)
+            .args(&paths)
+            .spawn()
+            .context("Failed to open a new window")?;
+        Ok(())
+    } else {
+        let action = if paths.len() > 1 { Merge } else { Load };
+        start_pcap(action, paths)
+    }
+}
+
+/// Restore the state of the user interface saved by the last run,
+/// reopening the capture files that were open.
+fn restore_session() -> Result<(), Error> {
+    use FileAction::*;
+    let path = session_path();
+    if !path.exists() {
+        return Ok(());
+    }
+    let session = Session::load(&path)?;
+    if let Some((width, height)) = session.window_size {
+        WINDOW.with(|win_opt| {
+            if let Some(window) = win_opt.borrow().as_ref() {
+                window.set_default_size(width, height);
+            }
+        });
+    }
+    let reopen = !session.files.is_empty() &&
+        session.files.iter().all(|file| file.exists());
+    WINDOW.with(|win_opt| {
+        if let Some(window) = win_opt.borrow().as_ref() {
+            for pane in Pane::ALL {
+                let name = format!("place-{}", pane.name());
+                let place = session.layout.place(pane).name();
+                if let Some(action) = window.lookup_action(&name) {
+                    action.change_state(&place.to_variant());
+                }
+            }
+        }
+    });
+    with_ui(|ui| {
+        ui.layout = session.layout.clone();
+        ui.storage_limit = session.storage_limit;
+        ui.storage_dir = session.storage_dir.clone();
+        set_storage_dir(session.storage_dir.clone());
+        arrange_panes(ui);
+        for (name, paned) in &ui.paneds {
+            if let Some(position) = session.panes.get(name) {
+                paned.set_position(*position);
+            }
+        }
+        ui.filter_entry.set_text(&session.filter);
+        ui.filter = parse_filter(&session.filter).unwrap_or_default();
+        if reopen {
+            ui.pending_session = Some(session.clone());
+        }
+        Ok(())
+    })?;
+    if reopen {
+        let action = if session.files.len() > 1 { Merge } else { Load };
+        start_pcap(action, session.files)?;
+    }
+    Ok(())
+}
+
+/// Restore the expanded items, selection and scroll position of the
+/// traffic view, once the capture has been reopened.
+fn restore_view(ui: &UserInterface, session: &Session) -> Result<(), Error> {
+    let model = match &ui.traffic_model {
+        Some(model) => model.clone(),
+        None => return Ok(()),
+    };
+    model.update()?;
+    // Revealing the first child of an item expands it. Items which can no
+    // longer be found are skipped.
+    for path in &session.expanded {
+        let mut child_path = path.clone();
+        child_path.push(0);
+        let _ = model.reveal(&child_path);
+    }
+    if let Some(path) = &session.selected {
+        if let Ok(row) = model.reveal(path) {
+            if let Some(selection) = &ui.traffic_selection {
+                selection.set_selected(row);
+            }
+        }
+    }
+    // Scroll once the view has been laid out with the expanded items.
+    let scroll = session.scroll;
+    gtk::glib::idle_add_local_once(move || {
+        display_error(with_ui(|ui| {
+            ui.traffic_window.vadjustment().set_value(scroll);
+            Ok(())
+        }));
+    });
+    Ok(())
+}
+
+/// Load the saved coloring rules, or the default rules if none are saved.
+/// Load the saved interface colors, if any.
+fn load_theme() -> Theme {
+    let path = theme_path();
+    if !path.exists() {
+        return Theme::new();
+    }
+    match Theme::load(&path) {
+        Ok(theme) => theme,
+        Err(e) => {
+            display_error(Err(e));
+            Theme::new()
+        }
+    }
+}
+
+fn load_color_rules(palette: Palette) -> ColorRules {
+    let path = color_rules_path();
+    if !path.exists() {
+        return ColorRules::defaults(palette);
+    }
+    match ColorRules::load(&path) {
+        Ok(rules) => rules,
+        Err(e) => {
+            display_error(Err(e));
+            ColorRules::defaults(palette)
+        }
+    }
+}
+
+/// Show the editor for coloring rules.
+fn show_color_rules() -> Result<(), Error> {
+    let rule_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let rule_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(200)
+        .vexpand(true)
+        .child(&rule_list)
+        .build();
+    let kind_dropdown = gtk::DropDown::from_strings(&CONDITION_KINDS);
+    let value_entry = gtk::Entry::builder()
+        .placeholder_text("e.g. 5, 5.0x81, bulk or a filter")
+        .hexpand(true)
+        .build();
+    let color_button = ColorButton::new();
+    let add_button = gtk::Button::builder()
+        .icon_name("list-add")
+        .tooltip_text("Add rule")
+        .build();
+    let add_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    add_box.append(&kind_dropdown);
+    add_box.append(&value_entry);
+    add_box.append(&color_button);
+    add_box.append(&add_button);
+    let help = Label::builder()
+        .label("Items are colored by the first enabled rule they match.")
+        .halign(Align::Start)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&help);
+    vbox.append(&rule_window);
+    vbox.append(&add_box);
+    vbox.append(&Separator::new(Orientation::Horizontal));
+    vbox.append(&theme_editor(&rule_list, &color_button)?);
+
+    let add = {
+        let rule_list = rule_list.clone();
+        let kind_dropdown = kind_dropdown.clone();
+        let value_entry = value_entry.clone();
+        let color_button = color_button.clone();
+        move || -> Result<(), Error> {
+            let kind = CONDITION_KINDS[kind_dropdown.selected() as usize];
+            let condition = Condition::parse(kind, &value_entry.text())?;
+            let color = rgba_color(&color_button.rgba());
+            let rule = ColorRule::new(condition, &color)?;
+            edit_color_rules(&rule_list, move |rules| rules.push(rule))?;
+            value_entry.set_text("");
+            offer_rule_color(&color_button)
+        }
+    };
+    let add_clicked = add.clone();
+    add_button.connect_clicked(move |_| display_error(add_clicked()));
+    value_entry.connect_activate(move |_| display_error(add()));
+
+    with_ui(|ui| {
+        fill_rule_list(&rule_list, &ui.color_rules);
+        Ok(())
+    })?;
+    offer_rule_color(&color_button)?;
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Coloring rules"))
+            .default_width(480)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Set the color offered for a new rule to the next of the palette's.
+fn offer_rule_color(color_button: &ColorButton) -> Result<(), Error> {
+    with_ui(|ui| {
+        let palette = ui.theme.borrow().palette();
+        color_button.set_rgba(
+            &color_rgba(palette.rule_color(ui.color_rules.len())));
+        Ok(())
+    })
+}
+
+/// A copy of the current interface colors.
+fn current_theme() -> Result<Theme, Error> {
+    let mut theme = None;
+    with_ui(|ui| {
+        theme = Some(ui.theme.borrow().clone());
+        Ok(())
+    })?;
+    theme.context("No theme")
+}
+
+/// Show a choice of palette, and buttons choosing the colors of other
+/// elements of the interface, in the current light or dark theme.
+fn theme_editor(rule_list: &ListBox, color_button: &ColorButton)
+    -> Result<gtk::Box, Error>
+{
+    let dark = dark_theme();
+    let theme = current_theme()?;
+    let palette_labels: Vec<&str> = Palette::ALL
+        .iter()
+        .map(|palette| palette.label())
+        .collect();
+    let palette_dropdown = DropDown::from_strings(&palette_labels);
+    let palette_index = Palette::ALL
+        .iter()
+        .position(|palette| *palette == theme.palette())
+        .unwrap_or(0);
+    palette_dropdown.set_selected(palette_index as u32);
+    let palette_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    palette_box.append(&Label::new(Some("Palette:")));
+    palette_box.append(&palette_dropdown);
+    let title = Label::builder()
+        .label(if dark {
+            "Interface colors, in the dark theme:"
+        } else {
+            "Interface colors, in the light theme:"
+        })
+        .halign(Align::Start)
+        .build();
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .build();
+    let mut buttons = Vec::new();
+    for (row, element) in Element::ALL.into_iter().enumerate() {
+        let label = Label::builder()
+            .label(element.label())
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        let button = ColorButton::with_rgba(
+            &color_rgba(theme.color(element, dark)));
+        button.connect_color_set(move |button| {
+            let color = rgba_color(&button.rgba());
+            display_error(edit_theme(move |theme|
+                theme.set_color(element, dark, Some(&color))));
+        });
+        grid.attach(&label, 0, row as i32, 1, 1);
+        grid.attach(&button, 1, row as i32, 1, 1);
+        buttons.push((element, button));
+    }
+    let buttons = Rc::new(buttons);
+    let show_colors = {
+        let buttons = buttons.clone();
+        move || -> Result<(), Error> {
+            let theme = current_theme()?;
+            for (element, button) in buttons.iter() {
+                button.set_rgba(&color_rgba(theme.color(*element, dark)));
+            }
+            Ok(())
+        }
+    };
+    let reset_button = gtk::Button::builder()
+        .label("Reset")
+        .tooltip_text("Return to the palette's colors")
+        .halign(Align::End)
+        .build();
+    let reset_colors = show_colors.clone();
+    reset_button.connect_clicked(move |_| {
+        display_error(edit_theme(|theme| {
+            theme.reset(dark);
+            Ok(())
+        }));
+        display_error(reset_colors());
+    });
+    let rule_list = rule_list.clone();
+    let color_button = color_button.clone();
+    palette_dropdown.connect_selected_notify(move |dropdown| {
+        let palette = Palette::ALL[dropdown.selected() as usize];
+        let change = || -> Result<(), Error> {
+            let previous = current_theme()?.palette();
+            edit_theme(|theme| {
+                theme.set_palette(palette);
+                Ok(())
+            })?;
+            edit_color_rules(&rule_list, |rules|
+                rules.recolor(previous, palette))?;
+            offer_rule_color(&color_button)?;
+            show_colors()
+        };
+        display_error(change());
+    });
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .build();
+    vbox.append(&palette_box);
+    vbox.append(&title);
+    vbox.append(&grid);
+    vbox.append(&reset_button);
+    Ok(vbox)
+}
+
+/// Change the interface colors, then save them and show their effect.
+fn edit_theme<F>(edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut Theme) -> Result<(), Error>
+{
+    with_ui(|ui| {
+        edit(&mut ui.theme.borrow_mut())?;
+        apply_theme(ui);
+        ui.theme.borrow().save(&theme_path())
+    })
+}
+
+/// Update the parts of the main window drawn in theme colors.
+fn apply_theme(ui: &UserInterface) {
+    let color = ui.theme
+        .borrow()
+        .color(Element::Highlight, dark_theme())
+        .to_string();
+    if let Some(tag) = ui.hex_view.buffer().tag_table().lookup("highlight") {
+        tag.set_background(Some(&color));
+    }
+    ui.graph_area.queue_draw();
+    ui.heatmap_area.queue_draw();
+}
+
+/// Whether to use the dark interface colors: as chosen in the preferences,
+/// or when the GTK theme in use is a dark one.
+fn dark_theme() -> bool {
+    match preferences().color_scheme {
+        ColorScheme::Light => return false,
+        ColorScheme::Dark => return true,
+        ColorScheme::Automatic => {}
+    }
+    let dark_name = |name: &str| name.to_lowercase().contains("dark");
+    if std::env::var("GTK_THEME").map_or(false, |name| dark_name(&name)) {
+        return true;
+    }
+    match gtk::Settings::default() {
+        Some(settings) =>
+            settings.is_gtk_application_prefer_dark_theme() ||
+            settings.gtk_theme_name().map_or(false, |name| dark_name(&name)),
+        None => false,
+    }
+}
+
+/// Convert a `#RRGGBB` color for use in a color button.
+fn color_rgba(color: &str) -> gtk::gdk::RGBA {
+    let (red, green, blue) = rgb(color);
+    gtk::gdk::RGBA::new(red as f32, green as f32, blue as f32, 1.0)
+}
+
+/// Convert a color button's color to `#RRGGBB` form.
+fn rgba_color(rgba: &gtk::gdk::RGBA) -> String {
+    format!("#{:02X}{:02X}{:02X}",
+        (rgba.red() * 255.0).round() as u8,
+        (rgba.green() * 255.0).round() as u8,
+        (rgba.blue() * 255.0).round() as u8)
+}
+
+/// Use a `#RRGGBB` color for drawing.
+fn set_source_color(context: &gtk::cairo::Context, color: &str) {
+    let (red, green, blue) = rgb(color);
+    context.set_source_rgb(red, green, blue);
+}
+
+/// Change the coloring rules, then save them and show their effect.
+fn edit_color_rules<F>(rule_list: &ListBox, edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut ColorRules)
+{
+    with_ui(|ui| {
+        let mut rules = ColorRules::clone(&ui.color_rules);
+        edit(&mut rules);
+        ui.color_rules = Arc::new(rules);
+        ui.color_css.load_from_data(&ui.color_rules.css());
+        replace_traffic_view(ui);
+        fill_rule_list(rule_list, &ui.color_rules);
+        ui.color_rules.save(&color_rules_path())
+    })
+}
+
+/// Rebuild the list of rules shown in the coloring rules editor.
+fn fill_rule_list(rule_list: &ListBox, rules: &ColorRules) {
+    while let Some(row) = rule_list.first_child() {
+        rule_list.remove(&row);
+    }
+    for (index, rule) in rules.iter().enumerate() {
+        let enabled_check = CheckButton::builder()
+            .active(rule.enabled)
+            .tooltip_text("Enable this rule")
+            .build();
+        let swatch = Label::builder()
+            .use_markup(true)
+            .label(format!("<span background=\"{}\">      </span>",
+                           rule.color))
+            .build();
+        let description = Label::builder()
+            .label(rule.condition.to_string())
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        let up_button = gtk::Button::builder()
+            .icon_name("go-up")
+            .tooltip_text("Check this rule earlier")
+            .sensitive(index > 0)
+            .build();
+        let remove_button = gtk::Button::builder()
+            .icon_name("list-remove")
+            .tooltip_text("Remove this rule")
+            .build();
+        let row = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        row.append(&enabled_check);
+        row.append(&swatch);
+        row.append(&description);
+        row.append(&up_button);
+        row.append(&remove_button);
+        rule_list.append(&row);
+
+        let list = rule_list.clone();
+        enabled_check.connect_toggled(move |check| {
+            let enabled = check.is_active();
+            display_error(edit_color_rules(&list, move |rules|
+                rules.set_enabled(index, enabled)))
+        });
+        let list = rule_list.clone();
+        up_button.connect_clicked(move |_|
+            display_error(edit_color_rules(&list, move |rules|
+                rules.move_up(index))));
+        let list = rule_list.clone();
+        remove_button.connect_clicked(move |_|
+            display_error(edit_color_rules(&list, move |rules|
+                rules.remove(index))));
+    }
+}
+
+/// Path of the file in which alert rules are saved.
+fn alert_rules_path() -> PathBuf {
+    let mut path = gtk::glib::user_config_dir();
+    path.push("packetry");
+    path.push("alert-rules.json");
+    path
+}
+
+/// Load the saved alert rules, if any.
+fn load_alert_rules() -> AlertRules {
+    let path = alert_rules_path();
+    if !path.exists() {
+        return AlertRules::new();
+    }
+    match AlertRules::load(&path) {
+        Ok(rules) => rules,
+        Err(e) => {
+            display_error(Err(e));
+            AlertRules::new()
+        }
+    }
+}
+
+/// Show the editor for alert rules.
+fn show_alert_rules() -> Result<(), Error> {
+    let rule_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let rule_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(160)
+        .vexpand(true)
+        .child(&rule_list)
+        .build();
+    let filter_entry = gtk::Entry::builder()
+        .placeholder_text("Display filter, e.g. error == yes")
+        .hexpand(true)
+        .build();
+    filter_entry.connect_changed(check_filter);
+    let action_labels: Vec<&str> = AlertAction::ALL
+        .iter()
+        .map(|action| action.label())
+        .collect();
+    let action_dropdown = DropDown::from_strings(&action_labels);
+    let add_button = gtk::Button::builder()
+        .icon_name("list-add")
+        .tooltip_text("Add rule")
+        .build();
+    let add_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    add_box.append(&filter_entry);
+    add_box.append(&action_dropdown);
+    add_box.append(&add_button);
+    let help = Label::builder()
+        .label("While capturing, traffic matching an enabled rule's filter \
+                raises its alert.")
+        .wrap(true)
+        .halign(Align::Start)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&help);
+    vbox.append(&rule_window);
+    vbox.append(&add_box);
+
+    let add = {
+        let rule_list = rule_list.clone();
+        let filter_entry = filter_entry.clone();
+        let action_dropdown = action_dropdown.clone();
+        move || -> Result<(), Error> {
+            let action = AlertAction::ALL[action_dropdown.selected() as usize];
+            let rule = AlertRule::new(&filter_entry.text(), action)?;
+            edit_alert_rules(&rule_list, move |rules| rules.push(rule))?;
+            filter_entry.set_text("");
+            Ok(())
+        }
+    };
+    let add_clicked = add.clone();
+    add_button.connect_clicked(move |_| display_error(add_clicked()));
+    filter_entry.connect_activate(move |_| display_error(add()));
+
+    with_ui(|ui| {
+        fill_alert_list(&rule_list, &ui.alert_rules);
+        Ok(())
+    })?;
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Alert rules"))
+            .default_width(480)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Change the alert rules, then save them. They apply from the next
+/// capture started.
+fn edit_alert_rules<F>(rule_list: &ListBox, edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut AlertRules)
+{
+    with_ui(|ui| {
+        edit(&mut ui.alert_rules);
+        fill_alert_list(rule_list, &ui.alert_rules);
+        ui.alert_rules.save(&alert_rules_path())
+    })
+}
+
+/// Rebuild the list of rules shown in the alert rules editor.
+fn fill_alert_list(rule_list: &ListBox, rules: &AlertRules) {
+    while let Some(row) = rule_list.first_child() {
+        rule_list.remove(&row);
+    }
+    for (index, rule) in rules.iter().enumerate() {
+        let enabled_check = CheckButton::builder()
+            .active(rule.enabled)
+            .tooltip_text("Enable this rule")
+            .build();
+        let description = Label::builder()
+            .label(rule.description())
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        let remove_button = gtk::Button::builder()
+            .icon_name("list-remove")
+            .tooltip_text("Remove this rule")
+            .build();
+        let row = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        row.append(&enabled_check);
+        row.append(&description);
+        row.append(&remove_button);
+        rule_list.append(&row);
+
+        let list = rule_list.clone();
+        enabled_check.connect_toggled(move |check| {
+            let enabled = check.is_active();
+            display_error(edit_alert_rules(&list, move |rules|
+                rules.set_enabled(index, enabled)))
+        });
+        let list = rule_list.clone();
+        remove_button.connect_clicked(move |_|
+            display_error(edit_alert_rules(&list, move |rules|
+                rules.remove(index))));
+    }
+}
+
+/// Act on live traffic matching an alert rule's filter. The filter is
+/// also shown in the status bar, until the next capture is started.
+fn raise_alert(action: AlertAction, filter_text: String) -> Result<(), Error> {
+    let message = trf("Traffic matched {}", &[&filter_text]);
+    with_ui(|ui| {
+        ui.last_alert = Some(filter_text);
+        Ok(())
+    })?;
+    match action {
+        AlertAction::Sound => {
+            if let Some(display) = gtk::gdk::Display::default() {
+                display.beep();
+            }
+        },
+        AlertAction::Notify => {
+            let notification = gio::Notification::new(&tr("Packetry alert"));
+            notification.set_body(Some(&message));
+            WINDOW.with(|win_opt| {
+                let application = win_opt
+                    .borrow()
+                    .as_ref()
+                    .and_then(|window| window.application());
+                if let Some(application) = application {
+                    application.send_notification(Some("alert"), &notification);
+                }
+            });
+        },
+        AlertAction::Stop => stop_cynthion()?,
+    }
+    Ok(())
+}
+
+/// Load the saved columns of the traffic view, if any.
+fn load_columns() -> Columns {
+    let path = columns_path();
+    if !path.exists() {
+        return Columns::new();
+    }
+    match Columns::load(&path) {
+        Ok(columns) => columns,
+        Err(e) => {
+            display_error(Err(e));
+            Columns::new()
+        }
+    }
+}
+
+/// Load the list of recent captures, or an empty list if none is saved.
+fn load_recent() -> RecentCaptures {
+    let path = recent_path();
+    if !path.exists() {
+        return RecentCaptures::new();
+    }
+    match RecentCaptures::load(&path) {
+        Ok(recent) => recent,
+        Err(e) => {
+            display_error(Err(e));
+            RecentCaptures::new()
+        }
+    }
+}
+
+/// Change the list of recent captures, and save it.
+///
+/// The list is read from its file each time, so that it is shared by all
+/// the Packetry windows open.
+fn edit_recent<F>(edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut RecentCaptures)
+{
+    let mut recent = load_recent();
+    edit(&mut recent);
+    recent.save(&recent_path())
+}
+
+/// Add a capture which has been opened to the list of recent captures.
+fn remember_capture(path: &Path) -> Result<(), Error> {
+    let mut capture = None;
+    with_ui(|ui| {
+        // A recovered capture is in a temporary file.
+        if ui.autosave_path.as_deref() != Some(path) {
+            capture = Some(ui.capture.clone());
+        }
+        Ok(())
+    })?;
+    match capture {
+        Some(mut capture) => {
+            let entry = RecentCapture::new(path, &mut capture)?;
+            edit_recent(|recent| recent.add(entry))
+        },
+        None => Ok(())
+    }
+}
+
+/// List the recent captures in the popover of the recent captures button.
+fn fill_recent_list(popover: &gtk::Popover, list: &ListBox) {
+    while let Some(row) = list.first_child() {
+        list.remove(&row);
+    }
+    let recent = load_recent();
+    if recent.is_empty() {
+        list.append(&Label::builder()
+            .label("No captures have been opened yet.")
+            .margin_top(6)
+            .margin_bottom(6)
+            .margin_start(6)
+            .margin_end(6)
+            .build());
+        return;
+    }
+    let refill = {
+        let popover = popover.clone();
+        let list = list.clone();
+        move || {
+            let popover = popover.clone();
+            let list = list.clone();
+            gtk::glib::idle_add_local_once(move ||
+                fill_recent_list(&popover, &list));
+        }
+    };
+    for capture in recent.iter() {
+        let name_label = Label::builder()
+            .label(capture.name())
+            .halign(Align::Start)
+            .build();
+        let details_label = Label::builder()
+            .label(capture.details())
+            .halign(Align::Start)
+            .build();
+        details_label.add_css_class("dim-label");
+        let text = gtk::Box::builder()
+            .orientation(Orientation::Vertical)
+            .build();
+        text.append(&name_label);
+        text.append(&details_label);
+        let open_button = Button::builder()
+            .child(&text)
+            .has_frame(false)
+            .hexpand(true)
+            .tooltip_text(capture.path.display().to_string())
+            .build();
+        let pin_button = ToggleButton::builder()
+            .icon_name("view-pin-symbolic")
+            .active(capture.pinned)
+            .tooltip_text("Keep this capture in the list")
+            .valign(Align::Center)
+            .build();
+        let remove_button = Button::builder()
+            .icon_name("list-remove")
+            .tooltip_text("Remove this capture from the list")
+            .valign(Align::Center)
+            .build();
+        let row = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        row.append(&open_button);
+        row.append(&pin_button);
+        row.append(&remove_button);
+        list.append(&row);
+
+        let path = capture.path.clone();
+        let opened_popover = popover.clone();
+        open_button.connect_clicked(move |_| {
+            opened_popover.popdown();
+            display_error(start_pcap(FileAction::Load, vec![path.clone()]));
+        });
+        let path = capture.path.clone();
+        let pinned_refill = refill.clone();
+        pin_button.connect_toggled(move |button| {
+            let pinned = button.is_active();
+            display_error(edit_recent(|recent|
+                recent.set_pinned(&path, pinned)));
+            pinned_refill();
+        });
+        let path = capture.path.clone();
+        let removed_refill = refill.clone();
+        remove_button.connect_clicked(move |_| {
+            display_error(edit_recent(|recent| recent.remove(&path)));
+            removed_refill();
+        });
+    }
+    let clear_button = Button::builder()
+        .label("Clear unpinned captures")
+        .margin_top(3)
+        .build();
+    clear_button.connect_clicked(move |_| {
+        display_error(edit_recent(RecentCaptures::clear));
+        refill();
+    });
+    list.append(&clear_button);
+}
+
+/// Load the saved capture profiles, if any.
+fn load_profiles() -> Profiles {
+    let path = profiles_path();
+    if !path.exists() {
+        return Profiles::new();
+    }
+    match Profiles::load(&path) {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            display_error(Err(e));
+            Profiles::new()
+        }
+    }
+}
+
+/// Load the saved preferences, or the defaults if none are saved.
+fn load_preferences() -> Preferences {
+    let path = preferences_path();
+    if !path.exists() {
+        return Preferences::new();
+    }
+    match Preferences::load(&path) {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            display_error(Err(e));
+            Preferences::new()
+        }
+    }
+}
+
+/// A copy of the current preferences.
+fn preferences() -> Preferences {
+    PREFERENCES.with(|cell| cell.borrow().clone())
+}
+
+/// Change the preferences, then save them.
+fn edit_preferences<F>(edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut Preferences)
+{
+    PREFERENCES.with(|cell| {
+        let mut preferences = cell.borrow_mut();
+        edit(&mut preferences);
+        preferences.save(&preferences_path())
+    })
+}
+
+/// Show a window for changing the preferences. Most take effect the next
+/// time a capture is started, or the application is run.
+fn show_preferences() -> Result<(), Error> {
+    let preferences = preferences();
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    let add_row = |row: i32, label: &str, widget: &gtk::Widget| {
+        let label = Label::builder()
+            .label(label)
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        grid.attach(&label, 0, row, 1, 1);
+        grid.attach(widget, 1, row, 1, 1);
+    };
+
+    let speeds = [Speed::High, Speed::Full, Speed::Low, Speed::Auto];
+    let mut speed_labels = vec!["First available"];
+    speed_labels.extend(speeds.iter().map(Speed::description));
+    let speed_dropdown = DropDown::from_strings(&speed_labels);
+    let speed_index = speeds
+        .iter()
+        .position(|speed| speed.description() == preferences.speed)
+        .map_or(0, |index| index + 1);
+    speed_dropdown.set_selected(speed_index as u32);
+    speed_dropdown.connect_selected_notify(move |dropdown| {
+        let speed = match dropdown.selected() as usize {
+            0 => String::new(),
+            index => speeds[index - 1].description().to_string(),
+        };
+        display_error(edit_preferences(|preferences|
+            preferences.speed = speed));
+    });
+    add_row(0, "Default capture speed:", speed_dropdown.upcast_ref());
+
+    let depth_spin = SpinButton::with_range(1.0, 1e6, 1.0);
+    depth_spin.set_value(preferences.pipeline_depth as f64);
+    depth_spin.set_tooltip_text(Some(
+        "Packets captured which may wait to be decoded, before reading \
+         from the device is held up"));
+    depth_spin.connect_value_changed(|spin| {
+        let depth = spin.value_as_int() as usize;
+        display_error(edit_preferences(|preferences|
+            preferences.pipeline_depth = depth));
+    });
+    add_row(1, "Decoding buffer (packets):", depth_spin.upcast_ref());
+
+    let scheme_labels: Vec<&str> = ColorScheme::ALL
+        .iter()
+        .map(|scheme| scheme.label())
+        .collect();
+    let scheme_dropdown = DropDown::from_strings(&scheme_labels);
+    let scheme_index = ColorScheme::ALL
+        .iter()
+        .position(|scheme| *scheme == preferences.color_scheme)
+        .unwrap_or(0);
+    scheme_dropdown.set_selected(scheme_index as u32);
+    scheme_dropdown.connect_selected_notify(|dropdown| {
+        let scheme = ColorScheme::ALL[dropdown.selected() as usize];
+        display_error(edit_preferences(|preferences|
+            preferences.color_scheme = scheme));
+        display_error(with_ui(|ui| {
+            apply_theme(ui);
+            Ok(())
+        }));
+    });
+    add_row(2, "Interface colors:", scheme_dropdown.upcast_ref());
+
+    let hide_sof_check = CheckButton::with_label("Hide SOF packets");
+    hide_sof_check.set_active(preferences.hide_sof);
+    hide_sof_check.connect_toggled(|check| {
+        let hidden = check.is_active();
+        display_error(edit_preferences(|preferences|
+            preferences.hide_sof = hidden));
+    });
+    let hide_polling_check = CheckButton::with_label(
+        "Hide polling answered with NAKs");
+    hide_polling_check.set_active(preferences.hide_polling);
+    hide_polling_check.connect_toggled(|check| {
+        let hidden = check.is_active();
+        display_error(edit_preferences(|preferences|
+            preferences.hide_polling = hidden));
+    });
+    let startup_box = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .build();
+    startup_box.append(&hide_sof_check);
+    startup_box.append(&hide_polling_check);
+    add_row(3, "On startup:", startup_box.upcast_ref());
+
+    let autosave_check = CheckButton::with_label(
+        "Save captured packets for recovery after a crash");
+    autosave_check.set_active(preferences.autosave);
+    autosave_check.connect_toggled(|check| {
+        let autosave = check.is_active();
+        display_error(edit_preferences(|preferences|
+            preferences.autosave = autosave));
+    });
+    add_row(4, "While capturing:", autosave_check.upcast_ref());
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Preferences"))
+            .child(&grid)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Change how the timestamp column shows times, adding the column if it
+/// is not already shown.
+fn set_timestamp_mode(mode: TimestampMode) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.columns.timestamp_mode = mode;
+        let shown = ui.columns
+            .iter()
+            .any(|column| matches!(column, Column::Timestamp));
+        if !shown {
+            ui.columns.push(Column::Timestamp);
+        }
+        replace_traffic_view(ui);
+        ui.columns.save(&columns_path())
+    })
+}
+
+/// Describe runs of at least `min` SOFs in the traffic view as idle frames,
+/// or show them all as SOF packets if None.
+fn set_idle_frames(min: Option<u64>) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.idle_frames = min;
+        replace_traffic_view(ui);
+        Ok(())
+    })
+}
+
+/// Describe groups of polls in the traffic view with the time they span.
+fn set_poll_durations(shown: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.poll_durations = shown;
+        replace_traffic_view(ui);
+        Ok(())
+    })
+}
+
+/// Show items in the traffic view down to the given level of detail.
+fn set_detail_level(level: DetailLevel) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.detail_level = level;
+        replace_traffic_view(ui);
+        Ok(())
+    })
+}
+
+/// Measure times from the selected item, or clear the reference so that
+/// times are measured from the start of the capture.
+fn set_time_reference(set: bool) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.time_reference = if set {
+            let item = selected_traffic_item(ui)?;
+            Some(ui.capture.item_timestamp(&item)?)
+        } else {
+            None
+        };
+        replace_traffic_view(ui);
+        Ok(())
+    })
+}
+
+/// Create the factory for the cells of an optional traffic view column.
+fn column_factory(model: &TrafficModel, column: &Column, base: TimeBase)
+    -> SignalListItemFactory
+{
+    let factory = SignalListItemFactory::new();
+    factory.connect_setup(move |_, list_item| {
+        let label = Label::builder()
+            .halign(Align::Start)
+            .build();
+        list_item.set_child(Some(&label));
+    });
+    let model = model.clone();
+    let column = column.clone();
+    let bind = move |list_item: &ListItem| -> Result<(), Error> {
+        let row = list_item
+            .item()
+            .context("ListItem has no item")?
+            .downcast::<TrafficRowData>()
+            .or_else(|_| bail!("Item is not TrafficRowData"))?;
+
+        let label = list_item
+            .child()
+            .context("ListItem has no child widget")?
+            .downcast::<Label>()
+            .or_else(|_| bail!("Child widget is not a Label"))?;
+
+        let text = match row.node() {
+            Ok(node_ref) => {
+                let node = node_ref.borrow();
+                model.column_text(&column, &node, &base)
+            },
+            Err(msg) => format!("Error: {msg}"),
+        };
+        label.set_text(&text);
+        Ok(())
+    };
+    factory.connect_bind(move |_, item| display_error(bind(item)));
+    factory
+}
+
+/// Show the editor for the columns of the traffic view, and its sorting.
+fn show_columns() -> Result<(), Error> {
+    let column_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .build();
+    let column_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(160)
+        .vexpand(true)
+        .child(&column_list)
+        .build();
+    let kind_dropdown = gtk::DropDown::from_strings(&COLUMN_KINDS);
+    let value_entry = gtk::Entry::builder()
+        .placeholder_text("Filter field or expression, for custom columns")
+        .hexpand(true)
+        .build();
+    let add_button = gtk::Button::builder()
+        .icon_name("list-add")
+        .tooltip_text("Add column")
+        .build();
+    let add_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    add_box.append(&kind_dropdown);
+    add_box.append(&value_entry);
+    add_box.append(&add_button);
+    let sort_dropdown = gtk::DropDown::from_strings(&["Capture order"]);
+    let descending_check = gtk::CheckButton::builder()
+        .label("Descending")
+        .build();
+    let sort_button = gtk::Button::builder()
+        .label("Sort")
+        .build();
+    let sort_status = Label::builder()
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let sort_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    sort_box.append(&Label::new(Some("Sort by")));
+    sort_box.append(&sort_dropdown);
+    sort_box.append(&descending_check);
+    sort_box.append(&sort_button);
+    sort_box.append(&sort_status);
+    let help = Label::builder()
+        .label("Columns are shown after the summary of each item. \
+                Sorting applies to the top-level items of a complete capture.")
+        .halign(Align::Start)
+        .wrap(true)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&help);
+    vbox.append(&column_window);
+    vbox.append(&add_box);
+    vbox.append(&gtk::Separator::new(Orientation::Horizontal));
+    vbox.append(&sort_box);
+
+    let add = {
+        let column_list = column_list.clone();
+        let sort_dropdown = sort_dropdown.clone();
+        let kind_dropdown = kind_dropdown.clone();
+        let value_entry = value_entry.clone();
+        move || -> Result<(), Error> {
+            let kind = COLUMN_KINDS[kind_dropdown.selected() as usize];
+            let column = Column::parse(kind, &value_entry.text())?;
+            edit_columns(&column_list, &sort_dropdown, move |columns|
+                columns.push(column))?;
+            value_entry.set_text("");
+            Ok(())
+        }
+    };
+    let add_clicked = add.clone();
+    add_button.connect_clicked(move |_| display_error(add_clicked()));
+    value_entry.connect_activate(move |_| display_error(add()));
+    let sort_choice = sort_dropdown.clone();
+    sort_button.connect_clicked(move |_|
+        display_error(sort_traffic(sort_choice.selected(),
+                                   descending_check.is_active(),
+                                   &sort_status)));
+
+    with_ui(|ui| {
+        fill_column_list(&column_list, &sort_dropdown, &ui.columns);
+        Ok(())
+    })?;
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Columns"))
+            .default_width(480)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Change the columns, then save them and show their effect.
+fn edit_columns<F>(column_list: &ListBox, sort_dropdown: &DropDown, edit: F)
+    -> Result<(), Error>
+    where F: FnOnce(&mut Columns)
+{
+    with_ui(|ui| {
+        edit(&mut ui.columns);
+        replace_traffic_view(ui);
+        fill_column_list(column_list, sort_dropdown, &ui.columns);
+        ui.columns.save(&columns_path())
+    })
+}
+
+/// Rebuild the list of columns shown in the column editor, and the
+/// columns offered for sorting.
+fn fill_column_list(column_list: &ListBox,
+                    sort_dropdown: &DropDown,
+                    columns: &Columns)
+{
+    while let Some(row) = column_list.first_child() {
+        column_list.remove(&row);
+    }
+    for (index, column) in columns.iter().enumerate() {
+        let description = Label::builder()
+            .label(column.title())
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        let left_button = gtk::Button::builder()
+            .icon_name("go-up")
+            .tooltip_text("Show this column further left")
+            .sensitive(index > 0)
+            .build();
+        let remove_button = gtk::Button::builder()
+            .icon_name("list-remove")
+            .tooltip_text("Remove this column")
+            .build();
+        let row = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .spacing(6)
+            .build();
+        row.append(&description);
+        row.append(&left_button);
+        row.append(&remove_button);
+        column_list.append(&row);
+
+        let list = column_list.clone();
+        let dropdown = sort_dropdown.clone();
+        left_button.connect_clicked(move |_|
+            display_error(edit_columns(&list, &dropdown, move |columns|
+                columns.move_left(index))));
+        let list = column_list.clone();
+        let dropdown = sort_dropdown.clone();
+        remove_button.connect_clicked(move |_|
+            display_error(edit_columns(&list, &dropdown, move |columns|
+                columns.remove(index))));
+    }
+    let mut names = vec![String::from("Capture order")];
+    names.extend(columns
+        .iter()
+        .filter(|column| column.sortable())
+        .map(Column::title));
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    if let Some(list) = sort_dropdown
+        .model()
+        .and_then(|model| model.downcast::<StringList>().ok())
+    {
+        list.splice(0, list.n_items(), &names);
+    }
+}
+
+/// Sort the top-level items of the traffic view in the background, by
+/// the sortable column chosen, or return them to capture order.
+fn sort_traffic(choice: u32, descending: bool, status: &Label)
+    -> Result<(), Error>
+{
+    let mut capture = None;
+    let mut filter = None;
+    let mut column = None;
+    with_ui(|ui| {
+        if choice == 0 || choice == gtk::INVALID_LIST_POSITION {
+            if ui.sort_order.take().is_some() {
+                replace_traffic_view(ui);
+            }
+            return Ok(());
+        }
+        let complete = ui.capture.shared.complete.load(Ordering::Acquire);
+        if !complete || ui.show_progress.is_some() {
+            bail!("Traffic can only be sorted once the capture is complete")
+        }
+        column = ui.columns
+            .iter()
+            .filter(|column| column.sortable())
+            .nth(choice as usize - 1)
+            .cloned();
+        capture = Some(ui.capture.clone());
+        filter = ui.filter.clone();
+        Ok(())
+    })?;
+    let column = match column {
+        Some(column) => column,
+        None => return Ok(()),
+    };
+    let mut capture = capture.context("No capture")?;
+    let sorted_filter = filter.clone();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = sort_items(
+            &mut capture, filter.as_deref(), &column, descending);
+        let _ = sender.send(result);
+    });
+    status.set_text("Sorting…");
+    let status = status.clone();
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                status.set_text("");
+                display_error(result.and_then(|order| with_ui(|ui| {
+                    // Discard the order if the filter changed meanwhile.
+                    if ui.filter == sorted_filter {
+                        ui.sort_order = Some(Arc::new(order));
+                        replace_traffic_view(ui);
+                    }
+                    Ok(())
+                })));
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+    Ok(())
+}
+
+/// Select the item at a packet or transaction number, or a time.
+fn go_to(text: &str) -> Result<(), Error> {
+    with_ui(|ui| {
+        let result = Target::parse(text)
+            .and_then(|target| select_target(ui, target));
+        match result {
+            Ok(()) => {
+                ui.goto_label.set_text("");
+                ui.goto_popover.popdown();
+            },
+            Err(error) => ui.goto_label.set_text(&format!("{error:#}")),
+        }
+        Ok(())
+    })
+}
+
+/// Select the first packet at or after a time chosen on the graph.
+fn go_to_time(timestamp: Timestamp) -> Result<(), Error> {
+    with_ui(|ui| select_target(ui, Target::AbsoluteTime(timestamp)))
+}
+
+/// Select the traffic item at a target in the traffic view.
+fn select_target(ui: &UserInterface, target: Target) -> Result<(), Error> {
+    let model = ui.traffic_model
+        .clone()
+        .context("No capture loaded")?;
+    let mut capture = model.capture();
+    let item = target.find(&mut capture)?;
+    match capture.item_path(&item)? {
+        Some(path) => select_path(ui, &model, &path),
+        None => bail!("That item is hidden by the filter"),
+    }
+}
+
+/// Select a bookmarked item in the traffic view.
+fn go_to_bookmark(index: i32) -> Result<(), Error> {
+    with_ui(|ui| {
+        let item = usize::try_from(index)
+            .ok()
+            .and_then(|index| ui.bookmark_items.get(index))
+            .copied()
+            .context("Bookmark not found")?;
+        let model = match &ui.traffic_model {
+            Some(model) => model.clone(),
+            None => return Ok(()),
+        };
+        match model.capture().item_path(&item)? {
+            Some(path) => select_path(ui, &model, &path),
+            None => bail!("The bookmarked item is hidden by the filter"),
+        }
+    })
+}
+
+/// Scroll a window so that a row of the list within it is in view.
+fn scroll_to_row(window: &ScrolledWindow, row: u32, row_count: u32) {
+    if row_count == 0 {
+        return;
+    }
+    // Rows are all the same height, so estimate the position of this one.
+    let adjustment = window.vadjustment();
+    let row_height = adjustment.upper() / row_count as f64;
+    let value = row_height * row as f64 -
+        (adjustment.page_size() - row_height) / 2.0;
+    let max = adjustment.upper() - adjustment.page_size();
+    adjustment.set_value(value.min(max).max(adjustment.lower()));
+}
+
+pub fn update_view() -> Result<(), Error> {
+    with_ui(|ui| {
+        use FileAction::*;
+        #[cfg(feature="record-ui-test")]
+        let guard = {
+            let guard = UPDATE_LOCK.lock();
+            let packet_count = ui.capture.packet_index.len();
+            ui.recording
+                .borrow_mut()
+                .log_update(packet_count);
+            guard
+        };
+        let mut more_updates = false;
+        if matches!(ui.show_progress, Some(Save(_) | Export | Split)) {
+            more_updates = true;
+        } else {
+            let (devices, endpoints, transactions, packets) = {
+                let cap = &ui.capture;
+                let devices = cap.devices.len() - 1;
+                let endpoints = cap.endpoints.len() - 2;
+                let transactions = cap.transaction_index.len();
+                let packets = cap.packet_index.len();
+                (devices, endpoints, transactions, packets)
+            };
+            let reconstructed =
+                ui.capture.shared.reconstructed.load(Ordering::Relaxed);
+            let stored = ui.capture.storage_size();
+            if let Some(limit) = ui.storage_limit {
+                if stored > limit && ui.stop_handle.is_some() {
+                    ui.storage_limit_reached = true;
+                    gtk::glib::idle_add_local_once(||
+                        display_error(stop_cynthion()));
+                }
+            }
+            ui.status_label.set_text(&format!(
+                "{}: {} devices, {} endpoints, {} transactions, {} packets, \
+                 {} stored{}{}{}{}{}",
+                ui.file_name.as_deref().unwrap_or("Unsaved capture"),
+                fmt_count(devices),
+                fmt_count(endpoints),
+                fmt_count(transactions),
+                fmt_count(packets),
+                fmt_size(stored),
+                if reconstructed {
+                    " (reconstructed from URBs)"
+                } else {
+                    ""
+                },
+                if ui.storage_limit_reached {
+                    " (stopped at storage limit)"
+                } else {
+                    ""
+                },
+                if ui.schedule_done {
+                    " (stopped as scheduled)"
+                } else {
+                    ""
+                },
+                match &ui.last_alert {
+                    Some(filter) => format!(" (alert: {filter})"),
+                    None => String::new(),
+                },
+                match &ui.verification {
+                    Some(verification) => format!(
+                        " ({})", verification.description().to_lowercase()),
+                    None => String::new(),
+                }
+            ));
+            if let Some(model) = &ui.traffic_model {
+                let old_count = model.n_items();
+                more_updates |= model.update()?;
+                let new_count = model.n_items();
+                // If any endpoints were added, we need to redraw the rows above
+                // to add the additional columns of the connecting lines.
+                if new_count > old_count {
+                    let new_ep_count = ui.capture.endpoints.len() as u16;
+                    if new_ep_count > ui.endpoint_count {
+                        model.items_changed(0, old_count, old_count);
+                        ui.endpoint_count = new_ep_count;
+                    }
+                }
+            }
+            if let Some(model) = &ui.device_model {
+                more_updates |= model.update()?;
+            }
+            if ui.activity.update(&mut ui.capture)? {
+                show_activity(ui);
+            }
+            let error_count = ui.errors.count();
+            let positions = ui.errors.update(&mut ui.capture)?;
+            if ui.errors.count() != error_count {
+                show_errors(ui, &positions);
+            }
+        }
+        if let Some(action) = ui.show_progress {
+            let total = TOTAL.load(Ordering::Relaxed);
+            let current = CURRENT.load(Ordering::Relaxed);
+            let fraction = (current as f64) / (total as f64);
+            let text = match action {
+                Load | Merge => format!("Loaded {} / {}",
+                                fmt_size(current), fmt_size(total)),
+                Reanalyze => format!("Analyzed {} / {} packets",
+                                fmt_count(current), fmt_count(total)),
+                Save(SaveFormat::Native) => format!("Saved {} / {}",
+                                fmt_size(current), fmt_size(total)),
+                Save(SaveFormat::JsonLines | SaveFormat::Csv) => format!(
+                                "Saved {} / {} items",
+                                fmt_count(current), fmt_count(total)),
+                Save(SaveFormat::Pcap | SaveFormat::Sanitized) |
+                Export | Split => format!(
+                                "Saved {} / {} packets",
+                                fmt_count(current), fmt_count(total)),
+            };
+            ui.progress_bar.set_text(Some(&text));
+            ui.progress_bar.set_fraction(fraction);
+        }
+        if more_updates {
+            gtk::glib::timeout_add_once(
+                UPDATE_INTERVAL,
+                || display_error(update_view())
+            );
         }
         #[cfg(feature="record-ui-test")]
         drop(guard);
         Ok(())
-    })
+    })
+}
+
+fn choose_file(action: FileAction) -> Result<(), Error> {
+    use FileAction::*;
+    let chooser = WINDOW.with(|cell| {
+        let borrow = cell.borrow();
+        let window = borrow.as_ref();
+        match action {
+            Load => gtk::FileChooserDialog::new(
+                Some("Open capture file"),
+                window,
+                gtk::FileChooserAction::Open,
+                &[("Open", gtk::ResponseType::Accept)]
+            ),
+            Merge => {
+                let chooser = gtk::FileChooserDialog::new(
+                    Some("Merge pcap files"),
+                    window,
+                    gtk::FileChooserAction::Open,
+                    &[("Merge", gtk::ResponseType::Accept)]
+                );
+                chooser.set_select_multiple(true);
+                chooser.add_choice("clocks", "Timestamps", &[
+                    ("recorded", "As recorded"),
+                    ("align", "Aligned to the first file's clock"),
+                ]);
+                chooser.set_choice("clocks", "recorded");
+                chooser
+            },
+            Save(_) | Export | Split | Reanalyze => {
+                let chooser = gtk::FileChooserDialog::new(
+                    Some("Save capture"),
+                    window,
+                    gtk::FileChooserAction::Save,
+                    &[("Save", gtk::ResponseType::Accept)]
+                );
+                chooser.add_choice("format", "Format", &[
+                    ("pcap", "Packets (pcap, or pcapng if named .pcapng)"),
+                    ("packetry", "Packets and decoded indexes (Packetry)"),
+                    ("jsonl", "Decoded traffic (JSON Lines)"),
+                    ("csv", "Visible traffic rows (CSV)"),
+                    ("sanitized",
+                     "Packets with data scrubbed, for sharing (pcap)"),
+                ]);
+                chooser.set_choice("format", "pcap");
+                chooser
+            },
+        }
+    });
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if action == Merge {
+                let files = dialog.files();
+                let paths: Vec<PathBuf> = (0..files.n_items())
+                    .filter_map(|i| files.item(i))
+                    .filter_map(|object| object.downcast::<gio::File>().ok())
+                    .filter_map(|file| file.path())
+                    .collect();
+                let align = dialog.choice("clocks").as_deref() == Some("align");
+                display_error(with_ui(|ui| {
+                    ui.align_clocks = align;
+                    Ok(())
+                }));
+                display_error(start_pcap(action, paths));
+            } else if let Some(file) = dialog.file() {
+                if let Some(path) = file.path() {
+                    let format = dialog.choice("format");
+                    let action = match (action, format.as_deref()) {
+                        (Save(_), Some("packetry")) => Save(SaveFormat::Native),
+                        (Save(_), Some("jsonl")) => Save(SaveFormat::JsonLines),
+                        (Save(_), Some("csv")) => Save(SaveFormat::Csv),
+                        (Save(_), Some("sanitized")) =>
+                            Save(SaveFormat::Sanitized),
+                        (action, _) => action,
+                    };
+                    display_error(start_pcap(action, vec![path]));
+                }
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+fn choose_stream_file() -> Result<(), Error> {
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Save to file while capturing"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Select", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(with_ui(|ui| {
+                    ui.stream_options.set_path(path);
+                    Ok(())
+                }));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Show all the data on the endpoint of the selected traffic item.
+fn follow_stream() -> Result<(), Error> {
+    let mut stream = None;
+    with_ui(|ui| {
+        let item = selected_traffic_item(ui)?;
+        stream = Some(FollowedStream::new(&mut ui.capture, &item)?);
+        Ok(())
+    })?;
+    let stream = Rc::new(stream.context("No stream found")?);
+    let mut colors = None;
+    with_ui(|ui| {
+        let theme = ui.theme.borrow();
+        let dark = dark_theme();
+        colors = Some((theme.color(Element::Inbound, dark).to_string(),
+                       theme.color(Element::Outbound, dark).to_string()));
+        Ok(())
+    })?;
+    let (in_color, out_color) = colors.context("No theme")?;
+
+    let view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .wrap_mode(gtk::WrapMode::Char)
+        .build();
+    let buffer = view.buffer();
+    buffer.create_tag(Some("in"), &[("foreground", &in_color)]);
+    buffer.create_tag(Some("out"), &[("foreground", &out_color)]);
+    let direction_dropdown = gtk::DropDown::from_strings(
+        &["Both directions", "IN only", "OUT only"]);
+    let hex_check = gtk::CheckButton::with_label("Hex");
+    let summary = Label::builder()
+        .label(format!("{} IN, {} OUT",
+            fmt_size(stream.total(Some(Direction::In))),
+            fmt_size(stream.total(Some(Direction::Out)))))
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save data to file")
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&direction_dropdown);
+    header.append(&hex_check);
+    header.append(&summary);
+    header.append(&sequence_button(stream.clone(), &direction_dropdown));
+    header.append(&save_button);
+    let window = gtk::ScrolledWindow::builder()
+        .min_content_height(400)
+        .vexpand(true)
+        .child(&view)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&window);
+
+    let fill = {
+        let stream = stream.clone();
+        let direction_dropdown = direction_dropdown.clone();
+        let hex_check = hex_check.clone();
+        move || {
+            buffer.set_text("");
+            let direction = stream_direction(direction_dropdown.selected());
+            let mut shown = 0;
+            for chunk in stream.chunks(direction) {
+                let mut end = buffer.end_iter();
+                if shown >= FOLLOW_DISPLAY_LIMIT {
+                    buffer.insert(&mut end, "\n… further data not shown");
+                    break;
+                }
+                let text = if hex_check.is_active() {
+                    chunk.hex()
+                } else {
+                    chunk.text()
+                };
+                let tag = match chunk.direction {
+                    Direction::In => "in",
+                    Direction::Out => "out",
+                };
+                buffer.insert_with_tags_by_name(&mut end, &text, &[tag]);
+                shown += chunk.data.len();
+            }
+        }
+    };
+    fill();
+    let refill = fill.clone();
+    hex_check.connect_toggled(move |_| refill());
+    direction_dropdown.connect_selected_notify(move |_| fill());
+    let title = format!("Follow stream on endpoint {}", stream.name);
+    save_button.connect_clicked(move |_| {
+        let direction = stream_direction(direction_dropdown.selected());
+        display_error(choose_stream_save(stream.clone(), direction))
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(title)
+            .default_width(640)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Generate a report on how each device in the capture was enumerated.
+fn generate_report() -> Result<Report, Error> {
+    let mut report = None;
+    with_ui(|ui| {
+        report = Some(enumeration_report(&mut ui.capture)?);
+        Ok(())
+    })?;
+    report.context("No report generated")
+}
+
+/// Show a report on how each device in the capture was enumerated.
+fn show_report() -> Result<(), Error> {
+    let report = Rc::new(RefCell::new(generate_report()?));
+    let view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .wrap_mode(gtk::WrapMode::Word)
+        .build();
+    view.buffer().set_text(&report.borrow().markdown());
+    let summary = Label::builder()
+        .label("Descriptors, configuration and problems seen for each device")
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let refresh_button = gtk::Button::builder()
+        .icon_name("view-refresh")
+        .tooltip_text("Update with traffic captured since")
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save report to file")
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&summary);
+    header.append(&refresh_button);
+    header.append(&save_button);
+    let window = gtk::ScrolledWindow::builder()
+        .min_content_height(480)
+        .vexpand(true)
+        .child(&view)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&window);
+
+    let current = report.clone();
+    refresh_button.connect_clicked(move |_| {
+        display_error(generate_report().map(|report| {
+            view.buffer().set_text(&report.markdown());
+            current.replace(report);
+        }))
+    });
+    save_button.connect_clicked(move |_| {
+        display_error(choose_report_save(
+            "Save enumeration report", report.clone()))
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Device enumeration report"))
+            .default_width(640)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Check the devices in the capture against chapter 9 of the USB
+/// specification, updating the results as traffic is captured.
+fn show_compliance() -> Result<(), Error> {
+    let generate = || {
+        let mut report = None;
+        with_ui(|ui| {
+            report = Some(compliance_report(&mut ui.capture)?);
+            Ok(())
+        })?;
+        report.context("No report generated")
+    };
+    let report = Rc::new(RefCell::new(generate()?));
+    let text = report.borrow().markdown();
+    let view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .wrap_mode(gtk::WrapMode::Word)
+        .build();
+    view.buffer().set_text(&text);
+    let summary = Label::builder()
+        .label("Checks are updated as traffic is captured. Each untested \
+                check says what to make the host do.")
+        .halign(Align::Start)
+        .hexpand(true)
+        .wrap(true)
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save report to file")
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&summary);
+    header.append(&save_button);
+    let scrolled = gtk::ScrolledWindow::builder()
+        .min_content_height(480)
+        .vexpand(true)
+        .child(&view)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&scrolled);
+
+    let saved_report = report.clone();
+    save_button.connect_clicked(move |_| {
+        display_error(choose_report_save(
+            "Save compliance report", saved_report.clone()))
+    });
+
+    let closed = Rc::new(Cell::new(false));
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Compliance checks"))
+            .default_width(640)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        let window_closed = closed.clone();
+        window.connect_close_request(move |_| {
+            window_closed.set(true);
+            gtk::glib::Propagation::Proceed
+        });
+        window.show();
+    });
+
+    // Check again each second, replacing the text only when a result has
+    // changed, so that the view is not scrolled back while being read.
+    let mut shown = text;
+    gtk::glib::timeout_add_local(Duration::from_secs(1), move || {
+        if closed.get() {
+            return gtk::glib::ControlFlow::Break;
+        }
+        match generate() {
+            Ok(new_report) => {
+                let text = new_report.markdown();
+                if text != shown {
+                    view.buffer().set_text(&text);
+                    report.replace(new_report);
+                    shown = text;
+                }
+                gtk::glib::ControlFlow::Continue
+            },
+            Err(error) => {
+                display_error(Err(error));
+                gtk::glib::ControlFlow::Break
+            },
+        }
+    });
+    Ok(())
+}
+
+fn choose_report_save(title: &str, report: Rc<RefCell<Report>>)
+    -> Result<(), Error>
+{
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some(title),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Save", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.add_choice("format", "Format", &[
+        ("markdown", "Markdown"),
+        ("html", "HTML"),
+    ]);
+    chooser.set_choice("format", "markdown");
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                let format = match dialog.choice("format").as_deref() {
+                    Some("html") => ReportFormat::Html,
+                    _ => ReportFormat::Markdown,
+                };
+                display_error(save_report(&report.borrow(), format, &path));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+fn save_report(report: &Report, format: ReportFormat, path: &Path)
+    -> Result<(), Error>
+{
+    let file = File::create(path).with_context(|| format!(
+        "Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    report.write(format, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Choose a file to save a table to, then write it there as CSV.
+fn choose_csv_save<F>(title: &str, write: F) -> Result<(), Error>
+    where F: Fn(&mut dyn Write) -> Result<(), Error> + 'static
+{
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some(title),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Save", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(save_csv(&path, &write));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+fn save_csv(path: &Path, write: &dyn Fn(&mut dyn Write) -> Result<(), Error>)
+    -> Result<(), Error>
+{
+    let file = File::create(path).with_context(|| format!(
+        "Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Choose a file to save a plot to, then draw it there as an SVG or PNG
+/// image of the given size.
+fn choose_image_save<F>(title: &str, width: i32, height: i32, draw: F)
+    -> Result<(), Error>
+    where F: Fn(&gtk::cairo::Context, i32, i32) + 'static
+{
+    if width <= 0 || height <= 0 {
+        bail!("Nothing is shown to save")
+    }
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some(title),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Save", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.add_choice("format", "Format", &[
+        ("svg", "Vector image (SVG)"),
+        ("png", "Bitmap image (PNG)"),
+    ]);
+    chooser.set_choice("format", "svg");
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                let svg = dialog.choice("format").as_deref() != Some("png");
+                display_error(save_image(&path, svg, width, height, &draw));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+fn save_image(path: &Path,
+              svg: bool,
+              width: i32,
+              height: i32,
+              draw: &dyn Fn(&gtk::cairo::Context, i32, i32))
+    -> Result<(), Error>
+{
+    use gtk::cairo::{Context, Format, ImageSurface, SvgSurface};
+    if svg {
+        let surface = SvgSurface::new(width as f64, height as f64, Some(path))
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        draw(&Context::new(&surface)?, width, height);
+        // The file is only complete once the surface is finished.
+        surface.finish();
+        surface.status()?;
+    } else {
+        let surface = ImageSurface::create(Format::ARgb32, width, height)?;
+        draw(&Context::new(&surface)?, width, height);
+        let mut file = File::create(path).with_context(|| format!(
+            "Failed to create {}", path.display()))?;
+        surface.write_to_png(&mut file)?;
+    }
+    Ok(())
+}
+
+/// Show statistics on the traffic in the capture.
+fn show_statistics() -> Result<(), Error> {
+    let filter_check = gtk::CheckButton::builder()
+        .label("Apply display filter")
+        .build();
+    let status = Label::builder()
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save statistics as CSV")
+        .build();
+    header.append(&filter_check);
+    header.append(&status);
+    header.append(&save_button);
+    let notebook = gtk::Notebook::new();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&notebook);
+
+    let stats = Rc::new(RefCell::new(Statistics::default()));
+    let update = {
+        let filter_check = filter_check.clone();
+        let stats = stats.clone();
+        move || display_error(update_statistics(
+            &notebook, &status, &stats, filter_check.is_active()))
+    };
+    update();
+    filter_check.connect_toggled(move |_| update());
+    save_button.connect_clicked(move |_| {
+        let stats = stats.clone();
+        display_error(choose_csv_save(
+            "Save statistics",
+            move |writer| stats.borrow().write_csv(writer)));
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Statistics"))
+            .default_width(640)
+            .default_height(400)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Show the details of the current capture, for editing. They are saved
+/// with the capture in native and pcapng files.
+fn show_properties() -> Result<(), Error> {
+    let mut metadata = None;
+    with_ui(|ui| {
+        metadata = Some(Metadata::clone(&ui.capture.shared.metadata.load()));
+        Ok(())
+    })?;
+    let mut metadata = metadata.context("No capture")?;
+    let grid = gtk::Grid::builder()
+        .row_spacing(6)
+        .column_spacing(12)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    type Field = fn(&mut Metadata) -> &mut String;
+    let fields: [(&str, Field); 4] = [
+        ("Title:", |metadata| &mut metadata.title),
+        ("Operator:", |metadata| &mut metadata.operator),
+        ("Device under test:", |metadata| &mut metadata.device),
+        ("Serial number:", |metadata| &mut metadata.serial),
+    ];
+    for (row, (label, field)) in fields.into_iter().enumerate() {
+        let label = Label::builder()
+            .label(label)
+            .halign(Align::Start)
+            .build();
+        let entry = Entry::builder()
+            .text(field(&mut metadata).as_str())
+            .hexpand(true)
+            .build();
+        entry.connect_changed(move |entry| {
+            let text = entry.text().to_string();
+            display_error(edit_metadata(|metadata|
+                *field(metadata) = text));
+        });
+        grid.attach(&label, 0, row as i32, 1, 1);
+        grid.attach(&entry, 1, row as i32, 1, 1);
+    }
+    let notes_label = Label::builder()
+        .label("Notes:")
+        .halign(Align::Start)
+        .valign(Align::Start)
+        .build();
+    let notes_view = gtk::TextView::builder()
+        .wrap_mode(gtk::WrapMode::WordChar)
+        .build();
+    notes_view.buffer().set_text(&metadata.notes);
+    notes_view.buffer().connect_changed(|buffer| {
+        let notes = buffer
+            .text(&buffer.start_iter(), &buffer.end_iter(), false)
+            .to_string();
+        display_error(edit_metadata(|metadata| metadata.notes = notes));
+    });
+    let notes_window = gtk::ScrolledWindow::builder()
+        .min_content_height(120)
+        .hexpand(true)
+        .vexpand(true)
+        .child(&notes_view)
+        .build();
+    grid.attach(&notes_label, 0, fields.len() as i32, 1, 1);
+    grid.attach(&notes_window, 1, fields.len() as i32, 1, 1);
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Capture properties"))
+            .default_width(480)
+            .child(&grid)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Change the details of the current capture.
+fn edit_metadata<F>(edit: F) -> Result<(), Error>
+    where F: FnOnce(&mut Metadata)
+{
+    with_ui(|ui| {
+        let shared = &ui.capture.shared;
+        let mut metadata = Metadata::clone(&shared.metadata.load());
+        edit(&mut metadata);
+        shared.metadata.store(Arc::new(metadata));
+        Ok(())
+    })
+}
+
+/// Show the space used by each of the stores holding the capture, and
+/// the limit at which a live capture is stopped.
+fn show_storage() -> Result<(), Error> {
+    let mut sizes = Vec::new();
+    let mut limit = None;
+    let mut dir = None;
+    with_ui(|ui| {
+        sizes = ui.capture.storage_sizes();
+        limit = ui.storage_limit;
+        dir = ui.storage_dir.clone();
+        Ok(())
+    })?;
+    let grid = gtk::Grid::builder()
+        .row_spacing(4)
+        .column_spacing(12)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    let mut size_labels = Vec::new();
+    for (row, (name, _)) in sizes.iter().enumerate() {
+        let name_label = Label::builder()
+            .label(*name)
+            .halign(Align::Start)
+            .build();
+        let size_label = Label::builder()
+            .halign(Align::End)
+            .build();
+        grid.attach(&name_label, 0, row as i32, 1, 1);
+        grid.attach(&size_label, 1, row as i32, 1, 1);
+        size_labels.push(size_label);
+    }
+    let total_row = sizes.len() as i32;
+    let total_name = Label::builder()
+        .label("<b>Total</b>")
+        .use_markup(true)
+        .halign(Align::Start)
+        .build();
+    let total_label = Label::builder()
+        .halign(Align::End)
+        .build();
+    grid.attach(&total_name, 0, total_row, 1, 1);
+    grid.attach(&total_label, 1, total_row, 1, 1);
+    let limit_label = Label::builder()
+        .label("Stop capturing above this many MB (0 for no limit):")
+        .halign(Align::Start)
+        .margin_top(6)
+        .build();
+    let limit_spin = SpinButton::with_range(0.0, 1e7, 100.0);
+    limit_spin.set_margin_top(6);
+    limit_spin.set_value(limit.map_or(0.0, |limit| (limit / 1_000_000) as f64));
+    limit_spin.connect_value_changed(|spin| {
+        let megabytes = spin.value_as_int() as u64;
+        display_error(with_ui(|ui| {
+            ui.storage_limit = match megabytes {
+                0 => None,
+                megabytes => Some(megabytes * 1_000_000),
+            };
+            Ok(())
+        }));
+    });
+    grid.attach(&limit_label, 0, total_row + 1, 1, 1);
+    grid.attach(&limit_spin, 1, total_row + 1, 1, 1);
+    let dir_label = Label::builder()
+        .label("Folder for the data of new captures:")
+        .halign(Align::Start)
+        .build();
+    let dir_button = Button::builder()
+        .label(storage_dir_name(dir.as_deref()))
+        .build();
+    let default_button = Button::builder()
+        .label("Use default")
+        .build();
+    let dir_box = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(4)
+        .build();
+    dir_box.append(&dir_button);
+    dir_box.append(&default_button);
+    dir_button.connect_clicked(|button|
+        display_error(choose_storage_dir(button)));
+    let default_dir_button = dir_button.clone();
+    default_button.connect_clicked(move |_|
+        display_error(set_capture_storage_dir(&default_dir_button, None)));
+    grid.attach(&dir_label, 0, total_row + 2, 1, 1);
+    grid.attach(&dir_box, 1, total_row + 2, 1, 1);
+
+    let update = move || -> Result<(), Error> {
+        with_ui(|ui| {
+            let sizes = ui.capture.storage_sizes();
+            for ((_, size), label) in sizes.iter().zip(&size_labels) {
+                label.set_text(&fmt_size(*size));
+            }
+            total_label.set_text(&fmt_size(ui.capture.storage_size()));
+            Ok(())
+        })
+    };
+    update()?;
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Capture storage"))
+            .child(&grid)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+        // Keep the sizes up to date during a capture, until closed.
+        gtk::glib::timeout_add_local(Duration::from_secs(1), move || {
+            if !window.is_visible() {
+                return gtk::glib::ControlFlow::Break;
+            }
+            display_error(update());
+            gtk::glib::ControlFlow::Continue
+        });
+    });
+    Ok(())
+}
+
+/// Description of the folder holding the data of captures.
+fn storage_dir_name(dir: Option<&Path>) -> String {
+    match dir {
+        Some(dir) => dir.display().to_string(),
+        None => "System temporary folder".to_string(),
+    }
+}
+
+/// Choose the folder holding the data of new captures.
+fn choose_storage_dir(button: &Button) -> Result<(), Error> {
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Folder for capture data"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::SelectFolder,
+            &[("Select", gtk::ResponseType::Accept)]
+        )
+    });
+    let button = button.clone();
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(set_capture_storage_dir(&button, Some(path)));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Set the folder holding the data of new captures.
+fn set_capture_storage_dir(button: &Button, dir: Option<PathBuf>)
+    -> Result<(), Error>
+{
+    button.set_label(&storage_dir_name(dir.as_deref()));
+    set_storage_dir(dir.clone());
+    with_ui(|ui| {
+        ui.storage_dir = dir;
+        Ok(())
+    })
+}
+
+/// Count the traffic in the background, then show the results.
+fn update_statistics(notebook: &gtk::Notebook,
+                     status: &Label,
+                     current: &Rc<RefCell<Statistics>>,
+                     apply_filter: bool)
+    -> Result<(), Error>
+{
+    let mut capture = None;
+    let mut filter = None;
+    with_ui(|ui| {
+        capture = Some(ui.capture.clone());
+        if apply_filter {
+            filter = ui.filter.clone();
+        }
+        Ok(())
+    })?;
+    let mut capture = capture.context("No capture")?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = Statistics::new(&mut capture, filter.as_deref());
+        let _ = sender.send(result);
+    });
+    status.set_text("Counting…");
+    let notebook = notebook.clone();
+    let status = status.clone();
+    let current = current.clone();
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                status.set_text("");
+                display_error(result.map(|stats| {
+                    fill_statistics(&notebook, &stats);
+                    current.replace(stats);
+                }));
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+    Ok(())
+}
+
+/// Show statistics in a page for each way of breaking down the traffic.
+fn fill_statistics(notebook: &gtk::Notebook, stats: &Statistics) {
+    while notebook.n_pages() > 0 {
+        notebook.remove_page(None);
+    }
+    for (title, rows) in stats.tables() {
+        let grid = gtk::Grid::builder()
+            .column_spacing(18)
+            .row_spacing(3)
+            .margin_top(6)
+            .margin_start(6)
+            .margin_end(6)
+            .build();
+        let headings = ["", "Transfers", "Transactions", "Packets",
+                        "Bytes", "Errors", "Bandwidth"];
+        for (column, heading) in headings.iter().enumerate() {
+            let label = Label::builder()
+                .use_markup(true)
+                .label(format!("<b>{heading}</b>"))
+                .halign(Align::End)
+                .build();
+            grid.attach(&label, column as i32, 0, 1, 1);
+        }
+        for (row, (name, counts)) in rows.iter().enumerate() {
+            let cells = [
+                name.clone(),
+                fmt_count(counts.transfers),
+                fmt_count(counts.transactions),
+                fmt_count(counts.packets),
+                fmt_size(counts.bytes),
+                fmt_count(counts.errors),
+                format!("{}/s",
+                        fmt_size(counts.bandwidth(stats.duration) as u64)),
+            ];
+            for (column, text) in cells.iter().enumerate() {
+                let label = Label::builder()
+                    .label(text.as_str())
+                    .halign(if column == 0 { Align::Start } else { Align::End })
+                    .build();
+                grid.attach(&label, column as i32, row as i32 + 1, 1, 1);
+            }
+        }
+        let window = gtk::ScrolledWindow::builder()
+            .vexpand(true)
+            .child(&grid)
+            .build();
+        notebook.append_page(&window, Some(&Label::new(Some(title))));
+    }
+}
+
+/// Show statistics on the control requests made to each device.
+fn show_requests() -> Result<(), Error> {
+    let mut capture = None;
+    with_ui(|ui| {
+        capture = Some(ui.capture.clone());
+        Ok(())
+    })?;
+    let mut capture = capture.context("No capture")?;
+    let device_dropdown = DropDown::from_strings(&["All devices"]);
+    device_dropdown.set_sensitive(false);
+    let status = Label::builder()
+        .label("Counting…")
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save the requests listed as CSV")
+        .sensitive(false)
+        .build();
+    header.append(&Label::new(Some("Device:")));
+    header.append(&device_dropdown);
+    header.append(&status);
+    header.append(&save_button);
+    let grid = gtk::Grid::builder()
+        .column_spacing(18)
+        .row_spacing(3)
+        .margin_top(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    let scrolled = gtk::ScrolledWindow::builder()
+        .vexpand(true)
+        .child(&grid)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&scrolled);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(RequestStatistics::new(&mut capture));
+    });
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                status.set_text("");
+                display_error(result.map(|stats| list_requests(
+                    stats, &device_dropdown, &save_button, &grid)));
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Control requests"))
+            .default_width(720)
+            .default_height(400)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// List the control requests counted, for the device selected.
+fn list_requests(stats: RequestStatistics,
+                 dropdown: &DropDown,
+                 save_button: &Button,
+                 grid: &gtk::Grid)
+{
+    let stats = Rc::new(stats);
+    let devices = Rc::new(stats.devices());
+    let mut names = vec![String::from("All devices")];
+    names.extend(devices.iter().map(|address| format!("Device {address}")));
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    dropdown.set_model(Some(&StringList::new(&names)));
+    dropdown.set_sensitive(true);
+    save_button.set_sensitive(true);
+    let saved_stats = stats.clone();
+    let saved_devices = devices.clone();
+    let saved_dropdown = dropdown.clone();
+    save_button.connect_clicked(move |_| {
+        let stats = saved_stats.clone();
+        let device = selected_device(&saved_devices, &saved_dropdown);
+        display_error(choose_csv_save(
+            "Save control requests",
+            move |writer| stats.write_csv(device, writer)));
+    });
+    let grid = grid.clone();
+    let fill = move |dropdown: &DropDown| {
+        fill_requests(&grid, &stats, selected_device(&devices, dropdown));
+    };
+    fill(dropdown);
+    dropdown.connect_selected_notify(fill);
+}
+
+/// The device chosen from a list of all devices followed by each address.
+fn selected_device(devices: &[u8], dropdown: &DropDown) -> Option<u8> {
+    match dropdown.selected() {
+        0 => None,
+        index => devices.get(index as usize - 1).copied(),
+    }
+}
+
+/// Show a row for each kind of request made, and a total.
+fn fill_requests(grid: &gtk::Grid,
+                 stats: &RequestStatistics,
+                 device: Option<u8>)
+{
+    while let Some(child) = grid.first_child() {
+        grid.remove(&child);
+    }
+    let headings = ["Device", "Request", "Count", "Completed", "Stalled",
+                    "Unfinished", "Stall rate", "Average time"];
+    for (column, heading) in headings.iter().enumerate() {
+        let label = Label::builder()
+            .use_markup(true)
+            .label(format!("<b>{heading}</b>"))
+            .halign(if column < 2 { Align::Start } else { Align::End })
+            .build();
+        grid.attach(&label, column as i32, 0, 1, 1);
+    }
+    let total = stats.total(device);
+    let rows = stats
+        .requests(device)
+        .map(|(kind, counts)| (kind.device.to_string(), kind.name(), *counts))
+        .chain([(String::new(), String::from("Total"), total)]);
+    for (row, (address, name, counts)) in rows.enumerate() {
+        let cells = [
+            address,
+            name,
+            fmt_count(counts.count()),
+            fmt_count(counts.completed),
+            fmt_count(counts.stalled),
+            fmt_count(counts.unfinished),
+            format!("{:.1}%", counts.stall_ratio() * 100.0),
+            counts.average_latency().map_or_else(
+                || String::from("-"),
+                |latency| format!("{:.3} ms", latency as f64 / 1e6)),
+        ];
+        for (column, text) in cells.iter().enumerate() {
+            let label = Label::builder()
+                .label(text.as_str())
+                .halign(if column < 2 { Align::Start } else { Align::End })
+                .build();
+            grid.attach(&label, column as i32, row as i32 + 1, 1, 1);
+        }
+    }
+}
+
+/// Show the time taken by split transactions through each hub port, and
+/// those retried more than expected.
+fn show_splits() -> Result<(), Error> {
+    let mut capture = None;
+    with_ui(|ui| {
+        capture = Some(ui.capture.clone());
+        Ok(())
+    })?;
+    let mut capture = capture.context("No capture")?;
+    let status = Label::builder()
+        .label("Timing split transactions…")
+        .halign(Align::Start)
+        .hexpand(true)
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save the ports listed as CSV")
+        .sensitive(false)
+        .build();
+    header.append(&status);
+    header.append(&save_button);
+    let grid = gtk::Grid::builder()
+        .column_spacing(18)
+        .row_spacing(3)
+        .margin_top(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    let flagged = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(3)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    let content = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(12)
+        .build();
+    content.append(&grid);
+    content.append(&flagged);
+    let scrolled = gtk::ScrolledWindow::builder()
+        .vexpand(true)
+        .child(&content)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&scrolled);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(SplitStatistics::new(&mut capture));
+    });
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                status.set_text("");
+                display_error(result.map(|stats| list_splits(
+                    stats, &status, &save_button, &grid, &flagged)));
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Split transactions"))
+            .default_width(800)
+            .default_height(400)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// List the split transactions timed, with a row for each hub port and a
+/// button to go to each split retried more than expected.
+fn list_splits(stats: SplitStatistics,
+               status: &Label,
+               save_button: &Button,
+               grid: &gtk::Grid,
+               flagged: &gtk::Box)
+{
+    if stats.ports.is_empty() {
+        status.set_text("No split transactions found");
+        return;
+    }
+    let stats = Rc::new(stats);
+    let saved_stats = stats.clone();
+    save_button.set_sensitive(true);
+    save_button.connect_clicked(move |_| {
+        let stats = saved_stats.clone();
+        display_error(choose_csv_save(
+            "Save split transactions",
+            move |writer| stats.write_csv(writer)));
+    });
+    let headings = ["Hub", "Port", "Count", "Completed", "Unfinished",
+                    "Average retries", "Most retries", "Excessive",
+                    "Average time", "Longest time"];
+    for (column, heading) in headings.iter().enumerate() {
+        let label = Label::builder()
+            .use_markup(true)
+            .label(format!("<b>{heading}</b>"))
+            .halign(if column < 2 { Align::Start } else { Align::End })
+            .build();
+        grid.attach(&label, column as i32, 0, 1, 1);
+    }
+    let fmt_latency = |latency: u64| format!("{:.3} ms", latency as f64 / 1e6);
+    let rows = stats.ports
+        .iter()
+        .map(|(port, counts)|
+            (port.hub.to_string(), port.port.to_string(), *counts))
+        .chain([(String::from("Total"), String::new(), stats.total())]);
+    for (row, (hub, port, counts)) in rows.enumerate() {
+        let cells = [
+            hub,
+            port,
+            fmt_count(counts.count()),
+            fmt_count(counts.completed),
+            fmt_count(counts.unfinished),
+            format!("{:.2}", counts.average_retries()),
+            fmt_count(counts.max_retries),
+            fmt_count(counts.excessive),
+            counts.average_latency()
+                .map_or_else(|| String::from("-"), fmt_latency),
+            if counts.completed == 0 {
+                String::from("-")
+            } else {
+                fmt_latency(counts.max_latency)
+            },
+        ];
+        for (column, text) in cells.iter().enumerate() {
+            let label = Label::builder()
+                .label(text.as_str())
+                .halign(if column < 2 { Align::Start } else { Align::End })
+                .build();
+            grid.attach(&label, column as i32, row as i32 + 1, 1, 1);
+        }
+    }
+    let heading = if stats.flagged.is_empty() {
+        format!("No splits were retried more than {MAX_EXPECTED_RETRIES} \
+                 times.")
+    } else {
+        format!("<b>Splits retried more than {MAX_EXPECTED_RETRIES} \
+                 times:</b>")
+    };
+    flagged.append(&Label::builder()
+        .use_markup(true)
+        .label(heading)
+        .halign(Align::Start)
+        .build());
+    for split in &stats.flagged {
+        let packet_number = split.packet_id.value + 1;
+        let outcome = split.latency.map_or_else(
+            || String::from("never completed"),
+            |latency| format!("completed in {}", fmt_latency(latency)));
+        let button = gtk::Button::builder()
+            .label(format!(
+                "Packet {packet_number}: hub {} port {}, device {} \
+                 endpoint {}, {} retries, {outcome}",
+                split.hub_port.hub, split.hub_port.port,
+                split.device, split.endpoint, split.retries))
+            .halign(Align::Start)
+            .has_frame(false)
+            .tooltip_text("Go to the start-split")
+            .build();
+        button.connect_clicked(move |_| display_error(with_ui(|ui|
+            select_target(ui, Target::Packet(packet_number)))));
+        flagged.append(&button);
+    }
+}
+
+/// Recount the traffic plotted in the bandwidth graph, in the background.
+fn update_graph() -> Result<(), Error> {
+    let mut capture = None;
+    let mut selection = Selection::All;
+    let mut speed = BusSpeed::High;
+    let mut widgets = None;
+    with_ui(|ui| {
+        if !ui.graph_button.is_active() {
+            return Ok(());
+        }
+        let options = Selection::options(&mut ui.capture)?;
+        let selections: Vec<Selection> = options
+            .iter()
+            .map(|(_, selection)| *selection)
+            .collect();
+        let current = ui.graph_options
+            .get(ui.graph_selection.selected() as usize)
+            .copied()
+            .unwrap_or(Selection::All);
+        if selections != ui.graph_options {
+            let names: Vec<&str> = options
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if let Some(list) = ui.graph_selection
+                .model()
+                .and_then(|model| model.downcast::<StringList>().ok())
+            {
+                list.splice(0, list.n_items(), &names);
+            }
+            let index = selections
+                .iter()
+                .position(|selection| *selection == current)
+                .unwrap_or(0);
+            ui.graph_options = selections;
+            ui.graph_selection.set_selected(index as u32);
+        }
+        selection = ui.graph_options
+            .get(ui.graph_selection.selected() as usize)
+            .copied()
+            .unwrap_or(Selection::All);
+        speed = graph_bus_speed(&ui.graph_speed);
+        capture = Some(ui.capture.clone());
+        widgets = Some((
+            ui.graph.clone(),
+            ui.graph_area.clone(),
+            ui.graph_label.clone()));
+        Ok(())
+    })?;
+    let (mut capture, (graph, area, label)) = match (capture, widgets) {
+        (Some(capture), Some(widgets)) => (capture, widgets),
+        _ => return Ok(()),
+    };
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = Graph::new(&mut capture, selection, GRAPH_INTERVALS);
+        let _ = sender.send(result);
+    });
+    label.set_text("Counting…");
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                match result {
+                    Ok(new_graph) => {
+                        label.set_text(&graph_summary(&new_graph, speed));
+                        *graph.borrow_mut() = new_graph;
+                        area.queue_draw();
+                    },
+                    Err(error) => {
+                        label.set_text("");
+                        display_error(Err(error));
+                    },
+                }
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+    Ok(())
+}
+
+/// Bus speed chosen for measuring utilization.
+fn graph_bus_speed(dropdown: &DropDown) -> BusSpeed {
+    BusSpeed::ALL
+        .get(dropdown.selected() as usize)
+        .copied()
+        .unwrap_or(BusSpeed::High)
+}
+
+/// Describe the peak throughput and utilization in the graph.
+fn graph_summary(graph: &Graph, speed: BusSpeed) -> String {
+    if graph.bytes.is_empty() {
+        return String::new();
+    }
+    let peak = graph.peak();
+    format!("Peak {}/s, {:.1}% of bus, in intervals of {:.3} ms",
+            fmt_size(peak as u64),
+            peak * 8.0 / speed.bits_per_second() * 100.0,
+            graph.interval as f64 / 1e6)
+}
+
+/// Plot the throughput in each interval as a bar, scaled to the peak.
+fn draw_graph(graph: &Graph,
+              speed: BusSpeed,
+              theme: &Theme,
+              context: &gtk::cairo::Context,
+              width: i32,
+              height: i32)
+{
+    let (width, height) = (width as f64, height as f64);
+    let dark = dark_theme();
+    set_source_color(context, theme.color(Element::PlotBackground, dark));
+    let _ = context.paint();
+    let peak = graph.peak();
+    if peak == 0.0 {
+        return;
+    }
+    let bar_width = width / graph.bytes.len() as f64;
+    set_source_color(context, theme.color(Element::PlotBars, dark));
+    for index in 0..graph.bytes.len() {
+        let bar_height = graph.throughput(index) / peak * height;
+        context.rectangle(
+            index as f64 * bar_width, height - bar_height,
+            bar_width, bar_height);
+    }
+    let _ = context.fill();
+    // Label the top of the scale in both throughput and utilization.
+    let utilization = format!("{:.1}%",
+        peak * 8.0 / speed.bits_per_second() * 100.0);
+    set_source_color(context, theme.color(Element::PlotText, dark));
+    context.set_font_size(11.0);
+    context.move_to(4.0, 12.0);
+    let _ = context.show_text(&format!("{}/s", fmt_size(peak as u64)));
+    if let Ok(extents) = context.text_extents(&utilization) {
+        context.move_to(width - extents.width() - 4.0, 12.0);
+        let _ = context.show_text(&utilization);
+    }
+}
+
+/// Draw the values read for a field as a line over the time they span,
+/// scaled to fit their range.
+fn draw_field_plot(plot: &FieldPlot,
+                   theme: &Theme,
+                   context: &gtk::cairo::Context,
+                   width: i32,
+                   height: i32)
+{
+    let (width, height) = (width as f64, height as f64);
+    let dark = dark_theme();
+    set_source_color(context, theme.color(Element::PlotBackground, dark));
+    let _ = context.paint();
+    let ((low, high), (start, end)) =
+        match (plot.value_range(), plot.time_range()) {
+            (Some(values), Some(times)) => (values, times),
+            _ => return,
+        };
+    // Leave room for the labels above and below the line.
+    let (top, bottom) = (16.0, height - 16.0);
+    let value_span = if high > low { high - low } else { 1.0 };
+    let time_span = end.saturating_sub(start).max(1) as f64;
+    set_source_color(context, theme.color(Element::PlotBars, dark));
+    context.set_line_width(1.5);
+    for (index, (time, value)) in plot.samples.iter().enumerate() {
+        let x = (time - start) as f64 / time_span * width;
+        let y = bottom - (value - low) / value_span * (bottom - top);
+        if index == 0 {
+            context.move_to(x, y);
+        } else {
+            context.line_to(x, y);
+        }
+    }
+    let _ = context.stroke();
+    set_source_color(context, theme.color(Element::PlotText, dark));
+    context.set_font_size(11.0);
+    context.move_to(4.0, 12.0);
+    let _ = context.show_text(&format!("{high}"));
+    context.move_to(4.0, height - 4.0);
+    let _ = context.show_text(&format!("{low}"));
+    let duration = format!("{:.3} s", time_span / 1e9);
+    if let Ok(extents) = context.text_extents(&duration) {
+        context.move_to(width - extents.width() - 4.0, height - 4.0);
+        let _ = context.show_text(&duration);
+    }
+}
+
+/// Recount the packets shown in the endpoint heat map, in the background.
+fn update_heatmap() -> Result<(), Error> {
+    let mut capture = None;
+    let mut widgets = None;
+    with_ui(|ui| {
+        if ui.heatmap_button.is_active() {
+            capture = Some(ui.capture.clone());
+            widgets = Some((
+                ui.heatmap.clone(),
+                ui.heatmap_area.clone(),
+                ui.heatmap_label.clone()));
+        }
+        Ok(())
+    })?;
+    let (mut capture, (heatmap, area, label)) = match (capture, widgets) {
+        (Some(capture), Some(widgets)) => (capture, widgets),
+        _ => return Ok(()),
+    };
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = HeatMap::new(&mut capture, HEATMAP_INTERVALS);
+        let _ = sender.send(result);
+    });
+    label.set_text("Counting…");
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                match result {
+                    Ok(new_heatmap) => {
+                        label.set_text(&heatmap_summary(&new_heatmap));
+                        let rows = new_heatmap.rows.len().max(1);
+                        area.set_content_height(
+                            (rows as f64 * HEATMAP_ROW_HEIGHT) as i32);
+                        *heatmap.borrow_mut() = new_heatmap;
+                        area.queue_draw();
+                    },
+                    Err(error) => {
+                        label.set_text("");
+                        display_error(Err(error));
+                    },
+                }
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+    Ok(())
+}
+
+/// Describe the scale of the heat map.
+fn heatmap_summary(heatmap: &HeatMap) -> String {
+    if heatmap.rows.is_empty() {
+        return String::new();
+    }
+    format!("Peak {} packets on an endpoint, in intervals of {:.3} ms",
+            heatmap.peak(),
+            heatmap.interval as f64 / 1e6)
+}
+
+/// Shade each interval on each endpoint by its packet rate, brightest at
+/// the peak.
+fn draw_heatmap(heatmap: &HeatMap,
+                theme: &Theme,
+                context: &gtk::cairo::Context,
+                width: i32,
+                _height: i32)
+{
+    let width = width as f64;
+    let dark = dark_theme();
+    set_source_color(context, theme.color(Element::PlotBackground, dark));
+    let _ = context.paint();
+    let plot_width = width - HEATMAP_LABEL_WIDTH;
+    let columns = heatmap.column_count();
+    if plot_width <= 0.0 || columns == 0 {
+        return;
+    }
+    let cell_width = plot_width / columns as f64;
+    let peak = heatmap.peak();
+    let (red, green, blue) = rgb(theme.color(Element::PlotBars, dark));
+    context.set_font_size(10.0);
+    for (index, row) in heatmap.rows.iter().enumerate() {
+        let top = index as f64 * HEATMAP_ROW_HEIGHT;
+        if index % 2 == 1 {
+            set_source_color(context, theme.color(Element::PlotStripe, dark));
+            context.rectangle(0.0, top, width, HEATMAP_ROW_HEIGHT);
+            let _ = context.fill();
+        }
+        set_source_color(context, theme.color(Element::PlotText, dark));
+        context.move_to(4.0, top + HEATMAP_ROW_HEIGHT - 2.0);
+        let _ = context.show_text(&row.name);
+        for column in 0..columns {
+            let intensity = heatmap.intensity(index, column, peak);
+            if intensity == 0.0 {
+                continue;
+            }
+            // Keep the least busy intervals visible against the background.
+            context.set_source_rgba(red, green, blue, 0.15 + 0.85 * intensity);
+            context.rectangle(
+                HEATMAP_LABEL_WIDTH + column as f64 * cell_width, top,
+                cell_width, HEATMAP_ROW_HEIGHT);
+            let _ = context.fill();
+        }
+    }
+}
+
+/// Choose a capture file to compare with the current capture.
+fn choose_comparison() -> Result<(), Error> {
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Compare with capture file"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Open,
+            &[("Compare", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(compare_with(path));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Load another capture and compare it with the current one, in the
+/// background.
+fn compare_with(path: PathBuf) -> Result<(), Error> {
+    let mut shared = None;
+    with_ui(|ui| {
+        ui.status_label.set_text("Comparing captures…");
+        shared = Some((ui.capture.clone(), ui.theme.clone()));
+        Ok(())
+    })?;
+    let (mut capture, theme) = shared.context("No capture")?;
+    let name = path
+        .file_name()
+        .map_or_else(
+            || path.to_string_lossy().to_string(),
+            |name| name.to_string_lossy().to_string());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = crate::loader::load(&[path]).and_then(|mut other|
+            Comparison::new(&mut capture, &mut other));
+        let _ = sender.send(result);
+    });
+    gtk::glib::timeout_add_local(UPDATE_INTERVAL, move || {
+        use std::sync::mpsc::TryRecvError;
+        match receiver.try_recv() {
+            Ok(result) => {
+                display_error(with_ui(|ui| {
+                    ui.status_label.set_text("Ready");
+                    Ok(())
+                }));
+                display_error(result.map(|comparison|
+                    show_comparison(&name, comparison, &theme)));
+                gtk::glib::ControlFlow::Break
+            },
+            Err(TryRecvError::Empty) => gtk::glib::ControlFlow::Continue,
+            Err(TryRecvError::Disconnected) => gtk::glib::ControlFlow::Break,
+        }
+    });
+    Ok(())
+}
+
+/// Show the steps of two captures side by side, with their differences.
+fn show_comparison(name: &str,
+                   comparison: Comparison,
+                   theme: &Rc<RefCell<Theme>>)
+{
+    let differences = comparison.changes
+        .iter()
+        .filter(|change| !matches!(change, Change::Same(..)))
+        .count();
+    let mut summary = match differences {
+        0 => "No differences found".to_string(),
+        1 => "1 difference found".to_string(),
+        n => format!("{n} differences found"),
+    };
+    if comparison.truncated {
+        summary.push_str(", comparing only the start of each capture");
+    }
+    let only_differences = gtk::CheckButton::builder()
+        .label("Show only differences")
+        .build();
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&Label::builder()
+        .label(summary.as_str())
+        .halign(Align::Start)
+        .hexpand(true)
+        .build());
+    header.append(&only_differences);
+    let titles = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .homogeneous(true)
+        .build();
+    for title in ["This capture", name] {
+        titles.append(&Label::builder()
+            .use_markup(true)
+            .label(format!("<b>{}</b>", gtk::glib::markup_escape_text(title)))
+            .halign(Align::Start)
+            .build());
+    }
+    let list = ListBox::new();
+    let list_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&titles);
+    vbox.append(&list_window);
+
+    // The item in this capture shown on each row, if any.
+    let row_items = Rc::new(RefCell::new(Vec::new()));
+    let comparison = Rc::new(comparison);
+    fill_comparison(&list, &comparison, &theme.borrow(), false,
+                    &mut row_items.borrow_mut());
+    let filled_list = list.clone();
+    let filled_items = row_items.clone();
+    let filled_theme = theme.clone();
+    only_differences.connect_toggled(move |check|
+        fill_comparison(&filled_list, &comparison, &filled_theme.borrow(),
+                        check.is_active(), &mut filled_items.borrow_mut()));
+    list.connect_row_activated(move |_, row| {
+        let item = usize::try_from(row.index())
+            .ok()
+            .and_then(|index| row_items.borrow().get(index).copied())
+            .flatten();
+        if let Some(item) = item {
+            display_error(go_to_item(item));
+        }
+    });
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(format!("Comparison with {name}"))
+            .default_width(900)
+            .default_height(500)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+}
+
+/// Fill the list of a comparison, with a row for each change.
+fn fill_comparison(list: &ListBox,
+                   comparison: &Comparison,
+                   theme: &Theme,
+                   only_differences: bool,
+                   row_items: &mut Vec<Option<TrafficItem>>)
+{
+    let dark = dark_theme();
+    let changed = theme.color(Element::Highlight, dark);
+    let removed = theme.color(Element::Removed, dark);
+    let added = theme.color(Element::Added, dark);
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+    row_items.clear();
+    let cell = |step: Option<&Step>, color: Option<&str>| {
+        let label = Label::builder()
+            .use_markup(true)
+            .wrap(true)
+            .xalign(0.0)
+            .build();
+        let summary = step.map(|step|
+            gtk::glib::markup_escape_text(&step.summary));
+        match (summary, color) {
+            (Some(summary), Some(color)) => label.set_markup(
+                &format!("<span background=\"{color}\">{summary}</span>")),
+            (Some(summary), None) => label.set_markup(&summary),
+            (None, _) => {},
+        }
+        label
+    };
+    for change in &comparison.changes {
+        use Change::*;
+        let (left, right, color) = match *change {
+            Same(..) if only_differences => continue,
+            Same(i, j) => (Some(i), Some(j), None),
+            Changed(i, j) => (Some(i), Some(j), Some(changed)),
+            Removed(i) => (Some(i), None, Some(removed)),
+            Added(j) => (None, Some(j), Some(added)),
+        };
+        let left = left.map(|i| &comparison.left[i]);
+        let right = right.map(|j| &comparison.right[j]);
+        let row_box = gtk::Box::builder()
+            .orientation(Orientation::Horizontal)
+            .homogeneous(true)
+            .spacing(12)
+            .build();
+        row_box.append(&cell(left, color));
+        row_box.append(&cell(right, color));
+        if let (Changed(..), Some(left), Some(right)) = (change, left, right) {
+            row_box.set_tooltip_text(Some(&format!(
+                "This capture: {}\nOther capture: {}",
+                left.content, right.content)));
+        }
+        list.append(&row_box);
+        row_items.push(left.map(|step| step.item));
+    }
+}
+
+/// Select an item of the current capture in the traffic view.
+fn go_to_item(item: TrafficItem) -> Result<(), Error> {
+    with_ui(|ui| {
+        let model = ui.traffic_model
+            .clone()
+            .context("No capture loaded")?;
+        let path = model.capture().item_path(&item)?;
+        match path {
+            Some(path) => select_path(ui, &model, &path),
+            None => bail!("That item is hidden by the filter"),
+        }
+    })
+}
+
+/// The state of a timeline window.
+struct TimelineView {
+    capture: CaptureReader,
+    /// Time of the first packet, from which the scrollbar is measured.
+    origin: Timestamp,
+    timeline: Timeline,
+    /// Halts found so far, updated as the capture grows.
+    halts: Halts,
+    /// Periods found so far during which the bus carried no packets.
+    idle: IdlePeriods,
+    /// OTG events found, and the number of packets they were found in.
+    otg: (Vec<OtgEvent>, u64),
+    /// An imported logic analyzer trace, and the capture time at which
+    /// its time zero is placed.
+    trace: Option<(LogicTrace, Timestamp)>,
+}
+
+/// Show the transactions on a time axis, in a lane for each endpoint.
+fn show_timeline() -> Result<(), Error> {
+    let mut shared = None;
+    with_ui(|ui| {
+        shared = Some((ui.capture.clone(), ui.theme.clone()));
+        Ok(())
+    })?;
+    let (mut capture, theme) = shared.context("No capture")?;
+    let (first, last) = capture_span(&mut capture)?
+        .context("The capture has no packets")?;
+    let duration = ((last - first) as f64).max(1.0);
+    // Start by showing a millisecond, or all of a shorter capture.
+    let visible = duration.min(1e6);
+    let adjustment = gtk::Adjustment::new(
+        0.0, 0.0, duration, visible / 10.0, visible, visible);
+    let view = Rc::new(RefCell::new(TimelineView {
+        capture,
+        origin: first,
+        timeline: Timeline::default(),
+        halts: Halts::new(),
+        idle: IdlePeriods::new(),
+        otg: (Vec::new(), 0),
+        trace: None,
+    }));
+
+    let area = gtk::DrawingArea::builder()
+        .hexpand(true)
+        .vexpand(true)
+        .build();
+    let drawn_view = view.clone();
+    let saved_theme = theme.clone();
+    area.set_draw_func(move |_, context, width, height| {
+        let view = drawn_view.borrow();
+        draw_timeline(&view.timeline, view.origin, &theme.borrow(),
+                      context, width, height)
+    });
+    let relayout = {
+        let view = view.clone();
+        let area = area.clone();
+        move |adjustment: &gtk::Adjustment| {
+            let mut view = view.borrow_mut();
+            let view = &mut *view;
+            let start = view.origin + adjustment.value() as u64;
+            let end = start + adjustment.page_size() as u64;
+            let result = view.halts
+                .update(&mut view.capture)
+                .and_then(|_| view.idle.update(&mut view.capture))
+                .and_then(|_| {
+                    // OTG events are found afresh, so only when the
+                    // capture has grown.
+                    let packet_count = view.capture.packet_index.len();
+                    if view.otg.1 != packet_count {
+                        let events =
+                            otg::find_events(&mut view.capture, &view.idle)?;
+                        view.otg = (events, packet_count);
+                    }
+                    Timeline::new(&mut view.capture, &view.halts, start, end)
+                });
+            match result {
+                Ok(mut timeline) => {
+                    timeline.add_idle(&view.idle);
+                    timeline.add_otg(&view.otg.0);
+                    if let Some((trace, zero)) = &view.trace {
+                        timeline.add_trace(trace, *zero);
+                    }
+                    // Leave a lane's height for the times and markers.
+                    let lanes = (timeline.lanes.len() +
+                                 timeline.channels.len()) as f64 + 1.0;
+                    area.set_content_height(
+                        (lanes * TIMELINE_LANE_HEIGHT) as i32);
+                    view.timeline = timeline;
+                },
+                Err(error) => display_error(Err(error)),
+            }
+            area.queue_draw();
+        }
+    };
+    relayout(&adjustment);
+    adjustment.connect_value_changed(relayout.clone());
+    adjustment.connect_changed(relayout.clone());
+
+    // A logic analyzer trace is placed with its time zero at the first
+    // packet, plus an offset which can be adjusted to line them up.
+    let offset_spin = SpinButton::with_range(-1e6, 1e6, 0.001);
+    offset_spin.set_digits(6);
+    offset_spin.set_sensitive(false);
+    offset_spin.set_tooltip_text(Some(
+        "Time of the trace's zero after the first packet, in milliseconds"));
+    let import_button = gtk::Button::builder()
+        .label("Import logic trace…")
+        .tooltip_text("Show the channels of a VCD file or Saleae CSV export")
+        .build();
+    let offset_view = view.clone();
+    let offset_adjustment = adjustment.clone();
+    let offset_relayout = relayout.clone();
+    offset_spin.connect_value_changed(move |spin| {
+        {
+            let mut view = offset_view.borrow_mut();
+            let origin = view.origin;
+            if let Some((_, zero)) = view.trace.as_mut() {
+                let offset = (spin.value() * 1e6) as i64;
+                *zero = (origin as i64 + offset).max(0) as Timestamp;
+            }
+        }
+        offset_relayout(&offset_adjustment);
+    });
+    let import_view = view.clone();
+    let import_spin = offset_spin.clone();
+    let import_adjustment = adjustment.clone();
+    import_button.connect_clicked(move |_| {
+        let view = import_view.clone();
+        let spin = import_spin.clone();
+        let adjustment = import_adjustment.clone();
+        let relayout = relayout.clone();
+        display_error(choose_logic_trace(move |trace| {
+            let origin = view.borrow().origin;
+            view.borrow_mut().trace = Some((trace, origin));
+            spin.set_sensitive(true);
+            spin.set_value(0.0);
+            relayout(&adjustment);
+        }));
+    });
+
+    let click = gtk::GestureClick::new();
+    let clicked_view = view.clone();
+    let clicked_area = area.clone();
+    click.connect_released(move |_, _, x, y| {
+        let target = {
+            let view = clicked_view.borrow();
+            let timeline = &view.timeline;
+            let plot_width = clicked_area.width() as f64 - TIMELINE_LABEL_WIDTH;
+            if x < TIMELINE_LABEL_WIDTH || plot_width <= 0.0 {
+                return;
+            }
+            let scale = (timeline.end - timeline.start).max(1) as f64 /
+                plot_width;
+            let time = timeline.start +
+                ((x - TIMELINE_LABEL_WIDTH) * scale) as u64;
+            let lane = (y / TIMELINE_LANE_HEIGHT) as usize;
+            // Outside a transaction, a halt selects the request which
+            // cleared it, or its first STALL if it was not cleared, and
+            // an idle period selects the packet which ended it.
+            match (timeline.span_at(lane, time, (2.0 * scale) as u64),
+                   timeline.halt_at(lane, time),
+                   timeline.idle_at(time))
+            {
+                (Some(span), ..) =>
+                    Target::Transaction(span.transaction_id.value + 1),
+                (None, Some(halt), _) => Target::Transaction(halt.cleared
+                    .map_or(halt.first_stall, |(cleared_by, _)| cleared_by)
+                    .value + 1),
+                (None, None, Some(period)) =>
+                    Target::Packet(period.resumed_by.value + 1),
+                (None, None, None) => return,
+            }
+        };
+        display_error(with_ui(|ui| select_target(ui, target)));
+    });
+    area.add_controller(click);
+
+    let zoom_in = gtk::Button::builder()
+        .icon_name("zoom-in")
+        .tooltip_text("Zoom in")
+        .build();
+    let zoom_out = gtk::Button::builder()
+        .icon_name("zoom-out")
+        .tooltip_text("Zoom out")
+        .build();
+    let zoom_fit = gtk::Button::builder()
+        .icon_name("zoom-fit-best")
+        .tooltip_text("Show the whole capture")
+        .build();
+    let zoomed = adjustment.clone();
+    zoom_in.connect_clicked(move |_| zoom_timeline(&zoomed, 0.5));
+    let zoomed = adjustment.clone();
+    zoom_out.connect_clicked(move |_| zoom_timeline(&zoomed, 2.0));
+    let zoomed = adjustment.clone();
+    zoom_fit.connect_clicked(move |_| zoom_timeline(&zoomed, f64::INFINITY));
+    let save_button = gtk::Button::builder()
+        .icon_name("document-save")
+        .tooltip_text("Save the visible timeline as an image")
+        .build();
+    let saved_view = view.clone();
+    let saved_area = area.clone();
+    save_button.connect_clicked(move |_| {
+        let (timeline, origin) = {
+            let view = saved_view.borrow();
+            (view.timeline.clone(), view.origin)
+        };
+        let theme = saved_theme.clone();
+        display_error(choose_image_save(
+            "Save timeline",
+            saved_area.width(),
+            saved_area.height(),
+            move |context, width, height|
+                draw_timeline(&timeline, origin, &theme.borrow(),
+                              context, width, height)));
+    });
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&zoom_in);
+    header.append(&zoom_out);
+    header.append(&zoom_fit);
+    header.append(&save_button);
+    header.append(&Label::new(Some(
+        "Click on a transaction to select it in the traffic view, on a \
+         halt to select the request clearing it, or on a suspended or \
+         keep-alive period to select the packet ending it. Dashed lines \
+         show OTG events, such as role swaps")));
+    header.append(&import_button);
+    header.append(&Label::new(Some("Offset (ms):")));
+    header.append(&offset_spin);
+
+    let lane_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&area)
+        .build();
+    let scrollbar = gtk::Scrollbar::new(
+        Orientation::Horizontal, Some(&adjustment));
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&lane_window);
+    vbox.append(&scrollbar);
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Timeline"))
+            .default_width(800)
+            .default_height(400)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// The state of a frames window.
+struct FramesView {
+    capture: CaptureReader,
+    /// Time of the first packet, from which frame times are measured.
+    origin: Timestamp,
+    /// The first SOF group listed.
+    start: u64,
+    /// The transaction selected by each row of the list, if any.
+    targets: Vec<Option<u64>>,
+}
+
+/// List the traffic in each (micro)frame, as begun by SOF packets, from
+/// the frame of the selected item.
+fn show_frames() -> Result<(), Error> {
+    let mut shared = None;
+    with_ui(|ui| {
+        let packet_id = match selected_traffic_item(ui) {
+            Ok(item) => Some(ui.capture.item_first_packet(&item)?),
+            Err(_) => None,
+        };
+        shared = Some((ui.capture.clone(), packet_id));
+        Ok(())
+    })?;
+    let (mut capture, packet_id) = shared.context("No capture")?;
+    let (origin, _) = capture_span(&mut capture)?
+        .context("The capture has no packets")?;
+    let mut frames = Frames::new(&mut capture);
+    if frames.group_count()? == 0 {
+        bail!("The capture has no SOF packets");
+    }
+    let start = match packet_id {
+        Some(packet_id) => frames.group_at(packet_id)?.unwrap_or(0),
+        None => 0,
+    };
+    let view = Rc::new(RefCell::new(FramesView {
+        capture,
+        origin,
+        start,
+        targets: Vec::new(),
+    }));
+
+    let list = ListBox::new();
+    let position = Label::new(None);
+    let earlier = gtk::Button::with_label("Earlier");
+    let later = gtk::Button::with_label("Later");
+    let fill = {
+        let view = view.clone();
+        let list = list.clone();
+        let position = position.clone();
+        let earlier = earlier.clone();
+        let later = later.clone();
+        move || {
+            let mut view = view.borrow_mut();
+            let start = view.start;
+            let mut frames = Frames::new(&mut view.capture);
+            let result = frames.group_count().and_then(|count|
+                Ok((count, frames.frames(start, FRAME_PAGE_GROUPS)?)));
+            let (count, page) = match result {
+                Ok(result) => result,
+                Err(error) => return display_error(Err(error)),
+            };
+            while let Some(row) = list.first_child() {
+                list.remove(&row);
+            }
+            view.targets.clear();
+            let end = count.min(start + FRAME_PAGE_GROUPS);
+            position.set_text(&format!(
+                "SOF groups {} to {} of {}",
+                fmt_count(start + 1), fmt_count(end), fmt_count(count)));
+            earlier.set_sensitive(start > 0);
+            later.set_sensitive(end < count);
+            if let Err(error) = list_frames(&mut view, &list, &page) {
+                display_error(Err(error));
+            }
+        }
+    };
+    fill();
+    let paged = view.clone();
+    let refill = fill.clone();
+    earlier.connect_clicked(move |_| {
+        let start = paged.borrow().start;
+        paged.borrow_mut().start = start.saturating_sub(FRAME_PAGE_GROUPS);
+        refill();
+    });
+    let paged = view.clone();
+    later.connect_clicked(move |_| {
+        paged.borrow_mut().start += FRAME_PAGE_GROUPS;
+        fill();
+    });
+    let activated = view.clone();
+    list.connect_row_activated(move |_, row| {
+        let target = activated
+            .borrow()
+            .targets
+            .get(row.index() as usize)
+            .copied()
+            .flatten();
+        if let Some(transaction) = target {
+            display_error(with_ui(|ui| select_target(ui,
+                Target::Transaction(transaction + 1))));
+        }
+    });
+
+    let header = gtk::Box::builder()
+        .orientation(Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.append(&earlier);
+    header.append(&later);
+    header.append(&position);
+    let list_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&header);
+    vbox.append(&list_window);
+    vbox.append(&Label::new(Some(
+        "Activate a row to select its traffic in the traffic view")));
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(tr("Frames"))
+            .default_width(500)
+            .default_height(600)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        window.show();
+    });
+    Ok(())
+}
+
+/// Add rows to the frames window for a page of frames.
+///
+/// Frame numbers skipped between SOFs are shown as a gap, and runs of
+/// frames with no other traffic are shown as a single row.
+fn list_frames(view: &mut FramesView, list: &ListBox, page: &[Frame])
+    -> Result<(), Error>
+{
+    let add_row = |view: &mut FramesView,
+                       text: String,
+                       indent: i32,
+                       target: Option<u64>|
+    {
+        let label = Label::builder()
+            .label(text)
+            .xalign(0.0)
+            .margin_start(3 + indent)
+            .margin_end(3)
+            .build();
+        list.append(&label);
+        view.targets.push(target);
+    };
+    let mut index = 0;
+    while index < page.len() {
+        let frame = &page[index];
+        if let Some((first, last)) = frame.missing() {
+            let text = if first == last {
+                format!("Frame {first} missing")
+            } else {
+                format!("Frames {first} to {last} missing")
+            };
+            add_row(view, text, 0, None);
+        }
+        let time = (frame.time - view.origin) as f64 / 1e9;
+        if frame.transactions.is_empty() {
+            // Gather the following frames with no traffic or gap before.
+            let mut end = index + 1;
+            while end < page.len() &&
+                page[end].missed == 0 &&
+                page[end].transactions.is_empty()
+            {
+                end += 1;
+            }
+            let last = &page[end - 1];
+            let text = if end - index == 1 {
+                format!("Frame {} at {time:.9} s", frame.number)
+            } else {
+                format!("Frames {} to {} at {time:.9} s",
+                        frame.number, last.number)
+            };
+            add_row(view, text, 0, Some(frame.transaction.value));
+            index = end;
+        } else {
+            add_row(view, format!("Frame {} at {time:.9} s", frame.number), 0,
+                    Some(frame.transaction.value));
+            list_transactions(view, &add_row, frame)?;
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Add a row to the frames window for each transaction in a frame.
+fn list_transactions<F>(view: &mut FramesView, add_row: &F, frame: &Frame)
+    -> Result<(), Error>
+    where F: Fn(&mut FramesView, String, i32, Option<u64>)
+{
+    for transaction_id in &frame.transactions {
+        let text = Frames::new(&mut view.capture).describe(*transaction_id)?;
+        add_row(view, text, 18, Some(transaction_id.value));
+    }
+    Ok(())
+}
+
+/// Change the time shown in the timeline by a factor, about its centre.
+fn zoom_timeline(adjustment: &gtk::Adjustment, factor: f64) {
+    let range = adjustment.upper() - adjustment.lower();
+    let page = (adjustment.page_size() * factor).max(1000.0).min(range);
+    let centre = adjustment.value() + adjustment.page_size() / 2.0;
+    let value = (centre - page / 2.0)
+        .max(adjustment.lower())
+        .min(adjustment.upper() - page);
+    adjustment.configure(value, adjustment.lower(), adjustment.upper(),
+                         page / 10.0, page, page);
+}
+
+/// Draw each lane of a timeline, with its transactions colored by outcome
+/// and its halts shaded, the channels of any logic analyzer trace, the
+/// periods when the bus was suspended or kept alive shaded across the
+/// lanes, and a line across the lanes at each marker.
+fn draw_timeline(timeline: &Timeline,
+                 origin: Timestamp,
+                 theme: &Theme,
+                 context: &gtk::cairo::Context,
+                 width: i32,
+                 height: i32)
+{
+    let (width, height) = (width as f64, height as f64);
+    let dark = dark_theme();
+    let text = theme.color(Element::PlotText, dark);
+    set_source_color(context, theme.color(Element::PlotBackground, dark));
+    let _ = context.paint();
+    let plot_width = width - TIMELINE_LABEL_WIDTH;
+    if plot_width <= 0.0 {
+        return;
+    }
+    let scale = plot_width / (timeline.end - timeline.start).max(1) as f64;
+    context.set_font_size(11.0);
+    for (index, lane) in timeline.lanes.iter().enumerate() {
+        let top = index as f64 * TIMELINE_LANE_HEIGHT;
+        if index % 2 == 1 {
+            set_source_color(context, theme.color(Element::PlotStripe, dark));
+            context.rectangle(0.0, top, width, TIMELINE_LANE_HEIGHT);
+            let _ = context.fill();
+        }
+        set_source_color(context, text);
+        context.move_to(4.0, top + 15.0);
+        let _ = context.show_text(&lane.name);
+        if lane.truncated {
+            context.move_to(width - 12.0, top + 15.0);
+            let _ = context.show_text("…");
+        }
+        let mut halt_labels = Vec::new();
+        for halt in &lane.halts {
+            let end = halt.cleared.map_or(timeline.end, |(_, end)| end);
+            let start = halt.start.max(timeline.start) - timeline.start;
+            let end = end.min(timeline.end).max(timeline.start) -
+                timeline.start;
+            let x = TIMELINE_LABEL_WIDTH + start as f64 * scale;
+            let halt_width = ((end - start) as f64 * scale).max(1.0);
+            context.set_source_rgba(0.85, 0.2, 0.2, 0.25);
+            context.rectangle(x, top, halt_width, TIMELINE_LANE_HEIGHT);
+            let _ = context.fill();
+            halt_labels.push((x, halt_width));
+        }
+        for span in &lane.spans {
+            use PID::*;
+            let (red, green, blue) = match span.outcome {
+                ACK => (0.3, 0.7, 0.3),
+                NAK | NYET => (0.9, 0.7, 0.2),
+                STALL | ERR | Malformed => (0.85, 0.2, 0.2),
+                SOF => (0.4, 0.5, 0.8),
+                _ => (0.5, 0.5, 0.5),
+            };
+            let start = span.start.max(timeline.start) - timeline.start;
+            let end = span.end.max(timeline.start) - timeline.start;
+            let x = TIMELINE_LABEL_WIDTH + start as f64 * scale;
+            let span_width = ((end - start) as f64 * scale).max(1.0);
+            context.set_source_rgb(red, green, blue);
+            context.rectangle(x, top + 4.0, span_width,
+                              TIMELINE_LANE_HEIGHT - 8.0);
+            let _ = context.fill();
+        }
+        // Label each halt wide enough to hold it.
+        set_source_color(context, text);
+        for (x, halt_width) in halt_labels {
+            if let Ok(extents) = context.text_extents("Halted") {
+                if extents.width() + 8.0 <= halt_width {
+                    context.move_to(x + 4.0, top + 15.0);
+                    let _ = context.show_text("Halted");
+                }
+            }
+        }
+    }
+    // Draw each channel of a logic analyzer trace as a waveform.
+    for (index, channel) in timeline.channels.iter().enumerate() {
+        let lane = timeline.lanes.len() + index;
+        let top = lane as f64 * TIMELINE_LANE_HEIGHT;
+        set_source_color(context, text);
+        context.move_to(4.0, top + 15.0);
+        let _ = context.show_text(&channel.name);
+        let y = |level: bool| if level {
+            top + 5.0
+        } else {
+            top + TIMELINE_LANE_HEIGHT - 5.0
+        };
+        context.set_source_rgb(0.2, 0.6, 0.6);
+        context.set_line_width(1.5);
+        context.move_to(TIMELINE_LABEL_WIDTH, y(channel.initial));
+        for &(time, level) in &channel.edges {
+            let x = TIMELINE_LABEL_WIDTH +
+                (time - timeline.start) as f64 * scale;
+            context.line_to(x, y(!level));
+            context.line_to(x, y(level));
+        }
+        let last = channel.edges.last().map_or(channel.initial, |edge| edge.1);
+        context.line_to(width, y(last));
+        let _ = context.stroke();
+    }
+    let lane_count = timeline.lanes.len() + timeline.channels.len();
+    let lanes_height = lane_count as f64 * TIMELINE_LANE_HEIGHT;
+    // Shade each idle period across the lanes, labeled at the top with its
+    // description, or just its kind, if wide enough to hold either.
+    for period in &timeline.idle {
+        let start = period.start.max(timeline.start) - timeline.start;
+        let end = period.end.min(timeline.end).max(timeline.start) -
+            timeline.start;
+        let x = TIMELINE_LABEL_WIDTH + start as f64 * scale;
+        let idle_width = ((end - start) as f64 * scale).max(1.0);
+        match period.kind {
+            IdleKind::Suspended => context.set_source_rgba(0.5, 0.5, 0.5, 0.3),
+            IdleKind::KeepAlive => context.set_source_rgba(0.3, 0.6, 0.9, 0.15),
+        }
+        context.rectangle(x, 0.0, idle_width, lanes_height);
+        let _ = context.fill();
+        let labels = [period.description(), period.kind.label().to_string()];
+        let fitting = labels.iter().find(|label|
+            matches!(context.text_extents(label),
+                     Ok(extents) if extents.width() + 8.0 <= idle_width));
+        if let Some(label) = fitting {
+            set_source_color(context, text);
+            context.move_to(x + 4.0, 15.0);
+            let _ = context.show_text(label);
+        }
+    }
+    // Draw each OTG event as a dashed line across the lanes, labeled at
+    // the top below any idle period's label.
+    for event in &timeline.otg {
+        let x = TIMELINE_LABEL_WIDTH +
+            (event.time - timeline.start) as f64 * scale;
+        match event.kind {
+            OtgEventKind::RoleSwap => context.set_source_rgb(0.9, 0.5, 0.1),
+            _ => context.set_source_rgb(0.7, 0.6, 0.2),
+        }
+        context.set_line_width(2.0);
+        context.set_dash(&[4.0, 3.0], 0.0);
+        context.move_to(x, 0.0);
+        context.line_to(x, lanes_height);
+        let _ = context.stroke();
+        context.set_dash(&[], 0.0);
+        context.move_to(x + 3.0, 28.0);
+        let _ = context.show_text(event.kind.label());
+    }
+    // Draw each marker, labeled below the lanes.
+    for marker in &timeline.markers {
+        let x = TIMELINE_LABEL_WIDTH +
+            (marker.time - timeline.start) as f64 * scale;
+        context.set_source_rgb(0.6, 0.3, 0.8);
+        context.set_line_width(2.0);
+        context.move_to(x, 0.0);
+        context.line_to(x, lanes_height + 4.0);
+        let _ = context.stroke();
+        context.move_to(x + 3.0, lanes_height + 14.0);
+        let _ = context.show_text(&marker.label);
+    }
+    // Mark the times at each end of the window, from the capture start.
+    let bottom = height - 6.0;
+    let start_time = format!("{:.9} s",
+        (timeline.start - origin) as f64 / 1e9);
+    let end_time = format!("{:.9} s",
+        (timeline.end - origin) as f64 / 1e9);
+    set_source_color(context, text);
+    context.move_to(TIMELINE_LABEL_WIDTH, bottom);
+    let _ = context.show_text(&start_time);
+    if let Ok(extents) = context.text_extents(&end_time) {
+        context.move_to(width - extents.width() - 4.0, bottom);
+        let _ = context.show_text(&end_time);
+    }
+}
+
+/// Choose a logic analyzer trace to import, passing it on once read.
+fn choose_logic_trace<F>(imported: F) -> Result<(), Error>
+    where F: Fn(LogicTrace) + 'static
+{
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Import logic analyzer trace"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Open,
+            &[("Import", gtk::ResponseType::Accept)]
+        )
+    });
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("VCD files or Saleae CSV exports"));
+    filter.add_pattern("*.vcd");
+    filter.add_pattern("*.csv");
+    chooser.add_filter(&filter);
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                match LogicTrace::load(&path) {
+                    Ok(trace) => imported(trace),
+                    Err(error) => display_error(Err(error)),
+                }
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Direction of data chosen in the follow stream window, if only one.
+fn stream_direction(choice: u32) -> Option<Direction> {
+    match choice {
+        1 => Some(Direction::In),
+        2 => Some(Direction::Out),
+        _ => None,
+    }
+}
+
+/// A button for checking sequence numbers in the data of a followed
+/// stream, in the direction chosen.
+fn sequence_button(stream: Rc<FollowedStream>, direction_dropdown: &DropDown)
+    -> MenuButton
+{
+    let offset_spin = SpinButton::with_range(0.0, 65535.0, 1.0);
+    let width_dropdown = DropDown::from_strings(
+        &["1 byte", "2 bytes", "4 bytes", "8 bytes"]);
+    width_dropdown.set_selected(1);
+    let order_dropdown = DropDown::from_strings(
+        &["Little endian", "Big endian"]);
+    let check_button = Button::builder()
+        .label("Check")
+        .build();
+    let result_view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .build();
+    let result_window = gtk::ScrolledWindow::builder()
+        .min_content_height(200)
+        .min_content_width(360)
+        .child(&result_view)
+        .build();
+    let grid = gtk::Grid::builder()
+        .row_spacing(4)
+        .column_spacing(4)
+        .build();
+    let labels = ["Offset in bytes:", "Width:", "Byte order:"];
+    for (row, text) in labels.into_iter().enumerate() {
+        let label = Label::builder()
+            .label(text)
+            .halign(Align::Start)
+            .build();
+        grid.attach(&label, 0, row as i32, 1, 1);
+    }
+    grid.attach(&offset_spin, 1, 0, 1, 1);
+    grid.attach(&width_dropdown, 1, 1, 1, 1);
+    grid.attach(&order_dropdown, 1, 2, 1, 1);
+    grid.attach(&check_button, 0, 3, 2, 1);
+    grid.attach(&result_window, 0, 4, 2, 1);
+    let popover = gtk::Popover::builder()
+        .child(&grid)
+        .build();
+    let direction_dropdown = direction_dropdown.clone();
+    check_button.connect_clicked(move |_| {
+        let width = 1 << width_dropdown.selected();
+        let order = match order_dropdown.selected() {
+            0 => ByteOrder::Little,
+            _ => ByteOrder::Big,
+        };
+        let offset = offset_spin.value_as_int() as usize;
+        let text = match FieldLocation::new(offset, width, order) {
+            Ok(location) => {
+                let direction = stream_direction(direction_dropdown.selected());
+                let check = SequenceCheck::new(
+                    stream.chunks(direction), &location);
+                let mut text = check.summary();
+                for finding in check.findings
+                    .iter()
+                    .take(SEQUENCE_DISPLAY_LIMIT)
+                {
+                    text.push('\n');
+                    text.push_str(&finding.description());
+                }
+                if check.findings.len() > SEQUENCE_DISPLAY_LIMIT {
+                    text.push_str("\n… further breaks not shown");
+                }
+                text
+            },
+            Err(e) => e.to_string(),
+        };
+        result_view.buffer().set_text(&text);
+    });
+    MenuButton::builder()
+        .icon_name("edit-find")
+        .tooltip_text("Check sequence numbers in the data")
+        .popover(&popover)
+        .build()
+}
+
+fn choose_stream_save(stream: Rc<FollowedStream>,
+                      direction: Option<Direction>)
+    -> Result<(), Error>
+{
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Save stream data to file"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Save", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(stream.save(&path, direction));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+fn choose_export() -> Result<(), Error> {
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some("Export selection to pcap file"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Export", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.add_choice("scope", "Export", &[
+        ("item", "Selected traffic item"),
+        ("reference", "All traffic from time reference to selected item"),
+        ("device", "Selected device"),
+        ("split", "Each device, to separate files"),
+    ]);
+    chooser.set_choice("scope", "item");
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(file) = dialog.file() {
+                if let Some(path) = file.path() {
+                    let scope = dialog.choice("scope");
+                    display_error(match scope.as_deref() {
+                        Some("split") =>
+                            start_pcap(FileAction::Split, vec![path]),
+                        other => choose_export_scope(other)
+                            .and_then(|()|
+                                start_pcap(FileAction::Export, vec![path])),
+                    });
+                }
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Range of times from the first to the last packet of a traffic item.
+fn item_time_range(capture: &mut CaptureReader, item: &TrafficItem)
+    -> Result<Range<Timestamp>, Error>
+{
+    let packet_ids = capture.export_packets(&ExportScope::Items(vec![*item]))?;
+    let (first, last) = match (packet_ids.first(), packet_ids.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => bail!("Selected item has no packets"),
+    };
+    Ok(capture.packet_time(first)?..capture.packet_time(last)? + 1)
+}
+
+/// Export the data carried on the selected traffic item's endpoint.
+fn choose_endpoint_export() -> Result<(), Error> {
+    let mut selection = None;
+    with_ui(|ui| {
+        let item = selected_traffic_item(ui)?;
+        let endpoint_id = ui.capture.item_endpoint_id(&item)?;
+        if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+            bail!("The selected item is not on a device endpoint")
+        }
+        let endpoint = ui.capture.endpoints.get(endpoint_id)?;
+        let data = EndpointData {
+            device: endpoint.device_address(),
+            number: endpoint.number(),
+            direction: None,
+            time_range: None,
+        };
+        selection = Some((item, data));
+        Ok(())
+    })?;
+    let (item, data) = selection.context("No endpoint selected")?;
+    let title = format!("Export data on endpoint {}.{} to file",
+                        data.device, data.number);
+    let chooser = WINDOW.with(|cell| {
+        gtk::FileChooserDialog::new(
+            Some(title.as_str()),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Export", gtk::ResponseType::Accept)]
+        )
+    });
+    chooser.add_choice("direction", "Direction", &[
+        ("both", "Both directions"),
+        ("in", "IN only"),
+        ("out", "OUT only"),
+    ]);
+    chooser.set_choice("direction", "both");
+    chooser.add_choice("range", "Time range", &[
+        ("all", "Whole capture"),
+        ("item", "During selected item"),
+        ("reference", "Between time reference and selected item"),
+    ]);
+    chooser.set_choice("range", "all");
+    chooser.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                let direction = dialog.choice("direction");
+                let range = dialog.choice("range");
+                display_error(export_endpoint_data(
+                    &path, data.clone(), &item,
+                    direction.as_deref(), range.as_deref()));
+            }
+            dialog.destroy();
+        }
+    });
+    chooser.show();
+    Ok(())
+}
+
+/// Let the user choose a class to decode the selected item's endpoint as,
+/// then analyze the capture again.
+fn choose_endpoint_class() -> Result<(), Error> {
+    let mut selection = None;
+    with_ui(|ui| {
+        if !ui.reanalyze_button.is_sensitive() {
+            bail!("The capture cannot be analyzed again while it is busy")
+        }
+        let item = selected_traffic_item(ui)?;
+        let endpoint_id = ui.capture.item_endpoint_id(&item)?;
+        if endpoint_id == INVALID_EP_ID || endpoint_id == FRAMING_EP_ID {
+            bail!("The selected item is not on a device endpoint")
+        }
+        let endpoint = ui.capture.endpoints.get(endpoint_id)?;
+        if endpoint.number() == EndpointNum(0) {
+            bail!("Endpoint 0 is always decoded as a control endpoint")
+        }
+        let device = endpoint.device_address();
+        let address = endpoint.address();
+        let current = ui.overrides.endpoint(device, address).copied();
+        selection = Some((device, address, current));
+        Ok(())
+    })?;
+    let (device, address, current) =
+        selection.context("No endpoint selected")?;
+
+    let mut names = vec!["Automatic, from descriptors"];
+    names.extend(EndpointClass::ALL.iter().map(|class| class.description()));
+    let class_dropdown = gtk::DropDown::from_strings(&names);
+    let size_spin = SpinButton::with_range(0.0, 1024.0, 1.0);
+    if let Some(ovr) = current {
+        let position = EndpointClass::ALL
+            .iter()
+            .position(|class| *class == ovr.class)
+            .unwrap_or(0);
+        class_dropdown.set_selected(position as u32 + 1);
+        size_spin.set_value(ovr.max_packet_size.unwrap_or(0) as f64);
+    }
+    let class_label = Label::builder()
+        .label("Class:")
+        .halign(Align::Start)
+        .build();
+    let size_label = Label::builder()
+        .label("Maximum packet size (0 if unknown):")
+        .halign(Align::Start)
+        .build();
+    let apply_button = Button::with_label("Analyze again");
+    let grid = gtk::Grid::builder()
+        .row_spacing(4)
+        .column_spacing(4)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    grid.attach(&class_label, 0, 0, 1, 1);
+    grid.attach(&class_dropdown, 1, 0, 1, 1);
+    grid.attach(&size_label, 0, 1, 1, 1);
+    grid.attach(&size_spin, 1, 1, 1, 1);
+    grid.attach(&apply_button, 1, 2, 1, 1);
+
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(format!("Decode endpoint 0x{:02X} of device {device} as",
+                           address.0))
+            .modal(true)
+            .child(&grid)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        let dialog = window.clone();
+        apply_button.connect_clicked(move |_| {
+            let class = match class_dropdown.selected() {
+                0 => None,
+                n => EndpointClass::ALL.get(n as usize - 1).copied(),
+            };
+            let max_packet_size = match size_spin.value_as_int() {
+                0 => None,
+                size => Some(size as usize),
+            };
+            dialog.destroy();
+            display_error(with_ui(|ui| {
+                ui.overrides.set_endpoint(device, address,
+                    class.map(|class| (class, max_packet_size)));
+                Ok(())
+            }).and_then(|()| start_pcap(FileAction::Reanalyze, Vec::new())));
+        });
+        window.show();
+    });
+    Ok(())
+}
+
+/// Read the descriptors of the selected device from this computer, if it is
+/// attached here, then analyze the capture again using them.
+fn choose_host_device() -> Result<(), Error> {
+    let mut selection = None;
+    with_ui(|ui| {
+        if !ui.reanalyze_button.is_sensitive() {
+            bail!("The capture cannot be analyzed again while it is busy")
+        }
+        let device_id = selected_device_id(ui)?;
+        let address = ui.capture.devices.get(device_id)?.address;
+        if address == DeviceAddr(0) {
+            bail!("Devices at address 0 cannot be identified")
+        }
+        selection = Some(address);
+        Ok(())
+    })?;
+    let address = selection.context("No device selected")?;
+    let mut candidates = HostDevice::with_address(address)?;
+    if candidates.is_empty() {
+        bail!("No device with address {address} is attached to this computer")
+    }
+    if candidates.len() == 1 {
+        return apply_host_descriptors(&candidates.remove(0));
+    }
+
+    // Devices on different buses may have the same address.
+    let names: Vec<String> = candidates
+        .iter()
+        .map(HostDevice::description)
+        .collect();
+    let name_refs: Vec<&str> = names.iter().map(String::as_str).collect();
+    let device_dropdown = gtk::DropDown::from_strings(&name_refs);
+    let fetch_button = Button::with_label("Fetch descriptors");
+    let vbox = gtk::Box::builder()
+        .orientation(Orientation::Vertical)
+        .spacing(6)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(6)
+        .margin_end(6)
+        .build();
+    vbox.append(&device_dropdown);
+    vbox.append(&fetch_button);
+    WINDOW.with(|win_opt| {
+        let window = gtk::Window::builder()
+            .title(format!("Choose the attached device at address {address}"))
+            .modal(true)
+            .child(&vbox)
+            .build();
+        window.set_transient_for(win_opt.borrow().as_ref());
+        let dialog = window.clone();
+        fetch_button.connect_clicked(move |_| {
+            dialog.destroy();
+            if let Some(device) =
+                candidates.get(device_dropdown.selected() as usize)
+            {
+                display_error(apply_host_descriptors(device));
+            }
+        });
+        window.show();
+    });
+    Ok(())
 }
 
-fn choose_file(action: FileAction) -> Result<(), Error> {
-    use FileAction::*;
+/// Use the descriptors of a device attached to this computer, and analyze
+/// the capture again.
+fn apply_host_descriptors(device: &HostDevice) -> Result<(), Error> {
+    let known = device.fetch_descriptors()?;
+    with_ui(|ui| {
+        ui.overrides.set_descriptors(known);
+        Ok(())
+    })?;
+    start_pcap(FileAction::Reanalyze, Vec::new())
+}
+
+/// Offer to save the selected device's descriptors as source code.
+fn choose_descriptor_export(language: Option<&gtk::glib::Variant>)
+    -> Result<(), Error>
+{
+    let language = language
+        .and_then(|language| language.str())
+        .and_then(Language::from_name)
+        .context("Unknown language")?;
+    let mut source = None;
+    with_ui(|ui| {
+        let device_id = selected_device_id(ui)?;
+        source = Some(descriptor_source(&mut ui.capture, device_id, language)?);
+        Ok(())
+    })?;
+    let source = source.context("No descriptors generated")?;
     let chooser = WINDOW.with(|cell| {
-        let borrow = cell.borrow();
-        let window = borrow.as_ref();
-        match action {
-            Load => gtk::FileChooserDialog::new(
-                Some("Open pcap file"),
-                window,
-                gtk::FileChooserAction::Open,
-                &[("Open", gtk::ResponseType::Accept)]
-            ),
-            Save => gtk::FileChooserDialog::new(
-                Some("Save pcap file"),
-                window,
-                gtk::FileChooserAction::Save,
-                &[("Save", gtk::ResponseType::Accept)]
-            ),
-        }
+        gtk::FileChooserDialog::new(
+            Some("Export descriptors as source code"),
+            cell.borrow().as_ref(),
+            gtk::FileChooserAction::Save,
+            &[("Export", gtk::ResponseType::Accept)]
+        )
     });
+    chooser.set_current_name(
+        &format!("descriptors.{}", language.extension()));
     chooser.connect_response(move |dialog, response| {
         if response == gtk::ResponseType::Accept {
-            if let Some(file) = dialog.file() {
-                if let Some(path) = file.path() {
-                    display_error(start_pcap(action, path));
-                }
+            if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                display_error(std::fs::write(&path, &source)
+                    .with_context(|| format!(
+                        "Failed to write {}", path.display())));
             }
             dialog.destroy();
         }
@@ -704,104 +8277,419 @@ fn choose_file(action: FileAction) -> Result<(), Error> {
     Ok(())
 }
 
-fn start_pcap(action: FileAction, path: PathBuf) -> Result<(), Error> {
+/// Write the data on an endpoint to a file, with the chosen options.
+fn export_endpoint_data(path: &Path,
+                        mut data: EndpointData,
+                        item: &TrafficItem,
+                        direction: Option<&str>,
+                        range: Option<&str>)
+    -> Result<(), Error>
+{
+    data.direction = match direction {
+        Some("in") => Some(Direction::In),
+        Some("out") => Some(Direction::Out),
+        _ => None,
+    };
+    let file = File::create(path).with_context(|| format!(
+        "Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    with_ui(|ui| {
+        data.time_range = match range {
+            Some("item") => Some(item_time_range(&mut ui.capture, item)?),
+            Some("reference") => {
+                let reference = ui.time_reference
+                    .context("No time reference is set")?;
+                let item_range = item_time_range(&mut ui.capture, item)?;
+                Some(min(reference, item_range.start)..
+                     max(reference + 1, item_range.end))
+            },
+            _ => None,
+        };
+        export::write_endpoint_data(&mut ui.capture, &data, &mut writer)?;
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Choose the packets to export, ready for the export to be started.
+fn choose_export_scope(choice: Option<&str>) -> Result<(), Error> {
+    with_ui(|ui| {
+        ui.export_scope = Some(match choice {
+            Some("item") =>
+                ExportScope::Items(vec![selected_traffic_item(ui)?]),
+            Some("reference") => {
+                let reference = ui.time_reference
+                    .context("No time reference is set")?;
+                let item = selected_traffic_item(ui)?;
+                let range = item_time_range(&mut ui.capture, &item)?;
+                ExportScope::TimeRange(min(reference, range.start),
+                                       max(reference + 1, range.end))
+            },
+            Some("device") => ExportScope::Device(selected_device_id(ui)?),
+            other => bail!("Unknown export scope {other:?}"),
+        });
+        Ok(())
+    })
+}
+
+fn visible_traffic_items(ui: &UserInterface)
+    -> Result<Vec<TrafficItem>, Error>
+{
+    let model = ui.traffic_model
+        .as_ref()
+        .context("No traffic model")?;
+    let mut items = Vec::with_capacity(model.n_items() as usize);
+    for position in 0..model.n_items() {
+        let row = model
+            .item(position)
+            .context("Traffic model has no item at position")?
+            .downcast::<TrafficRowData>()
+            .or_else(|_| bail!("Item is not TrafficRowData"))?;
+        // Rows which failed to load are skipped.
+        if let Ok(node) = row.node() {
+            items.push(node.borrow().item);
+        }
+    }
+    Ok(items)
+}
+
+fn selected_device_id(ui: &UserInterface) -> Result<DeviceId, Error> {
+    ui.device_selection
+        .as_ref()
+        .and_then(|selection| selection.selected_item())
+        .and_then(|object| object.downcast::<DeviceRowData>().ok())
+        .and_then(|row| row.node().ok())
+        .map(|node| node.borrow().item.device_id())
+        .context("No device selected")
+}
+
+fn selected_traffic_node(ui: &UserInterface)
+    -> Option<ItemNodeRc<TrafficItem>>
+{
+    ui.traffic_selection
+        .as_ref()
+        .and_then(|selection| selection.selected_item())
+        .and_then(|object| object.downcast::<TrafficRowData>().ok())
+        .and_then(|row| row.node().ok())
+}
+
+fn selected_traffic_item(ui: &UserInterface) -> Result<TrafficItem, Error> {
+    selected_traffic_node(ui)
+        .map(|node| node.borrow().item)
+        .context("No traffic item selected")
+}
+
+fn start_pcap(action: FileAction, paths: Vec<PathBuf>) -> Result<(), Error> {
     use FileAction::*;
-    let writer = if action == Load {
-        Some(reset_capture()?)
-    } else {
-        None
+    let path = match paths.first() {
+        Some(path) => path.clone(),
+        None if action == Reanalyze => PathBuf::new(),
+        None => bail!("No files chosen"),
+    };
+    // Packets to be analyzed again are read from the current capture, which
+    // remains readable after it is replaced.
+    let mut previous = None;
+    if action == Reanalyze {
+        with_ui(|ui| {
+            previous = Some((ui.capture.clone(),
+                             ui.file_paths.clone(),
+                             ui.overrides.clone()));
+            Ok(())
+        })?;
+    }
+    let writer = match action {
+        Load | Merge => Some(reset_capture()?),
+        Reanalyze => Some(replace_capture()?),
+        _ => None,
     };
     with_ui(|ui| {
+        let visible_items = match action {
+            Save(SaveFormat::Csv) => visible_traffic_items(ui)?,
+            _ => Vec::new(),
+        };
+        let export_scope = match action {
+            Export => Some(ui.export_scope
+                .take()
+                .context("No export scope chosen")?),
+            _ => None,
+        };
         #[cfg(feature="record-ui-test")]
         ui.recording.borrow_mut().log_open_file(&path, &ui.capture);
-        ui.file_name = path
-            .file_name()
-            .map(|path| path.to_string_lossy().to_string());
+        let file_names: Vec<String> = paths
+            .iter()
+            .map(|path| path
+                .file_name()
+                .map_or_else(
+                    || path.to_string_lossy().to_string(),
+                    |name| name.to_string_lossy().to_string()))
+            .collect();
+        match (action, &previous) {
+            (Reanalyze, Some((_, file_paths, _))) => {
+                ui.file_paths = file_paths.clone();
+            },
+            (Load | Merge, _) => {
+                ui.file_name = Some(file_names.join(" + "));
+                ui.file_paths = paths.clone();
+            },
+            _ => {
+                ui.file_name = Some(file_names.join(" + "));
+            }
+        }
+        if action == Merge {
+            ui.capture.shared.sources.store(Arc::new(file_names));
+        }
         ui.open_button.set_sensitive(false);
+        ui.merge_button.set_sensitive(false);
+        ui.reanalyze_button.set_sensitive(false);
         ui.save_button.set_sensitive(false);
+        ui.export_button.set_sensitive(false);
         ui.scan_button.set_sensitive(false);
         ui.selector.set_sensitive(false);
         ui.capture_button.set_sensitive(false);
         ui.stop_button.set_sensitive(true);
         let signal_id = ui.stop_button.connect_clicked(|_|
             display_error(stop_pcap()));
-        ui.vbox.insert_child_after(&ui.separator, Some(&ui.paned));
+        ui.vbox.insert_child_after(&ui.separator, Some(&ui.panes_box));
         ui.vbox.insert_child_after(&ui.progress_bar, Some(&ui.separator));
         ui.show_progress = Some(action);
         let mut capture = ui.capture.clone();
+        let filter = ui.filter.clone();
+        let opened_path = path.clone();
+        let align_clocks = std::mem::take(&mut ui.align_clocks);
         let worker = move || match action {
-            Load => {
-                let file = File::open(path)?;
-                let file_size = file.metadata()?.len();
-                TOTAL.store(file_size, Ordering::Relaxed);
-                let reader = BufReader::new(file);
-                let mut pcap = PcapReader::new(reader)?;
-                let mut bytes_read = size_of::<PcapHeader>() as u64;
-                let mut decoder = Decoder::new(writer.unwrap())?;
+            Load | Merge => {
+                TOTAL.store(0, Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let mut readers = Vec::with_capacity(paths.len());
+                for path in &paths {
+                    let file = File::open(path).with_context(|| format!(
+                        "Failed to open {}", path.display()))?;
+                    TOTAL.fetch_add(file.metadata()?.len(), Ordering::Relaxed);
+                    readers.push(pcap::decompress(
+                        CountingReader::new(file, &CURRENT))?);
+                }
+                let clocks = if align_clocks && action == Merge {
+                    crate::loader::estimate_clocks(&paths)?
+                } else {
+                    Vec::new()
+                };
                 #[cfg(feature="step-decoder")]
                 let (mut client, _addr) =
                     TcpListener::bind("127.0.0.1:46563")?.accept()?;
-                while let Some(result) = pcap.next_raw_packet() {
-                    #[cfg(feature="step-decoder")] {
-                        let mut buf = [0; 1];
-                        client.read(&mut buf).unwrap();
+                // Each packet is decoded while holding the update lock,
+                // which is released between packets.
+                #[cfg(feature="record-ui-test")]
+                let mut guard = None;
+                let (writer, verification) = crate::loader::decode_readers(
+                    &paths, readers, writer.unwrap(), &mut capture,
+                    &Overrides::default(), &clocks, |_count| {
+                        #[cfg(feature="record-ui-test")]
+                        drop(guard.take());
+                        #[cfg(feature="step-decoder")] {
+                            let mut buf = [0; 1];
+                            client.read(&mut buf).unwrap();
+                        };
+                        #[cfg(feature="record-ui-test")] {
+                            guard = Some(UPDATE_LOCK.lock());
+                        };
+                        !STOP.load(Ordering::Relaxed)
+                    })?;
+                #[cfg(feature="record-ui-test")]
+                drop(guard);
+                writer.print_storage_summary();
+                if verification.is_some() {
+                    gtk::glib::idle_add_once(move || {
+                        display_error(with_ui(|ui| {
+                            ui.verification = verification;
+                            Ok(())
+                        }));
+                        display_error(update_view());
+                    });
+                }
+                Ok(())
+            },
+            Reanalyze => {
+                let (mut previous, _, overrides) =
+                    previous.context("No capture to analyze")?;
+                TOTAL.store(previous.packet_index.len(), Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let writer = crate::loader::redecode(
+                    &mut previous, writer.unwrap(), &overrides, |count| {
+                        CURRENT.store(count, Ordering::Relaxed);
+                        !STOP.load(Ordering::Relaxed)
+                    })?;
+                writer.print_storage_summary();
+                Ok(())
+            },
+            Save(SaveFormat::Native) => {
+                TOTAL.store(native::saved_size(&capture), Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let mut writer = CountingWriter::new(
+                    CaptureFileWriter::create(&path)?, &CURRENT);
+                let key = signing_key()?;
+                native::save_signed(&mut capture, &mut writer, key.as_deref())?;
+                writer.into_inner().finish()?;
+                Ok(())
+            },
+            Save(SaveFormat::JsonLines) => {
+                let item_count = capture.item_index.len();
+                TOTAL.store(item_count, Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let mut writer = CaptureFileWriter::create(&path)?;
+                export::write_json_devices(&mut capture, &mut writer)?;
+                for i in 0..item_count {
+                    let item: TrafficItem = capture.item(None, i)?;
+                    let selected = match &filter {
+                        Some(filter) => filter.matches(&mut capture, &item)?,
+                        None => true,
                     };
-                    let packet = result?;
-                    #[cfg(feature="record-ui-test")]
-                    let guard = UPDATE_LOCK.lock();
-                    decoder.handle_raw_packet(&packet.data)?;
-                    #[cfg(feature="record-ui-test")]
-                    drop(guard);
-                    let size = 16 + packet.data.len();
-                    bytes_read += size as u64;
-                    CURRENT.store(bytes_read, Ordering::Relaxed);
+                    if selected {
+                        let item_id = TrafficItemId::from(i);
+                        export::write_json_transfer(
+                            &mut capture, item_id, &mut writer)?;
+                    }
+                    CURRENT.store(i + 1, Ordering::Relaxed);
                     if STOP.load(Ordering::Relaxed) {
                         break;
                     }
                 }
-                let writer = decoder.finish()?;
-                writer.print_storage_summary();
+                writer.finish()?;
                 Ok(())
             },
-            Save => {
-                let packet_count = capture.packet_index.len();
+            Save(SaveFormat::Csv) => {
+                let item_count = visible_items.len() as u64;
+                TOTAL.store(item_count, Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let mut writer = CaptureFileWriter::create(&path)?;
+                let start_time = if capture.packet_times.len() > 0 {
+                    capture.packet_time(PacketId::from(0))?
+                } else {
+                    0
+                };
+                export::write_csv_header(&mut writer)?;
+                for (i, item) in visible_items.iter().enumerate() {
+                    export::write_csv_item(
+                        &mut capture, item, start_time, &mut writer)?;
+                    CURRENT.store(i as u64 + 1, Ordering::Relaxed);
+                    if STOP.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                writer.finish()?;
+                Ok(())
+            },
+            Save(SaveFormat::Pcap) | Export => {
+                let selected = match &export_scope {
+                    Some(scope) => Some(capture.export_packets(scope)?),
+                    None => None,
+                };
+                let packet_count = match &selected {
+                    Some(packet_ids) => packet_ids.len() as u64,
+                    None => capture.packet_index.len(),
+                };
                 TOTAL.store(packet_count, Ordering::Relaxed);
                 CURRENT.store(0, Ordering::Relaxed);
-                let file = File::create(path)?;
-                let writer = BufWriter::new(file);
-                let header = PcapHeader {
-                    datalink: DataLink::USB_2_0,
-                    .. PcapHeader::default()
+                let metadata = capture.shared.metadata.load_full();
+                let mut writer =
+                    PacketFileWriter::create_with_metadata(&path, &metadata)?;
+                // Comments and markers are saved with packets in pcapng
+                // files, and details of the capture in its header.
+                let comments = if writer.has_comments() {
+                    capture.packet_comments()?
+                } else {
+                    BTreeMap::new()
                 };
-                let mut pcap = PcapWriter::with_header(writer, header)?;
                 for i in 0..packet_count {
-                    let packet_id = PacketId::from(i);
-                    let bytes = capture.packet(packet_id)?;
-                    let length: u32 = bytes
-                        .len()
-                        .try_into()
-                        .context("Packet too large for pcap file")?;
-                    let packet = RawPcapPacket {
-                        ts_sec: 0,
-                        ts_frac: 0,
-                        incl_len: length,
-                        orig_len: length,
-                        data: Cow::from(bytes)
+                    let packet_id = match &selected {
+                        Some(packet_ids) => packet_ids[i as usize],
+                        None => PacketId::from(i),
                     };
-                    pcap.write_raw_packet(&packet)?;
+                    let bytes = capture.packet(packet_id)?;
+                    let timestamp = capture.packet_time(packet_id)?;
+                    let comment = comments.get(&packet_id).map(String::as_str);
+                    writer.write_packet(&bytes, timestamp, comment)?;
+                    CURRENT.store(i + 1, Ordering::Relaxed);
+                    if STOP.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                writer.finish()?;
+                Ok(())
+            },
+            Split => {
+                let addresses = capture.device_address_packets()?;
+                if addresses.is_empty() {
+                    bail!("No packets were sent to any device")
+                }
+                let packet_count: usize = addresses
+                    .iter()
+                    .map(|(_, packet_ids)| packet_ids.len())
+                    .sum();
+                TOTAL.store(packet_count as u64, Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let metadata = capture.shared.metadata.load_full();
+                let comments = if pcap::is_pcapng_path(&path) {
+                    capture.packet_comments()?
+                } else {
+                    BTreeMap::new()
+                };
+                for (address, packet_ids) in addresses {
+                    let mut writer = PacketFileWriter::create_with_metadata(
+                        &pcap::device_path(&path, address), &metadata)?;
+                    for packet_id in packet_ids {
+                        let bytes = capture.packet(packet_id)?;
+                        let timestamp = capture.packet_time(packet_id)?;
+                        let comment =
+                            comments.get(&packet_id).map(String::as_str);
+                        writer.write_packet(&bytes, timestamp, comment)?;
+                        CURRENT.fetch_add(1, Ordering::Relaxed);
+                        if STOP.load(Ordering::Relaxed) {
+                            break;
+                        }
+                    }
+                    writer.finish()?;
+                    if STOP.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                Ok(())
+            },
+            Save(SaveFormat::Sanitized) => {
+                let packet_count = capture.packet_index.len();
+                TOTAL.store(packet_count, Ordering::Relaxed);
+                CURRENT.store(0, Ordering::Relaxed);
+                let sanitizer = Sanitizer::new(&mut capture)?;
+                let mut writer = PacketFileWriter::create(&path)?;
+                // Comments are left out, as they may describe the data.
+                for i in 0..packet_count {
+                    let packet_id = PacketId::from(i);
+                    let bytes = sanitizer.packet(&mut capture, packet_id)?;
+                    let timestamp = capture.packet_time(packet_id)?;
+                    writer.write_packet(&bytes, timestamp, None)?;
                     CURRENT.store(i + 1, Ordering::Relaxed);
                     if STOP.load(Ordering::Relaxed) {
                         break;
                     }
                 }
-                pcap.into_writer().flush()?;
+                writer.finish()?;
                 Ok(())
             },
         };
         std::thread::spawn(move || {
-            display_error(worker());
-            gtk::glib::idle_add_once(|| {
+            let result = worker();
+            let saved = result.is_ok() && matches!(
+                action, Save(SaveFormat::Pcap | SaveFormat::Native));
+            let opened = result.is_ok() && action == Load;
+            display_error(result);
+            gtk::glib::idle_add_once(move || {
                 STOP.store(false, Ordering::Relaxed);
+                if saved {
+                    display_error(discard_autosave());
+                }
+                if opened {
+                    display_error(remember_capture(&opened_path));
+                }
                 display_error(
                     with_ui(|ui| {
                         ui.show_progress = None;
@@ -810,13 +8698,27 @@ fn start_pcap(action: FileAction, path: PathBuf) -> Result<(), Error> {
                         ui.stop_button.disconnect(signal_id);
                         ui.stop_button.set_sensitive(false);
                         ui.open_button.set_sensitive(true);
+                        ui.merge_button.set_sensitive(true);
+                        ui.reanalyze_button.set_sensitive(true);
                         ui.save_button.set_sensitive(true);
+                        ui.export_button.set_sensitive(true);
                         ui.scan_button.set_sensitive(true);
                         ui.selector.set_sensitive(true);
                         ui.capture_button.set_sensitive(ui.selector.device_available());
+                        if action == Load {
+                            // A native capture may include bookmarks.
+                            refresh_bookmarks(ui);
+                        }
+                        if let Some(session) = ui.pending_session.take() {
+                            restore_view(ui, &session)?;
+                        }
                         Ok(())
                     })
                 );
+                if matches!(action, Load | Merge | Reanalyze) {
+                    display_error(update_graph());
+                    display_error(update_heatmap());
+                }
             });
         });
         gtk::glib::timeout_add_once(
@@ -847,6 +8749,66 @@ fn device_selection_changed() -> Result<(), Error> {
     with_ui(|ui| {
         ui.capture_button.set_sensitive(ui.selector.device_available());
         ui.selector.update_speeds();
+        ui.selector.update_replay_controls();
+        Ok(())
+    })
+}
+
+/// Apply the capture settings saved in the selected profile.
+fn profile_selected() -> Result<(), Error> {
+    with_ui(|ui| {
+        let profile = match ui.profile_options.selected() {
+            Some(profile) => profile.clone(),
+            None => return Ok(()),
+        };
+        ui.profile_options.name_entry.set_text(&profile.name);
+        ui.selector.select(&profile.device, &profile.speed);
+        ui.capture_button.set_sensitive(ui.selector.device_available());
+        ui.capture_filter_entry.set_text(&profile.capture_filter);
+        ui.trigger_entry.set_text(&profile.trigger);
+        ui.snap_spin.set_value(profile.snap_length as f64);
+        ui.packets_only_check.set_active(profile.packets_only);
+        ui.stream_options.apply(profile.stream.as_ref());
+        Ok(())
+    })
+}
+
+/// Save the current capture settings as a profile, under the name given.
+fn save_profile() -> Result<(), Error> {
+    with_ui(|ui| {
+        let name = ui.profile_options.name_entry.text().trim().to_string();
+        let profile = Profile {
+            name: name.clone(),
+            device: ui.selector.device_name(),
+            speed: ui.selector.speed_name(),
+            capture_filter: ui.capture_filter_entry.text().to_string(),
+            snap_length: ui.snap_spin.value_as_int() as u32,
+            packets_only: ui.packets_only_check.is_active(),
+            trigger: ui.trigger_entry.text().to_string(),
+            stream: ui.stream_options.profile(),
+        };
+        let options = &mut ui.profile_options;
+        options.profiles.set(profile)?;
+        options.profiles.save(&profiles_path())?;
+        options.refresh(Some(&name));
+        options.menu_button.popdown();
+        Ok(())
+    })
+}
+
+/// Delete the selected capture profile.
+fn delete_profile() -> Result<(), Error> {
+    with_ui(|ui| {
+        let options = &mut ui.profile_options;
+        let name = match options.selected() {
+            Some(profile) => profile.name.clone(),
+            None => bail!("No capture profile selected"),
+        };
+        options.profiles.remove(&name);
+        options.profiles.save(&profiles_path())?;
+        options.refresh(None);
+        options.name_entry.set_text("");
+        options.menu_button.popdown();
         Ok(())
     })
 }
@@ -854,33 +8816,179 @@ fn device_selection_changed() -> Result<(), Error> {
 pub fn start_cynthion() -> Result<(), Error> {
     let writer = reset_capture()?;
     with_ui(|ui| {
-        let (cynthion, speed) = ui.selector.open()?;
-        let (stream_handle, stop_handle) =
-            cynthion.start(speed, display_error)?;
+        let stream_settings = ui.stream_options.settings()?;
+        let capture_filter =
+            CaptureFilter::parse(&ui.capture_filter_entry.text())
+                .context("Invalid capture filter")?;
+        let snap_length = match ui.snap_spin.value_as_int() {
+            0 => None,
+            length => Some(length as usize),
+        };
+        let decode_depth = if ui.packets_only_check.is_active() {
+            DecodeDepth::Packets
+        } else {
+            DecodeDepth::Transfers
+        };
+        let trigger_filter = parse_filter(&ui.trigger_entry.text())
+            .context("Invalid trigger filter")?;
+        let schedule = ui.schedule_options.schedule()?;
+        let preferences = preferences();
+        let autosave_path = if preferences.autosave {
+            Some(autosave_path()?)
+        } else {
+            None
+        };
+        let (stream_handle, stop_handle, trigger_output, tester) =
+            ui.selector.start()?;
+        // Watch the traffic as it is decoded, pulsing the trigger output
+        // when it matches.
+        if let Some(filter) = trigger_filter {
+            let mut capture = ui.capture.clone();
+            let mut trigger = Trigger::new(filter.as_ref().clone());
+            std::thread::spawn(move || display_error(
+                trigger
+                    .watch(&mut capture, || trigger_output.pulse())
+                    .map(|_| ())));
+        }
+        // Watch the traffic for each alert rule too, acting on matches
+        // on the main thread.
+        for rule in ui.alert_rules.enabled() {
+            let mut capture = ui.capture.clone();
+            let mut watcher = Trigger::new(rule.filter().clone());
+            let action = rule.action;
+            let filter_text = rule.filter_text.clone();
+            let mut last_raised: Option<Instant> = None;
+            std::thread::spawn(move || display_error(
+                watcher
+                    .watch(&mut capture, || {
+                        let due = last_raised.map_or(true, |time|
+                            time.elapsed() >= ALERT_INTERVAL);
+                        if due {
+                            last_raised = Some(Instant::now());
+                            let filter_text = filter_text.clone();
+                            gtk::glib::idle_add_once(move || display_error(
+                                raise_alert(action, filter_text)));
+                        }
+                        Ok(())
+                    })
+                    .map(|_| ())));
+        }
         ui.stop_handle.replace(stop_handle);
+        ui.tester = Some(tester);
+        ui.autosave_path = autosave_path.clone();
         ui.open_button.set_sensitive(false);
+        ui.merge_button.set_sensitive(false);
+        ui.reanalyze_button.set_sensitive(false);
         ui.scan_button.set_sensitive(false);
         ui.selector.set_sensitive(false);
         ui.capture_button.set_sensitive(false);
+        ui.stream_options.menu_button.set_sensitive(false);
+        ui.schedule_options.menu_button.set_sensitive(false);
+        ui.capture_filter_entry.set_sensitive(false);
+        ui.trigger_entry.set_sensitive(false);
+        ui.snap_spin.set_sensitive(false);
+        ui.packets_only_check.set_sensitive(false);
+        ui.profile_options.set_sensitive(false);
         ui.stop_button.set_sensitive(true);
+        ui.autoscroll_button.set_sensitive(true);
+        ui.autoscroll_button.set_active(true);
         let signal_id = ui.stop_button.connect_clicked(|_|
             display_error(stop_cynthion()));
+        // Stop once the capture has run for the scheduled time, unless it
+        // has already finished.
+        let finished = Arc::new(AtomicBool::new(false));
+        if let Some(duration) = schedule.duration {
+            let finished = finished.clone();
+            gtk::glib::timeout_add_local_once(duration, move || {
+                if !finished.load(Ordering::Acquire) {
+                    display_error(stop_as_scheduled());
+                }
+            });
+        }
         let read_cynthion = move || {
             let mut decoder = Decoder::new(writer)?;
-            for packet in stream_handle {
-                decoder.handle_raw_packet(&packet)?;
-            }
+            decoder.set_snap_length(snap_length);
+            decoder.set_decode_depth(decode_depth)?;
+            decoder.set_pipeline_depth(preferences.pipeline_depth);
+            // Packets are read, filtered and saved on one thread, and
+            // decoded on this one.
+            decoder.decode_from(move |sender| {
+                let mut autosave_writer = match autosave_path {
+                    Some(path) => Some(
+                        StreamWriter::new(path, RotationLimits::default())?),
+                    None => None,
+                };
+                let mut stream_writer = match stream_settings {
+                    Some((path, limits)) =>
+                        Some(StreamWriter::new(path, limits)?),
+                    None => None,
+                };
+                let packets =
+                    FilteredPackets::new(stream_handle, capture_filter);
+                let mut count = 0;
+                let mut bytes = 0;
+                let mut limit_reached = false;
+                for packet in packets {
+                    count += 1;
+                    bytes += packet.len() as u64;
+                    // Packets are still read until the capture is
+                    // stopped, so none are lost while it shuts down.
+                    if !limit_reached && schedule.limit_reached(count, bytes) {
+                        limit_reached = true;
+                        gtk::glib::idle_add_once(||
+                            display_error(stop_as_scheduled()));
+                    }
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)?
+                        .as_nanos()
+                        .try_into()?;
+                    if let Some(autosave_writer) = autosave_writer.as_mut() {
+                        autosave_writer.write(&packet, timestamp)?;
+                    }
+                    if let Some(stream_writer) = stream_writer.as_mut() {
+                        stream_writer.write(&packet, timestamp)?;
+                    }
+                    sender.send(PipelinePacket { data: packet, timestamp })?;
+                }
+                if let Some(autosave_writer) = autosave_writer {
+                    autosave_writer.finish()?;
+                }
+                if let Some(stream_writer) = stream_writer {
+                    stream_writer.finish()?;
+                }
+                Ok(())
+            })?;
             decoder.finish()?;
             Ok(())
         };
         std::thread::spawn(move || {
             display_error(read_cynthion());
+            finished.store(true, Ordering::Release);
             gtk::glib::idle_add_once(|| {
                 display_error(
                     with_ui(|ui| {
+                        // An emulated capture ends by itself once its
+                        // scenario is complete, so may not be stopped yet.
+                        if let Some(stop_handle) = ui.stop_handle.take() {
+                            stop_handle.stop()?;
+                            ui.scan_button.set_sensitive(true);
+                            ui.reanalyze_button.set_sensitive(true);
+                            ui.save_button.set_sensitive(true);
+                            ui.export_button.set_sensitive(true);
+                        }
+                        ui.tester = None;
                         ui.stop_button.disconnect(signal_id);
                         ui.stop_button.set_sensitive(false);
+                        ui.autoscroll_button.set_sensitive(false);
                         ui.open_button.set_sensitive(true);
+                        ui.merge_button.set_sensitive(true);
+                        ui.stream_options.menu_button.set_sensitive(true);
+                        ui.schedule_options.menu_button.set_sensitive(true);
+                        ui.capture_filter_entry.set_sensitive(true);
+                        ui.trigger_entry.set_sensitive(true);
+                        ui.snap_spin.set_sensitive(true);
+                        ui.packets_only_check.set_sensitive(true);
+                        ui.profile_options.set_sensitive(true);
                         ui.selector.set_sensitive(true);
                         ui.capture_button.set_sensitive(ui.selector.device_available());
                         Ok(())
@@ -895,17 +9003,173 @@ pub fn start_cynthion() -> Result<(), Error> {
     })
 }
 
+/// Start a capture, at once or at the time it is scheduled for. Until
+/// then, the stop button cancels the scheduled start.
+fn schedule_capture() -> Result<(), Error> {
+    let mut scheduled = false;
+    with_ui(|ui| {
+        let schedule = ui.schedule_options.schedule()?;
+        let start_at = match schedule.start_at {
+            Some(start_at) => start_at,
+            None => return Ok(()),
+        };
+        scheduled = true;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let source_id = gtk::glib::timeout_add_local_once(
+            schedule.start_delay(now),
+            || display_error(start_scheduled_capture()));
+        let signal_id = ui.stop_button.connect_clicked(|_|
+            display_error(cancel_scheduled_capture()));
+        ui.scheduled_start = Some((source_id, signal_id));
+        ui.capture_button.set_sensitive(false);
+        ui.schedule_options.menu_button.set_sensitive(false);
+        ui.stop_button.set_sensitive(true);
+        ui.status_label.set_text(&format!(
+            "Waiting until {} UTC to start capturing",
+            Schedule::format_time_of_day(start_at)));
+        Ok(())
+    })?;
+    if !scheduled {
+        start_cynthion()?;
+    }
+    Ok(())
+}
+
+/// Start a capture at the time it was scheduled for.
+fn start_scheduled_capture() -> Result<(), Error> {
+    with_ui(|ui| {
+        // The source has been removed by returning from its callback.
+        if let Some((_, signal_id)) = ui.scheduled_start.take() {
+            ui.stop_button.disconnect(signal_id);
+        }
+        Ok(())
+    })?;
+    let result = start_cynthion();
+    if result.is_err() {
+        // Return to the state before the capture was scheduled.
+        cancel_scheduled_capture()?;
+    }
+    result
+}
+
+/// Cancel a scheduled capture before it is started.
+fn cancel_scheduled_capture() -> Result<(), Error> {
+    with_ui(|ui| {
+        if let Some((source_id, signal_id)) = ui.scheduled_start.take() {
+            source_id.remove();
+            ui.stop_button.disconnect(signal_id);
+        }
+        ui.stop_button.set_sensitive(false);
+        ui.schedule_options.menu_button.set_sensitive(true);
+        ui.capture_button.set_sensitive(ui.selector.device_available());
+        ui.status_label.set_text("Ready");
+        Ok(())
+    })
+}
+
+/// Stop a capture which has reached a limit of its schedule.
+fn stop_as_scheduled() -> Result<(), Error> {
+    let mut capturing = false;
+    with_ui(|ui| {
+        capturing = ui.stop_handle.is_some();
+        ui.schedule_done |= capturing;
+        Ok(())
+    })?;
+    if capturing {
+        stop_cynthion()?;
+    }
+    Ok(())
+}
+
 pub fn stop_cynthion() -> Result<(), Error> {
     with_ui(|ui| {
         if let Some(stop_handle) = ui.stop_handle.take() {
             stop_handle.stop()?;
         }
         ui.scan_button.set_sensitive(true);
+        ui.reanalyze_button.set_sensitive(true);
         ui.save_button.set_sensitive(true);
+        ui.export_button.set_sensitive(true);
         Ok(())
     })
 }
 
+fn recovery_dir() -> PathBuf {
+    let mut path = gtk::glib::user_cache_dir();
+    path.push("packetry");
+    path.push("recovery");
+    path
+}
+
+fn autosave_path() -> Result<PathBuf, Error> {
+    let dir = recovery_dir();
+    std::fs::create_dir_all(&dir).with_context(|| format!(
+        "Failed to create recovery directory {}", dir.display()))?;
+    Ok(dir.join(format!("capture-{}.pcap", std::process::id())))
+}
+
+/// Remove the autosaved copy of the current capture, if there is one.
+pub fn discard_autosave() -> Result<(), Error> {
+    let mut path = None;
+    with_ui(|ui| {
+        path = ui.autosave_path.take();
+        Ok(())
+    })?;
+    if let Some(path) = path {
+        if path.exists() {
+            std::fs::remove_file(&path).with_context(|| format!(
+                "Failed to remove autosaved capture {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature="test-ui-replay"))]
+fn offer_recovery() -> Result<(), Error> {
+    let entries = match std::fs::read_dir(recovery_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(()),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "pcap"))
+        .collect();
+    // Offer the most recently modified capture.
+    paths.sort_by_key(|path|
+        path.metadata().and_then(|meta| meta.modified()).ok());
+    let path = match paths.pop() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    WINDOW.with(|win_opt| {
+        let window = win_opt.borrow();
+        let dialog = MessageDialog::new(
+            window.as_ref(),
+            DialogFlags::MODAL,
+            MessageType::Question,
+            ButtonsType::YesNo,
+            "A capture from a previous session was not saved. Recover it?"
+        );
+        dialog.connect_response(move |dialog, response| {
+            dialog.destroy();
+            if response == gtk::ResponseType::Yes {
+                display_error(
+                    start_pcap(FileAction::Load, vec![path.clone()])
+                        .and_then(|_| with_ui(|ui| {
+                            ui.autosave_path = Some(path.clone());
+                            Ok(())
+                        })));
+            } else {
+                display_error(std::fs::remove_file(&path)
+                    .context("Failed to remove autosaved capture"));
+            }
+        });
+        dialog.show();
+    });
+    Ok(())
+}
+
 pub fn display_error(result: Result<(), Error>) {
     #[cfg(not(feature="test-ui-replay"))]
     if let Err(e) = result {